@@ -30,5 +30,30 @@ class CfgPatches {
     }));
 }
 
-criterion_group!(benches, bench_config);
+fn bench_rapify(c: &mut Criterion) {
+    let mut input = String::from("class CfgVehicles {\n");
+    for i in 0..2000 {
+        input += &format!("    class Vehicle_{}: Vehicle_Base {{
+        displayName = \"Vehicle {}\";
+        scope = 2;
+        armor = {}.5;
+        hiddenSelections[] = {{\"camo1\", \"camo2\", \"camo3\"}};
+        class Turrets {{
+            class MainTurret {{
+                weapons[] = {{\"cannon_{}\"}};
+                magazines[] = {{\"mag_{}\", \"mag_{}\"}};
+            }};
+        }};
+    }};\n", i, i, i, i, i, i);
+    }
+    input += "};\n";
+
+    let config = Config::from_string(input, None, &Vec::new()).unwrap();
+
+    c.bench_function("rapify", |b| b.iter(|| {
+        config.to_cursor().unwrap();
+    }));
+}
+
+criterion_group!(benches, bench_config, bench_rapify);
 criterion_main!(benches);
@@ -0,0 +1,150 @@
+use std::io::{Cursor};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use minilzo_rs::LZO;
+use texpresso::{Algorithm};
+
+use armake2::paa::*;
+use armake2::paa::bench::*;
+
+const SIZE: u32 = 1024;
+
+/// Deterministic noise blended with a gradient, so the blocks are neither trivial nor random.
+fn test_image(size: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut state: u32 = 0x1234_5678;
+    ImageBuffer::from_fn(size, size, |x, y| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+
+        let noise = state & 0x3F;
+        Rgba([
+            ((x * 255 / size + noise) % 256) as u8,
+            ((y * 255 / size + noise) % 256) as u8,
+            (((x + y) * 127 / size + noise) % 256) as u8,
+            (255 - noise) as u8,
+        ])
+    })
+}
+
+fn bench_compress(c: &mut Criterion) {
+    let img = test_image(SIZE);
+
+    let mut group = c.benchmark_group("compress");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((SIZE * SIZE * 4) as u64));
+
+    for (name, algorithm) in &[("fast", Algorithm::RangeFit), ("best", Algorithm::IterativeClusterFit)] {
+        group.bench_with_input(BenchmarkId::new("dxt1", name), algorithm, |b, algorithm| b.iter(|| {
+            compress_dxt1(&img, *algorithm).unwrap();
+        }));
+        group.bench_with_input(BenchmarkId::new("dxt5", name), algorithm, |b, algorithm| b.iter(|| {
+            compress_dxt5(&img, *algorithm).unwrap();
+        }));
+    }
+
+    group.finish();
+}
+
+fn bench_paa(c: &mut Criterion) {
+    let img = DynamicImage::ImageRgba8(test_image(SIZE));
+    let paa = Paa::from_image(&img, PaaType::DXT5, false).unwrap();
+
+    let mut group = c.benchmark_group("paa");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((SIZE * SIZE * 4) as u64));
+
+    group.bench_function("from_image", |b| b.iter(|| {
+        Paa::from_image(&img, PaaType::DXT5, false).unwrap();
+    }));
+    group.bench_function("to_image", |b| b.iter(|| {
+        paa.to_image().unwrap();
+    }));
+
+    group.finish();
+}
+
+fn bench_lzo(c: &mut Criterion) {
+    let data = compress_dxt5(&test_image(SIZE), Algorithm::RangeFit).unwrap();
+    let mut lzo = LZO::init().unwrap();
+    let compressed = lzo.compress(&data).unwrap();
+
+    let mut group = c.benchmark_group("lzo");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("compress", |b| b.iter(|| {
+        lzo.compress(&data).unwrap();
+    }));
+    group.bench_function("decompress", |b| b.iter(|| {
+        lzo.decompress_safe(&compressed, data.len()).unwrap();
+    }));
+
+    group.finish();
+}
+
+fn bench_paa_read(c: &mut Criterion) {
+    let img = DynamicImage::ImageRgba8(test_image(SIZE));
+    let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    Paa::from_image(&img, PaaType::DXT5, true).unwrap().write(&mut buffer).unwrap();
+    let bytes = buffer.into_inner();
+
+    c.bench_function("paa_read", |b| b.iter(|| {
+        Paa::from_reader(&mut Cursor::new(&bytes)).unwrap();
+    }));
+}
+
+/// Reading a texture with many LZO compressed levels and decompressing all of them, which
+/// happens in parallel with more than one thread
+fn bench_paa_read_threads(c: &mut Criterion) {
+    let img = DynamicImage::ImageRgba8(test_image(SIZE));
+    let mut bytes: Vec<u8> = Vec::new();
+    Paa::from_image(&img, PaaType::ARGB8888, true).unwrap().write_to(&mut bytes).unwrap();
+
+    let mut group = c.benchmark_group("paa_read_threads");
+    group.sample_size(10);
+    for threads in [1, 4] {
+        armake2::parallel::set_thread_count(threads).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &bytes, |b, bytes| b.iter(|| {
+            let mut paa = Paa::from_reader(&mut Cursor::new(bytes)).unwrap();
+            paa.decompress_levels(&(0..paa.mipmap_count()).collect::<Vec<usize>>()).unwrap();
+        }));
+    }
+    armake2::parallel::set_thread_count(0).unwrap();
+
+    group.finish();
+}
+
+/// Compressing mipmaps on several threads at once, which each use their own minilzo instance
+fn bench_minilzo_threads(c: &mut Criterion) {
+    let data = compress_dxt5(&test_image(SIZE), Algorithm::RangeFit).unwrap();
+    let chunks: Vec<&[u8]> = data.chunks(data.len() / 16).collect();
+
+    let mut group = c.benchmark_group("minilzo_threads");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for threads in [1, 4] {
+        armake2::parallel::set_thread_count(threads).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &chunks, |b, chunks| b.iter(|| {
+            let _: Vec<Vec<u8>> = armake2::parallel::map_collect(chunks.clone(), |chunk: &[u8]| MiniLzo.compress(chunk).unwrap());
+        }));
+    }
+    armake2::parallel::set_thread_count(0).unwrap();
+
+    group.finish();
+}
+
+/// Reading a texture and decoding a 64px thumbnail, which mod managers do for thousands of files.
+/// A thousand of them should take a few seconds at most.
+fn bench_paa_thumbnail(c: &mut Criterion) {
+    let img = DynamicImage::ImageRgba8(test_image(SIZE));
+    let mut buffer: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    Paa::from_image(&img, PaaType::DXT5, true).unwrap().write(&mut buffer).unwrap();
+    let bytes = buffer.into_inner();
+
+    c.bench_function("paa_thumbnail", |b| b.iter(|| {
+        Paa::from_reader(&mut Cursor::new(&bytes)).unwrap().thumbnail(64).unwrap();
+    }));
+}
+
+criterion_group!(benches, bench_compress, bench_paa, bench_lzo, bench_paa_read, bench_paa_read_threads, bench_minilzo_threads, bench_paa_thumbnail);
+criterion_main!(benches);
@@ -0,0 +1,315 @@
+//! Header checks of packed sound files
+//!
+//! The game plays Ogg Vorbis, WSS and WAV files. Broken ones crash it or stay silent without an
+//! error, so packing can read their headers and warn about what the engine doesn't handle well.
+//! Nothing is decoded or converted.
+
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::error::*;
+use crate::pbo::{PBO};
+
+/// Sample rates the engine plays without resampling
+pub const SAMPLE_RATES: [u32; 2] = [44100, 48000];
+
+/// Channels of a sound the engine can position, more are mixed down or fail
+pub const MAX_CHANNELS: u16 = 2;
+
+/// Container of a sound file, by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Ogg,
+    Wss,
+    Wav,
+}
+
+impl AudioFormat {
+    /// Returns the format of a file name, if it is a sound file.
+    pub fn from_path(path: &str) -> Option<AudioFormat> {
+        let extension = path.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "ogg" => Some(AudioFormat::Ogg),
+            "wss" => Some(AudioFormat::Wss),
+            "wav" => Some(AudioFormat::Wav),
+            _ => None
+        }
+    }
+}
+
+/// Stream properties of a sound file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioInfo {
+    pub format: AudioFormat,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Number of samples per channel
+    pub frames: u64,
+}
+
+/// What's wrong with a sound file. Each kind is a warning of its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioProblem {
+    /// The file ends before the data its headers describe
+    Truncated(String),
+    /// The headers are corrupt or of an unsupported codec
+    Invalid(String),
+    /// The stream has no samples
+    Empty,
+    SampleRate(u32),
+    Channels(u16),
+}
+
+impl AudioProblem {
+    /// Name of the warning the problem is reported as
+    pub fn warning_name(&self) -> &'static str {
+        match self {
+            AudioProblem::Truncated(_) => "audio-truncated",
+            AudioProblem::Invalid(_) => "audio-invalid",
+            AudioProblem::Empty => "audio-empty",
+            AudioProblem::SampleRate(_) => "audio-sample-rate",
+            AudioProblem::Channels(_) => "audio-channels",
+        }
+    }
+}
+
+impl fmt::Display for AudioProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioProblem::Truncated(reason) => write!(f, "The file is truncated: {}.", reason),
+            AudioProblem::Invalid(reason) => write!(f, "The file is invalid: {}.", reason),
+            AudioProblem::Empty => write!(f, "The sound has no samples."),
+            AudioProblem::SampleRate(rate) => write!(f, "Sample rate of {} Hz, the engine expects 44100 or 48000 Hz.", rate),
+            AudioProblem::Channels(channels) => write!(f, "{} channels, the engine plays at most {}.", channels, MAX_CHANNELS),
+        }
+    }
+}
+
+fn truncated<T>(reason: &str) -> Result<T, AudioProblem> {
+    Err(AudioProblem::Truncated(reason.to_string()))
+}
+
+fn invalid<T>(reason: &str) -> Result<T, AudioProblem> {
+    Err(AudioProblem::Invalid(reason.to_string()))
+}
+
+impl AudioInfo {
+    /// Reads the stream properties from the headers of a file. Each Ogg page is read to check
+    /// its checksum and find the length of the stream.
+    pub fn read(format: AudioFormat, data: &[u8]) -> Result<AudioInfo, AudioProblem> {
+        match format {
+            AudioFormat::Ogg => read_ogg(data),
+            AudioFormat::Wss => read_wss(data),
+            AudioFormat::Wav => read_wav(data),
+        }
+    }
+}
+
+/// Returns the CRC of an Ogg page, which has its checksum field zeroed for it.
+pub fn ogg_crc(data: &[u8]) -> u32 {
+    update_ogg_crc(0, data)
+}
+
+/// Continues an Ogg CRC with more data, so a page can be checked in parts.
+fn update_ogg_crc(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        (0..8).fold(crc ^ (u32::from(*byte) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 }
+        })
+    })
+}
+
+fn read_ogg(data: &[u8]) -> Result<AudioInfo, AudioProblem> {
+    let mut offset = 0;
+    let mut first_packet: Vec<u8> = Vec::new();
+    let mut first_packet_done = false;
+    let mut frames = 0;
+    let mut ended = false;
+
+    while offset < data.len() {
+        let page = &data[offset..];
+        if !page.starts_with(b"OggS") && !b"OggS".starts_with(page) {
+            return invalid(&format!("no Ogg page at byte {}", offset));
+        }
+        if page.len() < 27 {
+            return truncated("the last Ogg page header is incomplete");
+        }
+        if page[4] != 0 {
+            return invalid(&format!("unknown Ogg version {} at byte {}", page[4], offset));
+        }
+        if ended {
+            return invalid("there are pages after the end of the stream");
+        }
+
+        let segments = match page.get(27..27 + page[26] as usize) {
+            Some(segments) => segments,
+            None => return truncated("the last Ogg page header is incomplete")
+        };
+        let length = 27 + segments.len() + segments.iter().map(|s| *s as usize).sum::<usize>();
+        if page.len() < length {
+            return truncated("the last Ogg page is incomplete");
+        }
+
+        // The checksum field counts as zeroed
+        let crc = [&page[..22], &[0u8; 4], &page[26..length]].iter().fold(0, |crc, part| update_ogg_crc(crc, part));
+        if crc != LittleEndian::read_u32(&page[22..26]) {
+            return invalid(&format!("the Ogg page at byte {} has a wrong checksum", offset));
+        }
+
+        if !first_packet_done {
+            let mut body = &page[27 + segments.len()..length];
+            for segment in segments.iter() {
+                first_packet.extend_from_slice(&body[..*segment as usize]);
+                body = &body[*segment as usize..];
+                if *segment < 255 {
+                    first_packet_done = true;
+                    break;
+                }
+            }
+        }
+
+        // Pages on which no packet ends have a granule position of -1
+        let granule = LittleEndian::read_i64(&page[6..14]);
+        if granule >= 0 {
+            frames = frames.max(granule as u64);
+        }
+        ended = page[5] & 4 != 0;
+        offset += length;
+    }
+
+    if !first_packet_done {
+        return truncated("the stream ends within the first packet");
+    }
+    if first_packet.len() < 16 || first_packet[0] != 1 || &first_packet[1..7] != b"vorbis" {
+        return invalid("the stream isn't Vorbis");
+    }
+    if !ended {
+        return truncated("the stream has no last page");
+    }
+
+    Ok(AudioInfo {
+        format: AudioFormat::Ogg,
+        channels: u16::from(first_packet[11]),
+        sample_rate: LittleEndian::read_u32(&first_packet[12..16]),
+        frames,
+    })
+}
+
+/// Returns the channels, sample rate and block alignment of a `WAVEFORMATEX`.
+fn read_wave_format(data: &[u8]) -> (u16, u32, u16) {
+    (LittleEndian::read_u16(&data[2..4]), LittleEndian::read_u32(&data[4..8]), LittleEndian::read_u16(&data[12..14]))
+}
+
+/// Returns the samples per channel in PCM data of the block alignment.
+fn pcm_frames(length: usize, block_align: u16) -> Result<u64, AudioProblem> {
+    if block_align == 0 {
+        return invalid("the block alignment is 0");
+    }
+    Ok((length / block_align as usize) as u64)
+}
+
+/// WSS files start with `WSS0`, the compression (0 for PCM, 4 or 8 for the delta codecs of the
+/// engine) and a `WAVEFORMATEX` with the properties of the decoded stream.
+fn read_wss(data: &[u8]) -> Result<AudioInfo, AudioProblem> {
+    if data.len() < 4 || &data[0..4] != b"WSS0" {
+        return invalid("no WSS0 header");
+    }
+    if data.len() < 26 {
+        return truncated("the header is incomplete");
+    }
+
+    let compression = LittleEndian::read_u32(&data[4..8]);
+    let (channels, sample_rate, block_align) = read_wave_format(&data[8..26]);
+    let samples = &data[26..];
+    let frames = match compression {
+        0 => {
+            if block_align != 0 && !samples.len().is_multiple_of(block_align as usize) {
+                return truncated("the last sample is incomplete");
+            }
+            pcm_frames(samples.len(), block_align)?
+        },
+        // Each byte of the delta codecs is one 16-bit sample
+        4 | 8 => samples.len() as u64 / u64::from(channels.max(1)),
+        _ => return invalid(&format!("unknown compression {}", compression))
+    };
+
+    Ok(AudioInfo { format: AudioFormat::Wss, channels, sample_rate, frames })
+}
+
+fn read_wav(data: &[u8]) -> Result<AudioInfo, AudioProblem> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return invalid("no RIFF WAVE header");
+    }
+
+    let mut format: Option<(u16, u32, u16)> = None;
+    let mut offset = 12;
+    while offset < data.len() {
+        if data.len() < offset + 8 {
+            return truncated("the last chunk header is incomplete");
+        }
+        let id = &data[offset..offset + 4];
+        let length = LittleEndian::read_u32(&data[offset + 4..offset + 8]) as usize;
+        let chunk = &data[offset + 8..];
+
+        if id == b"fmt " {
+            if length < 16 || chunk.len() < 16 {
+                return truncated("the fmt chunk is incomplete");
+            }
+            format = Some(read_wave_format(chunk));
+        } else if id == b"data" {
+            let (channels, sample_rate, block_align) = match format {
+                Some(format) => format,
+                None => return invalid("the data chunk comes before the fmt chunk")
+            };
+            if chunk.len() < length {
+                return truncated(&format!("the data chunk has {} of {} bytes", chunk.len(), length));
+            }
+            let frames = pcm_frames(length, block_align)?;
+            return Ok(AudioInfo { format: AudioFormat::Wav, channels, sample_rate, frames });
+        }
+
+        // Chunks are padded to an even length
+        offset += 8 + length + length % 2;
+    }
+
+    truncated("there is no data chunk")
+}
+
+/// Returns the problems of a sound file, which are not about its properties if it can't be
+/// read.
+pub fn check(format: AudioFormat, data: &[u8]) -> Vec<AudioProblem> {
+    let info = match AudioInfo::read(format, data) {
+        Ok(info) => info,
+        Err(problem) => return vec![problem]
+    };
+
+    let mut problems: Vec<AudioProblem> = Vec::new();
+    if info.channels == 0 {
+        problems.push(AudioProblem::Invalid("the stream has no channels".to_string()));
+    } else if info.channels > MAX_CHANNELS {
+        problems.push(AudioProblem::Channels(info.channels));
+    }
+    if !SAMPLE_RATES.contains(&info.sample_rate) {
+        problems.push(AudioProblem::SampleRate(info.sample_rate));
+    }
+    if info.frames == 0 {
+        problems.push(AudioProblem::Empty);
+    }
+    problems
+}
+
+/// Returns the problems of the sound files in a PBO, by entry name.
+pub fn check_pbo(pbo: &PBO) -> Vec<(String, AudioProblem)> {
+    pbo.files.iter()
+        .filter_map(|(name, data)| AudioFormat::from_path(name).map(|format| (name, format, data)))
+        .flat_map(|(name, format, data)| check(format, data.get_ref()).into_iter().map(move |p| (name.clone(), p)))
+        .collect()
+}
+
+/// Warns about each problem, with the name of the file.
+pub fn report_problems(problems: &[(String, AudioProblem)]) {
+    for (path, problem) in problems {
+        warning(problem.to_string(), Some(problem.warning_name()), (Some(path.clone()), None));
+    }
+}
@@ -48,12 +48,12 @@ fn create_temp_directory(name: &str) -> Result<PathBuf, Error> {
 /// Binarizes the given path with BI's binarize.exe (Only available on Windows).
 pub fn binarize(input: &PathBuf) -> Result<Cursor<Box<[u8]>>, Error> {
     if !cfg!(windows) {
-        return Err(error!("binarize.exe is only available on windows. Use rapify to binarize configs."));
+        return Err(coded_error!("E0817", "binarize.exe is only available on windows. Use rapify to binarize configs."));
     }
 
     let binarize_exe = find_binarize_exe().prepend_error("Failed to find BI's binarize.exe:")?;
     if !binarize_exe.exists() {
-        return Err(error!("BI's binarize.exe found in registry, but doesn't exist."));
+        return Err(coded_error!("E0818", "BI's binarize.exe found in registry, but doesn't exist."));
     }
 
     let input_dir = PathBuf::from(input.parent().unwrap());
@@ -75,7 +75,7 @@ pub fn binarize(input: &PathBuf) -> Result<Cursor<Box<[u8]>>, Error> {
         };
         let outputhint = if !piped { "\nUse BIOUTPUT=1 to see binarize.exe's output." } else { "" };
 
-        return Err(error!("{}{}", msg, outputhint));
+        return Err(coded_error!("E0819", "{}{}", msg, outputhint));
     }
 
     let result_path = tempdir.join(input.strip_prefix(&input_dir).unwrap());
@@ -94,7 +94,7 @@ pub fn binarize(input: &PathBuf) -> Result<Cursor<Box<[u8]>>, Error> {
 /// Binarizes the given path using BI's binarize.exe (on Windows) and writes it to the output.
 pub fn cmd_binarize(input: PathBuf, output: PathBuf) -> Result<(), Error> {
     if !cfg!(windows) {
-        return Err(error!("binarize.exe is only available on windows. Use rapify to binarize configs."));
+        return Err(coded_error!("E0817", "binarize.exe is only available on windows. Use rapify to binarize configs."));
     }
 
     let cursor = binarize(&input)?;
@@ -0,0 +1,279 @@
+//! On-disk cache of files converted during `build`
+//!
+//! Every entry stores the artifact produced for one source file together with the content hashes
+//! of all files it was produced from, e.g. a config and everything it includes. An entry is used
+//! only while all of these hashes still match, so touching a file without changing it doesn't
+//! cause a rebuild. The tool version and the build options are part of the entry key, which
+//! means changing either never reuses artifacts of a different build.
+//!
+//! The warnings raised while producing an artifact are stored with it and raised again when it is
+//! reused, so a build from the cache reports the same warnings as one without it.
+//!
+//! `HashCache` keeps the content hashes of the files `--dedupe` packs in the same folder, so a
+//! file whose size and modification time didn't change is known to be identical to another one
+//! without reading it.
+
+use std::collections::{HashMap};
+use std::fs::{File, create_dir_all, read, read_dir, read_to_string, remove_file, rename, write};
+use std::io::{Error};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openssl::hash::{MessageDigest, hash};
+
+use crate::*;
+use crate::error::*;
+
+/// An artifact read from the cache
+#[derive(Debug, Clone)]
+pub struct CachedArtifact {
+    pub data: Vec<u8>,
+    /// Warnings raised while producing it, in the order they were raised
+    pub warnings: Vec<CollectedDiagnostic>,
+}
+
+/// Name of the cache folder created next to the source folder if no other is given
+pub const DEFAULT_CACHE_DIRECTORY: &str = ".armake2-cache";
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEPENDENCIES_EXTENSION: &str = "deps";
+const ARTIFACT_EXTENSION: &str = "bin";
+const WARNINGS_EXTENSION: &str = "warnings";
+const HASHES_FILE: &str = "hashes";
+
+fn sha1_hex(data: &[u8]) -> String {
+    let digest = hash(MessageDigest::sha1(), data).unwrap();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Cache of build artifacts in a folder
+pub struct BuildCache {
+    directory: PathBuf,
+    options: String,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl BuildCache {
+    /// Creates a cache in the given folder. `options` should describe everything besides the
+    /// input files that changes the artifacts, like include folders. The folder is created when
+    /// the first artifact is stored.
+    pub fn new(directory: PathBuf, options: &str) -> BuildCache {
+        BuildCache {
+            directory,
+            options: sha1_hex(options.as_bytes()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the default cache folder for a source folder, which is next to it so that it
+    /// doesn't end up in the PBO.
+    pub fn default_directory(source: &Path) -> PathBuf {
+        let source = source.canonicalize().unwrap_or_else(|_| source.to_path_buf());
+        source.parent().unwrap_or(&source).join(DEFAULT_CACHE_DIRECTORY)
+    }
+
+    /// Returns the cache folder.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Number of artifacts that were read from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of artifacts that had to be produced again
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn entry_path(&self, kind: &str, source: &Path, extension: &str) -> PathBuf {
+        let source = source.canonicalize().unwrap_or_else(|_| source.to_path_buf());
+        let key = format!("{}\n{}\n{}\n{}", VERSION, self.options, kind, source.display());
+        self.directory.join(sha1_hex(key.as_bytes())).with_extension(extension)
+    }
+
+    fn header(&self, kind: &str) -> String {
+        format!("armake2 {} {} {}", VERSION, self.options, kind)
+    }
+
+    /// Returns the cached artifact of the given kind for the source file if none of the files
+    /// it was produced from changed.
+    pub fn get(&self, kind: &str, source: &Path) -> Option<CachedArtifact> {
+        let dependencies_path = self.entry_path(kind, source, DEPENDENCIES_EXTENSION);
+        let result = read_to_string(&dependencies_path).ok()
+            .filter(|dependencies| self.is_fresh(kind, dependencies))
+            .and_then(|_| {
+                let warnings = read(self.entry_path(kind, source, WARNINGS_EXTENSION)).ok()
+                    .and_then(|content| serde_json::from_slice(&content).ok())?;
+                let data = read(self.entry_path(kind, source, ARTIFACT_EXTENSION)).ok()?;
+                Some(CachedArtifact { data, warnings })
+            });
+
+        match result {
+            Some(artifact) => {
+                // The modification time marks the last use for `gc`
+                if let Ok(file) = File::options().write(true).open(&dependencies_path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(artifact)
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn is_fresh(&self, kind: &str, dependencies: &str) -> bool {
+        let mut lines = dependencies.lines();
+        if lines.next() != Some(&self.header(kind)) {
+            return false;
+        }
+
+        lines.all(|line| match line.split_once(' ') {
+            Some(("-", path)) => !Path::new(path).exists(),
+            Some((digest, path)) => read(path).map(|content| sha1_hex(&content) == digest).unwrap_or(false),
+            None => false
+        })
+    }
+
+    /// Stores the artifact of the given kind for the source file. `dependencies` are the other
+    /// files the artifact was produced from and `absent` files whose creation would change it.
+    /// `warnings` are the ones raised while producing it.
+    pub fn insert(&self, kind: &str, source: &Path, dependencies: &[PathBuf], absent: &[PathBuf], artifact: &[u8], warnings: &[CollectedDiagnostic]) -> Result<(), Error> {
+        create_dir_all(&self.directory).prepend_error("Failed to create cache folder:")?;
+
+        let mut lines = vec![self.header(kind)];
+        for path in std::iter::once(&source.to_path_buf()).chain(dependencies.iter()) {
+            let content = read(path).prepend_error(format!("Failed to read {}:", path.display()))?;
+            lines.push(format!("{} {}", sha1_hex(&content), path.display()));
+        }
+        for path in absent {
+            lines.push(format!("- {}", path.display()));
+        }
+
+        // The dependencies are written last, an entry without them is never used.
+        let warnings = serde_json::to_vec(warnings).map_err(Error::from)?;
+        write(self.entry_path(kind, source, ARTIFACT_EXTENSION), artifact).prepend_error("Failed to write cache entry:")?;
+        write(self.entry_path(kind, source, WARNINGS_EXTENSION), warnings).prepend_error("Failed to write cache entry:")?;
+        write(self.entry_path(kind, source, DEPENDENCIES_EXTENSION), lines.join("\n") + "\n").prepend_error("Failed to write cache entry:")?;
+
+        Ok(())
+    }
+}
+
+/// Content hashes of files by path, size and modification time
+///
+/// Like the verify cache, this trusts that a file changed only if its size or modification time
+/// did, which a file modified in place with its modification time restored doesn't.
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, (u64, u128, String)>,
+}
+
+/// Returns the size and modification time of a file.
+fn stamp(path: &Path) -> Option<(u64, u128)> {
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some((metadata.len(), modified))
+}
+
+impl HashCache {
+    /// Loads the hashes stored in the cache folder, starting empty if there are none.
+    pub fn load(directory: &Path) -> HashCache {
+        let path = directory.join(HASHES_FILE);
+        let entries = read_to_string(&path).unwrap_or_default().lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, ' ');
+                let (hash, size, modified, file) = (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+                Some((PathBuf::from(file), (size.parse().ok()?, modified.parse().ok()?, hash.to_string())))
+            })
+            .collect();
+        HashCache { path, entries }
+    }
+
+    /// Returns the stored hash of the file if its size and modification time didn't change.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        let (size, modified) = stamp(path)?;
+        self.entries.get(path)
+            .filter(|(s, m, _)| *s == size && *m == modified)
+            .map(|(_, _, hash)| hash.as_str())
+    }
+
+    /// Stores the hash of the data read from the file and returns it.
+    pub fn insert(&mut self, path: &Path, data: &[u8]) -> String {
+        let hash = sha1_hex(data);
+        if let Some((size, modified)) = stamp(path) {
+            self.entries.insert(path.to_path_buf(), (size, modified, hash.clone()));
+        }
+        hash
+    }
+
+    /// Writes the hashes back to the cache folder, under a temporary name first so a concurrent
+    /// pack never reads a partial file.
+    pub fn save(&self) -> Result<(), Error> {
+        let mut lines: Vec<String> = self.entries.iter()
+            .map(|(path, (size, modified, hash))| format!("{} {} {} {}", hash, size, modified, path.display()))
+            .collect();
+        lines.sort();
+
+        create_dir_all(self.path.parent().unwrap()).prepend_error("Failed to create cache folder:")?;
+        let temporary = self.path.with_extension(format!("{}.tmp", std::process::id()));
+        write(&temporary, lines.join("\n") + "\n").prepend_error("Failed to write cache entry:")?;
+        rename(&temporary, &self.path).prepend_error("Failed to write cache entry:")
+    }
+}
+
+/// Removes all entries from the cache folder that weren't used for longer than `max_age` and
+/// returns the number of removed entries.
+pub fn gc(directory: &Path, max_age: Duration) -> Result<usize, Error> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for entry in read_dir(directory).prepend_error("Failed to read cache folder:")? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(DEPENDENCIES_EXTENSION) {
+            continue;
+        }
+
+        let modified = path.metadata()?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() > max_age {
+            for extension in [ARTIFACT_EXTENSION, WARNINGS_EXTENSION] {
+                let file = path.with_extension(extension);
+                if file.exists() {
+                    remove_file(&file)?;
+                }
+            }
+            remove_file(&path)?;
+            removed += 1;
+        }
+    }
+
+    // Artifacts and warnings of interrupted writes
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|e| e.to_str());
+        if (extension == Some(ARTIFACT_EXTENSION) || extension == Some(WARNINGS_EXTENSION)) && !path.with_extension(DEPENDENCIES_EXTENSION).exists() {
+            remove_file(&path)?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes cache entries that weren't used for more than `days` days.
+pub fn cmd_cache_gc(directory: &Path, days: u64) -> Result<(), Error> {
+    if !directory.exists() {
+        return Err(coded_error!("E0815", "Cache folder {} doesn't exist.", directory.display()));
+    }
+
+    let removed = gc(directory, Duration::from_secs(days * 24 * 60 * 60))?;
+    println!("Removed {} cache {}.", removed, if removed == 1 { "entry" } else { "entries" });
+
+    Ok(())
+}
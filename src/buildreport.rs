@@ -0,0 +1,181 @@
+//! Summaries of built PBOs for release notes
+//!
+//! `build --report` writes what a build produced: each PBO with its size, the SHA-1 of its
+//! checksum trailer, which is the hash signatures sign, the authority of its signature and how
+//! many files, configs and textures went into it and warnings were raised. A build that failed
+//! is still reported, with the error instead of the hash.
+//!
+//! JSON and TOML are for release tooling, Markdown is a table to paste into a changelog.
+
+use std::fs::{File};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize};
+
+use crate::*;
+use crate::error::*;
+use crate::io::{create_file};
+use crate::pbo::{BuildStats, PBO};
+
+/// Version of the report format, changed when fields are renamed or removed
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Toml,
+    Markdown,
+}
+
+/// Parses a report format, `json`, `toml` or `md`.
+pub fn parse_report_format(input: &str) -> Result<ReportFormat, Error> {
+    match input.to_lowercase().as_str() {
+        "json" => Ok(ReportFormat::Json),
+        "toml" => Ok(ReportFormat::Toml),
+        "md" | "markdown" => Ok(ReportFormat::Markdown),
+        _ => Err(coded_error!("E0901", "Unknown report format \"{}\", expected json, toml or md.", input))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PboStatus {
+    Built,
+    Failed,
+}
+
+/// One PBO of a build
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PboReport {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub status: PboStatus,
+    /// Size of the file in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Checksum of the PBO as hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    /// Authority of the signature, if the PBO was signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+    pub files: usize,
+    pub configs: usize,
+    pub textures: usize,
+    pub warnings: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PboReport {
+    /// Reports a PBO that was written to `target`, reading its size and checksum from the file.
+    pub fn built(source: &Path, target: &Path, stats: &BuildStats, authority: Option<String>, warnings: u32) -> Result<PboReport, Error> {
+        let size = target.metadata().prepend_error(format!("Failed to read {}:", target.display()))?.len();
+        let pbo = PBO::read(&mut File::open(target)?).prepend_error(format!("Failed to read {}:", target.display()))?;
+        let sha1 = pbo.checksum.as_ref().map(|checksum| checksum.iter().map(|b| format!("{:02x}", b)).collect());
+
+        Ok(PboReport {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            status: PboStatus::Built,
+            size: Some(size),
+            sha1,
+            authority,
+            files: stats.files,
+            configs: stats.configs,
+            textures: stats.textures,
+            warnings,
+            error: None,
+        })
+    }
+
+    /// Reports a PBO whose build failed with the error.
+    pub fn failed(source: &Path, target: &Path, error: &Error, warnings: u32) -> PboReport {
+        PboReport {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            status: PboStatus::Failed,
+            size: None,
+            sha1: None,
+            authority: None,
+            files: 0,
+            configs: 0,
+            textures: 0,
+            warnings,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// All PBOs of a build
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildReport {
+    pub version: u32,
+    /// Version of armake2 that built the PBOs
+    pub armake2: String,
+    pub pbos: Vec<PboReport>,
+}
+
+impl BuildReport {
+    pub fn new(pbos: Vec<PboReport>) -> BuildReport {
+        BuildReport {
+            version: FORMAT_VERSION,
+            armake2: env!("CARGO_PKG_VERSION").to_string(),
+            pbos,
+        }
+    }
+
+    pub fn write<O: Write>(&self, format: ReportFormat, output: &mut O) -> Result<(), Error> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *output, self).map_err(Error::from)?;
+                writeln!(output)
+            },
+            ReportFormat::Toml => {
+                let content = toml::to_string(self).map_err(|e| coded_error!("E0816", "Failed to write TOML: {}", e))?;
+                output.write_all(content.as_bytes())
+            },
+            ReportFormat::Markdown => self.write_markdown(output)
+        }
+    }
+
+    /// Writes a table of the PBOs, followed by the errors of the failed ones.
+    fn write_markdown<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+        writeln!(output, "| PBO | Size | SHA-1 | Signed by | Files | Configs | Textures | Warnings |")?;
+        writeln!(output, "|---|---:|---|---|---:|---:|---:|---:|")?;
+        for pbo in &self.pbos {
+            let (size, sha1) = match pbo.status {
+                PboStatus::Built => (pbo.size.map(|s| s.to_string()).unwrap_or_default(), pbo.sha1.as_ref().map(|s| format!("`{}`", s)).unwrap_or_default()),
+                PboStatus::Failed => (String::new(), "**failed**".to_string()),
+            };
+            writeln!(output, "| {} | {} | {} | {} | {} | {} | {} | {} |", markdown_cell(&file_name(&pbo.target)), size, sha1,
+                markdown_cell(pbo.authority.as_deref().unwrap_or("")), pbo.files, pbo.configs, pbo.textures, pbo.warnings)?;
+        }
+
+        for pbo in self.pbos.iter().filter(|pbo| pbo.status == PboStatus::Failed) {
+            writeln!(output)?;
+            writeln!(output, "{} failed:", file_name(&pbo.target))?;
+            writeln!(output)?;
+            writeln!(output, "```")?;
+            writeln!(output, "{}", pbo.error.as_deref().unwrap_or(""))?;
+            writeln!(output, "```")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the report to a file.
+    pub fn write_to_file(&self, format: ReportFormat, path: &Path) -> Result<(), Error> {
+        let mut file = create_file(path).prepend_error("Failed to open report file:")?;
+        self.write(format, &mut file).prepend_error("Failed to write report file:")
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// Escapes the characters that would end a table cell.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
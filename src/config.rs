@@ -1,9 +1,14 @@
 //! Functions for rapifying and derapifying Arma configs
 
 use std::cmp::{min};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, metadata};
 use std::io::{Read, Seek, Write, SeekFrom, Error, Cursor, BufReader, BufWriter};
 use std::iter::{Sum};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
@@ -11,11 +16,10 @@ use crate::*;
 use crate::io::*;
 use crate::error::*;
 use crate::preprocess::*;
+use crate::schema::{Schema};
 
-pub mod config_grammar {
-    #![allow(missing_docs)]
-    include!(concat!(env!("OUT_DIR"), "/config_grammar.rs"));
-}
+#[path = "config_grammar.rs"]
+pub mod config_grammar;
 
 /// Config
 ///
@@ -37,19 +41,110 @@ pub mod config_grammar {
 #[derive(Debug)]
 pub struct Config {
     root_body: ConfigClass,
+    entry_offsets: Vec<u64>,
+}
+
+/// Name of a class or property, compared ignoring ASCII case like the engine does while keeping
+/// the spelling it was written with for output. Every lookup of config names by key uses it, so
+/// they all agree with each other and with `ConfigClass::get`.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigName(String);
+
+impl ConfigName {
+    pub fn new(name: &str) -> ConfigName {
+        ConfigName(name.to_string())
+    }
+
+    /// Returns the name as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether the name is the same as `other`, ignoring case.
+    pub fn matches(&self, other: &str) -> bool {
+        self.0.eq_ignore_ascii_case(other)
+    }
+
+    /// Returns the length of the name in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the name is empty, like the parent of a class that doesn't inherit.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn folded(&self) -> impl Iterator<Item = u8> + '_ {
+        self.0.bytes().map(|b| b.to_ascii_lowercase())
+    }
+}
+
+impl PartialEq for ConfigName {
+    fn eq(&self, other: &ConfigName) -> bool {
+        self.matches(&other.0)
+    }
+}
+
+impl Eq for ConfigName {}
+
+impl std::hash::Hash for ConfigName {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for b in self.folded() {
+            state.write_u8(b);
+        }
+        state.write_u8(0xFF);
+    }
+}
+
+impl PartialOrd for ConfigName {
+    fn partial_cmp(&self, other: &ConfigName) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ConfigName {
+    fn cmp(&self, other: &ConfigName) -> std::cmp::Ordering {
+        self.folded().cmp(other.folded())
+    }
+}
+
+impl From<&str> for ConfigName {
+    fn from(name: &str) -> ConfigName {
+        ConfigName::new(name)
+    }
+}
+
+impl From<String> for ConfigName {
+    fn from(name: String) -> ConfigName {
+        ConfigName(name)
+    }
+}
+
+impl AsRef<[u8]> for ConfigName {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl std::fmt::Display for ConfigName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 /// Config class
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConfigClass {
-    parent: String,
-    is_external: bool,
+    parent: ConfigName,
     is_deletion: bool,
-    entries: Option<Vec<(String, ConfigEntry)>>,
+    entries: Option<Vec<(ConfigName, ConfigEntry)>>,
+    /// Positions of the entries in the preprocessed input, empty unless the class was parsed
+    positions: Vec<usize>,
 }
 
 /// Config entry
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ConfigEntry {
     /// String entry
     StringEntry(String),
@@ -61,17 +156,20 @@ pub enum ConfigEntry {
     ArrayEntry(ConfigArray),
     /// Class entry
     ClassEntry(ConfigClass),
+    /// Declaration of a class defined in another config, like `class Vehicle;`. Classes can
+    /// inherit from it, but its entries are unknown.
+    ExternalClass,
 }
 
 /// Config array
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConfigArray {
     is_expansion: bool,
     elements: Vec<ConfigArrayElement>,
 }
 
 /// Config array element
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ConfigArrayElement {
     /// String element
     StringElement(String),
@@ -83,31 +181,395 @@ pub enum ConfigArrayElement {
     ArrayElement(ConfigArray),
 }
 
+/// Config classes whose classes are listed in an array of CfgPatches
+const CFGPATCHES_ARRAYS: [(&str, &str); 3] = [("CfgVehicles", "units"), ("CfgWeapons", "weapons"), ("CfgMagazines", "magazines")];
+
+/// Class defined in a config that isn't listed in its CfgPatches
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingPatchEntry {
+    /// Class containing the definition, like `CfgVehicles`
+    pub config_class: String,
+    /// CfgPatches array the class belongs in, like `units`
+    pub array: String,
+    /// Name of the class
+    pub class: String,
+}
+
+/// Line of derapified output mapped to the entry of the rapified config it was written from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMapEntry {
+    /// Line in the derapified output, starting at 1
+    pub line: usize,
+    /// Offset of the entry's type byte in the rapified config
+    pub offset: u64,
+    /// Path of the entry, with class names separated by `/`
+    pub path: String,
+}
+
+/// Options for parsing configs
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    /// Lint that checks values against the types expected for their property names
+    pub type_lint: Option<TypeLint>,
+    /// Fail instead of merging classes that are defined more than once in the same scope
+    pub strict_duplicates: bool,
+    /// Fail on `#pragma` lines and other leftovers of preprocessing instead of skipping them
+    pub strict_directives: bool,
+    /// Schema to warn about unknown property names with, see the `schema` module
+    pub schema: Option<Schema>,
+    /// Limits for preprocessing the config
+    pub limits: PreprocessLimits,
+}
+
+/// Options for writing rapified configs
+///
+/// The format stores every string and number inline next to its name, only class bodies are
+/// referenced by offset. Repeated strings can therefore only be shared as part of a repeated
+/// class body.
+#[derive(Debug, Default, Clone)]
+pub struct RapifyOptions {
+    /// Encoding of the strings
+    pub encoding: StringEncoding,
+    /// Round floats to this many significant digits. This is lossy, values like `0.30000001` and
+    /// `0.3` become the same, which lets more classes be shared with `share_classes`. Floats are
+    /// always stored in 4 bytes, so on its own this doesn't make the output smaller.
+    pub quantize_floats: Option<usize>,
+    /// Write identical class bodies once and point all classes with that body to the same copy.
+    /// The engine follows the offsets like any other, but this layout isn't produced by the
+    /// official tools.
+    pub share_classes: bool,
+}
+
+/// Rounds a float to the given number of significant digits.
+pub fn quantize_float(value: f32, digits: usize) -> f32 {
+    if digits == 0 || !value.is_finite() {
+        return value;
+    }
+    format!("{:.*e}", digits - 1, value).parse().unwrap_or(value)
+}
+
+/// Returns the shortest text that reads back as the same float, always with a decimal point and
+/// never with an exponent, since configs have neither exponents nor integer-looking floats.
+/// Negative zero keeps its sign. Configs can't express infinities and NaN, which only come from
+/// corrupt files, so infinities become the largest float of their sign and NaN becomes `0.0`.
+pub fn format_float(value: f32) -> String {
+    let value = if value.is_nan() { 0.0 } else if value.is_infinite() { f32::MAX.copysign(value) } else { value };
+    let text = format!("{:?}", value);
+    let Some((mantissa, exponent)) = text.split_once('e') else {
+        return text;
+    };
+
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa)
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", whole, fraction);
+    let point = whole.len() as i32 + exponent.parse::<i32>().unwrap();
+    if point <= 0 {
+        format!("{}0.{}{}", sign, "0".repeat(-point as usize), digits)
+    } else if point as usize >= digits.len() {
+        format!("{}{}{}.0", sign, digits, "0".repeat(point as usize - digits.len()))
+    } else {
+        format!("{}{}.{}", sign, &digits[..point as usize], &digits[point as usize..])
+    }
+}
+
+/// Adds a string with its length to the key of `ConfigClass::sharing_key`.
+fn extend_key_string(key: &mut Vec<u8>, s: &str) {
+    key.extend((s.len() as u64).to_le_bytes());
+    key.extend(s.as_bytes());
+}
+
+/// Warns about floats `format_float` doesn't write as they are, and negative zero, which the
+/// engine treats like zero.
+fn warn_special_float(value: f32, key: &str) {
+    let problem = if value.is_nan() {
+        "is NaN, written as 0.0"
+    } else if value.is_infinite() {
+        "is infinite, written as the largest float"
+    } else if value == 0.0 && value.is_sign_negative() {
+        "is negative zero"
+    } else {
+        return;
+    };
+    warning(format!("Value of \"{}\" {}.", key, problem), Some("special-float"), (None, None));
+}
+
+/// Encoding of the strings in rapified configs
+///
+/// Config sources are always read as UTF-8. Arma 3 expects the strings of rapified configs to be
+/// UTF-8 as well, older games used the ANSI codepage of the system.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum StringEncoding {
+    /// UTF-8
+    #[default]
+    Utf8,
+    /// Windows-1251 (Cyrillic)
+    Windows1251,
+    /// Windows-1252 (Western European)
+    Windows1252,
+}
+
+/// Characters of Windows-1251 from 0x80 to 0xFF, 0 where undefined
+const WINDOWS_1251: [u16; 128] = [
+    0x0402, 0x0403, 0x201A, 0x0453, 0x201E, 0x2026, 0x2020, 0x2021, 0x20AC, 0x2030, 0x0409, 0x2039, 0x040A, 0x040C, 0x040B, 0x040F,
+    0x0452, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x0000, 0x2122, 0x0459, 0x203A, 0x045A, 0x045C, 0x045B, 0x045F,
+    0x00A0, 0x040E, 0x045E, 0x0408, 0x00A4, 0x0490, 0x00A6, 0x00A7, 0x0401, 0x00A9, 0x0404, 0x00AB, 0x00AC, 0x00AD, 0x00AE, 0x0407,
+    0x00B0, 0x00B1, 0x0406, 0x0456, 0x0491, 0x00B5, 0x00B6, 0x00B7, 0x0451, 0x2116, 0x0454, 0x00BB, 0x0458, 0x0405, 0x0455, 0x0457,
+    0x0410, 0x0411, 0x0412, 0x0413, 0x0414, 0x0415, 0x0416, 0x0417, 0x0418, 0x0419, 0x041A, 0x041B, 0x041C, 0x041D, 0x041E, 0x041F,
+    0x0420, 0x0421, 0x0422, 0x0423, 0x0424, 0x0425, 0x0426, 0x0427, 0x0428, 0x0429, 0x042A, 0x042B, 0x042C, 0x042D, 0x042E, 0x042F,
+    0x0430, 0x0431, 0x0432, 0x0433, 0x0434, 0x0435, 0x0436, 0x0437, 0x0438, 0x0439, 0x043A, 0x043B, 0x043C, 0x043D, 0x043E, 0x043F,
+    0x0440, 0x0441, 0x0442, 0x0443, 0x0444, 0x0445, 0x0446, 0x0447, 0x0448, 0x0449, 0x044A, 0x044B, 0x044C, 0x044D, 0x044E, 0x044F,
+];
+
+/// Characters of Windows-1252 from 0x80 to 0x9F, 0 where undefined. The rest matches Latin-1.
+const WINDOWS_1252: [u16; 32] = [
+    0x20AC, 0x0000, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0x0000, 0x017D, 0x0000,
+    0x0000, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x0000, 0x017E, 0x0178,
+];
+
+impl StringEncoding {
+    fn decode_byte(&self, b: u8) -> Option<char> {
+        let code = match (self, b) {
+            (_, 0..=0x7F) => b as u16,
+            (StringEncoding::Windows1251, _) => WINDOWS_1251[(b - 0x80) as usize],
+            (StringEncoding::Windows1252, 0x80..=0x9F) => WINDOWS_1252[(b - 0x80) as usize],
+            (StringEncoding::Windows1252, _) => b as u16,
+            (StringEncoding::Utf8, _) => 0,
+        };
+        if code == 0 && b != 0 { None } else { char::from_u32(code as u32) }
+    }
+
+    fn encode_char(&self, c: char) -> Option<u8> {
+        if (c as u32) < 0x80 {
+            return Some(c as u8);
+        }
+        (0x80..=0xFF).find(|b| self.decode_byte(*b) == Some(c))
+    }
+
+    /// Returns the number of bytes the string takes when encoded. Characters that can't be
+    /// encoded count as one byte, `encode` fails on them.
+    fn encoded_len(&self, s: &str) -> usize {
+        match self {
+            StringEncoding::Utf8 => s.len(),
+            _ => s.chars().count(),
+        }
+    }
+
+    /// Encodes the string, failing if it contains characters the codepage doesn't have.
+    pub fn encode(&self, s: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            StringEncoding::Utf8 => Ok(s.as_bytes().to_vec()),
+            _ => s.chars().map(|c| self.encode_char(c)
+                .ok_or_else(|| coded_error!("E0108", "Character \"{}\" can't be encoded as {:?}.", c, self))).collect()
+        }
+    }
+
+    /// Decodes an encoded string.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, Error> {
+        match self {
+            StringEncoding::Utf8 => String::from_utf8(bytes.to_vec())
+                .map_err(|_| coded_error!("E0108", "String \"{}\" is not valid UTF-8. It was probably written with an ANSI codepage, see --string-encoding.", String::from_utf8_lossy(bytes))),
+            _ => bytes.iter().map(|b| self.decode_byte(*b)
+                .ok_or_else(|| coded_error!("E0108", "Byte 0x{:02X} is not defined in {:?}.", b, self))).collect()
+        }
+    }
+}
+
+/// Parses the name of a string encoding: `utf8`, `cp1251` or `cp1252`.
+pub fn parse_string_encoding(input: &str) -> Result<StringEncoding, Error> {
+    match input.to_lowercase().replace(['-', '_'], "").as_str() {
+        "utf8" => Ok(StringEncoding::Utf8),
+        "cp1251" | "windows1251" => Ok(StringEncoding::Windows1251),
+        "cp1252" | "windows1252" => Ok(StringEncoding::Windows1252),
+        _ => Err(coded_error!("E0901", "Unknown string encoding: {}. Supported encodings: utf8, cp1251, cp1252", input))
+    }
+}
+
+/// Class defined or declared more than once in one scope
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct DuplicateClass {
+    /// Position of the first definition
+    first: usize,
+    /// Position of the merged definition
+    second: usize,
+    /// Name as spelled in the first definition
+    first_name: String,
+    /// Name as spelled in the merged definition
+    name: String,
+    /// Whether both have a body, rather than one of them being a forward declaration or a
+    /// class nested in one of the merged definitions
+    bodies: bool,
+}
+
+/// Merges classes that are defined multiple times in the same scope into their first definition
+/// like the engine does, recording each merged definition with a body and each one spelled
+/// differently in `duplicates`. Returns the entries along with their positions.
+pub(crate) fn merge_duplicate_classes(entries: Vec<(usize, ConfigName, ConfigEntry)>, duplicates: &mut HashSet<DuplicateClass>) -> (Vec<(ConfigName, ConfigEntry)>, Vec<usize>) {
+    let mut result: Vec<(ConfigName, ConfigEntry)> = Vec::with_capacity(entries.len());
+    let mut positions: Vec<usize> = Vec::with_capacity(entries.len());
+    // Index of the mergeable entry of each name, mergeable entries stay so when merged into
+    let mut classes: HashMap<ConfigName, usize> = HashMap::new();
+
+    for (pos, name, entry) in entries {
+        let existing = classes.get(&name).copied();
+        match (existing, entry) {
+            (Some(index), entry) if is_mergeable(&entry) => {
+                let (first_name, target) = &mut result[index];
+                // Forward declarations are expected, only merging two bodies is worth a warning
+                let bodies = matches!((&*target, &entry), (ConfigEntry::ClassEntry(_), ConfigEntry::ClassEntry(_)));
+                if bodies || first_name.as_str() != name.as_str() {
+                    duplicates.insert(DuplicateClass { first: positions[index], second: pos, first_name: first_name.to_string(), name: name.to_string(), bodies });
+                }
+                merge_class_entry(target, entry, duplicates);
+            },
+            (_, entry) => {
+                if is_mergeable(&entry) {
+                    classes.insert(name.clone(), result.len());
+                }
+                result.push((name, entry));
+                positions.push(pos);
+            }
+        }
+    }
+
+    (result, positions)
+}
+
+/// Returns whether the entry is a class or a declaration of one, which later definitions of the
+/// same class are merged into.
+fn is_mergeable(entry: &ConfigEntry) -> bool {
+    matches!(entry, ConfigEntry::ClassEntry(c) if !c.is_deletion) || matches!(entry, ConfigEntry::ExternalClass)
+}
+
+/// Merges a later definition or declaration of a class into an earlier one, both mergeable. A
+/// definition replaces a declaration, while a declaration doesn't change anything.
+fn merge_class_entry(target: &mut ConfigEntry, entry: ConfigEntry, duplicates: &mut HashSet<DuplicateClass>) {
+    match (target, entry) {
+        (ConfigEntry::ClassEntry(target), ConfigEntry::ClassEntry(class)) => target.merge(class, duplicates),
+        (target, entry @ ConfigEntry::ClassEntry(_)) => *target = entry,
+        _ => {}
+    }
+}
+
+/// Opt-in lint for values whose type likely doesn't match what the engine expects.
+///
+/// Warns about strings that look like numbers (`reloadTime = "0.1";`) and about bare numbers
+/// assigned to properties that are known to take strings.
+#[derive(Debug, Clone)]
+pub struct TypeLint {
+    string_properties: HashSet<ConfigName>,
+}
+
+const STRING_PROPERTIES: &[&str] = &[
+    "author", "description", "descriptionShort", "displayName", "displayNameShort", "icon",
+    "model", "picture", "text", "textPlural", "textSingular", "title", "tooltip", "versionStr",
+];
+
+impl Default for TypeLint {
+    fn default() -> TypeLint {
+        TypeLint {
+            string_properties: STRING_PROPERTIES.iter().map(|s| ConfigName::new(s)).collect(),
+        }
+    }
+}
+
+impl TypeLint {
+    /// Adds the property names from the given file to the list of properties that take strings.
+    ///
+    /// The file contains one property name per line, empty lines and lines starting with `//`
+    /// are ignored.
+    pub fn extend_from_file(&mut self, path: &Path) -> Result<(), Error> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+
+        for line in content.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with("//") { continue; }
+
+            self.string_properties.insert(ConfigName::new(line));
+        }
+
+        Ok(())
+    }
+
+    /// Checks a single entry, `pos` being the position of the entry in the preprocessed input.
+    pub fn check(&self, name: &ConfigName, entry: &ConfigEntry, pos: usize, warnings: &mut Vec<(usize, String, Option<&'static str>)>) {
+        let is_string_property = self.string_properties.contains(name);
+        let name = name.as_str();
+
+        match entry {
+            ConfigEntry::StringEntry(s) => {
+                let numeric = !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || "+-.".contains(c)) && s.parse::<f32>().is_ok();
+                if numeric && !is_string_property {
+                    warnings.push((pos, format!("String value \"{}\" of property \"{}\" looks like a number.", s, name), Some("string-looks-numeric")));
+                }
+            },
+            ConfigEntry::FloatEntry(_) | ConfigEntry::IntEntry(_) if is_string_property => {
+                warnings.push((pos, format!("Property \"{}\" expects a string but is assigned a number.", name), Some("number-in-string-property")));
+            },
+            _ => {}
+        }
+    }
+}
+
 impl ConfigArrayElement {
-    fn rapified_length(&self) -> usize {
+    fn rapified_length(&self, encoding: StringEncoding) -> usize {
         match self {
-            ConfigArrayElement::StringElement(s) => s.len() + 2,
+            ConfigArrayElement::StringElement(s) => encoding.encoded_len(s) + 2,
             ConfigArrayElement::FloatElement(_f) => 5,
             ConfigArrayElement::IntElement(_i) => 5,
             ConfigArrayElement::ArrayElement(a) => 1 + compressed_int_len(a.elements.len() as u32) +
-                usize::sum(a.elements.iter().map(|e| e.rapified_length()))
+                usize::sum(a.elements.iter().map(|e| e.rapified_length(encoding)))
         }
     }
 }
 
 impl ConfigArray {
-    fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+    fn extend_sharing_key(&self, key: &mut Vec<u8>) {
+        key.push(u8::from(self.is_expansion));
+        key.extend((self.elements.len() as u64).to_le_bytes());
+        for element in &self.elements {
+            match element {
+                ConfigArrayElement::StringElement(s) => {
+                    key.push(0);
+                    extend_key_string(key, s);
+                },
+                ConfigArrayElement::FloatElement(f) => {
+                    key.push(1);
+                    key.extend(f.to_bits().to_le_bytes());
+                },
+                ConfigArrayElement::IntElement(i) => {
+                    key.push(2);
+                    key.extend(i.to_le_bytes());
+                },
+                ConfigArrayElement::ArrayElement(a) => {
+                    key.push(3);
+                    a.extend_sharing_key(key);
+                },
+            }
+        }
+    }
+
+    /// Returns the elements of the array.
+    pub fn elements(&self) -> &[ConfigArrayElement] {
+        &self.elements
+    }
+
+    /// Writes the array as config source. `name` is the name of the entry, for warnings.
+    fn write<O: Write>(&self, output: &mut O, name: &str) -> Result<(), Error> {
         output.write_all(b"{")?;
         for (key, value) in self.elements.iter().enumerate() {
             match value {
                 ConfigArrayElement::ArrayElement(a) => {
-                    a.write(output)?;
+                    a.write(output, name)?;
                 },
                 ConfigArrayElement::StringElement(s) => {
-                    output.write_all(format!("\"{}\"", s.replace("\r", "\\r").replace("\n", "\\n").replace("\"", "\"\"")).as_bytes())?;
+                    output.write_all(quote_string(s).as_bytes())?;
                 },
                 ConfigArrayElement::FloatElement(f) => {
-                    output.write_all(format!("{:?}", f).as_bytes())?;
+                    warn_special_float(*f, name);
+                    output.write_all(format_float(*f).as_bytes())?;
                 },
                 ConfigArrayElement::IntElement(i) => {
                     output.write_all(format!("{}", i).as_bytes())?;
@@ -121,15 +583,16 @@ impl ConfigArray {
         Ok(())
     }
 
-    fn write_rapified<O: Write>(&self, output: &mut O) -> Result<usize, Error> {
-        let mut written = output.write_compressed_int(self.elements.len() as u32)?;
+    fn write_rapified<O: Write>(&self, output: &mut O, encoding: StringEncoding) -> Result<usize, Error> {
+        let mut written = output.write_compressed_int(field_u32(self.elements.len(), "Number of array elements")?)?;
 
         for element in &self.elements {
             match element {
                 ConfigArrayElement::StringElement(s) => {
+                    let encoded = encoding.encode(s)?;
                     output.write_all(&[0])?;
-                    output.write_cstring(s)?;
-                    written += s.len() + 2;
+                    output.write_cstring(&encoded)?;
+                    written += encoded.len() + 2;
                 },
                 ConfigArrayElement::FloatElement(f) => {
                     output.write_all(&[1])?;
@@ -143,7 +606,7 @@ impl ConfigArray {
                 },
                 ConfigArrayElement::ArrayElement(a) => {
                     output.write_all(&[3])?;
-                    written += 1 + a.write_rapified(output)?;
+                    written += 1 + a.write_rapified(output, encoding)?;
                 }
             }
         }
@@ -151,7 +614,17 @@ impl ConfigArray {
         Ok(written)
     }
 
-    fn read_rapified<I: Read + Seek>(input: &mut I) -> Result<ConfigArray, Error> {
+    fn quantize_floats(&mut self, digits: usize) {
+        for element in &mut self.elements {
+            match element {
+                ConfigArrayElement::FloatElement(f) => *f = quantize_float(*f, digits),
+                ConfigArrayElement::ArrayElement(a) => a.quantize_floats(digits),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_rapified<I: Read + Seek>(input: &mut I, encoding: StringEncoding) -> Result<ConfigArray, Error> {
         let num_elements: u32 = input.read_compressed_int()?;
         let mut elements: Vec<ConfigArrayElement> = Vec::with_capacity(num_elements as usize);
 
@@ -159,15 +632,15 @@ impl ConfigArray {
             let element_type: u8 = input.bytes().next().unwrap()?;
 
             if element_type == 0 {
-                elements.push(ConfigArrayElement::StringElement(input.read_cstring()?));
+                elements.push(ConfigArrayElement::StringElement(encoding.decode(&input.read_cstring_bytes()?)?));
             } else if element_type == 1 {
                 elements.push(ConfigArrayElement::FloatElement(input.read_f32::<LittleEndian>()?));
             } else if element_type == 2 {
                 elements.push(ConfigArrayElement::IntElement(input.read_i32::<LittleEndian>()?));
             } else if element_type == 3 {
-                elements.push(ConfigArrayElement::ArrayElement(ConfigArray::read_rapified(input)?));
+                elements.push(ConfigArrayElement::ArrayElement(ConfigArray::read_rapified(input, encoding)?));
             } else {
-                return Err(error!("Unrecognized array element type: {}", element_type));
+                return Err(coded_error!("E0107", "Unrecognized array element type: {}", element_type));
             }
         }
 
@@ -180,92 +653,146 @@ impl ConfigArray {
 
 impl ConfigEntry {
     // without the name
-    fn rapified_length(&self) -> usize {
+    fn rapified_length(&self, encoding: StringEncoding) -> usize {
         match self {
-            ConfigEntry::StringEntry(s) => s.len() + 3,
+            ConfigEntry::StringEntry(s) => encoding.encoded_len(s) + 3,
             ConfigEntry::FloatEntry(_f) => 6,
             ConfigEntry::IntEntry(_i) => 6,
             ConfigEntry::ArrayEntry(a) => {
                 let len = 1 + compressed_int_len(a.elements.len() as u32) +
-                    usize::sum(a.elements.iter().map(|e| e.rapified_length()));
+                    usize::sum(a.elements.iter().map(|e| e.rapified_length(encoding)));
                 if a.is_expansion { len + 4 } else { len }
             },
             ConfigEntry::ClassEntry(c) => {
-                if c.is_external || c.is_deletion { 1 } else { 5 }
-            }
+                if c.is_deletion { 1 } else { 5 }
+            },
+            ConfigEntry::ExternalClass => 1,
         }
     }
 }
 
 impl ConfigClass {
-    fn write<O: Write>(&self, mut output: &mut O, level: i32) -> Result<(), Error> {
-        match &self.entries {
-            Some(entries) => {
-                if level > 0 && !entries.is_empty() {
-                    output.write_all(b"\n")?;
-                }
-                for (key, value) in entries {
-                    output.write_all(String::from("    ").repeat(level as usize).as_bytes())?;
+    /// Merges a later definition of the same class into this one. Properties of the later
+    /// definition override earlier ones, nested classes are merged recursively. A forward
+    /// declaration (`class Foo;`) doesn't change a definition and is replaced by one. Nested
+    /// classes spelled differently in both are recorded in `duplicates`.
+    fn merge(&mut self, other: ConfigClass, duplicates: &mut HashSet<DuplicateClass>) {
+        let other_entries = match other.entries {
+            Some(entries) => entries,
+            None => return
+        };
+
+        if !other.parent.is_empty() {
+            self.parent = other.parent;
+        }
 
-                    match value {
-                        ConfigEntry::ClassEntry(c) => {
-                            if c.is_deletion {
-                                output.write_all(format!("delete {};\n", key).as_bytes())?;
-                            } else if c.is_external {
-                                output.write_all(format!("class {};\n", key).as_bytes())?;
-                            } else {
-                                let parent = if c.parent == "" { String::from("") } else { format!(": {}", c.parent) };
-                                match &c.entries {
-                                    Some(entries) => {
-                                        if !entries.is_empty() {
-                                            output.write_all(format!("class {}{} {{", key, parent).as_bytes())?;
-                                            c.write(output, level + 1)?;
-                                            output.write_all(String::from("    ").repeat(level as usize).as_bytes())?;
-                                            output.write_all(b"};\n")?;
-                                        } else {
-                                            output.write_all(format!("class {}{} {{}};\n", key, parent).as_bytes())?;
-                                        }
-                                    },
-                                    None => {
-                                        output.write_all(format!("class {}{} {{}};\n", key, parent).as_bytes())?;
-                                    },
-                                }
-                            }
-                        },
-                        ConfigEntry::StringEntry(s) => {
-                            output.write_all(format!("{} = \"{}\";\n", key, s.replace("\r", "\\r").replace("\n", "\\n").replace("\"", "\"\"")).as_bytes())?;
-                        },
-                        ConfigEntry::FloatEntry(f) => {
-                            output.write_all(format!("{} = {:?};\n", key, f).as_bytes())?;
-                        },
-                        ConfigEntry::IntEntry(i) => {
-                            output.write_all(format!("{} = {};\n", key, i).as_bytes())?;
-                        },
-                        ConfigEntry::ArrayEntry(a) => {
-                            if a.is_expansion {
-                                output.write_all(format!("{}[] += ", key).as_bytes())?;
-                            } else {
-                                output.write_all(format!("{}[] = ", key).as_bytes())?;
-                            }
-                            a.write(&mut output)?;
-                            output.write_all(b";\n")?;
-                        },
+        let entries = self.entries.get_or_insert_with(Vec::new);
+        // Index of the first entry of each name
+        let mut indexes: HashMap<ConfigName, usize> = HashMap::new();
+        for (i, (name, _)) in entries.iter().enumerate() {
+            indexes.entry(name.clone()).or_insert(i);
+        }
+
+        for (i, (name, entry)) in other_entries.into_iter().enumerate() {
+            let position = other.positions.get(i).copied();
+            let existing = indexes.get(&name).copied();
+            match (existing, entry) {
+                (Some(index), entry) if is_mergeable(&entries[index].1) && is_mergeable(&entry) => {
+                    let (first_name, target) = &mut entries[index];
+                    if let (true, Some(first), Some(second)) = (first_name.as_str() != name.as_str(), self.positions.get(index), position) {
+                        duplicates.insert(DuplicateClass { first: *first, second, first_name: first_name.to_string(), name: name.to_string(), bodies: false });
+                    }
+                    merge_class_entry(target, entry, duplicates);
+                },
+                (Some(index), entry) => {
+                    entries[index].1 = entry;
+                    if let (Some(position), Some(slot)) = (position, self.positions.get_mut(index)) {
+                        *slot = position;
                     }
+                },
+                (None, entry) => {
+                    indexes.insert(name.clone(), entries.len());
+                    entries.push((name, entry));
+                    self.positions.extend(position);
                 }
-            },
-            None => {}
+            }
+        }
+    }
+
+    /// Returns the name of the parent class, empty if the class doesn't inherit.
+    pub fn parent(&self) -> &str {
+        self.parent.as_str()
+    }
+
+    /// Returns whether this is a `delete` statement rather than a class.
+    pub fn is_deletion(&self) -> bool {
+        self.is_deletion
+    }
+
+    /// Returns the entries of the class, `None` for deletions.
+    pub fn entries(&self) -> Option<&[(ConfigName, ConfigEntry)]> {
+        self.entries.as_deref()
+    }
+
+    /// Returns the position of the entry with the given index in the preprocessed input, if the
+    /// class was parsed.
+    pub(crate) fn entry_position(&self, index: usize) -> Option<usize> {
+        match &self.entries {
+            Some(entries) if entries.len() == self.positions.len() => self.positions.get(index).copied(),
+            _ => None
+        }
+    }
+
+    /// Returns the entry with the given name, ignoring case like the engine does.
+    pub fn get(&self, name: &str) -> Option<&ConfigEntry> {
+        self.entries()?.iter().find(|(n, _)| n.matches(name)).map(|(_, e)| e)
+    }
+
+    /// Returns whether a class with the given name is defined or declared in this class.
+    fn has_class(&self, name: &str) -> bool {
+        self.entries().unwrap_or_default().iter().any(|(n, e)| n.matches(name) && is_mergeable(e))
+    }
+
+    fn write<O: Write>(&self, output: &mut O, level: i32, comments: &mut Option<IncludeComments>) -> Result<(), Error> {
+        if let Some(entries) = &self.entries {
+            if level > 0 && !entries.is_empty() {
+                output.write_all(b"\n")?;
+            }
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if let Some(comments) = comments && let Some(position) = self.positions.get(i) {
+                    let includes = comments.includes_at(*position);
+                    comments.switch(output, includes, level)?;
+                }
+                write_entry(output, key.as_str(), value, level, comments)?;
+            }
         }
 
         Ok(())
     }
 
-    fn rapified_length(&self) -> usize {
+    /// Calls `f` with the index, output line and path of every entry in the order they are
+    /// read from and written to a rapified config.
+    fn visit_entries<'a, F: FnMut(usize, usize, &str, &'a ConfigEntry)>(&'a self, prefix: &str, index: &mut usize, line: &mut usize, f: &mut F) {
+        for (name, entry) in self.entries().unwrap_or(&[]) {
+            let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+            f(*index, *line, &path, entry);
+            *index += 1;
+            *line += 1;
+
+            if let ConfigEntry::ClassEntry(c) = entry && !c.is_deletion && !c.entries().unwrap_or(&[]).is_empty() {
+                c.visit_entries(&path, index, line, f);
+                *line += 1;
+            }
+        }
+    }
+
+    fn rapified_length(&self, encoding: StringEncoding) -> usize {
         match &self.entries {
             Some(entries) => self.parent.len() + 1 +
                 compressed_int_len(entries.len() as u32) +
                 usize::sum(entries.iter().map(|(k,v)| {
-                    k.len() + 1 + v.rapified_length() + match v {
-                        ConfigEntry::ClassEntry(c) => c.rapified_length(),
+                    k.len() + 1 + v.rapified_length(encoding) + match v {
+                        ConfigEntry::ClassEntry(c) => c.rapified_length(encoding),
                         _ => 0
                     }
                 })),
@@ -273,7 +800,10 @@ impl ConfigClass {
         }
     }
 
-    fn write_rapified<O: Write>(&self, output: &mut O, offset: usize) -> Result<usize, Error> {
+    /// Writes the rapified class body at the given offset of the file. With `shared`, class
+    /// bodies identical to one that was already written point to the earlier copy instead of
+    /// being written again, `shared` maps the unrapified body to its offset.
+    fn write_rapified<O: Write>(&self, output: &mut O, offset: usize, encoding: StringEncoding, shared: &mut Option<HashMap<Vec<u8>, usize>>) -> Result<usize, Error> {
         let mut written = 0;
 
         match &self.entries {
@@ -281,21 +811,22 @@ impl ConfigClass {
                 output.write_cstring(&self.parent)?;
                 written += self.parent.len() + 1;
 
-                written += output.write_compressed_int(entries.len() as u32)?;
+                written += output.write_compressed_int(field_u32(entries.len(), "Number of class entries")?)?;
 
-                let entries_len = usize::sum(entries.iter().map(|(k,v)| k.len() + 1 + v.rapified_length()));
+                let entries_len = usize::sum(entries.iter().map(|(k,v)| k.len() + 1 + v.rapified_length(encoding)));
                 let mut class_offset = offset + written + entries_len;
-                let mut class_bodies: Vec<Cursor<Box<[u8]>>> = Vec::new();
+                let mut class_bodies: Vec<Vec<u8>> = Vec::new();
                 let pre_entries = written;
 
                 for (name, entry) in entries {
                     let pre_write = written;
                     match entry {
                         ConfigEntry::StringEntry(s) => {
+                            let encoded = encoding.encode(s).prepend_error(format!("Failed to encode {}:", name))?;
                             output.write_all(&[1, 0])?;
                             output.write_cstring(name)?;
-                            output.write_cstring(s)?;
-                            written += name.len() + s.len() + 4;
+                            output.write_cstring(&encoded)?;
+                            written += name.len() + encoded.len() + 4;
                         },
                         ConfigEntry::FloatEntry(f) => {
                             output.write_all(&[1, 1])?;
@@ -316,35 +847,47 @@ impl ConfigClass {
                                 written += 4;
                             }
                             output.write_cstring(name)?;
-                            written += name.len() + 2 + a.write_rapified(output)?;
+                            written += name.len() + 2 + a.write_rapified(output, encoding).prepend_error(format!("Failed to encode {}:", name))?;
+                        },
+                        ConfigEntry::ExternalClass => {
+                            output.write_all(&[3])?;
+                            output.write_cstring(name)?;
+                            written += name.len() + 2;
                         },
                         ConfigEntry::ClassEntry(c) => {
-                            if c.is_external || c.is_deletion {
-                                output.write_all(if c.is_deletion { &[4] } else { &[3] })?;
+                            if c.is_deletion {
+                                output.write_all(&[4])?;
                                 output.write_cstring(name)?;
                                 written += name.len() + 2;
                             } else {
+                                let key = shared.is_some().then(|| c.sharing_key());
+                                let existing = key.as_ref().and_then(|k| shared.as_ref()?.get(k).copied());
+
                                 output.write_all(&[0])?;
                                 output.write_cstring(name)?;
-                                output.write_u32::<LittleEndian>(class_offset as u32)?;
+                                output.write_u32::<LittleEndian>(field_u32(existing.unwrap_or(class_offset), &format!("Offset of class {}", name))?)?;
                                 written += name.len() + 6;
 
-                                let buffer: Box<[u8]> = vec![0; c.rapified_length()].into_boxed_slice();
-                                let mut cursor: Cursor<Box<[u8]>> = Cursor::new(buffer);
-                                class_offset += c.write_rapified(&mut cursor, class_offset).prepend_error(format!("Failed to rapify {}:",name))?;
-
-                                class_bodies.push(cursor);
+                                if existing.is_none() {
+                                    let mut body: Vec<u8> = Vec::with_capacity(c.rapified_length(encoding));
+                                    c.write_rapified(&mut body, class_offset, encoding, shared).prepend_error(format!("Failed to rapify {}:",name))?;
+                                    if let (Some(shared), Some(key)) = (shared.as_mut(), key) {
+                                        shared.insert(key, class_offset);
+                                    }
+                                    class_offset += body.len();
+                                    class_bodies.push(body);
+                                }
                             }
                         }
                     }
-                    assert_eq!(written - pre_write, entry.rapified_length() + name.len() + 1);
+                    assert_eq!(written - pre_write, entry.rapified_length(encoding) + name.len() + 1);
                 }
 
                 assert_eq!(written - pre_entries, entries_len);
 
-                for cursor in class_bodies {
-                    output.write_all(cursor.get_ref())?;
-                    written += cursor.get_ref().len();
+                for body in class_bodies {
+                    output.write_all(&body)?;
+                    written += body.len();
                 }
             },
             None => { unreachable!() }
@@ -353,7 +896,63 @@ impl ConfigClass {
         Ok(written)
     }
 
-    fn read_rapified<I: Read + Seek>(input: &mut I, level: u32) -> Result<ConfigClass, Error> {
+    /// Returns what identifies the rapified body of the class: its parent and its entries, which
+    /// also covers the entries of nested classes. Unlike writing the class as text, this raises
+    /// no warnings and keeps floats apart that are written the same, like NaN and `0.0`.
+    fn sharing_key(&self) -> Vec<u8> {
+        let mut key = Vec::new();
+        self.extend_sharing_key(&mut key);
+        key
+    }
+
+    fn extend_sharing_key(&self, key: &mut Vec<u8>) {
+        extend_key_string(key, self.parent.as_str());
+        key.push(u8::from(self.is_deletion));
+
+        let entries = self.entries().unwrap_or_default();
+        key.extend((entries.len() as u64).to_le_bytes());
+        for (name, entry) in entries {
+            extend_key_string(key, name.as_str());
+            match entry {
+                ConfigEntry::StringEntry(s) => {
+                    key.push(0);
+                    extend_key_string(key, s);
+                },
+                ConfigEntry::FloatEntry(f) => {
+                    key.push(1);
+                    key.extend(f.to_bits().to_le_bytes());
+                },
+                ConfigEntry::IntEntry(i) => {
+                    key.push(2);
+                    key.extend(i.to_le_bytes());
+                },
+                ConfigEntry::ArrayEntry(a) => {
+                    key.push(3);
+                    a.extend_sharing_key(key);
+                },
+                ConfigEntry::ClassEntry(c) => {
+                    key.push(4);
+                    c.extend_sharing_key(key);
+                },
+                ConfigEntry::ExternalClass => key.push(5),
+            }
+        }
+    }
+
+    /// Rounds all floats of the class and its nested classes to the given number of significant
+    /// digits.
+    fn quantize_floats(&mut self, digits: usize) {
+        for (_, entry) in self.entries.iter_mut().flatten() {
+            match entry {
+                ConfigEntry::FloatEntry(f) => *f = quantize_float(*f, digits),
+                ConfigEntry::ArrayEntry(a) => a.quantize_floats(digits),
+                ConfigEntry::ClassEntry(c) => c.quantize_floats(digits),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_rapified<I: Read + Seek>(input: &mut I, level: u32, offsets: &mut Vec<u64>, encoding: StringEncoding) -> Result<ConfigClass, Error> {
         let mut fp = 0;
         if level == 0 {
             input.seek(SeekFrom::Start(16))?;
@@ -366,29 +965,31 @@ impl ConfigClass {
 
         let parent = input.read_cstring()?;
         let num_entries: u32 = input.read_compressed_int()?;
-        let mut entries: Vec<(String, ConfigEntry)> = Vec::with_capacity(num_entries as usize);
+        let mut entries: Vec<(ConfigName, ConfigEntry)> = Vec::with_capacity(num_entries as usize);
 
         for _i in 0..num_entries {
+            offsets.push(input.stream_position()?);
             let entry_type: u8 = input.bytes().next().unwrap()?;
 
             if entry_type == 0 {
                 let name = input.read_cstring()?;
 
-                let class_entry = ConfigClass::read_rapified(input, level + 1)
+                let class_entry = ConfigClass::read_rapified(input, level + 1, offsets, encoding)
                     .prepend_error(format!("Failed to read rapified class \"{}\":", name))?;
-                entries.push((name, ConfigEntry::ClassEntry(class_entry)));
+                entries.push((name.into(), ConfigEntry::ClassEntry(class_entry)));
             } else if entry_type == 1 {
                 let subtype: u8 = input.bytes().next().unwrap()?;
                 let name = input.read_cstring()?;
 
                 if subtype == 0 {
-                    entries.push((name, ConfigEntry::StringEntry(input.read_cstring()?)));
+                    let value = encoding.decode(&input.read_cstring_bytes()?).prepend_error(format!("Failed to decode {}:", name))?;
+                    entries.push((name.into(), ConfigEntry::StringEntry(value)));
                 } else if subtype == 1 {
-                    entries.push((name, ConfigEntry::FloatEntry(input.read_f32::<LittleEndian>()?)));
+                    entries.push((name.into(), ConfigEntry::FloatEntry(input.read_f32::<LittleEndian>()?)));
                 } else if subtype == 2 {
-                    entries.push((name, ConfigEntry::IntEntry(input.read_i32::<LittleEndian>()?)));
+                    entries.push((name.into(), ConfigEntry::IntEntry(input.read_i32::<LittleEndian>()?)));
                 } else {
-                    return Err(error!("Unrecognized variable entry subtype: {}.", subtype));
+                    return Err(coded_error!("E0107", "Unrecognized variable entry subtype: {}.", subtype));
                 }
             } else if entry_type == 2 || entry_type == 5 {
                 if entry_type == 5 {
@@ -396,22 +997,25 @@ impl ConfigClass {
                 }
 
                 let name = input.read_cstring()?;
-                let mut array = ConfigArray::read_rapified(input).prepend_error("Failed to read rapified array:")?;
+                let mut array = ConfigArray::read_rapified(input, encoding).prepend_error("Failed to read rapified array:")?;
                 array.is_expansion = entry_type == 5;
 
-                entries.push((name.clone(), ConfigEntry::ArrayEntry(array)));
-            } else if entry_type == 3 || entry_type == 4 {
+                entries.push((name.into(), ConfigEntry::ArrayEntry(array)));
+            } else if entry_type == 3 {
+                entries.push((input.read_cstring()?.into(), ConfigEntry::ExternalClass));
+            } else if entry_type == 4 {
+                // 4 deletes a class, 5 is the array expansion above
                 let name = input.read_cstring()?;
                 let class_entry = ConfigClass {
-                    parent: String::from(""),
-                    is_external: entry_type == 3,
-                    is_deletion: entry_type == 5,
-                    entries: None
+                    parent: ConfigName::default(),
+                    is_deletion: true,
+                    entries: None,
+                    positions: Vec::new(),
                 };
 
-                entries.push((name.clone(), ConfigEntry::ClassEntry(class_entry)));
+                entries.push((name.into(), ConfigEntry::ClassEntry(class_entry)));
             } else {
-                return Err(error!("Unrecognized class entry type: {}.", entry_type));
+                return Err(coded_error!("E0107", "Unrecognized class entry type: {}.", entry_type));
             }
         }
 
@@ -420,18 +1024,37 @@ impl ConfigClass {
         }
 
         Ok(ConfigClass {
-            parent,
-            is_external: false,
+            parent: parent.into(),
             is_deletion: false,
             entries: Some(entries),
+            positions: Vec::new(),
         })
     }
 }
 
 impl Config {
+    /// Returns the root class of the config.
+    pub fn root(&self) -> &ConfigClass {
+        &self.root_body
+    }
+
     /// Writes the config (unrapified) to the output.
     pub fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
-        self.root_body.write(output, 0)
+        self.root_body.write(output, 0, &mut None)
+    }
+
+    /// Writes the config (unrapified) like `write`, with comments where the entries of every
+    /// included file begin and end. `preprocessed` and `info` have to be what the config was
+    /// parsed from. Included paths are written relative to `base` if they are inside it.
+    pub fn write_with_includes<O: Write>(&self, output: &mut O, preprocessed: &str, info: &PreprocessInfo, base: Option<&Path>) -> Result<(), Error> {
+        let mut comments = Some(IncludeComments {
+            newlines: preprocessed.match_indices('\n').map(|(i, _)| i).collect(),
+            info,
+            base,
+            open: Vec::new(),
+        });
+        self.root_body.write(output, 0, &mut comments)?;
+        comments.unwrap().switch(output, Vec::new(), 0)
     }
 
     /// Returns the unrapified config as a string.
@@ -445,28 +1068,59 @@ impl Config {
 
     /// Writes the rapified config to the output.
     pub fn write_rapified<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+        self.write_rapified_with_encoding(output, StringEncoding::default())
+    }
+
+    /// Writes the rapified config to the output like `write_rapified`, storing strings with the
+    /// given encoding.
+    pub fn write_rapified_with_encoding<O: Write>(&self, output: &mut O, encoding: StringEncoding) -> Result<(), Error> {
+        self.write_rapified_with_options(output, &RapifyOptions { encoding, ..Default::default() }).map(|_| ())
+    }
+
+    /// Writes the rapified config to the output like `write_rapified`, using the given options.
+    /// Returns the number of bytes written.
+    ///
+    /// Floats are quantized as part of writing, the config itself is left unchanged.
+    pub fn write_rapified_with_options<O: Write>(&self, output: &mut O, options: &RapifyOptions) -> Result<usize, Error> {
+        let quantized;
+        let root = match options.quantize_floats {
+            Some(digits) => {
+                let mut root = self.root_body.clone();
+                root.quantize_floats(digits);
+                quantized = root;
+                &quantized
+            },
+            None => &self.root_body
+        };
+
         let mut writer = BufWriter::new(output);
 
         writer.write_all(b"\0raP")?;
         writer.write_all(b"\0\0\0\0\x08\0\0\0")?; // always_0, always_8
 
-        let buffer: Box<[u8]> = vec![0; self.root_body.rapified_length()].into_boxed_slice();
-        let mut cursor: Cursor<Box<[u8]>> = Cursor::new(buffer);
-        self.root_body.write_rapified(&mut cursor, 16).prepend_error("Failed to rapify root class:")?;
+        let mut shared = if options.share_classes { Some(HashMap::new()) } else { None };
+        let mut body: Vec<u8> = Vec::with_capacity(root.rapified_length(options.encoding));
+        root.write_rapified(&mut body, 16, options.encoding, &mut shared).prepend_error("Failed to rapify root class:")?;
 
-        let enum_offset: u32 = 16 + cursor.get_ref().len() as u32;
+        let enum_offset = field_u32(16 + body.len(), "Size of rapified config")?;
         writer.write_u32::<LittleEndian>(enum_offset)?;
 
-        writer.write_all(cursor.get_ref())?;
+        writer.write_all(&body)?;
 
         writer.write_all(b"\0\0\0\0")?;
 
-        Ok(())
+        Ok(body.len() + 20)
+    }
+
+    /// Returns the size of the rapified config without any of the optimizations of
+    /// `RapifyOptions`.
+    pub fn rapified_size(&self, encoding: StringEncoding) -> usize {
+        self.root_body.rapified_length(encoding) + 20
     }
 
     /// Returns the rapified config as a `Cursor`.
     pub fn to_cursor(&self) -> Result<Cursor<Box<[u8]>>, Error> {
-        let len = self.root_body.rapified_length() + 20;
+        let len = self.root_body.rapified_length(StringEncoding::default()) + 20;
 
         let buffer: Box<[u8]> = vec![0; len].into_boxed_slice();
         let mut cursor: Cursor<Box<[u8]>> = Cursor::new(buffer);
@@ -481,25 +1135,109 @@ impl Config {
     /// messages. `includefolders` are the folders searched for absolute includes and should usually at
     /// least include the current working directory.
     pub fn read<I: Read>(input: &mut I, path: Option<PathBuf>, includefolders: &[PathBuf]) -> Result<Config, Error> {
+        Self::read_with_options(input, path, includefolders, &ParseOptions::default())
+    }
+
+    /// Reads the unrapified config from input like `read`, using the given parse options.
+    pub fn read_with_options<I: Read>(input: &mut I, path: Option<PathBuf>, includefolders: &[PathBuf], options: &ParseOptions) -> Result<Config, Error> {
         let mut buffer = String::new();
         input.read_to_string(&mut buffer).prepend_error("Failed to read input file:")?;
 
-        let (preprocessed, info) = preprocess(buffer, path, includefolders).prepend_error("Failed to preprocess config:")?;
+        let (preprocessed, info) = preprocess_with_limits(buffer, path, includefolders, None, &options.limits).prepend_error("Failed to preprocess config:")?;
 
+        Self::from_preprocessed(&preprocessed, &info, options)
+    }
+
+    /// Parses already preprocessed input. `info` is used to map errors and warnings back to the
+    /// original files.
+    ///
+    /// Classes defined more than once in the same scope are merged like the engine does, unless
+    /// `options.strict_duplicates` is set. Leftovers of preprocessing like `#pragma` lines are
+    /// skipped with a warning, unless `options.strict_directives` is set.
+    pub fn from_preprocessed(preprocessed: &str, info: &PreprocessInfo, options: &ParseOptions) -> Result<Config, Error> {
         let mut warnings: Vec<(usize, String, Option<&'static str>)> = Vec::new();
+        let mut duplicates: HashSet<DuplicateClass> = HashSet::new();
+
+        let mut invalid: Vec<(usize, &'static str, String)> = Vec::new();
+
+        let result = config_grammar::config(preprocessed, &mut warnings, &mut duplicates, &mut invalid, options);
+        // Constructs that are recognized as invalid explain the failure better than the tokens
+        // the grammar expected
+        let result = match invalid.iter().min_by_key(|(pos, _, _)| *pos) {
+            Some((pos, code, message)) if result.is_err() => Err(format_config_error_at(info, preprocessed, *pos, code, message)),
+            _ => result.format_config_error(info, preprocessed)
+        };
+
+        for (pos, message, name) in warnings.iter_mut() {
+            if *name == Some("ignored-directive") {
+                let directive = preprocessed[*pos..].lines().next().unwrap_or("").trim();
+                *message = format!("Ignored \"{}\".", directive);
+            }
+        }
 
-        let result = config_grammar::config(&preprocessed, &mut warnings).format_error(&info, &preprocessed);
+        // Duplicates recorded while parsing invalid input are meaningless
+        if result.is_err() {
+            duplicates.clear();
+        }
+        let mut duplicates: Vec<DuplicateClass> = duplicates.into_iter().collect();
+        duplicates.sort();
+
+        for DuplicateClass { first, second, first_name, name, bodies } in duplicates {
+            let (file, line) = origin_location(preprocessed, info, first);
+            let first_location = match (file, line) {
+                (Some(f), Some(l)) => format!("{}:{}", f, l),
+                (None, Some(l)) => format!("line {}", l),
+                _ => "an earlier definition".to_string()
+            };
 
-        for w in warnings {
+            if first_name != name {
+                warnings.push((second, format!("Class \"{}\" is spelled \"{}\" at {}. Names are compared ignoring case, so both are the same class.", name, first_name, first_location),
+                    Some("class-case-mismatch")));
+            }
+            if !bodies {
+                continue;
+            }
 
-            let location = if !warning_suppressed(w.2) {
-                let mut line = preprocessed[..w.0].chars().filter(|c| c == &'\n').count();
-                let file = info.line_origins[min(line, info.line_origins.len()) - 1].1.as_ref().map(|p| p.to_str().unwrap().to_string());
-                line = info.line_origins[min(line, info.line_origins.len()) - 1].0 as usize + 1;
+            if options.strict_duplicates {
+                let (file, line) = origin_location(preprocessed, info, second);
+                let location = match (file, line) {
+                    (Some(f), Some(l)) => format!("In file {}:{}: ", f, l),
+                    (None, Some(l)) => format!("In line {}: ", l),
+                    _ => "".to_string()
+                };
+                return Err(coded_error!("E0104", "{}Class \"{}\" is already defined in the same scope at {}.", location, name, first_location));
+            }
 
-                (file, Some(line as u32))
-            } else {
-                (None, None)
+            warnings.push((second, format!("Class \"{}\" is already defined in the same scope at {}, merging both definitions.", name, first_location),
+                Some("duplicate-class-merged")));
+        }
+
+        if options.strict_directives && result.is_ok()
+            && let Some((pos, message, _)) = warnings.iter().find(|w| w.2 == Some("ignored-directive")) {
+            let location = match origin_location(preprocessed, info, *pos) {
+                (Some(f), Some(l)) => format!("In file {}:{}: ", f, l),
+                (None, Some(l)) => format!("In line {}: ", l),
+                _ => "".to_string()
+            };
+            return Err(coded_error!("E0105", "{}{}", location, message.replace("Ignored", "Unexpected")));
+        }
+
+        // Checks of the parsed config don't always know the position of what they warn about
+        let mut warnings: Vec<(Option<usize>, String, Option<&'static str>)> = warnings.into_iter()
+            .map(|(pos, message, name)| (Some(pos), message, name))
+            .collect();
+        if let Ok(config) = &result {
+            undefined_parents(&config.root_body, &[], &mut warnings);
+        }
+        if let (Some(schema), Ok(config)) = (&options.schema, &result) {
+            schema.lint(config, &mut warnings);
+        }
+
+        for w in warnings {
+
+            let location = match w.0 {
+                Some(pos) if !warning_suppressed(w.2) => origin_location(preprocessed, info, pos),
+                _ => (None, None)
             };
 
             warning(w.1, w.2, location);
@@ -518,21 +1256,566 @@ impl Config {
         Self::read(&mut cursor, path, includefolders)
     }
 
+    /// Reads a config that is either rapified or plain text.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Config, Error> {
+        if data.starts_with(b"\0raP") {
+            Config::read_rapified(&mut Cursor::new(data)).prepend_error("Failed to read rapified config:")
+        } else {
+            Config::read(&mut Cursor::new(data), None, &[]).prepend_error("Failed to read config:")
+        }
+    }
+
     /// Reads the rapified config from input.
     pub fn read_rapified<I: Read + Seek>(input: &mut I) -> Result<Config, Error> {
+        Self::read_rapified_with_encoding(input, StringEncoding::default())
+    }
+
+    /// Reads the rapified config from input like `read_rapified`, decoding strings with the given
+    /// encoding.
+    pub fn read_rapified_with_encoding<I: Read + Seek>(input: &mut I, encoding: StringEncoding) -> Result<Config, Error> {
         let mut reader = BufReader::new(input);
 
         let mut buffer = [0; 4];
         reader.read_exact(&mut buffer)?;
 
         if &buffer != b"\0raP" {
-            return Err(error!("File doesn't seem to be a rapified config."));
+            return Err(coded_error!("E0106", "File doesn't seem to be a rapified config."));
         }
 
+        let mut entry_offsets: Vec<u64> = Vec::new();
+        let root_body = ConfigClass::read_rapified(&mut reader, 0, &mut entry_offsets, encoding)?;
+
         Ok(Config {
-            root_body: ConfigClass::read_rapified(&mut reader, 0)?
+            root_body,
+            entry_offsets,
         })
     }
+
+    /// Returns the line of every entry in the unrapified output with the offset of the entry in
+    /// the rapified config it was read from. Empty if the config wasn't read from a rapified one.
+    pub fn source_map(&self) -> Vec<SourceMapEntry> {
+        let mut map: Vec<SourceMapEntry> = Vec::new();
+        if self.entry_offsets.is_empty() {
+            return map;
+        }
+
+        self.root_body.visit_entries("", &mut 0, &mut 1, &mut |index, line, path, _| {
+            map.push(SourceMapEntry { line, offset: self.entry_offsets[index], path: path.to_string() });
+        });
+
+        map
+    }
+
+    /// Returns the entry at the given path, with class names separated by `/` or `>>` and
+    /// ignoring case, along with its offset in the rapified config if the config was read from
+    /// one.
+    pub fn find(&self, path: &str) -> Option<(&ConfigEntry, Option<u64>)> {
+        let path = path.replace(">>", "/");
+        let names: Vec<&str> = path.split('/').map(|n| n.trim()).filter(|n| !n.is_empty()).collect();
+        let normalized = names.join("/");
+
+        let mut found: Option<(&ConfigEntry, Option<u64>)> = None;
+        self.root_body.visit_entries("", &mut 0, &mut 1, &mut |index, _, path, entry| {
+            if found.is_none() && path.eq_ignore_ascii_case(&normalized) {
+                found = Some((entry, self.entry_offsets.get(index).copied()));
+            }
+        });
+
+        found
+    }
+
+    fn cfgpatches_listed(&self, array: &str) -> HashSet<ConfigName> {
+        let patches = match self.root_body.get("CfgPatches") {
+            Some(ConfigEntry::ClassEntry(c)) => c.entries().unwrap_or(&[]),
+            _ => &[]
+        };
+        patches.iter()
+            .filter_map(|(_, e)| match e { ConfigEntry::ClassEntry(c) => c.get(array), _ => None })
+            .filter_map(|e| match e { ConfigEntry::ArrayEntry(a) => Some(a.elements()), _ => None })
+            .flatten()
+            .filter_map(|e| match e { ConfigArrayElement::StringElement(s) => Some(ConfigName::new(s)), _ => None })
+            .collect()
+    }
+
+    /// Returns every string value of the config with the path of the property it belongs to,
+    /// including the elements of (nested) arrays.
+    pub fn strings(&self) -> Vec<(String, &str)> {
+        self.located_strings().into_iter().map(|(path, s, _)| (path, s)).collect()
+    }
+
+    /// Returns the strings like `strings` along with the position in the preprocessed input of
+    /// the entry each belongs to, if the config was parsed.
+    pub(crate) fn located_strings(&self) -> Vec<(String, &str, Option<usize>)> {
+        fn array_strings<'a>(path: &str, array: &'a ConfigArray, position: Option<usize>, strings: &mut Vec<(String, &'a str, Option<usize>)>) {
+            for element in &array.elements {
+                match element {
+                    ConfigArrayElement::StringElement(s) => strings.push((path.to_string(), s, position)),
+                    ConfigArrayElement::ArrayElement(a) => array_strings(path, a, position, strings),
+                    _ => {}
+                }
+            }
+        }
+
+        fn class_strings<'a>(prefix: &str, class: &'a ConfigClass, strings: &mut Vec<(String, &'a str, Option<usize>)>) {
+            for (index, (name, entry)) in class.entries().unwrap_or_default().iter().enumerate() {
+                let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+                match entry {
+                    ConfigEntry::StringEntry(s) => strings.push((path, s, class.entry_position(index))),
+                    ConfigEntry::ArrayEntry(a) => array_strings(&path, a, class.entry_position(index), strings),
+                    ConfigEntry::ClassEntry(c) => class_strings(&path, c, strings),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut strings: Vec<(String, &str, Option<usize>)> = Vec::new();
+        class_strings("", &self.root_body, &mut strings);
+        strings
+    }
+
+    /// Returns the classes of CfgVehicles, CfgWeapons and CfgMagazines that are defined in this
+    /// config, but not listed in the `units[]`, `weapons[]` or `magazines[]` of any CfgPatches
+    /// class. External classes and deletions are not defined here and never reported.
+    pub fn missing_cfgpatches_entries(&self) -> Vec<MissingPatchEntry> {
+        let mut missing: Vec<MissingPatchEntry> = Vec::new();
+
+        for (config_class, array) in CFGPATCHES_ARRAYS {
+            let mut listed = self.cfgpatches_listed(array);
+            let entries = match self.root_body.get(config_class) {
+                Some(ConfigEntry::ClassEntry(c)) => c.entries().unwrap_or(&[]),
+                _ => continue
+            };
+
+            for (name, entry) in entries {
+                if let ConfigEntry::ClassEntry(class) = entry && class.entries.is_some() && !class.is_deletion
+                    && listed.insert(name.clone()) {
+                    missing.push(MissingPatchEntry {
+                        config_class: config_class.to_string(),
+                        array: array.to_string(),
+                        class: name.to_string(),
+                    });
+                }
+            }
+        }
+
+        missing
+    }
+
+    /// Appends the classes returned by `missing_cfgpatches_entries` to the arrays of the first
+    /// CfgPatches class, creating arrays that don't exist yet, and returns them. Nothing is
+    /// changed if there is no CfgPatches class.
+    pub fn add_missing_cfgpatches_entries(&mut self) -> Vec<MissingPatchEntry> {
+        let missing = self.missing_cfgpatches_entries();
+
+        let patch = self.root_body.entries.iter_mut().flatten()
+            .find(|(name, _)| name.matches("CfgPatches"))
+            .and_then(|(_, entry)| match entry { ConfigEntry::ClassEntry(c) => c.entries.as_mut(), _ => None })
+            .and_then(|patches| patches.iter_mut().find_map(|(_, entry)| match entry {
+                ConfigEntry::ClassEntry(c) => c.entries.as_mut(),
+                _ => None
+            }));
+        let patch = match patch {
+            Some(patch) => patch,
+            None => return Vec::new()
+        };
+
+        let mut added: Vec<MissingPatchEntry> = Vec::new();
+        for entry in missing {
+            let index = match patch.iter().position(|(name, _)| name.matches(&entry.array)) {
+                Some(index) => index,
+                None => {
+                    patch.push((ConfigName::new(&entry.array), ConfigEntry::ArrayEntry(ConfigArray { is_expansion: false, elements: Vec::new() })));
+                    patch.len() - 1
+                }
+            };
+
+            // Properties that aren't arrays are left alone
+            if let ConfigEntry::ArrayEntry(array) = &mut patch[index].1 {
+                array.elements.push(ConfigArrayElement::StringElement(entry.class.clone()));
+                added.push(entry);
+            }
+        }
+
+        // Offsets of a rapified config no longer match
+        if !added.is_empty() {
+            self.entry_offsets.clear();
+        }
+
+        added
+    }
+}
+
+/// Warns about classes inheriting from a class that is neither defined nor declared with
+/// `class Name;` in their scope or the scopes around it, which the engine fails to load. Classes
+/// in a scope that inherits could get the parent from there, so they are only checked if no
+/// scope around them inherits.
+fn undefined_parents(class: &ConfigClass, scopes: &[&ConfigClass], warnings: &mut Vec<(Option<usize>, String, Option<&'static str>)>) {
+    let mut chain = scopes.to_vec();
+    chain.push(class);
+    let inherits = chain.iter().any(|c| !c.parent.is_empty());
+
+    for (index, (name, entry)) in class.entries().unwrap_or_default().iter().enumerate() {
+        let ConfigEntry::ClassEntry(c) = entry else { continue };
+        if c.entries.is_none() {
+            continue;
+        }
+
+        // `class Turrets: Turrets` inherits from the class its scope inherits
+        if !c.parent.is_empty() && !inherits && !c.parent.matches(name.as_str()) && !chain.iter().any(|scope| scope.has_class(c.parent.as_str())) {
+            warnings.push((class.entry_position(index),
+                format!("Class \"{}\" inherits from \"{}\", which isn't defined. Declare it with \"class {};\" if it is defined in another addon.", name, c.parent, c.parent),
+                Some("undefined-base-class")));
+        }
+
+        undefined_parents(c, &chain, warnings);
+    }
+}
+
+/// Classes deriving from each other further than this are assumed to be circular.
+const MAX_INHERITANCE_DEPTH: usize = 32;
+
+/// Parent class found by `find_parent`
+pub(crate) enum Parent<'a> {
+    /// Class defined in the config, with the scopes it is defined in
+    Defined(&'a ConfigClass, Vec<&'a ConfigClass>),
+    /// Class declared with `class Name;`, whose entries are unknown
+    External(&'a str),
+}
+
+/// Looks up the class `name` like the engine does for the parent of `child`: in the scope the
+/// child is defined in first, then in the scopes around it. A class inheriting from a class of
+/// the same name, like `class Turrets: Turrets`, inherits from the class its scope inherits.
+pub(crate) fn find_parent<'a>(name: &str, child: &ConfigClass, scopes: &[&'a ConfigClass], depth: usize) -> Option<Parent<'a>> {
+    let matching = |entries: Vec<Entry<'a>>| entries.into_iter()
+        .find(|(n, entry, _)| n.matches(name) && matches!(entry, ConfigEntry::ClassEntry(_) | ConfigEntry::ExternalClass));
+
+    for i in (0..scopes.len()).rev() {
+        let mut found = matching(effective_entries(scopes[i], &scopes[..i], depth + 1).entries);
+        if let Some((_, ConfigEntry::ClassEntry(class), _)) = found && std::ptr::eq(class, child) {
+            found = matching(inherited_entries(scopes[i], &scopes[..i], depth + 1).entries);
+        }
+
+        match found {
+            Some((_, ConfigEntry::ClassEntry(class), chain)) => return Some(Parent::Defined(class, chain)),
+            Some((name, _, _)) => return Some(Parent::External(name.as_str())),
+            None => {}
+        }
+    }
+
+    None
+}
+
+/// Entry of a class with the scopes it is defined in, so nested classes can look up their parents
+pub(crate) type Entry<'a> = (&'a ConfigName, &'a ConfigEntry, Vec<&'a ConfigClass>);
+
+/// Entries of a class with the ones it inherits
+pub(crate) struct Resolved<'a> {
+    pub entries: Vec<Entry<'a>>,
+    /// External class the inheritance stops at, whose entries are missing
+    pub external: Option<&'a str>,
+}
+
+/// Returns the entries a class inherits from its parent.
+pub(crate) fn inherited_entries<'a>(class: &'a ConfigClass, scopes: &[&'a ConfigClass], depth: usize) -> Resolved<'a> {
+    if class.parent().is_empty() || depth >= MAX_INHERITANCE_DEPTH {
+        return Resolved { entries: Vec::new(), external: None };
+    }
+
+    match find_parent(class.parent(), class, scopes, depth) {
+        Some(Parent::Defined(parent, parent_scopes)) => effective_entries(parent, &parent_scopes, depth + 1),
+        Some(Parent::External(name)) => Resolved { entries: Vec::new(), external: Some(name) },
+        None => Resolved { entries: Vec::new(), external: None }
+    }
+}
+
+/// Returns the entries of a class including the ones it inherits.
+pub(crate) fn effective_entries<'a>(class: &'a ConfigClass, scopes: &[&'a ConfigClass], depth: usize) -> Resolved<'a> {
+    let mut chain = scopes.to_vec();
+    chain.push(class);
+
+    let mut resolved = inherited_entries(class, scopes, depth);
+    for (name, entry) in class.entries().unwrap_or_default() {
+        match resolved.entries.iter().position(|(n, _, _)| *n == name) {
+            Some(index) => resolved.entries[index] = (name, entry, chain.clone()),
+            None => resolved.entries.push((name, entry, chain.clone()))
+        }
+    }
+
+    resolved
+}
+
+/// Returns the names of the classes a class inherits from, nearest first, looked up like
+/// `find_parent` does. The chain ends at the first parent that is declared with `class Name;` or
+/// not found at all, whose name is the last one; `None` if it is circular.
+pub(crate) fn ancestor_names<'a>(class: &'a ConfigClass, scopes: &[&'a ConfigClass]) -> Option<Vec<&'a str>> {
+    let mut names: Vec<&str> = Vec::new();
+    let (mut current, mut current_scopes) = (class, scopes.to_vec());
+
+    while !current.parent().is_empty() {
+        if names.len() >= MAX_INHERITANCE_DEPTH {
+            return None;
+        }
+        names.push(current.parent());
+
+        match find_parent(current.parent(), current, &current_scopes, 0) {
+            Some(Parent::Defined(parent, parent_scopes)) => (current, current_scopes) = (parent, parent_scopes),
+            _ => break
+        }
+    }
+
+    Some(names)
+}
+
+/// Quotes a string value so that parsing it gives back the same string. Quotes are doubled and
+/// newlines are written with the `"..." \n "..."` syntax of the engine, since the two characters
+/// `\n` inside a string are not an escape sequence.
+pub(crate) fn quote_string(s: &str) -> String {
+    let parts: Vec<String> = s.split('\n').map(|part| format!("\"{}\"", part.replace("\"", "\"\""))).collect();
+    parts.join(" \\n ")
+}
+
+/// Comments marking included files while writing a config, see `Config::write_with_includes`
+struct IncludeComments<'a> {
+    /// Positions of the line breaks in the preprocessed input
+    newlines: Vec<usize>,
+    info: &'a PreprocessInfo,
+    base: Option<&'a Path>,
+    /// Indices into `info.include_lines` of the includes the last entry was written from
+    open: Vec<usize>,
+}
+
+impl IncludeComments<'_> {
+    /// Returns the includes the preprocessed input at the position came from, outermost first.
+    fn includes_at(&self, position: usize) -> Vec<usize> {
+        let line = self.newlines.partition_point(|n| *n < position);
+        self.info.include_lines.iter().enumerate()
+            .filter(|(_, (_, lines))| lines.contains(&line))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Writes the comments for leaving the open includes that aren't in `includes` and entering
+    /// the new ones.
+    fn switch<O: Write>(&mut self, output: &mut O, includes: Vec<usize>, level: i32) -> Result<(), Error> {
+        let common = self.open.iter().zip(&includes).take_while(|(a, b)| a == b).count();
+        let indentation = "    ".repeat(level as usize);
+        while self.open.len() > common {
+            let index = self.open.pop().unwrap();
+            writeln!(output, "{}// End of {}", indentation, self.display(index))?;
+        }
+        for index in &includes[common..] {
+            writeln!(output, "{}// Begin of {}", indentation, self.display(*index))?;
+        }
+        self.open = includes;
+        Ok(())
+    }
+
+    fn display(&self, index: usize) -> String {
+        let path = &self.info.include_lines[index].0;
+        let path = self.base.and_then(|base| path.strip_prefix(base).ok()).unwrap_or(path);
+        path.to_string_lossy().replace('\\', "/")
+    }
+}
+
+fn write_entry<O: Write>(mut output: &mut O, key: &str, value: &ConfigEntry, level: i32, comments: &mut Option<IncludeComments>) -> Result<(), Error> {
+    output.write_all(String::from("    ").repeat(level as usize).as_bytes())?;
+
+    match value {
+        ConfigEntry::ClassEntry(c) => {
+            if c.is_deletion {
+                output.write_all(format!("delete {};\n", key).as_bytes())?;
+            } else {
+                let parent = if c.parent.is_empty() { String::from("") } else { format!(": {}", c.parent) };
+                match &c.entries {
+                    Some(entries) => {
+                        if !entries.is_empty() {
+                            output.write_all(format!("class {}{} {{", key, parent).as_bytes())?;
+                            let outer = comments.as_ref().map(|c| c.open.clone());
+                            c.write(output, level + 1, comments)?;
+                            if let (Some(comments), Some(outer)) = (comments.as_mut(), outer) {
+                                comments.switch(output, outer, level + 1)?;
+                            }
+                            output.write_all(String::from("    ").repeat(level as usize).as_bytes())?;
+                            output.write_all(b"};\n")?;
+                        } else {
+                            output.write_all(format!("class {}{} {{}};\n", key, parent).as_bytes())?;
+                        }
+                    },
+                    None => {
+                        output.write_all(format!("class {}{} {{}};\n", key, parent).as_bytes())?;
+                    },
+                }
+            }
+        },
+        ConfigEntry::StringEntry(s) => {
+            output.write_all(format!("{} = {};\n", key, quote_string(s)).as_bytes())?;
+        },
+        ConfigEntry::FloatEntry(f) => {
+            warn_special_float(*f, key);
+            output.write_all(format!("{} = {};\n", key, format_float(*f)).as_bytes())?;
+        },
+        ConfigEntry::IntEntry(i) => {
+            output.write_all(format!("{} = {};\n", key, i).as_bytes())?;
+        },
+        ConfigEntry::ArrayEntry(a) => {
+            if a.is_expansion {
+                output.write_all(format!("{}[] += ", key).as_bytes())?;
+            } else {
+                output.write_all(format!("{}[] = ", key).as_bytes())?;
+            }
+            a.write(&mut output, key)?;
+            output.write_all(b";\n")?;
+        },
+        ConfigEntry::ExternalClass => {
+            output.write_all(format!("class {};\n", key).as_bytes())?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Maps a byte position in preprocessed output back to the file and line it originated from.
+pub(crate) fn origin_location(preprocessed: &str, info: &PreprocessInfo, pos: usize) -> (Option<String>, Option<u32>) {
+    if info.line_origins.is_empty() {
+        return (None, None);
+    }
+
+    // Like for errors, the origins are looked up by 1-based line number
+    let line_number = preprocessed[..pos].chars().filter(|c| c == &'\n').count() + 1;
+    let (origin_line, origin_file) = &info.line_origins[min(line_number, info.line_origins.len()) - 1];
+    let file = origin_file.as_ref().map(|p| p.to_str().unwrap().to_string());
+
+    (file, Some(*origin_line))
+}
+
+/// Last successful parse of a root in a `ConfigSession`
+#[derive(Debug)]
+struct SessionRoot {
+    text: String,
+    includes: Vec<(PathBuf, SystemTime)>,
+    /// Files that were looked for but didn't exist, see `PreprocessInfo::probed_includes`
+    probed_includes: Vec<PathBuf>,
+    config: Arc<Config>,
+}
+
+impl SessionRoot {
+    fn is_fresh(&self, text: &str) -> bool {
+        self.text == text && self.includes.iter().all(|(path, modified)| {
+            metadata(path).and_then(|m| m.modified()).map(|m| m == *modified).unwrap_or(false)
+        }) && !self.probed_includes.iter().any(|path| path.exists())
+    }
+}
+
+/// Cache statistics of a `ConfigSession`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Included files that were taken from the cache
+    pub include_hits: u64,
+    /// Included files that had to be read and parsed
+    pub include_misses: u64,
+    /// Parses that returned the previous result of an unchanged root
+    pub root_hits: u64,
+    /// Parses that ran the whole pipeline
+    pub root_misses: u64,
+}
+
+/// Reusable context for repeatedly preprocessing and parsing configs, e.g. in an editor.
+///
+/// Included files are cached by path and modification time, so only changed includes are read
+/// again. The result for a root (identified by its origin) is reused as long as its text and
+/// every file it transitively included are unchanged, and no file it looked for without finding
+/// it was created. A session can be shared between threads.
+///
+/// # Examples
+///
+/// ```
+/// # use armake2::config::{ConfigSession, ParseOptions};
+/// let session = ConfigSession::new(Vec::new(), ParseOptions::default());
+///
+/// let config = session.parse("foo = 42;", None).expect("Failed to parse config");
+/// assert_eq!("foo = 42;\n", config.to_string().unwrap());
+///
+/// session.parse("foo = 42;", None).unwrap();
+/// assert_eq!(1, session.stats().root_hits);
+/// ```
+#[derive(Debug)]
+pub struct ConfigSession {
+    includefolders: Vec<PathBuf>,
+    options: ParseOptions,
+    includes: IncludeCache,
+    roots: Mutex<HashMap<Option<PathBuf>, SessionRoot>>,
+    root_hits: AtomicU64,
+    root_misses: AtomicU64,
+}
+
+impl ConfigSession {
+    /// Creates a session with empty caches.
+    ///
+    /// `includefolders` are the folders searched for absolute includes and should usually at least
+    /// include the current working directory.
+    pub fn new(includefolders: Vec<PathBuf>, options: ParseOptions) -> ConfigSession {
+        ConfigSession {
+            includefolders,
+            options,
+            includes: IncludeCache::new(),
+            roots: Mutex::new(HashMap::new()),
+            root_hits: AtomicU64::new(0),
+            root_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// First stage: preprocesses the text, reading includes through the session cache.
+    pub fn preprocess(&self, text: String, origin: Option<PathBuf>) -> Result<(String, PreprocessInfo), Error> {
+        preprocess_with_limits(text, origin, &self.includefolders, Some(&self.includes), &self.options.limits)
+    }
+
+    /// Preprocesses and parses the text, reusing the previous result for the same origin if
+    /// neither the text nor any included file changed since.
+    ///
+    /// `origin` is the path of the text if it is known and is used for relative includes and
+    /// error messages.
+    pub fn parse(&self, text: &str, origin: Option<PathBuf>) -> Result<Arc<Config>, Error> {
+        if let Some(root) = self.roots.lock().unwrap().get(&origin)
+            && root.is_fresh(text) {
+            self.root_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(root.config.clone());
+        }
+        self.root_misses.fetch_add(1, Ordering::Relaxed);
+
+        let (preprocessed, info) = self.preprocess(text.to_string(), origin.clone()).prepend_error("Failed to preprocess config:")?;
+        let config = Arc::new(Config::from_preprocessed(&preprocessed, &info, &self.options)?);
+
+        let includes = info.includes.iter()
+            .filter_map(|path| self.includes.modified(path).map(|modified| (path.clone(), modified)))
+            .collect();
+
+        self.roots.lock().unwrap().insert(origin, SessionRoot {
+            text: text.to_string(),
+            includes,
+            probed_includes: info.probed_includes,
+            config: config.clone(),
+        });
+
+        Ok(config)
+    }
+
+    /// Returns the cache statistics so far.
+    pub fn stats(&self) -> SessionStats {
+        let (include_hits, include_misses) = self.includes.stats();
+
+        SessionStats {
+            include_hits,
+            include_misses,
+            root_hits: self.root_hits.load(Ordering::Relaxed),
+            root_misses: self.root_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drops all cached includes and results.
+    pub fn clear(&self) {
+        self.includes.clear();
+        self.roots.lock().unwrap().clear();
+    }
 }
 
 /// Reads input, preprocesses and rapifies it and writes to output.
@@ -540,19 +1823,112 @@ impl Config {
 /// `path` is the path to the input if it is known and is used for relative includes and error
 /// messages. `includefolders` are the folders searched for absolute includes and should usually at
 /// least include the current working directory.
-pub fn cmd_rapify<I: Read, O: Write>(input: &mut I, output: &mut O, path: Option<PathBuf>, includefolders: &[PathBuf]) -> Result<(), Error> {
-    let config = Config::read(input, path, includefolders)?;
+///
+/// With `verbose`, the size of the output is printed along with how much the options of
+/// `rapify_options` saved.
+pub fn cmd_rapify<I: Read, O: Write>(input: &mut I, output: &mut O, path: Option<PathBuf>, includefolders: &[PathBuf], options: &ParseOptions, rapify_options: &RapifyOptions, verbose: bool) -> Result<(), Error> {
+    let config = Config::read_with_options(input, path, includefolders, options)?;
 
-    config.write_rapified(output).prepend_error("Failed to write rapified config:")?;
+    let size = config.write_rapified_with_options(output, rapify_options).prepend_error("Failed to write rapified config:")?;
+
+    if verbose {
+        let unoptimized = config.rapified_size(rapify_options.encoding);
+        let saved = unoptimized - size;
+        eprintln!("Rapified size: {} bytes ({} bytes or {:.1}% saved from {} bytes)", size, saved, saved as f64 * 100.0 / unoptimized as f64, unoptimized);
+    }
+
+    Ok(())
+}
+
+/// Reads input, preprocesses and parses it and writes it as a single formatted config, with
+/// comments where the content of every included file begins and ends.
+///
+/// Unlike the output of `cmd_preprocess`, the result can be read like the original config. Parse
+/// errors still refer to the original files.
+pub fn cmd_config_bundle<I: Read, O: Write>(input: &mut I, output: &mut O, path: Option<PathBuf>, includefolders: &[PathBuf], options: &ParseOptions) -> Result<(), Error> {
+    let mut buffer = String::new();
+    input.read_to_string(&mut buffer).prepend_error("Failed to read input file:")?;
+
+    let base = path.as_ref().and_then(|p| p.canonicalize().ok()).and_then(|p| p.parent().map(Path::to_path_buf));
+    let (preprocessed, info) = preprocess_with_limits(buffer, path, includefolders, None, &options.limits).prepend_error("Failed to preprocess config:")?;
+    let config = Config::from_preprocessed(&preprocessed, &info, options)?;
+
+    config.write_with_includes(output, &preprocessed, &info, base.as_deref()).prepend_error("Failed to write config:")?;
 
     Ok(())
 }
 
 /// Reads input, derapifies it and writes to output.
-pub fn cmd_derapify<I: Read + Seek, O: Write>(input: &mut I, output: &mut O) -> Result<(), Error> {
-    let config = Config::read_rapified(input).prepend_error("Failed to read rapified config:")?;
+pub fn cmd_derapify<I: Read + Seek, O: Write>(input: &mut I, output: &mut O, encoding: StringEncoding) -> Result<(), Error> {
+    let config = Config::read_rapified_with_encoding(input, encoding).prepend_error("Failed to read rapified config:")?;
+
+    config.write(output).prepend_error("Failed to derapify config:")?;
+
+    Ok(())
+}
+
+pub(crate) fn json_string(input: &str) -> String {
+    let mut output = String::from("\"");
+    for c in input.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c)
+        }
+    }
+    output.push('"');
+    output
+}
+
+/// Derapifies input like `cmd_derapify` and writes a source map to `source_map`, with one JSON
+/// object per line mapping an output line to the offset of its entry in the input, e.g.
+/// `{"line":2,"offset":31,"path":"CfgPatches/foo"}`.
+pub fn cmd_derapify_with_source_map<I: Read + Seek, O: Write, M: Write>(input: &mut I, output: &mut O, source_map: &mut M, encoding: StringEncoding) -> Result<(), Error> {
+    let config = Config::read_rapified_with_encoding(input, encoding).prepend_error("Failed to read rapified config:")?;
 
     config.write(output).prepend_error("Failed to derapify config:")?;
 
+    for entry in config.source_map() {
+        writeln!(source_map, "{{\"line\":{},\"offset\":{},\"path\":{}}}", entry.line, entry.offset, json_string(&entry.path))
+            .prepend_error("Failed to write source map:")?;
+    }
+
+    Ok(())
+}
+
+/// Reads a config from an entry of a PBO, like `config.bin`, without unpacking it. The entry can
+/// be rapified or plain text.
+pub fn load_from_pbo(pbo_path: &Path, entry_name: &str) -> Result<Config, Error> {
+    let mut input = BufReader::new(File::open(pbo_path).prepend_error(format!("Failed to open {}:", pbo_path.display()))?);
+    let data = pbo::read_entry(&mut input, entry_name).prepend_error(format!("Failed to read {}:", pbo_path.display()))?
+        .ok_or_else(|| coded_error!("E0311", "{} has no entry \"{}\".", pbo_path.display(), entry_name))?;
+    Config::from_bytes(data)
+}
+
+/// Reads a config, rapified or not, and writes the entry at the given path to output.
+///
+/// With `print_offset`, a comment with the offset of the entry in the rapified config is written
+/// before it.
+pub fn cmd_config_get<I: Read + Seek, O: Write>(input: &mut I, output: &mut O, path: &str, print_offset: bool) -> Result<(), Error> {
+    let mut magic = [0; 4];
+    let rapified = input.read_exact(&mut magic).is_ok() && &magic == b"\0raP";
+    input.seek(SeekFrom::Start(0))?;
+
+    let config = if rapified {
+        Config::read_rapified(input).prepend_error("Failed to read rapified config:")?
+    } else {
+        Config::read(input, None, &[]).prepend_error("Failed to read config:")?
+    };
+
+    let (entry, offset) = config.find(path).ok_or_else(|| coded_error!("E0109", "Entry \"{}\" not found.", path))?;
+    let name = path.replace(">>", "/").trim_end_matches('/').rsplit('/').next().unwrap().trim().to_string();
+
+    if print_offset {
+        let offset = offset.ok_or_else(|| coded_error!("E0110", "Offsets are only known for rapified configs."))?;
+        writeln!(output, "// offset {}", offset)?;
+    }
+    write_entry(output, &name, entry, 0, &mut None)?;
+
     Ok(())
 }
@@ -0,0 +1,203 @@
+// Every rule takes the grammar's arguments, most without using them
+#![allow(clippy::ptr_arg, clippy::too_many_arguments)]
+
+use std::collections::{HashSet};
+
+use crate::config::{format_float, Config, ConfigClass, ConfigEntry, ConfigArray, ConfigArrayElement, ConfigName, ParseOptions, DuplicateClass, merge_duplicate_classes};
+
+pub use self::parser::*;
+
+/// Error of a config that can't be parsed, with the line and column it failed at
+pub type ParseError = peg::error::ParseError<peg::str::LineCol>;
+
+peg::parser! {
+    grammar parser(warnings: &mut Vec<(usize, String, Option<&'static str>)>, duplicates: &mut HashSet<DuplicateClass>, invalid: &mut Vec<(usize, &'static str, String)>, options: &ParseOptions) for str {
+        rule whitespace() = quiet!{[' ' | '\r' | '\n' | '\t']+}
+
+        rule float() -> f32 = f:$(['-' | '+']? ['0'..='9']* "." ['0'..='9']+) {
+            f.parse().unwrap()
+        }
+
+        // Hexadecimal numbers are bit patterns, so 0xFFFFFFFF is -1
+        rule integer() -> i32 = i:$(['-' | '+']? (("0x" ['0'..='9' | 'a'..='f' | 'A'..='F']+) / ['0'..='9']+)) {?
+            match i.split_once("0x") {
+                Some((sign, hex)) => u32::from_str_radix(hex, 16).map(|h| if sign == "-" { (h as i32).wrapping_neg() } else { h as i32 }).map_err(|_| "32-bit integer"),
+                None => i.parse().map_err(|_| "32-bit integer")
+            }
+        }
+
+        // Integers too large for an integer entry are stored as the closest float
+        rule large_integer() -> f32 = pos:position!() i:$(['-' | '+']? ['0'..='9']+) {?
+            if i.parse::<i32>().is_ok() {
+                return Err("large integer");
+            }
+            let value: f32 = i.parse().unwrap();
+            warnings.push((pos, format!("{} doesn't fit into a 32-bit integer and is stored as the float {}.", i, format_float(value)), Some("integer-overflow")));
+            Ok(value)
+        }
+
+        rule doublequoted_part() -> String = "\"" s:$(("\"\"" / [^'"'])*) "\"" {
+            s.replace("\"\"", "\"")
+        }
+
+        rule string_continuation() -> String = whitespace()? "\\n" whitespace()? p:doublequoted_part() { p }
+
+        // "foo" \n "bar" is the engine's syntax for a string containing a newline
+        rule doublequoted_string() -> String = first:doublequoted_part() rest:string_continuation()* {
+            let mut result = first;
+            for part in rest {
+                result.push('\n');
+                result.push_str(&part);
+            }
+            result
+        }
+
+        rule singlequoted_string() -> String = "'" s:$(("''" / [^'\''])*) "'" {
+            s.replace("''", "'")
+        }
+
+        rule unquoted_string() -> String = pos:position!() s:$([^';' | '}']*) {
+            let result = s.trim().to_string();
+            warnings.push((pos, format!("String value \"{}\" is not quoted properly.", result), Some("unquoted-string")));
+            result
+        }
+
+        rule unquoted_string_array() -> String = pos:position!() s:$([^',' | '}' | ' ' | '\t'] [^',' | '}']*) {
+            let result = s.trim().to_string();
+            warnings.push((pos, format!("String array element \"{}\" is not quoted properly.", result), Some("unquoted-string")));
+            result
+        }
+
+        rule string() -> String = doublequoted_string() / singlequoted_string()
+
+        // Class definitions in arrays, which would otherwise be read as an unquoted string
+        rule array_class() = pos:position!() "class" whitespace()+ name() parent()? whitespace()? "{" {
+            invalid.push((pos, "E0102", String::from("Classes cannot appear inside arrays, define the class outside of the array instead.")));
+        }
+
+        rule array_element() -> ConfigArrayElement =
+            f:float()   &(whitespace()? [',' | '}']) { ConfigArrayElement::FloatElement(f) } /
+            i:integer() &(whitespace()? [',' | '}']) { ConfigArrayElement::IntElement(i) } /
+            f:large_integer() &(whitespace()? [',' | '}']) { ConfigArrayElement::FloatElement(f) } /
+            a:array()   &(whitespace()? [',' | '}']) { ConfigArrayElement::ArrayElement(a) } /
+            s:string()  &(whitespace()? [',' | '}']) { ConfigArrayElement::StringElement(s) } /
+            !array_class() s:unquoted_string_array() &(whitespace()? [',' | '}']) { ConfigArrayElement::StringElement(s) }
+
+        rule array_elements() -> Vec<ConfigArrayElement> = array_element() ** (whitespace()? "," whitespace()?)
+
+        rule array() -> ConfigArray = "{" whitespace()? elems:array_elements() whitespace()? ","? whitespace()? "}" {
+            ConfigArray {
+                is_expansion: false,
+                elements: elems
+            }
+        }
+
+        rule var() -> ConfigEntry =
+            f:float()   { ConfigEntry::FloatEntry(f) } /
+            i:integer() { ConfigEntry::IntEntry(i) } /
+            f:large_integer() { ConfigEntry::FloatEntry(f) } /
+            s:string()  { ConfigEntry::StringEntry(s) }
+
+        rule var_entry() -> (ConfigName, ConfigEntry) = n:name() whitespace()? "=" whitespace()? ce:var() {
+            (n, ce)
+        }
+
+        rule unquoted_string_entry() -> (ConfigName, ConfigEntry) = n:name() whitespace()? "=" whitespace()? s:unquoted_string() {
+            (n, ConfigEntry::StringEntry(s))
+        }
+
+        rule array_entry() -> (ConfigName, ConfigEntry) = n:name() whitespace()? "[" whitespace()? "]" whitespace()? "=" whitespace()? a:array() {
+            (n, ConfigEntry::ArrayEntry(a))
+        }
+
+        rule array_expansion_entry() -> (ConfigName, ConfigEntry) = n:name() whitespace()? "[" whitespace()? "]" whitespace()? "+=" whitespace()? a:array() {
+            (n, ConfigEntry::ArrayEntry(ConfigArray {
+                is_expansion: true,
+                ..a
+            }))
+        }
+
+        // SQF style assignments to single array elements, which configs don't have
+        rule indexed_array_entry() = n:name() whitespace()? "[" whitespace()? pos:position!() i:$(['0'..='9']+) whitespace()? "]" whitespace()? "+"? "=" {
+            invalid.push((pos, "E0103", format!("Indexed array assignment \"{}[{}]\" is not valid config syntax, use \"{}[] = {{...}}\" instead.", n, i, n)));
+        }
+
+        rule entry() -> (ConfigName, ConfigEntry) =
+            e:(class() / array_entry() / array_expansion_entry()) whitespace()? (";" / &"}") { e } /
+            pos:position!() e:var_entry() whitespace()? (";" / &"}") {
+                if let Some(lint) = &options.type_lint {
+                    lint.check(&e.0, &e.1, pos, warnings);
+                }
+                e
+            } /
+            e:unquoted_string_entry() whitespace()? (";" / &"}") { e } /
+            indexed_array_entry() {? Err("entry") }
+
+        rule positioned_entry() -> (usize, ConfigName, ConfigEntry) = pos:position!() e:entry() {
+            (pos, e.0, e.1)
+        }
+
+        rule exec_arguments() = "(" (doublequoted_part() / exec_arguments() / [^'(' | ')' | '"'])* ")"
+
+        // Leftovers of preprocessing the engine ignores, like #pragma lines or __EXEC and __EVAL statements
+        rule ignored_directive() -> usize = pos:position!() ("#" [^'\r' | '\n']* / ("__EXEC" / "__EVAL") whitespace()? exec_arguments() ";"?) {
+            pos
+        }
+
+        // The warning message is filled in from the input after parsing
+        rule entry_item() -> Option<(usize, ConfigName, ConfigEntry)> =
+            e:positioned_entry() { Some(e) } /
+            pos:ignored_directive() {
+                warnings.push((pos, String::new(), Some("ignored-directive")));
+                None
+            }
+
+        rule entries() -> (Vec<(ConfigName, ConfigEntry)>, Vec<usize>) = e:(entry_item() ** (whitespace()?)) {
+            merge_duplicate_classes(e.into_iter().flatten().collect(), duplicates)
+        }
+
+        rule name() -> ConfigName = n:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_']+) {
+            ConfigName::new(n)
+        }
+
+        rule parent() -> ConfigName = whitespace()? ":" whitespace()? n:name() {
+            n
+        }
+
+        rule regular_class() -> (ConfigName, ConfigEntry) = "class" whitespace()+ n:name() p:parent()? whitespace()? "{" whitespace()? e:entries() whitespace()? "}" {
+            (n, ConfigEntry::ClassEntry(ConfigClass {
+                parent: p.unwrap_or_default(),
+                is_deletion: false,
+                entries: Some(e.0),
+                positions: e.1
+            }))
+        }
+
+        rule external_class() -> (ConfigName, ConfigEntry) = "class" whitespace()+ n:name() {
+            (n, ConfigEntry::ExternalClass)
+        }
+
+        rule deleted_class() -> (ConfigName, ConfigEntry) = "delete" whitespace()+ n:name() {
+            (n, ConfigEntry::ClassEntry(ConfigClass {
+                parent: ConfigName::default(),
+                is_deletion: true,
+                entries: None,
+                positions: Vec::new()
+            }))
+        }
+
+        rule class() -> (ConfigName, ConfigEntry) = regular_class() / external_class() / deleted_class()
+
+        pub rule config() -> Config = whitespace()? e:entries() whitespace()? ![_] {
+            Config {
+                root_body: ConfigClass {
+                    parent: ConfigName::default(),
+                        is_deletion: false,
+                    entries: Some(e.0),
+                    positions: e.1
+                },
+                entry_offsets: Vec::new()
+            }
+        }
+    }
+}
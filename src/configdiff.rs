@@ -0,0 +1,346 @@
+//! Structured differences between two configs
+//!
+//! Entries are matched by name, ignoring case and order like the engine does, so a config that was
+//! only reformatted, reordered or converted between text and rapified form has no differences.
+//! With `DiffOptions::resolve_inheritance`, every class is compared with the entries it inherits,
+//! which shows how a change to a base class affects the classes deriving from it. Inheritance stops
+//! at classes declared with `class Name;`, so entries only one of the configs has in a class
+//! deriving from one are reported as unknown rather than added or removed.
+//! `DiffOptions::ignore` skips noisy entries like `dlc` or build dates, see `is_ignored`.
+
+use std::io::{Error, Read, Write};
+
+use colored::*;
+
+use crate::config::{effective_entries, format_float, json_string, quote_string, Config, ConfigArrayElement, ConfigClass, ConfigEntry, ConfigName};
+use crate::error::*;
+use crate::pbo::{matches_glob};
+
+/// Arrays with at most this many changed elements are diffed element by element, arrays with more
+/// are only summarized.
+pub const MAX_ARRAY_ELEMENT_CHANGES: usize = 5;
+
+/// Options for diffing configs
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Compare the entries classes inherit from their parents as well as their own
+    pub resolve_inheritance: bool,
+    /// Write one JSON object per line instead of text
+    pub json: bool,
+    /// Color the text lines by the kind of change
+    pub color: bool,
+    /// Glob patterns of entries to skip, see `is_ignored`
+    pub ignore: Vec<String>,
+}
+
+/// Returns true if an entry path matches one of the patterns, ignoring case. Patterns with a `/`
+/// are matched against the whole path, others against the name of the entry so `dlc` skips it in
+/// every class. A `*` matches any characters, including `/`. Matching a class skips it with
+/// everything in it, even entries no pattern matches.
+pub fn is_ignored(path: &str, patterns: &[String]) -> bool {
+    let path = path.to_lowercase();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        matches_glob(if pattern.contains('/') { &path } else { name }, &pattern)
+    })
+}
+
+/// Difference between two configs. Paths are separated by `/` like in `Config::find` and use the
+/// case of the new config where the entry exists in both.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigChange {
+    /// Class only in the new config
+    ClassAdded { path: String },
+    /// Class only in the old config
+    ClassRemoved { path: String },
+    /// Class in both configs that inherits from a different class
+    ParentChanged { path: String, old: String, new: String },
+    /// Property only in the new config
+    PropertyAdded { path: String, value: String },
+    /// Property only in the old config
+    PropertyRemoved { path: String, value: String },
+    /// Property with different values
+    PropertyChanged { path: String, old: String, new: String },
+    /// Element of an array with few changes, `None` where the array is too short
+    ElementChanged { path: String, index: usize, old: Option<String>, new: Option<String> },
+    /// Array with too many changes to list them
+    ArrayChanged { path: String, old_length: usize, new_length: usize },
+    /// Entry only in one config, in a class that inherits from the external class
+    /// `external_parent` in the other one, so it may have the entry from there. `None` is the
+    /// side whose value is unknown.
+    Unknown { path: String, external_parent: String, old: Option<String>, new: Option<String> },
+}
+
+/// Entry of a class, with inherited entries already resolved if requested
+enum Node {
+    /// `external` is the class declared with `class Name;` that resolving the entries stopped at,
+    /// the class itself for a declaration
+    Class { parent: ConfigName, entries: Vec<(ConfigName, Node)>, external: Option<String> },
+    Value(String),
+    Array(Vec<String>),
+}
+
+fn element_string(element: &ConfigArrayElement) -> String {
+    match element {
+        ConfigArrayElement::StringElement(s) => quote_string(s),
+        ConfigArrayElement::FloatElement(f) => format_float(*f),
+        ConfigArrayElement::IntElement(i) => i.to_string(),
+        ConfigArrayElement::ArrayElement(a) => {
+            let elements: Vec<String> = a.elements().iter().map(element_string).collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+    }
+}
+
+fn array_string(elements: &[String]) -> String {
+    format!("{{{}}}", elements.join(", "))
+}
+
+fn entry_node(name: &str, entry: &ConfigEntry, scopes: &[&ConfigClass], resolve: bool) -> Node {
+    match entry {
+        ConfigEntry::StringEntry(s) => Node::Value(quote_string(s)),
+        ConfigEntry::FloatEntry(f) => Node::Value(format_float(*f)),
+        ConfigEntry::IntEntry(i) => Node::Value(i.to_string()),
+        ConfigEntry::ArrayEntry(a) => Node::Array(a.elements().iter().map(element_string).collect()),
+        ConfigEntry::ClassEntry(c) if c.is_deletion() => Node::Value(String::from("delete")),
+        ConfigEntry::ClassEntry(c) => class_node(c, scopes, resolve),
+        ConfigEntry::ExternalClass => Node::Class {
+            parent: ConfigName::default(),
+            entries: Vec::new(),
+            external: if resolve { Some(name.to_string()) } else { None },
+        },
+    }
+}
+
+fn class_node(class: &ConfigClass, scopes: &[&ConfigClass], resolve: bool) -> Node {
+    if resolve {
+        let resolved = effective_entries(class, scopes, 0);
+        Node::Class {
+            parent: ConfigName::new(class.parent()),
+            entries: resolved.entries.into_iter()
+                .map(|(name, entry, chain)| (name.clone(), entry_node(name.as_str(), entry, &chain, resolve)))
+                .collect(),
+            external: resolved.external.map(str::to_string),
+        }
+    } else {
+        Node::Class {
+            parent: ConfigName::new(class.parent()),
+            entries: class.entries().unwrap_or_default().iter()
+                .map(|(name, entry)| (name.clone(), entry_node(name.as_str(), entry, &[], resolve)))
+                .collect(),
+            external: None,
+        }
+    }
+}
+
+fn node_string(node: &Node) -> String {
+    match node {
+        Node::Class { .. } => String::from("class"),
+        Node::Value(value) => value.clone(),
+        Node::Array(elements) => array_string(elements),
+    }
+}
+
+fn diff_arrays(path: &str, old: &[String], new: &[String], changes: &mut Vec<ConfigChange>) {
+    let changed: Vec<usize> = (0..old.len().max(new.len())).filter(|i| old.get(*i) != new.get(*i)).collect();
+
+    if changed.len() > MAX_ARRAY_ELEMENT_CHANGES {
+        changes.push(ConfigChange::ArrayChanged { path: path.to_string(), old_length: old.len(), new_length: new.len() });
+        return;
+    }
+
+    for index in changed {
+        changes.push(ConfigChange::ElementChanged {
+            path: path.to_string(),
+            index,
+            old: old.get(index).cloned(),
+            new: new.get(index).cloned(),
+        });
+    }
+}
+
+fn added(path: String, node: &Node, changes: &mut Vec<ConfigChange>) {
+    changes.push(match node {
+        Node::Class { .. } => ConfigChange::ClassAdded { path },
+        Node::Value(value) => ConfigChange::PropertyAdded { path, value: value.clone() },
+        Node::Array(elements) => ConfigChange::PropertyAdded { path, value: array_string(elements) },
+    });
+}
+
+fn removed(path: String, node: &Node, changes: &mut Vec<ConfigChange>) {
+    changes.push(match node {
+        Node::Class { .. } => ConfigChange::ClassRemoved { path },
+        Node::Value(value) => ConfigChange::PropertyRemoved { path, value: value.clone() },
+        Node::Array(elements) => ConfigChange::PropertyRemoved { path, value: array_string(elements) },
+    });
+}
+
+fn diff_nodes(path: String, old: &Node, new: &Node, ignore: &[String], changes: &mut Vec<ConfigChange>) {
+    match (old, new) {
+        (Node::Class { parent: old_parent, entries: old_entries, external: old_external }, Node::Class { parent: new_parent, entries: new_entries, external: new_external }) => {
+            if old_parent != new_parent {
+                changes.push(ConfigChange::ParentChanged { path: path.clone(), old: old_parent.to_string(), new: new_parent.to_string() });
+            }
+
+            let child_path = |name: &ConfigName| if path.is_empty() { name.to_string() } else { format!("{}/{}", path, name) };
+
+            for (name, new_node) in new_entries {
+                if is_ignored(&child_path(name), ignore) {
+                    continue;
+                }
+                match (old_entries.iter().find(|(n, _)| n == name), old_external) {
+                    (Some((_, old_node)), _) => diff_nodes(child_path(name), old_node, new_node, ignore, changes),
+                    (None, Some(external)) => changes.push(ConfigChange::Unknown {
+                        path: child_path(name),
+                        external_parent: external.clone(),
+                        old: None,
+                        new: Some(node_string(new_node)),
+                    }),
+                    (None, None) => added(child_path(name), new_node, changes)
+                }
+            }
+            for (name, old_node) in old_entries {
+                if is_ignored(&child_path(name), ignore) || new_entries.iter().any(|(n, _)| n == name) {
+                    continue;
+                }
+                match new_external {
+                    Some(external) => changes.push(ConfigChange::Unknown {
+                        path: child_path(name),
+                        external_parent: external.clone(),
+                        old: Some(node_string(old_node)),
+                        new: None,
+                    }),
+                    None => removed(child_path(name), old_node, changes)
+                }
+            }
+        },
+        (Node::Value(old_value), Node::Value(new_value)) => {
+            if old_value != new_value {
+                changes.push(ConfigChange::PropertyChanged { path, old: old_value.clone(), new: new_value.clone() });
+            }
+        },
+        (Node::Array(old_elements), Node::Array(new_elements)) => diff_arrays(&path, old_elements, new_elements, changes),
+        (Node::Class { .. }, _) | (_, Node::Class { .. }) => {
+            removed(path.clone(), old, changes);
+            added(path, new, changes);
+        },
+        (old, new) => {
+            changes.push(ConfigChange::PropertyChanged { path, old: node_string(old), new: node_string(new) });
+        }
+    }
+}
+
+/// Returns the differences between two configs, see `ConfigChange`.
+pub fn diff_configs(old: &Config, new: &Config, resolve_inheritance: bool) -> Vec<ConfigChange> {
+    diff_configs_ignoring(old, new, resolve_inheritance, &[])
+}
+
+/// Returns the differences between two configs, skipping the entries that match one of the
+/// patterns, see `is_ignored`.
+pub fn diff_configs_ignoring(old: &Config, new: &Config, resolve_inheritance: bool, ignore: &[String]) -> Vec<ConfigChange> {
+    let mut changes: Vec<ConfigChange> = Vec::new();
+    diff_nodes(String::new(),
+        &class_node(old.root(), &[], resolve_inheritance),
+        &class_node(new.root(), &[], resolve_inheritance),
+        ignore,
+        &mut changes);
+    changes
+}
+
+impl ConfigChange {
+    /// Returns the change as one line of text.
+    pub fn to_text(&self) -> String {
+        match self {
+            ConfigChange::ClassAdded { path } => format!("+ class {}", path),
+            ConfigChange::ClassRemoved { path } => format!("- class {}", path),
+            ConfigChange::ParentChanged { path, old, new } => format!("~ class {}: parent {:?} -> {:?}", path, old, new),
+            ConfigChange::PropertyAdded { path, value } => format!("+ {} = {}", path, value),
+            ConfigChange::PropertyRemoved { path, value } => format!("- {} = {}", path, value),
+            ConfigChange::PropertyChanged { path, old, new } => format!("~ {}: {} -> {}", path, old, new),
+            ConfigChange::ElementChanged { path, index, old: None, new: Some(new) } => format!("+ {}[{}] = {}", path, index, new),
+            ConfigChange::ElementChanged { path, index, old: Some(old), new: None } => format!("- {}[{}] = {}", path, index, old),
+            ConfigChange::ElementChanged { path, index, old, new } =>
+                format!("~ {}[{}]: {} -> {}", path, index, old.as_deref().unwrap_or_default(), new.as_deref().unwrap_or_default()),
+            ConfigChange::ArrayChanged { path, old_length, new_length } =>
+                format!("~ {}: array changed ({} -> {} elements)", path, old_length, new_length),
+            ConfigChange::Unknown { path, external_parent, old, new } => {
+                let unknown = format!("unknown — external parent \"{}\"", external_parent);
+                format!("? {}: {} -> {}", path, old.as_ref().unwrap_or(&unknown), new.as_ref().unwrap_or(&unknown))
+            },
+        }
+    }
+
+    /// Returns the change as one line of text like `to_text`, colored red if something was
+    /// removed, green if it was added and yellow if it changed. For changes, the old value is red
+    /// and the new one green as well.
+    pub fn to_colored_text(&self) -> String {
+        match self {
+            ConfigChange::ClassRemoved { .. } | ConfigChange::PropertyRemoved { .. } |
+            ConfigChange::ElementChanged { new: None, .. } => self.to_text().red().to_string(),
+            ConfigChange::ClassAdded { .. } | ConfigChange::PropertyAdded { .. } |
+            ConfigChange::ElementChanged { old: None, .. } => self.to_text().green().to_string(),
+            ConfigChange::ParentChanged { path, old, new } =>
+                format!("{} {}", format!("~ class {}: parent", path).yellow(), changed_values(&format!("{:?}", old), &format!("{:?}", new))),
+            ConfigChange::PropertyChanged { path, old, new } => format!("{} {}", format!("~ {}:", path).yellow(), changed_values(old, new)),
+            ConfigChange::ElementChanged { path, index, old: Some(old), new: Some(new) } =>
+                format!("{} {}", format!("~ {}[{}]:", path, index).yellow(), changed_values(old, new)),
+            ConfigChange::ArrayChanged { .. } | ConfigChange::Unknown { .. } => self.to_text().yellow().to_string(),
+        }
+    }
+
+    /// Returns the change as a JSON object, with the kind of change in `change`.
+    pub fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| value.as_deref().map(json_string).unwrap_or_else(|| String::from("null"));
+
+        match self {
+            ConfigChange::ClassAdded { path } => format!("{{\"change\":\"class-added\",\"path\":{}}}", json_string(path)),
+            ConfigChange::ClassRemoved { path } => format!("{{\"change\":\"class-removed\",\"path\":{}}}", json_string(path)),
+            ConfigChange::ParentChanged { path, old, new } =>
+                format!("{{\"change\":\"parent-changed\",\"path\":{},\"old\":{},\"new\":{}}}", json_string(path), json_string(old), json_string(new)),
+            ConfigChange::PropertyAdded { path, value } =>
+                format!("{{\"change\":\"added\",\"path\":{},\"new\":{}}}", json_string(path), json_string(value)),
+            ConfigChange::PropertyRemoved { path, value } =>
+                format!("{{\"change\":\"removed\",\"path\":{},\"old\":{}}}", json_string(path), json_string(value)),
+            ConfigChange::PropertyChanged { path, old, new } =>
+                format!("{{\"change\":\"changed\",\"path\":{},\"old\":{},\"new\":{}}}", json_string(path), json_string(old), json_string(new)),
+            ConfigChange::ElementChanged { path, index, old, new } =>
+                format!("{{\"change\":\"element-changed\",\"path\":{},\"index\":{},\"old\":{},\"new\":{}}}", json_string(path), index, optional(old), optional(new)),
+            ConfigChange::ArrayChanged { path, old_length, new_length } =>
+                format!("{{\"change\":\"array-changed\",\"path\":{},\"old_length\":{},\"new_length\":{}}}", json_string(path), old_length, new_length),
+            ConfigChange::Unknown { path, external_parent, old, new } =>
+                format!("{{\"change\":\"unknown\",\"path\":{},\"external_parent\":{},\"old\":{},\"new\":{}}}", json_string(path), json_string(external_parent), optional(old), optional(new)),
+        }
+    }
+}
+
+fn changed_values(old: &str, new: &str) -> String {
+    format!("{} {} {}", old.red(), "->".yellow(), new.green())
+}
+
+fn read_config<I: Read>(input: &mut I) -> Result<Config, Error> {
+    let mut data: Vec<u8> = Vec::new();
+    input.read_to_end(&mut data)?;
+    Config::from_bytes(data)
+}
+
+/// Reads two configs, each rapified or not, and writes their differences to output, one per line.
+/// Returns true if they differ.
+pub fn cmd_config_diff<I: Read, J: Read, O: Write>(old: &mut I, new: &mut J, output: &mut O, options: &DiffOptions) -> Result<bool, Error> {
+    let old = read_config(old).prepend_error("Failed to read old config:")?;
+    let new = read_config(new).prepend_error("Failed to read new config:")?;
+
+    let changes = diff_configs_ignoring(&old, &new, options.resolve_inheritance, &options.ignore);
+    for change in &changes {
+        let line = if options.json {
+            change.to_json()
+        } else if options.color {
+            change.to_colored_text()
+        } else {
+            change.to_text()
+        };
+        writeln!(output, "{}", line)?;
+    }
+
+    Ok(!changes.is_empty())
+}
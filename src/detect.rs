@@ -0,0 +1,280 @@
+//! Detection of the supported file formats from their content
+
+use std::fs::{File};
+use std::io::{Read, Error, stdout};
+use std::path::{Path};
+
+use crate::*;
+use crate::config;
+use crate::error::*;
+use crate::paa::{PaaType};
+use crate::pbo;
+use crate::sign;
+
+/// Number of bytes read from the start of a file for detection. Enough to contain whole keys
+/// so they can be told apart from signatures.
+pub const DETECT_LENGTH: usize = 4096;
+
+/// Format of a file as detected from its content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    /// Rapified config (`\0raP`)
+    RapifiedConfig,
+    /// PAA texture of the given type
+    Paa(PaaType),
+    /// PBO, with or without header extensions
+    Pbo,
+    /// Unbinarized model
+    Mlod,
+    /// Binarized model
+    Odol,
+    /// Public key (.bikey)
+    PublicKey,
+    /// Private key (.biprivatekey)
+    PrivateKey,
+    /// Signature (.bisign)
+    Signature,
+    /// None of the above
+    Unknown,
+}
+
+impl FileKind {
+    /// Detects the format of a file from its first bytes (at least `DETECT_LENGTH` bytes if the
+    /// file is that large).
+    ///
+    /// Detection only looks at the structure of the headers, so a truncated or otherwise broken
+    /// file may still be detected as its format.
+    pub fn detect(bytes: &[u8]) -> FileKind {
+        if bytes.starts_with(b"\0raP") {
+            return FileKind::RapifiedConfig;
+        }
+        if bytes.starts_with(b"MLOD") {
+            return FileKind::Mlod;
+        }
+        if bytes.starts_with(b"ODOL") {
+            return FileKind::Odol;
+        }
+        if let Some(kind) = detect_bi_blob(bytes) {
+            return kind;
+        }
+        if let Some(paa_type) = detect_paa(bytes) {
+            return FileKind::Paa(paa_type);
+        }
+        if is_pbo(bytes) {
+            return FileKind::Pbo;
+        }
+
+        FileKind::Unknown
+    }
+
+    /// Reads the start of the file and detects its format.
+    pub fn detect_file(path: &Path) -> Result<FileKind, Error> {
+        let mut buffer: Vec<u8> = Vec::new();
+        File::open(path)?.take(DETECT_LENGTH as u64).read_to_end(&mut buffer)?;
+        Ok(FileKind::detect(&buffer))
+    }
+
+    /// Returns a short description of the format.
+    pub fn description(&self) -> String {
+        match self {
+            FileKind::RapifiedConfig => "a rapified config".to_string(),
+            FileKind::Paa(t) => format!("a {:?} PAA texture", t),
+            FileKind::Pbo => "a PBO".to_string(),
+            FileKind::Mlod => "an unbinarized (MLOD) model".to_string(),
+            FileKind::Odol => "a binarized (ODOL) model".to_string(),
+            FileKind::PublicKey => "a public key".to_string(),
+            FileKind::PrivateKey => "a private key".to_string(),
+            FileKind::Signature => "a signature".to_string(),
+            FileKind::Unknown => "an unknown file".to_string(),
+        }
+    }
+
+    /// Returns the command that is most likely useful for this kind of file.
+    pub fn suggested_command(&self) -> Option<&'static str> {
+        match self {
+            FileKind::RapifiedConfig => Some("derapify"),
+            FileKind::Paa(_) => Some("paa2img"),
+            FileKind::Pbo => Some("inspect"),
+            FileKind::Mlod => Some("binarize"),
+            FileKind::Odol => Some("p3dinfo"),
+            FileKind::PublicKey | FileKind::Signature => Some("verify"),
+            FileKind::PrivateKey => Some("sign"),
+            FileKind::Unknown => None,
+        }
+    }
+}
+
+/// Keys and signatures start with the authority name, the blob length and a Windows CryptoAPI
+/// blob header.
+fn detect_bi_blob(bytes: &[u8]) -> Option<FileKind> {
+    let name_end = bytes.iter().take(256).position(|b| *b == 0)?;
+    let header = bytes.get((name_end + 1)..(name_end + 17))?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+    match (&header[4..12], &header[12..16]) {
+        (b"\x07\x02\x00\x00\x00\x24\x00\x00", b"RSA2") => Some(FileKind::PrivateKey),
+        (b"\x06\x02\x00\x00\x00\x24\x00\x00", b"RSA1") => {
+            // A signature contains a public key followed by the signatures themselves
+            if bytes.len() > name_end + 5 + length {
+                Some(FileKind::Signature)
+            } else {
+                Some(FileKind::PublicKey)
+            }
+        },
+        _ => None
+    }
+}
+
+fn detect_paa(bytes: &[u8]) -> Option<PaaType> {
+    let paa_type = PaaType::from_u16(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]))?;
+    let tagg = bytes.get(2..6)?;
+
+    if tagg == b"GGAT" || tagg == b"TAGG" {
+        Some(paa_type)
+    } else {
+        None
+    }
+}
+
+/// PBOs start with an entry header: a file name followed by the packing method. Most start with
+/// the header extension entry, older ones directly with a file entry.
+fn is_pbo(bytes: &[u8]) -> bool {
+    if bytes.starts_with(b"\0sreV") {
+        return true;
+    }
+
+    let name_end = match bytes.iter().take(512).position(|b| *b == 0) {
+        Some(pos) if pos > 0 => pos,
+        _ => return false
+    };
+    if !bytes[..name_end].iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return false;
+    }
+
+    match bytes.get((name_end + 1)..(name_end + 5)) {
+        Some(method) => method == b"\0\0\0\0" || method == b"srpC",
+        None => false
+    }
+}
+
+/// A container that tools ship instead of a `.pbo`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PboVariant {
+    /// Extension without the dot, compared ignoring case
+    pub extension: &'static str,
+    pub description: &'static str,
+}
+
+/// Known extensions of PBOs. Whether a file is encrypted is told by its content, see
+/// `ENCRYPTED_METHODS`, so one of these that isn't is read as a plain PBO.
+pub const PBO_VARIANTS: [PboVariant; 3] = [
+    PboVariant { extension: "ebo", description: "an encrypted PBO (EBO)" },
+    PboVariant { extension: "xbo", description: "an Xbox PBO" },
+    PboVariant { extension: "ifa", description: "a renamed PBO (IFA)" },
+];
+
+/// Packing methods of entries whose data is encrypted ("Encr"), with the container they mark.
+/// The headers are readable, but the data can't be read without the key only the game has.
+const ENCRYPTED_METHODS: [(&[u8; 4], &str); 1] = [
+    (b"rcnE", "an encrypted PBO (EBO)"),
+];
+
+/// Returns the variant for the extension of a path, if it is one of `PBO_VARIANTS`.
+pub fn pbo_variant(path: &Path) -> Option<PboVariant> {
+    let extension = path.extension()?.to_str()?;
+    PBO_VARIANTS.iter().find(|v| v.extension.eq_ignore_ascii_case(extension)).copied()
+}
+
+/// Returns the container of a PBO whose entry headers, as far as they are in `bytes`, mark
+/// encrypted data, see `ENCRYPTED_METHODS`.
+pub fn encrypted_container(bytes: &[u8]) -> Option<&'static str> {
+    let mut pos = 0;
+    loop {
+        let name_length = bytes.get(pos..)?.iter().position(|b| *b == 0)?;
+        let method = bytes.get((pos + name_length + 1)..(pos + name_length + 5))?;
+        if let Some((_, description)) = ENCRYPTED_METHODS.iter().find(|(m, _)| &m[..] == method) {
+            return Some(description);
+        }
+        pos += name_length + 21;
+
+        match (name_length, method) {
+            // The header extensions after the version header end with an empty string
+            (0, b"sreV") => loop {
+                let length = bytes.get(pos..)?.iter().position(|b| *b == 0)?;
+                pos += length + 1;
+                if length == 0 {
+                    break;
+                }
+            },
+            (0, _) => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Checks a file that is about to be read as a PBO. Encrypted ones fail with an error saying so
+/// instead of a header error further on. Plain ones with the extension of a variant are read with
+/// a `renamed-pbo` warning if they start with a PBO header, or fail if they don't.
+pub fn check_pbo_container(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    if let Some(description) = encrypted_container(bytes) {
+        return Err(coded_error!("E0308", "{} is {}, which can't be read. Its content is encrypted and only the game can decrypt it.",
+            path.display(), description));
+    }
+
+    let Some(variant) = pbo_variant(path) else {
+        return Ok(());
+    };
+    if !is_pbo(bytes) {
+        return Err(coded_error!("E0309", "{} has the extension of {}, but doesn't start with a PBO header.", path.display(), variant.description));
+    }
+    warning(format!("{} has the extension of {}, reading it as a plain PBO.", path.display(), variant.description), Some("renamed-pbo"), (Some(path.display().to_string()), None));
+    Ok(())
+}
+
+/// Reads the start of a file and checks it like `check_pbo_container`.
+pub fn check_pbo_file(path: &Path) -> Result<(), Error> {
+    let mut buffer: Vec<u8> = Vec::new();
+    File::open(path).prepend_error(format!("Failed to open {}:", path.display()))?.take(DETECT_LENGTH as u64).read_to_end(&mut buffer)
+        .prepend_error(format!("Failed to read {}:", path.display()))?;
+    check_pbo_container(path, &buffer)
+}
+
+fn print_hexdump(bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        println!("{:08x}  {:47}  |{}|", i * 16, hex.join(" "), ascii);
+    }
+}
+
+/// Detects the format of the file and inspects it with the matching command, or suggests a
+/// command if there's no inspection for the format.
+///
+/// Unknown files are shown as a hexdump of their first 64 bytes and result in an error.
+pub fn cmd_inspect_any(path: &Path) -> Result<(), Error> {
+    check_pbo_file(path)?;
+    let kind = FileKind::detect_file(path).prepend_error("Failed to read input file:")?;
+
+    match kind {
+        FileKind::Pbo => pbo::cmd_inspect(&mut File::open(path)?),
+        FileKind::RapifiedConfig => config::cmd_derapify(&mut File::open(path)?, &mut stdout(), config::StringEncoding::default()),
+        FileKind::Signature => sign::cmd_signature_info(path, &mut stdout()),
+        FileKind::Paa(_) => paa::cmd_paa_info(path, &mut stdout()),
+        FileKind::Unknown => {
+            let mut buffer: Vec<u8> = Vec::new();
+            File::open(path)?.take(64).read_to_end(&mut buffer)?;
+            print_hexdump(&buffer);
+            println!();
+            println!("Supported formats: rapified configs, PAA textures, PBOs, MLOD and ODOL models, keys and signatures.");
+
+            Err(coded_error!("E0828", "Failed to detect the format of {}.", path.display()))
+        },
+        kind => {
+            match kind.suggested_command() {
+                Some(command) => println!("{} looks like {}, try \"armake2 {}\".", path.display(), kind.description(), command),
+                None => println!("{} looks like {}, which can't be inspected.", path.display(), kind.description()),
+            }
+            Ok(())
+        }
+    }
+}
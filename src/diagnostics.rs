@@ -0,0 +1,465 @@
+//! Stable codes of errors and warnings
+//!
+//! Every distinct error of parsing, preprocessing and validation and every named warning has a
+//! code that doesn't change between versions, so editors and scripts can tell them apart without
+//! matching the message text. Errors are `E` codes and warnings `W` codes, grouped by hundreds:
+//! configs (01), the preprocessor (02), PBOs (03), textures (04), sounds (05), models (06),
+//! missions (07), signing and the rest (08) and arguments (09). Codes of removed diagnostics are
+//! never reused.
+//!
+//! Errors of files that can't be opened or read are the ones of the operating system and have no
+//! code.
+
+use std::io::{Error, Write};
+
+use crate::*;
+
+/// A kind of error or warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    /// Name of a warning, the one `-w` mutes
+    pub name: Option<&'static str>,
+    pub summary: &'static str,
+    /// What causes it and how to fix it, printed by `explain`
+    pub explanation: &'static str,
+}
+
+const fn error_entry(code: &'static str, summary: &'static str, explanation: &'static str) -> Diagnostic {
+    Diagnostic { code, name: None, summary, explanation }
+}
+
+const fn warning_entry(code: &'static str, name: &'static str, summary: &'static str, explanation: &'static str) -> Diagnostic {
+    Diagnostic { code, name: Some(name), summary, explanation }
+}
+
+pub const DIAGNOSTICS: &[Diagnostic] = &[
+    error_entry("E0101", "Config syntax error",
+        "The config doesn't follow the config syntax at the marked position. The message lists the tokens that \
+        could have followed. Common causes are a missing semicolon after a property or a class body, and \
+        unbalanced braces that a macro expanded to."),
+    error_entry("E0102", "Class inside an array",
+        "Arrays may only contain numbers, strings and other arrays. Define the class outside of the array and \
+        refer to it by name."),
+    error_entry("E0103", "Indexed array assignment",
+        "Assigning to a single element like \"list[2] = 1;\" is valid in scripts but not in configs. Assign the \
+        whole array with \"list[] = {...};\"."),
+    error_entry("E0104", "Class defined twice",
+        "A class is defined more than once in the same scope and --strict-duplicates is given. Without it, both \
+        definitions are merged like the engine does, with a W0103 warning."),
+    error_entry("E0105", "Unexpected directive",
+        "A preprocessor directive like #pragma is left over in the preprocessed config and --strict-directives is \
+        given. Without it, the line is skipped with a W0102 warning."),
+    error_entry("E0106", "Not a rapified config",
+        "The file doesn't start with the signature of rapified configs. It may be a text config, which has to be \
+        rapified first, or another kind of file."),
+    error_entry("E0107", "Unknown entry type in rapified config",
+        "A rapified config contains an entry, property or array element type that doesn't exist. The file is \
+        corrupt or was written by a broken tool."),
+    error_entry("E0108", "String can't be encoded",
+        "A string of the config doesn't exist in the chosen string encoding, or the bytes of a rapified string \
+        aren't valid in it. Choose the encoding the config was written in with --string-encoding."),
+    error_entry("E0109", "Config entry not found",
+        "No class or property of the config has the path that was asked for. Paths are class names separated by \
+        slashes, like CfgPatches/myaddon/units, and compared ignoring case."),
+    error_entry("E0110", "Offset of a text config",
+        "Only rapified configs store entries at offsets. Rapify the config first to learn where an entry is."),
+    error_entry("E0111", "Invalid schema",
+        "The file given with --schema isn't a JSON object with one key per config root whose value is a list of \
+        properties or an object mapping class names to them."),
+
+    error_entry("E0201", "Preprocessor syntax error",
+        "A line doesn't follow the syntax of preprocessor directives and macro calls at the marked position, for \
+        example a macro call with unbalanced parentheses."),
+    error_entry("E0202", "Included file not found",
+        "An #include names a file that exists neither relative to the including file nor in any of the include \
+        folders given with -i."),
+    error_entry("E0203", "Includes nested too deep",
+        "Includes are nested deeper than --max-include-depth allows. The message shows the chain of includes, \
+        which usually is a file that includes itself."),
+    error_entry("E0204", "Macros nested too deep",
+        "A macro expands to calls of macros deeper than --max-macro-depth allows. The message shows the chain of \
+        macros, which usually contains one that refers to itself."),
+    error_entry("E0205", "Preprocessed output too large",
+        "The preprocessed output or the expansion of a macro is larger than --max-output-size allows. Macros that \
+        refer to each other can grow exponentially."),
+    error_entry("E0206", "Invalid directive",
+        "A directive is missing its arguments or has arguments it doesn't take, like #define without a name."),
+
+    error_entry("E0301", "Version header out of place",
+        "The PBO contains a version header after its first entry header. The file is corrupt or was written by a \
+        broken tool."),
+    error_entry("E0302", "Entry data past the end of the file",
+        "The headers of the PBO declare more data than the file contains. The file is truncated, or its headers \
+        were damaged, which --lenient can work around when unpacking."),
+    error_entry("E0303", "Checksum missing",
+        "The SHA-1 checksum after the data of the last entry is missing or too short, so the file is truncated."),
+    error_entry("E0304", "Prefix disagrees with $PBO_META$.json",
+        "A header extension like the prefix has different values in $PBOPREFIX$ and $PBO_META$.json. Remove it \
+        from one of both files."),
+    error_entry("E0305", "PBO too large",
+        "The PBO would be larger than the 4 GiB the 32-bit offsets of the format can address. Split the files \
+        into several PBOs."),
+    error_entry("E0306", "Entry outside of the output folder",
+        "The name of an entry would be written outside of the folder the PBO is unpacked into, like \
+        \"..\\..\\file\". Unpack it with --lenient to sanitize the names."),
+    error_entry("E0307", "Entries collide",
+        "Several entries would be written to the same file, because their names only differ by case or the \
+        flattened names are the same. Nothing was written."),
+    error_entry("E0308", "Encrypted PBO",
+        "The entry headers of the file mark its data as encrypted, like those of EBOs, and only the game can \
+        decrypt it, so it can't be read or unpacked. The extension doesn't matter."),
+    error_entry("E0309", "Renamed PBO without PBO header",
+        "The file has the extension of a PBO variant like .xbo or .ebo, but its content isn't a PBO."),
+    error_entry("E0310", "Referenced files missing",
+        "Files the configs, models or materials of the PBO reference aren't part of it or of the paths given \
+        with --check-paths. Each one is listed as a W0304 warning."),
+    error_entry("E0311", "Entry not in the PBO",
+        "The PBO has no entry with the name that was asked for, or lost it since it was indexed. Names are \
+        compared ignoring case and slash direction."),
+    error_entry("E0312", "Path not in the mod folders",
+        "None of the PBOs in the --mod-dir folders contains the path, neither by its prefix nor by its entry \
+        names."),
+    error_entry("E0313", "Path leaves its folder",
+        "A path contains \"..\", a drive letter or stream name with \":\" or a null character, which could \
+        refer to a file outside of the folder it is resolved in. Paths in game can't contain any of them."),
+    error_entry("E0314", "File name isn't UTF-8",
+        "A file name isn't valid UTF-8, which the names of PBO entries have to be. Rename the file."),
+    error_entry("E0315", "Invalid $PBO_META$.json",
+        "The $PBO_META$.json of the source folder isn't a JSON object whose values are all strings. It is \
+        written by unpack and lists the header extensions of the PBO."),
+    error_entry("E0316", "Not a supported PBO patch",
+        "The file doesn't start with the signature of patches written by \"armake2 diff\", or was written by a \
+        version with another patch format."),
+    error_entry("E0317", "Corrupt PBO patch",
+        "The patch contains an unknown entry type or delta operation, or a delta that copies past the end of the \
+        old entry. The file is truncated or was modified."),
+    error_entry("E0318", "Patch for another PBO",
+        "The patch was made for a PBO with other contents than the one it is applied to, or refers to entries \
+        that PBO doesn't have. Apply it to the exact PBO it was made from."),
+    error_entry("E0319", "Patched PBO doesn't match",
+        "An entry or the whole PBO rebuilt from the patch doesn't have the checksum recorded in the patch. The \
+        old PBO or the patch is corrupt."),
+
+    error_entry("E0401", "Texture policy violated",
+        "Textures of a build don't follow the rules of its --texture-policy, or of the policy checked with \
+        --enforce. Each violation is listed with the rule it breaks."),
+    error_entry("E0402", "Albedo out of range",
+        "Diffuse textures have too many pixels outside the albedo range. Each one is listed as a W0416 \
+        warning."),
+    error_entry("E0403", "Unknown PAA type",
+        "The type code at the start of the PAA isn't one of the known DXT or ARGB types. The file is corrupt or \
+        isn't a PAA."),
+    error_entry("E0404", "Corrupt mipmap table",
+        "A mipmap of the PAA has a size or offset that can't be right, like a size that was truncated when it was \
+        written, a compressed mipmap without data or an offset into the taggs. The file was written by a broken \
+        tool and has to be converted again from its source image."),
+    error_entry("E0405", "Image too large for a PAA",
+        "The image is larger than the dimensions a PAA mipmap can store. Scale it down before converting it."),
+    error_entry("E0406", "Alpha lost with DXT1",
+        "The texture has an alpha channel that isn't fully opaque, which DXT1 can't store. Keep it DXT5 or pass \
+        --allow-lossy-alpha."),
+    error_entry("E0407", "Image unreadable",
+        "An image or mask isn't in a format that can be decoded, or its extension doesn't name one. PNG, TGA, \
+        JPEG and the other formats of the image crate are supported."),
+    error_entry("E0408", "Image can't be saved",
+        "The image couldn't be encoded in the format its extension names. Choose an extension like .png."),
+    error_entry("E0409", "Corrupt LZO data",
+        "LZO compressed data of a PAA doesn't decompress to the size its mipmap needs, refers to data before its \
+        start or ends early. The file is truncated or corrupt."),
+    error_entry("E0410", "PAA without mipmaps",
+        "The PAA has no mipmaps, so there is no image to read. The file was written by a broken tool."),
+    error_entry("E0411", "Mipmap doesn't exist",
+        "The mipmap level that was asked for is larger than the number of mipmaps of the PAA minus one."),
+    error_entry("E0412", "Not block compressed",
+        "Only DXT PAAs are made of blocks that can be copied into a DDS. Convert ARGB PAAs through an image."),
+    error_entry("E0413", "DDS can't be written",
+        "The mipmaps of the PAA don't halve in size like the ones of a DDS have to, or the DDS couldn't be \
+        created for their format."),
+    error_entry("E0414", "Can't be flipped",
+        "Blocks copied from a DDS as they are and mipmaps decoded in strips can't be flipped vertically. Convert \
+        the whole image or leave out the flip."),
+    error_entry("E0415", "Invalid mipmaps for a PAA",
+        "Mipmaps given to build a PAA are missing, have a width or height of 0, or have more or less data than \
+        their size and type need."),
+    error_entry("E0416", "Unsupported DDS",
+        "Only DDS files with a legacy header in DXT1, DXT3 or DXT5 can be copied into a PAA. Cube maps, volume \
+        textures and DX10 headers aren't supported. Save the DDS again as DXT1 or DXT5."),
+    error_entry("E0417", "Corrupt DDS",
+        "The DDS can't be read or ends within one of its mipmaps. The file is truncated or corrupt."),
+    error_entry("E0418", "PAA already has the type",
+        "The PAA already is of the type it was asked to be converted to, so there is nothing to do."),
+    error_entry("E0419", "Mipmap data size mismatch",
+        "A mipmap has another number of bytes than its size and type need, and isn't LZO compressed either. The \
+        file is corrupt or the type at its start is wrong."),
+    error_entry("E0420", "Image sizes differ",
+        "A mask or source image that is compared to a texture has to be as large as its first mipmap."),
+    error_entry("E0421", "Invalid texHeaders.bin",
+        "The file isn't a texHeaders.bin of version 1, or the mipmap counts in one of its headers disagree. \
+        Delete it and let build or texheaders write it again."),
+    error_entry("E0422", "Invalid texture policy",
+        "The texture policy file isn't valid TOML of the documented form, or a rule has no or an invalid files \
+        pattern, a max_size of 0 or an unknown PAA type."),
+
+    error_entry("E0601", "Corrupt ODOL model",
+        "A binarized model ends early, has an offset outside of the file, an unknown animation type or an LZSS \
+        stream that doesn't decompress. The file is truncated or was written by an unknown binarize version."),
+    error_entry("E0602", "Not a model",
+        "The file starts neither with the MLOD nor with the ODOL signature."),
+    error_entry("E0603", "Unsupported ODOL version",
+        "The binarized model has a version that can't be read. The message lists the supported versions."),
+
+    error_entry("E0701", "Mission folder name",
+        "Missions have to be in a folder named missionname.worldname, like mymission.Altis, unless the world is \
+        given explicitly, since the name of the mission PBO is made from both."),
+    error_entry("E0702", "briefingName isn't a string",
+        "The briefingName of the mission.sqm has to be a string to be used as the name of the mission."),
+    error_entry("E0703", "mission.sqm missing",
+        "The mission folder has no mission.sqm, which every mission needs."),
+
+    error_entry("E0801", "Invalid key",
+        "The key file is shorter than its header says, isn't an RSA key or, for manifests, the secret is empty. \
+        The file is truncated or isn't a key."),
+    error_entry("E0802", "Signature doesn't match",
+        "One of the three hashes of the signature doesn't match the PBO. It was modified after signing, signed \
+        with a different key or signed with a different signature version."),
+    error_entry("E0803", "Invalid signature",
+        "The signature file is truncated or has a BISign version other than 2 and 3."),
+    error_entry("E0804", "Wrong kind of key or file",
+        "The command got a public key where it needs the private one, a key where it needs a signature, or the \
+        other way around. The message says which file it expects."),
+    error_entry("E0805", "Output already exists",
+        "The file or folder to be written already exists. Pass -f or --force to overwrite it."),
+    error_entry("E0806", "Invalid authority",
+        "An authority is part of the signature file name, so it can't be empty or contain slashes, and two keys \
+        can't have the same one."),
+    error_entry("E0807", "PBO of the signature not found",
+        "Signatures are named <pbo name>.<authority>.bisign, and no PBO with that name is next to the signature."),
+    error_entry("E0808", "Signed with another key",
+        "The signature or manifest was made with a key other than the one given. Only the private key that made \
+        a signature can convert it, the others have to sign the PBO for the first time."),
+    error_entry("E0809", "PBOs not accepted",
+        "Some of the checked PBOs have no signature that the given keys accept. Each one is listed above."),
+    error_entry("E0810", "Manifest signature invalid",
+        "The signature line of the manifest is missing, of another kind than the key or doesn't match its \
+        contents. The manifest was modified after signing or signed with a different key."),
+    error_entry("E0811", "Invalid manifest",
+        "The manifest isn't one written by \"armake2 manifest\" of this version, or has a line that can't be \
+        read."),
+    error_entry("E0812", "File name can't be listed",
+        "File names with line breaks can't be written to a manifest, which lists one file per line."),
+    error_entry("E0813", "Files don't match the manifest",
+        "Files were added, removed or modified since the manifest was written. Each one is listed above."),
+    error_entry("E0814", "Invalid job manifest",
+        "The job manifest of a resumed batch conversion isn't valid JSON. Delete it to start over."),
+    error_entry("E0815", "Cache folder missing",
+        "The cache folder to clean up doesn't exist."),
+    error_entry("E0816", "Report can't be written",
+        "The build report couldn't be encoded in the chosen format."),
+    error_entry("E0817", "Not available",
+        "The feature isn't available on this platform or in this build, like binarize.exe outside of Windows, \
+        Unix sockets outside of Unix or serve without the serve feature."),
+    error_entry("E0818", "binarize.exe not found",
+        "The registry names a binarize.exe of the Arma 3 Tools that doesn't exist. Install or repair the tools."),
+    error_entry("E0819", "binarize.exe failed",
+        "binarize.exe ended with an error. Set BIOUTPUT=1 to see its output."),
+    error_entry("E0820", "Threads unavailable",
+        "The pool of worker threads couldn't be created."),
+    error_entry("E0821", "Invalid project file",
+        "The armake.toml isn't valid TOML of the documented form, or a value is out of range, like an alignment \
+        of 0 or a signature version other than 2 and 3."),
+    error_entry("E0822", "Conversions failed",
+        "Some files of a batch conversion failed. Each one is listed above with its error."),
+    error_entry("E0823", "Server stopped",
+        "All workers of the verification server stopped, so no more connections can be handled."),
+    error_entry("E0824", "Invalid request",
+        "A request to the verification server is larger than allowed, isn't valid JSON, names an unknown \
+        operation or a path that isn't a file."),
+    error_entry("E0825", "Targets collide",
+        "Several files would be written to the same target, which happens when names only differ in case on a \
+        filesystem that ignores it. Rename one of them."),
+    error_entry("E0826", "Path too long",
+        "The path or one of its components is longer than Windows allows. Unpack to a shorter folder."),
+    error_entry("E0827", "Value too large for its field",
+        "A size or count is larger than the 32-bit field the file format stores it in."),
+    error_entry("E0828", "Unknown file format",
+        "The file isn't one of the formats inspect knows: rapified configs, PAA textures, PBOs, models, keys \
+        and signatures."),
+    error_entry("E0829", "PBOs failed to build",
+        "Some PBOs of build --all failed. Each one is listed above with its error. Without --keep-going, PBOs \
+        that weren't started yet when the first one failed aren't built or reported."),
+
+    error_entry("E0901", "Invalid option value",
+        "The value of an option or argument isn't one it takes. The message lists the accepted values."),
+    error_entry("E0902", "Options conflict",
+        "Options were combined that can't be used together, like signing a PBO written to stdout. The message \
+        says which."),
+    error_entry("E0903", "Argument missing",
+        "The command needs an argument or option that wasn't given."),
+
+    warning_entry("W0101", "unquoted-string", "String not quoted",
+        "A string value or array element isn't quoted. The engine accepts it, but it ends at the next semicolon \
+        or comma, and macros in it are expanded. Quote the string."),
+    warning_entry("W0102", "ignored-directive", "Directive left in the config",
+        "A preprocessor directive like #pragma is left over after preprocessing and skipped. --strict-directives \
+        makes it the error E0105."),
+    warning_entry("W0103", "duplicate-class-merged", "Class defined twice",
+        "A class is defined more than once in the same scope, and both definitions are merged like the engine \
+        does. --strict-duplicates makes it the error E0104."),
+    warning_entry("W0104", "class-case-mismatch", "Class spelled differently",
+        "A class is spelled with other letter case than where it was first defined. Class names are compared \
+        ignoring case, so both refer to the same class."),
+    warning_entry("W0105", "undefined-base-class", "Undefined base class",
+        "A class inherits from a class that isn't defined in its scope. Declare it with \"class Name;\" if it comes \
+        from another addon."),
+    warning_entry("W0106", "unknown-property", "Unknown property",
+        "A class has a property the schema given with --schema doesn't know, often a typo of a known one."),
+    warning_entry("W0107", "number-in-string-property", "Number in string property",
+        "A property the engine reads as a string is assigned a number. Quote the value."),
+    warning_entry("W0108", "string-looks-numeric", "String looks like a number",
+        "A property that isn't known to be a string is assigned a quoted number, which the engine doesn't read as \
+        a number. Remove the quotes if a number is meant."),
+    warning_entry("W0109", "integer-overflow", "Integer too large",
+        "An integer doesn't fit into 32 bits and is stored as a float, which can't represent it exactly."),
+    warning_entry("W0110", "special-float", "NaN, infinite or negative zero float",
+        "A rapified config contains a float that text configs can't express. NaN is written as 0.0 and infinities \
+        as the largest float. Such values usually come from corrupt files."),
+
+    warning_entry("W0301", "legacy-pbo-header", "Legacy PBO header",
+        "The version header of the PBO has fields that current tools leave empty. They are kept when the PBO is \
+        written again."),
+    warning_entry("W0302", "unlocatable-entry", "Entry data not found",
+        "While unpacking with --lenient, the data of an entry couldn't be located in the file, so the entry is \
+        skipped."),
+    warning_entry("W0303", "cfgpatches-missing-class", "Class not in CfgPatches",
+        "A vehicle or weapon class the config defines isn't listed in units[] or weapons[] of CfgPatches, so the \
+        editor and Zeus don't know it. --auto-cfgpatches adds the missing ones."),
+    warning_entry("W0304", "missing-resource", "Referenced file missing",
+        "A config, model or material references a file that isn't part of the PBO or of the paths given with \
+        --check-paths. --strict-resources makes missing files the error E0310."),
+    warning_entry("W0305", "renamed-pbo", "Renamed PBO",
+        "The file is a plain PBO with the extension of a variant, like .xbo or an .ebo that isn't encrypted, and \
+        is read as a PBO."),
+    warning_entry("W0306", "non-windows-binarization", "Binarization unavailable",
+        "binarize.exe only runs on Windows, so the file is packed without binarizing it."),
+    warning_entry("W0307", "grep-derapify-failed", "Config can't be derapified for grep",
+        "A rapified config couldn't be derapified for --derap and is searched as binary instead."),
+
+    warning_entry("W0401", "dxt-size-mismatch", "DXT mipmap size mismatch",
+        "A DXT mipmap has more data than its dimensions need. The rest is ignored."),
+    warning_entry("W0402", "dxt1-alpha", "Alpha lost with DXT1",
+        "The alpha channel is put into a DXT1 texture, which only stores fully opaque or fully transparent \
+        pixels. Use DXT5 to keep it."),
+    warning_entry("W0403", "compression-misdetected", "Unflagged LZO compression",
+        "A mipmap is LZO compressed without the flag that says so. It is decompressed for decoding, and written \
+        flagged correctly."),
+    warning_entry("W0404", "icc-ignored", "ICC profile ignored",
+        "The image has an embedded color profile, which is ignored because textures are treated as sRGB. Convert \
+        the image to sRGB first if its colors shift."),
+    warning_entry("W0405", "lzo-unavailable", "LZO compression failed",
+        "The LZO compressor couldn't be used, so the remaining mipmaps are written uncompressed."),
+    warning_entry("W0406", "nonstandard-compression-flag", "Compression flag on the height",
+        "A mipmap has its LZO flag on the height instead of the width, like some third-party tools write it. It \
+        is read as compressed."),
+    warning_entry("W0407", "nonstandard-tagg-order", "Taggs after mipmap data",
+        "The PAA has taggs after non-tagg data, so it was written by a nonstandard tool. It is normalized when it \
+        is written again."),
+    warning_entry("W0408", "thumbnail-failed", "Thumbnail failed",
+        "A texture couldn't be decoded for its thumbnail, so a placeholder was written."),
+    warning_entry("W0409", "unordered-mipmaps", "Mipmaps out of order",
+        "The mipmaps are not stored from largest to smallest. They are sorted when the PAA is written again."),
+    warning_entry("W0410", "downscale-failed", "Downscaling failed",
+        "A texture couldn't be scaled down to --texture-max-size, so the original is packed."),
+    warning_entry("W0411", "texheaders-copied", "Texture header copied",
+        "A texture of another addon isn't in the --mod-dir folders, so its header is copied from the previous \
+        texHeaders.bin, which may be outdated."),
+    warning_entry("W0412", "texheaders-missing", "Texture header missing",
+        "A texture of another addon is neither in the --mod-dir folders nor in the previous texHeaders.bin, so \
+        it is left out of the new one."),
+    warning_entry("W0413", "texheaders-unreadable", "Texture header unreadable",
+        "A texture of the PBO couldn't be read for texHeaders.bin, so it is left out."),
+    warning_entry("W0414", "texture-policy", "Texture policy violated",
+        "A texture doesn't follow a rule of the texture policy. --enforce makes violations the error E0401, \
+        which they always are when building or packing with a policy."),
+    warning_entry("W0415", "texture-policy-unreadable", "Texture unreadable for the policy",
+        "A texture couldn't be read to check it against the texture policy."),
+    warning_entry("W0416", "albedo-out-of-range", "Albedo out of range",
+        "Too many pixels of a diffuse texture are brighter or darker than physically plausible, which looks \
+        wrong under the lighting of the engine."),
+    warning_entry("W0417", "textures-downscaled", "Textures downscaled",
+        "Textures larger than --texture-max-size were packed downscaled. The sources are unchanged."),
+
+    warning_entry("W0501", "audio-truncated", "Truncated sound file",
+        "A sound file ends before the data its headers describe. The engine may crash or play nothing."),
+    warning_entry("W0502", "audio-invalid", "Invalid sound file",
+        "The headers of a sound file are corrupt or of a codec the engine doesn't play."),
+    warning_entry("W0503", "audio-empty", "Empty sound",
+        "A sound file has no samples."),
+    warning_entry("W0504", "audio-sample-rate", "Unusual sample rate",
+        "A sound has a sample rate other than 44100 or 48000 Hz, which the engine resamples or plays at the wrong \
+        speed."),
+    warning_entry("W0505", "audio-channels", "Too many channels",
+        "A sound has more than two channels, which the engine can't position."),
+
+    warning_entry("W0601", "modelcfg-unknown-skeleton", "Unknown skeleton",
+        "A model or skeleton refers to a skeleton that isn't defined in CfgSkeletons."),
+    warning_entry("W0602", "modelcfg-bone-pairs", "Odd number of bones",
+        "skeletonBones[] lists pairs of bone and parent, but has an odd number of elements."),
+    warning_entry("W0603", "modelcfg-duplicate-bone", "Bone defined twice",
+        "A bone is listed more than once in a skeleton."),
+    warning_entry("W0604", "modelcfg-undefined-parent", "Undefined parent bone",
+        "The parent of a bone isn't a bone of the skeleton, or is defined after its child."),
+    warning_entry("W0605", "modelcfg-unknown-selection", "Animation of unknown bone",
+        "An animation moves a selection that isn't a bone of the skeleton of the model."),
+    warning_entry("W0606", "modelcfg-missing-selection", "Selection missing in model",
+        "An animation or bone uses a selection the model doesn't have."),
+    warning_entry("W0607", "modelcfg-missing-model", "Model without class",
+        "A model next to the model.cfg has no class in CfgModels, so it gets no skeleton or animations."),
+    warning_entry("W0608", "modelcfg-unknown-model", "Class without model",
+        "A class of CfgModels has no model of its name next to the model.cfg."),
+
+    warning_entry("W0701", "mission-briefing-name", "briefingName unreadable",
+        "The briefingName of description.ext couldn't be read. The mission is packed without checking it."),
+    warning_entry("W0702", "mission-image-exists", "Mission image already converted",
+        "An image of the mission is left out because a PAA of the same name already exists."),
+    warning_entry("W0703", "mission-pbo-name", "Mission PBO name",
+        "The PBO of a mission should be named missionname.worldname.pbo after its folder, which tells the \
+        game the world it plays on."),
+
+    warning_entry("W0801", "invalid-key", "Invalid key",
+        "A file in the keys folder couldn't be read as a public key and is ignored."),
+    warning_entry("W0802", "trailing-data", "Data after the checksum",
+        "The PBO has data after its checksum, which is ignored when reading and signing it."),
+    warning_entry("W0803", "serve-connection-failed", "Connection failed",
+        "A connection to the verification server failed or couldn't be accepted."),
+    warning_entry("W0804", "legacy-cli", "Command line of the original armake",
+        "The command line uses spellings of the original armake, which are translated to the armake2 ones. They \
+        might be removed in the future."),
+];
+
+/// Returns the diagnostic with the code, ignoring case.
+pub fn by_code(code: &str) -> Option<&'static Diagnostic> {
+    DIAGNOSTICS.iter().find(|d| d.code.eq_ignore_ascii_case(code))
+}
+
+/// Returns the code of a warning name.
+pub fn warning_code(name: &str) -> Option<&'static str> {
+    DIAGNOSTICS.iter().find(|d| d.name == Some(name)).map(|d| d.code)
+}
+
+/// Returns the name of a warning type as it is in the table, `None` for unknown names.
+pub fn warning_name(name: &str) -> Option<&'static str> {
+    DIAGNOSTICS.iter().find_map(|d| d.name.filter(|n| *n == name))
+}
+
+/// Prints the summary and explanation of a code.
+pub fn cmd_explain<O: Write>(code: &str, output: &mut O) -> Result<(), Error> {
+    let diagnostic = by_code(code).ok_or_else(|| coded_error!("E0901", "Unknown diagnostic code \"{}\".", code))?;
+    match diagnostic.name {
+        Some(name) => writeln!(output, "{}: {} (warning \"{}\")", diagnostic.code, diagnostic.summary, name)?,
+        None => writeln!(output, "{}: {}", diagnostic.code, diagnostic.summary)?,
+    }
+    writeln!(output)?;
+    writeln!(output, "{}", diagnostic.explanation)?;
+    Ok(())
+}
@@ -1,14 +1,20 @@
 #![macro_use]
 
+use std::cell::{RefCell};
 use std::cmp::{min};
-use std::collections::HashSet;
-use std::fmt::{Display};
-use std::io::{Error};
+use std::fmt::{self, Display};
+use std::io::{Error, Write};
 use std::path::{PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use colored::*;
+use peg::error::{ExpectedSet};
+use serde::{Deserialize, Serialize};
 
 use crate::config::*;
+use crate::diagnostics;
+use crate::io::{create_file};
 use crate::preprocess::*;
 use crate::warnings::*;
 
@@ -19,6 +25,160 @@ macro_rules! error {
     )
 }
 
+/// Creates an error with a code of `diagnostics::DIAGNOSTICS`, which `prepend_error` keeps.
+#[macro_export]
+macro_rules! coded_error {
+    ($code:expr, $($arg:tt)*) => (
+        std::io::Error::from($crate::error::CodedError::new($code, format!($($arg)*)))
+    )
+}
+
+/// An error with a diagnostic code and, for parse errors, the position in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodedError {
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl CodedError {
+    pub fn new(code: &'static str, message: String) -> CodedError {
+        CodedError { code, message, file: None, line: None, column: None }
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+impl From<CodedError> for Error {
+    fn from(error: CodedError) -> Error {
+        Error::other(error)
+    }
+}
+
+/// Returns the code of an error created with `coded_error!`, if it has one.
+pub fn coded(error: &Error) -> Option<&CodedError> {
+    error.get_ref().and_then(|e| e.downcast_ref::<CodedError>())
+}
+
+/// Whether errors and warnings are printed as JSON lines, see `set_json_diagnostics`
+static JSON_DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+
+/// Prints errors and warnings as one JSON object per line instead of text, without colors.
+pub fn set_json_diagnostics(enabled: bool) {
+    JSON_DIAGNOSTICS.store(enabled, Ordering::Relaxed);
+    if enabled {
+        colored::control::set_override(false);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or warning as printed with `--message-format json` and collected with
+/// `collect_diagnostics`. File, line and column are the location passed to `warning`, or the
+/// position of a parse error.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollectedDiagnostic {
+    pub level: Severity,
+    /// Code of `diagnostics::DIAGNOSTICS`, if the error or warning has one
+    pub code: Option<String>,
+    /// Name of the warning type, the one `-w` takes
+    pub name: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+/// Diagnostics collected so far, `None` unless `collect_diagnostics` is enabled
+static COLLECTED: Mutex<Option<Vec<CollectedDiagnostic>>> = Mutex::new(None);
+
+/// File the collected diagnostics are written to by `write_collected_diagnostics`, `-` for stdout
+static COLLECTED_OUTPUT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Collects the warnings that would be shown and the errors printed with `print_error` instead of
+/// printing them, until `take_diagnostics` drains them. Muted warnings and the ones over their
+/// maximum aren't collected, but the summary of them is. Disabling prints again and drops what
+/// wasn't taken.
+pub fn collect_diagnostics(enabled: bool) {
+    if let Ok(mut collected) = COLLECTED.lock() {
+        if enabled {
+            collected.get_or_insert_with(Vec::new);
+            colored::control::set_override(false);
+        } else {
+            *collected = None;
+        }
+    }
+}
+
+/// Returns the diagnostics collected so far and starts over with none.
+pub fn take_diagnostics() -> Vec<CollectedDiagnostic> {
+    COLLECTED.lock().ok()
+        .and_then(|mut collected| collected.as_mut().map(std::mem::take))
+        .unwrap_or_default()
+}
+
+/// Collects the diagnostic if collecting is enabled, otherwise returns it to be printed.
+fn collect_diagnostic(diagnostic: CollectedDiagnostic) -> Option<CollectedDiagnostic> {
+    if let Ok(mut collected) = COLLECTED.lock() && let Some(collected) = collected.as_mut() {
+        collected.push(diagnostic);
+        return None;
+    }
+    Some(diagnostic)
+}
+
+/// Collects all diagnostics of the run to write them to a file as one JSON array at its end, or
+/// to stdout for `-`.
+pub fn set_json_diagnostics_output(path: PathBuf) {
+    collect_diagnostics(true);
+    if let Ok(mut output) = COLLECTED_OUTPUT.lock() {
+        *output = Some(path);
+    }
+}
+
+/// Writes the diagnostics collected so far to the file set with `set_json_diagnostics_output`.
+/// Does nothing without one.
+pub fn write_collected_diagnostics() -> Result<(), Error> {
+    let Some(path) = COLLECTED_OUTPUT.lock().ok().and_then(|output| output.clone()) else {
+        return Ok(());
+    };
+
+    let mut content = serde_json::to_string_pretty(&take_diagnostics()).map_err(Error::from)?;
+    content.push('\n');
+    if path.as_os_str() == "-" {
+        std::io::stdout().write_all(content.as_bytes())
+    } else {
+        create_file(&path).and_then(|mut file| file.write_all(content.as_bytes()))
+            .prepend_error(format!("Failed to write diagnostics to {}:", path.display()))
+    }
+}
+
+/// Prints the summary of suppressed warnings and writes the collected diagnostics, at the end of
+/// a run.
+pub fn finish_diagnostics() {
+    print_warning_summary();
+    if let Err(error) = write_collected_diagnostics() {
+        eprintln!("{}: {}", "error".red().bold(), error);
+    }
+}
+
+/// Returns a diagnostic as one line of JSON, the format of `--message-format json`.
+fn json_diagnostic(diagnostic: &CollectedDiagnostic) -> String {
+    serde_json::to_string(diagnostic).unwrap_or_default()
+}
+
 pub trait ErrorExt<T> {
     fn prepend_error<M: AsRef<[u8]> + Display>(self, msg: M) -> Result<T, Error>;
     fn print_error(self, exit: bool) -> ();
@@ -27,16 +187,38 @@ impl<T> ErrorExt<T> for Result<T, Error> {
     fn prepend_error<M: AsRef<[u8]> + Display>(self, msg: M) -> Result<T, Error> {
         match self {
             Ok(t) => Ok(t),
-            Err(e) => Err(error!("{}\n{}", msg, e))
+            Err(e) => match coded(&e) {
+                Some(c) => Err(CodedError { message: format!("{}\n{}", msg, e), ..c.clone() }.into()),
+                None => Err(error!("{}\n{}", msg, e))
+            }
         }
     }
 
     fn print_error(self, exit: bool) {
         if let Err(error) = self {
-            eprintln!("{}: {}", "error".red().bold(), error);
+            let code = coded(&error);
+            let diagnostic = CollectedDiagnostic {
+                level: Severity::Error,
+                code: code.map(|c| c.code.to_string()),
+                name: None,
+                file: code.and_then(|c| c.file.clone()),
+                line: code.and_then(|c| c.line).map(|l| l as u32),
+                column: code.and_then(|c| c.column).map(|c| c as u32),
+                message: error.to_string(),
+            };
+            if let Some(diagnostic) = collect_diagnostic(diagnostic) {
+                if JSON_DIAGNOSTICS.load(Ordering::Relaxed) {
+                    eprintln!("{}", json_diagnostic(&diagnostic));
+                } else {
+                    match code {
+                        Some(code) => eprintln!("{}: {} [{}]", "error".red().bold(), error, code.code),
+                        None => eprintln!("{}: {}", "error".red().bold(), error)
+                    }
+                }
+            }
 
             if exit {
-                print_warning_summary();
+                finish_diagnostics();
                 std::process::exit(1);
             }
         }
@@ -51,80 +233,209 @@ impl<T> PreprocessParseErrorExt<T> for Result<T, preprocess_grammar::ParseError>
         match self {
             Ok(t) => Ok(t),
             Err(pe) => {
-                let line_origin = pe.line - 1;
+                let line_origin = pe.location.line - 1;
                 let file_origin = match origin {
                     Some(path) => format!("{}:", path.to_str().unwrap().to_string()),
                     None => "".to_string()
                 };
 
-                let line = input.lines().nth(pe.line - 1).unwrap_or("");
+                let line = input.lines().nth(pe.location.line - 1).unwrap_or("");
 
-                Err(format_parse_error(line, file_origin, line_origin, pe.column, pe.expected))
+                Err(format_parse_error("E0201", line, file_origin, line_origin, pe.location.column, pe.expected))
             }
         }
     }
 }
 
 pub trait ConfigParseErrorExt<T> {
-    fn format_error(self, info: &PreprocessInfo, input: &str) -> Result<T, Error>;
+    fn format_config_error(self, info: &PreprocessInfo, input: &str) -> Result<T, Error>;
 }
 impl<T> ConfigParseErrorExt<T> for Result<T, config_grammar::ParseError> {
-    fn format_error(self, info: &PreprocessInfo, input: &str) -> Result<T, Error> {
+    fn format_config_error(self, info: &PreprocessInfo, input: &str) -> Result<T, Error> {
         match self {
             Ok(t) => Ok(t),
             Err(pe) => {
-                let line_origin = info.line_origins[min(pe.line, info.line_origins.len()) - 1].0 as usize;
-                let file_origin = match &info.line_origins[min(pe.line, info.line_origins.len()) - 1].1 {
-                    Some(path) => format!("{}:", path.to_str().unwrap().to_string()),
-                    None => "".to_string()
-                };
-
-                let line = input.lines().nth(pe.line - 1).unwrap_or("");
-
-                Err(format_parse_error(line, file_origin, line_origin, pe.column, pe.expected))
+                let (line, file_origin, line_origin) = config_error_line(info, input, pe.location.line);
+                Err(format_parse_error("E0101", line, file_origin, line_origin, pe.location.column, pe.expected))
             }
         }
     }
 }
 
-fn format_parse_error(line: &str, file: String, line_number: usize, column_number: usize, expected: HashSet<&'static str>) -> Error {
+/// Returns the line of the preprocessed config with the file and line number it came from.
+fn config_error_line<'a>(info: &PreprocessInfo, input: &'a str, line_number: usize) -> (&'a str, String, usize) {
+    let line_origin = info.line_origins[min(line_number, info.line_origins.len()) - 1].0 as usize;
+    let file_origin = match &info.line_origins[min(line_number, info.line_origins.len()) - 1].1 {
+        Some(path) => format!("{}:", path.to_str().unwrap().to_string()),
+        None => "".to_string()
+    };
+
+    (input.lines().nth(line_number - 1).unwrap_or(""), file_origin, line_origin)
+}
+
+/// Formats an error with a specific message at a position of the preprocessed config, in the
+/// same layout as parse errors.
+pub(crate) fn format_config_error_at(info: &PreprocessInfo, input: &str, pos: usize, code: &'static str, message: &str) -> Error {
+    let line_number = input[..pos].matches('\n').count() + 1;
+    let column = pos - input[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+    let (line, file_origin, line_origin) = config_error_line(info, input, line_number);
+    format_syntax_error(code, line, file_origin, line_origin, column, message)
+}
+
+fn format_parse_error(code: &'static str, line: &str, file: String, line_number: usize, column_number: usize, expected: ExpectedSet) -> Error {
+    // Literals are already quoted, character classes are shown like ['0'..='9']
+    let expected_list: Vec<&str> = expected.tokens().collect();
+
+    format_syntax_error(code, line, file, line_number, column_number, &format!("Unexpected token \"{}\", expected: {}",
+        line.chars().map(|x| x.to_string()).nth(column_number - 1).unwrap_or_else(|| "\\n".to_string()),
+        expected_list.join(", ")))
+}
+
+fn format_syntax_error(code: &'static str, line: &str, file: String, line_number: usize, column_number: usize, message: &str) -> Error {
     let trimmed = line.trim_start();
-    let expected_list: Vec<String> = expected.iter().cloned().map(|x| format!("{:?}", x)).collect();
 
-    error!("In line {}{}:\n\n  {}\n  {}{}\n\nUnexpected token \"{}\", expected: {}",
+    let message = format!("In line {}{}:\n\n  {}\n  {}{}\n\n{}",
         file,
         line_number,
         trimmed,
         " ".to_string().repeat(column_number - 1 - (line.len() - trimmed.len())),
         "^".red().bold(),
-        line.chars().map(|x| x.to_string()).nth(column_number - 1).unwrap_or_else(|| "\\n".to_string()),
-        expected_list.join(", "))
+        message);
+    CodedError {
+        code,
+        message,
+        file: file.strip_suffix(':').map(str::to_string),
+        line: Some(line_number),
+        column: Some(column_number),
+    }.into()
 }
 
-pub fn warning<M: AsRef<[u8]> + Display>(msg: M, name: Option<&'static str>, location: (Option<M>,Option<u32>)) {
+/// A formatted warning that wasn't printed yet, see `buffer_warnings`
+#[derive(Debug, Clone)]
+pub(crate) struct PendingWarning {
+    name: Option<&'static str>,
+    /// File and line for `raise_warning_at`, if it has both
+    location: Option<(String, u32)>,
+    text: String,
+    diagnostic: CollectedDiagnostic,
+}
+
+/// Warnings raised on a thread are collected in its buffer instead of being printed
+pub(crate) type WarningBuffer = Arc<Mutex<Vec<PendingWarning>>>;
+
+thread_local! {
+    static WARNING_BUFFER: RefCell<Option<WarningBuffer>> = const { RefCell::new(None) };
+}
+
+/// Resets the buffer of the thread when `buffer_warnings` returns or unwinds
+struct BufferGuard(Option<WarningBuffer>);
+
+impl Drop for BufferGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        WARNING_BUFFER.with(|b| *b.borrow_mut() = previous);
+    }
+}
+
+/// Returns the buffer warnings of this thread are collected in, `None` if they are printed.
+pub(crate) fn current_warning_buffer() -> Option<WarningBuffer> {
+    WARNING_BUFFER.with(|b| b.borrow().clone())
+}
+
+/// Runs `f` with the warnings it raises on this thread collected in `buffer`.
+pub(crate) fn buffer_warnings<R, F: FnOnce() -> R>(buffer: WarningBuffer, f: F) -> R {
+    let _guard = BufferGuard(WARNING_BUFFER.with(|b| b.borrow_mut().replace(buffer)));
+    f()
+}
+
+/// Passes warnings on to a buffer, or prints them without one. Whether a warning is shown is
+/// only decided when printing, so the maximum per type counts them in the order they are
+/// printed in.
+pub(crate) fn emit_warnings(buffer: Option<&WarningBuffer>, warnings: Vec<PendingWarning>) {
+    match buffer {
+        Some(buffer) => buffer.lock().unwrap().extend(warnings),
+        None => warnings.into_iter().for_each(print_warning)
+    }
+}
+
+/// Runs `f` and returns the warnings it raised on this thread besides its result, for
+/// `replay_warning`. The warnings are passed on as if they were raised directly.
+pub(crate) fn record_warnings<R, F: FnOnce() -> R>(f: F) -> (R, Vec<CollectedDiagnostic>) {
+    let buffer: WarningBuffer = Arc::new(Mutex::new(Vec::new()));
+    let result = buffer_warnings(buffer.clone(), f);
+    let warnings = std::mem::take(&mut *buffer.lock().unwrap());
+    let recorded = warnings.iter().map(|w| w.diagnostic.clone()).collect();
+    emit_warnings(current_warning_buffer().as_ref(), warnings);
+    (result, recorded)
+}
+
+/// Raises a warning returned by `record_warnings` again.
+pub(crate) fn replay_warning(diagnostic: &CollectedDiagnostic) {
+    let name = diagnostic.name.as_deref().and_then(diagnostics::warning_name);
+    warning(diagnostic.message.as_str(), name, (diagnostic.file.as_deref(), diagnostic.line));
+}
+
+fn print_warning(warning: PendingWarning) {
     // Check if warning should be shown
-    if let Some(name) = name {
-        if !raise_warning(name) {
-            return; // Warning is muted or exceeded maximum
+    if let Some(name) = warning.name {
+        let shown = match warning.location {
+            Some((file, line)) => raise_warning_at(name, file, line, warning.diagnostic.message.as_str()),
+            None => raise_warning(name)
+        };
+        if !shown {
+            return; // Warning is muted, exceeded maximum or was already shown at this location
         }
     }
 
-    let loc_str = if location.0.is_some() && location.1.is_some() {
-        format!("In file {}:{}: ", location.0.unwrap(), location.1.unwrap())
-    } else if location.0.is_some() {
-        format!("In file {}: ", location.0.unwrap())
-    } else if location.1.is_some() {
-        format!("In line {}: ", location.1.unwrap())
-    } else {
-        "".to_string()
+    if collect_diagnostic(warning.diagnostic).is_some() {
+        eprintln!("{}", warning.text);
+    }
+}
+
+/// Prints a warning, or collects it if the thread buffers its warnings, like the work items of
+/// parallel batches do.
+pub fn warning<M: AsRef<[u8]> + Display>(msg: M, name: Option<&'static str>, location: (Option<M>,Option<u32>)) {
+    let pending_location = match &location {
+        (Some(file), Some(line)) => Some((file.to_string(), *line)),
+        _ => None
     };
 
-    let name_str = match name {
-        Some(name) => format!(" [{}]", name),
-        None => "".to_string()
+    let loc_str = match &location {
+        (Some(file), Some(line)) => format!("In file {}:{}: ", file, line),
+        (Some(file), None) => format!("In file {}: ", file),
+        (None, Some(line)) => format!("In line {}: ", line),
+        (None, None) => "".to_string()
     };
 
-    eprintln!("{}{}: {}{}", loc_str, "warning".yellow().bold(), msg, name_str);
+    let code = name.and_then(diagnostics::warning_code);
+    let diagnostic = CollectedDiagnostic {
+        level: Severity::Warning,
+        code: code.map(str::to_string),
+        name: name.map(str::to_string),
+        file: location.0.as_ref().map(|f| f.to_string()),
+        line: location.1,
+        column: None,
+        message: msg.to_string(),
+    };
+    let text = if JSON_DIAGNOSTICS.load(Ordering::Relaxed) {
+        json_diagnostic(&diagnostic)
+    } else {
+        let name_str = match (name, code) {
+            (Some(name), Some(code)) => format!(" [{}] [{}]", name, code),
+            (Some(name), None) => format!(" [{}]", name),
+            _ => "".to_string()
+        };
+        format!("{}{}: {}{}", loc_str, "warning".yellow().bold(), msg, name_str)
+    };
+
+    let pending = PendingWarning {
+        name,
+        location: pending_location,
+        text,
+        diagnostic,
+    };
+    emit_warnings(current_warning_buffer().as_ref(), vec![pending]);
 }
 
 pub fn warning_suppressed(name: Option<&'static str>) -> bool {
@@ -140,13 +451,23 @@ pub fn print_warning_summary() {
 
     for (name, _raised, excess) in summary {
         if excess > 0 {
+            let cap = match get_warning_maximum_for(&name) {
+                Some(maximum) => format!("the maximum of {} set for this type with \"-w {}={}\"", maximum, name, maximum),
+                None => format!("the maximum of {} for each type", get_warnings_maximum()),
+            };
             if excess > 1 {
-                warning(format!("{} warnings of type \"{}\" were suppressed to prevent spam. Use \"-w {}\" to disable these warnings entirely.",
-                    excess, name, name), None, (None::<String>, None));
+                warning(format!("{} warnings of type \"{}\" were suppressed after reaching {}. Use \"-w {}\" to disable these warnings entirely.",
+                    excess, name, cap, name), None, (None::<String>, None));
             } else {
-                warning(format!("{} warning of type \"{}\" was suppressed to prevent spam. Use \"-w {}\" to disable these warnings entirely.",
-                    excess, name, name), None, (None::<String>, None));
+                warning(format!("{} warning of type \"{}\" was suppressed after reaching {}. Use \"-w {}\" to disable these warnings entirely.",
+                    excess, name, cap, name), None, (None::<String>, None));
             }
         }
     }
+
+    for (name, duplicates) in get_duplicate_summary() {
+        let occurrences = if duplicates > 1 { "occurrences" } else { "occurrence" };
+        warning(format!("{} duplicate {} of warnings of type \"{}\" at the same location were suppressed.", duplicates, occurrences, name),
+            None, (None::<String>, None));
+    }
 }
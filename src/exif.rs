@@ -0,0 +1,99 @@
+//! EXIF orientation of photos
+//!
+//! Cameras store photos the way the sensor was held and record how to turn them upright in the
+//! orientation tag of their EXIF data. Viewers apply it, so a photo that looks upright everywhere
+//! else comes out rotated if its pixels are used as stored.
+
+use image::{DynamicImage};
+
+/// EXIF tag of the orientation
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// TIFF type of 16-bit unsigned values
+const SHORT_TYPE: u16 = 3;
+
+/// Returns the EXIF orientation of a JPEG or TIFF file, a value from 1 to 8. Files without one,
+/// other formats and broken EXIF data return `None`.
+pub fn orientation(data: &[u8]) -> Option<u16> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        tiff_orientation(jpeg_exif(data)?)
+    } else {
+        tiff_orientation(data)
+    }
+}
+
+/// Returns the TIFF structure in the APP1 segment of a JPEG file.
+fn jpeg_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // Fill bytes before a marker
+            0xFF => { pos += 1; continue; },
+            // Markers without a length
+            0x01 | 0xD0..=0xD8 => { pos += 2; continue; },
+            // Start of the image data or end of the image, metadata comes before
+            0xD9 | 0xDA => return None,
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get((pos + 4)..(pos + 2 + length))?;
+        if marker == 0xE1 && let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+            return Some(tiff);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Returns the orientation in the first IFD of a TIFF structure.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?, *tiff.get(pos + 2)?, *tiff.get(pos + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    let count = u16_at(ifd)? as usize;
+    for i in 0..count {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == ORIENTATION_TAG {
+            if u16_at(entry + 2)? != SHORT_TYPE || u32_at(entry + 4)? != 1 {
+                return None;
+            }
+            return Some(u16_at(entry + 8)?).filter(|o| (1..=8).contains(o));
+        }
+    }
+    None
+}
+
+/// Turns an image with the given EXIF orientation upright. Unknown orientations leave it as it
+/// is.
+pub fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img
+    }
+}
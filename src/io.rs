@@ -1,6 +1,15 @@
-use std::fs::{File};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions, hard_link, remove_file, rename};
 use std::io;
-use std::io::{Read, Seek, Write, Stdout, Cursor};
+use std::io::{BufWriter, Read, Seek, Write, Stdout, Cursor, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, LazyLock, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime};
+
+use crate::error::*;
+use crate::pbo::{list_files};
 
 pub enum Input {
     File(File),
@@ -46,13 +55,357 @@ impl Write for Output {
     }
 }
 
+/// What a command does when the file it writes to already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputPolicy {
+    /// Fail, the default without `-f`
+    #[default]
+    Fail,
+    /// Overwrite the target
+    Overwrite,
+    /// Leave targets that already exist as they are
+    SkipExisting,
+    /// Leave targets that were modified at the same time as their source or later as they are
+    IfNewer,
+}
+
+/// Returns the error for a target that exists without `--force`, which keeps the `AlreadyExists`
+/// kind callers check for.
+fn exists_error(target: &Path) -> io::Error {
+    io::Error::new(ErrorKind::AlreadyExists, CodedError::new("E0805",
+        format!("Output file '{}' already exists. Use -f/--force to overwrite.", target.display())))
+}
+
+impl OutputPolicy {
+    /// Returns the policy for the `--force`, `--skip-existing` and `--if-newer` flags.
+    pub fn from_flags(force: bool, skip_existing: bool, if_newer: bool) -> OutputPolicy {
+        match (force, skip_existing, if_newer) {
+            (_, true, _) => OutputPolicy::SkipExisting,
+            (_, _, true) => OutputPolicy::IfNewer,
+            (true, _, _) => OutputPolicy::Overwrite,
+            _ => OutputPolicy::Fail
+        }
+    }
+
+    /// Returns whether the target for source should be left as it is, checked before doing any
+    /// work for it. A source folder counts as modified when its newest file was.
+    pub fn skips(&self, source: &Path, target: &Path) -> io::Result<bool> {
+        match self {
+            OutputPolicy::SkipExisting => Ok(target.exists()),
+            OutputPolicy::IfNewer => match target.metadata() {
+                Ok(metadata) => Ok(modified(source)? <= metadata.modified()?),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e)
+            },
+            _ => Ok(false)
+        }
+    }
+
+    fn overwrites(&self, target: &Path) -> bool {
+        match self {
+            OutputPolicy::Overwrite => true,
+            OutputPolicy::IfNewer => target.exists(),
+            _ => false
+        }
+    }
+
+    /// Opens the target for writing, `None` if it should be left as it is. Unless the target is
+    /// meant to be overwritten, it is created with `create_new`, so a file that appeared since
+    /// `skips` was checked isn't overwritten either.
+    pub fn create(&self, target: &Path) -> io::Result<Option<File>> {
+        if self.overwrites(target) {
+            return create_file(target).map(Some);
+        }
+
+        match OpenOptions::new().write(true).create_new(true).open(extended_path(target)) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && *self == OutputPolicy::Fail => Err(exists_error(target)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(creation_error(target, e))
+        }
+    }
+
+    /// Returns the result of a write whose target exists and isn't meant to be overwritten.
+    fn existing(&self, target: &Path) -> io::Result<bool> {
+        match self {
+            OutputPolicy::Fail => Err(exists_error(target)),
+            _ => Ok(false)
+        }
+    }
+
+    /// Writes the target like `create` with what `write` writes, returning `false` if it was left
+    /// as it is. The content goes to a temporary file next to the target that replaces it once
+    /// complete, so a failed write leaves the old target or none at all, never part of a file.
+    /// Unless the target is meant to be overwritten, the temporary file is linked to it, which
+    /// fails if it appeared in the meantime instead of replacing it.
+    ///
+    /// Writes to the same target from other threads, with paths that are the same ignoring
+    /// case, wait until this one is done. Other processes aren't locked out.
+    pub fn write<F: FnOnce(&mut BufWriter<File>) -> io::Result<()>>(&self, target: &Path, write: F) -> io::Result<bool> {
+        let _lock = TargetLock::acquire(target);
+
+        let replace = self.overwrites(target);
+        if !replace && target.exists() {
+            return self.existing(target);
+        }
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut temporary = target.file_name().unwrap_or_default().to_os_string();
+        temporary.push(format!(".{}.{}.tmp", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let temporary = target.with_file_name(temporary);
+
+        let result = create_file(&temporary).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()
+        }).and_then(|_| if replace {
+            rename(extended_path(&temporary), extended_path(target)).map(|_| true)
+        } else {
+            persist_new(&temporary, target)
+        }.map_err(|e| creation_error(target, e)));
+
+        remove_file(extended_path(&temporary)).ok();
+        match result {
+            Ok(true) => Ok(true),
+            Ok(false) => self.existing(target),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+/// Moves a complete temporary file to a target that doesn't exist yet, returning `false` if it
+/// does. Without support for hard links, the target is created empty first and replaced, so it
+/// exists empty for a moment.
+fn persist_new(temporary: &Path, target: &Path) -> io::Result<bool> {
+    match hard_link(extended_path(temporary), extended_path(target)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(_) => match OpenOptions::new().write(true).create_new(true).open(extended_path(target)) {
+            Ok(_) => rename(extended_path(temporary), extended_path(target)).map(|_| true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+/// Returns the path targets are compared by, absolute and in lowercase so paths differing only
+/// in case are the same on every platform.
+fn target_key(path: &Path) -> String {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_lowercase()
+}
+
+/// Keys of the targets being written by `OutputPolicy::write`
+static LOCKED_TARGETS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+static TARGET_RELEASED: Condvar = Condvar::new();
+
+/// Lock of a target, released when dropped
+struct TargetLock(String);
+
+impl TargetLock {
+    fn acquire(target: &Path) -> TargetLock {
+        let key = target_key(target);
+        let mut locked = LOCKED_TARGETS.lock().unwrap();
+        while locked.contains(&key) {
+            locked = TARGET_RELEASED.wait(locked).unwrap();
+        }
+        locked.insert(key.clone());
+        TargetLock(key)
+    }
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        LOCKED_TARGETS.lock().unwrap().remove(&self.0);
+        TARGET_RELEASED.notify_all();
+    }
+}
+
+/// Fails if several (source, target) pairs of a batch have the same target, ignoring case, with
+/// all the sources of each such target, in the order of the batch.
+pub fn check_target_collisions(files: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let mut targets: Vec<(&Path, Vec<&Path>)> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+    for (source, target) in files {
+        match indices.get(&target_key(target)) {
+            Some(index) => targets[*index].1.push(source),
+            None => {
+                indices.insert(target_key(target), targets.len());
+                targets.push((target, vec![source]));
+            }
+        }
+    }
+
+    let collisions: Vec<String> = targets.iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(target, sources)| {
+            let sources: Vec<String> = sources.iter().map(|s| s.display().to_string()).collect();
+            format!("    {} -> {}", sources.join(", "), target.display())
+        })
+        .collect();
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    Err(coded_error!("E0825", "Several files would be written to the same target, file names are compared ignoring case:\n{}", collisions.join("\n")))
+}
+
+/// Longest path Windows accepts without the `\\?\` extended-length prefix, including the
+/// terminating null
+const MAX_PATH: usize = 260;
+
+/// Longest path Windows accepts with the extended-length prefix, in UTF-16 units
+const MAX_EXTENDED_PATH: usize = 32767;
+
+/// Longest file or folder name most file systems allow, in the unit of `path_length`
+const MAX_COMPONENT_LENGTH: usize = 255;
+
+/// Unit of `path_length` for messages
+const LENGTH_UNIT: &str = if cfg!(windows) { "UTF-16 units" } else { "bytes" };
+
+/// Returns the length of a path or name in the unit its limits are in, UTF-16 units on Windows
+/// and bytes elsewhere.
+#[cfg(windows)]
+fn path_length(path: &std::ffi::OsStr) -> usize {
+    std::os::windows::ffi::OsStrExt::encode_wide(path).count()
+}
+
+#[cfg(not(windows))]
+fn path_length(path: &std::ffi::OsStr) -> usize {
+    path.len()
+}
+
+/// Returns the path to open files at. On Windows, paths too long for the Win32 limit are made
+/// absolute and given the `\\?\` prefix, other paths are returned as they are.
+pub fn extended_path(path: &Path) -> PathBuf {
+    // Folders need room for a file name of 8.3 characters
+    if !cfg!(windows) || path_length(path.as_os_str()) < MAX_PATH - 12 {
+        return path.to_path_buf();
+    }
+
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return path.to_path_buf()
+    };
+    let absolute = absolute.to_string_lossy();
+    if absolute.starts_with(r"\\?\") {
+        PathBuf::from(absolute.into_owned())
+    } else if let Some(unc) = absolute.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", unc))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", absolute))
+    }
+}
+
+/// Fails if the path can't be created on this system because it or one of its components is too
+/// long, before anything is written.
+pub fn check_path_length(path: &Path) -> io::Result<()> {
+    if let Some(component) = path.components().map(|c| c.as_os_str()).find(|c| path_length(c) > MAX_COMPONENT_LENGTH) {
+        return Err(coded_error!("E0826", "Path \"{}\" can't be created: \"{}\" is longer than {} {}.", path.display(), component.to_string_lossy(), MAX_COMPONENT_LENGTH, LENGTH_UNIT));
+    }
+    if cfg!(windows) && path_length(extended_path(path).as_os_str()) > MAX_EXTENDED_PATH {
+        return Err(coded_error!("E0826", "Path \"{}\" can't be created: it is longer than {} {}.", path.display(), MAX_EXTENDED_PATH, LENGTH_UNIT));
+    }
+    Ok(())
+}
+
+/// Adds the path and the likely cause to an error creating it, keeping the error kind.
+fn creation_error(path: &Path, error: io::Error) -> io::Error {
+    let hint = if error.kind() == ErrorKind::PermissionDenied {
+        Some("the folder is read-only or not writable by the current user")
+    } else if path.parent().is_some_and(|p| !p.as_os_str().is_empty() && !p.exists()) {
+        Some("its parent folder doesn't exist")
+    } else if check_path_length(path).is_err() || (cfg!(windows) && path_length(path.as_os_str()) >= MAX_PATH) {
+        Some("the path is too long")
+    } else {
+        None
+    };
+
+    match hint {
+        Some(hint) => io::Error::new(error.kind(), format!("Failed to create \"{}\": {} ({}).", path.display(), error, hint)),
+        None => io::Error::new(error.kind(), format!("Failed to create \"{}\": {}.", path.display(), error))
+    }
+}
+
+/// Creates or truncates a file like `File::create`, using `extended_path` for long paths. Errors
+/// name the path and the likely cause.
+pub fn create_file(path: &Path) -> io::Result<File> {
+    File::create(extended_path(path)).map_err(|e| creation_error(path, e))
+}
+
+/// Creates a folder and its missing parents like `create_dir_all`, see `create_file`.
+pub fn create_folder(path: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(extended_path(path)).map_err(|e| creation_error(path, e))
+}
+
+/// Returns when a file, or the newest file in a folder, was last modified.
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    if !path.is_dir() {
+        return path.metadata()?.modified();
+    }
+
+    let mut newest = path.metadata()?.modified()?;
+    for file in list_files(&path.to_path_buf())? {
+        newest = newest.max(file.metadata()?.modified()?);
+    }
+    Ok(newest)
+}
+
+/// Number of files a batch command converted, left as they were and failed to convert
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BatchSummary {
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl BatchSummary {
+    /// Counts the results of converting each of the files, printing the errors.
+    pub(crate) fn from_results(files: &[PathBuf], results: Vec<io::Result<bool>>) -> BatchSummary {
+        let mut summary = BatchSummary::default();
+        for (file, result) in files.iter().zip(results) {
+            match result {
+                Ok(true) => summary.converted += 1,
+                Ok(false) => summary.skipped += 1,
+                Err(e) => {
+                    Err::<(), io::Error>(e).prepend_error(format!("Failed to convert {}:", file.display())).print_error(false);
+                    summary.failed += 1;
+                }
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} converted, {} skipped, {} failed", self.converted, self.skipped, self.failed)
+    }
+}
+
+/// Returns the files of a folder and its subfolders with one of the extensions, ignoring case,
+/// sorted and relative to the folder.
+pub(crate) fn files_with_extensions(directory: &Path, extensions: &[&str]) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = list_files(&directory.to_path_buf())?.into_iter()
+        .filter(|f| f.extension().and_then(|e| e.to_str()).is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x))))
+        .filter_map(|f| f.strip_prefix(directory).ok().map(Path::to_path_buf))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
 pub trait ReadExt: Read {
     fn read_cstring(&mut self) -> io::Result<String>;
+    fn read_cstring_bytes(&mut self) -> io::Result<Vec<u8>>;
+    fn read_cstring_lossy(&mut self) -> io::Result<String>;
     fn read_compressed_int(&mut self) -> io::Result<u32>;
 }
 
 impl<T: Read> ReadExt for T {
     fn read_cstring(&mut self) -> io::Result<String> {
+        let bytes = self.read_cstring_bytes()?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_cstring_bytes(&mut self) -> io::Result<Vec<u8>> {
         let mut bytes: Vec<u8> = Vec::new();
         for byte in self.bytes() {
             let b = byte?;
@@ -63,7 +416,22 @@ impl<T: Read> ReadExt for T {
             }
         }
 
-        Ok(String::from_utf8(bytes).unwrap())
+        Ok(bytes)
+    }
+
+    fn read_cstring_lossy(&mut self) -> io::Result<String> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut byte = [0; 1];
+        loop {
+            self.read_exact(&mut byte)?;
+            if byte[0] == 0 {
+                break;
+            } else {
+                bytes.push(byte[0]);
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
     }
 
     fn read_compressed_int(&mut self) -> io::Result<u32> {
@@ -110,6 +478,13 @@ impl<T: Write> WriteExt for T {
     }
 }
 
+/// Converts a size or offset to the 32-bit field it is written to, failing instead of writing a
+/// truncated value. Which sizes are truncated by `as u32` depends on the width of `usize`, so
+/// writers use this to produce the same output or error on every platform.
+pub fn field_u32<T: TryInto<u32> + Copy + fmt::Display>(value: T, what: &str) -> io::Result<u32> {
+    value.try_into().map_err(|_| coded_error!("E0827", "{} {} doesn't fit into a 32-bit field.", what, value))
+}
+
 pub fn compressed_int_len(x: u32) -> usize {
     let mut temp = x;
     let mut len = 0;
@@ -0,0 +1,194 @@
+//! Restartable batch conversions
+//!
+//! A job manifest is a JSON file listing every conversion of one batch: its source, its target,
+//! the hash of the source content and of the options, and whether it succeeded, with the error
+//! if it didn't. It is written before converting anything and updated while the jobs finish, so
+//! a batch that was interrupted or had failures can be run again with the same manifest to
+//! convert only what isn't done yet.
+//!
+//! Unlike the build cache it stores no artifacts and is meant to be read by people: the failed
+//! jobs and their errors are the list to work through before running the batch again.
+
+use std::collections::{HashMap};
+use std::fs::{read, read_to_string, rename};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex};
+use std::time::{Duration, Instant};
+
+use openssl::hash::{MessageDigest, hash};
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+use crate::io::{create_file};
+use crate::parallel;
+
+/// Version of the manifest format, manifests of other versions are started over
+const FORMAT_VERSION: u32 = 1;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Shortest time between two writes of the manifest while jobs finish
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn sha1_hex(data: &[u8]) -> String {
+    let digest = hash(MessageDigest::sha1(), data).unwrap();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the hash of the options that change the output of a job, given as (name, value)
+/// pairs, and the armake2 version.
+pub fn options_hash(options: &[(&str, String)]) -> String {
+    let mut content = VERSION.to_string();
+    for (name, value) in options {
+        content.push_str(&format!("\n{}={}", name, value));
+    }
+    sha1_hex(content.as_bytes())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Planned, or interrupted before it finished
+    Pending,
+    Done,
+    Failed,
+}
+
+/// One conversion of a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub source_hash: String,
+    pub options_hash: String,
+    pub status: JobStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile {
+    version: u32,
+    jobs: Vec<Job>,
+}
+
+/// Jobs of a batch, stored in a file while they run
+pub struct JobManifest {
+    path: PathBuf,
+    jobs: Mutex<Vec<Job>>,
+    last_save: Mutex<Instant>,
+}
+
+impl JobManifest {
+    /// Reads the jobs of a manifest file, none if it doesn't exist or is of another format
+    /// version.
+    pub fn read(path: &Path) -> Result<Vec<Job>, Error> {
+        let content = match read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).prepend_error("Failed to read job manifest:")
+        };
+        let file: ManifestFile = serde_json::from_str(&content).map_err(|e| coded_error!("E0814", "Invalid job manifest: {}", e))?;
+        Ok(if file.version == FORMAT_VERSION { file.jobs } else { Vec::new() })
+    }
+
+    /// Plans the conversion of each (source, target) pair and writes the manifest. Jobs that
+    /// are done in the existing manifest are kept as done while their source content, options
+    /// and target are unchanged. All others are pending, including earlier failures.
+    pub fn plan(path: PathBuf, files: &[(PathBuf, PathBuf)], options_hash: &str) -> Result<JobManifest, Error> {
+        let previous: HashMap<PathBuf, Job> = JobManifest::read(&path)?.into_iter().map(|job| (job.source.clone(), job)).collect();
+
+        let hashes: Vec<Result<String, Error>> = parallel::map_collect(files.to_vec(), |(source, _)| {
+            read(&source).map(|content| sha1_hex(&content)).prepend_error(format!("Failed to read {}:", source.display()))
+        });
+
+        let mut jobs: Vec<Job> = Vec::with_capacity(files.len());
+        for ((source, target), source_hash) in files.iter().zip(hashes) {
+            let (source_hash, error) = match source_hash {
+                Ok(source_hash) => (source_hash, None),
+                Err(e) => (String::new(), Some(e.to_string()))
+            };
+            let done = error.is_none() && previous.get(source).is_some_and(|job| {
+                job.status == JobStatus::Done && job.target == *target && job.source_hash == source_hash &&
+                    job.options_hash == options_hash && target.exists()
+            });
+            jobs.push(Job {
+                source: source.clone(),
+                target: target.clone(),
+                source_hash,
+                options_hash: options_hash.to_string(),
+                status: match (done, &error) {
+                    (_, Some(_)) => JobStatus::Failed,
+                    (true, None) => JobStatus::Done,
+                    (false, None) => JobStatus::Pending
+                },
+                error,
+            });
+        }
+
+        let manifest = JobManifest {
+            path,
+            jobs: Mutex::new(jobs),
+            last_save: Mutex::new(Instant::now()),
+        };
+        manifest.save()?;
+        Ok(manifest)
+    }
+
+    /// Returns the indices of the jobs that still have to run.
+    pub fn pending(&self) -> Vec<usize> {
+        self.jobs.lock().unwrap().iter().enumerate()
+            .filter(|(_, job)| job.status != JobStatus::Done)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns a copy of the job.
+    pub fn job(&self, index: usize) -> Job {
+        self.jobs.lock().unwrap()[index].clone()
+    }
+
+    /// Records the result of a job, writing the manifest if it hasn't been for a second. A write
+    /// that fails doesn't fail the job, the next one and `save` at the end of the batch try again.
+    pub fn finish(&self, index: usize, result: &Result<bool, Error>) {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let job = &mut jobs[index];
+            match result {
+                Ok(_) => {
+                    job.status = JobStatus::Done;
+                    job.error = None;
+                },
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        }
+
+        let mut last_save = self.last_save.lock().unwrap();
+        if last_save.elapsed() >= SAVE_INTERVAL && self.save().is_ok() {
+            *last_save = Instant::now();
+        }
+    }
+
+    /// Writes the manifest to its file. It is written under a temporary name first and then
+    /// renamed, so an interrupted batch never leaves a partial manifest.
+    pub fn save(&self) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(&ManifestFile {
+            version: FORMAT_VERSION,
+            jobs: self.jobs.lock().unwrap().clone(),
+        }).map_err(Error::from)?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = create_file(&temporary)?;
+        file.write_all(content.as_bytes()).prepend_error("Failed to write job manifest:")?;
+        file.sync_all()?;
+        drop(file);
+        rename(&temporary, &self.path).prepend_error("Failed to replace job manifest:")
+    }
+}
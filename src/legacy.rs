@@ -0,0 +1,139 @@
+//! Command line compatibility with the original armake
+//!
+//! Build scripts written for the C armake call it with its own command and flag spellings. These
+//! are rewritten into their armake2 equivalents before the arguments are parsed. Every rewrite is
+//! described, so a single `legacy-cli` warning can point to the new spelling.
+
+use std::io::{Error};
+use std::path::{Path};
+
+/// Options of the original armake that take a value
+const VALUE_OPTIONS: &[&str] = &[
+    "-w", "--warning", "-i", "--include", "-x", "--exclude", "-e", "--headerext", "-k", "--key",
+    "-s", "--signature", "-d", "--indent", "-t", "--type",
+];
+
+/// Extensions of the files the original `binarize` rapified instead of passing to binarize.exe
+const CONFIG_EXTENSIONS: &[&str] = &["cpp", "ext", "rvmat", "sqm", "bikb", "bisurf"];
+
+/// An option with its value or a positional argument
+struct Group {
+    name: Option<String>,
+    args: Vec<String>,
+}
+
+impl Group {
+    fn is(&self, names: &[&str]) -> bool {
+        self.name.as_deref().is_some_and(|n| names.contains(&n))
+    }
+
+    fn value(&self) -> Option<String> {
+        match (&self.name, self.args.as_slice()) {
+            (Some(_), [_, value]) => Some(value.clone()),
+            (Some(_), [arg]) => arg.split_once('=').map(|(_, v)| v.to_string()),
+            _ => None
+        }
+    }
+}
+
+/// Splits the arguments into options with their values and positional arguments.
+fn group(args: &[String]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut iter = args.iter();
+    let mut only_positional = false;
+
+    while let Some(arg) = iter.next() {
+        if only_positional || !arg.starts_with('-') || arg == "-" {
+            groups.push(Group { name: None, args: vec![arg.clone()] });
+            continue;
+        }
+        if arg == "--" {
+            only_positional = true;
+            groups.push(Group { name: None, args: vec![arg.clone()] });
+            continue;
+        }
+
+        if !arg.starts_with("--") {
+            // Stacked short options like -fp, a value option takes the rest or the next argument
+            for (i, c) in arg.char_indices().skip(1) {
+                let name = format!("-{}", c);
+                if VALUE_OPTIONS.contains(&name.as_str()) {
+                    let rest = &arg[(i + c.len_utf8())..];
+                    let value = if rest.is_empty() { iter.next().cloned() } else { Some(rest.to_string()) };
+                    groups.push(Group { name: Some(name.clone()), args: std::iter::once(name).chain(value).collect() });
+                    break;
+                }
+                groups.push(Group { name: Some(name.clone()), args: vec![name] });
+            }
+            continue;
+        }
+
+        let mut group = match arg.split_once('=') {
+            Some((name, _)) => Group { name: Some(name.to_string()), args: vec![arg.clone()] },
+            None => Group { name: Some(arg.clone()), args: vec![arg.clone()] }
+        };
+        if !arg.contains('=') && VALUE_OPTIONS.contains(&arg.as_str()) && let Some(value) = iter.next() {
+            group.args.push(value.clone());
+        }
+        groups.push(group);
+    }
+
+    groups
+}
+
+/// Rewrites the spellings of the original armake in the command line `argv`, including the
+/// program name, into the ones of armake2. Returns the new command line and a description of
+/// every rewrite, empty if nothing had to be changed.
+///
+/// Original spellings that armake2 has no equivalent for are an error rather than being
+/// ignored, since ignoring them would silently produce different files.
+pub fn translate_args(argv: Vec<String>) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut notes: Vec<String> = Vec::new();
+    if argv.len() < 2 {
+        return Ok((argv, notes));
+    }
+
+    // -v printed the version, `armake2 -v` alone isn't valid otherwise
+    if argv.len() == 2 && argv[1] == "-v" {
+        notes.push("\"armake -v\" is \"armake2 --version\"".to_string());
+        return Ok((vec![argv[0].clone(), "--version".to_string()], notes));
+    }
+
+    let mut command = argv[1].clone();
+    let mut groups = group(&argv[2..]);
+
+    match command.as_str() {
+        "build" if groups.iter().any(|g| g.is(&["-p", "--packonly"])) => {
+            // Packing doesn't preprocess anything, so include folders don't apply
+            groups.retain(|g| !g.is(&["-p", "--packonly", "-i", "--include"]));
+            command = "pack".to_string();
+            notes.push("\"armake build -p\" is \"armake2 pack\"".to_string());
+        },
+        "sign" => {
+            if let Some(index) = groups.iter().position(|g| g.is(&["-s", "--signature"])) {
+                let flag = groups.remove(index);
+                let signature = flag.value().ok_or_else(|| coded_error!("E0903", "Missing signature path after {}.", flag.args[0]))?;
+                groups.push(Group { name: None, args: vec![signature] });
+                notes.push("\"armake sign -s <signature> <privatekey> <pbo>\" is \"armake2 sign <privatekey> <pbo> <signature>\"".to_string());
+            }
+        },
+        "binarize" => {
+            let source = groups.iter().find(|g| g.name.is_none()).map(|g| g.args[0].clone());
+            let extension = source.as_ref().and_then(|s| Path::new(s).extension()).map(|e| e.to_string_lossy().to_lowercase());
+            if extension.is_some_and(|e| CONFIG_EXTENSIONS.contains(&e.as_str())) {
+                command = "rapify".to_string();
+                notes.push("\"armake binarize\" of configs is \"armake2 rapify\"".to_string());
+            }
+        },
+        "unpack" => {
+            if let Some(flag) = groups.iter().find(|g| g.is(&["-i", "--include", "-x", "--exclude"])) {
+                return Err(coded_error!("E0902", "The include and exclude patterns of the original armake's unpack ({}) aren't supported. Unpack everything and remove what isn't needed.", flag.args[0]));
+            }
+        },
+        _ => {}
+    }
+
+    let mut translated = vec![argv[0].clone(), command];
+    translated.extend(groups.into_iter().flat_map(|g| g.args));
+    Ok((translated, notes))
+}
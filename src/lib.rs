@@ -1,11 +1,41 @@
+pub mod audio;
 pub mod binarize;
+pub mod buildcache;
+pub mod buildreport;
 pub mod config;
+pub mod configdiff;
+pub mod detect;
+pub mod diagnostics;
 pub mod error;
+pub mod exif;
 pub mod io;
+pub mod jobmanifest;
+pub mod legacy;
+pub mod lzo;
+pub mod manifest;
+pub mod mission;
+pub mod modelcfg;
+pub mod odol;
 pub mod p3d;
 pub mod paa;
+pub mod paaanalysis;
+pub mod parallel;
+pub mod paths;
+pub mod patch;
 pub mod pbo;
+pub mod pbogrep;
 pub mod preprocess;
+pub mod progress;
+pub mod project;
+pub mod resources;
 pub mod run;
+pub mod schema;
+#[cfg(feature = "serve")]
+pub mod serve;
 pub mod sign;
+pub mod texheaders;
+pub mod texpolicy;
+pub mod texreport;
+pub mod verifycache;
+pub mod vfs;
 pub mod warnings;
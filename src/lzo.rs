@@ -0,0 +1,215 @@
+//! Streaming LZO1X decompression
+//!
+//! minilzo only decompresses whole buffers, so decoding a large compressed mipmap needs both the
+//! compressed and the decompressed data in memory at once. `LzoReader` produces the decompressed
+//! data as it is read instead, keeping only the last 48 kB that later matches can refer to.
+
+use std::io::{Error, Read};
+
+/// Furthest back an LZO1X match can refer to
+const MAX_DISTANCE: usize = 0xBFFF;
+
+/// Bytes decompressed ahead at once when the reader runs out
+const CHUNK_SIZE: usize = 0x4000;
+
+/// Reader of the decompressed contents of LZO1X compressed data, as written by minilzo
+pub struct LzoReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    size: usize,
+    produced: usize,
+    /// Decompressed bytes, starting at most `MAX_DISTANCE` bytes before the ones not read yet
+    window: Vec<u8>,
+    read: usize,
+    started: bool,
+    finished: bool,
+    /// Number of literals copied by the last instruction, which changes the meaning of the next
+    state: usize,
+    literals: usize,
+    /// Distance and remaining length of the match being copied
+    copy: Option<(usize, usize)>,
+}
+
+impl<'a> LzoReader<'a> {
+    /// Creates a reader of the data, which is expected to decompress to exactly `size` bytes.
+    pub fn new(input: &'a [u8], size: usize) -> LzoReader<'a> {
+        LzoReader {
+            input,
+            position: 0,
+            size,
+            produced: 0,
+            window: Vec::with_capacity(MAX_DISTANCE + 2 * CHUNK_SIZE),
+            read: 0,
+            started: false,
+            finished: false,
+            state: 0,
+            literals: 0,
+            copy: None,
+        }
+    }
+
+    /// Reads the rest of the data, failing if it doesn't decompress to exactly the expected size.
+    pub fn finish(mut self) -> Result<(), Error> {
+        std::io::copy(&mut self, &mut std::io::sink())?;
+        if self.produced < self.size {
+            return Err(coded_error!("E0409", "LZO decompression failed: data decompresses to {} bytes, expected {}", self.produced, self.size));
+        }
+        while !self.finished {
+            if self.copy.is_some() || self.literals > 0 {
+                return Err(coded_error!("E0409", "LZO decompression failed: data decompresses to more than {} bytes", self.size));
+            }
+            self.next_instruction()?;
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self) -> Result<usize, Error> {
+        let byte = *self.input.get(self.position).ok_or_else(|| coded_error!("E0409", "LZO decompression failed: input ends unexpectedly"))?;
+        self.position += 1;
+        Ok(byte as usize)
+    }
+
+    fn le16(&mut self) -> Result<usize, Error> {
+        Ok(self.byte()? | (self.byte()? << 8))
+    }
+
+    /// Reads a length encoded as zero bytes adding 255 each and a final nonzero byte.
+    fn long_length(&mut self, base: usize) -> Result<usize, Error> {
+        let mut length = base;
+        loop {
+            match self.byte()? {
+                0 => length += 255,
+                byte => return Ok(length + byte)
+            }
+        }
+    }
+
+    /// Decodes the next instruction into the literals and match to copy.
+    fn next_instruction(&mut self) -> Result<(), Error> {
+        if !self.started {
+            self.started = true;
+            match self.input.first().map(|b| *b as usize) {
+                Some(first) if first >= 22 => {
+                    self.position = 1;
+                    self.literals = first - 17;
+                    self.state = 4;
+                },
+                Some(first) if first >= 18 => {
+                    self.position = 1;
+                    self.literals = first - 17;
+                    self.state = first - 17;
+                },
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let instruction = self.byte()?;
+        let (distance, length, state) = if instruction >= 0x40 {
+            let distance = (self.byte()? << 3) + ((instruction >> 2) & 0x7) + 1;
+            (distance, (instruction >> 5) + 1, instruction & 0x3)
+        } else if instruction >= 0x20 {
+            let length = match instruction & 0x1F {
+                0 => self.long_length(31 + 2)?,
+                l => l + 2
+            };
+            let value = self.le16()?;
+            ((value >> 2) + 1, length, value & 0x3)
+        } else if instruction >= 0x10 {
+            let length = match instruction & 0x7 {
+                0 => self.long_length(7 + 2)?,
+                l => l + 2
+            };
+            let value = self.le16()?;
+            let distance = ((instruction & 0x8) << 11) + (value >> 2);
+            if distance == 0 {
+                self.finished = true;
+                return Ok(());
+            }
+            (distance + 0x4000, length, value & 0x3)
+        } else if self.state == 0 {
+            self.literals = match instruction {
+                0 => self.long_length(15 + 3)?,
+                l => l + 3
+            };
+            self.state = 4;
+            return Ok(());
+        } else if self.state < 4 {
+            ((instruction >> 2) + (self.byte()? << 2) + 1, 2, instruction & 0x3)
+        } else {
+            ((instruction >> 2) + (self.byte()? << 2) + 2049, 3, instruction & 0x3)
+        };
+
+        if distance > self.window.len() {
+            return Err(coded_error!("E0409", "LZO decompression failed: match refers to data before the start"));
+        }
+        self.copy = Some((distance, length));
+        self.literals = state;
+        self.state = state;
+        Ok(())
+    }
+
+    /// Decompresses up to `limit` more bytes into the window.
+    fn fill(&mut self, limit: usize) -> Result<(), Error> {
+        let target = self.produced + limit.min(self.size - self.produced);
+        while self.produced < target {
+            let space = target - self.produced;
+            if let Some((distance, length)) = self.copy {
+                let count = length.min(space);
+                for _ in 0..count {
+                    let byte = self.window[self.window.len() - distance];
+                    self.window.push(byte);
+                }
+                self.produced += count;
+                self.copy = if count < length { Some((distance, length - count)) } else { None };
+            } else if self.literals > 0 {
+                let count = self.literals.min(space);
+                let literals = self.input.get(self.position..self.position + count)
+                    .ok_or_else(|| coded_error!("E0409", "LZO decompression failed: input ends unexpectedly"))?;
+                self.window.extend_from_slice(literals);
+                self.position += count;
+                self.produced += count;
+                self.literals -= count;
+            } else if self.finished {
+                break;
+            } else {
+                self.next_instruction()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for LzoReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.read == self.window.len() {
+            if self.read > MAX_DISTANCE + CHUNK_SIZE {
+                self.window.drain(..self.read - MAX_DISTANCE);
+                self.read = MAX_DISTANCE;
+            }
+            self.fill(buf.len().max(CHUNK_SIZE))?;
+        }
+
+        let count = buf.len().min(self.window.len() - self.read);
+        buf[..count].copy_from_slice(&self.window[self.read..self.read + count]);
+        self.read += count;
+        Ok(count)
+    }
+}
+
+/// Decompresses LZO1X data that is expected to decompress to exactly `size` bytes.
+pub fn decompress(input: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let mut output = vec![0u8; size];
+    let mut reader = LzoReader::new(input, size);
+    reader.read_exact(&mut output).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => coded_error!("E0409", "LZO decompression failed: data decompresses to less than {} bytes", size),
+        _ => e
+    })?;
+    reader.finish()?;
+    Ok(output)
+}
+
+/// Checks that LZO1X data decompresses to exactly `size` bytes without keeping the result.
+pub fn verify(input: &[u8], size: usize) -> Result<(), Error> {
+    LzoReader::new(input, size).finish()
+}
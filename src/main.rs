@@ -1,11 +1,21 @@
 use armake2::*;
 use docopt::Docopt;
 
+use crate::error::*;
 use crate::run::{USAGE, Args};
 
 fn main() {
+    let (argv, legacy) = match legacy::translate_args(std::env::args().collect()) {
+        Ok(translated) => translated,
+        Err(error) => {
+            Err::<(), _>(error).print_error(true);
+            unreachable!()
+        }
+    };
+
     let mut args: Args = Docopt::new(USAGE)
-                            .and_then(|d| d.deserialize())
+                            .and_then(|d| d.argv(argv).deserialize())
                             .unwrap_or_else(|e| e.exit());
+    args.legacy = legacy;
     armake2::run::args(&mut args);
 }
@@ -0,0 +1,335 @@
+//! Signed manifests of all files in a mod folder
+//!
+//! BI signatures only cover PBOs. A manifest lists every other file of a mod as well, like keys,
+//! userconfigs and DLLs, with its size and SHA-256 hash, so a launcher can detect any change to
+//! the folder. Manifests are signed either with the same BI keys used for PBOs or with an HMAC
+//! over a shared secret.
+//!
+//! The manifest is a text file:
+//!
+//! ```text
+//! armake2-manifest 1
+//! exclude *.log
+//! file 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae 3 addons/mod.pbo
+//! signature rsa myauthority 3a8f...
+//! ```
+//!
+//! Files are sorted by their relative path with `/` separators. The signature covers everything
+//! before the signature line, including the exclusion patterns, so verification always uses the
+//! patterns the manifest was created with.
+
+use std::collections::{BTreeMap};
+use std::fs::{File, read, read_dir};
+use std::io::{Read, Write, Error};
+use std::path::{Path, PathBuf};
+
+use openssl::hash::{MessageDigest, hash};
+use openssl::memcmp;
+use openssl::pkey::{PKey};
+use openssl::sign::{Signer, Verifier};
+
+use crate::error::*;
+use crate::pbo::{file_allowed};
+use crate::sign::{BIPrivateKey, BIPublicKey};
+
+/// Name of the manifest inside the mod folder if no other path is given. It is never listed.
+pub const MANIFEST_FILE: &str = "armake2.manifest";
+
+const MANIFEST_HEADER: &str = "armake2-manifest 1";
+
+/// Key a manifest is signed or verified with. RSA keys are BI keys, so one keypair covers PBO
+/// signatures and manifests.
+pub enum ManifestKey {
+    /// BI private key, for creating and verifying manifests
+    Private(BIPrivateKey),
+    /// BI public key, only for verifying manifests
+    Public(BIPublicKey),
+    /// Secret shared between the creator and the verifier
+    Secret(Vec<u8>),
+}
+
+impl ManifestKey {
+    /// Reads a key from a file. `.biprivatekey` and `.bikey` files are read as BI keys, any other
+    /// file is a shared secret. Trailing whitespace of the secret is ignored.
+    pub fn from_file(path: &Path) -> Result<ManifestKey, Error> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let mut file = File::open(path).prepend_error("Failed to open key:")?;
+
+        match extension.as_str() {
+            "biprivatekey" => Ok(ManifestKey::Private(BIPrivateKey::read(&mut file).prepend_error("Failed to read private key:")?)),
+            "bikey" => Ok(ManifestKey::Public(BIPublicKey::read(&mut file).prepend_error("Failed to read public key:")?)),
+            _ => {
+                let mut secret: Vec<u8> = Vec::new();
+                file.read_to_end(&mut secret).prepend_error("Failed to read secret:")?;
+                while secret.last().map(|b| b.is_ascii_whitespace()).unwrap_or(false) {
+                    secret.pop();
+                }
+                if secret.is_empty() {
+                    return Err(coded_error!("E0801", "The secret in {} is empty.", path.display()));
+                }
+                Ok(ManifestKey::Secret(secret))
+            }
+        }
+    }
+
+    fn hmac(secret: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = PKey::hmac(secret)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(data)?;
+        Ok(signer.sign_to_vec()?)
+    }
+
+    /// Returns the signature line for the data.
+    fn sign(&self, data: &[u8]) -> Result<String, Error> {
+        match self {
+            ManifestKey::Private(key) => {
+                let rsa = PKey::from_rsa(key.to_rsa()?)?;
+                let mut signer = Signer::new(MessageDigest::sha256(), &rsa)?;
+                signer.update(data)?;
+                Ok(format!("signature rsa {} {}", key.name(), to_hex(&signer.sign_to_vec()?)))
+            },
+            ManifestKey::Public(_) => Err(coded_error!("E0804", "Manifests can't be signed with a public key.")),
+            ManifestKey::Secret(secret) => Ok(format!("signature hmac-sha256 {}", to_hex(&Self::hmac(secret, data)?)))
+        }
+    }
+
+    /// Checks the signature line for the data.
+    fn verify(&self, data: &[u8], signature: &str) -> Result<(), Error> {
+        let parts: Vec<&str> = signature.split(' ').collect();
+        match (self, parts.as_slice()) {
+            (ManifestKey::Private(_) | ManifestKey::Public(_), ["signature", "rsa", authority, signature]) => {
+                let derived;
+                let public = match self {
+                    ManifestKey::Private(key) => {
+                        derived = key.to_public_key();
+                        &derived
+                    },
+                    ManifestKey::Public(key) => key,
+                    ManifestKey::Secret(_) => unreachable!()
+                };
+                if !public.name().eq_ignore_ascii_case(authority) {
+                    return Err(coded_error!("E0808", "Manifest is signed by \"{}\", not \"{}\".", authority, public.name()));
+                }
+
+                let rsa = PKey::from_rsa(public.to_rsa()?)?;
+                let mut verifier = Verifier::new(MessageDigest::sha256(), &rsa)?;
+                verifier.update(data)?;
+                if !verifier.verify(&from_hex(signature)?).unwrap_or(false) {
+                    return Err(coded_error!("E0810", "Manifest signature is invalid."));
+                }
+                Ok(())
+            },
+            (ManifestKey::Secret(secret), ["signature", "hmac-sha256", signature]) => {
+                let expected = Self::hmac(secret, data)?;
+                let signature = from_hex(signature)?;
+                if expected.len() != signature.len() || !memcmp::eq(&expected, &signature) {
+                    return Err(coded_error!("E0810", "Manifest signature is invalid."));
+                }
+                Ok(())
+            },
+            (_, ["signature", kind, ..]) => Err(coded_error!("E0810", "Manifest has a \"{}\" signature, which doesn't match the given key.", kind)),
+            _ => Err(coded_error!("E0810", "Manifest has no valid signature line."))
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(input: &str) -> Result<Vec<u8>, Error> {
+    if !input.len().is_multiple_of(2) || !input.is_ascii() {
+        return Err(coded_error!("E0811", "Invalid hex string in manifest."));
+    }
+    (0..input.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..(i + 2)], 16).map_err(|_| coded_error!("E0811", "Invalid hex string in manifest.")))
+        .collect()
+}
+
+/// Size and SHA-256 hash of a file in a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    /// Size in bytes
+    pub size: u64,
+    /// SHA-256 hash as lowercase hex
+    pub sha256: String,
+}
+
+/// Contents of a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    /// Glob patterns of relative paths that aren't listed
+    pub excludes: Vec<String>,
+    /// Files by relative path with `/` separators
+    pub files: BTreeMap<String, ManifestFile>,
+}
+
+fn list_files(directory: &Path, root: &Path, excludes: &[String], files: &mut Vec<(String, PathBuf)>) -> Result<(), Error> {
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, root, excludes, files)?;
+            continue;
+        }
+
+        let relative: Vec<String> = path.strip_prefix(root).unwrap().components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let relative = relative.join("/");
+        if relative.contains('\n') {
+            return Err(coded_error!("E0812", "File name {:?} contains a line break.", relative));
+        }
+        if relative == MANIFEST_FILE || !file_allowed(&relative, excludes) {
+            continue;
+        }
+
+        files.push((relative, path));
+    }
+
+    Ok(())
+}
+
+impl Manifest {
+    /// Lists all files in the mod folder that don't match any of the exclusion patterns.
+    /// Patterns are matched against the relative path with `/` separators.
+    pub fn from_directory(directory: &Path, excludes: &[String]) -> Result<Manifest, Error> {
+        let mut paths: Vec<(String, PathBuf)> = Vec::new();
+        list_files(directory, directory, excludes, &mut paths).prepend_error("Failed to read mod folder:")?;
+
+        let mut files: BTreeMap<String, ManifestFile> = BTreeMap::new();
+        for (relative, path) in paths {
+            let content = read(&path).prepend_error(format!("Failed to read {}:", path.display()))?;
+            files.insert(relative, ManifestFile {
+                size: content.len() as u64,
+                sha256: to_hex(&hash(MessageDigest::sha256(), &content)?),
+            });
+        }
+
+        Ok(Manifest {
+            excludes: excludes.to_vec(),
+            files,
+        })
+    }
+
+    fn body(&self) -> String {
+        let mut body = format!("{}\n", MANIFEST_HEADER);
+        for pattern in &self.excludes {
+            body += &format!("exclude {}\n", pattern);
+        }
+        for (path, file) in &self.files {
+            body += &format!("file {} {} {}\n", file.sha256, file.size, path);
+        }
+        body
+    }
+
+    /// Writes the manifest signed with the key.
+    pub fn write_signed<O: Write>(&self, output: &mut O, key: &ManifestKey) -> Result<(), Error> {
+        let body = self.body();
+        let signature = key.sign(body.as_bytes())?;
+        output.write_all(body.as_bytes())?;
+        writeln!(output, "{}", signature)?;
+        Ok(())
+    }
+
+    /// Reads a manifest and checks its signature with the key.
+    pub fn read_verified<I: Read>(input: &mut I, key: &ManifestKey) -> Result<Manifest, Error> {
+        let mut text = String::new();
+        input.read_to_string(&mut text).prepend_error("Failed to read manifest:")?;
+
+        let start = text.rfind("\nsignature ").ok_or_else(|| coded_error!("E0810", "Manifest has no signature."))? + 1;
+        let (body, signature) = text.split_at(start);
+        key.verify(body.as_bytes(), signature.trim_end())?;
+
+        let mut lines = body.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(coded_error!("E0811", "Not an armake2 manifest or unsupported version."));
+        }
+
+        let mut manifest = Manifest::default();
+        for line in lines {
+            if let Some(pattern) = line.strip_prefix("exclude ") {
+                manifest.excludes.push(pattern.to_string());
+            } else if let Some(entry) = line.strip_prefix("file ") {
+                let mut parts = entry.splitn(3, ' ');
+                let (sha256, size, path) = match (parts.next(), parts.next().and_then(|s| s.parse().ok()), parts.next()) {
+                    (Some(sha256), Some(size), Some(path)) => (sha256, size, path),
+                    _ => return Err(coded_error!("E0811", "Invalid manifest line \"{}\".", line))
+                };
+                manifest.files.insert(path.to_string(), ManifestFile { size, sha256: sha256.to_string() });
+            } else {
+                return Err(coded_error!("E0811", "Invalid manifest line \"{}\".", line));
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Differences between a manifest and the current state of the mod folder
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManifestDiff {
+    /// Files that aren't in the manifest
+    pub added: Vec<String>,
+    /// Files of the manifest that don't exist anymore
+    pub removed: Vec<String>,
+    /// Files whose size or content changed
+    pub modified: Vec<String>,
+}
+
+impl ManifestDiff {
+    /// Returns true if the folder matches the manifest.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Compares the mod folder to a verified manifest, using the exclusion patterns recorded in it.
+pub fn diff_manifest(directory: &Path, manifest: &Manifest) -> Result<ManifestDiff, Error> {
+    let current = Manifest::from_directory(directory, &manifest.excludes)?;
+    let mut diff = ManifestDiff::default();
+
+    for (path, file) in &current.files {
+        match manifest.files.get(path) {
+            None => diff.added.push(path.clone()),
+            Some(expected) if expected != file => diff.modified.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = manifest.files.keys().filter(|p| !current.files.contains_key(*p)).cloned().collect();
+
+    Ok(diff)
+}
+
+/// Creates a manifest of the mod folder signed with the key or secret in `key_path` and
+/// writes it to output.
+pub fn cmd_manifest_create<O: Write>(directory: &Path, key_path: &Path, output: &mut O, excludes: &[String]) -> Result<(), Error> {
+    let key = ManifestKey::from_file(key_path)?;
+    let manifest = Manifest::from_directory(directory, excludes)?;
+    manifest.write_signed(output, &key).prepend_error("Failed to write manifest:")
+}
+
+/// Verifies the manifest with the key or secret in `key_path` and prints every added, removed
+/// and modified file. Fails if the signature is invalid or the folder doesn't match.
+pub fn cmd_manifest_verify<I: Read>(directory: &Path, key_path: &Path, manifest: &mut I) -> Result<(), Error> {
+    let key = ManifestKey::from_file(key_path)?;
+    let manifest = Manifest::read_verified(manifest, &key)?;
+    let diff = diff_manifest(directory, &manifest)?;
+
+    for path in &diff.added {
+        println!("ADDED     {}", path);
+    }
+    for path in &diff.removed {
+        println!("REMOVED   {}", path);
+    }
+    for path in &diff.modified {
+        println!("MODIFIED  {}", path);
+    }
+
+    if !diff.is_empty() {
+        return Err(coded_error!("E0813", "{} of {} files don't match the manifest.", diff.added.len() + diff.removed.len() + diff.modified.len(),
+            manifest.files.len() + diff.added.len()));
+    }
+
+    println!("{} files match the manifest.", manifest.files.len());
+    Ok(())
+}
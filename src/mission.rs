@@ -0,0 +1,142 @@
+//! Packing of mission and campaign folders
+//!
+//! Missions are packed without binarization like `pack`, with a few rules of their own: the
+//! folder must contain a `mission.sqm`, which can optionally be rapified, `description.ext` is
+//! kept as it is, and the PBO is named `missionname.worldname.pbo`.
+
+use std::ffi::{OsStr};
+use std::fs::{File};
+use std::io::{Cursor, Error, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, ConfigEntry};
+use crate::error::*;
+use crate::paa::{Paa};
+use crate::pbo::{PBO};
+use crate::progress::{Progress};
+
+/// Extensions of the images converted to PAA with `MissionOptions::convert_images`
+const IMAGE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// Options for packing a mission
+#[derive(Debug, Clone, Default)]
+pub struct MissionOptions {
+    /// Rapify `mission.sqm` unless it already is
+    pub binarize_sqm: bool,
+    /// Convert JPG and PNG images, like overview and loading screen pictures, to PAA
+    pub convert_images: bool,
+    /// World name to use instead of the one in the folder name
+    pub world: Option<String>,
+}
+
+/// Returns the mission and world name of a mission folder named `missionname.worldname`. The
+/// world from the folder name is replaced by `world` if one is given.
+pub fn mission_name(directory: &Path, world: Option<&str>) -> Result<(String, String), Error> {
+    let folder = directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf());
+    let folder = folder.file_name().and_then(|n| n.to_str())
+        .ok_or_else(|| coded_error!("E0701", "Failed to get the name of the mission folder {}.", directory.display()))?;
+
+    let (mission, folder_world) = match folder.rsplit_once('.') {
+        Some((mission, world)) if !mission.is_empty() && !world.is_empty() => (mission, Some(world)),
+        _ => (folder, None)
+    };
+
+    match world.or(folder_world) {
+        Some(world) => Ok((mission.to_string(), world.to_string())),
+        None => Err(coded_error!("E0701", "Mission folder \"{}\" isn't named missionname.worldname and no world was given.", folder))
+    }
+}
+
+/// Returns the file name of the PBO for a mission folder, `missionname.worldname.pbo`.
+pub fn mission_pbo_name(directory: &Path, world: Option<&str>) -> Result<String, Error> {
+    let (mission, world) = mission_name(directory, world)?;
+    Ok(format!("{}.{}.pbo", mission, world))
+}
+
+/// Returns the `briefingName` of a `description.ext`, or `None` if it has none.
+pub fn read_briefing_name(path: &Path) -> Result<Option<String>, Error> {
+    let config = Config::read(&mut File::open(path)?, Some(path.to_path_buf()), &[])?;
+    match config.root().get("briefingName") {
+        Some(ConfigEntry::StringEntry(name)) => Ok(Some(name.clone())),
+        Some(_) => Err(coded_error!("E0702", "briefingName is not a string.")),
+        None => Ok(None)
+    }
+}
+
+fn find_entry(pbo: &PBO, name: &str) -> Option<String> {
+    pbo.files.keys().find(|k| k.eq_ignore_ascii_case(name)).cloned()
+}
+
+/// Converts the images of the mission to PAA. Images with a PAA of the same name next to them
+/// are left out.
+fn convert_images(pbo: &mut PBO, directory: &Path) -> Result<(), Error> {
+    let images: Vec<String> = pbo.files.keys()
+        .filter(|k| Path::new(k.as_str()).extension().and_then(OsStr::to_str)
+            .is_some_and(|e| IMAGE_EXTENSIONS.iter().any(|i| e.eq_ignore_ascii_case(i))))
+        .cloned()
+        .collect();
+
+    for name in images {
+        pbo.files.remove(&name);
+
+        let paa_name = Path::new(&name).with_extension("paa").to_string_lossy().to_string();
+        if find_entry(pbo, &paa_name).is_some() {
+            warning(format!("{} is left out, {} already exists.", name, paa_name), Some("mission-image-exists"), (None, None));
+            continue;
+        }
+
+        let path = directory.join(name.replace('\\', "/"));
+        let img = image::open(&path).map_err(|e| coded_error!("E0407", "Failed to open image {}: {}", name, e))?;
+        let paa = Paa::try_from(&img).prepend_error(format!("Failed to convert {}:", name))?;
+
+        let mut buffer: Vec<u8> = Vec::new();
+        paa.write_to(&mut buffer)?;
+        pbo.files.insert(paa_name, Cursor::new(buffer.into_boxed_slice()));
+    }
+
+    Ok(())
+}
+
+impl PBO {
+    /// Constructs a PBO from a mission folder. Fails if there is no `mission.sqm`, warns if the
+    /// `briefingName` of `description.ext` can't be read.
+    pub fn from_mission_directory(directory: PathBuf, exclude_patterns: &[String], options: &MissionOptions, progress: Option<&dyn Fn(Progress)>) -> Result<PBO, Error> {
+        let mut pbo = PBO::from_directory_with_progress(directory.clone(), false, exclude_patterns, &[], progress)?;
+
+        let sqm = find_entry(&pbo, "mission.sqm")
+            .ok_or_else(|| coded_error!("E0703", "Mission folder {} has no mission.sqm.", directory.display()))?;
+
+        if options.binarize_sqm && !pbo.files[&sqm].get_ref().starts_with(b"\0raP") {
+            let path = directory.join(&sqm);
+            let config = Config::read(&mut pbo.files[&sqm].clone(), Some(path), &[]).prepend_error("Failed to parse mission.sqm:")?;
+            *pbo.files.get_mut(&sqm).unwrap() = config.to_cursor()?;
+        }
+
+        if let Some(description) = find_entry(&pbo, "description.ext") {
+            let path = directory.join(&description);
+            if let Err(e) = read_briefing_name(&path) {
+                warning(format!("Failed to read the briefingName of description.ext: {}", e), Some("mission-briefing-name"), (None, None));
+            }
+        }
+
+        if options.convert_images {
+            convert_images(&mut pbo, &directory)?;
+        }
+
+        Ok(pbo)
+    }
+}
+
+/// Packs a mission folder into a PBO, see `PBO::from_mission_directory`.
+pub fn cmd_pack_mission<O: Write>(input: PathBuf, output: &mut O, headerext: &[String], excludes: &[String], options: &MissionOptions, progress: Option<&dyn Fn(Progress)>) -> Result<(), Error> {
+    let mut pbo = PBO::from_mission_directory(input, excludes, options, progress)?;
+
+    for h in headerext {
+        let (key, value) = h.split_once('=').unwrap_or((h, ""));
+        pbo.header_extensions.insert(key.to_string(), value.to_string());
+    }
+
+    pbo.write(output).prepend_error("Failed to write PBO:")?;
+
+    Ok(())
+}
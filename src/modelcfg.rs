@@ -0,0 +1,358 @@
+//! Functions for checking model.cfg files
+//!
+//! model.cfg files are regular configs, so they are parsed with the config pipeline. The checks
+//! cover the mistakes that most often result in invisible or T-posing models: bones whose parent
+//! is not defined, animations referencing selections that are neither a bone nor a section, and
+//! `CfgModels` classes that don't match the name of the model next to them.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, read_dir};
+use std::io::{Read, Seek, SeekFrom, Error};
+use std::path::{Path, PathBuf};
+
+use regex::{Regex};
+
+use crate::config::*;
+use crate::error::*;
+use crate::p3d::*;
+use crate::preprocess::*;
+
+/// Maximum depth when following inheritance, guards against inheritance loops
+const MAX_DEPTH: usize = 32;
+
+/// Problem found in a model.cfg
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelCfgIssue {
+    /// Name of the warning this issue is reported as
+    pub name: &'static str,
+    /// Description of the problem
+    pub message: String,
+    /// File the problem was found in, if known
+    pub file: Option<String>,
+    /// Line the problem was found on, if known
+    pub line: Option<u32>,
+}
+
+/// Preprocessed model.cfg, used to locate classes and values for issue locations
+struct Source {
+    preprocessed: String,
+    info: PreprocessInfo,
+}
+
+impl Source {
+    /// Returns the position of the first match of `pattern` after `from`, or `from` if there is none.
+    fn find(&self, from: usize, pattern: &str) -> usize {
+        Regex::new(pattern).unwrap()
+            .find(&self.preprocessed[from..])
+            .map(|m| from + m.start())
+            .unwrap_or(from)
+    }
+
+    fn find_class(&self, from: usize, name: &str) -> usize {
+        self.find(from, &format!(r"(?i)\bclass\s+{}\b", regex::escape(name)))
+    }
+
+    fn find_string(&self, from: usize, value: &str) -> usize {
+        self.find(from, &regex::escape(&format!("\"{}\"", value)))
+    }
+
+    fn issue(&self, pos: usize, name: &'static str, message: String) -> ModelCfgIssue {
+        let (file, line) = origin_location(&self.preprocessed, &self.info, pos);
+
+        ModelCfgIssue {
+            name,
+            message,
+            file,
+            line,
+        }
+    }
+}
+
+fn string_entry<'a>(class: &'a ConfigClass, name: &str) -> Option<&'a str> {
+    match class.get(name) {
+        Some(ConfigEntry::StringEntry(s)) => Some(s),
+        _ => None
+    }
+}
+
+fn string_array<'a>(class: &'a ConfigClass, name: &str) -> Option<Vec<&'a str>> {
+    match class.get(name) {
+        Some(ConfigEntry::ArrayEntry(a)) => Some(a.elements().iter().filter_map(|e| match e {
+            ConfigArrayElement::StringElement(s) => Some(s.as_str()),
+            _ => None
+        }).collect()),
+        _ => None
+    }
+}
+
+/// Returns all subclasses that have a body, in order.
+fn subclasses(class: &ConfigClass) -> Vec<(&str, &ConfigClass)> {
+    class.entries().unwrap_or(&[]).iter().filter_map(|(name, entry)| match entry {
+        ConfigEntry::ClassEntry(c) if c.entries().is_some() => Some((name.as_str(), c)),
+        _ => None
+    }).collect()
+}
+
+fn class_map<'a>(classes: &[(&'a str, &'a ConfigClass)]) -> HashMap<ConfigName, &'a ConfigClass> {
+    classes.iter().map(|(name, class)| (ConfigName::new(name), *class)).collect()
+}
+
+/// Looks up a value on the class or, following config inheritance, on its parents among its siblings.
+fn lookup<'a, T>(siblings: &HashMap<ConfigName, &'a ConfigClass>, class: &'a ConfigClass, f: impl Fn(&'a ConfigClass) -> Option<T>) -> Option<T> {
+    let mut current = class;
+    for _i in 0..MAX_DEPTH {
+        if let Some(value) = f(current) {
+            return Some(value);
+        }
+        current = siblings.get(&ConfigName::new(current.parent()))?;
+    }
+    None
+}
+
+/// Returns the bones of the skeleton including the ones inherited via `skeletonInherit`.
+fn skeleton_bones(skeletons: &HashMap<ConfigName, &ConfigClass>, name: &str, depth: usize) -> Vec<String> {
+    let mut bones: Vec<String> = Vec::new();
+    let class = match skeletons.get(&ConfigName::new(name)) {
+        Some(class) if depth < MAX_DEPTH => class,
+        _ => return bones
+    };
+
+    if let Some(inherit) = lookup(skeletons, class, |c| string_entry(c, "skeletonInherit"))
+        && !inherit.is_empty() {
+        bones.extend(skeleton_bones(skeletons, inherit, depth + 1));
+    }
+    if let Some(own) = lookup(skeletons, class, |c| string_array(c, "skeletonBones")) {
+        bones.extend(own.iter().step_by(2).map(|b| b.to_string()));
+    }
+
+    bones
+}
+
+/// Returns the sections of the model including the ones inherited via `sectionsInherit`.
+fn model_sections(models: &HashMap<ConfigName, &ConfigClass>, name: &str, depth: usize) -> Vec<String> {
+    let mut sections: Vec<String> = Vec::new();
+    let class = match models.get(&ConfigName::new(name)) {
+        Some(class) if depth < MAX_DEPTH => class,
+        _ => return sections
+    };
+
+    if let Some(inherit) = lookup(models, class, |c| string_entry(c, "sectionsInherit"))
+        && !inherit.is_empty() {
+        sections.extend(model_sections(models, inherit, depth + 1));
+    }
+    if let Some(own) = lookup(models, class, |c| string_array(c, "sections")) {
+        sections.extend(own.iter().map(|s| s.to_string()));
+    }
+
+    sections
+}
+
+/// Reads the named selections of all LODs of an MLOD. Returns `None` for binarized models.
+fn p3d_selections(path: &Path) -> Result<Option<HashSet<String>>, Error> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    if &magic != b"MLOD" {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    let p3d = P3D::read(&mut file)?;
+    Ok(Some(p3d.lods.iter()
+        .flat_map(|lod| lod.taggs.keys())
+        .filter(|name| !name.starts_with('#'))
+        .map(|name| name.to_lowercase())
+        .collect()))
+}
+
+fn check_skeletons(source: &Source, skeletons: &[(&str, &ConfigClass)], issues: &mut Vec<ModelCfgIssue>) {
+    let skeleton_map = class_map(skeletons);
+    let section_pos = source.find_class(0, "CfgSkeletons");
+
+    for (name, skeleton) in skeletons {
+        let pos = source.find_class(section_pos, name);
+
+        let mut known: HashSet<String> = HashSet::new();
+        if let Some(inherit) = lookup(&skeleton_map, skeleton, |c| string_entry(c, "skeletonInherit")) {
+            if !inherit.is_empty() && !skeleton_map.contains_key(&ConfigName::new(inherit)) {
+                if string_entry(skeleton, "skeletonInherit").is_some() {
+                    issues.push(source.issue(pos, "modelcfg-unknown-skeleton",
+                        format!("Skeleton \"{}\" inherits from unknown skeleton \"{}\".", name, inherit)));
+                }
+            } else if !inherit.is_empty() {
+                known.extend(skeleton_bones(&skeleton_map, inherit, 1).iter().map(|b| b.to_lowercase()));
+            }
+        }
+
+        // Inherited bone lists were already checked on the parent
+        let bones = match string_array(skeleton, "skeletonBones") {
+            Some(bones) => bones,
+            None => continue
+        };
+
+        if bones.len() % 2 != 0 {
+            issues.push(source.issue(pos, "modelcfg-bone-pairs",
+                format!("skeletonBones of skeleton \"{}\" has an odd number of elements, expected bone and parent pairs.", name)));
+        }
+
+        let own: HashSet<String> = bones.iter().step_by(2).map(|b| b.to_lowercase()).collect();
+        for pair in bones.chunks(2) {
+            let bone = pair[0];
+            let bone_pos = source.find_string(pos, bone);
+
+            if !known.insert(bone.to_lowercase()) {
+                issues.push(source.issue(bone_pos, "modelcfg-duplicate-bone",
+                    format!("Bone \"{}\" is defined more than once in skeleton \"{}\".", bone, name)));
+            }
+
+            let parent = match pair.get(1) {
+                Some(parent) if !parent.is_empty() => parent,
+                _ => continue
+            };
+            if known.contains(&parent.to_lowercase()) {
+                continue;
+            }
+
+            let message = if own.contains(&parent.to_lowercase()) {
+                format!("Parent \"{}\" of bone \"{}\" in skeleton \"{}\" is defined after its child.", parent, bone, name)
+            } else {
+                format!("Parent \"{}\" of bone \"{}\" in skeleton \"{}\" is not defined.", parent, bone, name)
+            };
+            issues.push(source.issue(bone_pos, "modelcfg-undefined-parent", message));
+        }
+    }
+}
+
+fn check_models(source: &Source, models: &[(&str, &ConfigClass)], skeletons: &[(&str, &ConfigClass)], directory: Option<&Path>, issues: &mut Vec<ModelCfgIssue>) -> Result<(), Error> {
+    let model_map = class_map(models);
+    let skeleton_map = class_map(skeletons);
+    let section_pos = source.find_class(0, "CfgModels");
+
+    let p3ds: Vec<PathBuf> = match directory {
+        Some(directory) => read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && path.extension().map(|e| e.eq_ignore_ascii_case("p3d")).unwrap_or(false))
+            .collect(),
+        None => Vec::new()
+    };
+    let p3d_stem = |path: &PathBuf| path.file_stem().unwrap().to_string_lossy().to_lowercase();
+
+    for (name, model) in models {
+        let pos = source.find_class(section_pos, name);
+
+        let skeleton_name = lookup(&model_map, model, |c| string_entry(c, "skeletonName")).unwrap_or("");
+        let skeleton_known = skeleton_name.is_empty() || skeleton_map.contains_key(&ConfigName::new(skeleton_name));
+        if !skeleton_known && string_entry(model, "skeletonName").is_some() {
+            issues.push(source.issue(pos, "modelcfg-unknown-skeleton",
+                format!("Model \"{}\" uses unknown skeleton \"{}\".", name, skeleton_name)));
+        }
+
+        let mut selections: HashSet<String> = skeleton_bones(&skeleton_map, skeleton_name, 0).iter().map(|b| b.to_lowercase()).collect();
+        selections.extend(model_sections(&model_map, name, 0).iter().map(|s| s.to_lowercase()));
+
+        let p3d_selections = match p3ds.iter().find(|p| p3d_stem(p) == name.to_lowercase()) {
+            Some(path) => p3d_selections(path).prepend_error(format!("Failed to read \"{}\":", path.display()))?,
+            None => None
+        };
+
+        let mut used: Vec<(usize, String)> = string_array(model, "sections").unwrap_or_default().iter()
+            .map(|s| (source.find_string(pos, s), s.to_string()))
+            .collect();
+
+        if let Some(ConfigEntry::ClassEntry(animations)) = model.get("Animations") {
+            let animations_list = subclasses(animations);
+            let animation_map = class_map(&animations_list);
+            let animations_pos = source.find_class(pos, "Animations");
+
+            for (animation_name, animation) in &animations_list {
+                let animation_pos = source.find_class(animations_pos, animation_name);
+                let selection = match lookup(&animation_map, animation, |c| string_entry(c, "selection")) {
+                    Some(selection) if !selection.is_empty() => selection,
+                    _ => continue
+                };
+
+                if skeleton_known && !selections.contains(&selection.to_lowercase()) {
+                    issues.push(source.issue(animation_pos, "modelcfg-unknown-selection",
+                        format!("Animation \"{}\" of model \"{}\" uses selection \"{}\", which is neither a bone nor a section.", animation_name, name, selection)));
+                }
+                used.push((animation_pos, selection.to_string()));
+            }
+        }
+
+        if let Some(p3d_selections) = p3d_selections {
+            let mut reported: HashSet<String> = HashSet::new();
+            for (used_pos, selection) in used {
+                if !p3d_selections.contains(&selection.to_lowercase()) && reported.insert(selection.to_lowercase()) {
+                    issues.push(source.issue(used_pos, "modelcfg-missing-selection",
+                        format!("Selection \"{}\" used by model \"{}\" doesn't exist in \"{}.p3d\".", selection, name, name)));
+                }
+            }
+        }
+    }
+
+    if p3ds.is_empty() {
+        return Ok(());
+    }
+
+    for path in &p3ds {
+        if !model_map.contains_key(&ConfigName::new(&p3d_stem(path))) {
+            issues.push(source.issue(section_pos, "modelcfg-missing-model",
+                format!("There is no class in CfgModels for model \"{}\".", path.file_name().unwrap().to_string_lossy())));
+        }
+    }
+
+    let parents: HashSet<ConfigName> = models.iter().map(|(_, m)| ConfigName::new(m.parent())).collect();
+    for (name, _) in models {
+        let class = ConfigName::new(name);
+        if class.matches("default") || parents.contains(&class) || p3ds.iter().any(|p| class.matches(&p3d_stem(p))) {
+            continue;
+        }
+        issues.push(source.issue(source.find_class(section_pos, name), "modelcfg-unknown-model",
+            format!("Model class \"{}\" doesn't match any model in the folder.", name)));
+    }
+
+    Ok(())
+}
+
+/// Parses the model.cfg at the given path (or inside the given folder) and checks it.
+///
+/// If a model next to the model.cfg is an unbinarized MLOD, the selections used by its class are
+/// also checked against the model. `includefolders` are the folders searched for absolute
+/// includes and should usually at least include the current working directory.
+pub fn check_modelcfg(path: &Path, includefolders: &[PathBuf]) -> Result<Vec<ModelCfgIssue>, Error> {
+    let path = if path.is_dir() { path.join("model.cfg") } else { path.to_path_buf() };
+
+    let mut buffer = String::new();
+    File::open(&path).prepend_error(format!("Failed to open \"{}\":", path.display()))?
+        .read_to_string(&mut buffer).prepend_error("Failed to read model.cfg:")?;
+
+    let (preprocessed, info) = preprocess(buffer, Some(path.clone()), includefolders).prepend_error("Failed to preprocess model.cfg:")?;
+    let config = Config::from_preprocessed(&preprocessed, &info, &ParseOptions::default())?;
+    let source = Source { preprocessed, info };
+
+    let skeletons = match config.root().get("CfgSkeletons") {
+        Some(ConfigEntry::ClassEntry(c)) => subclasses(c),
+        _ => Vec::new()
+    };
+    let models = match config.root().get("CfgModels") {
+        Some(ConfigEntry::ClassEntry(c)) => subclasses(c),
+        _ => Vec::new()
+    };
+
+    let mut issues: Vec<ModelCfgIssue> = Vec::new();
+    check_skeletons(&source, &skeletons, &mut issues);
+    let directory = path.parent().map(|p| if p.as_os_str().is_empty() { Path::new(".") } else { p });
+    check_models(&source, &models, &skeletons, directory, &mut issues)?;
+
+    Ok(issues)
+}
+
+/// Checks the model.cfg at the given path (or inside the given folder) and prints a warning for
+/// every issue found.
+pub fn cmd_modelcfg_check(path: &Path, includefolders: &[PathBuf]) -> Result<(), Error> {
+    for issue in check_modelcfg(path, includefolders)? {
+        warning(issue.message, Some(issue.name), (issue.file, issue.line));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,449 @@
+//! Read-only parser for binarized (ODOL) models
+//!
+//! Only the parts needed to list the LODs of a model are parsed: the LOD resolutions, textures,
+//! materials and named selections. Everything else is skipped, which still requires walking
+//! through the model info and animations since the LOD offsets are stored after them.
+//!
+//! Arrays in ODOL files are compressed once they reach 1024 bytes, with LZSS before version 44
+//! and LZO since then. From version 64 on, a flag in front of every array tells whether it is
+//! compressed instead.
+
+use std::io::{Cursor, Read, Error};
+use std::ops::{RangeInclusive};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::error::*;
+use crate::io::*;
+use crate::p3d::{LODInfo, P3DFormat, P3DInfo};
+use crate::paa::{LzoCodec, MiniLzo};
+
+/// ODOL versions that can be read
+pub const SUPPORTED_VERSIONS: RangeInclusive<u32> = 40..=73;
+
+/// Arrays smaller than this are never compressed
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Animation types that don't store an axis per LOD
+const ANIMATION_DIRECT: u32 = 8;
+const ANIMATION_HIDE: u32 = 9;
+
+struct OdolReader<'a> {
+    input: Cursor<&'a [u8]>,
+    version: u32,
+}
+
+impl OdolReader<'_> {
+    fn u8(&mut self) -> Result<u8, Error> {
+        self.input.read_u8()
+    }
+
+    fn bool(&mut self) -> Result<bool, Error> {
+        Ok(self.input.read_u8()? != 0)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.input.read_u32::<LittleEndian>()
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        self.input.read_i32::<LittleEndian>()
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        self.input.read_f32::<LittleEndian>()
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.input.read_cstring_lossy()
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, Error> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn remaining(&self) -> &[u8] {
+        let data = *self.input.get_ref();
+        &data[(self.input.position() as usize).min(data.len())..]
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), Error> {
+        if self.remaining().len() < length {
+            return Err(coded_error!("E0601", "Unexpected end of file at offset {}.", self.input.position()));
+        }
+        self.input.set_position(self.input.position() + length as u64);
+        Ok(())
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<(), Error> {
+        if offset as usize > self.input.get_ref().len() {
+            return Err(coded_error!("E0601", "Offset {} is outside of the file.", offset));
+        }
+        self.input.set_position(offset as u64);
+        Ok(())
+    }
+
+    fn vertex_index_size(&self) -> usize {
+        if self.version >= 69 { 4 } else { 2 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut buffer = vec![0; length];
+        self.input.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Reads data that is `size` bytes long when decompressed.
+    fn compressed(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let compressed = if self.version >= 64 { self.bool()? } else { size >= COMPRESSION_THRESHOLD };
+        if !compressed {
+            return self.bytes(size);
+        }
+
+        let offset = self.input.position();
+        let (data, length) = if self.version >= 44 {
+            lzo_decompress(self.remaining(), size)
+        } else {
+            lzss_decompress(self.remaining(), size)
+        }.prepend_error(format!("Failed to decompress array at offset {}:", offset))?;
+
+        self.skip(length)?;
+        Ok(data)
+    }
+
+    /// Skips an array with its element count in front.
+    fn skip_compressed_array(&mut self, element_size: usize) -> Result<(), Error> {
+        let count = self.u32()? as usize;
+        self.compressed(count * element_size)?;
+        Ok(())
+    }
+
+    /// Skips an array that is either filled with a single value or stored compressed.
+    fn skip_condensed_array(&mut self, element_size: usize) -> Result<(), Error> {
+        let count = self.u32()? as usize;
+        if self.bool()? {
+            self.skip(element_size)
+        } else {
+            self.compressed(count * element_size).map(|_| ())
+        }
+    }
+
+    fn skip_skeleton(&mut self) -> Result<(), Error> {
+        let name = self.string()?;
+        if name.is_empty() {
+            return Ok(());
+        }
+
+        self.bool()?; // discrete
+        let bones = self.u32()?;
+        for _i in 0..bones {
+            self.string()?;
+            self.string()?;
+        }
+        if self.version > 40 {
+            self.string()?; // pivots model, obsolete
+        }
+        Ok(())
+    }
+
+    fn skip_model_info(&mut self) -> Result<(), Error> {
+        // Special flags, bounding and geometry sphere, remarks, hints, aiming center, colors, view density
+        self.skip(4 * 6 + 12 + 4 * 3)?;
+        self.skip(12 * 2)?; // bounding box
+        if self.version >= 70 { self.f32()?; } // LOD density coefficient
+        if self.version >= 71 { self.f32()?; } // draw importance
+        if self.version >= 52 { self.skip(12 * 2)?; } // visual bounding box
+        self.skip(12 * 3 + 4 * 9)?; // bounding center, geometry center, center of mass, inverse inertia
+        self.skip(4)?; // auto center, lock auto center, can occlude, can be occluded
+        if self.version >= 73 { self.bool()?; } // AI covers
+        if self.version >= 42 { self.skip(4 * 4)?; } // armor of the HT, AF and MF
+        if self.version >= 43 { self.skip(4 * 2)?; }
+        self.bool()?; // force not alpha model
+        self.skip(4 + 1)?; // shadow buffer source, prefer shadow volume
+        if self.version >= 48 { self.f32()?; } // shadow offset
+        self.bool()?; // animated
+
+        self.skip_skeleton().prepend_error("Failed to read skeleton:")?;
+
+        self.u8()?; // map type
+        self.skip_compressed_array(4)?; // mass array
+        self.skip(4 * 4)?; // mass, inverse mass, armor, inverse armor
+        if self.version >= 72 { self.f32()?; } // explosion shielding
+        if self.version >= 53 { self.u8()?; } // simple geometry LOD
+        if self.version >= 54 { self.u8()?; } // physx geometry LOD
+        self.skip(12)?; // indices of the special LODs
+        self.u32()?; // minimum shadow
+        self.bool()?; // can blend
+
+        self.string()?; // class
+        self.string()?; // damage
+        self.bool()?; // frame sequence
+        self.u32()?;
+        if self.version >= 57 { self.skip(4 * 3)?; } // preferred shadow LODs
+
+        Ok(())
+    }
+
+    fn skip_animations(&mut self, lods: usize) -> Result<(), Error> {
+        let count = self.u32()?;
+        let mut types: Vec<u32> = Vec::with_capacity(count as usize);
+        for _i in 0..count {
+            let animation_type = self.u32()?;
+            self.string()?; // name
+            self.string()?; // source
+            self.skip(4 * 2)?; // minimum and maximum value
+            if self.version >= 56 { self.skip(4 * 2)?; } // minimum and maximum phase
+            self.u32()?; // source address
+
+            match animation_type {
+                0..=7 => self.skip(4 * 2)?, // angles or offsets
+                ANIMATION_DIRECT => self.skip(12 * 2 + 4 * 2)?, // axis position and direction, angle, axis offset
+                ANIMATION_HIDE => self.skip(if self.version >= 55 { 4 * 2 } else { 4 })?,
+                _ => return Err(coded_error!("E0601", "Unknown animation type {}.", animation_type))
+            }
+            types.push(animation_type);
+        }
+
+        // Animations of every bone
+        let resolutions = self.u32()? as usize;
+        if resolutions != lods {
+            return Err(coded_error!("E0601", "Animations are defined for {} LODs, but the model has {}.", resolutions, lods));
+        }
+        for _i in 0..lods {
+            let bones = self.u32()?;
+            for _j in 0..bones {
+                let animations = self.u32()? as usize;
+                self.skip(4 * animations)?;
+            }
+        }
+
+        // Bone and axis of every animation
+        for _i in 0..lods {
+            for animation_type in &types {
+                let bone = self.i32()?;
+                if bone != -1 && *animation_type != ANIMATION_DIRECT && *animation_type != ANIMATION_HIDE {
+                    self.skip(12 * 2)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn skip_stage_texture(&mut self, material_version: u32) -> Result<(), Error> {
+        if material_version >= 5 { self.u32()?; } // filter
+        self.string()?;
+        if material_version >= 8 { self.u32()?; } // stage
+        if material_version >= 11 { self.bool()?; } // world environment map
+        Ok(())
+    }
+
+    fn read_material(&mut self) -> Result<String, Error> {
+        let name = self.string()?;
+        let material_version = self.u32()?;
+
+        self.skip(16 * 6 + 4)?; // colors and specular power
+        self.skip(4 * 4)?; // shaders, main light, fog mode
+        if material_version == 3 { self.bool()?; }
+        if material_version >= 6 { self.string()?; } // surface
+        if material_version >= 4 { self.skip(4 * 2)?; } // render flags
+
+        let stages = self.u32()?;
+        let transforms = if material_version > 6 { self.u32()? } else { stages };
+        for _i in 0..stages {
+            self.skip_stage_texture(material_version)?;
+        }
+        for _i in 0..transforms {
+            self.skip(4 + 4 * 12)?; // UV source and transformation
+        }
+        if material_version >= 10 {
+            self.skip_stage_texture(material_version)?;
+        }
+
+        Ok(name)
+    }
+
+    fn skip_section(&mut self) -> Result<(), Error> {
+        // Face range, bones, common point flags and texture, face flags
+        self.skip(4 * 5 + 2 + 4)?;
+        if self.i32()? == -1 {
+            self.string()?; // surface
+        }
+        let stages = self.u32()? as usize;
+        self.skip(4 * stages)?; // area over texture
+        if self.version >= 67 && self.u32()? > 0 {
+            self.skip(4 * 11)?;
+        }
+        Ok(())
+    }
+
+    fn read_selection(&mut self) -> Result<String, Error> {
+        let name = self.string()?;
+        self.skip_compressed_array(self.vertex_index_size())?; // faces
+        self.u32()?;
+        self.bool()?; // sectional
+        self.skip_compressed_array(4)?; // sections
+        self.skip_compressed_array(self.vertex_index_size())?; // vertices
+        self.skip_compressed_array(1)?; // vertex weights
+        Ok(name)
+    }
+
+    fn read_lod(&mut self, resolution: f32) -> Result<LODInfo, Error> {
+        let proxies = self.u32()?;
+        for _i in 0..proxies {
+            self.string()?;
+            self.skip(4 * 12 + 4 * 3)?; // transformation, sequence ID, selection and bone index
+            self.u32()?; // section index
+        }
+
+        let bones = self.u32()? as usize;
+        self.skip(4 * bones)?;
+        let bones = self.u32()?;
+        for _i in 0..bones {
+            let links = self.u32()? as usize;
+            self.skip(4 * links)?;
+        }
+
+        if self.version >= 50 {
+            self.u32()?; // vertex count
+        } else {
+            self.skip_condensed_array(4)?; // point flags
+        }
+        if self.version >= 51 { self.f32()?; } // face area
+        self.skip(4 * 2 + 12 * 3 + 4)?; // hints, bounding box and sphere
+
+        let textures = self.strings().prepend_error("Failed to read textures:")?;
+        let materials = self.u32()?;
+        let materials = (0..materials).map(|_| self.read_material()).collect::<Result<Vec<String>, Error>>()
+            .prepend_error("Failed to read materials:")?;
+
+        self.skip_compressed_array(self.vertex_index_size())?; // MLOD edges
+        self.skip_compressed_array(self.vertex_index_size())?; // vertex edges
+
+        let faces = self.u32()?;
+        self.skip(4 + 2)?;
+        for _i in 0..faces {
+            let vertices = self.u8()? as usize;
+            self.skip(vertices * self.vertex_index_size())?;
+        }
+
+        let sections = self.u32()?;
+        for _i in 0..sections {
+            self.skip_section().prepend_error("Failed to read sections:")?;
+        }
+
+        let selections = self.u32()?;
+        let selections = (0..selections).map(|_| self.read_selection()).collect::<Result<Vec<String>, Error>>()
+            .prepend_error("Failed to read named selections:")?;
+
+        Ok(LODInfo {
+            resolution,
+            textures,
+            materials,
+            selections,
+        })
+    }
+}
+
+/// LZO streams don't store their length, so the end is found by trying every end of stream
+/// marker until one decompresses to the expected size. Returns the data and the stream length.
+fn lzo_decompress(data: &[u8], size: usize) -> Result<(Vec<u8>, usize), Error> {
+    let mut last_error = coded_error!("E0601", "No end of stream marker found.");
+    for end in (3..=data.len()).filter(|end| data[end - 3..*end] == [0x11, 0, 0]) {
+        match MiniLzo.decompress(&data[..end], size) {
+            Ok(decompressed) => return Ok((decompressed, end)),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Decompresses BI's LZSS variant, which is followed by a checksum of the decompressed data.
+/// Returns the data and the stream length including the checksum.
+fn lzss_decompress(data: &[u8], size: usize) -> Result<(Vec<u8>, usize), Error> {
+    let mut output: Vec<u8> = Vec::with_capacity(size);
+    let mut pos = 0;
+    let next = |pos: &mut usize| -> Result<u8, Error> {
+        let byte = *data.get(*pos).ok_or_else(|| coded_error!("E0601", "Unexpected end of LZSS stream."))?;
+        *pos += 1;
+        Ok(byte)
+    };
+
+    while output.len() < size {
+        let flags = next(&mut pos)?;
+        for bit in 0..8 {
+            if output.len() >= size {
+                break;
+            }
+
+            if flags & (1 << bit) != 0 {
+                output.push(next(&mut pos)?);
+                continue;
+            }
+
+            let (low, high) = (next(&mut pos)? as usize, next(&mut pos)? as usize);
+            let distance = low | ((high & 0xf0) << 4);
+            let length = ((high & 0x0f) + 3).min(size - output.len());
+            for _i in 0..length {
+                // References before the start of the data are spaces
+                let byte = if distance > output.len() { b' ' } else { output[output.len() - distance] };
+                output.push(byte);
+            }
+        }
+    }
+
+    let checksum = data.get(pos..pos + 4).ok_or_else(|| coded_error!("E0601", "Unexpected end of LZSS stream."))?;
+    let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let expected = output.iter().fold(0u32, |sum, b| sum.wrapping_add(*b as u32));
+    if checksum != expected {
+        return Err(coded_error!("E0601", "LZSS checksum mismatch."));
+    }
+
+    Ok((output, pos + 4))
+}
+
+/// Reads the LODs of an ODOL model.
+pub fn read_odol(data: &[u8]) -> Result<P3DInfo, Error> {
+    if !data.starts_with(b"ODOL") {
+        return Err(coded_error!("E0602", "File is not an ODOL model."));
+    }
+
+    let mut reader = OdolReader { input: Cursor::new(data), version: 0 };
+    reader.skip(4)?;
+    let version = reader.u32()?;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(coded_error!("E0603", "ODOL version {} not supported, supported versions are {} to {}.", version,
+            SUPPORTED_VERSIONS.start(), SUPPORTED_VERSIONS.end()));
+    }
+    reader.version = version;
+
+    if version >= 59 { reader.u32()?; } // application ID
+    if version >= 58 { reader.string()?; } // muzzle flash
+
+    let lods = reader.u32()? as usize;
+    let resolutions = (0..lods).map(|_| reader.f32()).collect::<Result<Vec<f32>, Error>>()?;
+
+    reader.skip_model_info().prepend_error("Failed to read model info:")?;
+    if reader.bool()? {
+        reader.skip_animations(lods).prepend_error("Failed to read animations:")?;
+    }
+
+    let starts = (0..lods).map(|_| reader.u32()).collect::<Result<Vec<u32>, Error>>()?;
+
+    let mut lods: Vec<LODInfo> = Vec::with_capacity(resolutions.len());
+    for (i, (resolution, start)) in resolutions.iter().zip(starts.iter()).enumerate() {
+        let lod = reader.seek(*start).and_then(|_| reader.read_lod(*resolution));
+        lods.push(lod.prepend_error(format!("Failed to read LOD {}:", i + 1))?);
+    }
+
+    Ok(P3DInfo {
+        format: P3DFormat::Odol,
+        version,
+        lods,
+    })
+}
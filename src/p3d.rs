@@ -1,9 +1,11 @@
-use std::io::{Read, Seek, Write, Error, BufReader, BufWriter};
+use std::io::{Read, Seek, SeekFrom, Write, Error, BufReader, BufWriter};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use linked_hash_map::LinkedHashMap;
 
+use crate::error::*;
 use crate::io::*;
+use crate::odol;
 
 #[derive(Debug, Default)]
 pub struct Point {
@@ -43,6 +45,43 @@ pub struct P3D {
     pub lods: Vec<LOD>,
 }
 
+/// Format of a model file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum P3DFormat {
+    /// Unbinarized model as edited in Object Builder
+    Mlod,
+    /// Binarized model, which can only be read
+    Odol,
+}
+
+/// Textures, materials and named selections of one LOD
+#[derive(Debug, Clone, PartialEq)]
+pub struct LODInfo {
+    pub resolution: f32,
+    pub textures: Vec<String>,
+    pub materials: Vec<String>,
+    pub selections: Vec<String>,
+}
+
+/// Read-only summary of a model in either format
+#[derive(Debug, Clone, PartialEq)]
+pub struct P3DInfo {
+    pub format: P3DFormat,
+    pub version: u32,
+    pub lods: Vec<LODInfo>,
+}
+
+/// Returns the non-empty names in the order they are first used.
+fn unique<'a>(names: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for name in names {
+        if !name.is_empty() && !result.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            result.push(name.clone());
+        }
+    }
+    result
+}
+
 impl Point {
     #[allow(dead_code)]
     pub fn new() -> Point {
@@ -125,7 +164,7 @@ impl Face {
     }
 
     fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
-        output.write_u32::<LittleEndian>(self.vertices.len() as u32)?;
+        output.write_u32::<LittleEndian>(field_u32(self.vertices.len(), "Number of face vertices")?)?;
 
         for vert in &self.vertices {
             vert.write(output)?;
@@ -208,9 +247,9 @@ impl LOD {
         output.write_all(b"P3DM")?;
         output.write_u32::<LittleEndian>(self.version_major)?;
         output.write_u32::<LittleEndian>(self.version_minor)?;
-        output.write_u32::<LittleEndian>(self.points.len() as u32)?;
-        output.write_u32::<LittleEndian>(self.face_normals.len() as u32)?;
-        output.write_u32::<LittleEndian>(self.faces.len() as u32)?;
+        output.write_u32::<LittleEndian>(field_u32(self.points.len(), "Number of points")?)?;
+        output.write_u32::<LittleEndian>(field_u32(self.face_normals.len(), "Number of face normals")?)?;
+        output.write_u32::<LittleEndian>(field_u32(self.faces.len(), "Number of faces")?)?;
         output.write_all(b"\0\0\0\0")?;
 
         for point in &self.points {
@@ -232,7 +271,7 @@ impl LOD {
         for (name, buffer) in &self.taggs {
             output.write_all(&[1])?;
             output.write_cstring(name)?;
-            output.write_u32::<LittleEndian>(buffer.len() as u32)?;
+            output.write_u32::<LittleEndian>(field_u32(buffer.len(), &format!("Size of tagg {}", name))?)?;
             output.write_all(buffer)?;
         }
 
@@ -274,7 +313,7 @@ impl P3D {
 
         writer.write_all(b"MLOD")?;
         writer.write_u32::<LittleEndian>(self.version)?;
-        writer.write_u32::<LittleEndian>(self.lods.len() as u32)?;
+        writer.write_u32::<LittleEndian>(field_u32(self.lods.len(), "Number of LODs")?)?;
 
         for lod in &self.lods {
             lod.write(&mut writer)?;
@@ -282,4 +321,81 @@ impl P3D {
 
         Ok(())
     }
+
+    /// Returns the textures used by any LOD.
+    pub fn textures(&self) -> Vec<String> {
+        unique(self.lods.iter().flat_map(|lod| lod.faces.iter().map(|face| &face.texture)))
+    }
+
+    /// Returns the materials used by any LOD.
+    pub fn materials(&self) -> Vec<String> {
+        unique(self.lods.iter().flat_map(|lod| lod.faces.iter().map(|face| &face.material)))
+    }
+
+    /// Returns a summary of the LODs.
+    pub fn info(&self) -> P3DInfo {
+        P3DInfo {
+            format: P3DFormat::Mlod,
+            version: self.version,
+            lods: self.lods.iter().map(|lod| LODInfo {
+                resolution: lod.resolution,
+                textures: unique(lod.faces.iter().map(|face| &face.texture)),
+                materials: unique(lod.faces.iter().map(|face| &face.material)),
+                selections: lod.taggs.keys().filter(|name| !name.starts_with('#')).cloned().collect(),
+            }).collect(),
+        }
+    }
+}
+
+impl P3DInfo {
+    /// Reads an MLOD or ODOL model.
+    pub fn read<I: Read + Seek>(input: &mut I) -> Result<P3DInfo, Error> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic).prepend_error("Failed to read model:")?;
+        input.seek(SeekFrom::Current(-4))?;
+
+        match &magic {
+            b"MLOD" => Ok(P3D::read(input)?.info()),
+            b"ODOL" => {
+                let mut buffer: Vec<u8> = Vec::new();
+                input.read_to_end(&mut buffer)?;
+                odol::read_odol(&buffer)
+            },
+            _ => Err(coded_error!("E0602", "File is not a model."))
+        }
+    }
+
+    /// Returns the textures used by any LOD.
+    pub fn textures(&self) -> Vec<String> {
+        unique(self.lods.iter().flat_map(|lod| lod.textures.iter()))
+    }
+
+    /// Returns the materials used by any LOD.
+    pub fn materials(&self) -> Vec<String> {
+        unique(self.lods.iter().flat_map(|lod| lod.materials.iter()))
+    }
+}
+
+/// Prints the format, the LODs and the textures and materials of a model.
+pub fn cmd_p3d_info<I: Read + Seek, O: Write>(input: &mut I, output: &mut O) -> Result<(), Error> {
+    let info = P3DInfo::read(input)?;
+
+    writeln!(output, "format: {:?}", info.format)?;
+    writeln!(output, "version: {}", info.version)?;
+    writeln!(output, "lods: {}", info.lods.len())?;
+    for lod in &info.lods {
+        writeln!(output, "    {:<16} {} textures, {} materials, {} selections", lod.resolution, lod.textures.len(),
+            lod.materials.len(), lod.selections.len())?;
+    }
+
+    writeln!(output, "textures:")?;
+    for texture in info.textures() {
+        writeln!(output, "    {}", texture)?;
+    }
+    writeln!(output, "materials:")?;
+    for material in info.materials() {
+        writeln!(output, "    {}", material)?;
+    }
+
+    Ok(())
 }
@@ -1,12 +1,33 @@
-use std::io::{Read, Write, Cursor, Seek, SeekFrom};
-use std::path::Path;
-use std::fs::File;
-
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use image::{ImageBuffer, Rgba, DynamicImage};
+use std::borrow::{Cow};
+use std::io::{BufReader, Read, Write, Cursor, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::fs::{File};
+use std::cell::{RefCell};
+use std::sync::{Mutex};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use ddsfile::{Caps2, D3DFormat, Dds, NewD3dParams};
+use image::{ImageBuffer, ImageDecoder, ImageFormat, Luma, LumaA, Rgba, DynamicImage};
+use image::codecs::jpeg::{JpegDecoder};
+use image::codecs::png::{PngDecoder};
+use image::codecs::tiff::{TiffDecoder};
 use texpresso::{Format, Algorithm, Params};
 use minilzo_rs::LZO;
 
+use crate::detect::{FileKind};
+use crate::error::*;
+use crate::exif;
+use crate::io::{BatchSummary, OutputPolicy, check_target_collisions, create_file, create_folder, field_u32, files_with_extensions};
+use crate::jobmanifest::{self, JobManifest};
+use crate::lzo::{self, LzoReader};
+use crate::paaanalysis;
+use crate::parallel;
+use crate::pbo::{list_files};
+use crate::progress::{Progress};
+
+/// Extensions of the images `cmd_img2paa_folder` converts
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "tga", "dds"];
+
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PaaType {
@@ -14,17 +35,19 @@ pub enum PaaType {
     DXT3 = 0xFF03,
     DXT5 = 0xFF05,
     ARGB4444 = 0x4444,
+    ARGB8888 = 0x8888,
     ARGB1555 = 0x1555,
     AI88 = 0x8080,
 }
 
 impl PaaType {
-    fn from_u16(value: u16) -> Option<Self> {
+    pub(crate) fn from_u16(value: u16) -> Option<Self> {
         match value {
             0xFF01 => Some(PaaType::DXT1),
             0xFF03 => Some(PaaType::DXT3),
             0xFF05 => Some(PaaType::DXT5),
             0x4444 => Some(PaaType::ARGB4444),
+            0x8888 => Some(PaaType::ARGB8888),
             0x1555 => Some(PaaType::ARGB1555),
             0x8080 => Some(PaaType::AI88),
             _ => None,
@@ -32,13 +55,242 @@ impl PaaType {
     }
 }
 
+/// Which mipmaps are LZO compressed when encoding
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LzoPolicy {
+    /// Compress every mipmap that gets smaller, the default like `DEFAULT_COMPRESSION`
+    #[default]
+    Always,
+    /// Store every mipmap uncompressed
+    Never,
+    /// Like `Always`, but stop trying once a mipmap got less than `min_savings` percent smaller
+    Adaptive { min_savings: f32 },
+}
+
+/// Savings below which `adaptive` without a percentage stops compressing
+const DEFAULT_MIN_SAVINGS: f32 = 10.0;
+
+/// Options for encoding images, the defaults match `img2paa` without options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncodeOptions {
+    /// Which mipmaps are LZO compressed
+    pub lzo: LzoPolicy,
+    /// Mirror the image top to bottom before generating the mipmaps, for textures exported with
+    /// the V axis pointing the other way than the engine's
+    pub flip_vertical: bool,
+    /// Mirror the image left to right before generating the mipmaps
+    pub flip_horizontal: bool,
+    /// Encode photos as stored instead of turning them upright by their EXIF orientation, only
+    /// used when the image is read from a file
+    pub ignore_exif: bool,
+    /// How hard the DXT encoder looks for the best colors of each block
+    pub quality: EncodeQuality,
+    /// Store the name and version of armake2 in a `TOOL_TAGG`
+    pub tag_tool: bool,
+    /// Convert the image to 8-bit RGBA before generating the mipmaps, like earlier versions did,
+    /// instead of resizing 16-bit and grayscale images as they are. For comparing results only.
+    pub legacy_pipeline: bool,
+}
+
+impl EncodeOptions {
+    /// Returns the options that change the PAA written for an image, with the type it is
+    /// encoded as, for `jobmanifest::options_hash`.
+    pub fn job_options(&self, paa_type: Option<PaaType>) -> Vec<(&'static str, String)> {
+        let lzo = match self.lzo {
+            LzoPolicy::Always => "always".to_string(),
+            LzoPolicy::Never => "never".to_string(),
+            LzoPolicy::Adaptive { min_savings } => format!("adaptive:{}", min_savings),
+        };
+        let quality = match self.quality {
+            EncodeQuality::Default => "default",
+            EncodeQuality::Cluster => "cluster",
+            EncodeQuality::Fast => "fast",
+            EncodeQuality::Exhaustive => "exhaustive",
+        };
+        vec![
+            ("type", paa_type.map(|t| format!("0x{:04X}", t as u16)).unwrap_or_else(|| "auto".to_string())),
+            ("lzo", lzo),
+            ("flip_vertical", self.flip_vertical.to_string()),
+            ("flip_horizontal", self.flip_horizontal.to_string()),
+            ("ignore_exif", self.ignore_exif.to_string()),
+            ("quality", quality.to_string()),
+            ("tag_tool", self.tag_tool.to_string()),
+            ("legacy_pipeline", self.legacy_pipeline.to_string()),
+        ]
+    }
+}
+
+/// How hard the DXT encoder looks for the best colors of each block
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EncodeQuality {
+    /// A single iterative cluster fit per block
+    #[default]
+    Default,
+    /// A single cluster fit per block, without the iterations of `Default`. A bit faster, with
+    /// slightly more error.
+    Cluster,
+    /// A range fit per block, which only looks at the extent of the colors. Several times faster
+    /// than `Default` for batches where quality matters less, like previews.
+    Fast,
+    /// Encode every block with all fitting algorithms and endpoints from its most distant
+    /// colors, keeping the one closest to the source. Several times slower, but reduces banding
+    /// on gradients.
+    Exhaustive,
+}
+
+impl EncodeQuality {
+    /// Returns the fitting algorithm of texpresso used for every block, the default one for
+    /// `Exhaustive`, which tries all of them.
+    fn algorithm(self) -> Algorithm {
+        match self {
+            EncodeQuality::Default | EncodeQuality::Exhaustive => Algorithm::IterativeClusterFit,
+            EncodeQuality::Cluster => Algorithm::ClusterFit,
+            EncodeQuality::Fast => Algorithm::RangeFit,
+        }
+    }
+}
+
+/// Squared errors of the blocks of an exhaustive encode, compared to the default one
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityReport {
+    pub blocks: usize,
+    /// Total squared error of the RGBA channels with `EncodeQuality::Default`
+    pub default_error: u64,
+    /// Total squared error of the blocks that were kept
+    pub error: u64,
+}
+
+impl QualityReport {
+    /// Returns by how much the squared error of a block was reduced on average.
+    pub fn average_improvement(&self) -> f64 {
+        if self.blocks == 0 {
+            return 0.0;
+        }
+        (self.default_error - self.error) as f64 / self.blocks as f64
+    }
+}
+
+/// Size of a mipmap before and after LZO compression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MipmapStats {
+    pub width: u16,
+    pub height: u16,
+    /// Size of the uncompressed data
+    pub original_size: usize,
+    /// Size of the data in the file
+    pub stored_size: usize,
+    /// Whether the data is LZO compressed
+    pub lzo: bool,
+}
+
+/// Share of the pixels that have to be transparent for `auto_paa_type` to pick DXT5, so a few
+/// stray pixels of an exporter don't
+pub const AUTO_ALPHA_THRESHOLD: f64 = 0.001;
+/// Whether `img2paa` compresses mipmaps without `--lzo`, also used by `Paa::try_from`
+pub const DEFAULT_COMPRESSION: bool = true;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum CompressionType {
     None = 0,
     LZO = 2,
 }
 
-#[derive(Debug)]
+/// LZO implementation used for mipmap data.
+///
+/// Exists so callers can swap out minilzo, e.g. to handle environments where it can't be
+/// initialized. Both methods should fail with `ErrorKind::Unsupported` if LZO isn't available at all.
+pub trait LzoCodec {
+    /// Compresses the data.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error>;
+    /// Decompresses the data, which is expected to decompress to exactly `size` bytes.
+    fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, std::io::Error>;
+}
+
+/// minilzo, initialized once per thread on first use
+///
+/// `lzo_init` only checks the platform and minilzo keeps no global state, but an `LZO` owns the
+/// work memory compression writes to, so one instance must not be used by two threads at once.
+/// Each thread gets its own instead of all of them waiting for a shared one.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MiniLzo;
+
+thread_local! {
+    static MINILZO: RefCell<Option<Result<LZO, String>>> = const { RefCell::new(None) };
+}
+
+impl MiniLzo {
+    /// Calls `f` with the instance of this thread. `f` must not use `MiniLzo` itself.
+    fn with_instance<T>(f: impl FnOnce(&mut LZO) -> Result<T, std::io::Error>) -> Result<T, std::io::Error> {
+        MINILZO.with(|instance| {
+            let mut instance = instance.borrow_mut();
+            match instance.get_or_insert_with(|| LZO::init().map_err(|e| e.to_string())) {
+                Ok(lzo) => f(lzo),
+                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("LZO initialization failed: {}", e)))
+            }
+        })
+    }
+}
+
+impl LzoCodec for MiniLzo {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        Self::with_instance(|lzo| lzo.compress(data).map_err(|e| coded_error!("E0409", "LZO compression failed: {}", e)))
+    }
+
+    fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, std::io::Error> {
+        Self::with_instance(|lzo| lzo.decompress_safe(data, size).map_err(|e| coded_error!("E0409", "LZO decompression failed: {}", e)))
+    }
+}
+
+/// Already encoded data of one mipmap level, e.g. DXT blocks produced on the GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawMipmap {
+    pub width: u16,
+    pub height: u16,
+    /// Encoded data without LZO compression
+    pub data: Vec<u8>,
+}
+
+/// Block compression format of the data of a DXT PAA, as GPU APIs name it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BcFormat {
+    /// DXT1, 8 bytes per block of 4x4 pixels
+    Bc1,
+    /// DXT3, 16 bytes per block
+    Bc2,
+    /// DXT5, 16 bytes per block
+    Bc3,
+}
+
+/// Block compressed data of one mipmap level, ready to be uploaded to the GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBlocks<'a> {
+    pub format: BcFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Blocks from left to right and top to bottom, borrowed from the PAA unless the level was
+    /// LZO compressed
+    pub data: Cow<'a, [u8]>,
+}
+
+/// Options for composing a PAA from raw mipmaps
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RawMipmapOptions {
+    /// LZO compress the mipmaps where it makes them smaller
+    pub compress: bool,
+}
+
+/// Options for decoding mipmaps
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DecodeOptions {
+    /// Fail on DXT mipmaps with too little data instead of filling the rest with zeros
+    pub strict: bool,
+    /// Mirror the decoded image top to bottom, undoing `EncodeOptions::flip_vertical`
+    pub flip_vertical: bool,
+    /// Mirror the decoded image left to right
+    pub flip_horizontal: bool,
+}
+
+#[derive(Debug, Clone)]
 struct Tagg {
     name: [u8; 4],
     data_size: u32,
@@ -50,6 +302,8 @@ struct MipMap {
     width: u16,
     height: u16,
     data: Vec<u8>,
+    /// Whether `data` is LZO compressed and known to decompress to the full size
+    lzo: bool,
 }
 
 #[derive(Debug)]
@@ -57,458 +311,2181 @@ pub struct Paa {
     pub paa_type: PaaType,
     taggs: Vec<Tagg>,
     mipmaps: Vec<MipMap>,
+    canonical_layout: bool,
+    compressed: bool,
 }
 
-impl Paa {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let mut file = File::open(path)?;
-        Self::from_reader(&mut file)
-    }
+/// Name of the tagg with the tool that wrote a texture, `TOOL` reversed like the names of the
+/// engine's own taggs. The engine looks up the taggs it uses by name, so it skips this one.
+pub const TOOL_TAGG: [u8; 4] = *b"LOOT";
 
-    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, std::io::Error> {
-        let paa_type_raw = reader.read_u16::<LittleEndian>()?;
-        let paa_type = PaaType::from_u16(paa_type_raw)
-            .ok_or_else(|| error!("Unknown PAA type: 0x{:04X}", paa_type_raw))?;
+/// Returns the tool identification `EncodeOptions::tag_tool` writes.
+pub fn tool_name() -> String {
+    format!("armake2 {}", env!("CARGO_PKG_VERSION"))
+}
 
-        let mut taggs = Vec::new();
-        loop {
-            let mut tagg_name = [0u8; 4];
-            match reader.read_exact(&mut tagg_name) {
-                Ok(_) => {},
-                Err(_) => break,  // End of TAGGs
-            }
+/// How far past the regular tagg section the reader looks for misplaced taggs
+const TAGG_SCAN_WINDOW: u64 = 4096;
 
-            // Check if this is a TAGG (might be stored as GGAT reversed)
-            if &tagg_name != b"GGAT" && &tagg_name != b"TAGG" {
-                reader.seek(SeekFrom::Current(-4))?;
-                break;
-            }
+/// Entries of the offset tagg the engine reads, the ones without a mipmap are 0
+const OFFSET_TABLE_ENTRIES: usize = 16;
 
-            let mut tagg_sig = [0u8; 4];
-            reader.read_exact(&mut tagg_sig)?;
-            let data_size = reader.read_u32::<LittleEndian>()?;
+/// Written between the taggs and the first mipmap, the size of an empty palette
+const EMPTY_PALETTE: [u8; 2] = [0; 2];
 
-            let mut data = vec![0u8; data_size as usize];
-            reader.read_exact(&mut data)?;
+/// Written after the last mipmap, the header of an empty mipmap and two more zero bytes like
+/// armake writes it
+const MIPMAP_TERMINATOR: [u8; 6] = [0; 6];
 
-            taggs.push(Tagg {
-                name: tagg_sig,
-                data_size,
-                data,
-            });
-        }
+/// Taggs that are written first and in this order, the others follow in their own order
+const LEADING_TAGGS: [[u8; 4]; 3] = [*b"CGVA", *b"CXAM", *b"GALF"];
 
-        let mut offset_table = Vec::new();
-        for tagg in &taggs {
-            if &tagg.name == b"SFFO" {
-                let mut cursor = Cursor::new(&tagg.data);
-                let num_mipmaps = tagg.data_size / 4;
-                for _ in 0..num_mipmaps {
-                    offset_table.push(cursor.read_u32::<LittleEndian>()?);
-                }
-                break;
-            }
-        }
+/// Channel sources of the SWIZ tagg that leave every channel where it is
+const IDENTITY_SWIZZLE: [u8; 4] = [0, 1, 2, 3];
 
-        let mut mipmaps = Vec::new();
-        if !offset_table.is_empty() {
-            for i in 0..offset_table.len() {
-                if offset_table[i] == 0 {
-                    continue;  // Skip mipmaps with null offset
-                }
-                reader.seek(SeekFrom::Start(offset_table[i] as u64))?;
-
-                let width_raw = reader.read_u16::<LittleEndian>()?;
-                let height_raw = reader.read_u16::<LittleEndian>()?;
-
-                // The high bit (0x8000) in width appears to be a compression flag
-                // Mask it off to get the actual width
-                let width = width_raw & 0x7FFF;
-                let height = height_raw;
-                // Read 24-bit size (3 bytes)
-                let mut size_bytes = [0u8; 3];
-                reader.read_exact(&mut size_bytes)?;
-                let size = u32::from_le_bytes([size_bytes[0], size_bytes[1], size_bytes[2], 0]);
-
-                let actual_size = (size & 0xFFFFFF) as usize;
-                let expected_uncompressed = calculate_mipmap_size(paa_type, width, height);
-
-                // Check if data appears to be compressed
-                // The high bit in width (0x8000) or size field (0x800000) indicates LZO compression
-                let compression_type = if (width_raw & 0x8000) != 0 || (size & 0x800000) != 0 || actual_size < expected_uncompressed / 2 {
-                    CompressionType::LZO
-                } else {
-                    CompressionType::None
-                };
-
-                let mut data = vec![0u8; actual_size];
-                reader.read_exact(&mut data)?;
+/// Mipmap sides have to be smaller than this, the highest bit of the width flags compression.
+/// The encoder's halving chain therefore has at most 15 levels, read PAAs may list any number.
+pub const MAX_MIPMAP_DIMENSION: u32 = 0x8000;
 
-                if compression_type == CompressionType::LZO {
-                    let decompressed_size = calculate_mipmap_size(paa_type, width, height);
-
-                    let lzo = LZO::init().map_err(|_| error!("LZO init failed"))?;
-                    let result = lzo.decompress_safe(&data[..], decompressed_size);
-                    match result {
-                        Ok(decompressed_data) => {
-                            data = decompressed_data;
-                        },
-                        Err(_) => return Err(error!("LZO decompression failed"))
-                    }
-                }
+/// Returns the number of mipmap levels from the given size down to 1x1, halving both sides
+/// each level.
+pub fn mipmap_levels(width: u32, height: u32) -> usize {
+    (32 - width.max(height).max(1).leading_zeros()) as usize
+}
 
-                mipmaps.push(MipMap {
-                    width,
-                    height,
-                    data,
-                });
-            }
-        }
+/// Largest amount of mipmap data the 24-bit size field can hold
+pub const MAX_MIPMAP_DATA_SIZE: usize = 0xFFFFFF;
 
-        Ok(Paa {
-            paa_type,
-            taggs,
-            mipmaps,
-        })
+/// Returns an error if the stored data of the mipmap is too large for its size field. An
+/// uncompressed ARGB8888 mipmap reaches this at 2048x2048.
+fn check_mipmap_size(paa_type: PaaType, mipmap: &MipMap) -> Result<(), std::io::Error> {
+    if mipmap.data.len() > MAX_MIPMAP_DATA_SIZE {
+        let hint = if matches!(paa_type, PaaType::DXT1 | PaaType::DXT3 | PaaType::DXT5) { "Use smaller mipmaps" } else { "Enable LZO compression or use a DXT format" };
+        return Err(coded_error!("E0405", "Mipmap {}x{} has {} bytes of {:?} data, more than the {} bytes the size field of a PAA mipmap can hold. {}.",
+            mipmap.width, mipmap.height, mipmap.data.len(), paa_type, MAX_MIPMAP_DATA_SIZE, hint));
     }
+    Ok(())
+}
 
-    pub fn to_image(&self) -> Result<DynamicImage, std::io::Error> {
-        if self.mipmaps.is_empty() {
-            return Err(error!("No mipmaps found in PAA file"));
-        }
+/// Writes the header and data of a mipmap.
+fn write_mipmap<W: Write>(writer: &mut W, paa_type: PaaType, mipmap: &MipMap) -> Result<(), std::io::Error> {
+    check_mipmap_size(paa_type, mipmap)?;
 
-        let mipmap = &self.mipmaps[0];
-        let width = mipmap.width as u32;
-        let height = mipmap.height as u32;
+    // LZO compression is flagged by the high bit of the width
+    let is_compressed = mipmap.data.len() < calculate_mipmap_size(paa_type, mipmap.width, mipmap.height);
+    let width = if is_compressed { mipmap.width | 0x8000 } else { mipmap.width };
+    writer.write_u16::<LittleEndian>(width)?;
+    writer.write_u16::<LittleEndian>(mipmap.height)?;
 
-        let mut rgba_data = vec![0u8; (width * height * 4) as usize];
+    // Checked to fit into the 24 bits above
+    writer.write_u24::<LittleEndian>(mipmap.data.len() as u32)?;
+    writer.write_all(&mipmap.data)
+}
 
-        match self.paa_type {
-            PaaType::DXT1 => {
-                let expected_size = calculate_mipmap_size(self.paa_type, mipmap.width, mipmap.height);
-                if mipmap.data.len() != expected_size {
-                    eprintln!("Warning: DXT1 data size mismatch. Expected {} bytes, got {} bytes", expected_size, mipmap.data.len());
-                }
-                let format = Format::Bc1;
-                format.decompress(&mipmap.data, width as usize, height as usize, &mut rgba_data);
-            },
-            PaaType::DXT5 => {
-                let expected_size = calculate_mipmap_size(self.paa_type, mipmap.width, mipmap.height);
-                if mipmap.data.len() != expected_size {
-                    eprintln!("Warning: DXT5 data size mismatch. Expected {} bytes, got {} bytes", expected_size, mipmap.data.len());
+fn mipmap_area(mipmap: &MipMap) -> u32 {
+    mipmap.width as u32 * mipmap.height as u32
+}
+
+fn is_offset_tagg(tagg: &Tagg) -> bool {
+    &tagg.name == b"SFFO" && tagg.data.chunks(4).any(|c| c.iter().any(|b| *b != 0))
+}
+
+/// A mipmap as stored in the file, before decompressing it
+struct StoredMipmap {
+    index: usize,
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+    expected_size: usize,
+    /// Whether the data is LZO compressed, and if so whether that was only guessed from its size
+    lzo: Option<bool>,
+}
+
+/// Returns whether mipmap data of the stored size appears to be LZO compressed.
+fn looks_compressed(flagged: bool, size: u32, expected_size: usize) -> bool {
+    // The high bit in width/height (0x8000) or size field (0x800000) indicates LZO compression,
+    // unless the size is exactly that of the uncompressed data, which reaches 0x800000 at 2048x1024 ARGB8888
+    flagged || (size as usize != expected_size && ((size & 0x800000) != 0 || (size as usize) < expected_size / 2))
+}
+
+/// Reads the mipmap at the current position, the one with the given index in the offsets.
+fn read_stored_mipmap<R: Read>(reader: &mut R, paa_type: PaaType, index: usize) -> Result<StoredMipmap, std::io::Error> {
+    let width_raw = reader.read_u16::<LittleEndian>()?;
+    let height_raw = reader.read_u16::<LittleEndian>()?;
+
+    // The high bit (0x8000) in width appears to be a compression flag
+    // Mask it off to get the actual width. Some old exporters set it on the
+    // height instead, so treat both fields the same way.
+    let width = width_raw & 0x7FFF;
+    let height = height_raw & 0x7FFF;
+    let flagged = ((width_raw | height_raw) & 0x8000) != 0;
+
+    if (height_raw & 0x8000) != 0 {
+        warning(format!("Mipmap {} ({}x{}) has the compression flag set on its height instead of its width.", index, width, height),
+            Some("nonstandard-compression-flag"), (None, None));
+    }
+    let size = reader.read_u24::<LittleEndian>()?;
+
+    let actual_size = size as usize;
+    let expected_uncompressed = calculate_mipmap_size(paa_type, width, height);
+
+    // Writers that don't check the size store only the lower 24 bits of larger mipmaps
+    if !flagged && expected_uncompressed > MAX_MIPMAP_DATA_SIZE && actual_size == expected_uncompressed & MAX_MIPMAP_DATA_SIZE {
+        return Err(coded_error!("E0404", "Mipmap {} ({}x{}) has a size of {} bytes, but its {} bytes of uncompressed {:?} data don't fit into the size field. The file was written with a truncated size and is corrupt.",
+            index, width, height, actual_size, expected_uncompressed, paa_type));
+    }
+    if flagged && actual_size == 0 {
+        return Err(coded_error!("E0404", "Mipmap {} ({}x{}) is flagged as LZO compressed but has no data.", index, width, height));
+    }
+
+    let compression_type = if looks_compressed(flagged, size, expected_uncompressed) {
+        CompressionType::LZO
+    } else {
+        CompressionType::None
+    };
+
+    let mut data = vec![0u8; actual_size];
+    reader.read_exact(&mut data)?;
+
+    Ok(StoredMipmap {
+        index,
+        width,
+        height,
+        data,
+        expected_size: expected_uncompressed,
+        lzo: (compression_type == CompressionType::LZO).then_some(!flagged && (size & 0x800000) == 0),
+    })
+}
+
+/// Turns a mipmap as stored into one to decode, decompressing it with the codec. Also returns
+/// whether it was LZO compressed.
+fn load_mipmap(stored: StoredMipmap, codec: Option<&dyn LzoCodec>) -> Result<(MipMap, bool), std::io::Error> {
+    let StoredMipmap { index, width, height, mut data, expected_size, lzo: guessed } = stored;
+
+    // Without a codec, compressed data is decompressed in strips when decoding, or up front with
+    // `decompress_levels`, which both fail for invalid data. Only compression that was guessed
+    // from the size is checked here, since the data may just be truncated.
+    let mut is_lzo = false;
+    let mut compressed = false;
+    if guessed == Some(false) && codec.is_none() && data.len() < expected_size {
+        is_lzo = true;
+        compressed = true;
+    } else if let Some(guessed) = guessed {
+        match decompress_mipmap(&data, expected_size, codec) {
+            Ok(decompressed) => {
+                compressed = true;
+                match decompressed {
+                    Some(decompressed) => data = decompressed,
+                    None => is_lzo = true
                 }
-                let format = Format::Bc3;
-                format.decompress(&mipmap.data, width as usize, height as usize, &mut rgba_data);
             },
-            _ => {
-                return Err(error!("Unsupported PAA type for conversion: {:?}", self.paa_type));
-            }
+            // Compression was only assumed from the size, the data may just be truncated
+            Err(e) if guessed && e.kind() != std::io::ErrorKind::Unsupported => {},
+            Err(e) => return Err(e).prepend_error(format!("Failed to decompress mipmap {} ({}x{}):", index, width, height))
         }
+    }
 
-        let img_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, rgba_data)
-            .ok_or_else(|| error!("Failed to create image buffer"))?;
+    Ok((MipMap {
+        width,
+        height,
+        data,
+        lzo: is_lzo,
+    }, compressed))
+}
 
-        Ok(DynamicImage::ImageRgba8(img_buffer))
+/// Compressed bytes of a PAA from which its levels are decompressed in parallel. Below that,
+/// starting the threads takes longer than decompressing.
+const PARALLEL_LZO_MIN_SIZE: usize = 0x10000;
+
+/// Decompresses the data of a mipmap with the codec. Without one, data that is smaller than the
+/// uncompressed size is only verified and `None` is returned.
+fn decompress_mipmap(data: &[u8], size: usize, codec: Option<&dyn LzoCodec>) -> Result<Option<Vec<u8>>, std::io::Error> {
+    match codec {
+        Some(codec) => codec.decompress(data, size).map(Some),
+        None if data.len() < size => lzo::verify(data, size).map(|_| None),
+        None => lzo::decompress(data, size).map(Some)
     }
+}
 
-    pub fn from_image(img: &DynamicImage, paa_type: PaaType, use_compression: bool) -> Result<Self, std::io::Error> {
-        let rgba = img.to_rgba8();
-        let width = rgba.width();
-        let height = rgba.height();
+fn has_offsets(taggs: &[Tagg]) -> bool {
+    taggs.iter().any(is_offset_tagg)
+}
 
-        let mut taggs = Vec::new();
+fn tool_of(taggs: &[Tagg]) -> Option<String> {
+    taggs.iter().find(|t| t.name == TOOL_TAGG).map(|t| String::from_utf8_lossy(&t.data).into_owned())
+}
 
-        let avg_color = calculate_average_color(&rgba);
-        taggs.push(Tagg {
-            name: *b"CGVA",
-            data_size: 4,
-            data: avg_color.to_vec(),
-        });
+/// Reads consecutive taggs until something that isn't a tagg or the end of the input is reached.
+fn read_taggs<R: Read + Seek>(reader: &mut R, taggs: &mut Vec<Tagg>) -> Result<(), std::io::Error> {
+    loop {
+        let mut tagg_name = [0u8; 4];
+        match reader.read_exact(&mut tagg_name) {
+            Ok(_) => {},
+            Err(_) => break,  // End of TAGGs
+        }
+
+        // Check if this is a TAGG (might be stored as GGAT reversed)
+        if &tagg_name != b"GGAT" && &tagg_name != b"TAGG" {
+            reader.seek(SeekFrom::Current(-4))?;
+            break;
+        }
+
+        let mut tagg_sig = [0u8; 4];
+        reader.read_exact(&mut tagg_sig)?;
+        let data_size = reader.read_u32::<LittleEndian>()?;
+
+        let mut data = vec![0u8; data_size as usize];
+        reader.read_exact(&mut data)?;
 
-        let max_color = calculate_maximum_color(&rgba);
         taggs.push(Tagg {
-            name: *b"CXAM",
-            data_size: 4,
-            data: max_color.to_vec(),
+            name: tagg_sig,
+            data_size,
+            data,
         });
+    }
 
-        let mut mipmaps = Vec::new();
-        let mut current_img = img.clone();
-        let mut mipmap_width = width;
-        let mut mipmap_height = height;
+    Ok(())
+}
 
-        while mipmap_width >= 1 && mipmap_height >= 1 && mipmaps.len() < 15 {
-            let rgba = current_img.to_rgba8();
+/// Type, color taggs and mipmap sizes of a PAA, read without its mipmap data
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaaHeader {
+    pub paa_type: PaaType,
+    /// Content of the CGVA tagg
+    pub average_color: Option<[u8; 4]>,
+    /// Content of the CXAM tagg
+    pub max_color: Option<[u8; 4]>,
+    /// Value of the FLAG tagg
+    pub flag: Option<u32>,
+    /// Content of the `TOOL_TAGG`
+    pub tool: Option<String>,
+    /// Name and data size of each tagg in the order of the file, including the mipmap offsets
+    pub taggs: Vec<(String, u32)>,
+    /// Width, height and offset in the file of each mipmap
+    pub mipmaps: Vec<(u16, u16, u32)>,
+    /// Whether any mipmap appears to be LZO compressed, judged from its flag and size without
+    /// checking the data
+    pub compressed: bool,
+    pub file_size: u64,
+}
 
-            let compressed_data = match paa_type {
-                PaaType::DXT1 => compress_dxt1(&rgba)?,
-                PaaType::DXT5 => compress_dxt5(&rgba)?,
-                _ => return Err(error!("Unsupported PAA type: {:?}", paa_type)),
-            };
+impl PaaHeader {
+    /// Reads the type and taggs of a PAA and the size of each mipmap from its offset, skipping
+    /// the data. Unlike `Paa::from_reader`, it doesn't look for misplaced taggs.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<PaaHeader, std::io::Error> {
+        let paa_type_raw = reader.read_u16::<LittleEndian>()?;
+        let paa_type = PaaType::from_u16(paa_type_raw)
+            .ok_or_else(|| coded_error!("E0403", "Unknown PAA type: 0x{:04X}", paa_type_raw))?;
 
-            let mut final_data = compressed_data.clone();
-
-            if use_compression && compressed_data.len() > 128 {
-                let mut lzo = match LZO::init() {
-                    Ok(lzo) => lzo,
-                    Err(_) => continue,  // Skip compression if LZO init fails
-                };
-                let result = lzo.compress(&compressed_data[..]);
-                if let Ok(lzo_compressed) = result {
-                    if lzo_compressed.len() < compressed_data.len() {
-                        final_data = lzo_compressed;
-                    }
-                }
+        let mut taggs = Vec::new();
+        read_taggs(reader, &mut taggs)?;
+        let color = |name: &[u8; 4]| taggs.iter()
+            .find(|t| &t.name == name && t.data.len() >= 4)
+            .map(|t| [t.data[0], t.data[1], t.data[2], t.data[3]]);
+        let average_color = color(b"CGVA");
+        let max_color = color(b"CXAM");
+        let flag = color(b"GALF").map(|c| LittleEndian::read_u32(&c));
+        let tool = tool_of(&taggs);
+
+        let offsets = taggs.iter().find(|t| is_offset_tagg(t))
+            .ok_or_else(|| coded_error!("E0404", "PAA has no mipmap offsets."))?;
+        let mut mipmaps = Vec::new();
+        let mut compressed = false;
+        for offset in offsets.data.chunks_exact(4).map(LittleEndian::read_u32).filter(|o| *o != 0) {
+            reader.seek(SeekFrom::Start(offset as u64))?;
+            let width_raw = reader.read_u16::<LittleEndian>()?;
+            let height_raw = reader.read_u16::<LittleEndian>()?;
+            let (width, height) = (width_raw & 0x7FFF, height_raw & 0x7FFF);
+            if width > 0 && height > 0 {
+                let size = reader.read_u24::<LittleEndian>()?;
+                compressed |= looks_compressed(((width_raw | height_raw) & 0x8000) != 0, size, calculate_mipmap_size(paa_type, width, height));
+                mipmaps.push((width, height, offset));
             }
+        }
 
-            mipmaps.push(MipMap {
-                width: mipmap_width as u16,
-                height: mipmap_height as u16,
-                data: final_data,
-            });
+        Ok(PaaHeader {
+            paa_type,
+            average_color,
+            max_color,
+            flag,
+            tool,
+            taggs: taggs.iter().map(|t| (String::from_utf8_lossy(&t.name).into_owned(), t.data_size)).collect(),
+            mipmaps,
+            compressed,
+            file_size: reader.seek(SeekFrom::End(0))?,
+        })
+    }
+}
 
-            if mipmap_width == 1 && mipmap_height == 1 {
-                break;
+impl Paa {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let mut file = File::open(&path)?;
+        Self::from_reader(&mut file).prepend_error(format!("Failed to read \"{}\":", path.as_ref().display()))
+    }
+
+    /// Reads a PAA, keeping LZO compressed mipmaps compressed until they are decoded.
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, std::io::Error> {
+        Self::read(reader, None)
+    }
+
+    /// Reads a PAA like `from_reader`, decompressing mipmaps with the given LZO codec right away.
+    pub fn from_reader_with_lzo<R: Read + Seek>(reader: &mut R, lzo: &dyn LzoCodec) -> Result<Self, std::io::Error> {
+        Self::read(reader, Some(lzo))
+    }
+
+    fn read<R: Read + Seek>(reader: &mut R, codec: Option<&dyn LzoCodec>) -> Result<Self, std::io::Error> {
+        let paa_type_raw = reader.read_u16::<LittleEndian>()?;
+        let paa_type = PaaType::from_u16(paa_type_raw)
+            .ok_or_else(|| coded_error!("E0403", "Unknown PAA type: 0x{:04X}", paa_type_raw))?;
+
+        let mut taggs = Vec::new();
+        read_taggs(reader, &mut taggs)?;
+
+        // Some old tools write the taggs after filler bytes or a placeholder offset tagg, so look
+        // a bit further ahead before giving up on finding the offsets
+        let mut canonical_layout = true;
+        let mut scanned: u64 = 0;
+        while !has_offsets(&taggs) && scanned < TAGG_SCAN_WINDOW {
+            let start = reader.stream_position()?;
+            let mut window: Vec<u8> = Vec::new();
+            reader.by_ref().take(TAGG_SCAN_WINDOW - scanned).read_to_end(&mut window)?;
+
+            match window.windows(4).position(|w| w == b"GGAT" || w == b"TAGG") {
+                Some(pos) => {
+                    reader.seek(SeekFrom::Start(start + pos as u64))?;
+                    read_taggs(reader, &mut taggs)?;
+                    canonical_layout = false;
+                    scanned += pos as u64 + 4;
+                },
+                None => break
             }
+        }
+
+        if !canonical_layout && has_offsets(&taggs) {
+            warning("PAA taggs were found after non-tagg data. The file was probably created by a nonstandard tool and will be normalized when written.",
+                Some("nonstandard-tagg-order"), (None, None));
+        }
 
-            mipmap_width = (mipmap_width / 2).max(1);
-            mipmap_height = (mipmap_height / 2).max(1);
+        let mut offset_table = Vec::new();
+        if let Some(tagg) = taggs.iter().find(|t| is_offset_tagg(t)) {
+            let mut cursor = Cursor::new(&tagg.data);
+            let num_mipmaps = tagg.data_size / 4;
+            for _ in 0..num_mipmaps {
+                offset_table.push(cursor.read_u32::<LittleEndian>()?);
+            }
+        }
+        // Only keep the offsets that were actually used, they are rewritten on write
+        taggs.retain(|t| &t.name != b"SFFO");
 
-            if mipmap_width >= 4 || mipmap_height >= 4 {
-                current_img = current_img.resize_exact(
-                    mipmap_width,
-                    mipmap_height,
-                    image::imageops::FilterType::Lanczos3
-                );
+        let mut stored: Vec<StoredMipmap> = Vec::new();
+        for (index, offset) in offset_table.iter().enumerate() {
+            if *offset == 0 {
+                continue;  // Skip mipmaps with null offset
             }
+            reader.seek(SeekFrom::Start(*offset as u64))?;
+            stored.push(read_stored_mipmap(reader, paa_type, index)?);
+        }
+
+        let mut mipmaps = Vec::new();
+        let mut compressed = false;
+        for mipmap in stored {
+            let (mipmap, lzo) = load_mipmap(mipmap, codec)?;
+            compressed |= lzo;
+            mipmaps.push(mipmap);
+        }
+
+        // Some exporters write the offsets in the wrong order, but consumers expect the base level first
+        if mipmaps.windows(2).any(|w| mipmap_area(&w[0]) < mipmap_area(&w[1])) {
+            warning("PAA mipmaps are not stored from largest to smallest. They will be sorted and written in the correct order.",
+                Some("unordered-mipmaps"), (None, None));
+            mipmaps.sort_by_key(|m| std::cmp::Reverse(mipmap_area(m)));
         }
 
         Ok(Paa {
             paa_type,
             taggs,
             mipmaps,
+            canonical_layout,
+            compressed,
         })
     }
 
-    pub fn write<W: Write + Seek>(&self, writer: &mut W) -> Result<(), std::io::Error> {
-        writer.write_u16::<LittleEndian>(self.paa_type as u16)?;
+    /// Returns whether the file this was read from had its taggs in a nonstandard place. `write`
+    /// always produces the standard layout.
+    pub fn has_nonstandard_layout(&self) -> bool {
+        !self.canonical_layout
+    }
 
-        for tagg in &self.taggs {
-            writer.write_all(b"TAGG")?;
-            writer.write_all(&tagg.name)?;
-            writer.write_u32::<LittleEndian>(tagg.data_size)?;
-            writer.write_all(&tagg.data)?;
-        }
+    /// Returns whether any mipmap was LZO compressed when read or encoded.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
 
-        let _offset_tagg_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.write_all(b"TAGG")?;
-        writer.write_all(b"SFFO")?;
-        writer.write_u32::<LittleEndian>((self.mipmaps.len() * 4) as u32)?;
+    /// Returns the value of the FLAG tagg, 1 for interpolated alpha and 2 for alpha testing.
+    pub fn flag(&self) -> Option<u32> {
+        self.taggs.iter()
+            .find(|t| &t.name == b"GALF" && t.data.len() >= 4)
+            .map(|t| LittleEndian::read_u32(&t.data))
+    }
 
-        let mut offset_positions = Vec::new();
-        for _ in 0..self.mipmaps.len() {
-            offset_positions.push(writer.seek(SeekFrom::Current(0))?);
-            writer.write_u32::<LittleEndian>(0)?;
-        }
+    /// Returns the channel sources of the SWIZ tagg in A, R, G, B order, see `parse_swizzle`.
+    pub fn swizzle(&self) -> Option<[u8; 4]> {
+        self.taggs.iter()
+            .find(|t| &t.name == b"ZIWS" && t.data.len() >= 4)
+            .map(|t| [t.data[0], t.data[1], t.data[2], t.data[3]])
+    }
 
-        let mut offsets = Vec::new();
-        for mipmap in &self.mipmaps {
-            offsets.push(writer.seek(SeekFrom::Current(0))? as u32);
+    /// Returns the content of the `TOOL_TAGG`, the tool that wrote the texture if it says so.
+    pub fn tool(&self) -> Option<String> {
+        tool_of(&self.taggs)
+    }
 
-            writer.write_u16::<LittleEndian>(mipmap.width)?;
-            writer.write_u16::<LittleEndian>(mipmap.height)?;
+    /// Sets the `TOOL_TAGG`, or removes it with `None`. Other taggs don't change.
+    pub fn set_tool(&mut self, tool: Option<&str>) -> Result<(), std::io::Error> {
+        self.taggs.retain(|t| t.name != TOOL_TAGG);
+        if let Some(tool) = tool {
+            self.taggs.push(Tagg { name: TOOL_TAGG, data_size: field_u32(tool.len(), "Tool tagg size")?, data: tool.as_bytes().to_vec() });
+        }
+        Ok(())
+    }
 
-            let is_compressed = mipmap.data.len() < calculate_mipmap_size(self.paa_type, mipmap.width, mipmap.height);
-            let size = if is_compressed {
-                0x80000000 | mipmap.data.len() as u32
-            } else {
-                mipmap.data.len() as u32
-            };
-            // Write 24-bit size (3 bytes)
-            let size_bytes = size.to_le_bytes();
-            writer.write_all(&size_bytes[0..3])?;
-            writer.write_all(&mipmap.data)?;
+    pub fn to_image(&self) -> Result<DynamicImage, std::io::Error> {
+        self.to_image_with_options(&DecodeOptions::default())
+    }
+
+    pub fn to_image_with_options(&self, options: &DecodeOptions) -> Result<DynamicImage, std::io::Error> {
+        if self.mipmaps.is_empty() {
+            return Err(coded_error!("E0410", "No mipmaps found in PAA file"));
         }
 
-        for (i, offset_pos) in offset_positions.iter().enumerate() {
-            writer.seek(SeekFrom::Start(*offset_pos))?;
-            writer.write_u32::<LittleEndian>(offsets[i])?;
+        self.to_image_level_with_options(0, options)
+    }
+
+    /// Decompresses the LZO compressed data of the mipmaps with the given indices now and keeps it,
+    /// so decoding them doesn't decompress them again. The levels are independent, so more than
+    /// one is decompressed in parallel, each thread with its own minilzo instance.
+    pub fn decompress_levels(&mut self, levels: &[usize]) -> Result<(), std::io::Error> {
+        for index in levels {
+            self.mipmap(*index)?;
         }
 
+        let paa_type = self.paa_type;
+        let pending: Vec<(usize, &MipMap)> = self.mipmaps.iter().enumerate()
+            .filter(|(index, mipmap)| mipmap.lzo && levels.contains(index))
+            .collect();
+        let size: usize = pending.iter().map(|(_, m)| m.data.len()).sum();
+        let decompress = |(index, mipmap): (usize, &MipMap)| {
+            MiniLzo.decompress(&mipmap.data, calculate_mipmap_size(paa_type, mipmap.width, mipmap.height))
+                .prepend_error(format!("Failed to decompress mipmap {} ({}x{}):", index, mipmap.width, mipmap.height))
+                .map(|data| (index, data))
+        };
+        let decompressed: Vec<Result<(usize, Vec<u8>), std::io::Error>> = if pending.len() > 1 && size >= PARALLEL_LZO_MIN_SIZE {
+            parallel::map_collect(pending, decompress)
+        } else {
+            pending.into_iter().map(decompress).collect()
+        };
+
+        for result in decompressed {
+            let (index, data) = result?;
+            self.mipmaps[index].data = data;
+            self.mipmaps[index].lzo = false;
+        }
         Ok(())
     }
 
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
-        let mut file = File::create(path)?;
-        self.write(&mut file)
+    /// Returns the number of mipmaps, the first one being the full size image.
+    pub fn mipmap_count(&self) -> usize {
+        self.mipmaps.len()
     }
-}
 
-fn calculate_mipmap_size(paa_type: PaaType, width: u16, height: u16) -> usize {
-    let blocks_x = ((width + 3) / 4) as usize;
-    let blocks_y = ((height + 3) / 4) as usize;
-
-    match paa_type {
-        PaaType::DXT1 => blocks_x * blocks_y * 8,
-        PaaType::DXT5 => blocks_x * blocks_y * 16,
-        _ => (width as usize) * (height as usize) * 4,
+    /// Returns the width and height of the mipmap of the given level, 0 being the full size image.
+    pub fn dimensions(&self, level: usize) -> Option<(u32, u32)> {
+        self.mipmaps.get(level).map(|m| (m.width as u32, m.height as u32))
     }
-}
-
-fn compress_dxt1(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<Vec<u8>, std::io::Error> {
-    let width = img.width() as usize;
-    let height = img.height() as usize;
-    let blocks_x = (width + 3) / 4;
-    let blocks_y = (height + 3) / 4;
 
-    let mut output = vec![0u8; blocks_x * blocks_y * 8];
-
-    let format = Format::Bc1;
-    let params = Params {
-        algorithm: Algorithm::IterativeClusterFit,
-        weights: [1.0, 1.0, 1.0],
-        weigh_colour_by_alpha: false,
-    };
+    /// Returns the mipmap with the given index, failing with the levels there are if it doesn't
+    /// exist.
+    fn mipmap(&self, index: usize) -> Result<&MipMap, std::io::Error> {
+        match self.mipmaps.get(index) {
+            Some(mipmap) => Ok(mipmap),
+            None if self.mipmaps.is_empty() => Err(coded_error!("E0410", "No mipmaps found in PAA file")),
+            None => Err(coded_error!("E0411", "PAA has no mipmap {}, only levels 0 to {}.", index, self.mipmaps.len() - 1))
+        }
+    }
 
-    for by in 0..blocks_y {
-        for bx in 0..blocks_x {
-            let mut rgba_block: [[u8; 4]; 16] = [[0; 4]; 16];
-
-            for py in 0..4 {
-                for px in 0..4 {
-                    let x = (bx * 4 + px).min(width - 1);
-                    let y = (by * 4 + py).min(height - 1);
-                    let pixel = img.get_pixel(x as u32, y as u32);
-
-                    let idx = py * 4 + px;
-                    rgba_block[idx][0] = pixel[0];
-                    rgba_block[idx][1] = pixel[1];
-                    rgba_block[idx][2] = pixel[2];
-                    rgba_block[idx][3] = pixel[3];
-                }
-            }
+    /// Decodes the mipmap of the given level. Decoding one of the small mipmaps is a lot faster
+    /// than decoding the full image when only an approximation is needed.
+    pub fn to_image_level(&self, level: usize) -> Result<DynamicImage, std::io::Error> {
+        self.to_image_level_with_options(level, &DecodeOptions::default())
+    }
 
-            let block_index = (by * blocks_x + bx) * 8;
-            format.compress_block_masked(
-                rgba_block,
-                0xFFFF,
-                params,
-                &mut output[block_index..block_index + 8]
-            );
+    /// Decodes like `to_image_level` with the options. The channels are moved back to where the
+    /// SWIZ tagg took them from, so e.g. normal maps come out like their source image.
+    pub fn to_image_level_with_options(&self, level: usize, options: &DecodeOptions) -> Result<DynamicImage, std::io::Error> {
+        let mut img = self.stored_image(level, options)?;
+        if let Some(swizzle) = self.swizzle() && swizzle != IDENTITY_SWIZZLE {
+            let DynamicImage::ImageRgba8(buffer) = &mut img else { unreachable!() };
+            unswizzle(buffer, swizzle);
         }
+        Ok(img)
     }
 
-    Ok(output)
-}
+    /// Decodes the mipmap with the channels as they are stored, ignoring the SWIZ tagg. This is
+    /// what is encoded again when the taggs are kept.
+    fn stored_image(&self, index: usize, options: &DecodeOptions) -> Result<DynamicImage, std::io::Error> {
+        decode_mipmap(self.paa_type, self.mipmap(index)?, options)
+    }
 
-fn compress_dxt5(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<Vec<u8>, std::io::Error> {
-    let width = img.width() as usize;
-    let height = img.height() as usize;
-    let blocks_x = (width + 3) / 4;
-    let blocks_y = (height + 3) / 4;
+    /// Decodes the mipmap with the given index from top to bottom in strips of 4 rows, calling
+    /// `callback` with the index of the first row and the RGBA pixels of each strip. Channels are
+    /// unswizzled like by `to_image_level`.
+    ///
+    /// Only one strip is decoded and decompressed at a time, so consumers that never need the
+    /// whole image, like thumbnailers or hashing, don't have to hold it in memory.
+    pub fn decode_strips<F: FnMut(u32, &[u8]) -> Result<(), std::io::Error>>(&self, index: usize, callback: F) -> Result<(), std::io::Error> {
+        self.decode_strips_with_options(index, &DecodeOptions::default(), callback)
+    }
 
-    let mut output = vec![0u8; blocks_x * blocks_y * 16];
+    /// Returns the block compressed data of the mipmap with the given index without decoding it,
+    /// e.g. to upload it to the GPU as is. Only this level is LZO decompressed if it has to be,
+    /// and data of the wrong length is handled like when decoding. Fails for PAA types that
+    /// aren't block compressed.
+    pub fn raw_mipmap(&self, index: usize) -> Result<RawBlocks<'_>, std::io::Error> {
+        let format = match self.paa_type {
+            PaaType::DXT1 => BcFormat::Bc1,
+            PaaType::DXT3 => BcFormat::Bc2,
+            PaaType::DXT5 => BcFormat::Bc3,
+            _ => return Err(coded_error!("E0412", "{:?} PAAs aren't block compressed", self.paa_type))
+        };
+        let mipmap = self.mipmap(index)?;
+
+        let expected_size = calculate_mipmap_size(self.paa_type, mipmap.width, mipmap.height);
+        let data = if !mipmap.lzo && mipmap.data.len() == expected_size {
+            Cow::Borrowed(&mipmap.data[..])
+        } else {
+            let mut data = Vec::with_capacity(expected_size);
+            dxt_reader(self.paa_type, mipmap, &DecodeOptions::default())?.read_to_end(&mut data)?;
+            Cow::Owned(data)
+        };
+
+        Ok(RawBlocks { format, width: mipmap.width as u32, height: mipmap.height as u32, data })
+    }
 
-    let format = Format::Bc3;
-    let params = Params {
-        algorithm: Algorithm::IterativeClusterFit,
-        weights: [1.0, 1.0, 1.0],
-        weigh_colour_by_alpha: false,
-    };
+    /// Writes the DXT blocks of all mipmaps to a DDS as they are, DXT1 for BC1 and DXT5 for BC3.
+    /// Channels aren't unswizzled, since that would need the blocks to be encoded again. Fails for
+    /// PAA types that aren't block compressed and for mipmaps that don't halve in size from one
+    /// level to the next like a DDS mip chain.
+    pub fn to_dds<W: Write>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        self.to_dds_from_level(0, writer)
+    }
 
-    for by in 0..blocks_y {
-        for bx in 0..blocks_x {
-            let mut rgba_block: [[u8; 4]; 16] = [[0; 4]; 16];
-
-            for py in 0..4 {
-                for px in 0..4 {
-                    let x = (bx * 4 + px).min(width - 1);
-                    let y = (by * 4 + py).min(height - 1);
-                    let pixel = img.get_pixel(x as u32, y as u32);
-
-                    let idx = py * 4 + px;
-                    rgba_block[idx][0] = pixel[0];
-                    rgba_block[idx][1] = pixel[1];
-                    rgba_block[idx][2] = pixel[2];
-                    rgba_block[idx][3] = pixel[3];
-                }
+    /// Writes a DDS like `to_dds`, with the mipmap at the given index as its largest level.
+    pub fn to_dds_from_level<W: Write>(&self, level: usize, writer: &mut W) -> Result<(), std::io::Error> {
+        let first = self.raw_mipmap(level)?;
+        let format = match first.format {
+            BcFormat::Bc1 => D3DFormat::DXT1,
+            BcFormat::Bc2 => D3DFormat::DXT3,
+            BcFormat::Bc3 => D3DFormat::DXT5,
+        };
+        let (width, height) = (first.width, first.height);
+
+        let mut data: Vec<u8> = Vec::new();
+        for (i, index) in (level..self.mipmaps.len()).enumerate() {
+            let blocks = if i == 0 { first.clone() } else { self.raw_mipmap(index)? };
+            let expected = ((width >> i).max(1), (height >> i).max(1));
+            if (blocks.width, blocks.height) != expected {
+                return Err(coded_error!("E0413", "Mipmap {} is {}x{}, but DDS mipmaps halve in size, so it would have to be {}x{}.",
+                    index, blocks.width, blocks.height, expected.0, expected.1));
             }
-
-            let block_index = (by * blocks_x + bx) * 16;
-            format.compress_block_masked(
-                rgba_block,
-                0xFFFF,
-                params,
-                &mut output[block_index..block_index + 16]
-            );
+            data.extend_from_slice(&blocks.data);
         }
+
+        let mut dds = Dds::new_d3d(NewD3dParams {
+            height,
+            width,
+            depth: None,
+            format,
+            mipmap_levels: Some((self.mipmaps.len() - level) as u32),
+            caps2: None,
+        }).map_err(|e| coded_error!("E0413", "Failed to create DDS: {}", e))?;
+        dds.data = data;
+        dds.write(writer).map_err(|e| coded_error!("E0413", "Failed to write DDS: {}", e))
     }
 
-    Ok(output)
-}
+    /// Decodes in strips like `decode_strips`. Strips are always passed from top to bottom, so
+    /// `flip_vertical` isn't supported.
+    pub fn decode_strips_with_options<F: FnMut(u32, &[u8]) -> Result<(), std::io::Error>>(&self, index: usize, options: &DecodeOptions, mut callback: F) -> Result<(), std::io::Error> {
+        let mipmap = self.mipmap(index)?;
 
-fn calculate_average_color(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> [u8; 4] {
-    let mut r_sum = 0u64;
-    let mut g_sum = 0u64;
-    let mut b_sum = 0u64;
-    let mut a_sum = 0u64;
-    let pixel_count = (img.width() * img.height()) as u64;
+        if options.flip_vertical {
+            return Err(coded_error!("E0414", "Mipmaps decoded in strips can't be flipped vertically"));
+        }
+        let swizzle = self.swizzle().filter(|s| *s != IDENTITY_SWIZZLE);
+        if !options.flip_horizontal && swizzle.is_none() {
+            return decode_mipmap_strips(self.paa_type, mipmap, options, callback);
+        }
 
-    for pixel in img.pixels() {
-        r_sum += pixel[0] as u64;
-        g_sum += pixel[1] as u64;
-        b_sum += pixel[2] as u64;
-        a_sum += pixel[3] as u64;
+        let mut changed: Vec<u8> = Vec::new();
+        decode_mipmap_strips(self.paa_type, mipmap, options, |y, strip| {
+            changed.clear();
+            changed.extend_from_slice(strip);
+            flip_rgba(&mut changed, mipmap.width as u32, false, options.flip_horizontal);
+            if let Some(swizzle) = swizzle {
+                unswizzle(&mut changed, swizzle);
+            }
+            callback(y, &changed)
+        })
     }
 
-    [
-        (r_sum / pixel_count) as u8,
-        (g_sum / pixel_count) as u8,
-        (b_sum / pixel_count) as u8,
-        (a_sum / pixel_count) as u8,
-    ]
-}
+    /// Composes a PAA from mipmaps that are already encoded in the given type, without
+    /// re-encoding them.
+    ///
+    /// The data length of every level is checked against its size. The average and maximum
+    /// color taggs are computed from the smallest level only, which is cheap and close enough
+    /// for the game's purposes.
+    pub fn from_raw_mipmaps(paa_type: PaaType, mipmaps: Vec<RawMipmap>, options: &RawMipmapOptions) -> Result<Self, std::io::Error> {
+        if mipmaps.is_empty() {
+            return Err(coded_error!("E0415", "At least one mipmap is required"));
+        }
 
-fn calculate_maximum_color(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> [u8; 4] {
-    let mut max_r = 0u8;
-    let mut max_g = 0u8;
-    let mut max_b = 0u8;
-    let mut max_a = 0u8;
+        for (i, mipmap) in mipmaps.iter().enumerate() {
+            // The highest bit of the width flags LZO compression
+            if mipmap.width == 0 || mipmap.height == 0 || mipmap.width >= 0x8000 {
+                return Err(coded_error!("E0415", "Mipmap {} has an invalid size of {}x{}", i, mipmap.width, mipmap.height));
+            }
 
-    for pixel in img.pixels() {
-        max_r = max_r.max(pixel[0]);
-        max_g = max_g.max(pixel[1]);
-        max_b = max_b.max(pixel[2]);
-        max_a = max_a.max(pixel[3]);
-    }
+            let expected_size = calculate_mipmap_size(paa_type, mipmap.width, mipmap.height);
+            if mipmap.data.len() != expected_size {
+                return Err(coded_error!("E0415", "Mipmap {} ({}x{}) has {} bytes of data, expected {} bytes for {:?}",
+                    i, mipmap.width, mipmap.height, mipmap.data.len(), expected_size, paa_type));
+            }
+        }
 
-    [max_r, max_g, max_b, max_a]
-}
+        let mipmaps: Vec<MipMap> = mipmaps.into_iter().map(|m| MipMap { width: m.width, height: m.height, data: m.data, lzo: false }).collect();
 
-pub fn cmd_paa2img(source: &Path, target: &Path, force: bool) -> Result<(), std::io::Error> {
-    // Check if target exists and force flag is not set
-    if target.exists() && !force {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            format!("Output file '{}' already exists. Use -f/--force to overwrite.", target.display())
-        ));
-    }
-    let paa = Paa::from_file(source)?;
-    let img = paa.to_image()?;
-    img.save(target).map_err(|e| error!("Failed to save image: {}", e))?;
-    Ok(())
-}
+        let smallest = mipmaps.iter().min_by_key(|m| mipmap_area(m)).unwrap();
+        let stats = paaanalysis::analyze(&decode_mipmap(paa_type, smallest, &DecodeOptions::default())?);
+        let taggs = color_taggs(&stats);
+
+        let paa = Paa {
+            paa_type,
+            taggs,
+            mipmaps,
+            canonical_layout: true,
+            compressed: false,
+        };
 
-pub fn cmd_img2paa(source: &Path, target: &Path, paa_type: PaaType, compress: bool, force: bool) -> Result<(), std::io::Error> {
-    // Check if target exists and force flag is not set
-    if target.exists() && !force {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            format!("Output file '{}' already exists. Use -f/--force to overwrite.", target.display())
-        ));
+        let paa = if options.compress { paa.to_compressed(&MiniLzo) } else { paa };
+        for mipmap in &paa.mipmaps {
+            check_mipmap_size(paa_type, mipmap)?;
+        }
+        Ok(paa)
     }
-    let img = image::open(source).map_err(|e| error!("Failed to open image: {}", e))?;
-    let paa = Paa::from_image(&img, paa_type, compress)?;
-    paa.write_to_file(target)?;
-    Ok(())
-}
\ No newline at end of file
+
+    /// Reads a DDS with DXT1, DXT3 or DXT5 blocks and copies them into a PAA of the same type as
+    /// they are, without decoding and encoding them again. Every mipmap level the DDS has is
+    /// kept, even if the chain is shorter than the one `from_image` would generate. Like
+    /// `from_raw_mipmaps`, only the smallest level is decoded for the color taggs.
+    ///
+    /// DDS files with a DX10 header, cube maps and volume textures aren't supported.
+    pub fn from_dds<R: Read>(reader: R) -> Result<Self, std::io::Error> {
+        let dds = Dds::read(reader).map_err(|e| coded_error!("E0417", "Failed to read DDS: {}", e))?;
+        if let Some(header10) = &dds.header10 {
+            return Err(coded_error!("E0416", "DDS files with a DX10 header aren't supported, this one is {:?}. Save it as DXT1 or DXT5 with a legacy header.", header10.dxgi_format));
+        }
+        if dds.header.caps2.intersects(Caps2::CUBEMAP | Caps2::VOLUME) || dds.header.depth.is_some_and(|d| d > 1) {
+            return Err(coded_error!("E0416", "DDS cube maps and volume textures can't be converted to PAA"));
+        }
+
+        let paa_type = match dds.get_d3d_format() {
+            Some(D3DFormat::DXT1) => PaaType::DXT1,
+            Some(D3DFormat::DXT3) => PaaType::DXT3,
+            Some(D3DFormat::DXT5) => PaaType::DXT5,
+            Some(format) => return Err(coded_error!("E0416", "DDS files in {:?} can't be copied into a PAA, only DXT1, DXT3 and DXT5 ones.", format)),
+            None => return Err(coded_error!("E0416", "DDS has an unknown pixel format, only DXT1, DXT3 and DXT5 can be copied into a PAA."))
+        };
+
+        let (width, height) = (dds.get_width(), dds.get_height());
+        if width == 0 || height == 0 || width >= MAX_MIPMAP_DIMENSION || height >= MAX_MIPMAP_DIMENSION {
+            return Err(coded_error!("E0405", "DDS is {}x{}, PAA mipmaps can be at most {} pixels wide and high.", width, height, MAX_MIPMAP_DIMENSION - 1));
+        }
+
+        let mut mipmaps: Vec<RawMipmap> = Vec::new();
+        let mut offset = 0;
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let width = (width >> level).max(1) as u16;
+            let height = (height >> level).max(1) as u16;
+            let size = calculate_mipmap_size(paa_type, width, height);
+            let data = dds.data.get(offset..offset + size)
+                .ok_or_else(|| coded_error!("E0417", "DDS ends within mipmap {} ({}x{}), its data is {} bytes long.", level, width, height, dds.data.len()))?;
+            mipmaps.push(RawMipmap { width, height, data: data.to_vec() });
+            offset += size;
+
+            if width == 1 && height == 1 {
+                break;
+            }
+        }
+
+        Self::from_raw_mipmaps(paa_type, mipmaps, &RawMipmapOptions::default())
+    }
+
+    pub fn from_image(img: &DynamicImage, paa_type: PaaType, use_compression: bool) -> Result<Self, std::io::Error> {
+        Self::encode(img, paa_type, &EncodeOptions { lzo: compression_policy(use_compression), ..Default::default() }, &MiniLzo, None).map(|(paa, _)| paa)
+    }
+
+    /// Encodes an image like `from_image`, compressing mipmaps with the given LZO codec as the
+    /// options' policy says and calling `progress` after each mipmap level.
+    pub fn from_image_with_options(img: &DynamicImage, paa_type: PaaType, options: &EncodeOptions, lzo: &dyn LzoCodec, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<Self, std::io::Error> {
+        Self::from_image_with_report(img, paa_type, options, lzo, progress).map(|(paa, _)| paa)
+    }
+
+    /// Encodes like `from_image_with_options`. With `EncodeQuality::Exhaustive` and a DXT type,
+    /// also returns how much closer to the image the blocks of all mipmaps got.
+    pub fn from_image_with_report(img: &DynamicImage, paa_type: PaaType, options: &EncodeOptions, lzo: &dyn LzoCodec, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<(Self, Option<QualityReport>), std::io::Error> {
+        Self::encode(img, paa_type, options, lzo, progress)
+    }
+
+    /// Encodes an image like `from_image`, compressing mipmaps with the given LZO codec.
+    ///
+    /// If compression fails, the remaining mipmaps are stored uncompressed and a warning is shown.
+    pub fn from_image_with_lzo(img: &DynamicImage, paa_type: PaaType, use_compression: bool, lzo: &dyn LzoCodec) -> Result<Self, std::io::Error> {
+        Self::encode(img, paa_type, &EncodeOptions { lzo: compression_policy(use_compression), ..Default::default() }, lzo, None).map(|(paa, _)| paa)
+    }
+
+    /// Encodes an image like `from_image`, calling `progress` after each mipmap level.
+    pub fn from_image_with_progress(img: &DynamicImage, paa_type: PaaType, use_compression: bool, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<Self, std::io::Error> {
+        Self::encode(img, paa_type, &EncodeOptions { lzo: compression_policy(use_compression), ..Default::default() }, &MiniLzo, progress).map(|(paa, _)| paa)
+    }
+
+    fn encode(img: &DynamicImage, paa_type: PaaType, options: &EncodeOptions, lzo: &dyn LzoCodec, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<(Self, Option<QualityReport>), std::io::Error> {
+        let policy = options.lzo;
+        let width = img.width();
+        let height = img.height();
+        if width >= MAX_MIPMAP_DIMENSION || height >= MAX_MIPMAP_DIMENSION {
+            return Err(coded_error!("E0405", "Image is {}x{}, PAA mipmaps can be at most {} pixels wide and high.", width, height, MAX_MIPMAP_DIMENSION - 1));
+        }
+
+        let mut mipmaps = Vec::new();
+        let mut report: Option<QualityReport> = None;
+        let mut compress = policy != LzoPolicy::Never;
+        // Flipped once before the mipmaps are generated from it, which the taggs don't change
+        let mut current_img = working_image(img, paa_type, options.legacy_pipeline);
+        let taggs = color_taggs(&paaanalysis::analyze(&current_img));
+        if options.flip_vertical {
+            current_img = current_img.flipv();
+        }
+        if options.flip_horizontal {
+            current_img = current_img.fliph();
+        }
+        let num_mipmaps = mipmap_levels(width, height);
+
+        // Each level is resized from the one before it, so only the encoding can run in parallel
+        let mut levels: Vec<(u32, u32, DynamicImage)> = vec![(width, height, current_img)];
+        while levels.len() < num_mipmaps {
+            let (previous_width, previous_height, previous) = levels.last().unwrap();
+            let mipmap_width = (previous_width / 2).max(1);
+            let mipmap_height = (previous_height / 2).max(1);
+
+            // Block compressed formats pad the smallest mipmaps to 4x4 anyway
+            let img = if mipmap_width >= 4 || mipmap_height >= 4 || matches!(paa_type, PaaType::ARGB4444 | PaaType::ARGB1555 | PaaType::ARGB8888 | PaaType::AI88) {
+                previous.resize_exact(mipmap_width, mipmap_height, image::imageops::FilterType::Lanczos3)
+            } else {
+                previous.clone()
+            };
+            levels.push((mipmap_width, mipmap_height, img));
+        }
+
+        let sizes: Vec<(u32, u32)> = levels.iter().map(|(w, h, _)| (*w, *h)).collect();
+        let finished = Mutex::new(0);
+        let encoded: Result<Vec<_>, std::io::Error> = parallel::map_collect(levels, |(mipmap_width, mipmap_height, img)| {
+            // Converted to 8 bits only for encoding, the next level is resized from the original
+            let encoded = encode_level(&img, paa_type, options.quality)?;
+
+            if let Some(progress) = progress {
+                let mut finished = finished.lock().unwrap();
+                *finished += 1;
+                progress(Progress {
+                    current: *finished,
+                    total: num_mipmaps,
+                    label: format!("Mipmap {}x{}", mipmap_width, mipmap_height),
+                });
+            }
+            Ok(encoded)
+        });
+
+        // Compressed in order, since the adaptive policy stops after the first level that saves too little
+        for ((mipmap_width, mipmap_height), (compressed_data, level_report)) in sizes.into_iter().zip(encoded?) {
+            if let Some(level_report) = level_report {
+                let total = report.get_or_insert_with(QualityReport::default);
+                total.blocks += level_report.blocks;
+                total.default_error += level_report.default_error;
+                total.error += level_report.error;
+            }
+
+            let uncompressed_size = compressed_data.len();
+            let final_data = if compress {
+                let original_size = compressed_data.len();
+                let data = lzo_compress_mipmap(compressed_data, lzo, &mut compress);
+
+                // Mipmaps too small to be compressed don't say anything about the texture
+                if let LzoPolicy::Adaptive { min_savings } = policy && original_size > LZO_MIN_SIZE
+                    && (1.0 - data.len() as f32 / original_size as f32) * 100.0 < min_savings {
+                    compress = false;
+                }
+                data
+            } else {
+                compressed_data
+            };
+
+            let mipmap = MipMap {
+                width: mipmap_width as u16,
+                height: mipmap_height as u16,
+                lzo: final_data.len() < uncompressed_size,
+                data: final_data,
+            };
+            check_mipmap_size(paa_type, &mipmap)?;
+            mipmaps.push(mipmap);
+        }
+
+        let mut paa = Paa {
+            paa_type,
+            taggs,
+            compressed: mipmaps.iter().any(|m| m.data.len() < calculate_mipmap_size(paa_type, m.width, m.height)),
+            mipmaps,
+            canonical_layout: true,
+        };
+        if options.tag_tool {
+            paa.set_tool(Some(&tool_name()))?;
+        }
+        Ok((paa, report))
+    }
+
+    /// Returns a copy with every uncompressed mipmap LZO compressed where that makes it smaller,
+    /// exactly like encoding with compression enabled would.
+    pub(crate) fn to_compressed(&self, lzo: &dyn LzoCodec) -> Paa {
+        let mut compress = true;
+        let mipmaps: Vec<MipMap> = self.mipmaps.iter().map(|m| {
+            let uncompressed = m.data.len() >= calculate_mipmap_size(self.paa_type, m.width, m.height);
+            let data = if uncompressed && compress { lzo_compress_mipmap(m.data.clone(), lzo, &mut compress) } else { m.data.clone() };
+            MipMap {
+                width: m.width,
+                height: m.height,
+                lzo: m.lzo || data.len() < m.data.len(),
+                data,
+            }
+        }).collect();
+
+        Paa {
+            paa_type: self.paa_type,
+            taggs: self.taggs.clone(),
+            compressed: mipmaps.iter().any(|m| m.data.len() < calculate_mipmap_size(self.paa_type, m.width, m.height)),
+            mipmaps,
+            canonical_layout: self.canonical_layout,
+        }
+    }
+
+    /// Returns a copy whose largest side is at most `max_size`, or `None` if the texture already is
+    /// that small. The mipmaps that are too large are dropped if there is a small enough one,
+    /// otherwise the base mipmap is resized to the largest power of two sizes that fit and encoded
+    /// again. Type, taggs and compression are kept either way.
+    pub fn downscaled(&self, max_size: u32) -> Result<Option<Paa>, std::io::Error> {
+        let mut mipmaps: Vec<&MipMap> = self.mipmaps.iter().collect();
+        mipmaps.sort_by_key(|m| std::cmp::Reverse(mipmap_area(m)));
+        let fits = |m: &MipMap| u32::from(m.width.max(m.height)) <= max_size;
+
+        match mipmaps.first() {
+            None => return Err(coded_error!("E0410", "No mipmaps found in PAA file")),
+            Some(base) if fits(base) => return Ok(None),
+            _ => {}
+        }
+
+        if let Some(index) = mipmaps.iter().position(|m| fits(m)) {
+            let paa = Paa {
+                paa_type: self.paa_type,
+                taggs: self.taggs.clone(),
+                mipmaps: mipmaps[index..].iter().map(|m| MipMap { width: m.width, height: m.height, data: m.data.clone(), lzo: m.lzo }).collect(),
+                canonical_layout: self.canonical_layout,
+                compressed: false,
+            };
+            return Ok(Some(if self.compressed { paa.to_compressed(&MiniLzo) } else { paa }));
+        }
+
+        // Rounded down to powers of two, which the engine requires
+        let img = self.stored_image(0, &DecodeOptions::default())?;
+        let scale = max_size as f32 / img.width().max(img.height()) as f32;
+        let power_of_two = |size: f32| 1u32 << (31 - (size as u32).max(1).leading_zeros());
+        let width = power_of_two(img.width() as f32 * scale);
+        let height = power_of_two(img.height() as f32 * scale);
+        let img = img.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
+
+        let mut paa = Paa::from_image(&img, self.paa_type, self.compressed)?;
+        paa.keep_taggs(&self.taggs);
+        Ok(Some(paa))
+    }
+
+    /// Returns the texture scaled to fit into `max_dim` pixels on both sides, for previews. Only
+    /// the smallest mipmap that is still at least that large is decoded, or the base level if
+    /// none is, and it is downsized if it is larger.
+    pub fn thumbnail(&self, max_dim: u32) -> Result<DynamicImage, std::io::Error> {
+        if max_dim == 0 {
+            return Err(coded_error!("E0901", "Thumbnails have to be at least 1 pixel large."));
+        }
+
+        let index = (0..self.mipmaps.len())
+            .rev()
+            .find(|i| self.dimensions(*i).is_some_and(|(w, h)| w.max(h) >= max_dim))
+            .unwrap_or(0);
+        let img = self.to_image_level(index)?;
+
+        if img.width().max(img.height()) > max_dim {
+            Ok(img.thumbnail(max_dim, max_dim))
+        } else {
+            Ok(img)
+        }
+    }
+
+    /// Reads the thumbnail of a PAA like `thumbnail` does for one that was read, but only reads
+    /// and decodes the mipmap it is made from, which the offsets lead to. Files without offsets
+    /// in the standard place are read whole.
+    pub fn read_thumbnail<R: Read + Seek>(reader: &mut R, max_dim: u32) -> Result<DynamicImage, std::io::Error> {
+        if max_dim == 0 {
+            return Err(coded_error!("E0901", "Thumbnails have to be at least 1 pixel large."));
+        }
+
+        let paa_type_raw = reader.read_u16::<LittleEndian>()?;
+        let paa_type = PaaType::from_u16(paa_type_raw)
+            .ok_or_else(|| coded_error!("E0403", "Unknown PAA type: 0x{:04X}", paa_type_raw))?;
+        let mut taggs = Vec::new();
+        read_taggs(reader, &mut taggs)?;
+        let Some(offsets) = taggs.iter().find(|t| is_offset_tagg(t)) else {
+            reader.seek(SeekFrom::Start(0))?;
+            return Self::from_reader(reader)?.thumbnail(max_dim);
+        };
+
+        let mut levels: Vec<(usize, u32, u32, u32)> = Vec::new();
+        for (index, offset) in offsets.data.chunks_exact(4).map(LittleEndian::read_u32).enumerate().filter(|(_, o)| *o != 0) {
+            reader.seek(SeekFrom::Start(offset as u64))?;
+            let width = (reader.read_u16::<LittleEndian>()? & 0x7FFF) as u32;
+            let height = (reader.read_u16::<LittleEndian>()? & 0x7FFF) as u32;
+            levels.push((index, width, height, offset));
+        }
+        levels.sort_by_key(|(_, width, height, _)| std::cmp::Reverse(width * height));
+        let Some(&(index, _, _, offset)) = levels.iter().rev().find(|(_, w, h, _)| *w.max(h) >= max_dim).or(levels.first()) else {
+            return Err(coded_error!("E0410", "No mipmaps found in PAA file"));
+        };
+
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let (mipmap, compressed) = load_mipmap(read_stored_mipmap(reader, paa_type, index)?, None)?;
+        taggs.retain(|t| &t.name != b"SFFO");
+        let paa = Paa { paa_type, taggs, mipmaps: vec![mipmap], canonical_layout: true, compressed };
+        paa.thumbnail(max_dim)
+    }
+
+    /// Returns a copy encoded in another type. Every mipmap is decoded and encoded again on its
+    /// own, so the levels stay the ones of the original. Taggs like the flags and the swizzle
+    /// are kept, the color taggs are computed again from the base level and the mipmaps are
+    /// compressed if the original was.
+    ///
+    /// Fails if alpha would be lost, which are all textures with alpha that isn't fully opaque
+    /// retyped to DXT1, unless `allow_lossy_alpha` is set.
+    pub fn retype(&self, paa_type: PaaType, allow_lossy_alpha: bool) -> Result<Paa, std::io::Error> {
+        if paa_type == self.paa_type {
+            return Err(coded_error!("E0418", "The PAA already is {:?}.", paa_type));
+        }
+
+        let mut mipmaps: Vec<&MipMap> = self.mipmaps.iter().collect();
+        mipmaps.sort_by_key(|m| std::cmp::Reverse(mipmap_area(m)));
+        let Some(base) = mipmaps.first() else {
+            return Err(coded_error!("E0410", "No mipmaps found in PAA file"));
+        };
+
+        let stats = paaanalysis::analyze(&decode_mipmap(self.paa_type, base, &DecodeOptions::default())?);
+        if paa_type == PaaType::DXT1 && !allow_lossy_alpha && stats.has_alpha {
+            return Err(coded_error!("E0406", "DXT1 can't store the alpha channel of this {:?} texture, which isn't fully opaque. Use --allow-lossy-alpha to convert it anyway.", self.paa_type));
+        }
+
+        let taggs = color_taggs(&stats);
+
+        let mut retyped = Vec::with_capacity(mipmaps.len());
+        for mipmap in mipmaps {
+            let img = decode_mipmap(self.paa_type, mipmap, &DecodeOptions::default())?;
+            let retyped_mipmap = MipMap {
+                width: mipmap.width,
+                height: mipmap.height,
+                data: encode_level(&img, paa_type, EncodeQuality::Default)?.0,
+                lzo: false,
+            };
+            check_mipmap_size(paa_type, &retyped_mipmap)?;
+            retyped.push(retyped_mipmap);
+        }
+
+        let mut paa = Paa {
+            paa_type,
+            taggs,
+            mipmaps: retyped,
+            canonical_layout: true,
+            compressed: false,
+        };
+        paa.keep_taggs(&self.taggs);
+        Ok(if self.compressed { paa.to_compressed(&MiniLzo) } else { paa })
+    }
+
+    /// Adds the taggs of the texture this one was encoded again from, replacing the ones of the
+    /// same name. Only the color taggs are kept, since they describe the new mipmaps.
+    fn keep_taggs(&mut self, taggs: &[Tagg]) {
+        let kept: Vec<&Tagg> = taggs.iter().filter(|t| &t.name != b"CGVA" && &t.name != b"CXAM" && &t.name != b"SFFO").collect();
+        self.taggs.retain(|t| !kept.iter().any(|k| k.name == t.name));
+        self.taggs.extend(kept.into_iter().cloned());
+    }
+
+    /// Returns the taggs in the order they are written, see `LEADING_TAGGS`.
+    fn ordered_taggs(&self) -> Vec<&Tagg> {
+        let mut taggs: Vec<&Tagg> = self.taggs.iter().collect();
+        taggs.sort_by_key(|t| LEADING_TAGGS.iter().position(|name| *name == t.name).unwrap_or(LEADING_TAGGS.len()));
+        taggs
+    }
+
+    /// Writes the taggs and the header of the offset tagg, whose offsets follow.
+    fn write_taggs<W: Write>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        writer.write_u16::<LittleEndian>(self.paa_type as u16)?;
+
+        // Like the names, the signature is stored reversed
+        for tagg in self.ordered_taggs() {
+            writer.write_all(b"GGAT")?;
+            writer.write_all(&tagg.name)?;
+            writer.write_u32::<LittleEndian>(tagg.data_size)?;
+            writer.write_all(&tagg.data)?;
+        }
+
+        writer.write_all(b"GGAT")?;
+        writer.write_all(b"SFFO")?;
+        writer.write_u32::<LittleEndian>((self.offset_entries() * 4) as u32)
+    }
+
+    /// Returns the number of entries of the offset tagg, more than `OFFSET_TABLE_ENTRIES` only
+    /// for PAAs that were read with more mipmaps.
+    fn offset_entries(&self) -> usize {
+        self.mipmaps.len().max(OFFSET_TABLE_ENTRIES)
+    }
+
+    /// Returns the sizes of all mipmaps before and after compression, largest first.
+    pub fn mipmap_stats(&self) -> Vec<MipmapStats> {
+        let mut mipmaps: Vec<&MipMap> = self.mipmaps.iter().collect();
+        mipmaps.sort_by_key(|m| std::cmp::Reverse(mipmap_area(m)));
+        mipmaps.into_iter().map(|m| MipmapStats {
+            width: m.width,
+            height: m.height,
+            original_size: calculate_mipmap_size(self.paa_type, m.width, m.height).max(m.data.len()),
+            stored_size: m.data.len(),
+            lzo: m.lzo,
+        }).collect()
+    }
+
+    /// Writes the PAA like `write_to`. Kept for writers that can seek, which it doesn't need.
+    pub fn write<W: Write + Seek>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        self.write_to(writer)
+    }
+
+    /// Writes the PAA with the taggs in the standard order, followed by the offset tagg and the
+    /// mipmaps from largest to smallest, in a single forward pass, e.g. into a compressed stream.
+    /// The mipmap offsets are computed up front from the sizes of the taggs and mipmaps, relative
+    /// to the start of the PAA.
+    ///
+    /// Average color, maximum color and flags come first, then an offset tagg of at least 16
+    /// entries padded with zeros, an empty palette and, after the last mipmap, an empty one. LZO
+    /// compressed mipmaps have the highest bit of their width set.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), std::io::Error> {
+        let mut mipmaps: Vec<&MipMap> = self.mipmaps.iter().collect();
+        mipmaps.sort_by_key(|m| std::cmp::Reverse(mipmap_area(m)));
+        for mipmap in &mipmaps {
+            check_mipmap_size(self.paa_type, mipmap)?;
+        }
+
+        let taggs_size: usize = self.taggs.iter().map(|t| 12 + t.data.len()).sum();
+        let mut offset = 2 + taggs_size + 12 + self.offset_entries() * 4 + EMPTY_PALETTE.len();
+        let mut offsets: Vec<u32> = Vec::with_capacity(mipmaps.len());
+        for mipmap in &mipmaps {
+            offsets.push(field_u32(offset, "Mipmap offset")?);
+            offset += 7 + mipmap.data.len();
+        }
+
+        self.write_taggs(writer)?;
+        for i in 0..self.offset_entries() {
+            writer.write_u32::<LittleEndian>(offsets.get(i).copied().unwrap_or(0))?;
+        }
+        writer.write_all(&EMPTY_PALETTE)?;
+
+        for mipmap in mipmaps {
+            write_mipmap(writer, self.paa_type, mipmap)?;
+        }
+        writer.write_all(&MIPMAP_TERMINATOR)
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        let mut file = std::io::BufWriter::new(create_file(path.as_ref())?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+}
+
+/// Decodes the full size mipmap like `Paa::to_image`.
+impl TryFrom<&Paa> for DynamicImage {
+    type Error = std::io::Error;
+
+    fn try_from(paa: &Paa) -> Result<Self, Self::Error> {
+        paa.to_image()
+    }
+}
+
+/// Encodes the image with all mipmaps like `img2paa` without options: the type is picked with
+/// `auto_paa_type` and mipmaps are LZO compressed with the default `EncodeOptions`. Use
+/// `Paa::from_image_with_options` to choose them.
+impl TryFrom<&DynamicImage> for Paa {
+    type Error = std::io::Error;
+
+    fn try_from(img: &DynamicImage) -> Result<Self, Self::Error> {
+        Paa::from_image_with_options(img, auto_paa_type(img), &EncodeOptions::default(), &MiniLzo, None)
+    }
+}
+
+/// Rows of pixels that are decoded at once, the height of a row of DXT blocks
+const STRIP_HEIGHT: usize = 4;
+
+/// Returns a reader of the DXT blocks of the mipmap. Data of the wrong length is most likely LZO
+/// compressed without being detected as such when reading, so decompressing it is tried
+/// first. Otherwise extra data is cut off and missing data filled with zeros, unless
+/// `options.strict` is set.
+fn dxt_reader<'a>(paa_type: PaaType, mipmap: &'a MipMap, options: &DecodeOptions) -> Result<Box<dyn Read + 'a>, std::io::Error> {
+    let expected_size = calculate_mipmap_size(paa_type, mipmap.width, mipmap.height);
+    if mipmap.lzo {
+        return Ok(Box::new(LzoReader::new(&mipmap.data, expected_size)));
+    }
+    if mipmap.data.len() == expected_size {
+        return Ok(Box::new(&mipmap.data[..]));
+    }
+
+    if lzo::verify(&mipmap.data, expected_size).is_ok() {
+        warning(format!("{:?} mipmap {}x{} was LZO compressed without being flagged as such. It was decompressed for decoding.",
+            paa_type, mipmap.width, mipmap.height), Some("compression-misdetected"), (None, None));
+        return Ok(Box::new(LzoReader::new(&mipmap.data, expected_size)));
+    }
+
+    let mismatch = format!("{:?} mipmap {}x{} has {} bytes of data, expected {} bytes", paa_type, mipmap.width, mipmap.height,
+        mipmap.data.len(), expected_size);
+    if mipmap.data.len() > expected_size {
+        warning(format!("{}. The extra data is ignored.", mismatch), Some("dxt-size-mismatch"), (None, None));
+        return Ok(Box::new(&mipmap.data[..expected_size]));
+    }
+    if options.strict {
+        return Err(coded_error!("E0419", "{} and isn't LZO compressed either.", mismatch));
+    }
+
+    warning(format!("{}. The missing data is filled with zeros, so the bottom of the image is black. The file is most likely broken.", mismatch),
+        Some("dxt-size-mismatch"), (None, None));
+    let missing = (expected_size - mipmap.data.len()) as u64;
+    Ok(Box::new((&mipmap.data[..]).chain(std::io::repeat(0).take(missing))))
+}
+
+/// Decodes the mipmap from top to bottom in strips of `STRIP_HEIGHT` rows, calling `callback`
+/// with the first row and the RGBA pixels of each. Compressed data is decompressed as the
+/// strips need it, so only one strip of it is held in memory at a time.
+fn decode_mipmap_strips<F: FnMut(u32, &[u8]) -> Result<(), std::io::Error>>(paa_type: PaaType, mipmap: &MipMap, options: &DecodeOptions, mut callback: F) -> Result<(), std::io::Error> {
+    let width = mipmap.width as usize;
+    let height = mipmap.height as usize;
+
+    let (mut reader, format, strip_size) = match paa_type {
+        PaaType::DXT1 => (dxt_reader(paa_type, mipmap, options)?, Some(Format::Bc1), width.div_ceil(4) * 8),
+        PaaType::DXT3 => (dxt_reader(paa_type, mipmap, options)?, Some(Format::Bc2), width.div_ceil(4) * 16),
+        PaaType::DXT5 => (dxt_reader(paa_type, mipmap, options)?, Some(Format::Bc3), width.div_ceil(4) * 16),
+        PaaType::ARGB4444 | PaaType::ARGB1555 | PaaType::ARGB8888 | PaaType::AI88 => {
+            let expected_size = calculate_mipmap_size(paa_type, mipmap.width, mipmap.height);
+            let reader: Box<dyn Read> = if mipmap.lzo {
+                Box::new(LzoReader::new(&mipmap.data, expected_size))
+            } else if mipmap.data.len() == expected_size {
+                Box::new(&mipmap.data[..])
+            } else {
+                return Err(coded_error!("E0419", "{:?} data size mismatch. Expected {} bytes, got {} bytes", paa_type, expected_size, mipmap.data.len()));
+            };
+            (reader, None, expected_size / height * STRIP_HEIGHT)
+        },
+    };
+
+    let mut data = vec![0u8; strip_size];
+    let mut rgba = vec![0u8; width * STRIP_HEIGHT * 4];
+    for y in (0..height).step_by(STRIP_HEIGHT) {
+        let rows = STRIP_HEIGHT.min(height - y);
+        let rgba = &mut rgba[..width * rows * 4];
+        match format {
+            Some(format) => {
+                reader.read_exact(&mut data)?;
+                format.decompress(&data, width, rows, rgba);
+            },
+            None => {
+                let data = &mut data[..strip_size / STRIP_HEIGHT * rows];
+                reader.read_exact(data)?;
+                match paa_type {
+                    PaaType::ARGB8888 => decode_argb8888(data, rgba),
+                    PaaType::ARGB1555 => decode_argb1555(data, rgba),
+                    PaaType::AI88 => decode_ai88(data, rgba),
+                    _ => decode_argb4444(data, rgba)
+                }
+            }
+        }
+        callback(y as u32, rgba)?;
+    }
+
+    Ok(())
+}
+
+/// Decodes the data of a mipmap.
+fn decode_mipmap(paa_type: PaaType, mipmap: &MipMap, options: &DecodeOptions) -> Result<DynamicImage, std::io::Error> {
+    let width = mipmap.width as u32;
+    let height = mipmap.height as u32;
+
+    let mut rgba_data = vec![0u8; (width * height * 4) as usize];
+    decode_mipmap_strips(paa_type, mipmap, options, |y, strip| {
+        let start = (y * width * 4) as usize;
+        rgba_data[start..start + strip.len()].copy_from_slice(strip);
+        Ok(())
+    })?;
+    flip_rgba(&mut rgba_data, width, options.flip_vertical, options.flip_horizontal);
+
+    let img_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, rgba_data)
+        .ok_or_else(|| coded_error!("E0419", "Failed to create image buffer"))?;
+
+    Ok(DynamicImage::ImageRgba8(img_buffer))
+}
+
+/// Returns the average color, maximum color and, if the image isn't opaque, flag taggs of the
+/// encoder.
+fn color_taggs(stats: &paaanalysis::ImageStats) -> Vec<Tagg> {
+    let mut taggs = vec![
+        Tagg { name: *b"CGVA", data_size: 4, data: stats.average_color.to_vec() },
+        Tagg { name: *b"CXAM", data_size: 4, data: stats.maximum_color.to_vec() },
+    ];
+    if let Some(flag) = stats.alpha_flag() {
+        taggs.push(Tagg { name: *b"GALF", data_size: 4, data: flag.to_le_bytes().to_vec() });
+    }
+    taggs
+}
+
+/// Moves the channels of RGBA pixels back to where the swizzle of a SWIZ tagg took them from.
+/// The sources of the constants can't be restored, so those channels keep their stored value,
+/// like channels that no swizzled channel came from.
+fn unswizzle(pixels: &mut [u8], swizzle: [u8; 4]) {
+    // Index of the A, R, G and B channels in an RGBA pixel
+    const CHANNELS: [usize; 4] = [3, 0, 1, 2];
+    for pixel in pixels.as_chunks_mut::<4>().0 {
+        let stored = *pixel;
+        for (channel, source) in CHANNELS.iter().zip(swizzle) {
+            let value = stored[*channel];
+            match source {
+                0..=3 => pixel[CHANNELS[source as usize]] = value,
+                4..=7 => pixel[CHANNELS[source as usize - 4]] = 255 - value,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Mirrors RGBA pixels of the given width in place, vertically by swapping rows and horizontally by
+/// reversing the pixels of every row.
+fn flip_rgba(pixels: &mut [u8], width: u32, vertical: bool, horizontal: bool) {
+    let row_size = width as usize * 4;
+    if row_size == 0 {
+        return;
+    }
+
+    if vertical {
+        let rows = pixels.len() / row_size;
+        for y in 0..rows / 2 {
+            let (top, bottom) = pixels.split_at_mut((rows - 1 - y) * row_size);
+            top[y * row_size..(y + 1) * row_size].swap_with_slice(&mut bottom[..row_size]);
+        }
+    }
+
+    if horizontal {
+        for row in pixels.chunks_exact_mut(row_size) {
+            row.as_chunks_mut::<4>().0.reverse();
+        }
+    }
+}
+
+fn calculate_mipmap_size(paa_type: PaaType, width: u16, height: u16) -> usize {
+    let blocks_x = ((width + 3) / 4) as usize;
+    let blocks_y = ((height + 3) / 4) as usize;
+
+    match paa_type {
+        PaaType::DXT1 => blocks_x * blocks_y * 8,
+        PaaType::DXT3 | PaaType::DXT5 => blocks_x * blocks_y * 16,
+        PaaType::ARGB4444 | PaaType::ARGB1555 | PaaType::AI88 => (width as usize) * (height as usize) * 2,
+        PaaType::ARGB8888 => (width as usize) * (height as usize) * 4,
+    }
+}
+
+/// Mipmaps of this size or smaller are never compressed
+const LZO_MIN_SIZE: usize = 128;
+
+fn compression_policy(use_compression: bool) -> LzoPolicy {
+    if use_compression { LzoPolicy::Always } else { LzoPolicy::Never }
+}
+
+/// Compresses mipmap data if it is large enough and gets smaller. If LZO fails, a warning is
+/// shown and `compress` is cleared so the remaining mipmaps are stored uncompressed.
+fn lzo_compress_mipmap(data: Vec<u8>, lzo: &dyn LzoCodec, compress: &mut bool) -> Vec<u8> {
+    if data.len() <= LZO_MIN_SIZE {
+        return data;
+    }
+
+    match lzo.compress(&data[..]) {
+        Ok(lzo_compressed) if lzo_compressed.len() < data.len() => lzo_compressed,
+        Ok(_) => data,
+        Err(e) => {
+            warning(format!("{}. Writing the remaining mipmaps uncompressed.", e), Some("lzo-unavailable"), (None, None));
+            *compress = false;
+            data
+        }
+    }
+}
+
+/// Encodes a single mipmap level in the given type, without LZO compression. AI88 is encoded
+/// from the grayscale image and every other type from RGBA, each converted to 8 bits only here.
+fn encode_level(img: &DynamicImage, paa_type: PaaType, quality: EncodeQuality) -> Result<(Vec<u8>, Option<QualityReport>), std::io::Error> {
+    let rgba = || img.to_rgba8();
+    match (paa_type, quality) {
+        (PaaType::DXT1 | PaaType::DXT3 | PaaType::DXT5, EncodeQuality::Exhaustive) => {
+            let format = match paa_type {
+                PaaType::DXT1 => Format::Bc1,
+                PaaType::DXT3 => Format::Bc2,
+                _ => Format::Bc3
+            };
+            let (data, report) = compress_dxt_exhaustive(&rgba(), format);
+            Ok((data, Some(report)))
+        },
+        (PaaType::DXT1, _) => Ok((compress_dxt1(&rgba(), quality.algorithm())?, None)),
+        (PaaType::DXT3, _) => Ok((compress_dxt3(&rgba(), quality.algorithm())?, None)),
+        (PaaType::DXT5, _) => Ok((compress_dxt5(&rgba(), quality.algorithm())?, None)),
+        (PaaType::ARGB4444, _) => Ok((encode_argb4444(&rgba()), None)),
+        (PaaType::ARGB1555, _) => Ok((encode_argb1555(&rgba()), None)),
+        (PaaType::ARGB8888, _) => Ok((encode_argb8888(&rgba()), None)),
+        (PaaType::AI88, _) => Ok((encode_ai88(&img.to_luma_alpha8()), None)),
+    }
+}
+
+/// Returns the image the mipmaps of a PAA type are resized from. Images with more than 8 bits
+/// per channel stay 16-bit and grayscale ones or those encoded as AI88 stay grayscale, so they are
+/// only quantized to 8 bits once, when a level is encoded.
+fn working_image(img: &DynamicImage, paa_type: PaaType, legacy: bool) -> DynamicImage {
+    if legacy {
+        return DynamicImage::ImageRgba8(img.to_rgba8());
+    }
+
+    let color = img.color();
+    let high_depth = color.bytes_per_pixel() > color.channel_count();
+    match (paa_type == PaaType::AI88 || !color.has_color(), high_depth) {
+        (true, true) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        (true, false) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (false, true) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        (false, false) => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+/// Stores each pixel as 8 bits of intensity followed by 8 bits of alpha.
+fn encode_ai88(img: &ImageBuffer<LumaA<u8>, Vec<u8>>) -> Vec<u8> {
+    img.as_raw().clone()
+}
+
+fn decode_ai88(data: &[u8], rgba: &mut [u8]) {
+    for (value, pixel) in data.chunks_exact(2).zip(rgba.chunks_exact_mut(4)) {
+        pixel.copy_from_slice(&[value[0], value[0], value[0], value[1]]);
+    }
+}
+
+/// Packs each pixel into 16 bits with 4 bits per channel, alpha in the highest nibble.
+fn encode_argb4444(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    let quantize = |v: u8| (v as u16 * 15 + 127) / 255;
+
+    let mut output = Vec::with_capacity((img.width() * img.height() * 2) as usize);
+    for pixel in img.pixels() {
+        let value = (quantize(pixel[3]) << 12) | (quantize(pixel[0]) << 8) | (quantize(pixel[1]) << 4) | quantize(pixel[2]);
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+    output
+}
+
+fn decode_argb4444(data: &[u8], rgba: &mut [u8]) {
+    for (value, pixel) in data.chunks_exact(2).zip(rgba.chunks_exact_mut(4)) {
+        let value = u16::from_le_bytes([value[0], value[1]]);
+        pixel[0] = ((value >> 8) & 0xF) as u8 * 17;
+        pixel[1] = ((value >> 4) & 0xF) as u8 * 17;
+        pixel[2] = (value & 0xF) as u8 * 17;
+        pixel[3] = (value >> 12) as u8 * 17;
+    }
+}
+
+/// Packs each pixel into 16 bits with 5 bits per color, alpha is the highest bit and set from 128.
+fn encode_argb1555(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    let quantize = |v: u8| (v as u16 * 31 + 127) / 255;
+
+    let mut output = Vec::with_capacity((img.width() * img.height() * 2) as usize);
+    for pixel in img.pixels() {
+        let alpha = if pixel[3] >= 128 { 0x8000 } else { 0 };
+        let value = alpha | (quantize(pixel[0]) << 10) | (quantize(pixel[1]) << 5) | quantize(pixel[2]);
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+    output
+}
+
+fn decode_argb1555(data: &[u8], rgba: &mut [u8]) {
+    // Repeating the highest bits in the lowest maps 31 to 255
+    let expand = |v: u16| ((v << 3) | (v >> 2)) as u8;
+
+    for (value, pixel) in data.chunks_exact(2).zip(rgba.chunks_exact_mut(4)) {
+        let value = u16::from_le_bytes([value[0], value[1]]);
+        pixel[0] = expand((value >> 10) & 0x1F);
+        pixel[1] = expand((value >> 5) & 0x1F);
+        pixel[2] = expand(value & 0x1F);
+        pixel[3] = if value & 0x8000 != 0 { 255 } else { 0 };
+    }
+}
+
+/// Stores each pixel as a little endian 32-bit ARGB value, so in BGRA order.
+fn encode_argb8888(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut output = Vec::with_capacity((img.width() * img.height() * 4) as usize);
+    for pixel in img.pixels() {
+        output.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+    }
+    output
+}
+
+fn decode_argb8888(data: &[u8], rgba: &mut [u8]) {
+    for (value, pixel) in data.chunks_exact(4).zip(rgba.chunks_exact_mut(4)) {
+        pixel.copy_from_slice(&[value[2], value[1], value[0], value[3]]);
+    }
+}
+
+fn compress_dxt1(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+    compress_blocks(img, Format::Bc1, algorithm)
+}
+
+/// Compresses the image into DXT3 blocks, which store 4 bits of alpha per pixel and suit sharp
+/// alpha edges better than the interpolated alpha of DXT5.
+fn compress_dxt3(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+    compress_blocks(img, Format::Bc2, algorithm)
+}
+
+fn compress_dxt5(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+    compress_blocks(img, Format::Bc3, algorithm)
+}
+
+/// Compresses the image into blocks of the format, one row of blocks per task on the thread
+/// pool. Every block only depends on its own pixels, so the output is the same for any number
+/// of threads.
+fn compress_blocks(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, format: Format, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+    let blocks_x = img.width().div_ceil(4);
+    let blocks_y = img.height().div_ceil(4);
+    let block_size = format.block_size();
+
+    let mut output = vec![0u8; (blocks_x * blocks_y) as usize * block_size];
+
+    let params = Params {
+        algorithm,
+        weights: [1.0, 1.0, 1.0],
+        weigh_colour_by_alpha: false,
+    };
+
+    parallel::for_each_chunk_mut(&mut output, blocks_x as usize * block_size, |by, row| {
+        for (bx, block) in row.chunks_exact_mut(block_size).enumerate() {
+            format.compress_block_masked(read_block(img, bx as u32, by as u32), 0xFFFF, params, block);
+        }
+    });
+
+    Ok(output)
+}
+
+/// Returns the pixels of the block at the given block coordinates, repeating the last row and
+/// column for blocks reaching past the edge.
+fn read_block(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, bx: u32, by: u32) -> [[u8; 4]; 16] {
+    let mut block: [[u8; 4]; 16] = [[0; 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let x = (bx * 4 + i as u32 % 4).min(img.width() - 1);
+        let y = (by * 4 + i as u32 / 4).min(img.height() - 1);
+        *pixel = img.get_pixel(x, y).0;
+    }
+    block
+}
+
+/// Returns the squared error of the RGBA channels of an encoded block.
+fn block_error(format: Format, encoded: &[u8], source: &[[u8; 4]; 16]) -> u64 {
+    format.decompress_block(encoded).iter().zip(source)
+        .flat_map(|(a, b)| a.iter().zip(b))
+        .map(|(a, b)| (i64::from(*a) - i64::from(*b)).pow(2) as u64)
+        .sum()
+}
+
+/// Writes a 4-color block with the two most distant colors of the source block as endpoints.
+/// Cluster fits can settle on endpoints inside the range of a gradient, these never do.
+fn encode_extremes_block(source: &[[u8; 4]; 16], output: &mut [u8]) {
+    let distance = |a: &[u8; 4], b: &[u8; 4]| -> i32 { (0..3).map(|c| (i32::from(a[c]) - i32::from(b[c])).pow(2)).sum() };
+    let (mut first, mut second) = (0, 0);
+    for i in 0..16 {
+        for j in (i + 1)..16 {
+            if distance(&source[i], &source[j]) > distance(&source[first], &source[second]) {
+                (first, second) = (i, j);
+            }
+        }
+    }
+
+    let pack = |c: &[u8; 4]| -> u16 {
+        (((u16::from(c[0]) * 31 + 127) / 255) << 11) | (((u16::from(c[1]) * 63 + 127) / 255) << 5) | ((u16::from(c[2]) * 31 + 127) / 255)
+    };
+    let unpack = |c: u16| -> [i32; 3] {
+        let (r, g, b) = ((c >> 11) as i32, ((c >> 5) & 0x3F) as i32, (c & 0x1F) as i32);
+        [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+    };
+
+    // The first endpoint has to be larger for 4 colors
+    let (mut start, mut end) = (pack(&source[first]), pack(&source[second]));
+    if start < end {
+        (start, end) = (end, start);
+    }
+    let (a, b) = (unpack(start), unpack(end));
+    let palette: [[i32; 3]; 4] = [a, b, [0, 1, 2].map(|c| (2 * a[c] + b[c]) / 3), [0, 1, 2].map(|c| (a[c] + 2 * b[c]) / 3)];
+
+    let mut indices: u32 = 0;
+    if start != end {
+        for (i, pixel) in source.iter().enumerate() {
+            let index = (0..4).min_by_key(|p| (0..3).map(|c| (palette[*p][c] - i32::from(pixel[c])).pow(2)).sum::<i32>()).unwrap();
+            indices |= (index as u32) << (i * 2);
+        }
+    }
+
+    output[0..2].copy_from_slice(&start.to_le_bytes());
+    output[2..4].copy_from_slice(&end.to_le_bytes());
+    output[4..8].copy_from_slice(&indices.to_le_bytes());
+}
+
+/// Encodes every block with each of texpresso's fitting algorithms and with `encode_extremes_block`,
+/// keeping the one with the smallest error. Blocks are encoded in parallel.
+fn compress_dxt_exhaustive(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, format: Format) -> (Vec<u8>, QualityReport) {
+    let blocks_x = img.width().div_ceil(4);
+    let blocks_y = img.height().div_ceil(4);
+    let block_size = format.block_size();
+
+    let encode = |algorithm: Algorithm, source: &[[u8; 4]; 16]| -> Vec<u8> {
+        let mut block = vec![0u8; block_size];
+        let params = Params { algorithm, weights: [1.0, 1.0, 1.0], weigh_colour_by_alpha: false };
+        format.compress_block_masked(*source, 0xFFFF, params, &mut block);
+        block
+    };
+
+    let blocks: Vec<(Vec<u8>, u64, u64)> = parallel::map_collect((0..blocks_x * blocks_y).collect(), |i| {
+        let source = read_block(img, i % blocks_x, i / blocks_x);
+
+        let default = encode(Algorithm::IterativeClusterFit, &source);
+        let mut candidates = vec![encode(Algorithm::ClusterFit, &source), encode(Algorithm::RangeFit, &source)];
+        // DXT1 blocks with transparent pixels need the 3-color mode
+        if format != Format::Bc1 || source.iter().all(|p| p[3] >= 128) {
+            let mut extremes = default.clone();
+            encode_extremes_block(&source, &mut extremes[(block_size - 8)..]);
+            candidates.push(extremes);
+        }
+
+        let default_error = block_error(format, &default, &source);
+        let (best, error) = candidates.into_iter()
+            .map(|c| { let e = block_error(format, &c, &source); (c, e) })
+            .fold((default, default_error), |best, candidate| if candidate.1 < best.1 { candidate } else { best });
+        (best, default_error, error)
+    });
+
+    let mut report = QualityReport { blocks: blocks.len(), ..Default::default() };
+    let mut output: Vec<u8> = Vec::with_capacity(blocks.len() * block_size);
+    for (block, default_error, error) in blocks {
+        output.extend(block);
+        report.default_error += default_error;
+        report.error += error;
+    }
+    (output, report)
+}
+
+/// Internal encoding helpers, only exposed for the benchmarks.
+#[cfg(feature = "bench")]
+pub mod bench {
+    use image::{ImageBuffer, Rgba};
+    use texpresso::Algorithm;
+
+    /// Compresses the image into DXT1 blocks.
+    pub fn compress_dxt1(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+        super::compress_dxt1(img, algorithm)
+    }
+
+    /// Compresses the image into DXT5 blocks.
+    pub fn compress_dxt5(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, algorithm: Algorithm) -> Result<Vec<u8>, std::io::Error> {
+        super::compress_dxt5(img, algorithm)
+    }
+}
+
+/// Average color to store in the CGVA tagg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AverageColor {
+    /// Compute the average color from the top mipmap.
+    Auto,
+    /// Store the given RGBA value.
+    Value([u8; 4]),
+}
+
+/// Tagg changes applied by `cmd_paa_set`. Fields left as `None` are not touched.
+#[derive(Debug, Default, Clone)]
+pub struct TaggEdits {
+    /// Value of the FLAG tagg (1 for interpolated alpha, 2 for alpha testing).
+    pub flag: Option<u32>,
+    pub avg_color: Option<AverageColor>,
+    /// Channel sources for the SWIZ tagg, in A, R, G, B order.
+    pub swizzle: Option<[u8; 4]>,
+}
+
+/// Parses an LZO policy, `always`, `never`, `adaptive` or `adaptive:<percent>`.
+pub fn parse_lzo_policy(input: &str) -> Result<LzoPolicy, std::io::Error> {
+    let lower = input.to_lowercase();
+    match lower.split_once(':') {
+        None if lower == "always" => Ok(LzoPolicy::Always),
+        None if lower == "never" => Ok(LzoPolicy::Never),
+        None if lower == "adaptive" => Ok(LzoPolicy::Adaptive { min_savings: DEFAULT_MIN_SAVINGS }),
+        Some(("adaptive", percent)) => match percent.parse::<f32>() {
+            Ok(min_savings) if (0.0..=100.0).contains(&min_savings) => Ok(LzoPolicy::Adaptive { min_savings }),
+            _ => Err(coded_error!("E0901", "Invalid percentage \"{}\" for the adaptive LZO policy, expected 0 to 100.", percent))
+        },
+        _ => Err(coded_error!("E0901", "Unknown LZO policy \"{}\", expected always, never or adaptive[:<percent>].", input))
+    }
+}
+
+/// Prints the size of every mipmap before and after compression, and the total savings.
+pub fn print_mipmap_stats<O: Write>(paa: &Paa, output: &mut O) -> Result<(), std::io::Error> {
+    let stats = paa.mipmap_stats();
+    for mipmap in &stats {
+        writeln!(output, "{:>5}x{:<5} {:>9} -> {:>9}", mipmap.width, mipmap.height, mipmap.original_size, mipmap.stored_size)?;
+    }
+
+    let original: usize = stats.iter().map(|m| m.original_size).sum();
+    let stored: usize = stats.iter().map(|m| m.stored_size).sum();
+    let percent = if original == 0 { 0.0 } else { (1.0 - stored as f64 / original as f64) * 100.0 };
+    writeln!(output, "Total: {} -> {} bytes, {:.1}% saved", original, stored, percent)?;
+    Ok(())
+}
+
+/// Parses an encode quality, `fast`, `cluster`, `default` or `exhaustive`.
+pub fn parse_encode_quality(input: &str) -> Result<EncodeQuality, std::io::Error> {
+    match input.to_lowercase().as_str() {
+        "fast" => Ok(EncodeQuality::Fast),
+        "cluster" => Ok(EncodeQuality::Cluster),
+        "default" => Ok(EncodeQuality::Default),
+        "exhaustive" => Ok(EncodeQuality::Exhaustive),
+        _ => Err(coded_error!("E0901", "Unknown encode quality \"{}\", expected fast, cluster, default or exhaustive.", input))
+    }
+}
+
+/// Parses the name of a PAA type that can be encoded, ignoring case.
+pub fn parse_paa_type(input: &str) -> Result<PaaType, std::io::Error> {
+    match input.to_uppercase().as_str() {
+        "DXT1" => Ok(PaaType::DXT1),
+        "DXT3" => Ok(PaaType::DXT3),
+        "DXT5" => Ok(PaaType::DXT5),
+        "ARGB4444" => Ok(PaaType::ARGB4444),
+        "ARGB1555" => Ok(PaaType::ARGB1555),
+        "ARGB8888" => Ok(PaaType::ARGB8888),
+        "AI88" => Ok(PaaType::AI88),
+        _ => Err(coded_error!("E0901", "Unknown PAA type: {}. Supported types: DXT1, DXT3, DXT5, ARGB4444, ARGB1555, ARGB8888, AI88", input)),
+    }
+}
+
+/// Parses a PAA type like `parse_paa_type`, or `auto` for `None`, to pick one per image with
+/// `auto_paa_type`.
+pub fn parse_paa_type_or_auto(input: &str) -> Result<Option<PaaType>, std::io::Error> {
+    if input.eq_ignore_ascii_case("auto") {
+        return Ok(None);
+    }
+    parse_paa_type(input).map(Some)
+}
+
+/// Parses an average color given either as `auto` or as `RRGGBBAA` hex value.
+pub fn parse_avg_color(input: &str) -> Result<AverageColor, std::io::Error> {
+    if input.eq_ignore_ascii_case("auto") {
+        return Ok(AverageColor::Auto);
+    }
+
+    if input.len() != 8 || !input.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(coded_error!("E0901", "Invalid average color \"{}\", expected \"auto\" or RRGGBBAA.", input));
+    }
+
+    let mut color = [0u8; 4];
+    for (i, c) in color.iter_mut().enumerate() {
+        *c = u8::from_str_radix(&input[(i * 2)..(i * 2 + 2)], 16).unwrap();
+    }
+
+    Ok(AverageColor::Value(color))
+}
+
+/// Parses a swizzle given as four comma-separated channel sources for the A, R, G and B
+/// channels. A source is one of `A`, `R`, `G`, `B`, the inverted `1-A`, `1-R`, `1-G`, `1-B`
+/// or one of the constants `1` and `0`.
+pub fn parse_swizzle(input: &str) -> Result<[u8; 4], std::io::Error> {
+    let sources: Vec<&str> = input.split(',').map(|s| s.trim()).collect();
+    if sources.len() != 4 {
+        return Err(coded_error!("E0901", "Invalid swizzle \"{}\", expected four channel sources for A, R, G and B.", input));
+    }
+
+    let mut swizzle = [0u8; 4];
+    for (s, source) in swizzle.iter_mut().zip(sources) {
+        *s = match source.to_uppercase().as_str() {
+            "A" => 0,
+            "R" => 1,
+            "G" => 2,
+            "B" => 3,
+            "1-A" => 4,
+            "1-R" => 5,
+            "1-G" => 6,
+            "1-B" => 7,
+            "1" => 8,
+            "0" => 9,
+            _ => return Err(coded_error!("E0901", "Invalid swizzle channel source \"{}\".", source))
+        };
+    }
+
+    Ok(swizzle)
+}
+
+fn set_tagg(taggs: &mut Vec<Tagg>, name: [u8; 4], data: Vec<u8>) {
+    if let Some(tagg) = taggs.iter_mut().find(|t| t.name == name) {
+        tagg.data_size = data.len() as u32;
+        tagg.data = data;
+        return;
+    }
+
+    // New taggs go before the offsets, which are conventionally the last tagg
+    let index = taggs.iter().position(|t| &t.name == b"SFFO").unwrap_or(taggs.len());
+    taggs.insert(index, Tagg {
+        name,
+        data_size: data.len() as u32,
+        data,
+    });
+}
+
+/// Returns the output policy of a command that may write a PAA back to its source, which it
+/// replaces without `force`.
+fn in_place_policy(source: &Path, target: &Path, force: bool) -> OutputPolicy {
+    OutputPolicy::from_flags(force || target == source, false, false)
+}
+
+/// Changes the taggs of a PAA without re-encoding it. Everything after the tagg section,
+/// including the mipmap data, is copied verbatim; only the mipmap offsets are moved if the tagg
+/// section changes size. Source and target may be the same file.
+pub fn cmd_paa_set(source: &Path, target: &Path, edits: &TaggEdits, force: bool) -> Result<(), std::io::Error> {
+    let bytes = std::fs::read(source)?;
+    let mut reader = Cursor::new(&bytes);
+
+    let paa_type_raw = reader.read_u16::<LittleEndian>()?;
+    PaaType::from_u16(paa_type_raw).ok_or_else(|| coded_error!("E0403", "Unknown PAA type: 0x{:04X}", paa_type_raw))?;
+
+    let mut taggs: Vec<Tagg> = Vec::new();
+    read_taggs(&mut reader, &mut taggs)?;
+    let taggs_end = reader.position() as usize;
+
+    if let Some(flag) = edits.flag {
+        set_tagg(&mut taggs, *b"GALF", flag.to_le_bytes().to_vec());
+    }
+    if let Some(avg_color) = edits.avg_color {
+        let color = match avg_color {
+            AverageColor::Auto => {
+                paaanalysis::average_color(&Paa::from_reader(&mut Cursor::new(&bytes))?.stored_image(0, &DecodeOptions::default())?)
+            },
+            AverageColor::Value(color) => color,
+        };
+        set_tagg(&mut taggs, *b"CGVA", color.to_vec());
+    }
+    if let Some(swizzle) = edits.swizzle {
+        set_tagg(&mut taggs, *b"ZIWS", swizzle.to_vec());
+    }
+
+    let new_taggs_end = 2 + taggs.iter().map(|t| 12 + t.data.len()).sum::<usize>();
+
+    if let Some(offsets) = taggs.iter_mut().find(|t| &t.name == b"SFFO") {
+        let mut adjusted: Vec<u8> = Vec::with_capacity(offsets.data.len());
+        for chunk in offsets.data.chunks(4) {
+            if chunk.len() < 4 {
+                adjusted.extend(chunk);
+                continue;
+            }
+
+            let offset = LittleEndian::read_u32(chunk) as usize;
+            if offset == 0 {
+                adjusted.extend(chunk);
+                continue;
+            }
+            if offset < taggs_end {
+                return Err(coded_error!("E0404", "Mipmap offset {} points into the tagg section.", offset));
+            }
+
+            adjusted.write_u32::<LittleEndian>(field_u32(offset - taggs_end + new_taggs_end, "Mipmap offset")?)?;
+        }
+        offsets.data = adjusted;
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(new_taggs_end + bytes.len() - taggs_end);
+    output.write_u16::<LittleEndian>(paa_type_raw)?;
+    for tagg in &taggs {
+        output.write_all(b"GGAT")?;
+        output.write_all(&tagg.name)?;
+        output.write_u32::<LittleEndian>(field_u32(tagg.data.len(), "Tagg size")?)?;
+        output.write_all(&tagg.data)?;
+    }
+    output.write_all(&bytes[taggs_end..])?;
+
+    in_place_policy(source, target, force).write(target, |file| file.write_all(&output)).map(|_| ())
+}
+
+/// Prints the type, taggs and mipmap sizes of a PAA, and the tool that wrote it if it has a
+/// `TOOL_TAGG`. Then lists the name and size of each tagg and the size of each mipmap with the
+/// bytes it takes in the file.
+pub fn cmd_paa_info<O: Write>(path: &Path, output: &mut O) -> Result<(), std::io::Error> {
+    // Read once for both the header and the mipmaps
+    let data = std::fs::read(path)?;
+    let header = PaaHeader::read(&mut Cursor::new(&data)).prepend_error("Failed to read PAA:")?;
+    let color = |c: Option<[u8; 4]>| c.map(|c| format!("{:02x}{:02x}{:02x}{:02x}", c[0], c[1], c[2], c[3])).unwrap_or_else(|| "-".to_string());
+
+    writeln!(output, "Type:          {:?}", header.paa_type)?;
+    if let Some((width, height, _)) = header.mipmaps.first() {
+        writeln!(output, "Size:          {}x{}", width, height)?;
+    }
+    writeln!(output, "Mipmaps:       {}", header.mipmaps.len())?;
+    writeln!(output, "Average color: {}", color(header.average_color))?;
+    writeln!(output, "Maximum color: {}", color(header.max_color))?;
+    writeln!(output, "Flag:          {}", header.flag.map(|f| f.to_string()).unwrap_or_else(|| "-".to_string()))?;
+    if let Some(tool) = &header.tool {
+        writeln!(output, "Written by:    {}", tool)?;
+    }
+
+    writeln!(output, "\nTagg  Bytes")?;
+    for (name, size) in &header.taggs {
+        writeln!(output, "{:4} {:>6}", name, size)?;
+    }
+
+    let paa = Paa::from_reader(&mut Cursor::new(&data)).prepend_error(format!("Failed to read \"{}\":", path.display()))?;
+    writeln!(output, "\nLevel      Size    Stored  LZO")?;
+    for (level, mipmap) in paa.mipmap_stats().iter().enumerate() {
+        writeln!(output, "{:>5} {:>9} {:>9}  {}", level, format!("{}x{}", mipmap.width, mipmap.height), mipmap.stored_size, if mipmap.lzo { "yes" } else { "no" })?;
+    }
+    Ok(())
+}
+
+/// Converts the mipmap of the given level of a PAA, 0 for the full size image, to an image of the
+/// format of the target's extension. Returns `false` if the target was left as it is because of
+/// the output policy.
+pub fn cmd_paa2img(source: &Path, target: &Path, level: usize, options: &DecodeOptions, policy: OutputPolicy) -> Result<bool, std::io::Error> {
+    if policy.skips(source, target)? {
+        return Ok(false);
+    }
+    if is_dds(target) {
+        if options.flip_vertical || options.flip_horizontal {
+            return Err(coded_error!("E0414", "The blocks of a DDS are copied as they are, so they can't be flipped."));
+        }
+        // Every level from the first one on is copied, so they are all decompressed at once
+        let mut paa = Paa::from_file(source)?;
+        paa.decompress_levels(&(level..paa.mipmap_count()).collect::<Vec<usize>>())?;
+        return policy.write(target, |output| paa.to_dds_from_level(level, output));
+    }
+
+    let format = image::ImageFormat::from_path(target).map_err(|e| coded_error!("E0408", "Failed to save image: {}", e))?;
+    let paa = Paa::from_file(source)?;
+    let img = paa.to_image_level_with_options(level, options)?;
+
+    policy.write(target, |output| img.write_to(output, format).map_err(|e| coded_error!("E0408", "Failed to save image: {}", e)))
+}
+
+/// Returns the share of the pixels that aren't fully opaque. The outermost row and column on
+/// each side don't count, since some exporters leave a transparent border around images.
+pub fn transparent_share(img: &DynamicImage) -> f64 {
+    if !img.color().has_alpha() {
+        return 0.0;
+    }
+
+    let rgba = img.to_rgba8();
+    let border = u32::from(rgba.width() > 2 && rgba.height() > 2);
+    let (width, height) = (rgba.width() - 2 * border, rgba.height() - 2 * border);
+    let transparent = (border..border + height)
+        .flat_map(|y| (border..border + width).map(move |x| (x, y)))
+        .filter(|(x, y)| rgba.get_pixel(*x, *y)[3] != 255)
+        .count();
+    transparent as f64 / f64::from(width * height)
+}
+
+/// Picks DXT5 for images with alpha and DXT1 for opaque ones. Images count as opaque if no more
+/// than `AUTO_ALPHA_THRESHOLD` of the pixels are transparent, see `transparent_share`.
+pub fn auto_paa_type(img: &DynamicImage) -> PaaType {
+    paa_type_for_transparency(transparent_share(img))
+}
+
+fn paa_type_for_transparency(share: f64) -> PaaType {
+    if share > AUTO_ALPHA_THRESHOLD { PaaType::DXT5 } else { PaaType::DXT1 }
+}
+
+fn is_dds(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("dds"))
+}
+
+/// Copies the blocks of a DDS into a PAA with `Paa::from_dds`. The type has to be the one of the
+/// DDS if one is given, and any LZO policy but `never` compresses every mipmap that gets smaller.
+fn dds_to_paa(source: &Path, paa_type: Option<PaaType>, options: &EncodeOptions) -> Result<Paa, std::io::Error> {
+    if options.flip_vertical || options.flip_horizontal {
+        return Err(coded_error!("E0414", "The blocks of a DDS are copied as they are, so they can't be flipped."));
+    }
+    let file = File::open(source).prepend_error(format!("Failed to open {}:", source.display()))?;
+    let mut paa = Paa::from_dds(std::io::BufReader::new(file)).prepend_error(format!("Failed to read {}:", source.display()))?;
+    if let Some(paa_type) = paa_type
+        && paa_type != paa.paa_type {
+        return Err(coded_error!("E0902", "{} is {:?}, which is copied into the PAA as it is. Leave out the type or convert the DDS to an image first to get {:?}.",
+            source.display(), paa.paa_type, paa_type));
+    }
+
+    if options.lzo != LzoPolicy::Never {
+        paa = paa.to_compressed(&MiniLzo);
+    }
+    if options.tag_tool {
+        paa.set_tool(Some(&tool_name()))?;
+    }
+    Ok(paa)
+}
+
+/// Converts an image to a PAA, printing the mipmap sizes with `verbose`. Returns `false` if the
+/// target was left as it is because of the output policy. Without a type, one is picked with
+/// `auto_paa_type` and printed with `verbose`.
+pub fn cmd_img2paa(source: &Path, target: &Path, paa_type: Option<PaaType>, options: &EncodeOptions, verbose: bool, policy: OutputPolicy, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<bool, std::io::Error> {
+    if policy.skips(source, target)? {
+        return Ok(false);
+    }
+    let (paa, report) = if is_dds(source) {
+        (dds_to_paa(source, paa_type, options)?, None)
+    } else {
+        let img = open_image(source, !options.ignore_exif)?;
+        let paa_type = match paa_type {
+            Some(paa_type) => paa_type,
+            None => {
+                let share = transparent_share(&img);
+                let paa_type = paa_type_for_transparency(share);
+                if verbose {
+                    eprintln!("Picked {:?} for {}, {:.1}% of its pixels are transparent.", paa_type, source.display(), share * 100.0);
+                }
+                paa_type
+            }
+        };
+        Paa::from_image_with_report(&img, paa_type, options, &MiniLzo, progress)?
+    };
+
+    if !policy.write(target, |output| paa.write_to(output))? {
+        return Ok(false);
+    }
+    if verbose {
+        print_mipmap_stats(&paa, &mut std::io::stderr())?;
+        if let Some(report) = report {
+            let percent = if report.default_error == 0 { 0.0 } else { (1.0 - report.error as f64 / report.default_error as f64) * 100.0 };
+            eprintln!("Exhaustive encoding lowered the squared error by {:.1} per block on average ({:.1}% of {} blocks).",
+                report.average_improvement(), percent, report.blocks);
+        }
+    }
+    Ok(true)
+}
+
+/// Converts every PAA in a folder and its subfolders to an image of the same name with the
+/// given extension in the target folder. Failed conversions are printed and counted without
+/// stopping the others, including PAAs without a mipmap of the level.
+pub fn cmd_paa2img_folder(source: &Path, target: &Path, extension: &str, level: usize, options: &DecodeOptions, policy: OutputPolicy) -> Result<BatchSummary, std::io::Error> {
+    let paas = files_with_extensions(source, &["paa"]).prepend_error("Failed to read source folder:")?;
+    check_target_collisions(&paas.iter().map(|paa| (source.join(paa), target.join(paa).with_extension(extension))).collect::<Vec<_>>())?;
+    let results = parallel::map_collect(paas.clone(), |paa| {
+        let output = target.join(&paa).with_extension(extension);
+        create_folder(output.parent().unwrap())?;
+        cmd_paa2img(&source.join(&paa), &output, level, options, policy)
+    });
+    Ok(BatchSummary::from_results(&paas, results))
+}
+
+/// Returns the image written for textures that fail to decode, a magenta and black checkerboard.
+fn placeholder_thumbnail(size: u32) -> DynamicImage {
+    let square = (size / 4).max(1);
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(size, size, |x, y| {
+        if (x / square + y / square).is_multiple_of(2) { Rgba([255, 0, 255, 255]) } else { Rgba([0, 0, 0, 255]) }
+    }))
+}
+
+/// Writes a PNG thumbnail of at most `size` pixels on either side for every PAA in a folder and
+/// its subfolders to the same path in the target folder, in parallel. Files are recognized by
+/// their header, whatever their extension. Thumbnails that are newer than their PAA are kept,
+/// so running it again only converts what changed. Use a separate target folder for each size.
+///
+/// Textures that fail to decode get a placeholder and a `thumbnail-failed` warning instead of
+/// stopping the others, and are counted as failed.
+pub fn cmd_paa_thumbs(source: &Path, target: &Path, size: u32) -> Result<BatchSummary, std::io::Error> {
+    if size == 0 {
+        return Err(coded_error!("E0901", "Thumbnails have to be at least 1 pixel large."));
+    }
+
+    let mut files: Vec<PathBuf> = list_files(&source.to_path_buf()).prepend_error("Failed to read source folder:")?.into_iter()
+        .filter_map(|f| f.strip_prefix(source).ok().map(Path::to_path_buf))
+        .collect();
+    files.sort();
+
+    let results: Vec<Result<BatchSummary, std::io::Error>> = parallel::map_collect(files.clone(), |file: PathBuf| {
+        let path = source.join(&file);
+        let output = target.join(&file).with_extension("png");
+        if !matches!(FileKind::detect_file(&path)?, FileKind::Paa(_)) {
+            return Ok(BatchSummary::default());
+        }
+        if OutputPolicy::IfNewer.skips(&path, &output)? {
+            return Ok(BatchSummary { skipped: 1, ..Default::default() });
+        }
+        create_folder(output.parent().unwrap())?;
+
+        let thumbnail = File::open(&path).and_then(|file| Paa::read_thumbnail(&mut BufReader::new(file), size))
+            .prepend_error(format!("Failed to read \"{}\":", path.display()));
+        let (img, summary) = match thumbnail {
+            Ok(img) => (img, BatchSummary { converted: 1, ..Default::default() }),
+            Err(e) => {
+                warning(format!("Failed to decode {}, writing a placeholder thumbnail: {}", file.display(), e), Some("thumbnail-failed"), (None, None));
+                (placeholder_thumbnail(size), BatchSummary { failed: 1, ..Default::default() })
+            }
+        };
+
+        OutputPolicy::Overwrite.write(&output, |writer| img.write_to(writer, ImageFormat::Png).map_err(|e| coded_error!("E0408", "Failed to save image: {}", e)))?;
+        Ok(summary)
+    });
+
+    let mut summary = BatchSummary::default();
+    for (file, result) in files.iter().zip(results) {
+        match result {
+            Ok(counts) => {
+                summary.converted += counts.converted;
+                summary.skipped += counts.skipped;
+                summary.failed += counts.failed;
+            },
+            Err(e) => {
+                Err::<(), std::io::Error>(e).prepend_error(format!("Failed to write a thumbnail for {}:", file.display())).print_error(false);
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Returns the embedded ICC profile of an image, for the formats that can have one.
+fn icc_profile(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Png => PngDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(Cursor::new(data)).ok()?.icc_profile(),
+        _ => None
+    }
+}
+
+/// Opens an image to encode. JPEG and TIFF photos are turned upright by their EXIF orientation
+/// if `apply_orientation` is set. Embedded ICC profiles are ignored with an `icc-ignored`
+/// warning, since all images are treated as sRGB.
+pub fn open_image(source: &Path, apply_orientation: bool) -> Result<DynamicImage, std::io::Error> {
+    let data = std::fs::read(source).prepend_error("Failed to open image:")?;
+    let format = ImageFormat::from_path(source).map_err(|e| coded_error!("E0407", "Failed to open image: {}", e))?;
+    let img = image::load_from_memory_with_format(&data, format).map_err(|e| coded_error!("E0407", "Failed to open image: {}", e))?;
+
+    if icc_profile(&data, format).is_some() {
+        warning(format!("{} has an embedded ICC profile, which is ignored. Colors may shift, since the image is treated as sRGB.", source.display()),
+            Some("icc-ignored"), (None, None));
+    }
+
+    match exif::orientation(&data) {
+        Some(orientation) if apply_orientation && [ImageFormat::Jpeg, ImageFormat::Tiff].contains(&format) => {
+            Ok(exif::apply_orientation(img, orientation))
+        },
+        _ => Ok(img)
+    }
+}
+
+/// Converts every PNG, JPG, TGA and DDS image in a folder and its subfolders to a PAA of the same name
+/// in the target folder, like `cmd_paa2img_folder`.
+///
+/// With a job manifest, the jobs are written to it before converting and their results as they
+/// finish. Jobs that are done in it with the same source content and options are skipped, so
+/// running an interrupted or partly failed batch again only converts the rest.
+pub fn cmd_img2paa_folder(source: &Path, target: &Path, paa_type: Option<PaaType>, options: &EncodeOptions, policy: OutputPolicy, manifest: Option<&Path>) -> Result<BatchSummary, std::io::Error> {
+    let images = files_with_extensions(source, &IMAGE_EXTENSIONS).prepend_error("Failed to read source folder:")?;
+    let files: Vec<(PathBuf, PathBuf)> = images.iter().map(|image| (source.join(image), target.join(image).with_extension("paa"))).collect();
+    check_target_collisions(&files)?;
+    if let Some(manifest) = manifest {
+        let options_hash = jobmanifest::options_hash(&options.job_options(paa_type));
+        let manifest = JobManifest::plan(manifest.to_path_buf(), &files, &options_hash)?;
+
+        let pending = manifest.pending();
+        let results = parallel::map_collect(pending.clone(), |index| {
+            let job = manifest.job(index);
+            let result = create_folder(job.target.parent().unwrap())
+                .and_then(|_| cmd_img2paa(&job.source, &job.target, paa_type, options, false, policy, None));
+            manifest.finish(index, &result);
+            result
+        });
+        manifest.save()?;
+
+        let pending_images: Vec<PathBuf> = pending.iter().map(|i| images[*i].clone()).collect();
+        let mut summary = BatchSummary::from_results(&pending_images, results);
+        summary.skipped += images.len() - pending.len();
+        return Ok(summary);
+    }
+
+    let results = parallel::map_collect(images.clone(), |image| {
+        let output = target.join(&image).with_extension("paa");
+        create_folder(output.parent().unwrap())?;
+        cmd_img2paa(&source.join(&image), &output, paa_type, options, false, policy, None)
+    });
+    Ok(BatchSummary::from_results(&images, results))
+}
+
+/// Writes the alpha channel of the base mipmap of a PAA as an 8-bit grayscale image.
+pub fn cmd_paa_alpha_extract(source: &Path, target: &Path, force: bool) -> Result<(), std::io::Error> {
+    let paa = Paa::from_file(source)?;
+    let rgba = paa.stored_image(0, &DecodeOptions::default())?.to_rgba8();
+    let mask = ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| Luma([rgba.get_pixel(x, y)[3]]));
+    let format = ImageFormat::from_path(target).map_err(|e| coded_error!("E0408", "Failed to save image: {}", e))?;
+    OutputPolicy::from_flags(force, false, false)
+        .write(target, |output| mask.write_to(output, format).map_err(|e| coded_error!("E0408", "Failed to save image: {}", e)))
+        .map(|_| ())
+}
+
+/// Converts a PAA to another type without going through an image file, see `Paa::retype`.
+/// Source and target may be the same file.
+pub fn cmd_paa_retype(source: &Path, target: &Path, paa_type: PaaType, allow_lossy_alpha: bool, force: bool) -> Result<(), std::io::Error> {
+    let paa = Paa::from_file(source)?.retype(paa_type, allow_lossy_alpha)?;
+    in_place_policy(source, target, force).write(target, |output| paa.write_to(output)).map(|_| ())
+}
+
+/// Replaces the alpha channel of a PAA with a grayscale mask of the same size.
+///
+/// The texture is decoded and encoded again with its original type (or `retype`) and compression,
+/// so the mipmaps and generated taggs are recreated. Other taggs like the flags are kept.
+pub fn cmd_paa_alpha_inject(source: &Path, mask: &Path, target: &Path, retype: Option<PaaType>, force: bool) -> Result<(), std::io::Error> {
+    let paa = Paa::from_file(source)?;
+    let mut rgba = paa.stored_image(0, &DecodeOptions::default())?.to_rgba8();
+    let mask = image::open(mask).map_err(|e| coded_error!("E0407", "Failed to open mask: {}", e))?.to_luma8();
+
+    if mask.dimensions() != rgba.dimensions() {
+        return Err(coded_error!("E0420", "Mask is {}x{} but the texture is {}x{}.", mask.width(), mask.height(), rgba.width(), rgba.height()));
+    }
+
+    let paa_type = retype.unwrap_or(paa.paa_type);
+    if paa_type == PaaType::DXT1 {
+        warning("DXT1 only stores 1-bit alpha, so most of the mask will be lost. Use \"--retype DXT5\" to keep it.",
+            Some("dxt1-alpha"), (None, None));
+    }
+
+    for (pixel, alpha) in rgba.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = alpha[0];
+    }
+
+    let mut result = Paa::from_image(&DynamicImage::ImageRgba8(rgba), paa_type, paa.is_compressed())?;
+    result.keep_taggs(&paa.taggs);
+    in_place_policy(source, target, force).write(target, |output| result.write_to(output)).map(|_| ())
+}
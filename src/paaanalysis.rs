@@ -0,0 +1,411 @@
+//! Comparison of the PAA formats an image could be encoded with, and the color statistics the
+//! encoder stores in the taggs of a PAA
+//!
+//! Every candidate is encoded with the same code `img2paa` uses and decoded again, so the
+//! reported errors and file sizes are exactly what the written PAA would have. Two existing PAAs
+//! can be compared with each other or with the image they were converted from the same way.
+//!
+//! The statistics take an image of any color type and work on it converted to 8 bits like the
+//! encoder does, so other tools get exactly the values the written texture has.
+
+use std::io::{Cursor, Error, Write};
+use std::path::{Path};
+
+use image::{DynamicImage, GrayAlphaImage, RgbaImage};
+use crate::error::*;
+use crate::paa::{MiniLzo, Paa, PaaType, open_image, parse_paa_type};
+use crate::parallel;
+
+/// Formats compared when no others are requested
+pub const DEFAULT_FORMATS: [PaaType; 3] = [PaaType::DXT1, PaaType::DXT5, PaaType::ARGB4444];
+
+/// Highest mean color difference (CIE76 ΔE) that still counts as visually lossless
+pub const LOSSLESS_DELTA_E_MEAN: f64 = 1.0;
+/// Highest 99th percentile color difference that still counts as visually lossless. 2.3 is
+/// about the smallest difference that is noticeable side by side.
+pub const LOSSLESS_DELTA_E_P99: f64 = 2.3;
+/// Lowest PSNR of the alpha channel that still counts as visually lossless
+pub const LOSSLESS_ALPHA_PSNR: f64 = 40.0;
+
+/// Quality and size of an image encoded with one PAA format
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatAnalysis {
+    /// Candidate format
+    pub paa_type: PaaType,
+    /// PSNR of the color channels in dB, infinite if they are unchanged
+    pub psnr_rgb: f64,
+    /// PSNR of the alpha channel in dB, infinite if it is unchanged
+    pub psnr_alpha: f64,
+    /// Mean CIE76 color difference
+    pub delta_e_mean: f64,
+    /// 99th percentile of the CIE76 color difference
+    pub delta_e_p99: f64,
+    /// Size of the PAA file without compression
+    pub size: usize,
+    /// Size of the PAA file with LZO compression
+    pub size_lzo: usize,
+}
+
+impl FormatAnalysis {
+    /// Returns true if the differences are too small to be seen.
+    pub fn is_visually_lossless(&self) -> bool {
+        self.delta_e_mean <= LOSSLESS_DELTA_E_MEAN && self.delta_e_p99 <= LOSSLESS_DELTA_E_P99 && self.psnr_alpha >= LOSSLESS_ALPHA_PSNR
+    }
+}
+
+/// Parses a comma separated list of formats like `dxt1,dxt5`.
+pub fn parse_formats(input: &str) -> Result<Vec<PaaType>, Error> {
+    let mut formats: Vec<PaaType> = Vec::new();
+    for name in input.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+        let format = parse_paa_type(name)?;
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+
+    if formats.is_empty() {
+        return Err(coded_error!("E0903", "No formats given."));
+    }
+    Ok(formats)
+}
+
+fn psnr(squared_error: f64, count: usize) -> f64 {
+    if squared_error == 0.0 || count == 0 {
+        return f64::INFINITY;
+    }
+    let mse = squared_error / count as f64;
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+fn srgb_to_lab(pixel: &[u8]) -> [f64; 3] {
+    let linear = |v: u8| {
+        let v = v as f64 / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+
+    // D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Compares the decoded image to the source. Colors of fully transparent source pixels are
+/// ignored since they are never visible.
+fn compare(source: &RgbaImage, decoded: &RgbaImage, paa_type: PaaType, size: usize, size_lzo: usize) -> FormatAnalysis {
+    let mut rgb_error = 0.0;
+    let mut alpha_error = 0.0;
+    let mut delta_e: Vec<f64> = Vec::new();
+
+    for (a, b) in source.pixels().zip(decoded.pixels()) {
+        let alpha_diff = a[3] as f64 - b[3] as f64;
+        alpha_error += alpha_diff * alpha_diff;
+
+        if a[3] == 0 {
+            continue;
+        }
+        for channel in 0..3 {
+            let diff = a[channel] as f64 - b[channel] as f64;
+            rgb_error += diff * diff;
+        }
+
+        let (lab_a, lab_b) = (srgb_to_lab(&a.0), srgb_to_lab(&b.0));
+        delta_e.push(lab_a.iter().zip(lab_b.iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f64>().sqrt());
+    }
+
+    delta_e.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let delta_e_mean = if delta_e.is_empty() { 0.0 } else { delta_e.iter().sum::<f64>() / delta_e.len() as f64 };
+    let delta_e_p99 = if delta_e.is_empty() { 0.0 } else { delta_e[((delta_e.len() - 1) as f64 * 0.99).round() as usize] };
+
+    FormatAnalysis {
+        paa_type,
+        psnr_rgb: psnr(rgb_error, delta_e.len() * 3),
+        psnr_alpha: psnr(alpha_error, (source.width() * source.height()) as usize),
+        delta_e_mean,
+        delta_e_p99,
+        size,
+        size_lzo,
+    }
+}
+
+fn file_size(paa: &Paa) -> Result<usize, Error> {
+    let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    paa.write(&mut cursor)?;
+    Ok(cursor.into_inner().len())
+}
+
+/// Encodes the image with the format and returns the analysis with the uncompressed and the LZO
+/// compressed PAA.
+fn analyze_format(img: &DynamicImage, source: &RgbaImage, paa_type: PaaType) -> Result<(FormatAnalysis, Paa, Paa), Error> {
+    let paa = Paa::from_image(img, paa_type, false).prepend_error(format!("Failed to encode {:?}:", paa_type))?;
+    let compressed = paa.to_compressed(&MiniLzo);
+    let decoded = paa.to_image()?.to_rgba8();
+
+    let analysis = compare(source, &decoded, paa_type, file_size(&paa)?, file_size(&compressed)?);
+    Ok((analysis, paa, compressed))
+}
+
+fn encode_formats(img: &DynamicImage, formats: &[PaaType]) -> Result<Vec<(FormatAnalysis, Paa, Paa)>, Error> {
+    let source = img.to_rgba8();
+    parallel::map_collect(formats.to_vec(), |format| analyze_format(img, &source, format))
+}
+
+/// Encodes the image with each of the formats in parallel and compares the decoded result to
+/// the source. The analyses are returned in the order of `formats`.
+pub fn analyze_formats(img: &DynamicImage, formats: &[PaaType]) -> Result<Vec<FormatAnalysis>, Error> {
+    Ok(encode_formats(img, formats)?.into_iter().map(|(analysis, _, _)| analysis).collect())
+}
+
+/// Returns the smallest visually lossless format, or the one with the smallest errors if none
+/// of them is.
+pub fn recommend(analyses: &[FormatAnalysis]) -> Option<&FormatAnalysis> {
+    let lossless = analyses.iter()
+        .filter(|a| a.is_visually_lossless())
+        .min_by_key(|a| a.size_lzo);
+
+    lossless.or_else(|| analyses.iter().min_by(|a, b| {
+        a.delta_e_p99.partial_cmp(&b.delta_e_p99).unwrap()
+            .then(b.psnr_alpha.partial_cmp(&a.psnr_alpha).unwrap())
+    }))
+}
+
+fn format_psnr(psnr: f64) -> String {
+    if psnr.is_infinite() { "inf".to_string() } else { format!("{:.2}", psnr) }
+}
+
+fn format_size(size: usize) -> String {
+    if size < 1024 { format!("{} B", size) } else { format!("{:.1} KiB", size as f64 / 1024.0) }
+}
+
+/// Returns how many dB lower the new PSNR is, 0 if both are infinite.
+pub fn psnr_loss(old: f64, new: f64) -> f64 {
+    if old == new { 0.0 } else { old - new }
+}
+
+/// Returns true if each mipmap of both PAAs decodes to the same pixels.
+fn same_pixels(old: &Paa, new: &Paa) -> Result<bool, Error> {
+    if old.mipmap_count() != new.mipmap_count() {
+        return Ok(false);
+    }
+    for index in 0..old.mipmap_count() {
+        if old.to_image_level(index)?.to_rgba8() != new.to_image_level(index)?.to_rgba8() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Compares the first mipmap of the PAA to the reference, if they are of the same size.
+fn compare_paa(reference: &RgbaImage, paa: &Paa) -> Result<Option<FormatAnalysis>, Error> {
+    let decoded = paa.to_image()?.to_rgba8();
+    if decoded.dimensions() != reference.dimensions() {
+        return Ok(None);
+    }
+    Ok(Some(compare(reference, &decoded, paa.paa_type, file_size(paa)?, 0)))
+}
+
+/// Writes a table of the type, size and mipmaps of two PAAs. Without a source image, the errors
+/// of the new PAA are those against the old one. With one, the errors of both against the source
+/// are listed, and with `max_psnr_loss` the new PAA may be up to that many dB worse than the old
+/// one in color and alpha before they count as different. Returns true if they differ.
+pub fn cmd_paa_compare<O: Write>(old: &Path, new: &Path, source: Option<&Path>, max_psnr_loss: Option<f64>, output: &mut O) -> Result<bool, Error> {
+    let old = Paa::from_file(old).prepend_error("Failed to read old PAA:")?;
+    let new = Paa::from_file(new).prepend_error("Failed to read new PAA:")?;
+
+    let (old_analysis, new_analysis) = match source {
+        Some(source) => {
+            let source = open_image(source, true)?.to_rgba8();
+            match (compare_paa(&source, &old)?, compare_paa(&source, &new)?) {
+                (Some(old), Some(new)) => (Some(old), Some(new)),
+                _ => return Err(coded_error!("E0420", "The source image is of another size than the PAAs."))
+            }
+        },
+        None => (None, compare_paa(&old.to_image()?.to_rgba8(), &new)?)
+    };
+
+    writeln!(output, "{:4} {:9} {:>11} {:>7} {:>10} {:>9} {:>9} {:>8} {:>8}", "", "Type", "Size", "Mipmaps", "File size", "PSNR RGB", "PSNR A", "dE mean", "dE p99")?;
+    for (name, paa, analysis) in [("Old", &old, &old_analysis), ("New", &new, &new_analysis)] {
+        let (width, height) = paa.dimensions(0).unwrap_or_default();
+        let errors = match analysis {
+            Some(a) => format!("{:>9} {:>9} {:>8.2} {:>8.2}", format_psnr(a.psnr_rgb), format_psnr(a.psnr_alpha), a.delta_e_mean, a.delta_e_p99),
+            None => format!("{:>9} {:>9} {:>8} {:>8}", "-", "-", "-", "-")
+        };
+        writeln!(output, "{:4} {:9} {:>11} {:>7} {:>10} {}", name, format!("{:?}", paa.paa_type), format!("{}x{}", width, height),
+            paa.mipmap_count(), format_size(file_size(paa)?), errors)?;
+    }
+
+    if same_pixels(&old, &new)? {
+        writeln!(output, "\nThe PAAs decode to the same pixels.")?;
+        return Ok(false);
+    }
+
+    if let (Some(max), Some(old_analysis), Some(new_analysis)) = (max_psnr_loss, &old_analysis, &new_analysis) {
+        let loss = psnr_loss(old_analysis.psnr_rgb, new_analysis.psnr_rgb).max(psnr_loss(old_analysis.psnr_alpha, new_analysis.psnr_alpha));
+        let within = loss <= max;
+        writeln!(output, "\nThe new PAA is {} dB worse than the old one, {} dB are allowed.", format_psnr(loss.max(0.0)), format_psnr(max))?;
+        return Ok(!within);
+    }
+
+    writeln!(output, "\nThe PAAs differ.")?;
+    Ok(true)
+}
+
+/// Prints a comparison of the formats for the source image and the recommended one. With
+/// `write_best`, the recommended format is written to `target`, LZO compressed if `compress` is
+/// set.
+pub fn cmd_img2paa_analyze(source: &Path, target: Option<&Path>, formats: &[PaaType], write_best: bool, compress: bool, force: bool) -> Result<(), Error> {
+    if write_best {
+        let target = target.ok_or_else(|| coded_error!("E0903", "A target is required to write the best format."))?;
+        if target.exists() && !force {
+            return Err(coded_error!("E0805", "Output file '{}' already exists. Use -f/--force to overwrite.", target.display()));
+        }
+    }
+
+    let img = open_image(source, true)?;
+    let mut results = encode_formats(&img, formats)?;
+    let analyses: Vec<FormatAnalysis> = results.iter().map(|(analysis, _, _)| analysis.clone()).collect();
+
+    println!("{:8} {:>9} {:>9} {:>8} {:>8} {:>10} {:>10}  Lossless", "Format", "PSNR RGB", "PSNR A", "dE mean", "dE p99", "Size", "Size LZO");
+    for a in &analyses {
+        println!("{:8} {:>9} {:>9} {:>8.2} {:>8.2} {:>10} {:>10}  {}", format!("{:?}", a.paa_type), format_psnr(a.psnr_rgb), format_psnr(a.psnr_alpha),
+            a.delta_e_mean, a.delta_e_p99, format_size(a.size), format_size(a.size_lzo), if a.is_visually_lossless() { "yes" } else { "no" });
+    }
+
+    let best = recommend(&analyses).ok_or_else(|| coded_error!("E0903", "No formats to compare."))?;
+    if best.is_visually_lossless() {
+        println!("\nRecommended: {:?}, the smallest visually lossless format.", best.paa_type);
+    } else {
+        println!("\nRecommended: {:?}. None of the formats is visually lossless, this one has the smallest errors.", best.paa_type);
+    }
+
+    if write_best {
+        let index = analyses.iter().position(|a| a.paa_type == best.paa_type).unwrap();
+        let (_, paa, compressed) = results.swap_remove(index);
+        let paa = if compress { compressed } else { paa };
+        paa.write_to_file(target.unwrap()).prepend_error("Failed to write PAA:")?;
+    }
+
+    Ok(())
+}
+
+/// Statistics of the RGBA channels of an image. All channels are 0 for empty images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageStats {
+    /// Mean of each channel, rounded down, stored in the CGVA tagg
+    pub average_color: [u8; 4],
+    /// Largest value of each channel, stored in the CXAM tagg
+    pub maximum_color: [u8; 4],
+    /// Smallest value of each channel
+    pub minimum_color: [u8; 4],
+    /// Whether any pixel isn't fully opaque
+    pub has_alpha: bool,
+    /// Whether every pixel is either fully transparent or fully opaque
+    pub binary_alpha: bool,
+    /// Whether red, green and blue are the same in every pixel
+    pub grayscale: bool,
+}
+
+impl ImageStats {
+    /// Returns the value of the FLAG tagg that fits the alpha channel: none for opaque
+    /// images, 2 (alpha testing) if every pixel is either fully transparent or opaque and 1
+    /// (interpolated alpha) otherwise.
+    pub fn alpha_flag(&self) -> Option<u32> {
+        match (self.has_alpha, self.binary_alpha) {
+            (false, _) => None,
+            (true, true) => Some(2),
+            (true, false) => Some(1),
+        }
+    }
+}
+
+/// Returns the statistics of the image, see `ImageStats`. Grayscale images are analyzed
+/// without converting them to RGBA.
+pub fn analyze(img: &DynamicImage) -> ImageStats {
+    if img.color().has_color() {
+        analyze_rgba(&img.to_rgba8())
+    } else {
+        analyze_luma_alpha(&img.to_luma_alpha8())
+    }
+}
+
+/// Returns the statistics of a grayscale image, whose red, green and blue channels are its
+/// intensity, see `ImageStats`.
+pub fn analyze_luma_alpha(img: &GrayAlphaImage) -> ImageStats {
+    let mut sums = [0u64; 2];
+    let mut maximum = [0u8; 2];
+    let mut minimum = [255u8; 2];
+    let mut binary_alpha = true;
+    for pixel in img.pixels() {
+        for channel in 0..2 {
+            sums[channel] += u64::from(pixel[channel]);
+            maximum[channel] = maximum[channel].max(pixel[channel]);
+            minimum[channel] = minimum[channel].min(pixel[channel]);
+        }
+        binary_alpha &= pixel[1] == 0 || pixel[1] == 255;
+    }
+
+    let count = u64::from(img.width()) * u64::from(img.height());
+    if count == 0 {
+        minimum = [0; 2];
+    }
+    let rgba = |[intensity, alpha]: [u8; 2]| [intensity, intensity, intensity, alpha];
+    ImageStats {
+        average_color: rgba(sums.map(|sum| sum.checked_div(count).unwrap_or(0) as u8)),
+        maximum_color: rgba(maximum),
+        minimum_color: rgba(minimum),
+        has_alpha: count > 0 && minimum[1] != 255,
+        binary_alpha,
+        grayscale: true,
+    }
+}
+
+/// Returns the statistics of an image that is already RGBA, see `ImageStats`.
+pub fn analyze_rgba(rgba: &RgbaImage) -> ImageStats {
+    let mut sums = [0u64; 4];
+    let mut maximum = [0u8; 4];
+    let mut minimum = [255u8; 4];
+    let mut binary_alpha = true;
+    let mut grayscale = true;
+    for pixel in rgba.pixels() {
+        for channel in 0..4 {
+            sums[channel] += u64::from(pixel[channel]);
+            maximum[channel] = maximum[channel].max(pixel[channel]);
+            minimum[channel] = minimum[channel].min(pixel[channel]);
+        }
+        binary_alpha &= pixel[3] == 0 || pixel[3] == 255;
+        grayscale &= pixel[0] == pixel[1] && pixel[1] == pixel[2];
+    }
+
+    let count = u64::from(rgba.width()) * u64::from(rgba.height());
+    if count == 0 {
+        minimum = [0; 4];
+    }
+    ImageStats {
+        average_color: sums.map(|sum| sum.checked_div(count).unwrap_or(0) as u8),
+        maximum_color: maximum,
+        minimum_color: minimum,
+        has_alpha: count > 0 && minimum[3] != 255,
+        binary_alpha,
+        grayscale,
+    }
+}
+
+/// Returns the average color the encoder stores in the CGVA tagg.
+pub fn average_color(img: &DynamicImage) -> [u8; 4] {
+    analyze(img).average_color
+}
+
+/// Returns the maximum color the encoder stores in the CXAM tagg.
+pub fn maximum_color(img: &DynamicImage) -> [u8; 4] {
+    analyze(img).maximum_color
+}
+
+/// Returns the FLAG tagg value that fits the alpha channel, see `ImageStats::alpha_flag`.
+pub fn alpha_flag(img: &DynamicImage) -> Option<u32> {
+    analyze(img).alpha_flag()
+}
@@ -0,0 +1,129 @@
+//! Thread pool shared by all parallel operations
+//!
+//! Nothing is run on rayon's global pool, so the number of threads set here bounds every
+//! parallel code path. With a single thread, work runs sequentially on the calling thread,
+//! which keeps the order of warnings and progress updates deterministic. With more, the
+//! warnings of each item are held back until it and all items before it are done and then
+//! printed in the order of the items, so the output is the same for every run. Progress is
+//! still reported live.
+
+use std::collections::{BTreeMap};
+use std::io::{Error};
+use std::sync::{Arc, Mutex, RwLock};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::iter::{FromParallelIterator, IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSliceMut};
+
+use crate::error::{PendingWarning, WarningBuffer, buffer_warnings, current_warning_buffer, emit_warnings};
+
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// Sets the number of threads used for parallel work. 0 restores the default, one thread per
+/// core.
+pub fn set_thread_count(threads: usize) -> Result<(), Error> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| coded_error!("E0820", "Failed to create thread pool: {}", e))?;
+    *POOL.write().unwrap() = Some(Arc::new(pool));
+    Ok(())
+}
+
+/// Returns the pool, creating one with the default size on first use. `None` if no threads
+/// could be started, in which case work runs sequentially.
+fn pool() -> Option<Arc<ThreadPool>> {
+    if let Some(pool) = POOL.read().unwrap().as_ref() {
+        return Some(pool.clone());
+    }
+
+    let mut pool = POOL.write().unwrap();
+    if pool.is_none() {
+        *pool = ThreadPoolBuilder::new().build().ok().map(Arc::new);
+    }
+    pool.clone()
+}
+
+/// Returns the number of threads used for parallel work.
+pub fn thread_count() -> usize {
+    pool().map(|p| p.current_num_threads()).unwrap_or(1)
+}
+
+/// Passes the warnings of the items of a batch on in the order of the items
+struct OrderedWarnings {
+    /// Buffer of the thread that started the batch, so nested batches end up in the item of the
+    /// outer one they are part of
+    target: Option<WarningBuffer>,
+    /// Index of the next item to pass on and the warnings of the items done after it
+    pending: Mutex<(usize, BTreeMap<usize, Vec<PendingWarning>>)>,
+}
+
+impl OrderedWarnings {
+    fn new() -> OrderedWarnings {
+        OrderedWarnings {
+            target: current_warning_buffer(),
+            pending: Mutex::new((0, BTreeMap::new())),
+        }
+    }
+
+    /// Records the warnings of a finished item and passes on those of all items up to the
+    /// first one still running.
+    fn finish(&self, index: usize, warnings: Vec<PendingWarning>) {
+        let mut pending = self.pending.lock().unwrap();
+        let (next, done) = &mut *pending;
+        done.insert(index, warnings);
+        while let Some(warnings) = done.remove(next) {
+            emit_warnings(self.target.as_ref(), warnings);
+            *next += 1;
+        }
+    }
+}
+
+impl Drop for OrderedWarnings {
+    /// Passes on what is left when items weren't run, like after an error that stopped the batch.
+    fn drop(&mut self) {
+        let (_, done) = &mut *self.pending.lock().unwrap();
+        for warnings in std::mem::take(done).into_values() {
+            emit_warnings(self.target.as_ref(), warnings);
+        }
+    }
+}
+
+/// Maps all items with `f` on the thread pool and collects the results in the order of the
+/// items. Warnings are printed in the order of the items too, see the module documentation.
+pub fn map_collect<T, R, C, F>(items: Vec<T>, f: F) -> C
+where
+    T: Send,
+    R: Send,
+    C: FromIterator<R> + FromParallelIterator<R> + Send,
+    F: Fn(T) -> R + Sync + Send,
+{
+    match pool() {
+        Some(pool) if pool.current_num_threads() > 1 => {
+            let warnings = OrderedWarnings::new();
+            pool.install(|| items.into_par_iter().enumerate().map(|(i, item)| {
+                let buffer: WarningBuffer = Arc::default();
+                let result = buffer_warnings(buffer.clone(), || f(item));
+                warnings.finish(i, std::mem::take(&mut *buffer.lock().unwrap()));
+                result
+            }).collect())
+        },
+        _ => items.into_iter().map(f).collect()
+    }
+}
+
+/// Calls `f` with the index and content of every chunk of `chunk_size` items of `data` on the
+/// thread pool, for work that fills disjoint parts of one buffer like the blocks of a texture.
+/// Unlike `map_collect`, warnings aren't held back, so `f` shouldn't raise any.
+pub fn for_each_chunk_mut<T, F>(data: &mut [T], chunk_size: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync + Send,
+{
+    match pool() {
+        Some(pool) if pool.current_num_threads() > 1 => {
+            pool.install(|| data.par_chunks_mut(chunk_size).enumerate().for_each(|(i, chunk)| f(i, chunk)))
+        },
+        _ => data.chunks_mut(chunk_size).enumerate().for_each(|(i, chunk)| f(i, chunk))
+    }
+}
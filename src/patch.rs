@@ -0,0 +1,304 @@
+//! Functions for creating and applying patches between two versions of a PBO
+//!
+//! A patch reconstructs the new PBO byte for byte, so signatures created for it stay valid. The
+//! header section and the checksum trailer of the new PBO are stored verbatim, entry data is
+//! either reused from the old PBO, stored in full or stored as a delta against the old entry of
+//! the same (normalized) name.
+//!
+//! Both commands hold the PBOs they read in memory, the old one to look entries up by name and
+//! the new one to compute its checksum before writing anything. A patch is read and written in
+//! one pass, but neither PBO is streamed.
+
+use std::collections::{HashMap};
+use std::io::{Read, Write, Error};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use openssl::hash::{Hasher, MessageDigest};
+
+use crate::error::*;
+use crate::io::*;
+use crate::paths::{InternalPath};
+use crate::pbo::{read_layout};
+
+const MAGIC: &[u8; 8] = b"PBOPATCH";
+const VERSION: u32 = 1;
+
+const ENTRY_COPY: u8 = 0;
+const ENTRY_FULL: u8 = 1;
+const ENTRY_DELTA: u8 = 2;
+
+const DELTA_COPY: u8 = 0;
+const DELTA_INSERT: u8 = 1;
+const DELTA_END: u8 = 2;
+
+const BLOCK_SIZE: usize = 16;
+
+fn sha1(data: &[u8]) -> Vec<u8> {
+    let mut h = Hasher::new(MessageDigest::sha1()).unwrap();
+    h.update(data).unwrap();
+    h.finish().unwrap().to_vec()
+}
+
+/// Single operation of an entry delta
+enum DeltaOp {
+    /// Copy `length` bytes starting at `offset` from the old entry
+    Copy(u32, u32),
+    /// Insert the given bytes
+    Insert(Vec<u8>),
+}
+
+fn compute_delta(old: &[u8], new: &[u8]) -> Vec<DeltaOp> {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..old.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        index.entry(&old[offset..(offset + BLOCK_SIZE)]).or_insert(offset);
+    }
+
+    let mut ops: Vec<DeltaOp> = Vec::new();
+    let mut insert_start = 0;
+    let mut i = 0;
+
+    while i + BLOCK_SIZE <= new.len() {
+        match index.get(&new[i..(i + BLOCK_SIZE)]) {
+            Some(&offset) => {
+                let mut length = BLOCK_SIZE;
+                while offset + length < old.len() && i + length < new.len() && old[offset + length] == new[i + length] {
+                    length += 1;
+                }
+
+                if insert_start < i {
+                    ops.push(DeltaOp::Insert(new[insert_start..i].to_vec()));
+                }
+                ops.push(DeltaOp::Copy(offset as u32, length as u32));
+
+                i += length;
+                insert_start = i;
+            },
+            None => {
+                i += 1;
+            }
+        }
+    }
+
+    if insert_start < new.len() {
+        ops.push(DeltaOp::Insert(new[insert_start..].to_vec()));
+    }
+
+    ops
+}
+
+fn write_delta<O: Write>(output: &mut O, ops: &[DeltaOp]) -> Result<(), Error> {
+    for op in ops {
+        match op {
+            DeltaOp::Copy(offset, length) => {
+                output.write_all(&[DELTA_COPY])?;
+                output.write_u32::<LittleEndian>(*offset)?;
+                output.write_u32::<LittleEndian>(*length)?;
+            },
+            DeltaOp::Insert(data) => {
+                output.write_all(&[DELTA_INSERT])?;
+                output.write_u32::<LittleEndian>(field_u32(data.len(), "Size of inserted data")?)?;
+                output.write_all(data)?;
+            }
+        }
+    }
+    output.write_all(&[DELTA_END])?;
+
+    Ok(())
+}
+
+fn apply_delta<I: Read>(input: &mut I, old: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut result: Vec<u8> = Vec::new();
+
+    loop {
+        match input.read_u8()? {
+            DELTA_COPY => {
+                let offset = input.read_u32::<LittleEndian>()? as usize;
+                let length = input.read_u32::<LittleEndian>()? as usize;
+                let end = offset.checked_add(length).filter(|end| *end <= old.len())
+                    .ok_or_else(|| coded_error!("E0317", "Delta copies beyond the end of the old entry."))?;
+                result.extend_from_slice(&old[offset..end]);
+            },
+            DELTA_INSERT => {
+                let length = input.read_u32::<LittleEndian>()?;
+                read_data(input, length, &mut result)?;
+            },
+            DELTA_END => break,
+            op => return Err(coded_error!("E0317", "Unknown delta operation: {}", op))
+        }
+    }
+
+    Ok(result)
+}
+
+/// Appends `length` bytes of the patch to the buffer.
+fn read_data<I: Read>(input: &mut I, length: u32, buffer: &mut Vec<u8>) -> Result<(), Error> {
+    // Don't trust the declared size for allocation, it may run past the end of the patch
+    let start = buffer.len();
+    input.by_ref().take(u64::from(length)).read_to_end(buffer)?;
+    if buffer.len() - start < length as usize {
+        return Err(coded_error!("E0317", "Patch ends {} bytes into data of {} bytes.", buffer.len() - start, length));
+    }
+    Ok(())
+}
+
+fn read_blob<I: Read>(input: &mut I) -> Result<Vec<u8>, Error> {
+    let length = input.read_u32::<LittleEndian>()?;
+    let mut buffer: Vec<u8> = Vec::new();
+    read_data(input, length, &mut buffer)?;
+    Ok(buffer)
+}
+
+fn write_blob<O: Write>(output: &mut O, data: &[u8]) -> Result<(), Error> {
+    output.write_u32::<LittleEndian>(field_u32(data.len(), "Size of patch data")?)?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+/// Creates a patch that turns the `old` PBO into the `new` one and writes it to output.
+pub fn cmd_pbo_diff<I: Read, J: Read, O: Write>(old: &mut I, new: &mut J, output: &mut O) -> Result<(), Error> {
+    let mut old_bytes: Vec<u8> = Vec::new();
+    old.read_to_end(&mut old_bytes).prepend_error("Failed to read old PBO:")?;
+    let mut new_bytes: Vec<u8> = Vec::new();
+    new.read_to_end(&mut new_bytes).prepend_error("Failed to read new PBO:")?;
+
+    let old_layout = read_layout(&old_bytes).prepend_error("Failed to read old PBO:")?;
+    let new_layout = read_layout(&new_bytes).prepend_error("Failed to read new PBO:")?;
+
+    // Entries with names leaving their folder are never matched and always written in full
+    let old_entries: HashMap<InternalPath, (&str, &[u8])> = old_layout.entries.iter()
+        .filter_map(|(name, range)| Some((InternalPath::new(name).ok()?, (name.as_str(), &old_bytes[range.clone()]))))
+        .collect();
+
+    output.write_all(MAGIC)?;
+    output.write_u32::<LittleEndian>(VERSION)?;
+    output.write_all(&sha1(&old_bytes))?;
+    output.write_all(&sha1(&new_bytes))?;
+    write_blob(output, &new_bytes[..new_layout.header_end])?;
+
+    output.write_u32::<LittleEndian>(field_u32(new_layout.entries.len(), "Number of entries")?)?;
+    for (name, range) in &new_layout.entries {
+        let data = &new_bytes[range.clone()];
+
+        output.write_cstring(name)?;
+        output.write_all(&sha1(data))?;
+
+        match InternalPath::new(name).ok().and_then(|n| old_entries.get(&n)) {
+            Some((old_name, old_data)) if *old_data == data => {
+                output.write_all(&[ENTRY_COPY])?;
+                output.write_cstring(old_name)?;
+            },
+            Some((old_name, old_data)) => {
+                let mut delta: Vec<u8> = Vec::new();
+                write_delta(&mut delta, &compute_delta(old_data, data))?;
+
+                if delta.len() < data.len() {
+                    output.write_all(&[ENTRY_DELTA])?;
+                    output.write_cstring(old_name)?;
+                    output.write_all(&delta)?;
+                } else {
+                    output.write_all(&[ENTRY_FULL])?;
+                    write_blob(output, data)?;
+                }
+            },
+            None => {
+                output.write_all(&[ENTRY_FULL])?;
+                write_blob(output, data)?;
+            }
+        }
+    }
+
+    write_blob(output, &new_bytes[new_layout.data_end..])?;
+
+    let new_names: Vec<InternalPath> = new_layout.entries.iter().filter_map(|(name, _)| InternalPath::new(name).ok()).collect();
+    let removed: Vec<&String> = old_layout.entries.iter()
+        .map(|(name, _)| name)
+        .filter(|name| InternalPath::new(name).map_or(true, |n| !new_names.contains(&n)))
+        .collect();
+    output.write_u32::<LittleEndian>(field_u32(removed.len(), "Number of removed entries")?)?;
+    for name in removed {
+        output.write_cstring(name)?;
+    }
+
+    Ok(())
+}
+
+/// Applies a patch created by `cmd_pbo_diff` to the `old` PBO and writes the new PBO to output.
+///
+/// Fails if the patch was created for a different PBO or if any reconstructed entry doesn't
+/// match the checksum recorded in the patch.
+pub fn cmd_pbo_patch<I: Read, J: Read, O: Write>(old: &mut I, patch: &mut J, output: &mut O) -> Result<(), Error> {
+    let mut old_bytes: Vec<u8> = Vec::new();
+    old.read_to_end(&mut old_bytes).prepend_error("Failed to read old PBO:")?;
+
+    let mut magic = [0; 8];
+    patch.read_exact(&mut magic).prepend_error("Failed to read patch:")?;
+    if &magic != MAGIC {
+        return Err(coded_error!("E0316", "File doesn't seem to be a PBO patch."));
+    }
+
+    let version = patch.read_u32::<LittleEndian>()?;
+    if version != VERSION {
+        return Err(coded_error!("E0316", "Unsupported PBO patch version {}, expected {}.", version, VERSION));
+    }
+
+    let mut old_hash = vec![0; 20];
+    patch.read_exact(&mut old_hash)?;
+    let mut new_hash = vec![0; 20];
+    patch.read_exact(&mut new_hash)?;
+
+    if sha1(&old_bytes) != old_hash {
+        return Err(coded_error!("E0318", "Patch was not created for this PBO."));
+    }
+
+    let old_layout = read_layout(&old_bytes).prepend_error("Failed to read old PBO:")?;
+    let old_entries: HashMap<String, &[u8]> = old_layout.entries.iter()
+        .map(|(name, range)| (name.clone(), &old_bytes[range.clone()]))
+        .collect();
+
+    let mut h = Hasher::new(MessageDigest::sha1()).unwrap();
+
+    let header = read_blob(patch)?;
+    output.write_all(&header)?;
+    h.update(&header).unwrap();
+
+    let num_entries = patch.read_u32::<LittleEndian>()?;
+    for _i in 0..num_entries {
+        let name = patch.read_cstring()?;
+        let mut entry_hash = vec![0; 20];
+        patch.read_exact(&mut entry_hash)?;
+
+        let data = match patch.read_u8()? {
+            ENTRY_COPY => {
+                let source = patch.read_cstring()?;
+                old_entries.get(&source)
+                    .ok_or_else(|| coded_error!("E0318", "Entry \"{}\" referenced by the patch is missing from the old PBO.", source))?
+                    .to_vec()
+            },
+            ENTRY_FULL => read_blob(patch)?,
+            ENTRY_DELTA => {
+                let source = patch.read_cstring()?;
+                let old_data = old_entries.get(&source)
+                    .ok_or_else(|| coded_error!("E0318", "Entry \"{}\" referenced by the patch is missing from the old PBO.", source))?;
+                apply_delta(patch, old_data).prepend_error(format!("Failed to apply delta for \"{}\":", name))?
+            },
+            t => return Err(coded_error!("E0317", "Unknown patch entry type: {}", t))
+        };
+
+        if sha1(&data) != entry_hash {
+            return Err(coded_error!("E0319", "Reconstructed entry \"{}\" doesn't match the patch checksum.", name));
+        }
+
+        output.write_all(&data)?;
+        h.update(&data).unwrap();
+    }
+
+    let trailer = read_blob(patch)?;
+    output.write_all(&trailer)?;
+    h.update(&trailer).unwrap();
+
+    if *h.finish().unwrap() != new_hash[..] {
+        return Err(coded_error!("E0319", "Reconstructed PBO doesn't match the patch checksum."));
+    }
+
+    Ok(())
+}
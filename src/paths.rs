@@ -0,0 +1,133 @@
+//! Engine-style paths
+//!
+//! The game addresses files with backslashes and ignores case, so `\A3\Data_F\foo.paa` and
+//! `a3/data_f/FOO.paa` are the same file. Entry names, prefixes, config references and the
+//! paths on disk they are packed from all mix both, and every comparison of them has to agree.
+//! `InternalPath` is the one normalized form they are compared in.
+
+use std::fmt;
+use std::io::{Error};
+use std::path::{Component, Path, PathBuf};
+
+/// Lowercase path with backslashes and without leading, trailing or repeated ones, e.g.
+/// `a3\data_f\foo.paa`
+///
+/// It never contains `..` or components with a `:`, like drive letters or alternate data
+/// streams, so it stays inside the folder it is converted to a host path in on every platform.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InternalPath(String);
+
+/// Returns why `new` rejects a component, if it does.
+fn invalid_component(component: &str) -> Option<&'static str> {
+    if component == ".." {
+        Some("refers to a parent folder")
+    } else if component.contains(':') {
+        Some("contains a drive letter or stream name")
+    } else if component.contains('\0') {
+        Some("contains a null character")
+    } else {
+        None
+    }
+}
+
+/// Returns the nonempty components other than `.` of a path with either kind of slash.
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['\\', '/']).filter(|c| !c.is_empty() && *c != ".")
+}
+
+impl InternalPath {
+    /// Normalizes a path with either kind of slash. Fails on paths that could leave the folder
+    /// they are resolved in.
+    pub fn new(path: &str) -> Result<InternalPath, Error> {
+        if let Some(reason) = split(path).find_map(invalid_component) {
+            return Err(coded_error!("E0313", "Path \"{}\" {}.", path, reason));
+        }
+
+        let components: Vec<String> = split(path).map(|c| c.to_lowercase()).collect();
+        Ok(InternalPath(components.join("\\")))
+    }
+
+    /// Converts a path on disk inside `root` to the path relative to it. `path` can be relative
+    /// to `root` or start with it. Fails like `entry_name`.
+    pub fn from_host(root: &Path, path: &Path) -> Result<InternalPath, Error> {
+        Self::new(&entry_name(root, path)?)
+    }
+
+    /// Returns the path on disk in `root`. The case is the normalized one, so on case-sensitive
+    /// filesystems it only matches files with lowercase names.
+    pub fn to_host(&self, root: &Path) -> PathBuf {
+        let mut path = root.to_path_buf();
+        path.extend(self.components());
+        path
+    }
+
+    /// Returns the normalized path.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns whether this is the empty path, e.g. of a PBO without prefix.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the folders and file name of the path.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split('\\').filter(|c| !c.is_empty())
+    }
+
+    /// Returns the path of `other` inside this one.
+    pub fn join(&self, other: &InternalPath) -> InternalPath {
+        match (self.is_empty(), other.is_empty()) {
+            (true, _) => other.clone(),
+            (_, true) => self.clone(),
+            _ => InternalPath(format!("{}\\{}", self.0, other.0))
+        }
+    }
+
+    /// Returns the rest of the path if it is inside of `prefix` or equal to it. Only whole
+    /// components match, so `a3\data_f` isn't inside of `a3\data`.
+    pub fn strip_prefix(&self, prefix: &InternalPath) -> Option<InternalPath> {
+        if prefix.is_empty() {
+            return Some(self.clone());
+        }
+        match self.0.strip_prefix(&prefix.0) {
+            Some("") => Some(InternalPath::default()),
+            Some(rest) => rest.strip_prefix('\\').map(|r| InternalPath(r.to_string())),
+            None => None
+        }
+    }
+
+    /// Returns whether the path is inside of `prefix` or equal to it.
+    pub fn starts_with(&self, prefix: &InternalPath) -> bool {
+        self.strip_prefix(prefix).is_some()
+    }
+}
+
+impl fmt::Display for InternalPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Returns the name of the PBO entry a file on disk inside `root` is packed as, with backslashes
+/// but in the case of the file, unlike `InternalPath::from_host`. `path` can be relative to
+/// `root` or start with it. Fails on paths outside of it and on names that aren't valid UTF-8 or
+/// that `InternalPath::new` fails on.
+pub fn entry_name(root: &Path, path: &Path) -> Result<String, Error> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_str()
+                .ok_or_else(|| coded_error!("E0314", "Path \"{}\" isn't valid UTF-8.", path.display()))?),
+            Component::CurDir => {},
+            _ => return Err(coded_error!("E0313", "Path \"{}\" isn't inside of \"{}\".", path.display(), root.display()))
+        }
+    }
+
+    let name = components.join("\\");
+    InternalPath::new(&name)?;
+    Ok(name)
+}
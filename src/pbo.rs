@@ -1,19 +1,101 @@
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr};
-use std::fs::{File, create_dir_all, read_dir};
-use std::io::{Read, Write, Seek, SeekFrom, Error, Cursor};
-use std::path::{PathBuf};
+use std::fs::{File, hard_link, read_dir, remove_file};
+use std::io::{BufReader, Read, Write, Seek, SeekFrom, Error, Cursor, stdout};
+use std::ops::{Range};
+use std::path::{Path, PathBuf};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use linked_hash_map::{LinkedHashMap};
-use openssl::hash::{Hasher, MessageDigest};
+use openssl::hash::{Hasher, MessageDigest, hash};
 use regex::{Regex};
 
 use crate::error::*;
 use crate::io::*;
 use crate::config::*;
 use crate::preprocess::*;
+use crate::progress::{Progress};
+use crate::audio;
 use crate::binarize;
+use crate::buildcache::{BuildCache, HashCache};
+use crate::paa::{Paa};
+use crate::paths::{InternalPath, entry_name};
+use crate::resources::{ResourceCheck, check_resources};
+use crate::texheaders;
+use crate::texpolicy::{TexturePolicy, report_violations};
+use crate::vfs::{VirtualFilesystem};
+use crate::warnings::{begin_warning_scope};
+
+/// Largest PBO the 32-bit entry sizes and offsets of the format can describe
+const MAX_PBO_SIZE: u64 = u32::MAX as u64;
+
+/// Packing method of the version header that starts the header extensions, "Vers"
+const VERSION: u32 = 0x5665_7273;
+/// Packing method of compressed entries, "Cprs"
+const COMPRESSED: u32 = 0x4370_7273;
+/// Packing method of encrypted entries, "Encr"
+const ENCRYPTED: u32 = 0x456e_6372;
+/// Longest entry name considered when looking for the end of the header extensions
+const MAX_ENTRY_NAME: usize = 1024;
+/// Most null bytes between the header extensions and the first entry header that are skipped
+const MAX_HEADER_PADDING: usize = 512;
+
+/// Header extension whose value is sized so the data section of an aligned PBO starts aligned
+const ALIGN_EXTENSION: &str = "$ALIGN$";
+/// Name prefix of the zero filled entries inserted before entries of an aligned PBO
+const PADDING_PREFIX: &str = "$PAD$";
+/// File that keeps all header extensions of an unpacked PBO in their original order
+pub const META_FILE: &str = "$PBO_META$.json";
+/// File with the prefix and other header extensions of a source folder
+const PREFIX_FILE: &str = "$PBOPREFIX$";
+
+/// Files that mark a folder and its subfolders to be packed without binarizing, like the
+/// original armake did
+pub const NOBIN_FILES: [&str; 2] = ["$NOBIN$", "$NOBIN-NOTEST$"];
+
+/// Name of the zero-byte entries that stand for empty folders, see `add_empty_folders`. It is
+/// a marker like `$PBOPREFIX$`, so real placeholder files like `.keep` or `.gitkeep` are packed
+/// and unpacked as the files they are.
+pub const EMPTY_FOLDER_FILE: &str = "$EMPTYDIR$";
+
+/// Layout of the entry listing printed by `inspect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListingFormat {
+    /// Table of all headers with packing method, sizes and data offsets
+    #[default]
+    Native,
+    /// Tab separated lines in the layout of Mikero's `ExtractPbo -L`, for scripts written
+    /// against it
+    Mikero,
+}
+
+/// Parses the name of a listing format, `native` or `mikero`.
+pub fn parse_listing_format(input: &str) -> Result<ListingFormat, Error> {
+    match input.to_lowercase().as_str() {
+        "native" => Ok(ListingFormat::Native),
+        "mikero" => Ok(ListingFormat::Mikero),
+        _ => Err(coded_error!("E0901", "Unknown listing format \"{}\", expected native or mikero.", input))
+    }
+}
+
+/// Formats a unix timestamp as `dd/mm/yyyy hh:mm:ss` in UTC.
+fn format_timestamp(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:02}/{:02}/{} {:02}:{:02}:{:02}", day, month, year, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
 
 struct PBOHeader {
     filename: String,
@@ -24,6 +106,20 @@ struct PBOHeader {
     data_size: u32,
 }
 
+/// Deviations from the regular header layout in PBOs from very old tools, kept so the headers are
+/// written the same way again
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LegacyHeader {
+    /// Original size, reserved field, timestamp and data size of the version header, which
+    /// are usually zero
+    version: [u32; 4],
+    /// The header extensions end without an empty name and the first entry header follows
+    /// directly
+    unterminated: bool,
+    /// Null bytes between the end of the header extensions and the first entry header
+    padding: usize,
+}
+
 /// PBO file
 ///
 /// # Examples
@@ -42,11 +138,16 @@ struct PBOHeader {
 /// ```
 pub struct PBO {
     pub files: LinkedHashMap<String, Cursor<Box<[u8]>>>,
-    pub header_extensions: HashMap<String, String>,
+    pub header_extensions: LinkedHashMap<String, String>,
     headers: Vec<PBOHeader>,
+    /// Header quirks of a PBO that was read, reproduced when it is written without alignment
+    legacy_header: Option<LegacyHeader>,
     /// only defined when reading existing PBOs, for created PBOs this is calculated during writing
     /// and included in the output
     pub checksum: Option<Vec<u8>>,
+    /// Number of bytes after the checksum of a PBO that was read, like metadata appended by
+    /// download managers. They are dropped when the PBO is written.
+    pub trailing_data: usize,
 }
 
 impl PBOHeader {
@@ -61,6 +162,17 @@ impl PBOHeader {
         })
     }
 
+    fn read_lenient<I: Read>(input: &mut I) -> Result<PBOHeader, Error> {
+        Ok(PBOHeader {
+            filename: input.read_cstring_lossy()?,
+            packing_method: input.read_u32::<LittleEndian>()?,
+            original_size: input.read_u32::<LittleEndian>()?,
+            reserved: input.read_u32::<LittleEndian>()?,
+            timestamp: input.read_u32::<LittleEndian>()?,
+            data_size: input.read_u32::<LittleEndian>()?,
+        })
+    }
+
     fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
         output.write_cstring(&self.filename)?;
         output.write_u32::<LittleEndian>(self.packing_method)?;
@@ -72,21 +184,18 @@ impl PBOHeader {
     }
 }
 
-fn matches_glob(s: &str, pattern: &str) -> bool {
+pub(crate) fn matches_glob(s: &str, pattern: &str) -> bool {
     if let Some(index) = pattern.find('*') {
-        if s[..index] != pattern[..index] { return false; }
-
-        for i in (index+1)..(s.len()-1) {
-            if matches_glob(&s[i..].to_string(), &pattern[(index+1)..].to_string()) { return true; }
-        }
+        if !s.starts_with(&pattern[..index]) { return false; }
 
-        false
+        // The star matches any number of characters, including none
+        (index..=s.len()).filter(|i| s.is_char_boundary(*i)).any(|i| matches_glob(&s[i..], &pattern[(index+1)..]))
     } else {
         s == pattern
     }
 }
 
-fn file_allowed(name: &str, exclude_patterns: &[String]) -> bool {
+pub(crate) fn file_allowed(name: &str, exclude_patterns: &[String]) -> bool {
     for pattern in exclude_patterns {
         if matches_glob(&name, &pattern) { return false; }
     }
@@ -94,141 +203,613 @@ fn file_allowed(name: &str, exclude_patterns: &[String]) -> bool {
     true
 }
 
-impl PBO {
-    /// Reads an existing PBO from input.
-    pub fn read<I: Read>(input: &mut I) -> Result<PBO, Error> {
-        let mut headers: Vec<PBOHeader> = Vec::new();
-        let mut first = true;
-        let mut header_extensions: HashMap<String, String> = HashMap::new();
+/// Returns true if the entry name can't escape the folder it is unpacked into.
+fn is_contained_entry_name(name: &str) -> bool {
+    !name.starts_with(['\\', '/']) && InternalPath::new(name).is_ok()
+}
 
-        loop {
-            let header = PBOHeader::read(input)?;
-            // todo: garbage filter
+/// Makes an entry name safe to use as a relative path on any filesystem.
+fn sanitize_entry_name(name: &str) -> String {
+    let components: Vec<String> = name.split(['\\', '/'])
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .map(|c| {
+            let replaced: String = c.chars().map(|x| if x.is_control() || "<>:\"|?*".contains(x) { '_' } else { x }).collect();
+            let trimmed = replaced.trim_end_matches(['.', ' ']);
+            if trimmed.is_empty() { "_".to_string() } else { trimmed.to_string() }
+        })
+        .collect();
 
-            if header.packing_method == 0x5665_7273 {
-                if !first { unreachable!(); }
+    if components.is_empty() {
+        "unnamed".to_string()
+    } else {
+        components.join("\\")
+    }
+}
 
-                loop {
-                    let s = input.read_cstring()?;
-                    if s.is_empty() { break; }
+/// Appends a numeric suffix to the sanitized name until it doesn't collide (ignoring case and
+/// slash direction) with a name in `taken`.
+fn deduplicate_entry_name(name: String, taken: &HashSet<InternalPath>) -> String {
+    // Sanitized names never leave their folder
+    let is_taken = |name: &str| InternalPath::new(name).is_ok_and(|n| taken.contains(&n));
+    if !is_taken(&name) {
+        return name;
+    }
 
-                    header_extensions.insert(s, input.read_cstring()?);
-                }
-            } else if header.filename == "" {
-                break;
+    let (stem, ext) = match name.rfind('.') {
+        Some(index) if index > name.rfind('\\').map(|i| i + 1).unwrap_or(0) => (&name[..index], &name[index..]),
+        _ => (&name[..], "")
+    };
+
+    let mut i = 1;
+    loop {
+        let candidate = format!("{}_{}{}", stem, i, ext);
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+/// Returns true for the zero filled entries inserted by `write_aligned`.
+fn is_padding_entry(name: &str, data: &[u8]) -> bool {
+    name.starts_with(PADDING_PREFIX) && data.iter().all(|b| *b == 0)
+}
+
+/// Reader over the header section that can look ahead, to tell the header quirks of old tools
+/// apart from regular headers
+struct HeaderReader<'a, I: Read> {
+    input: &'a mut I,
+    /// Bytes read ahead, of which those from `position` on weren't consumed yet
+    ahead: Vec<u8>,
+    position: usize,
+}
+
+impl<'a, I: Read> HeaderReader<'a, I> {
+    fn new(input: &'a mut I) -> HeaderReader<'a, I> {
+        HeaderReader { input, ahead: Vec::new(), position: 0 }
+    }
+
+    /// Returns the next `n` bytes without consuming them, fewer at the end of the input.
+    fn peek(&mut self, n: usize) -> Result<&[u8], Error> {
+        let available = self.ahead.len() - self.position;
+        if available < n {
+            self.input.by_ref().take((n - available) as u64).read_to_end(&mut self.ahead)?;
+        }
+        let end = self.ahead.len().min(self.position + n);
+        Ok(&self.ahead[self.position..end])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.position = (self.position + n).min(self.ahead.len());
+    }
+
+    /// Returns the bytes that were read ahead past the headers.
+    fn into_rest(self) -> Vec<u8> {
+        self.ahead[self.position..].to_vec()
+    }
+}
+
+impl<I: Read> Read for HeaderReader<'_, I> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.position < self.ahead.len() {
+            let n = buf.len().min(self.ahead.len() - self.position);
+            buf[..n].copy_from_slice(&self.ahead[self.position..(self.position + n)]);
+            self.position += n;
+            return Ok(n);
+        }
+        self.input.read(buf)
+    }
+}
+
+/// Returns whether the bytes start with an entry header whose fields are consistent, and the
+/// length of its name if so.
+fn plausible_entry_header(bytes: &[u8]) -> Option<usize> {
+    let length = bytes.iter().take(MAX_ENTRY_NAME + 1).position(|b| *b == 0)?;
+    let name = std::str::from_utf8(&bytes[..length]).ok()?;
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return None;
+    }
+
+    let mut fields = bytes.get((length + 1)..(length + 21))?;
+    let packing_method = fields.read_u32::<LittleEndian>().ok()?;
+    let original_size = fields.read_u32::<LittleEndian>().ok()?;
+    let reserved = fields.read_u32::<LittleEndian>().ok()?;
+    let _timestamp = fields.read_u32::<LittleEndian>().ok()?;
+    let data_size = fields.read_u32::<LittleEndian>().ok()?;
+
+    let consistent = match packing_method {
+        0 => original_size == 0 || original_size == data_size,
+        COMPRESSED | ENCRYPTED => true,
+        _ => false
+    };
+    (consistent && reserved == 0).then_some(length)
+}
+
+/// Reads the header extensions after the version header, detecting where a list without an
+/// empty name at its end stops and null padding after it.
+fn read_extensions<I: Read>(reader: &mut HeaderReader<I>, extensions: &mut LinkedHashMap<String, String>, legacy: &mut LegacyHeader, lenient: bool) -> Result<(), Error> {
+    loop {
+        let ahead = reader.peek(MAX_HEADER_PADDING + MAX_ENTRY_NAME + 21)?;
+
+        if ahead.first() == Some(&0) {
+            // The final header of a PBO without entries also starts with nulls, but isn't
+            // followed by an entry header
+            let zeros = ahead[1..].iter().take(MAX_HEADER_PADDING).take_while(|b| **b == 0).count();
+            if zeros > 0 && plausible_entry_header(&ahead[(1 + zeros)..]).is_some() {
+                legacy.padding = zeros;
+                reader.consume(1 + zeros);
             } else {
-                headers.push(header);
+                reader.consume(1);
+            }
+            return Ok(());
+        }
+
+        // Extension names are never paths, entry names practically always have an extension
+        if let Some(length) = plausible_entry_header(ahead) && ahead[..length].rsplit(|b| *b == b'\\').next().unwrap().contains(&b'.') {
+            legacy.unterminated = true;
+            return Ok(());
+        }
+
+        let key = if lenient { reader.read_cstring_lossy()? } else { reader.read_cstring()? };
+        let value = if lenient { reader.read_cstring_lossy()? } else { reader.read_cstring()? };
+        extensions.insert(key, value);
+    }
+}
+
+/// Headers of a PBO
+struct Headers {
+    entries: Vec<PBOHeader>,
+    extensions: LinkedHashMap<String, String>,
+    legacy: Option<LegacyHeader>,
+    /// Bytes after the headers that were already read while looking for their end
+    rest: Vec<u8>,
+}
+
+/// Reads the headers of a PBO, with the names of entries and extensions made valid UTF-8 if
+/// `lenient` is set. Quirks of old tools are recognized, see `warn_legacy_header`.
+fn read_headers_with<I: Read>(input: &mut I, lenient: bool) -> Result<Headers, Error> {
+    let mut reader = HeaderReader::new(input);
+    let mut entries: Vec<PBOHeader> = Vec::new();
+    let mut extensions: LinkedHashMap<String, String> = LinkedHashMap::new();
+    let mut legacy = LegacyHeader::default();
+    let mut first = true;
+
+    loop {
+        let header = if lenient { PBOHeader::read_lenient(&mut reader)? } else { PBOHeader::read(&mut reader)? };
+        // todo: garbage filter
+
+        if header.packing_method == VERSION && first {
+            legacy.version = [header.original_size, header.reserved, header.timestamp, header.data_size];
+            read_extensions(&mut reader, &mut extensions, &mut legacy, lenient)?;
+        } else if header.packing_method == VERSION && !lenient {
+            return Err(coded_error!("E0301", "Version header after the first header."));
+        } else if header.filename.is_empty() {
+            break;
+        } else {
+            entries.push(header);
+        }
+
+        first = false;
+    }
+
+    Ok(Headers {
+        entries,
+        extensions,
+        legacy: (legacy != LegacyHeader::default()).then_some(legacy),
+        rest: reader.into_rest(),
+    })
+}
+
+/// Raises a `legacy-pbo-header` warning for each quirk of old tools in the headers. Only reading
+/// a whole PBO does, so a file is warned about once however often its entries are looked up.
+fn warn_legacy_header(legacy: Option<&LegacyHeader>) {
+    let Some(legacy) = legacy else { return };
+    if legacy.version != [0; 4] {
+        warning(format!("PBO version header has nonzero fields {:?}, like PBOs from old tools. They are kept when the PBO is written.", legacy.version),
+            Some("legacy-pbo-header"), (None, None));
+    }
+    if legacy.unterminated {
+        warning("PBO header extensions aren't terminated before the first entry header, like in PBOs from old tools. They are read up to the first entry.",
+            Some("legacy-pbo-header"), (None, None));
+    }
+    if legacy.padding > 0 {
+        warning(format!("PBO header extensions are followed by {} null bytes of padding, like in PBOs from old tools. They are skipped.", legacy.padding),
+            Some("legacy-pbo-header"), (None, None));
+    }
+}
+
+fn read_headers<I: Read>(input: &mut I) -> Result<Headers, Error> {
+    read_headers_with(input, false)
+}
+
+/// Reads only the headers of a PBO, returning its prefix and the names of its entries.
+pub(crate) fn read_entry_names<I: Read>(input: &mut I) -> Result<(Option<String>, Vec<String>), Error> {
+    let mut headers = read_headers(input)?;
+    Ok((headers.extensions.remove("prefix"), headers.entries.into_iter().map(|h| h.filename).collect()))
+}
+
+/// Reads the data of a single entry, skipping the data of all others. Names are compared
+/// ignoring case and slash direction. Returns `None` if there is no such entry and fails on
+/// names leaving their folder.
+pub fn read_entry<I: Read + Seek>(input: &mut I, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let name = InternalPath::new(name)?;
+    let headers = read_headers(input)?;
+
+    let mut offset: i64 = -(headers.rest.len() as i64);
+    for header in headers.entries {
+        if InternalPath::new(&header.filename).is_ok_and(|n| n == name) {
+            input.seek(SeekFrom::Current(offset))?;
+            let mut data: Vec<u8> = Vec::new();
+            input.take(u64::from(header.data_size)).read_to_end(&mut data)?;
+            if data.len() < header.data_size as usize {
+                return Err(coded_error!("E0302", "Data for entry \"{}\" runs past the end of the file.", header.filename));
             }
+            return Ok(Some(data));
+        }
+        offset += i64::from(header.data_size);
+    }
+
+    Ok(None)
+}
+
+/// Reads the entries of a PBO one at a time, calling `f` with the name and data of each one
+/// `select` returns true for, so only one entry is held in memory. The data of other entries is
+/// skipped without reading it.
+pub(crate) fn for_each_entry<I: Read + Seek, S: Fn(&str) -> bool, F: FnMut(&str, &[u8]) -> Result<(), Error>>(input: &mut I, select: S, mut f: F) -> Result<(), Error> {
+    let headers = read_headers(input)?;
+    let mut offset = input.stream_position()? - headers.rest.len() as u64;
+    let end = input.seek(SeekFrom::End(0))?;
+
+    let mut data: Vec<u8> = Vec::new();
+    for header in headers.entries {
+        let size = u64::from(header.data_size);
+        if offset + size > end {
+            return Err(coded_error!("E0302", "Data for entry \"{}\" runs past the end of the file.", header.filename));
+        }
+
+        if select(&header.filename) {
+            input.seek(SeekFrom::Start(offset))?;
+            data.clear();
+            input.by_ref().take(size).read_to_end(&mut data)?;
+            f(&header.filename, &data)?;
+        }
+        offset += size;
+    }
+
+    Ok(())
+}
+
+/// Byte layout of a PBO file
+pub(crate) struct PBOLayout {
+    /// End of the header section, where the data of the first entry starts
+    pub header_end: usize,
+    /// Name and data range of every entry, in file order
+    pub entries: Vec<(String, Range<usize>)>,
+    /// End of the data of the last entry, where the checksum trailer starts
+    pub data_end: usize,
+}
 
-            first = false;
+/// Determines the byte layout of a PBO held in memory without copying any entry data.
+pub(crate) fn read_layout(bytes: &[u8]) -> Result<PBOLayout, Error> {
+    let mut cursor = Cursor::new(bytes);
+    let headers = read_headers(&mut cursor)?;
+
+    let header_end = cursor.position() as usize - headers.rest.len();
+    let headers = headers.entries;
+    let mut offset = header_end;
+    let mut entries: Vec<(String, Range<usize>)> = Vec::with_capacity(headers.len());
+
+    for header in headers {
+        let end = offset + header.data_size as usize;
+        if end > bytes.len() {
+            return Err(coded_error!("E0302", "Data for entry \"{}\" ends at byte {}, but the file is only {} bytes. The file exceeds PBO format limits or is truncated.",
+                header.filename, end, bytes.len()));
         }
 
+        entries.push((header.filename, offset..end));
+        offset = end;
+    }
+
+    Ok(PBOLayout {
+        header_end,
+        entries,
+        data_end: offset,
+    })
+}
+
+impl PBO {
+    /// Reads an existing PBO from input.
+    pub fn read<I: Read>(input: &mut I) -> Result<PBO, Error> {
+        let Headers { entries: headers, extensions: header_extensions, legacy, rest } = read_headers(input)?;
+        warn_legacy_header(legacy.as_ref());
+        let mut input = Cursor::new(rest).chain(input);
+
         let mut files: LinkedHashMap<String, Cursor<Box<[u8]>>> = LinkedHashMap::new();
+        let mut offset: u64 = 0;
         for header in &headers {
-            let mut buffer: Box<[u8]> = vec![0; header.data_size as usize].into_boxed_slice();
-            input.read_exact(&mut buffer)?;
-            files.insert(header.filename.clone(), Cursor::new(buffer));
+            // Don't trust the declared size for allocation, it may run past the end of the file
+            let mut buffer: Vec<u8> = Vec::new();
+            input.by_ref().take(u64::from(header.data_size)).read_to_end(&mut buffer)?;
+            offset += u64::from(header.data_size);
+
+            if buffer.len() < header.data_size as usize {
+                return Err(coded_error!("E0302", "Data for entry \"{}\" ends {} bytes into the data section, past the end of the file. The file exceeds PBO format limits or is truncated.",
+                    header.filename, offset));
+            }
+
+            files.insert(header.filename.clone(), Cursor::new(buffer.into_boxed_slice()));
         }
 
-        input.bytes().next();
-        let mut checksum = vec![0; 20];
-        input.read_exact(&mut checksum)?;
+        // The checksum trailer directly follows the data of the last entry, anything after it
+        // is ignored
+        let mut trailer: Vec<u8> = Vec::new();
+        input.read_to_end(&mut trailer)?;
+        if trailer.len() < 21 {
+            return Err(coded_error!("E0303", "Checksum after the data of the last entry is missing or truncated."));
+        }
 
         Ok(PBO {
             files,
             header_extensions,
             headers,
-            checksum: Some(checksum),
+            legacy_header: legacy,
+            checksum: Some(trailer[1..21].to_vec()),
+            trailing_data: trailer.len() - 21,
         })
     }
 
+    /// Reads an existing PBO from input, tolerating the tricks used by mission "protection" tools.
+    ///
+    /// Entry names are sanitized for the filesystem and deduplicated, and entries whose data
+    /// can't be located are skipped with a warning instead of failing. Also returns a list of
+    /// `(sanitized, original)` names for every entry that had to be renamed.
+    pub fn read_lenient<I: Read>(input: &mut I) -> Result<(PBO, Vec<(String, String)>), Error> {
+        let Headers { entries: headers, extensions: header_extensions, legacy, rest } = read_headers_with(input, true)?;
+        warn_legacy_header(legacy.as_ref());
+
+        let mut data: Vec<u8> = rest;
+        input.read_to_end(&mut data)?;
+
+        let mut files: LinkedHashMap<String, Cursor<Box<[u8]>>> = LinkedHashMap::new();
+        let mut renamed: Vec<(String, String)> = Vec::new();
+        let mut taken: HashSet<InternalPath> = HashSet::new();
+        let mut offset: usize = 0;
+
+        for header in &headers {
+            let end = offset + header.data_size as usize;
+            if end > data.len() {
+                warning(format!("Data for entry \"{}\" can't be located, skipping.", header.filename.escape_debug()),
+                    Some("unlocatable-entry"), (None, None));
+                continue;
+            }
+
+            let name = deduplicate_entry_name(sanitize_entry_name(&header.filename), &taken);
+            taken.insert(InternalPath::new(&name)?);
+            if name != header.filename {
+                renamed.push((name.clone(), header.filename.clone()));
+            }
+
+            files.insert(name, Cursor::new(data[offset..end].to_vec().into_boxed_slice()));
+            offset = end;
+        }
+
+        let checksum = if data.len() >= offset + 21 {
+            Some(data[(offset + 1)..(offset + 21)].to_vec())
+        } else {
+            None
+        };
+
+        Ok((PBO {
+            files,
+            header_extensions,
+            headers,
+            legacy_header: legacy,
+            checksum,
+            trailing_data: data.len().saturating_sub(offset + 21),
+        }, renamed))
+    }
+
     /// Constructs a PBO from a directory with optional binarization.
     ///
     /// `exclude_patterns` contains glob patterns to exclude from the PBO, `includefolders` contain
     /// paths to search for absolute includes and should generally include the current working
     /// directory.
-    pub fn from_directory(directory: PathBuf, mut binarize: bool, exclude_patterns: &[String], includefolders: &[PathBuf]) -> Result<PBO, Error> {
-        let file_list = list_files(&directory)?;
-        let mut files: LinkedHashMap<String, Cursor<Box<[u8]>>> = LinkedHashMap::new();
-        let mut header_extensions: HashMap<String,String> = HashMap::new();
+    pub fn from_directory(directory: PathBuf, binarize: bool, exclude_patterns: &[String], includefolders: &[PathBuf]) -> Result<PBO, Error> {
+        Self::from_directory_with_progress(directory, binarize, exclude_patterns, includefolders, None)
+    }
 
-        if directory.join("$NOBIN$").exists() || directory.join("$NOBIN-NOTEST$").exists() {
-            binarize = false;
-        }
+    /// Constructs a PBO from a directory like `from_directory`, calling `progress` after each
+    /// file, including excluded ones.
+    pub fn from_directory_with_progress(directory: PathBuf, binarize: bool, exclude_patterns: &[String], includefolders: &[PathBuf], progress: Option<&dyn Fn(Progress)>) -> Result<PBO, Error> {
+        Self::from_directory_with_cache(directory, binarize, &BuildOptions {
+            pack: PackOptions { excludes: exclude_patterns, progress, ..Default::default() },
+            includefolders,
+            ..Default::default()
+        })
+    }
 
-        for path in file_list {
+    /// Constructs a PBO from a directory like `from_directory_with_progress`, reusing rapified
+    /// configs and binarized files from the cache if their sources didn't change.
+    ///
+    /// Binarized files are only checked against their own source, not the textures or
+    /// `model.cfg` binarize.exe reads alongside them.
+    ///
+    /// `cfgpatches` of the options controls whether rapified `config.cpp` files are checked for
+    /// classes missing from their CfgPatches.
+    ///
+    /// With `dedupe`, files copied as they are that are hard links to one already read, like those
+    /// of `cmd_unpack` with `dedupe`, reuse its data instead of being read again, on Unix. Files
+    /// with the content hash of one already read do as well once an earlier pack stored their
+    /// hash, see `HashCache`. The hashes are kept in the cache folder, or next to the source
+    /// folder without a cache.
+    pub fn from_directory_with_cache(directory: PathBuf, binarize: bool, options: &BuildOptions) -> Result<PBO, Error> {
+        let BuildOptions { pack: PackOptions { excludes: exclude_patterns, progress, dedupe, .. }, includefolders, cache, cfgpatches, .. } = *options;
+        let file_list = list_files(&directory)?;
+        let total = file_list.len();
+        let report = |current: usize, name: &str| if let Some(progress) = progress {
+            progress(Progress {
+                current,
+                total,
+                label: name.to_string(),
+            });
+        };
+        let mut files: LinkedHashMap<String, Cursor<Box<[u8]>>> = LinkedHashMap::new();
+        let mut header_extensions: LinkedHashMap<String, String> = LinkedHashMap::new();
+        let mut meta: Option<LinkedHashMap<String, String>> = None;
+        let mut read_files: HashMap<(u64, u64), String> = HashMap::new();
+        let mut hashes = dedupe.then(|| HashCache::load(cache.map(|c| c.directory().to_path_buf())
+            .unwrap_or_else(|| BuildCache::default_directory(&directory)).as_path()));
+        let mut read_contents: HashMap<String, String> = HashMap::new();
+
+        let nobin: Vec<PathBuf> = file_list.iter()
+            .filter(|path| path.file_name().is_some_and(|name| NOBIN_FILES.iter().any(|n| OsStr::new(n) == name)))
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+
+        for (i, path) in file_list.into_iter().enumerate() {
+            let binarize = binarize && !nobin.iter().any(|folder| path.starts_with(folder));
             let mut relative = path.strip_prefix(&directory).unwrap().to_path_buf();
             if binarize && relative.file_name() == Some(OsStr::new("config.cpp")) {
                 relative = relative.with_file_name("config.bin");
             }
 
-            let mut name: String = relative.to_str().unwrap().replace("/", "\\");
+            let mut name: String = entry_name(&directory, &relative)?;
             let is_binarizable = Regex::new(".(rtm|p3d)$").unwrap().is_match(&name);
 
-            if !file_allowed(&name, &exclude_patterns) { continue; }
+            if !file_allowed(&name, &exclude_patterns) {
+                report(i + 1, &name);
+                continue;
+            }
 
             let mut file = File::open(&path)?;
 
-            if name == "$PBOPREFIX$" {
-                let mut content = String::new();
-                file.read_to_string(&mut content)?;
-                for l in content.lines() {
-                    if l.is_empty() { break; }
-
-                    let eq: Vec<String> = l.split('=').map(|s| s.to_string()).collect();
-                    if eq.len() == 1 {
-                        header_extensions.insert("prefix".to_string(), l.to_string());
-                    } else {
-                        header_extensions.insert(eq[0].clone(), eq[1].clone());
-                    }
-                }
+            if name == PREFIX_FILE {
+                read_prefix_file(&mut file, &mut header_extensions)?;
+            } else if name == META_FILE {
+                meta = Some(read_meta(&mut file).prepend_error(format!("Failed to read {}:", META_FILE))?);
             } else if binarize && vec!["cpp", "rvmat"].contains(&path.extension().unwrap_or_else(|| OsStr::new("")).to_str().unwrap()) {
-                let config = Config::read(&mut file, Some(path.clone()), includefolders).prepend_error("Failed to parse config:")?;
-                let cursor = config.to_cursor()?;
+                let cursor = match cache.and_then(|c| c.get("rapify", &path)) {
+                    Some(artifact) => {
+                        artifact.warnings.iter().for_each(replay_warning);
+                        Cursor::new(artifact.data.into_boxed_slice())
+                    },
+                    None => {
+                        // The CfgPatches check is part of the recorded warnings, a cached config
+                        // already has the classes Fix added
+                        let (parsed, warnings) = record_warnings(|| {
+                            let (mut config, info) = parse_config_file(&mut file, &path, includefolders)?;
+                            if path.file_name() == Some(OsStr::new("config.cpp")) {
+                                check_cfgpatches(&mut config, cfgpatches, &relative);
+                            }
+                            Ok::<_, Error>((config, info))
+                        });
+                        let (config, info) = parsed.prepend_error("Failed to parse config:")?;
+                        let cursor = config.to_cursor()?;
+                        if let Some(cache) = cache {
+                            cache.insert("rapify", &path, &info.includes, &info.probed_includes, cursor.get_ref(), &warnings)?;
+                        }
+                        cursor
+                    }
+                };
 
-                files.insert(name, cursor);
+                files.insert(name.clone(), cursor);
             } else if cfg!(windows) && binarize && is_binarizable {
-                let cursor = binarize::binarize(&path).prepend_error(format!("Failed to binarize {:?}:", relative).to_string())?;
+                let cursor = match cache.and_then(|c| c.get("binarize", &path)) {
+                    Some(artifact) => {
+                        artifact.warnings.iter().for_each(replay_warning);
+                        Cursor::new(artifact.data.into_boxed_slice())
+                    },
+                    None => {
+                        let (binarized, warnings) = record_warnings(|| binarize::binarize(&path));
+                        let cursor = binarized.prepend_error(format!("Failed to binarize {:?}:", relative).to_string())?;
+                        if let Some(cache) = cache {
+                            cache.insert("binarize", &path, &[], &[], cursor.get_ref(), &warnings)?;
+                        }
+                        cursor
+                    }
+                };
 
-                files.insert(name, cursor);
+                files.insert(name.clone(), cursor);
             } else {
                 if is_binarizable && !cfg!(windows) {
                     warning("On non-Windows systems binarize.exe cannot be used; file will be copied as-is.", Some("non-windows-binarization"), (Some(&relative.to_str().unwrap()), None));
                 }
 
-                let mut buffer: Vec<u8> = Vec::new();
-                file.read_to_end(&mut buffer)?;
+                // Hard links and files with a known hash that was read before aren't read again
+                let identity = if dedupe { file_identity(&file) } else { None };
+                let known = hashes.as_ref().and_then(|h| h.get(&path)).map(str::to_string);
+                let original = identity.and_then(|i| read_files.get(&i))
+                    .or_else(|| known.as_ref().and_then(|h| read_contents.get(h)))
+                    .and_then(|n| files.get(n));
+                let data = match original {
+                    Some(original) => original.get_ref().clone(),
+                    None => {
+                        let mut buffer: Vec<u8> = Vec::new();
+                        file.read_to_end(&mut buffer)?;
+                        buffer.into_boxed_slice()
+                    }
+                };
 
                 name = Regex::new(".p3do$").unwrap().replace_all(&name, ".p3d").to_string();
 
-                files.insert(name, Cursor::new(buffer.into_boxed_slice()));
+                if let Some(identity) = identity {
+                    read_files.entry(identity).or_insert_with(|| name.clone());
+                }
+                if let Some(hashes) = &mut hashes {
+                    let hash = known.unwrap_or_else(|| hashes.insert(&path, &data));
+                    read_contents.entry(hash).or_insert_with(|| name.clone());
+                }
+                files.insert(name.clone(), Cursor::new(data));
             }
+
+            report(i + 1, &name);
         }
 
-        if header_extensions.get("prefix").is_none() {
-            let prefix: String = directory.file_name().unwrap().to_str().unwrap().to_string();
-            header_extensions.insert("prefix".to_string(), prefix);
+        // The hashes only save reading files, a pack doesn't fail without them
+        if let Some(hashes) = hashes {
+            let _ = hashes.save();
         }
 
         Ok(PBO {
             files,
-            header_extensions,
+            header_extensions: merge_header_extensions(&directory, header_extensions, meta)?,
             headers: Vec::new(),
+            legacy_header: None,
             checksum: None,
+            trailing_data: 0,
         })
     }
 
     /// Writes PBO to output.
     pub fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+        self.write_aligned(output, 1)
+    }
+
+    /// Writes PBO to output with the data of every non-empty entry starting at a multiple of
+    /// `alignment` bytes, for loaders that memory-map PBOs.
+    ///
+    /// The gaps are filled by zero filled entries named `$PAD$0001` and so on, which keeps the
+    /// data of the real entries unchanged for standard readers. The start of the data section is
+    /// aligned by the length of the `$ALIGN$` header extension. Both are included in the
+    /// checksum like any other entry and header. Padding entries of a PBO that was read are
+    /// dropped and recreated as needed.
+    pub fn write_aligned<O: Write>(&self, output: &mut O, alignment: u32) -> Result<(), Error> {
+        if alignment == 0 {
+            return Err(coded_error!("E0901", "PBO alignment must be at least 1 byte."));
+        }
+        let alignment = alignment as u64;
+
         let mut headers: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 
+        // Alignment depends on the exact header size, so aligned PBOs are always written regularly
+        let legacy = self.legacy_header.clone().filter(|_| alignment == 1).unwrap_or_default();
         let ext_header = PBOHeader {
             filename: "".to_string(),
-            packing_method: 0x5665_7273,
-            original_size: 0,
-            reserved: 0,
-            timestamp: 0,
-            data_size: 0,
+            packing_method: VERSION,
+            original_size: legacy.version[0],
+            reserved: legacy.version[1],
+            timestamp: legacy.version[2],
+            data_size: legacy.version[3],
         };
         ext_header.write(&mut headers)?;
 
@@ -238,43 +819,86 @@ impl PBO {
         }
 
         for (key, value) in self.header_extensions.iter() {
-            if key == "prefix" { continue; }
+            if key == "prefix" || key == ALIGN_EXTENSION { continue; }
 
             headers.write_cstring(key)?;
             headers.write_cstring(value)?;
         }
-        headers.write_cstring("".to_string())?;
 
-        let mut files_sorted: Vec<(String,&Cursor<Box<[u8]>>)> = self.files.iter().map(|(a,b)| (a.clone(),b)).collect();
+        let mut files_sorted: Vec<(String, &[u8])> = self.files.iter()
+            .filter(|(name, cursor)| !is_padding_entry(name, cursor.get_ref()))
+            .map(|(a, b)| (a.clone(), &b.get_ref()[..]))
+            .collect();
         files_sorted.sort_by(|a, b| a.0.to_lowercase().cmp(&b.0.to_lowercase()));
 
-        for (name, cursor) in &files_sorted {
+        // Offsets are relative to the data section here, its start is aligned below
+        let padding: Vec<u8> = vec![0; (alignment - 1) as usize];
+        let mut entries: Vec<(String, &[u8])> = Vec::with_capacity(files_sorted.len());
+        let mut data_size: u64 = 0;
+        let mut padding_entries = 0;
+        for (name, data) in files_sorted {
+            let gap = if data.is_empty() { 0 } else { (alignment - data_size % alignment) % alignment };
+            if gap > 0 {
+                padding_entries += 1;
+                entries.push((format!("{}{:04}", PADDING_PREFIX, padding_entries), &padding[..gap as usize]));
+                data_size += gap;
+            }
+
+            data_size += data.len() as u64;
+            entries.push((name, data));
+        }
+
+        let mut entry_headers: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        for (name, data) in &entries {
             let header = PBOHeader {
                 filename: name.clone(),
                 packing_method: 0,
-                original_size: cursor.get_ref().len() as u32,
+                original_size: field_u32(data.len(), &format!("Size of {}", name))?,
                 reserved: 0,
                 timestamp: 0,
-                data_size: cursor.get_ref().len() as u32,
+                data_size: field_u32(data.len(), &format!("Size of {}", name))?,
             };
 
-            header.write(&mut headers)?;
+            header.write(&mut entry_headers)?;
         }
 
         let header = PBOHeader {
+            filename: "".to_string(),
             packing_method: 0,
-            ..ext_header
+            original_size: 0,
+            reserved: 0,
+            timestamp: 0,
+            data_size: 0,
         };
-        header.write(&mut headers)?;
+        header.write(&mut entry_headers)?;
+
+        if alignment > 1 {
+            let header_size = (headers.get_ref().len() + ALIGN_EXTENSION.len() + 3 + entry_headers.get_ref().len()) as u64;
+            let filler = (alignment - header_size % alignment) % alignment;
+            headers.write_cstring(ALIGN_EXTENSION)?;
+            headers.write_cstring("0".repeat(filler as usize))?;
+        }
+        if !legacy.unterminated {
+            headers.write_cstring("".to_string())?;
+        }
+        headers.write_all(&vec![0; legacy.padding])?;
+        headers.write_all(entry_headers.get_ref())?;
+
+        // Sizes and offsets are 32-bit, so fail before writing anything instead of wrapping
+        let total_size = headers.get_ref().len() as u64 + data_size + 21;
+        if total_size > MAX_PBO_SIZE {
+            return Err(coded_error!("E0305", "PBO would be {} bytes, which exceeds the PBO format limit of {} bytes. Split the files into multiple PBOs.",
+                total_size, MAX_PBO_SIZE));
+        }
 
         let mut h = Hasher::new(MessageDigest::sha1()).unwrap();
 
         output.write_all(headers.get_ref())?;
         h.update(headers.get_ref()).unwrap();
 
-        for (_, cursor) in &files_sorted {
-            output.write_all(cursor.get_ref())?;
-            h.update(cursor.get_ref()).unwrap();
+        for (_, data) in &entries {
+            output.write_all(data)?;
+            h.update(data).unwrap();
         }
 
         output.write_all(&[0])?;
@@ -294,7 +918,7 @@ impl PBO {
     }
 }
 
-fn list_files(directory: &PathBuf) -> Result<Vec<PathBuf>, Error> {
+pub(crate) fn list_files(directory: &PathBuf) -> Result<Vec<PathBuf>, Error> {
     let mut files: Vec<PathBuf> = Vec::new();
 
     for entry in read_dir(directory)? {
@@ -311,24 +935,139 @@ fn list_files(directory: &PathBuf) -> Result<Vec<PathBuf>, Error> {
     Ok(files)
 }
 
+/// Returns the device and inode of a file, which all hard links to it share.
+#[cfg(unix)]
+fn file_identity(file: &File) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    file.metadata().ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_file: &File) -> Option<(u64, u64)> {
+    None
+}
+
+/// What a build does about classes that are missing from the CfgPatches of their config
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CfgPatchesMode {
+    /// Don't check CfgPatches
+    #[default]
+    Ignore,
+    /// Warn about every class that is missing
+    Check,
+    /// Add the missing classes to the first CfgPatches class and warn about them
+    Fix,
+}
+
+fn check_cfgpatches(config: &mut Config, mode: CfgPatchesMode, path: &Path) {
+    let location = (Some(path.to_string_lossy().to_string()), None);
+    match mode {
+        CfgPatchesMode::Ignore => {},
+        CfgPatchesMode::Check => {
+            for entry in config.missing_cfgpatches_entries() {
+                warning(format!("Class \"{}\" of {} is not listed in {}[] of CfgPatches.", entry.class, entry.config_class, entry.array),
+                    Some("cfgpatches-missing-class"), location.clone());
+            }
+        },
+        CfgPatchesMode::Fix => {
+            let added = config.add_missing_cfgpatches_entries();
+            for entry in &added {
+                warning(format!("Class \"{}\" of {} was missing from {}[] of CfgPatches and was added.", entry.class, entry.config_class, entry.array),
+                    Some("cfgpatches-missing-class"), location.clone());
+            }
+            if added.is_empty() && !config.missing_cfgpatches_entries().is_empty() {
+                warning("Classes are missing from CfgPatches, but there is no CfgPatches class to add them to.".to_string(),
+                    Some("cfgpatches-missing-class"), location);
+            }
+        }
+    }
+}
+
+/// Preprocesses and parses a config file, returning the preprocessing info for the files the
+/// result depends on.
+fn parse_config_file(file: &mut File, path: &Path, includefolders: &[PathBuf]) -> Result<(Config, PreprocessInfo), Error> {
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer).prepend_error("Failed to read input file:")?;
+
+    let (preprocessed, info) = preprocess(buffer, Some(path.to_path_buf()), includefolders).prepend_error("Failed to preprocess config:")?;
+    let config = Config::from_preprocessed(&preprocessed, &info, &ParseOptions::default())?;
+
+    Ok((config, info))
+}
+
 pub fn cmd_inspect<I: Read>(input: &mut I) -> Result<(), Error> {
-    let pbo = PBO::read(input).prepend_error("Failed to read PBO:")?;
+    cmd_inspect_with_format(input, &mut stdout(), ListingFormat::Native)
+}
+
+/// Writes a listing of the header extensions and entries of the PBO in the given format.
+///
+/// The Mikero format has one `key=value` line per header extension, `prefix` first and the
+/// others sorted, followed by an empty line and one line per entry in the order of the PBO
+/// headers with the tab separated columns path, original size and timestamp. The timestamp is
+/// an empty column for entries without one, which includes everything armake2 builds. Alignment
+/// padding is left out since ExtractPbo has no equivalent.
+pub fn cmd_inspect_with_format<I: Read, O: Write>(input: &mut I, output: &mut O, format: ListingFormat) -> Result<(), Error> {
+    let mut bytes: Vec<u8> = Vec::new();
+    input.read_to_end(&mut bytes).prepend_error("Failed to read PBO:")?;
+    let pbo = PBO::read(&mut Cursor::new(&bytes)).prepend_error("Failed to read PBO:")?;
+
+    match format {
+        ListingFormat::Native => write_native_listing(&pbo, &bytes, output),
+        ListingFormat::Mikero => write_mikero_listing(&pbo, output)
+    }
+}
+
+fn write_native_listing<O: Write>(pbo: &PBO, bytes: &[u8], output: &mut O) -> Result<(), Error> {
+    let layout = read_layout(bytes).prepend_error("Failed to read PBO:")?;
 
     if !pbo.header_extensions.is_empty() {
-        println!("Header extensions:");
+        writeln!(output, "Header extensions:")?;
         for (key, value) in pbo.header_extensions.iter() {
-            println!("- {}={}", key, value);
+            if key == ALIGN_EXTENSION {
+                writeln!(output, "- {} ({} bytes of alignment padding)", key, value.len())?;
+            } else {
+                writeln!(output, "- {}={}", key, value)?;
+            }
         }
-        println!();
+        writeln!(output)?;
+    }
+
+    writeln!(output, "# Files: {}\n", pbo.files.len())?;
+    if pbo.trailing_data > 0 {
+        writeln!(output, "# Trailing data: {} bytes after the checksum\n", pbo.trailing_data)?;
+    }
+
+    writeln!(output, "Path                                                  Method  Original    Packed      Data")?;
+    writeln!(output, "                                                                  Size      Size    Offset")?;
+    writeln!(output, "==========================================================================================")?;
+    for (header, (_, range)) in pbo.headers.iter().zip(layout.entries.iter()) {
+        writeln!(output, "{:50} {:9} {:9} {:9} {:9}", header.filename, header.packing_method, header.original_size, header.data_size, range.start)?;
+    }
+
+    Ok(())
+}
+
+fn write_mikero_listing<O: Write>(pbo: &PBO, output: &mut O) -> Result<(), Error> {
+    let mut keys: Vec<&String> = pbo.header_extensions.keys().filter(|k| *k != "prefix" && *k != ALIGN_EXTENSION).collect();
+    keys.sort();
+    if let Some(prefix) = pbo.header_extensions.get("prefix") {
+        writeln!(output, "prefix={}", prefix)?;
+    }
+    for key in keys {
+        writeln!(output, "{}={}", key, pbo.header_extensions[key])?;
     }
+    writeln!(output)?;
 
-    println!("# Files: {}\n", pbo.files.len());
+    for header in &pbo.headers {
+        let data = pbo.files.get(&header.filename).map(|c| &c.get_ref()[..]).unwrap_or(&[]);
+        if is_padding_entry(&header.filename, data) {
+            continue;
+        }
 
-    println!("Path                                                  Method  Original    Packed");
-    println!("                                                                  Size      Size");
-    println!("================================================================================");
-    for header in pbo.headers {
-        println!("{:50} {:9} {:9} {:9}", header.filename, header.packing_method, header.original_size, header.data_size);
+        let timestamp = if header.timestamp == 0 { String::new() } else { format_timestamp(header.timestamp) };
+        // Uncompressed entries usually have no original size
+        let size = if header.original_size > 0 { header.original_size } else { header.data_size };
+        writeln!(output, "{}\t{}\t{}", header.filename.replace('/', "\\"), size, timestamp)?;
     }
 
     Ok(())
@@ -349,61 +1088,578 @@ pub fn cmd_cat<I: Read, O: Write>(input: &mut I, output: &mut O, name: &str) ->
     Ok(())
 }
 
-pub fn cmd_unpack<I: Read>(input: &mut I, output: PathBuf, force: bool) -> Result<(), Error> {
-    // Check if output directory exists and has files
-    if output.exists() && !force {
-        // Check if directory is not empty
-        if read_dir(&output).map(|mut d| d.next().is_some()).unwrap_or(false) {
-            return Err(error!("Output directory '{}' already exists and is not empty. Use -f/--force to overwrite.", output.display()));
+/// Separator `cmd_unpack` puts between the folders of entry names in flat mode by default
+pub const DEFAULT_FLAT_SEPARATOR: &str = "__";
+
+/// Returns the entry name with its folders joined by `separator` into a single file name, e.g.
+/// `data__textures__thing.paa` for `data\textures\thing.paa`.
+pub fn flatten_entry_name(name: &str, separator: &str) -> String {
+    name.split(['\\', '/']).filter(|c| !c.is_empty()).collect::<Vec<&str>>().join(separator)
+}
+
+/// Decides where every entry of the PBO is written, failing if two entries would be written to
+/// the same path, ignoring case, or one outside of the output folder.
+fn plan_unpack<F: FnMut(&str) -> Option<PathBuf>>(pbo: &PBO, mut rename: F) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut plan: Vec<(String, PathBuf)> = Vec::new();
+    let mut taken: HashMap<String, &str> = HashMap::new();
+    let mut collisions: Vec<String> = Vec::new();
+
+    for (name, _) in pbo.files.iter().filter(|(name, cursor)| !is_padding_entry(name, cursor.get_ref())) {
+        let Some(path) = rename(name) else { continue };
+
+        if path.is_absolute() || path.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            return Err(coded_error!("E0306", "Entry \"{}\" would be written outside of the output folder as \"{}\".", name, path.display()));
+        }
+
+        // Windows and macOS don't tell names apart by case
+        let key = path.to_string_lossy().to_lowercase();
+        match taken.get(&key) {
+            Some(other) => collisions.push(format!("\"{}\" and \"{}\" would both be written to \"{}\".", other, name, path.display())),
+            None => { taken.insert(key, name); }
         }
+        plan.push((name.clone(), path));
     }
 
-    let pbo = PBO::read(input).prepend_error("Failed to read PBO:")?;
+    if !collisions.is_empty() {
+        return Err(coded_error!("E0307", "Entries collide:\n{}", collisions.join("\n")));
+    }
 
-    create_dir_all(&output).prepend_error("Failed to create output folder:")?;
+    Ok(plan)
+}
 
-    if !pbo.header_extensions.is_empty() {
-        let prefix_path = output.join(PathBuf::from("$PBOPREFIX$"));
-        let mut prefix_file = File::create(prefix_path).prepend_error("Failed to create prefix file:")?;
+/// Length and SHA-1 hash of data, which identify a file or entry by its content
+type ContentKey = (usize, Vec<u8>);
 
-        for (key, value) in pbo.header_extensions.iter() {
-            prefix_file.write_all(format!("{}={}\n", key, value).as_bytes()).prepend_error("Failed to write prefix file:")?;
+fn content_key(data: &[u8]) -> Result<ContentKey, Error> {
+    Ok((data.len(), hash(MessageDigest::sha1(), data)?.to_vec()))
+}
+
+/// Hard links files with the same data as one written before instead of writing the data again.
+///
+/// Only hard links are made, no copy-on-write clones, so linked files share later edits. Where a
+/// link can't be made, like across filesystems or on FAT, the data is written as a copy and the
+/// failure counted for the report.
+#[derive(Debug, Default)]
+struct Deduplicator {
+    written: HashMap<ContentKey, PathBuf>,
+    linked: usize,
+    saved: usize,
+    failed: usize,
+    /// Why the first link that failed couldn't be made
+    first_failure: Option<String>,
+}
+
+impl Deduplicator {
+    /// Links the path to the first file written with the same data, if there is one and the
+    /// filesystem supports hard links, and returns whether it did.
+    fn link(&mut self, path: &Path, data: &[u8]) -> Result<bool, Error> {
+        if data.is_empty() {
+            return Ok(false);
+        }
+
+        let key = content_key(data)?;
+        if let Some(original) = self.written.get(&key) {
+            match hard_link(extended_path(original), extended_path(path)) {
+                Ok(()) => {
+                    self.linked += 1;
+                    self.saved += data.len();
+                    return Ok(true);
+                },
+                Err(e) => {
+                    self.failed += 1;
+                    self.first_failure.get_or_insert_with(|| format!("{} ({})", path.display(), e));
+                }
+            }
+        } else {
+            self.written.insert(key, path.to_path_buf());
         }
+        Ok(false)
     }
 
-    for (file_name, cursor) in pbo.files.iter() {
-        // @todo: windows
-        let path = output.join(PathBuf::from(file_name.replace("\\", pathsep())));
-        create_dir_all(path.parent().unwrap()).prepend_error("Failed to create output folder:")?;
-        let mut file = File::create(path).prepend_error("Failed to open output file:")?;
-        file.write_all(cursor.get_ref()).prepend_error("Failed to write output file:")?;
+    fn report(&self) {
+        eprintln!("Linked {} duplicate files, saving {} bytes.", self.linked, self.saved);
+        if let Some(failure) = &self.first_failure {
+            eprintln!("Copied {} duplicate files that couldn't be hard linked, like {}.", self.failed, failure);
+        }
+    }
+}
+
+/// Fails if any path of the plan is too long to be created in the output folder.
+fn check_path_lengths(output: &Path, plan: &[(String, PathBuf)]) -> Result<(), Error> {
+    plan.iter().try_for_each(|(_, path)| check_path_length(&output.join(path)))
+}
+
+/// Writes the planned entries, whose paths were checked with `check_path_lengths`.
+fn write_entries(pbo: &PBO, output: &Path, plan: &[(String, PathBuf)], mut dedupe: Option<&mut Deduplicator>) -> Result<(), Error> {
+    for (name, path) in plan {
+        let path = output.join(path);
+        let data = pbo.files[name].get_ref();
+        create_folder(path.parent().unwrap())?;
+
+        // Writing into an existing file would also change the files it is hard linked to
+        if extended_path(&path).symlink_metadata().is_ok_and(|m| m.is_file()) {
+            remove_file(extended_path(&path)).prepend_error(format!("Failed to replace {}:", path.display()))?;
+        }
+
+        if let Some(dedupe) = dedupe.as_deref_mut() && dedupe.link(&path, data)? {
+            continue;
+        }
+        let mut file = create_file(&path)?;
+        file.write_all(data).prepend_error("Failed to write output file:")?;
     }
 
     Ok(())
 }
 
-pub fn cmd_pack<O: Write>(input: PathBuf, output: &mut O, headerext: &[String], excludes: &[String]) -> Result<(), Error> {
-    let mut pbo = PBO::from_directory(input, false, excludes, &Vec::new())?;
+/// Writes the entries of the PBO into the output folder at the paths `rename` returns for their
+/// names, relative to the folder. Entries it returns `None` for are skipped. Returns the entry
+/// names with the paths they were written to.
+///
+/// All paths are checked before anything is written, so entries that would be written to the
+/// same path or outside of the output folder fail without touching it.
+pub fn unpack_with<F: FnMut(&str) -> Option<PathBuf>>(pbo: &PBO, output: &Path, rename: F) -> Result<Vec<(String, PathBuf)>, Error> {
+    let plan = plan_unpack(pbo, rename)?;
+    check_path_lengths(output, &plan)?;
+    write_entries(pbo, output, &plan, None)?;
+    Ok(plan)
+}
 
-    for h in headerext {
-        let (key, value) = (h.split('=').nth(0).unwrap(), h.split('=').nth(1).unwrap());
-        pbo.header_extensions.insert(key.to_string(), value.to_string());
+/// Writes the header extensions as a JSON object in their order, leaving out the alignment padding
+/// that is recreated when packing.
+fn write_meta<O: Write>(header_extensions: &LinkedHashMap<String, String>, output: &mut O) -> Result<(), Error> {
+    let meta: serde_json::Map<String, serde_json::Value> = header_extensions.iter()
+        .filter(|(key, _)| *key != ALIGN_EXTENSION)
+        .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())))
+        .collect();
+    serde_json::to_writer_pretty(&mut *output, &meta).map_err(Error::from)?;
+    output.write_all(b"\n")
+}
+
+/// Reads the header extensions of a `$PBOPREFIX$` file, where a line without a key is the prefix.
+fn read_prefix_file<I: Read>(input: &mut I, header_extensions: &mut LinkedHashMap<String, String>) -> Result<(), Error> {
+    let mut content = String::new();
+    input.read_to_string(&mut content)?;
+    for l in content.lines() {
+        if l.is_empty() { break; }
+
+        match l.split_once('=') {
+            Some((key, value)) => header_extensions.insert(key.to_string(), value.to_string()),
+            None => header_extensions.insert("prefix".to_string(), l.to_string())
+        };
+    }
+    Ok(())
+}
+
+/// Combines the header extensions of `$PBOPREFIX$` and the meta file of a source folder, naming
+/// the prefix after the folder if neither has one.
+fn merge_header_extensions(directory: &Path, mut header_extensions: LinkedHashMap<String, String>, meta: Option<LinkedHashMap<String, String>>) -> Result<LinkedHashMap<String, String>, Error> {
+    // The meta file keeps the order, $PBOPREFIX$ only adds the keys it doesn't have
+    if let Some(mut meta) = meta {
+        for (key, value) in header_extensions {
+            match meta.get(&key) {
+                Some(other) if *other != value => {
+                    return Err(coded_error!("E0304", "Header extension \"{}\" is \"{}\" in $PBOPREFIX$ but \"{}\" in {}.", key, value, other, META_FILE));
+                },
+                Some(_) => {},
+                None => { meta.insert(key, value); }
+            }
+        }
+        header_extensions = meta;
     }
 
-    pbo.write(output).prepend_error("Failed to write PBO:")?;
+    if header_extensions.get("prefix").is_none() {
+        let prefix: String = directory.file_name().unwrap().to_str().unwrap().to_string();
+        header_extensions.insert("prefix".to_string(), prefix);
+    }
+
+    Ok(header_extensions)
+}
+
+/// Reads the header extensions a PBO packed from the source folder would have, without reading
+/// any other file.
+pub(crate) fn source_header_extensions(directory: &Path) -> Result<LinkedHashMap<String, String>, Error> {
+    let mut header_extensions: LinkedHashMap<String, String> = LinkedHashMap::new();
+    let prefix_path = directory.join(PREFIX_FILE);
+    if prefix_path.is_file() {
+        read_prefix_file(&mut File::open(&prefix_path)?, &mut header_extensions)?;
+    }
+    let meta_path = directory.join(META_FILE);
+    let meta = match meta_path.is_file() {
+        true => Some(read_meta(&mut File::open(&meta_path)?).prepend_error(format!("Failed to read {}:", META_FILE))?),
+        false => None
+    };
+    merge_header_extensions(directory, header_extensions, meta)
+}
 
+fn read_meta<I: Read>(input: &mut I) -> Result<LinkedHashMap<String, String>, Error> {
+    let meta: serde_json::Map<String, serde_json::Value> = serde_json::from_reader(input).map_err(|e| coded_error!("E0315", "{}", e))?;
+    meta.into_iter().map(|(key, value)| match value {
+        serde_json::Value::String(value) => Ok((key, value)),
+        value => Err(coded_error!("E0315", "Value of \"{}\" is {}, not a string.", key, value))
+    }).collect()
+}
+
+/// Unpacks a PBO into the output folder.
+///
+/// In `lenient` mode entry names are sanitized and deduplicated, entries that can't be located
+/// are skipped and a `$MANIFEST$` file mapping written paths to original entry names is created
+/// for every renamed entry. The default strict mode fails on any of these.
+///
+/// All header extensions are written to `$PBO_META$.json` in their order, which
+/// `PBO::from_directory` reads back, and `$PBOPREFIX$` for other tools.
+///
+/// With a `flat` separator, every entry is written directly into the output folder with its
+/// folders joined by the separator, see `flatten_entry_name`, and the manifest lists every entry.
+///
+/// With `dedupe`, entries with the same data as one written before are hard linked to it instead
+/// of written again, where the filesystem supports it, and copied otherwise. Editing a linked file
+/// changes all of them.
+///
+/// Zero-byte entries are written as empty files. With `keep_empty_dirs`, the `$EMPTYDIR$` entries
+/// of `add_empty_folders` only create their folder, except in flat mode, which has no folders.
+pub fn cmd_unpack<I: Read>(input: &mut I, output: PathBuf, force: bool, lenient: bool, flat: Option<&str>, dedupe: bool, keep_empty_dirs: bool) -> Result<(), Error> {
+    check_unpack_target(&output, force)?;
+
+    let mut deduplicator = Deduplicator::default();
+    unpack_into(input, &output, lenient, flat, dedupe.then_some(&mut deduplicator), keep_empty_dirs)?;
+    if dedupe {
+        deduplicator.report();
+    }
     Ok(())
 }
 
-pub fn cmd_build<O: Write>(input: PathBuf, output: &mut O, headerext: &[String], excludes: &[String], includefolders: &[PathBuf]) -> Result<(), Error> {
-    let mut pbo = PBO::from_directory(input, true, excludes, includefolders)?;
+/// Unpacks every PBO in the source folder and its subfolders like `cmd_unpack` into the folder of
+/// the same name in the target, e.g. `addons/main.pbo` into `addons/main`, and returns how many
+/// there were. With `dedupe`, entries are linked to identical ones of any PBO unpacked before.
+pub fn cmd_unpack_folder(source: &Path, target: &Path, force: bool, lenient: bool, flat: Option<&str>, dedupe: bool, keep_empty_dirs: bool) -> Result<usize, Error> {
+    let pbos = files_with_extensions(source, &["pbo"]).prepend_error(format!("Failed to read {}:", source.display()))?;
+    let outputs: Vec<PathBuf> = pbos.iter().map(|pbo| target.join(pbo.with_extension(""))).collect();
+    for output in &outputs {
+        check_unpack_target(output, force)?;
+    }
 
-    for h in headerext {
-        let (key, value) = (h.split('=').nth(0).unwrap(), h.split('=').nth(1).unwrap());
-        pbo.header_extensions.insert(key.to_string(), value.to_string());
+    let mut deduplicator = Deduplicator::default();
+    for (pbo, output) in pbos.iter().zip(&outputs) {
+        crate::detect::check_pbo_file(&source.join(pbo))?;
+        let mut input = BufReader::new(File::open(source.join(pbo)).prepend_error(format!("Failed to open {}:", pbo.display()))?);
+        unpack_into(&mut input, output, lenient, flat, dedupe.then_some(&mut deduplicator), keep_empty_dirs).prepend_error(format!("Failed to unpack {}:", pbo.display()))?;
+    }
+    if dedupe {
+        deduplicator.report();
     }
+    Ok(pbos.len())
+}
 
-    pbo.write(output).prepend_error("Failed to write PBO:")?;
+fn check_unpack_target(output: &Path, force: bool) -> Result<(), Error> {
+    if !force && read_dir(output).map(|mut d| d.next().is_some()).unwrap_or(false) {
+        return Err(coded_error!("E0805", "Output directory '{}' already exists and is not empty. Use -f/--force to overwrite.", output.display()));
+    }
+    Ok(())
+}
+
+fn unpack_into<I: Read>(input: &mut I, output: &Path, lenient: bool, flat: Option<&str>, dedupe: Option<&mut Deduplicator>, keep_empty_dirs: bool) -> Result<(), Error> {
+    let (pbo, renamed) = if lenient {
+        PBO::read_lenient(input).prepend_error("Failed to read PBO:")?
+    } else {
+        (PBO::read(input).prepend_error("Failed to read PBO:")?, Vec::new())
+    };
 
+    for file_name in pbo.files.keys() {
+        if !is_contained_entry_name(file_name) {
+            return Err(coded_error!("E0306", "Entry \"{}\" would be written outside of the output folder. Use --lenient to sanitize entry names.", file_name));
+        }
+    }
+
+    let mut plan = plan_unpack(&pbo, |name| Some(PathBuf::from(match flat {
+        Some(separator) => flatten_entry_name(name, separator),
+        None => name.replace("\\", pathsep())
+    })))?;
+    let mut empty_folders: Vec<PathBuf> = Vec::new();
+    if keep_empty_dirs && flat.is_none() {
+        plan.retain(|(name, path)| {
+            let marker = is_empty_folder_entry(name, pbo.files[name].get_ref());
+            if marker {
+                empty_folders.push(path.parent().map(Path::to_path_buf).unwrap_or_default());
+            }
+            !marker
+        });
+    }
+
+    check_path_lengths(output, &plan)?;
+    create_folder(output)?;
+
+    let manifest: Vec<(String, &str)> = match flat {
+        Some(_) => {
+            let originals: HashMap<&str, &str> = renamed.iter().map(|(s, o)| (s.as_str(), o.as_str())).collect();
+            plan.iter().map(|(name, path)| (path.to_string_lossy().into_owned(), originals.get(name.as_str()).copied().unwrap_or(name))).collect()
+        },
+        None => renamed.iter().map(|(s, o)| (s.clone(), o.as_str())).collect()
+    };
+    if !manifest.is_empty() {
+        let mut file = create_file(&output.join("$MANIFEST$"))?;
+        for (written, original) in &manifest {
+            file.write_all(format!("{}\t{}\n", written, original.escape_debug()).as_bytes()).prepend_error("Failed to write manifest file:")?;
+        }
+    }
+
+    if !pbo.header_extensions.is_empty() {
+        let prefix_path = output.join(PREFIX_FILE);
+        let mut prefix_file = create_file(&prefix_path)?;
+
+        for (key, value) in pbo.header_extensions.iter().filter(|(key, _)| *key != ALIGN_EXTENSION) {
+            prefix_file.write_all(format!("{}={}\n", key, value).as_bytes()).prepend_error("Failed to write prefix file:")?;
+        }
+
+        let mut meta_file = create_file(&output.join(META_FILE))?;
+        write_meta(&pbo.header_extensions, &mut meta_file).prepend_error("Failed to write meta file:")?;
+    }
+
+    for folder in &empty_folders {
+        create_folder(&output.join(folder))?;
+    }
+    write_entries(&pbo, output, &plan, dedupe)
+}
+
+/// Replaces every PAA entry whose largest side exceeds `max_size` with a downscaled copy, see
+/// `Paa::downscaled`, and returns the number of bytes saved. Textures that can't be downscaled are
+/// kept as they are with a warning.
+pub fn downscale_textures(pbo: &mut PBO, max_size: u32) -> usize {
+    downscale_textures_counted(pbo, max_size).1
+}
+
+/// Downscales like `downscale_textures`, also returning the number of entries that were.
+fn downscale_textures_counted(pbo: &mut PBO, max_size: u32) -> (usize, usize) {
+    let mut downscaled_entries = 0;
+    let mut saved = 0;
+    let mut done: HashMap<ContentKey, Option<Box<[u8]>>> = HashMap::new();
+
+    for (name, cursor) in pbo.files.iter_mut() {
+        if !name.to_lowercase().ends_with(".paa") {
+            continue;
+        }
+
+        // Copies of the same texture are only downscaled once
+        let key = content_key(cursor.get_ref()).ok();
+        if let Some(data) = key.as_ref().and_then(|k| done.get(k)) {
+            if let Some(data) = data {
+                saved += cursor.get_ref().len().saturating_sub(data.len());
+                *cursor = Cursor::new(data.clone());
+                downscaled_entries += 1;
+            }
+            continue;
+        }
+
+        let downscaled = Paa::from_reader(&mut Cursor::new(cursor.get_ref())).and_then(|paa| paa.downscaled(max_size))
+            .and_then(|paa| paa.map(|paa| {
+                let mut buffer: Vec<u8> = Vec::new();
+                paa.write_to(&mut buffer).map(|_| buffer)
+            }).transpose());
+
+        match downscaled {
+            Ok(Some(data)) => {
+                saved += cursor.get_ref().len().saturating_sub(data.len());
+                *cursor = Cursor::new(data.into_boxed_slice());
+                downscaled_entries += 1;
+                if let Some(key) = key {
+                    done.insert(key, Some(cursor.get_ref().clone()));
+                }
+            },
+            Ok(None) => if let Some(key) = key {
+                done.insert(key, None);
+            },
+            Err(e) => warning(format!("Failed to downscale {}, packing the original: {}", name, e), Some("downscale-failed"), (None, None))
+        }
+    }
+
+    (downscaled_entries, saved)
+}
+
+/// Downscales the textures if there is a maximum size, returning the number that were.
+fn apply_texture_max_size(pbo: &mut PBO, texture_max_size: Option<u32>) -> usize {
+    match texture_max_size {
+        Some(max_size) => {
+            let (downscaled, saved) = downscale_textures_counted(pbo, max_size);
+            if downscaled > 0 {
+                warning(format!("Downscaled {} textures to at most {} pixels, which saved {} bytes.", downscaled, max_size, saved),
+                    Some("textures-downscaled"), (None, None));
+            }
+            downscaled
+        },
+        None => 0
+    }
+}
+
+/// What went into a PBO made by `cmd_build` or `cmd_pack`, for `buildreport`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildStats {
+    /// Entries of the PBO
+    pub files: usize,
+    /// Entries that are rapified configs or materials
+    pub configs: usize,
+    /// PAAs that were downscaled for `texture_max_size`
+    pub textures: usize,
+}
+
+impl BuildStats {
+    fn of(pbo: &PBO, textures: usize) -> BuildStats {
+        BuildStats {
+            files: pbo.files.len(),
+            configs: pbo.files.values().filter(|data| data.get_ref().starts_with(b"\0raP")).count(),
+            textures,
+        }
+    }
+}
+
+/// Sets the `key=value` header extensions given on the command line. Keys that are already set,
+/// for example by `$PBO_META$.json`, keep their position.
+fn apply_headerext(pbo: &mut PBO, headerext: &[String]) {
+    for h in headerext {
+        let (key, value) = h.split_once('=').unwrap_or((h, ""));
+        match pbo.header_extensions.get_mut(key) {
+            Some(existing) => *existing = value.to_string(),
+            None => { pbo.header_extensions.insert(key.to_string(), value.to_string()); }
+        }
+    }
+}
+
+/// Adds a zero-byte `$EMPTYDIR$` entry for every empty folder below the directory that isn't
+/// excluded, since PBOs have no entries for folders. Unpacking with `keep_empty_dirs` creates
+/// the folders again instead of the files.
+pub fn add_empty_folders(pbo: &mut PBO, directory: &Path, exclude_patterns: &[String]) -> Result<(), Error> {
+    for folder in list_empty_folders(directory)? {
+        let name = format!("{}\\{}", entry_name(directory, &folder)?, EMPTY_FOLDER_FILE);
+        if file_allowed(&name, exclude_patterns) {
+            pbo.files.insert(name, Cursor::new(Box::new([])));
+        }
+    }
     Ok(())
 }
+
+fn list_empty_folders(directory: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut folders: Vec<PathBuf> = Vec::new();
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if read_dir(&path)?.next().is_none() {
+                folders.push(path);
+            } else {
+                folders.extend(list_empty_folders(&path)?);
+            }
+        }
+    }
+    Ok(folders)
+}
+
+/// Returns true for the entries `add_empty_folders` adds.
+fn is_empty_folder_entry(name: &str, data: &[u8]) -> bool {
+    data.is_empty() && name.rsplit('\\').next() == Some(EMPTY_FOLDER_FILE)
+}
+
+/// Options of `cmd_pack`, which `cmd_build` uses as well
+#[derive(Clone, Copy)]
+pub struct PackOptions<'a> {
+    /// Header extensions as `key=value`, see `apply_headerext`
+    pub headerext: &'a [String],
+    /// Glob patterns of files to leave out
+    pub excludes: &'a [String],
+    /// Start the data of every entry at a multiple of this many bytes, 1 by default
+    pub alignment: u32,
+    /// Downscale PAAs larger than this like `downscale_textures` does
+    pub texture_max_size: Option<u32>,
+    /// Fail on textures the policy doesn't allow
+    pub texture_policy: Option<&'a TexturePolicy>,
+    /// Read hard linked and identical files once, see `PBO::from_directory_with_cache`. The PBO
+    /// is the same.
+    pub dedupe: bool,
+    /// Warn about problems of the sound files, see `audio::check`
+    pub check_audio: bool,
+    /// Pack empty folders as `$EMPTYDIR$` entries, see `add_empty_folders`
+    pub keep_empty_dirs: bool,
+    pub progress: Option<&'a dyn Fn(Progress)>,
+}
+
+impl Default for PackOptions<'_> {
+    fn default() -> Self {
+        PackOptions {
+            headerext: &[],
+            excludes: &[],
+            alignment: 1,
+            texture_max_size: None,
+            texture_policy: None,
+            dedupe: false,
+            check_audio: false,
+            keep_empty_dirs: false,
+            progress: None,
+        }
+    }
+}
+
+/// Options of `cmd_build` besides the ones it shares with `cmd_pack`
+#[derive(Clone, Default)]
+pub struct BuildOptions<'a> {
+    pub pack: PackOptions<'a>,
+    /// Folders included files are searched in
+    pub includefolders: &'a [PathBuf],
+    /// Cache of converted files, see `BuildCache`
+    pub cache: Option<&'a BuildCache>,
+    pub cfgpatches: CfgPatchesMode,
+    /// Where resources referenced by configs are looked up, see `check_resources`
+    pub resources: ResourceCheck,
+    /// PBOs textures of other addons are read from for `texheaders::update_pbo`
+    pub filesystem: Option<&'a VirtualFilesystem>,
+}
+
+/// Packs a folder into a PBO without binarizing anything, see `PackOptions`.
+pub fn cmd_pack<O: Write>(input: PathBuf, output: &mut O, options: &PackOptions) -> Result<BuildStats, Error> {
+    let mut pbo = PBO::from_directory_with_cache(input.clone(), false, &BuildOptions { pack: *options, ..Default::default() })?;
+    if options.keep_empty_dirs {
+        add_empty_folders(&mut pbo, &input, options.excludes)?;
+    }
+    let downscaled = apply_texture_max_size(&mut pbo, options.texture_max_size);
+
+    // Checked after downscaling, like the textures end up in the PBO
+    if let Some(policy) = options.texture_policy {
+        report_violations(&policy.check_pbo(&pbo), true)?;
+    }
+    if options.check_audio {
+        audio::report_problems(&audio::check_pbo(&pbo));
+    }
+
+    apply_headerext(&mut pbo, options.headerext);
+
+    pbo.write_aligned(output, options.alignment).prepend_error("Failed to write PBO:")?;
+
+    Ok(BuildStats::of(&pbo, downscaled))
+}
+
+/// Binarizes and packs a folder into a PBO, see `BuildOptions`.
+pub fn cmd_build<O: Write>(input: PathBuf, output: &mut O, options: &BuildOptions) -> Result<BuildStats, Error> {
+    let pack = &options.pack;
+    // Repeats of deduplicated warnings only count within one PBO
+    begin_warning_scope();
+    // Read from the source models, the textures of binarized ones can't always be read
+    let models = texheaders::model_textures(&input, pack.excludes).prepend_error(format!("Failed to write {}:", texheaders::TEXHEADERS_FILE))?;
+    let mut pbo = PBO::from_directory_with_cache(input.clone(), true, options)?;
+    if pack.keep_empty_dirs {
+        add_empty_folders(&mut pbo, &input, pack.excludes)?;
+    }
+    let downscaled = apply_texture_max_size(&mut pbo, pack.texture_max_size);
+
+    // Checked after downscaling, like the textures end up in the PBO
+    if let Some(policy) = pack.texture_policy {
+        report_violations(&policy.check_pbo(&pbo), true)?;
+    }
+    if pack.check_audio {
+        audio::report_problems(&audio::check_pbo(&pbo));
+    }
+    // Made from the packed textures, since binarize.exe is only run on single models
+    texheaders::update_pbo(&mut pbo, Some(&models), options.filesystem).prepend_error(format!("Failed to write {}:", texheaders::TEXHEADERS_FILE))?;
+
+    apply_headerext(&mut pbo, pack.headerext);
+
+    check_resources(&pbo, &options.resources, &input, options.includefolders)?;
+
+    pbo.write_aligned(output, pack.alignment).prepend_error("Failed to write PBO:")?;
+
+    Ok(BuildStats::of(&pbo, downscaled))
+}
@@ -0,0 +1,137 @@
+//! Searching the entries of PBOs without unpacking them
+
+use std::fs::{File};
+use std::io::{BufReader, Cursor, Error, Write};
+use std::path::{Path, PathBuf};
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::config::{Config};
+use crate::error::*;
+use crate::parallel;
+use crate::paths::{InternalPath};
+use crate::pbo::{for_each_entry, matches_glob};
+use crate::sign::{find_files};
+
+/// Options for `cmd_pbo_grep`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GrepOptions {
+    /// Match letters of either case
+    pub ignore_case: bool,
+    /// Interpret the pattern as a regular expression instead of literal text
+    pub regex: bool,
+    /// Glob patterns of the entry names to search, all entries if empty. Compared ignoring case
+    /// and slash direction.
+    pub entries: Vec<String>,
+    /// Search rapified configs and missions as derapified text
+    pub derap: bool,
+    /// Search binary entries byte by byte instead of skipping them
+    pub binary: bool,
+}
+
+/// Builds the matcher for the pattern.
+fn matcher(pattern: &str, options: &GrepOptions) -> Result<Regex, Error> {
+    let pattern = if options.regex { pattern.to_string() } else { regex::escape(pattern) };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map_err(|e| coded_error!("E0901", "Invalid pattern: {}", e))
+}
+
+/// Returns whether the entry is one of those to search.
+fn entry_selected(name: &str, patterns: &[InternalPath]) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    // Names leaving their folder match no pattern
+    let Ok(name) = InternalPath::new(name) else { return false };
+    patterns.iter().any(|p| matches_glob(name.as_str(), p.as_str()))
+}
+
+/// Returns the matches in one entry as output lines.
+fn grep_entry(pbo: &str, name: &str, data: &[u8], matcher: &Regex, options: &GrepOptions, results: &mut Vec<String>) {
+    let derapified: Vec<u8>;
+    let mut text = data;
+
+    if options.derap && data.starts_with(b"\0raP") {
+        let mut output: Vec<u8> = Vec::new();
+        match Config::read_rapified(&mut Cursor::new(data)).and_then(|c| c.write(&mut output)) {
+            Ok(()) => {
+                derapified = output;
+                text = &derapified;
+            },
+            Err(e) => warning(format!("Failed to derapify {} in {}, searching it as binary: {}", name, pbo, e.to_string().replace('\n', " ")),
+                Some("grep-derapify-failed"), (None, None))
+        }
+    }
+
+    if text.contains(&0) {
+        if options.binary {
+            for found in matcher.find_iter(text) {
+                results.push(format!("{}:{}:@{}: {}", pbo, name, found.start(), String::from_utf8_lossy(found.as_bytes()).escape_debug()));
+            }
+        }
+        return;
+    }
+
+    for (i, line) in text.split(|b| *b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if matcher.is_match(line) {
+            results.push(format!("{}:{}:{}: {}", pbo, name, i + 1, String::from_utf8_lossy(line)));
+        }
+    }
+}
+
+/// Returns the matches in one PBO as output lines, reading one entry at a time.
+fn grep_pbo(path: &Path, matcher: &Regex, entries: &[InternalPath], options: &GrepOptions) -> Result<Vec<String>, Error> {
+    crate::detect::check_pbo_file(path)?;
+    let mut input = BufReader::new(File::open(path)?);
+    let pbo = path.display().to_string();
+
+    let mut results: Vec<String> = Vec::new();
+    for_each_entry(&mut input, |name| entry_selected(name, entries), |name, data| {
+        grep_entry(&pbo, name, data, matcher, options, &mut results);
+        Ok(())
+    })?;
+
+    Ok(results)
+}
+
+/// Searches the entries of PBOs for a pattern and writes every match as `pbo:entry:line: text`.
+/// Folders are searched for PBOs recursively. Text entries are searched line by line, entries
+/// containing null bytes count as binary and are skipped unless `options.binary` is set, which
+/// writes matches as `pbo:entry:@offset: match`.
+///
+/// PBOs are searched in parallel without unpacking them, but the matches are written in the
+/// order of the PBOs and their entries. Returns the number of matches.
+pub fn cmd_pbo_grep<O: Write>(pattern: &str, paths: &[PathBuf], options: &GrepOptions, output: &mut O) -> Result<usize, Error> {
+    let matcher = matcher(pattern, options)?;
+    let entries = options.entries.iter()
+        .map(|p| InternalPath::new(p).prepend_error("Invalid entry pattern:"))
+        .collect::<Result<Vec<InternalPath>, Error>>()?;
+
+    let mut pbos: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = Vec::new();
+            find_files(path, "pbo", true, &mut found).prepend_error(format!("Failed to read {}:", path.display()))?;
+            found.sort();
+            pbos.extend(found);
+        } else {
+            pbos.push(path.clone());
+        }
+    }
+
+    let results: Vec<Result<Vec<String>, Error>> = parallel::map_collect(pbos.clone(), |pbo| grep_pbo(&pbo, &matcher, &entries, options));
+
+    let mut count = 0;
+    for (pbo, result) in pbos.iter().zip(results) {
+        let lines = result.prepend_error(format!("Failed to search {}:", pbo.display()))?;
+        for line in &lines {
+            writeln!(output, "{}", line)?;
+        }
+        count += lines.len();
+    }
+
+    Ok(count)
+}
@@ -3,17 +3,20 @@
 use std::clone::Clone;
 use std::collections::HashMap;
 use std::env::current_dir;
-use std::fs::{File, read_dir};
+use std::fs::{File, read_dir, metadata};
 use std::io::{Read, Write, Error};
 use std::iter::{Sum};
+use std::ops::{Range};
 use std::path::{Path, PathBuf, Component};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime};
 
 use crate::error::*;
+use crate::paths::{InternalPath};
 
-pub mod preprocess_grammar {
-    #![allow(missing_docs)]
-    include!(concat!(env!("OUT_DIR"), "/preprocess_grammar.rs"));
-}
+#[path = "preprocess_grammar.rs"]
+pub mod preprocess_grammar;
 
 /// Macro definition
 #[derive(Clone, Debug)]
@@ -37,6 +40,12 @@ pub enum Directive {
     IfDefDirective(String),
     /// `#ifndef` directive containing the name of the macro
     IfNDefDirective(String),
+    /// `#if __has_include(path)` or `#ifexists path` directive containing the path and whether
+    /// the condition is negated (`#if !__has_include(path)`)
+    HasIncludeDirective(String, bool),
+    /// Any other directive, like `#pragma`, containing the whole line. It is passed through to
+    /// the output for the config parser to deal with.
+    UnknownDirective(String),
     /// `#else` directive
     ElseDirective,
     /// `#endif` directive
@@ -84,7 +93,152 @@ pub struct PreprocessInfo {
     /// `PathBuf` to the file where the line was found. The path may be `None` if the line was in the
     /// original input to `preprocess` and `origin` was not given.
     pub line_origins: Vec<(u32, Option<PathBuf>)>,
-    import_stack: Vec<PathBuf>
+    /// Every file that was included, directly or transitively, in the order they were included.
+    pub includes: Vec<PathBuf>,
+    /// For every include, in the order of `includes`, the file and the range of output lines its
+    /// content ended up in. Ranges of nested includes lie within the range of the including file.
+    pub include_lines: Vec<(PathBuf, Range<usize>)>,
+    /// Paths checked by `__has_include` that didn't exist. Output depends on them as well, since
+    /// it changes once they are created.
+    pub probed_includes: Vec<PathBuf>,
+    import_stack: Vec<PathBuf>,
+    limits: PreprocessLimits,
+    output_size: usize,
+    expanded_size: usize,
+}
+
+/// Limits that stop inputs from making the preprocessor use unbounded memory and time, like
+/// macros that expand to millions of tokens or files that include themselves.
+///
+/// Exceeding a limit is an error naming the macros or includes that caused it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreprocessLimits {
+    /// Largest size of the output in bytes, and of all macro expansions, including arguments
+    /// that aren't used
+    pub max_output_size: usize,
+    /// Largest number of macros being expanded within each other, counting the expansion of
+    /// arguments
+    pub max_macro_depth: usize,
+    /// Largest number of files being included within each other
+    pub max_include_depth: usize,
+}
+
+impl Default for PreprocessLimits {
+    fn default() -> PreprocessLimits {
+        PreprocessLimits {
+            max_output_size: 64 * 1024 * 1024,
+            max_macro_depth: 128,
+            max_include_depth: 64,
+        }
+    }
+}
+
+/// Macros being expanded for one line, outermost first, and the bytes all expansions so far
+/// produced
+struct Expansion {
+    limits: PreprocessLimits,
+    chain: Vec<String>,
+    size: usize,
+}
+
+impl Expansion {
+    fn new(limits: PreprocessLimits, size: usize) -> Expansion {
+        Expansion { limits, chain: Vec::new(), size }
+    }
+
+    fn enter(&mut self, name: &str) -> Result<(), Error> {
+        self.chain.push(name.to_string());
+        if self.chain.len() > self.limits.max_macro_depth {
+            return Err(coded_error!("E0204", "Macro expansion is deeper than {} levels: {}", self.limits.max_macro_depth, self.chain.join(" > ")));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.chain.pop();
+    }
+
+    /// Counts the bytes a macro adds, failing once all expansions get larger than the limit.
+    /// Bytes are only counted where they come from, not again when the result of a macro ends
+    /// up in the one it was used in.
+    fn add(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        self.size += tokens.iter().map(Token::size).sum::<usize>();
+        if self.size > self.limits.max_output_size {
+            return Err(coded_error!("E0205", "Macros expand to more than {} bytes: {}", self.limits.max_output_size, self.chain.join(" > ")));
+        }
+        Ok(())
+    }
+}
+
+impl PreprocessInfo {
+    /// Counts bytes written to the output, failing once it gets larger than the limit.
+    fn add_output(&mut self, size: usize) -> Result<(), Error> {
+        self.output_size += size;
+        if self.output_size > self.limits.max_output_size {
+            return Err(coded_error!("E0205", "Output is larger than {} bytes.", self.limits.max_output_size));
+        }
+        Ok(())
+    }
+}
+
+/// Modification time and parsed lines of a cached include file
+type CachedFile = (SystemTime, Arc<Vec<Line>>);
+
+/// Cache of parsed include files that can be shared between preprocessor runs and threads.
+///
+/// Entries are keyed by path and reused as long as the modification time of the file doesn't
+/// change. Only the parsed lines are cached; macros are still expanded on every run since the
+/// result depends on the definitions at the point of inclusion.
+#[derive(Debug, Default)]
+pub struct IncludeCache {
+    files: Mutex<HashMap<PathBuf, CachedFile>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl IncludeCache {
+    /// Creates an empty cache.
+    pub fn new() -> IncludeCache {
+        IncludeCache::default()
+    }
+
+    /// Returns the parsed lines of the file, reading it only if it isn't cached or has changed.
+    fn load(&self, path: &Path) -> Result<Arc<Vec<Line>>, Error> {
+        let modified = metadata(path)?.modified()?;
+
+        if let Some((cached_modified, lines)) = self.files.lock().unwrap().get(path)
+            && *cached_modified == modified {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(lines.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let lines = Arc::new(read_lines(path)?);
+        self.files.lock().unwrap().insert(path.to_path_buf(), (modified, lines.clone()));
+
+        Ok(lines)
+    }
+
+    /// Returns the modification time of the cached version of the file, if any.
+    pub fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.files.lock().unwrap().get(path).map(|(modified, _)| *modified)
+    }
+
+    /// Returns the number of cache hits and misses so far.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    /// Removes all cached files.
+    pub fn clear(&self) {
+        self.files.lock().unwrap().clear();
+    }
+}
+
+fn read_lines(path: &Path) -> Result<Vec<Line>, Error> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    preprocess_grammar::file(&content).format_error(&Some(path.to_path_buf()), &content)
 }
 
 fn parse_macro(input: &str) -> Macro {
@@ -120,7 +274,7 @@ impl Clone for Token {
 }
 
 impl Definition {
-    fn value(&self, arguments: &Option<Vec<String>>, def_map: &HashMap<String,Definition>, stack: &[Definition]) -> Result<Option<Vec<Token>>, Error> {
+    fn value(&self, arguments: &Option<Vec<String>>, def_map: &HashMap<String,Definition>, stack: &[&str], expansion: &mut Expansion) -> Result<Option<Vec<Token>>, Error> {
         let params = self.parameters.clone().unwrap_or_default();
         let args = arguments.clone().unwrap_or_default();
 
@@ -130,12 +284,14 @@ impl Definition {
 
         let mut tokens = self.value.clone();
 
-        if stack.iter().any(|d| d.name == self.name) {
+        if stack.contains(&self.name.as_str()) {
             return Ok(Some(tokens));
         }
 
-        let mut stack_new: Vec<Definition> = stack.to_vec();
-        stack_new.push(self.clone());
+        let mut stack_new: Vec<&str> = stack.to_vec();
+        stack_new.push(&self.name);
+        expansion.enter(&self.name)?;
+        expansion.add(&tokens)?;
 
         if !params.is_empty() {
             let mut local_map: HashMap<String,Definition> = HashMap::new();
@@ -144,11 +300,11 @@ impl Definition {
                 local_map.insert(key.clone(), value.clone());
             }
 
-            // @todo: handle these errors properly
             for (param, arg) in params.iter().zip(args.iter()) {
-                let mut tokens = preprocess_grammar::tokens(&arg).expect("Failed to parse macro argument");
-                let stack: Vec<Definition> = Vec::new();
-                tokens = Macro::resolve_all(&tokens, &def_map, &stack).expect("Failed to resolve macro arguments");
+                let mut tokens = preprocess_grammar::tokens(arg)
+                    .map_err(|e| coded_error!("E0201", "Failed to parse argument \"{}\" of macro {}: {}", arg, self.name, e))?;
+                let stack: Vec<&str> = Vec::new();
+                tokens = Macro::resolve_all(&tokens, &def_map, &stack, expansion)?;
 
                 local_map.insert(param.clone(), Definition {
                     name: param.clone(),
@@ -158,17 +314,18 @@ impl Definition {
                 });
             }
 
-            tokens = Macro::resolve_all(&tokens, &local_map, &stack_new)?;
+            tokens = Macro::resolve_all(&tokens, &local_map, &stack_new, expansion)?;
         } else {
-            tokens = Macro::resolve_all(&tokens, &def_map, &stack_new)?;
+            tokens = Macro::resolve_all(&tokens, &def_map, &stack_new, expansion)?;
         }
+        expansion.leave();
 
         Ok(Some(tokens))
     }
 }
 
 impl Macro {
-    fn resolve_pseudoargs(&self, def_map: &HashMap<String, Definition>, stack: &[Definition]) -> Result<Vec<Token>, Error> {
+    fn resolve_pseudoargs(&self, def_map: &HashMap<String, Definition>, stack: &[&str], expansion: &mut Expansion) -> Result<Vec<Token>, Error> {
         let mut tokens: Vec<Token> = Vec::new();
         tokens.push(Token::RegularToken(self.name.clone()));
 
@@ -177,9 +334,10 @@ impl Macro {
         }
 
         let (_, without_name) = self.original.split_at(self.name.len());
-        let mut arg_tokens = preprocess_grammar::tokens(&without_name).expect("Failed to parse macro arguments.");
+        let mut arg_tokens = preprocess_grammar::tokens(without_name)
+            .map_err(|e| coded_error!("E0201", "Failed to parse the arguments of macro {}: {}", self.name, e))?;
 
-        arg_tokens = Macro::resolve_all(&arg_tokens, &def_map, &stack)?;
+        arg_tokens = Macro::resolve_all(&arg_tokens, &def_map, &stack, expansion)?;
         for t in arg_tokens {
             tokens.push(t);
         }
@@ -187,10 +345,10 @@ impl Macro {
         Ok(tokens)
     }
 
-    fn resolve(&self, def_map: &HashMap<String, Definition>, stack: &[Definition]) -> Result<Vec<Token>, Error> {
+    fn resolve(&self, def_map: &HashMap<String, Definition>, stack: &[&str], expansion: &mut Expansion) -> Result<Vec<Token>, Error> {
         match def_map.get(&self.name) {
             Some(def) => {
-                let value = def.value(&self.arguments, def_map, stack)?;
+                let value = def.value(&self.arguments, def_map, stack, expansion)?;
 
                 if !def.local && self.quoted {
                     // @todo: complain
@@ -206,26 +364,26 @@ impl Macro {
                         Ok(tokens)
                     }
                 } else {
-                    self.resolve_pseudoargs(def_map, stack)
+                    self.resolve_pseudoargs(def_map, stack, expansion)
                 }
             },
-            None => self.resolve_pseudoargs(def_map, stack)
+            None => self.resolve_pseudoargs(def_map, stack, expansion)
         }
     }
 
-    fn resolve_all(tokens: &[Token], def_map: &HashMap<String, Definition>, stack: &[Definition]) -> Result<Vec<Token>, Error> {
+    fn resolve_all(tokens: &[Token], def_map: &HashMap<String, Definition>, stack: &[&str], expansion: &mut Expansion) -> Result<Vec<Token>, Error> {
         let mut result: Vec<Token> = Vec::new();
 
         for token in tokens {
             match token {
                 Token::MacroToken(m) => {
-                    let resolved = m.resolve(def_map, stack)?;
+                    let resolved = m.resolve(def_map, stack, expansion)?;
                     for t in resolved {
-                        result.push(t);
+                        Token::push(&mut result, t);
                     }
                 },
                 _ => {
-                    result.push(token.clone());
+                    Token::push(&mut result, token.clone());
                 }
             }
         }
@@ -235,6 +393,25 @@ impl Macro {
 }
 
 impl Token {
+    /// Appends the token, joining it with the last one if both are regular tokens. Resolved
+    /// tokens are only ever concatenated, joining them just saves memory.
+    fn push(tokens: &mut Vec<Token>, token: Token) {
+        if let (Some(Token::RegularToken(last)), Token::RegularToken(s)) = (tokens.last_mut(), &token) {
+            last.push_str(s);
+        } else {
+            tokens.push(token);
+        }
+    }
+
+    /// Returns the number of bytes the token adds to the output.
+    fn size(&self) -> usize {
+        match self {
+            Token::RegularToken(s) | Token::NewlineToken(s, _) => s.len(),
+            Token::MacroToken(m) => m.original.len(),
+            _ => 0,
+        }
+    }
+
     fn concat(tokens: &[Token]) -> (String, u32) {
         let mut output = String::new();
         let mut newlines = 0;
@@ -274,10 +451,12 @@ pub fn pathsep() -> &'static str {
     if cfg!(windows) { "\\" } else { "/" }
 }
 
-fn matches_include_path(path: &PathBuf, include_path: &str) -> bool {
-    let include_pathbuf = PathBuf::from(&include_path.replace("\\", pathsep()));
-
-    if path.file_name() != include_pathbuf.file_name() { return false; }
+/// Returns whether the file is the one the include path refers to, with the prefix of the
+/// closest folder above it that has a `$PBOPREFIX$`. Paths are compared like the game does,
+/// ignoring case and slash direction.
+fn matches_include_path(path: &Path, include_path: &InternalPath) -> bool {
+    let file_name = path.file_name().and_then(|n| n.to_str()).map(str::to_lowercase);
+    if file_name.as_deref() != include_path.components().last() { return false; }
 
     for parent in path.ancestors() {
         if parent.is_file() { continue; }
@@ -285,20 +464,9 @@ fn matches_include_path(path: &PathBuf, include_path: &str) -> bool {
         let prefixpath = parent.join("$PBOPREFIX$");
         if !prefixpath.is_file() { continue; }
 
-        let mut prefix = read_prefix(&prefixpath);
-
-        prefix = if !prefix.is_empty() && prefix.chars().nth(0).unwrap() != '\\' {
-            format!("\\{}", prefix)
-        } else {
-            prefix
-        };
-
-        let prefix_pathbuf = PathBuf::from(prefix.replace("\\", pathsep()));
-
-        let relative = path.strip_prefix(parent).unwrap();
-        let test_path = prefix_pathbuf.join(relative);
-
-        if test_path == include_pathbuf {
+        // A prefix leaving its folder can't be included from
+        let Ok(prefix) = InternalPath::new(&read_prefix(&prefixpath)) else { continue };
+        if InternalPath::from_host(parent, path).is_ok_and(|relative| prefix.join(&relative) == *include_path) {
             return true;
         }
     }
@@ -306,7 +474,7 @@ fn matches_include_path(path: &PathBuf, include_path: &str) -> bool {
     false
 }
 
-fn search_directory(include_path: &str, directory: PathBuf) -> Option<PathBuf> {
+fn search_directory(include_path: &str, internal: &InternalPath, directory: PathBuf) -> Option<PathBuf> {
     for entry in read_dir(&directory).unwrap() {
         let path = entry.unwrap().path();
         if path.is_dir() {
@@ -314,10 +482,10 @@ fn search_directory(include_path: &str, directory: PathBuf) -> Option<PathBuf> {
                 continue;
             }
 
-            if let Some(path) = search_directory(include_path, path) {
+            if let Some(path) = search_directory(include_path, internal, path) {
                 return Some(path);
             }
-        } else if matches_include_path(&path, include_path) {
+        } else if matches_include_path(&path, internal) {
             return Some(path);
         }
     }
@@ -347,44 +515,60 @@ fn canonicalize(path: PathBuf) -> PathBuf {
     result
 }
 
-fn find_include_file(include_path: &str, origin: Option<&PathBuf>, search_paths: &[PathBuf]) -> Result<PathBuf, Error> {
-    if include_path.chars().nth(0).unwrap() != '\\' {
-        let mut path = PathBuf::from(include_path.replace("\\", pathsep()));
+fn relative_include_path(include_path: &str, origin: Option<&PathBuf>) -> Result<PathBuf, Error> {
+    let mut path = PathBuf::from(include_path.replace("\\", pathsep()));
 
-        if let Some(origin_path) = origin {
-            let absolute = PathBuf::from(&origin_path).canonicalize()?;
-            let origin_dir = absolute.parent().unwrap();
-            path = origin_dir.join(path);
-        } else {
-            path = current_dir()?.join(path);
-        }
+    if let Some(origin_path) = origin {
+        let absolute = PathBuf::from(&origin_path).canonicalize()?;
+        let origin_dir = absolute.parent().unwrap();
+        path = origin_dir.join(path);
+    } else {
+        path = current_dir()?.join(path);
+    }
 
-        let absolute = canonicalize(path);
+    Ok(canonicalize(path))
+}
+
+fn find_include_file(include_path: &str, origin: Option<&PathBuf>, search_paths: &[PathBuf]) -> Result<PathBuf, Error> {
+    if include_path.chars().nth(0).unwrap() != '\\' {
+        let absolute = relative_include_path(include_path, origin)?;
 
         if !absolute.is_file() {
             match origin {
-                Some(origin_path) => Err(error!("File \"{}\" included from \"{}\" not found.", include_path, origin_path.to_str().unwrap().to_string())),
-                None => Err(error!("Included file \"{}\" not found.", include_path))
+                Some(origin_path) => Err(coded_error!("E0202", "File \"{}\" included from \"{}\" not found.", include_path, origin_path.to_str().unwrap().to_string())),
+                None => Err(coded_error!("E0202", "Included file \"{}\" not found.", include_path))
             }
         } else {
             Ok(absolute)
         }
     } else {
+        let internal = InternalPath::new(include_path).prepend_error("Invalid include path:")?;
         for search_path in search_paths {
-            if let Some(file_path) = search_directory(include_path, search_path.canonicalize()?) {
+            if let Some(file_path) = search_directory(include_path, &internal, search_path.canonicalize()?) {
                 return Ok(file_path);
             }
         }
 
         match origin {
-            Some(origin_path) => Err(error!("File \"{}\" included from \"{}\" not found.", include_path, origin_path.to_str().unwrap().to_string())),
-            None => Err(error!("Included file \"{}\" not found.", include_path))
+            Some(origin_path) => Err(coded_error!("E0202", "File \"{}\" included from \"{}\" not found.", include_path, origin_path.to_str().unwrap().to_string())),
+            None => Err(coded_error!("E0202", "Included file \"{}\" not found.", include_path))
         }
     }
 }
 
-fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut HashMap<String, Definition>, info: &mut PreprocessInfo, includefolders: &[PathBuf]) -> Result<String, Error> {
-    let lines = preprocess_grammar::file(&input).format_error(&origin, &input)?;
+/// Returns the paths where creating a file would make the include resolve. For prefixed paths
+/// these are the direct paths in the search folders, files in folders with a matching
+/// `$PBOPREFIX$` aren't known in advance.
+fn include_candidates(include_path: &str, origin: Option<&PathBuf>, search_paths: &[PathBuf]) -> Vec<PathBuf> {
+    if !include_path.starts_with('\\') {
+        relative_include_path(include_path, origin).into_iter().collect()
+    } else {
+        let relative = include_path[1..].replace("\\", pathsep());
+        search_paths.iter().map(|p| p.canonicalize().unwrap_or_else(|_| p.clone()).join(&relative)).collect()
+    }
+}
+
+fn preprocess_rec(lines: &[Line], origin: Option<PathBuf>, definition_map: &mut HashMap<String, Definition>, info: &mut PreprocessInfo, includefolders: &[PathBuf], cache: Option<&IncludeCache>) -> Result<String, Error> {
     let mut output = String::from("");
     let mut original_lineno = 1;
     let mut level = 0;
@@ -393,7 +577,7 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
     for line in lines {
         match line {
             Line::DirectiveLine(dir, newlines) => {
-                original_lineno += newlines;
+                original_lineno += *newlines;
 
                 match dir {
                     Directive::IncludeDirective(path) => {
@@ -405,15 +589,26 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
                         //    // @todo: complain
                         //}
 
-                        let file_path = find_include_file(&path, origin.as_ref(), includefolders)?;
+                        let file_path = find_include_file(path, origin.as_ref(), includefolders)?;
 
+                        if info.import_stack.len() >= info.limits.max_include_depth {
+                            let chain: Vec<String> = info.import_stack.iter().chain(std::iter::once(&file_path)).map(|p| p.display().to_string()).collect();
+                            return Err(coded_error!("E0203", "Includes are nested deeper than {} levels: {}", info.limits.max_include_depth, chain.join(" > ")));
+                        }
                         info.import_stack.push(file_path.clone());
+                        info.includes.push(file_path.clone());
+                        let start = info.line_origins.len();
+                        let index = info.include_lines.len();
+                        info.include_lines.push((file_path.clone(), start..start));
 
-                        let mut content = String::new();
-                        File::open(&file_path)?.read_to_string(&mut content)?;
-                        let result = preprocess_rec(content, Some(file_path), definition_map, info, includefolders).prepend_error(format!("Failed to preprocess include \"{}\":", path))?;
+                        let included = match cache {
+                            Some(cache) => cache.load(&file_path),
+                            None => read_lines(&file_path).map(Arc::new)
+                        }.prepend_error(format!("Failed to preprocess include \"{}\":", path))?;
+                        let result = preprocess_rec(&included, Some(file_path), definition_map, info, includefolders, cache).prepend_error(format!("Failed to preprocess include \"{}\":", path))?;
 
                         info.import_stack.pop();
+                        info.include_lines[index].1.end = info.line_origins.len();
 
                         output += &result;
                     },
@@ -430,21 +625,46 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
                             // @todo: warn about redefine
                         }
 
-                        definition_map.insert(def.name.clone(), def);
+                        definition_map.insert(def.name.clone(), def.clone());
                     }
                     Directive::UndefDirective(name) => {
                         if level > level_true { continue; }
 
-                        definition_map.remove(&name);
+                        definition_map.remove(name);
                     }
                     Directive::IfDefDirective(name) => {
-                        level_true += if level_true == level && definition_map.contains_key(&name) { 1 } else { 0 };
+                        level_true += if level_true == level && definition_map.contains_key(name) { 1 } else { 0 };
                         level += 1;
                     }
                     Directive::IfNDefDirective(name) => {
-                        level_true += if level_true == level && !definition_map.contains_key(&name) { 1 } else { 0 };
+                        level_true += if level_true == level && !definition_map.contains_key(name) { 1 } else { 0 };
+                        level += 1;
+                    }
+                    Directive::HasIncludeDirective(path, negated) => {
+                        // Only resolve the path, the file is read by an #include in the taken branch
+                        if level_true == level {
+                            let exists = !path.is_empty() && find_include_file(path, origin.as_ref(), includefolders).is_ok();
+                            if !exists {
+                                info.probed_includes.extend(include_candidates(path, origin.as_ref(), includefolders));
+                            }
+                            level_true += if exists != *negated { 1 } else { 0 };
+                        }
                         level += 1;
                     }
+                    Directive::UnknownDirective(line) => {
+                        if level > level_true { continue; }
+
+                        // Known directives only end up here if their arguments are invalid
+                        let name = line[1..].trim_start().split(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or("");
+                        if ["include", "define", "undef", "if", "ifdef", "ifndef", "ifexists", "else", "endif"].contains(&name) {
+                            return Err(coded_error!("E0206", "Invalid #{} directive \"{}\".", name, line));
+                        }
+
+                        output += line;
+                        output += "\n";
+                        info.line_origins.push((original_lineno, origin.clone()));
+                        info.add_output(line.len() + 1)?;
+                    }
                     Directive::ElseDirective => {
                         if level_true + 1 == level {
                             level_true = level;
@@ -462,8 +682,10 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
                 }
             },
             Line::TokenLine(tokens) => {
-                let stack: Vec<Definition> = Vec::new();
-                let resolved = Macro::resolve_all(&tokens, &definition_map, &stack).prepend_error("Failed to resolve macros:")?;
+                let stack: Vec<&str> = Vec::new();
+                let mut expansion = Expansion::new(info.limits, info.expanded_size);
+                let resolved = Macro::resolve_all(tokens, definition_map, &stack, &mut expansion).prepend_error("Failed to resolve macros:")?;
+                info.expanded_size = expansion.size;
 
                 let (mut result, newlines) = Token::concat(&resolved);
                 result = result.replace("\r\n", "\n");
@@ -476,6 +698,7 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
 
                 output += &result;
                 output += "\n";
+                info.add_output(result.len() + 1)?;
 
                 info.line_origins.push((original_lineno, origin.clone()));
                 original_lineno += (before - result.len()) as u32 / 2;
@@ -513,14 +736,31 @@ fn preprocess_rec(input: String, origin: Option<PathBuf>, definition_map: &mut H
 ///
 /// assert_eq!("foo = \"abc_xyz\";", output.trim());
 /// ```
-pub fn preprocess(mut input: String, origin: Option<PathBuf>, includefolders: &[PathBuf]) -> Result<(String, PreprocessInfo), Error> {
-    if input[..3].as_bytes() == [0xef,0xbb,0xbf] {
-        input = input[3..].to_string();
+pub fn preprocess(input: String, origin: Option<PathBuf>, includefolders: &[PathBuf]) -> Result<(String, PreprocessInfo), Error> {
+    preprocess_with_cache(input, origin, includefolders, None)
+}
+
+/// Preprocesses the input like `preprocess`, reading included files through the given cache.
+pub fn preprocess_with_cache(input: String, origin: Option<PathBuf>, includefolders: &[PathBuf], cache: Option<&IncludeCache>) -> Result<(String, PreprocessInfo), Error> {
+    preprocess_with_limits(input, origin, includefolders, cache, &PreprocessLimits::default())
+}
+
+/// Preprocesses the input like `preprocess_with_cache`, failing once it exceeds one of the
+/// limits.
+pub fn preprocess_with_limits(mut input: String, origin: Option<PathBuf>, includefolders: &[PathBuf], cache: Option<&IncludeCache>, limits: &PreprocessLimits) -> Result<(String, PreprocessInfo), Error> {
+    if input.starts_with('\u{feff}') {
+        input.drain(..3);
     }
 
     let mut info = PreprocessInfo {
         line_origins: Vec::new(),
-        import_stack: Vec::new()
+        includes: Vec::new(),
+        include_lines: Vec::new(),
+        probed_includes: Vec::new(),
+        import_stack: Vec::new(),
+        limits: *limits,
+        output_size: 0,
+        expanded_size: 0,
     };
 
     if let Some(path) = &origin {
@@ -529,7 +769,8 @@ pub fn preprocess(mut input: String, origin: Option<PathBuf>, includefolders: &[
 
     let mut def_map: HashMap<String, Definition> = HashMap::new();
 
-    match preprocess_rec(input, origin, &mut def_map, &mut info, includefolders) {
+    let lines = preprocess_grammar::file(&input).format_error(&origin, &input)?;
+    match preprocess_rec(&lines, origin, &mut def_map, &mut info, includefolders, cache) {
         Ok(result) => Ok((result, info)),
         Err(e) => Err(e)
     }
@@ -540,11 +781,11 @@ pub fn preprocess(mut input: String, origin: Option<PathBuf>, includefolders: &[
 /// `path` is the `path` to the input if it is known and is used for relative includes and error
 /// messages. `includefolders` are the folders searched for absolute includes and should usually at
 /// least include the current working directory.
-pub fn cmd_preprocess<I: Read, O: Write>(input: &mut I, output: &mut O, path: Option<PathBuf>, includefolders: &[PathBuf]) -> Result<(), Error> {
+pub fn cmd_preprocess<I: Read, O: Write>(input: &mut I, output: &mut O, path: Option<PathBuf>, includefolders: &[PathBuf], limits: &PreprocessLimits) -> Result<(), Error> {
     let mut buffer = String::new();
     input.read_to_string(&mut buffer).prepend_error("Failed to read input file")?;
 
-    let (result, _) = preprocess(buffer, path, includefolders)?;
+    let (result, _) = preprocess_with_limits(buffer, path, includefolders, None, limits)?;
 
     output.write_all(result.as_bytes()).prepend_error("Failed to write output")?;
 
@@ -0,0 +1,116 @@
+use crate::preprocess::*;
+
+pub use self::parser::*;
+
+/// Error of a file that can't be preprocessed, with the line and column it failed at
+pub type ParseError = peg::error::ParseError<peg::str::LineCol>;
+
+peg::parser! {
+    grammar parser() for str {
+        rule newline() = "\r\n" / "\n"
+
+        rule name() -> String = n:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_']+) {
+            n.to_string()
+        }
+
+        rule include_path() -> String =
+            "\"" path:$([^'"']*) "\"" { path.to_string() } /
+            "<" path:$([^'>']*) ">"   { path.to_string() }
+
+        rule parameters() -> Vec<String> = "(" [' ' | '\t']* p:(name() ** ([' ' | '\t']* "," [' ' | '\t']*)) [' ' | '\t']* ")" {
+            p
+        }
+
+        rule definition_value() -> Vec<Token> = ([' ' | '\t']+ / &("\\" newline())) v:(token()*) {
+            v
+        }
+
+        rule definition() -> Definition = n:name() p:parameters()? v:definition_value()? {
+            Definition {
+                name: n,
+                parameters: p,
+                value: v.unwrap_or_default(),
+                local: false
+            }
+        }
+
+        rule directive() -> Directive =
+            "#" [' ' | '\t']* "include" [' ' | '\t']+ path:include_path() { Directive::IncludeDirective(path) } /
+            "#" [' ' | '\t']* "define" [' ' | '\t']+ d:definition() { Directive::DefineDirective(d) } /
+            "#" [' ' | '\t']* "undef" [' ' | '\t']+ n:name() { Directive::UndefDirective(n) } /
+            "#" [' ' | '\t']* "if" [' ' | '\t']* neg:"!"? [' ' | '\t']* "__has_include" [' ' | '\t']* "(" [' ' | '\t']* path:include_path() [' ' | '\t']* ")" { Directive::HasIncludeDirective(path, neg.is_some()) } /
+            "#" [' ' | '\t']* "ifexists" [' ' | '\t']* path:include_path() { Directive::HasIncludeDirective(path, false) } /
+            "#" [' ' | '\t']* "ifdef" [' ' | '\t']+ n:name() { Directive::IfDefDirective(n) } /
+            "#" [' ' | '\t']* "ifndef" [' ' | '\t']+ n:name() { Directive::IfNDefDirective(n) } /
+            "#" [' ' | '\t']* "else" { Directive::ElseDirective } /
+            "#" [' ' | '\t']* "endif" { Directive::EndIfDirective } /
+            "#" [' ' | '\t']* d:$(name() [^'\r' | '\n']*) { Directive::UnknownDirective(format!("#{}", d.trim_end())) }
+
+        rule arg_rec() = "(" (arg_rec() / "\\\\" / ("\\" newline()) / [^'\r' | '\n' | ')'])* ")"
+
+        rule argument() -> String = a:$((arg_rec() / "\\\\" / ("\\" newline()) / [^'\r' | '\n' | ',' | ')'])*) {
+            a.to_string()
+        }
+
+        pub rule arguments() -> Vec<String> = [' ' | '\t']* "(" [' ' | '\t']* a:(argument() ** ([' ' | '\t']* "," [' ' | '\t']*)) [' ' | '\t']* ")" {
+            a
+        }
+
+        rule nonmacro_token() -> String = s:$((!macro_proper() !comment_token() !concat_token() ("\\\\" / ("\\" newline()) / [^'"' | '\r' | '\n']))+) {
+            s.to_string()
+        }
+
+        rule string_token() -> (String, u32) =
+                s:$("\"" ("\\\\" / ("\\" newline()) / "\"\"" / [^'\r' | '\n' | '"'])* "\"") {
+            let newlines = s.chars().filter(|c| c == &'\n').count() as u32;
+            (s.replace("\r\n", "\n").replace("\\\n", ""), newlines)
+        }
+
+        pub rule macro_proper() -> Macro = quoted:"#"? n:name() args:arguments()? {
+            Macro {
+                name: n,
+                arguments: args,
+                original: String::new(),
+                quoted: quoted.is_some()
+            }
+        }
+
+        rule macro_token() -> Macro = original:$(macro_proper()) {
+            parse_macro(original)
+        }
+
+        rule concat_token() = "##"
+
+        rule sl_comment() -> u32 = "//" (!newline() [_])* &newline() {
+            0
+        }
+
+        rule ml_comment() -> u32 = [' ' | '\t']* "/*" content:$((!"*/" (newline() / [_]))*) "*/" {
+            content.chars().filter(|c| c == &'\n').count() as u32
+        }
+
+        rule comment_token() -> u32 = sl_comment() / ml_comment()
+
+        rule token() -> Token =
+            c:comment_token() { Token::CommentToken(c) } /
+            sn:string_token() { Token::NewlineToken(sn.0, sn.1) } /
+            concat_token() { Token::ConcatToken } /
+            m:macro_token() { Token::MacroToken(m) } /
+            nm:nonmacro_token() { Token::RegularToken(nm) }
+
+        pub rule tokens() -> Vec<Token> = t:(token()*) {
+            t
+        }
+
+        // @todo: comments after directives (same line)
+        rule line() -> Line =
+            [' ' | '\t']* d:directive() cmts:(comment_token())* [' ' | '\t']* {
+                Line::DirectiveLine(d, cmts.iter().sum())
+            } /
+            [' ' | '\t']* t:tokens() { Line::TokenLine(t) }
+
+        pub rule file() -> Vec<Line> = lines:(line() ** newline()) ![_] {
+            lines
+        }
+    }
+}
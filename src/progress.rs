@@ -0,0 +1,34 @@
+//! Progress reporting for long running operations like encoding textures or packing PBOs
+
+use std::io::{IsTerminal, Write, stderr};
+
+/// State of an operation, passed to progress callbacks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// Number of finished steps, starting at 1 for the first step
+    pub current: usize,
+    /// Total number of steps
+    pub total: usize,
+    /// Description of the step that was just finished, like a file name
+    pub label: String,
+}
+
+/// Prints the progress as a single line to stderr that is overwritten on every update.
+pub fn print_progress(progress: Progress) {
+    let mut err = stderr().lock();
+    write!(err, "\r\x1b[K[{}/{}] {}", progress.current, progress.total, progress.label).ok();
+    if progress.current >= progress.total {
+        writeln!(err).ok();
+    }
+    err.flush().ok();
+}
+
+/// Returns a callback printing progress to stderr if it is a terminal, so piped or logged output
+/// isn't cluttered.
+pub fn stderr_progress() -> Option<&'static (dyn Fn(Progress) + Sync)> {
+    if stderr().is_terminal() {
+        Some(&print_progress)
+    } else {
+        None
+    }
+}
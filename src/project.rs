@@ -0,0 +1,142 @@
+//! Per-project build settings from `armake.toml`
+//!
+//! Instead of passing the same flags to every `build` and `pack`, a project can keep them in an
+//! `armake.toml` next to its source folders, which is found by looking upward from the source
+//! folder. Flags given on the command line override the values of the file, lists included.
+//!
+//! ```toml
+//! [build]
+//! exclude = ["*.psd", "*.txt"]
+//! headerext = ["version=1.2"]
+//! binarize = true
+//! align = 1
+//!
+//! [preprocess]
+//! include = ["include"]
+//!
+//! [textures]
+//! max_size = 2048
+//! policy = "texture-policy.toml"
+//!
+//! [sign]
+//! key = "keys/mygroup.biprivatekey"
+//! version = 3
+//! ```
+//!
+//! Relative paths are relative to the folder of the file. Unknown keys are an error, so a typo
+//! doesn't silently build without the setting.
+
+use std::fs::{read_to_string};
+use std::io::{Error};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize};
+
+use crate::error::*;
+use crate::sign::{BISignVersion};
+
+/// Name of the project file
+pub const PROJECT_FILE: &str = "armake.toml";
+
+/// Settings of the `[build]` section
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildSettings {
+    /// Glob patterns of files to leave out of the PBO
+    pub exclude: Vec<String>,
+    /// Header extensions as `key=value`
+    pub headerext: Vec<String>,
+    /// Rapify and binarize files like `build`, `false` packs them as they are like `pack`
+    pub binarize: Option<bool>,
+    /// Alignment of the entry data in bytes
+    pub align: Option<u32>,
+}
+
+/// Settings of the `[preprocess]` section
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreprocessSettings {
+    /// Folders to search for absolute includes
+    pub include: Vec<PathBuf>,
+}
+
+/// Settings of the `[textures]` section
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextureSettings {
+    /// Largest side of packed PAAs, larger ones are downscaled
+    pub max_size: Option<u32>,
+    /// Texture policy file that built PBOs have to pass, see `texpolicy`
+    pub policy: Option<PathBuf>,
+}
+
+/// Settings of the `[sign]` section
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignSettings {
+    /// Private key to sign the PBO with
+    pub key: Option<PathBuf>,
+    /// Signature version, 2 or 3
+    pub version: Option<u32>,
+}
+
+/// Contents of an `armake.toml`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub build: BuildSettings,
+    pub preprocess: PreprocessSettings,
+    pub textures: TextureSettings,
+    pub sign: SignSettings,
+}
+
+impl ProjectConfig {
+    /// Returns the path of the `armake.toml` in the folder or the closest of its parents.
+    pub fn find(folder: &Path) -> Option<PathBuf> {
+        let folder = folder.canonicalize().unwrap_or_else(|_| folder.to_path_buf());
+        folder.ancestors().map(|f| f.join(PROJECT_FILE)).find(|p| p.is_file())
+    }
+
+    /// Reads a project file, making its relative paths relative to its folder.
+    pub fn load(path: &Path) -> Result<ProjectConfig, Error> {
+        let content = read_to_string(path).prepend_error(format!("Failed to read {}:", path.display()))?;
+        let mut config = Self::parse(&content).prepend_error(format!("Failed to read {}:", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for folder in &mut config.preprocess.include {
+            *folder = base.join(&*folder);
+        }
+        if let Some(policy) = &mut config.textures.policy {
+            *policy = base.join(&*policy);
+        }
+        if let Some(key) = &mut config.sign.key {
+            *key = base.join(&*key);
+        }
+
+        Ok(config)
+    }
+
+    /// Parses the content of a project file.
+    pub fn parse(content: &str) -> Result<ProjectConfig, Error> {
+        let config: ProjectConfig = toml::from_str(content).map_err(|e| coded_error!("E0821", "{}", e))?;
+
+        if config.textures.max_size == Some(0) {
+            return Err(coded_error!("E0821", "textures.max_size has to be at least 1."));
+        }
+        if config.build.align == Some(0) {
+            return Err(coded_error!("E0821", "build.align has to be at least 1."));
+        }
+        config.sign_version()?;
+
+        Ok(config)
+    }
+
+    /// Returns the signature version of the `[sign]` section, v3 if it isn't set.
+    pub fn sign_version(&self) -> Result<BISignVersion, Error> {
+        match self.sign.version {
+            None | Some(3) => Ok(BISignVersion::V3),
+            Some(2) => Ok(BISignVersion::V2),
+            Some(version) => Err(coded_error!("E0821", "sign.version has to be 2 or 3, not {}.", version))
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Check that files referenced by configs exist
+//!
+//! Configs refer to models, textures, materials, sounds and scripts by path, like
+//! `model = "\myMod\data\thing.p3d";`. The game only complains about a wrong path once the file
+//! is needed, so `build` looks up every such path in the PBO being packed and in other mods.
+//!
+//! The configs in the PBO are rapified and don't know where their entries came from, so the
+//! sources of configs with missing references are parsed again to warn at the file and line of
+//! each reference.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, read_to_string};
+use std::io::{BufReader, Cursor, Error};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Config, ParseOptions, origin_location};
+use crate::error::*;
+use crate::paths::{InternalPath};
+use crate::pbo::{PBO, list_files, read_entry_names};
+use crate::preprocess::{preprocess};
+
+/// Extensions of the files whose paths are checked
+const RESOURCE_EXTENSIONS: [&str; 6] = ["p3d", "paa", "rvmat", "ogg", "wss", "sqf"];
+
+/// Where files referenced by configs are looked up besides the PBO itself
+#[derive(Debug, Clone, Default)]
+pub struct ResourceCheck {
+    /// Mod folders whose files and PBOs are searched, or prefixes like `a3` whose files are all
+    /// assumed to exist
+    pub paths: Vec<PathBuf>,
+    /// Fail instead of warning if any referenced file is missing
+    pub strict: bool,
+}
+
+/// Reference to a file that couldn't be found
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingResource {
+    /// PBO entry containing the reference, like `config.bin`
+    pub entry: String,
+    /// Path of the property, like `CfgVehicles/car/model`
+    pub property: String,
+    /// Referenced path as written in the config
+    pub path: String,
+}
+
+/// Files known to exist
+#[derive(Default)]
+struct KnownFiles {
+    files: HashSet<InternalPath>,
+    prefixes: Vec<InternalPath>,
+}
+
+/// Returns true for strings that look like paths of files the game loads. Procedural textures
+/// and empty strings never do.
+fn is_resource_path(value: &str) -> bool {
+    value.starts_with('\\') && match value.rsplit_once('.') {
+        Some((_, extension)) => RESOURCE_EXTENSIONS.iter().any(|e| extension.eq_ignore_ascii_case(e)),
+        None => false
+    }
+}
+
+impl KnownFiles {
+    fn add_pbo<'a, I: Iterator<Item = &'a String>>(&mut self, prefix: Option<&str>, names: I) {
+        // Files with paths leaving their folder can't be referenced
+        let Ok(prefix) = InternalPath::new(prefix.unwrap_or_default()) else { return };
+        for name in names {
+            if let Ok(name) = InternalPath::new(name) {
+                self.files.insert(prefix.join(&name));
+            }
+        }
+    }
+
+    /// Adds the files and PBO entries of a folder, or a prefix if the path isn't a folder.
+    fn add_path(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.is_dir() {
+            self.prefixes.push(InternalPath::new(&path.to_string_lossy())?);
+            return Ok(());
+        }
+
+        for file in list_files(&path.to_path_buf())? {
+            if file.extension().is_some_and(|e| e.eq_ignore_ascii_case("pbo")) {
+                let (prefix, names) = read_entry_names(&mut BufReader::new(File::open(&file)?))
+                    .prepend_error(format!("Failed to read {}:", file.display()))?;
+                self.add_pbo(prefix.as_deref(), names.iter());
+            }
+            if let Ok(relative) = InternalPath::from_host(path, &file) {
+                self.files.insert(relative);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        // A path leaving its folder can't be one of the files
+        let Ok(path) = InternalPath::new(path) else { return false };
+        self.files.contains(&path) || self.prefixes.iter().any(|p| path.strip_prefix(p).is_some_and(|rest| !rest.is_empty()))
+    }
+}
+
+/// Returns the references in the rapified configs of the PBO to files that are neither part of
+/// it nor found in `check.paths`.
+pub fn find_missing_resources(pbo: &PBO, check: &ResourceCheck) -> Result<Vec<MissingResource>, Error> {
+    let mut known = KnownFiles::default();
+    known.add_pbo(pbo.header_extensions.get("prefix").map(|p| p.as_str()), pbo.files.keys());
+    for path in &check.paths {
+        known.add_path(path).prepend_error(format!("Failed to read {}:", path.display()))?;
+    }
+
+    let mut missing: Vec<MissingResource> = Vec::new();
+    for (name, data) in pbo.files.iter() {
+        if !data.get_ref().starts_with(b"\0raP") {
+            continue;
+        }
+
+        let config = Config::read_rapified(&mut Cursor::new(&data.get_ref()[..])).prepend_error(format!("Failed to read {}:", name))?;
+        for (property, value) in config.strings() {
+            if is_resource_path(value) && !known.contains(value) {
+                missing.push(MissingResource {
+                    entry: name.clone(),
+                    property,
+                    path: value.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+/// File and line of a string in a config, by property path and value
+type StringLocations = HashMap<(String, String), (Option<String>, Option<u32>)>;
+
+/// Returns the source file a PBO entry was built from, `config.cpp` for a `config.bin`.
+fn entry_source(source: &Path, entry: &str) -> PathBuf {
+    let path = source.join(entry.replace('\\', "/"));
+    match path.file_name() {
+        Some(name) if name.eq_ignore_ascii_case("config.bin") => path.with_file_name("config.cpp"),
+        _ => path
+    }
+}
+
+/// Parses a config source again and returns where its strings are, `None` if it can't be parsed.
+/// Its warnings were raised when it was built, so they are dropped.
+fn string_locations(path: &Path, includefolders: &[PathBuf]) -> Option<StringLocations> {
+    let input = read_to_string(path).ok()?;
+    buffer_warnings(Arc::new(Mutex::new(Vec::new())), || {
+        let (preprocessed, info) = preprocess(input, Some(path.to_path_buf()), includefolders).ok()?;
+        let config = Config::from_preprocessed(&preprocessed, &info, &ParseOptions::default()).ok()?;
+        let mut locations = StringLocations::new();
+        for (property, value, position) in config.located_strings() {
+            if let Some(position) = position {
+                locations.entry((property, value.to_string())).or_insert_with(|| origin_location(&preprocessed, &info, position));
+            }
+        }
+        Some(locations)
+    })
+}
+
+/// Warns about every reference returned by `find_missing_resources` at its line of the config
+/// source in the `source` folder, or at the PBO entry if it can't be found. In strict mode,
+/// fails if there are any.
+pub fn check_resources(pbo: &PBO, check: &ResourceCheck, source: &Path, includefolders: &[PathBuf]) -> Result<(), Error> {
+    let missing = find_missing_resources(pbo, check)?;
+    let mut locations: HashMap<&str, Option<StringLocations>> = HashMap::new();
+    for resource in &missing {
+        let location = locations.entry(&resource.entry)
+            .or_insert_with(|| string_locations(&entry_source(source, &resource.entry), includefolders))
+            .as_ref()
+            .and_then(|l| l.get(&(resource.property.clone(), resource.path.clone())).cloned())
+            .unwrap_or_else(|| (Some(resource.entry.clone()), None));
+        warning(format!("{} references \"{}\", which isn't part of the PBO or any of the checked paths.", resource.property, resource.path),
+            Some("missing-resource"), location);
+    }
+
+    if check.strict && !missing.is_empty() {
+        return Err(coded_error!("E0310", "{} referenced files are missing.", missing.len()));
+    }
+
+    Ok(())
+}
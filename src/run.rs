@@ -1,16 +1,42 @@
 use std::fs::{File};
-use std::io::{Error, Read, Cursor, stdin, stdout};
+use std::io::{Error, IsTerminal, Read, Write, Cursor, sink, stdin, stdout};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::*;
 use crate::binarize;
+use crate::buildcache;
+use crate::buildreport;
 use crate::config;
+use crate::configdiff;
+use crate::detect;
+use crate::diagnostics;
 use crate::error::*;
-use crate::io::{Input, Output};
+use crate::io::{BatchSummary, Input, Output, OutputPolicy, check_target_collisions, create_file, create_folder};
+use crate::manifest;
+use crate::mission;
+use crate::modelcfg;
+use crate::p3d;
 use crate::paa;
+use crate::paaanalysis;
+use crate::parallel;
+use crate::patch;
 use crate::pbo;
+use crate::pbogrep;
 use crate::preprocess;
+use crate::progress;
+use crate::project;
+use crate::resources;
+use crate::schema;
+#[cfg(feature = "serve")]
+use crate::serve;
 use crate::sign;
+use crate::texpolicy;
+use crate::texheaders;
+use crate::texreport;
+use crate::verifycache;
+use crate::vfs;
 use crate::warnings;
 
 use serde::Deserialize;
@@ -19,97 +45,406 @@ pub const USAGE: &str = "
 armake2
 
 Usage:
-    armake2 rapify [-v] [-f] [-w <wname>]... [-i <includefolder>]... [<source> [<target>]]
-    armake2 preprocess [-v] [-f] [-w <wname>]... [-i <includefolder>]... [<source> [<target>]]
-    armake2 derapify [-v] [-f] [-d <indentation>] [<source> [<target>]]
+    armake2 rapify [-v] [-f] [-w <wname>]... [--dedupe-warnings] [--message-format <format>] [--json-diagnostics <file>] [-i <includefolder>]... [--lint-types] [--string-properties <file>] [--strict-duplicates] [--strict-directives] [--schema <schema>] [--string-encoding <encoding>] [--share-classes] [--quantize-floats <digits>] [--max-output-size <bytes>] [--max-macro-depth <n>] [--max-include-depth <n>] [<source> [<target>]]
+    armake2 preprocess [-v] [-f] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [-i <includefolder>]... [--max-output-size <bytes>] [--max-macro-depth <n>] [--max-include-depth <n>] [<source> [<target>]]
+    armake2 configbundle [-v] [-f] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [-i <includefolder>]... [--mod-dir <moddir>]... [<source> [<target>]]
+    armake2 derapify [-v] [-f] [-d <indentation>] [--source-map <map>] [--string-encoding <encoding>] [<source> [<target>]]
+    armake2 configget [-v] [--offset] [--mod-dir <moddir>]... <source> <entrypath>
+    armake2 configdiff [-v] [-f] [--resolve-inheritance] [--json] [--quiet] [--ignore-property <pattern>]... <old> <new> [<target>]
+    armake2 modelcfg [-v] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [-i <includefolder>]... <source>
     armake2 binarize [-v] [-f] [-w <wname>]... <source> <target>
-    armake2 build [-v] [-f] [-w <wname>]... [-i <includefolder>]... [-x <excludepattern>]... [-e <headerext>]... [-k <privatekey>] [-s <signature>] <sourcefolder> [<target>]
-    armake2 pack [-v] [-f] [-x <excludepattern>]... [-e <headerext>]... [-k <privatekey>] [-s <signature>] <sourcefolder> [<target>]
-    armake2 inspect [-v] [<source>]
-    armake2 unpack [-v] [-f] <source> <targetfolder>
+    armake2 p3dinfo [-v] [<source>]
+    armake2 build [-v] [-f] [-w <wname>]... [--dedupe-warnings] [--message-format <format>] [--json-diagnostics <file>] [--threads <n>] [-i <includefolder>]... [-x <excludepattern>]... [-e <headerext>]... [-k <privatekey>] [-s <signature>] [--align <n>] [--cache <cachedir> | --no-cache] [--auto-cfgpatches | --check-cfgpatches] [--check-paths <path>]... [--strict-resources] [--texture-max-size <n>] [--texture-policy <file>] [--check-audio] [--keep-empty-dirs] [--mod-dir <moddir>]... [--config <file>] [--report <file> [--report-format <format>]] [--skip-existing | --if-newer] [--all [--keep-going]] <sourcefolder> [<target>]
+    armake2 pack [-v] [-f] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [--threads <n>] [-x <excludepattern>]... [-e <headerext>]... [-k <privatekey>] [-s <signature>] [--align <n>] [--texture-max-size <n>] [--texture-policy <file>] [--dedupe] [--check-audio] [--keep-empty-dirs] [--config <file>] [--skip-existing | --if-newer] <sourcefolder> [<target>]
+    armake2 packmission [-v] [-f] [--message-format <format>] [--json-diagnostics <file>] [-x <excludepattern>]... [-e <headerext>]... [-k <privatekey>] [-s <signature>] [--binarize-sqm] [--convert-images] [--world <world>] <sourcefolder> [<target>]
+    armake2 cache gc [-v] [--max-age <days>] [<cachedir>]
+    armake2 inspect [-v] [--format <listformat>] [<source>]
+    armake2 unpack [-v] [-f] [--message-format <format>] [--json-diagnostics <file>] [--lenient] [--flat [--separator <separator>]] [--dedupe] [--keep-empty-dirs] <source> <targetfolder>
     armake2 cat [-v] <source> <filename> [<target>]
+    armake2 grep [-v] [-w <wname>]... [--ignore-case] [--regex] [--entries <glob>]... [--derap] [--binary] [--threads <n>] <pattern> <pbos>...
+    armake2 pbodiff [-v] [-f] <old> <new> [<target>]
+    armake2 pbopatch [-v] [-f] <old> <patch> [<target>]
     armake2 keygen [-v] [-f] <keyname>
-    armake2 sign [-v] [-f] [--v2] <privatekey> <pbo> [<signature>]
-    armake2 verify [-v] <publickey> <pbo> [<signature>]
-    armake2 paa2img [-v] [-f] [<source> [<target>]]
-    armake2 img2paa [-v] [-f] [-z] [-t <paatype>] [<source> [<target>]]
+    armake2 sign [-v] [-f] [--v2] [-k <privatekey>]... [--authority <authority>]... <privatekey> <pbo> [<signature>]
+    armake2 resign [-v] [--v2] <privatekey> <pbos>...
+    armake2 verify [-v] [--cache-file <file> [--no-cache-trust]] <publickey> <pbo> [<signature>]
+    armake2 checksignatures [-v] [--threads <n>] [--cache-file <file> [--no-cache-trust]] <addonsfolder> <keysfolder>
+    armake2 serve [-v] [--threads <n>] [--idle-timeout <secs>] <address> <keysfolder> <rootfolder>
+    armake2 manifest create [-v] [-f] [-x <excludepattern>]... <key> <moddir> [<manifest>]
+    armake2 manifest verify [-v] <key> <moddir> [<manifest>]
+    armake2 paa2img [-v] [-f] [--threads <n>] [--strict] [--flip-v] [--flip-h] [--level <n>] [--skip-existing | --if-newer] [<source> [<target>]]
+    armake2 img2paa [-v] [-f] [-z] [--threads <n>] [--lzo <policy>] [-t <paatype>] [--flip-v] [--flip-h] [--ignore-exif] [--quality <quality>] [--tag-tool] [--manifest <file>] [--skip-existing | --if-newer] [<source> [<target>]]
+    armake2 img2paa --analyze [-v] [-f] [-z] [--threads <n>] [--formats <formats>] [--write-best] <source> [<target>]
+    armake2 paaset [-v] [-f] [--flag <flag>] [--avg-color <color>] [--swizzle <swizzle>] <source> [<target>]
+    armake2 paaretype [-v] [-f] [--allow-lossy-alpha] -t <paatype> <source> [<target>]
+    armake2 paaalpha [-v] [-f] <source> <target>
+    armake2 paacompare [-v] [--quiet] [--source <image> [--max-psnr-loss <db>]] <old> <new>
+    armake2 paathumbs [-v] [-w <wname>]... [--threads <n>] [--size <n>] <sourcefolder> <targetfolder>
+    armake2 paasetalpha [-v] [-f] [--retype <paatype>] <source> <mask> [<target>]
+    armake2 texturepolicy [-v] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [--threads <n>] [--enforce] <policy> <sourcefolder>
+    armake2 texheaders [-v] [-w <wname>]... [--mod-dir <moddir>]... <sourcefolder>
+    armake2 texreport [-v] [-w <wname>]... [--message-format <format>] [--json-diagnostics <file>] [--threads <n>] [--albedo] [--albedo-range <range>] [--max-outside <percent>] [--csv <file>] <sourcefolder>
+    armake2 explain <code>
     armake2 (-h | --help)
     armake2 --version
 
 Commands:
     rapify      Preprocess and rapify a config file.
     preprocess  Preprocess a file.
+    configbundle  Write a config with all includes and macros resolved as one formatted file.
     derapify    Derapify a config.
+    configget   Print an entry of a config, e.g. \"CfgPatches/my_addon/units\".
+    configdiff  List the classes and properties added, removed or changed between two configs.
+    modelcfg    Check a model.cfg (or the one in the given folder) for skeleton and animation errors.
     binarize    Binarize a file using BI's binarize.exe (Windows only).
+    p3dinfo     List the LODs, textures and materials of an MLOD or ODOL model.
     build       Build a PBO from a folder.
     pack        Pack a folder into a PBO without any binarization or rapification.
-    inspect     Inspect a PBO and list contained files.
-    unpack      Unpack a PBO into a folder.
+    packmission  Pack a mission folder into a PBO. A target folder gets missionname.worldname.pbo.
+    cache gc    Remove build cache entries that weren't used for a while. Defaults to .armake2-cache in the CWD.
+    inspect     Inspect a file of any supported format. Reads a PBO from stdin without source.
+    unpack      Unpack a PBO into a folder. Unpacks every PBO of a source folder into a folder of its name.
     cat         Read the named file from the target PBO to stdout.
+    grep        Search the entries of PBOs, or of all PBOs in folders, for text without unpacking them.
+    pbodiff     Create a patch that turns the old PBO into the new one.
+    pbopatch    Apply a patch to the old PBO, recreating the new one.
     keygen      Generate a keypair with the specified path (extensions are added).
     sign        Sign a PBO with the given private key.
+    resign      Convert signatures to v3 (or v2 with --v2) by signing their PBOs again with the private key
+                that made them. Takes PBOs, signatures and folders of PBOs.
     verify      Verify a PBO's signature with the given public key.
-    paa2img     Convert PAA to image (PNG only).
-    img2paa     Convert image to PAA.
+    checksignatures  Check that every PBO in a folder is signed by one of the keys in another.
+    serve       Answer signature and checksum requests for the PBOs in a folder as JSON lines on a TCP
+                address or a unix:<path> socket. Only in builds with the serve feature.
+    manifest    Create or verify a manifest of all files in a mod folder, signed with a BI key or, for any
+                other key file, an HMAC of its content. Written to armake2.manifest in the folder by default.
+    paa2img     Convert PAA to image (PNG only). Converts every PAA of a source folder into the target folder.
+                DXT PAAs are written to .dds targets with their blocks as they are.
+    img2paa     Convert image to PAA. With --analyze, compare the quality and size of the PAA formats.
+                Converts every PNG, JPG, TGA and DDS image of a source folder into the target folder.
+                The blocks of DXT1, DXT3 and DXT5 DDS files are copied without encoding them again.
+    paaset      Change the taggs of a PAA without re-encoding it. Edits in place without target.
+    paaretype   Convert a PAA to another type, keeping its mipmaps and taggs. Edits in place without target.
+    paaalpha    Extract the alpha channel of a PAA as a grayscale image.
+    paacompare  Compare the type, size and pixels of two PAAs, optionally against their source image.
+    paathumbs   Write PNG thumbnails of all PAAs in a folder, keeping the ones newer than their PAA.
+    paasetalpha  Replace the alpha channel of a PAA with a grayscale image. Edits in place without target.
+    texturepolicy  Check all PAAs in a folder against the rules of a texture policy file.
+    texheaders  Write the texHeaders.bin of a source folder again. build also does that for the PBO.
+    texreport   List all PAAs in a folder, optionally checking the albedo of diffuse (_co) textures.
+    explain     Describe an error or warning code, e.g. E0202, and how to fix its cause.
 
 Options:
     -v --verbose                Enable verbose output.
     -f --force                  Overwrite the target file/folder if it already exists.
-    -w --warning <wname>        Warning to disable
+       --skip-existing          Leave targets that already exist as they are instead of failing.
+       --if-newer               Only overwrite targets that were modified before their source. The source
+                                of a PBO counts as modified when its newest file was.
+    -w --warning <wname>        Warning to disable, or name=N to show at most N warnings of that type and
+                                name=unlimited to show all of them. 10 of each type are shown by default.
+       --dedupe-warnings        Show a warning repeated with the same message at the same line once, e.g. from
+                                an include of several configs, and count the repeats in the summary.
+       --message-format <format>  Print errors and warnings as text or as JSON lines with their codes. text by
+                                default.
+       --json-diagnostics <file>  Collect all errors and warnings, including the summary of suppressed ones,
+                                and write them to the file as one JSON array at the end. - for stdout, unless the
+                                command writes its output there.
     -i --include <includefolder>    Folder to search for includes, defaults to CWD.
     -x --exclude <excludepattern>   Glob pattern to exclude from PBO.
                                       For unpack: pattern to exclude from output folder.
+       --lint-types             Warn about values whose type doesn't match the property.
+       --string-properties <file>  File with additional property names that take strings.
+       --strict-duplicates      Fail on classes defined more than once in the same scope instead of merging them.
+       --strict-directives      Fail on #pragma lines, __EXEC statements and similar leftovers instead of skipping them.
+       --schema <schema>        JSON file with the known properties of config classes to warn about unknown
+                                ones, or \"builtin\" for the core properties of CfgPatches, CfgWeapons and
+                                CfgVehicles.
+       --string-encoding <encoding>  Encoding of rapified strings: utf8 (Arma 3), cp1251 or cp1252. utf8 by default.
+       --share-classes          Write identical class bodies once and point all classes with them to the same copy.
+       --quantize-floats <digits>  Round floats to this many significant digits (lossy). Makes more classes
+                                identical for --share-classes.
+       --max-output-size <bytes>  Fail if preprocessing gives more than this many bytes. 64 MiB by default.
+       --max-macro-depth <n>    Fail if macros expand within each other deeper than this. 128 by default.
+       --max-include-depth <n>  Fail if files include each other deeper than this. 64 by default.
+       --lenient                Sanitize entry names and skip broken entries when unpacking.
+       --flat                   Unpack every entry directly into the target folder, joining its folders into
+                                the file name, and list the original names in $MANIFEST$.
+       --separator <separator>  Separator between the folders of flattened names. \"__\" by default.
+       --dedupe                 Hard link identical entries instead of writing them again, falling back to copies.
+                                For pack: read hard linked files and files with a hash known from an earlier
+                                pack once, keeping the hashes in .armake2-cache next to the source folder.
+       --keep-empty-dirs        Pack empty folders as zero-byte $EMPTYDIR$ entries and create the folders of those entries
+                                when unpacking instead of writing the files.
+       --ignore-case            Match letters of either case.
+       --regex                  Interpret the pattern as a regular expression instead of literal text.
+       --entries <glob>         Only search entries matching the glob pattern, e.g. \"*.sqf\".
+       --derap                  Search rapified configs and missions derapified.
+       --binary                 Search binary entries too, printing the byte offsets of matches.
     -d --indent <indentation>   String to use for indentation. 4 spaces by default.
-    -e --headerext <headerext>  Extension to add to PBO header as \"key=value\".
-    -k --key <privatekey>       Sign the PBO with the given private key.
+       --format <listformat>    PBO listing format: native or mikero (tab separated like ExtractPbo -L). native by default.
+       --source-map <map>       Write a JSON lines file mapping output lines to offsets in the rapified config.
+       --offset                 Print the offset of the entry in the rapified config.
+       --resolve-inheritance    Compare the entries classes inherit as well as their own.
+       --json                   Print one JSON object per difference instead of text.
+       --ignore-property <pattern>  Skip entries matching the glob pattern, e.g. \"dlc\" or \"CfgVehicles/*/author\".
+                                Patterns without / match the entry name. Matching a class skips all of it.
+       --quiet                  Print nothing. Exit with 0 if there are no differences, 1 if there are and 2
+                                on errors. For paacompare: differences within --max-psnr-loss don't count.
+       --mod-dir <moddir>       Read the source from the PBOs in this folder by its path in game, e.g.
+                                \\a3\\weapons_f\\config.bin. Folders are searched in the given order.
+                                For configbundle: rapified configs are written derapified.
+                                For build and texheaders: read textures of other addons from these PBOs.
+    -e --headerext <headerext>  Extension to add to PBO header as \"key=value\", overriding $PBO_META$.json.
+    -k --key <privatekey>       Sign the PBO with the given private key. For sign: additional key to sign with.
+       --authority <authority>  Authority in the signature file name instead of the key name, one per key
+                                in the order of <privatekey> and then -k.
     -s --signature <signature>  Signature path to use when signing the PBO.
-       --v2                     Generate an older v2 signature.
-    -z --compress               Compress final PAA where possible.
-    -t --type <paatype>         PAA type. DXT1 or DXT5
+       --v2                     Generate an older v2 signature. For resign: convert to v2.
+       --align <n>              Start the data of every PBO entry at a multiple of n bytes.
+       --cache <cachedir>       Folder for cached build artifacts. .armake2-cache next to the source folder by default.
+       --no-cache               Convert every file again without reading or writing the cache.
+       --cache-file <file>      File to keep the hashes of verified PBOs in. PBOs whose size and modification
+                                time match are not hashed again.
+       --no-cache-trust         Hash every PBO again, only updating the cache file.
+       --auto-cfgpatches        Add classes of CfgVehicles, CfgWeapons and CfgMagazines that are missing from
+                                units[], weapons[] or magazines[] of CfgPatches.
+       --check-cfgpatches       Only warn about classes missing from CfgPatches.
+       --check-paths <path>     Mod folder or prefix to look for files referenced by configs in, besides the PBO.
+       --strict-resources       Fail instead of warning if a file referenced by a config is missing.
+       --texture-max-size <n>   Pack PAAs larger than n pixels on their longest side downscaled to at most n.
+       --texture-policy <file>  Fail if a packed PAA breaks a rule of the texture policy file.
+       --check-audio            Warn about OGG, WSS and WAV files the engine can't play well: sample rates
+                                other than 44100 or 48000 Hz, more than 2 channels, empty or broken files.
+       --enforce                Fail instead of warning if a texture breaks a rule.
+       --config <file>          Project file with build settings. armake.toml in the source folder or the
+                                closest of its parents by default.
+       --report <file>          Write the size, SHA-1, signature and contents of the built PBOs to the file,
+                                also if the build fails.
+       --report-format <format>  Format of the report: json, toml or md (a Markdown table). json by default.
+       --all                    Build every folder in <sourcefolder> into <name>.pbo in the <target> folder,
+                                in parallel.
+       --keep-going             With --all, build the other PBOs when one fails instead of stopping.
+       --binarize-sqm           Rapify mission.sqm.
+       --convert-images         Convert JPG and PNG images in the mission to PAA.
+       --world <world>          World name of the mission instead of the suffix of the folder name.
+       --max-age <days>         Days since their last use after which cache entries are removed. 30 by default.
+       --idle-timeout <secs>    Seconds a connection may be idle before it is closed. 60 by default.
+    -z --compress               Compress final PAA where possible. img2paa always does unless --lzo never is given.
+       --lzo <policy>           Which mipmaps to compress: always, the default, never or adaptive[:<percent>],
+                                which stops once a mipmap got less than percent (10 by default) smaller.
+    -t --type <paatype>         PAA type. DXT1, DXT3, DXT5, ARGB4444, ARGB1555, ARGB8888 or AI88. For img2paa also
+                                auto, the default, which picks DXT5 for images with alpha and DXT1 for others.
+       --analyze                Compare the PAA formats instead of converting.
+       --formats <formats>      Comma separated formats to compare. dxt1,dxt5,argb4444 by default.
+       --write-best             Write the recommended format to the target.
+       --source <image>         Also compare both PAAs to the image they were converted from.
+       --max-psnr-loss <db>     How many dB lower the PSNR of the new PAA against the source may be than that of
+                                the old one, in color and alpha, for both to count as the same.
+       --flag <flag>            Value of the PAA FLAG tagg.
+       --avg-color <color>      Average color as RRGGBBAA, or \"auto\" to compute it.
+       --swizzle <swizzle>      Channel sources for A,R,G,B, e.g. \"1-A,R,G,B\".
+       --strict                 Fail on DXT mipmaps with too little data instead of filling them with zeros.
+       --level <n>              Convert this mipmap level, 0 (the default) being the full size image.
+       --flip-v                 Mirror the texture top to bottom, before generating mipmaps for img2paa.
+       --flip-h                 Mirror the texture left to right, before generating mipmaps for img2paa.
+       --ignore-exif            Encode JPEG and TIFF photos as stored instead of upright by their EXIF orientation.
+       --quality <quality>      DXT encoding effort: fast, cluster, default or exhaustive. fast uses a range fit
+                                for quick previews, exhaustive tries several encodings per block and keeps the
+                                closest. Slower, but with less banding on gradients.
+       --tag-tool               Store the armake2 version in a TOOL tagg that inspect shows. The engine skips it.
+       --manifest <file>        Record the jobs of a folder conversion in a JSON file. Running it again with the
+                                same file only converts failed, changed and unfinished files.
+       --retype <paatype>       Encode the PAA with a different type. DXT1 or DXT5
+       --allow-lossy-alpha      Retype to DXT1 even if the alpha channel isn't fully opaque.
+       --size <n>               Largest side of the thumbnails in pixels. 64 by default.
+       --albedo                 Check that diffuse textures stay within the albedo range.
+       --albedo-range <range>   Expected albedo range per channel as min-max. 20-230 by default.
+       --max-outside <percent>  Percentage of pixels allowed outside the albedo range. 5 by default.
+       --csv <file>             Also write the report to a CSV file.
+       --threads <n>            Number of threads for parallel work, one per core by default. With 1, everything
+                                runs sequentially.
     -h --help                   Show usage information and exit.
        --version                Print the version number and exit.
+
+The mikero listing format prints the header extensions as key=value lines and, after an empty
+line, one \"path<TAB>size<TAB>date\" line per entry in PBO order, with dates as dd/mm/yyyy
+hh:mm:ss in UTC. ExtractPbo's own output can't be matched exactly: the date column is empty
+for entries without a timestamp, like all entries armake2 writes, and alignment padding is
+left out.
 ";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Debug, Deserialize)]
 pub struct Args {
+    /// Rewritten spellings of the original armake, see `legacy::translate_args`
+    #[serde(skip)]
+    pub legacy: Vec<String>,
     cmd_rapify: bool,
     cmd_preprocess: bool,
+    cmd_configbundle: bool,
     cmd_derapify: bool,
+    cmd_configget: bool,
+    cmd_configdiff: bool,
+    cmd_modelcfg: bool,
     cmd_binarize: bool,
+    cmd_p3dinfo: bool,
     cmd_build: bool,
     cmd_pack: bool,
+    cmd_packmission: bool,
+    cmd_cache: bool,
+    cmd_gc: bool,
     cmd_inspect: bool,
     cmd_unpack: bool,
     cmd_cat: bool,
+    cmd_grep: bool,
+    cmd_pbodiff: bool,
+    cmd_pbopatch: bool,
     cmd_keygen: bool,
     cmd_sign: bool,
+    cmd_resign: bool,
     cmd_verify: bool,
+    cmd_checksignatures: bool,
+    cmd_serve: bool,
+    cmd_manifest: bool,
+    cmd_create: bool,
     cmd_paa2img: bool,
     cmd_img2paa: bool,
+    cmd_paaset: bool,
+    cmd_paaalpha: bool,
+    cmd_paacompare: bool,
+    cmd_paathumbs: bool,
+    cmd_paasetalpha: bool,
+    cmd_paaretype: bool,
+    cmd_texheaders: bool,
+    cmd_texreport: bool,
+    cmd_explain: bool,
+    cmd_texturepolicy: bool,
     flag_verbose: bool,
     flag_force: bool,
+    flag_skip_existing: bool,
+    flag_if_newer: bool,
+    flag_lenient: bool,
+    flag_flat: bool,
+    flag_dedupe: bool,
+    flag_keep_empty_dirs: bool,
+    flag_separator: Option<String>,
+    flag_lint_types: bool,
+    flag_strict_duplicates: bool,
+    flag_strict_directives: bool,
+    flag_string_encoding: Option<String>,
+    flag_string_properties: Option<String>,
+    flag_schema: Option<String>,
+    flag_share_classes: bool,
+    flag_quantize_floats: Option<usize>,
+    flag_max_output_size: Option<usize>,
+    flag_max_macro_depth: Option<usize>,
+    flag_max_include_depth: Option<usize>,
     flag_warning: Vec<String>,
     flag_include: Vec<String>,
     flag_exclude: Vec<String>,
     flag_headerext: Vec<String>,
-    flag_key: Option<String>,
+    flag_key: Vec<String>,
     flag_signature: Option<String>,
     flag_indent: Option<String>,
+    flag_source_map: Option<String>,
+    flag_format: Option<String>,
+    flag_offset: bool,
+    flag_mod_dir: Vec<String>,
+    flag_resolve_inheritance: bool,
+    flag_json: bool,
+    flag_quiet: bool,
+    flag_ignore_property: Vec<String>,
     flag_v2: bool,
+    flag_config: Option<String>,
+    flag_report: Option<String>,
+    flag_report_format: Option<String>,
+    flag_all: bool,
+    flag_keep_going: bool,
+    flag_authority: Vec<String>,
+    flag_align: Option<u32>,
+    flag_cache: Option<String>,
+    flag_no_cache: bool,
+    flag_cache_file: Option<String>,
+    flag_no_cache_trust: bool,
+    flag_max_age: Option<u64>,
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    flag_idle_timeout: Option<u64>,
+    flag_binarize_sqm: bool,
+    flag_convert_images: bool,
+    flag_world: Option<String>,
+    flag_auto_cfgpatches: bool,
+    flag_check_paths: Vec<String>,
+    flag_strict_resources: bool,
+    flag_texture_max_size: Option<u32>,
+    flag_texture_policy: Option<String>,
+    flag_check_audio: bool,
+    flag_enforce: bool,
+    flag_check_cfgpatches: bool,
     flag_compress: bool,
+    flag_lzo: Option<String>,
     flag_type: Option<String>,
+    flag_analyze: bool,
+    flag_formats: Option<String>,
+    flag_write_best: bool,
+    flag_source: Option<String>,
+    flag_max_psnr_loss: Option<f64>,
+    flag_flag: Option<u32>,
+    flag_avg_color: Option<String>,
+    flag_swizzle: Option<String>,
+    flag_retype: Option<String>,
+    flag_allow_lossy_alpha: bool,
+    flag_size: Option<u32>,
+    flag_strict: bool,
+    flag_level: Option<usize>,
+    flag_flip_v: bool,
+    flag_flip_h: bool,
+    flag_ignore_exif: bool,
+    flag_quality: Option<String>,
+    flag_tag_tool: bool,
+    flag_manifest: Option<String>,
+    flag_message_format: Option<String>,
+    flag_dedupe_warnings: bool,
+    flag_json_diagnostics: Option<String>,
+    flag_albedo: bool,
+    flag_albedo_range: Option<String>,
+    flag_max_outside: Option<f32>,
+    flag_csv: Option<String>,
+    flag_threads: Option<usize>,
+    flag_ignore_case: bool,
+    flag_regex: bool,
+    flag_entries: Vec<String>,
+    flag_derap: bool,
+    flag_binary: bool,
     flag_version: bool,
     arg_source: Option<String>,
     arg_target: Option<String>,
     arg_filename: String,
     arg_sourcefolder: String,
+    arg_policy: String,
     arg_targetfolder: String,
     arg_keyname: String,
     arg_privatekey: String,
     arg_publickey: String,
     arg_signature: Option<String>,
     arg_pbo: String,
+    arg_addonsfolder: String,
+    arg_keysfolder: String,
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    arg_address: String,
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    arg_rootfolder: String,
+    arg_old: String,
+    arg_new: String,
+    arg_patch: String,
+    arg_mask: String,
+    arg_entrypath: String,
+    arg_cachedir: Option<String>,
+    arg_key: String,
+    arg_moddir: String,
+    arg_manifest: Option<String>,
+    arg_pattern: String,
+    arg_pbos: Vec<String>,
+    arg_code: String,
 }
 
 fn get_input(args: &Args) -> Result<Input, Error> {
@@ -122,18 +457,103 @@ fn get_input(args: &Args) -> Result<Input, Error> {
     }
 }
 
+fn output_policy(args: &Args) -> OutputPolicy {
+    OutputPolicy::from_flags(args.flag_force, args.flag_skip_existing, args.flag_if_newer)
+}
+
 fn get_output(args: &Args) -> Result<Output, Error> {
-    if let Some(target) = &args.arg_target {
-        // Check if file exists and force flag is not set
-        if Path::new(target).exists() && !args.flag_force {
-            return Err(error!("Output file '{}' already exists. Use -f/--force to overwrite.", target));
+    open_output(output_policy(args), args.arg_target.as_deref().map(Path::new))
+}
+
+/// Opens the target with the policy, or stdout without one.
+fn open_output(policy: OutputPolicy, target: Option<&Path>) -> Result<Output, Error> {
+    if let Some(target) = target {
+        match policy.create(target) {
+            Ok(Some(file)) => Ok(Output::File(file)),
+            Ok(None) => Err(coded_error!("E0805", "Output file '{}' already exists.", target.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(e),
+            Err(e) => Err(e).prepend_error("Failed to open output file:")
         }
-        Ok(Output::File(File::create(target).prepend_error("Failed to open output file:")?))
     } else {
         Ok(Output::Standard(stdout()))
     }
 }
 
+/// Whether the command prints its output to stdout, where `--json-diagnostics -` would mix with it.
+fn writes_to_stdout(args: &Args) -> bool {
+    let has_target = args.cmd_rapify || args.cmd_preprocess || args.cmd_configbundle || args.cmd_build || args.cmd_pack || args.cmd_packmission;
+    (has_target && args.arg_target.is_none()) || args.cmd_texreport
+}
+
+/// Returns the filesystem of the `--mod-dir` folders, if there are any.
+fn mod_dirs(args: &Args) -> Option<vfs::VirtualFilesystem> {
+    if args.flag_mod_dir.is_empty() {
+        return None;
+    }
+    Some(vfs::VirtualFilesystem::new(args.flag_mod_dir.iter().map(PathBuf::from).collect()))
+}
+
+/// Returns the default preprocessing limits with the ones given by flags.
+fn preprocess_limits(args: &Args) -> preprocess::PreprocessLimits {
+    let defaults = preprocess::PreprocessLimits::default();
+    preprocess::PreprocessLimits {
+        max_output_size: args.flag_max_output_size.unwrap_or(defaults.max_output_size),
+        max_macro_depth: args.flag_max_macro_depth.unwrap_or(defaults.max_macro_depth),
+        max_include_depth: args.flag_max_include_depth.unwrap_or(defaults.max_include_depth),
+    }
+}
+
+/// Prints how many files of a folder were converted and skipped, failing if any conversion did.
+fn finish_batch(summary: BatchSummary) -> Result<(), Error> {
+    eprintln!("{}", summary);
+    if summary.failed > 0 {
+        return Err(coded_error!("E0822", "{} of {} files failed to convert.", summary.failed, summary.converted + summary.skipped + summary.failed));
+    }
+    Ok(())
+}
+
+/// Checks the source of a command that reads a PBO for encrypted and renamed containers.
+fn check_pbo_source(args: &Args) -> Result<(), Error> {
+    match &args.arg_source {
+        Some(source) => detect::check_pbo_file(Path::new(source)),
+        None => Ok(())
+    }
+}
+
+/// Returns the key given with `-k` to commands that sign the PBO they write with a single key.
+fn single_key(args: &Args) -> Result<Option<&String>, Error> {
+    if args.flag_key.len() > 1 {
+        return Err(coded_error!("E0902", "Got {} private keys, the PBO can only be signed with one here. Use sign to add more signatures.", args.flag_key.len()));
+    }
+    Ok(args.flag_key.first())
+}
+
+/// Returns the folders in a folder of addons for `build --all`, sorted by name. Hidden folders
+/// like the build cache are left out.
+fn addon_folders(folder: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut folders = Vec::new();
+    for entry in std::fs::read_dir(folder).prepend_error(format!("Failed to read {}:", folder.display()))? {
+        let path = entry?.path();
+        if path.is_dir() && !path.file_name().unwrap().to_string_lossy().starts_with('.') {
+            folders.push(path);
+        }
+    }
+    if folders.is_empty() {
+        return Err(coded_error!("E0903", "{} contains no addon folders to build.", folder.display()));
+    }
+    folders.sort();
+    Ok(folders)
+}
+
+/// Result of building one PBO, its stats and the authority of its signature
+type PboResult = Result<(pbo::BuildStats, Option<String>), Error>;
+
+fn print_skipped(target: &Path, written: bool) {
+    if !written {
+        eprintln!("Skipped {}.", target.display());
+    }
+}
+
 fn run_command(args: &Args) -> Result<(), Error> {
     let path = args.arg_source.as_ref().map(PathBuf::from);
     let signature = args.arg_signature.as_ref().map(PathBuf::from);
@@ -141,66 +561,561 @@ fn run_command(args: &Args) -> Result<(), Error> {
     let mut includefolders: Vec<PathBuf> = args.flag_include.iter().map(PathBuf::from).collect();
     includefolders.push(PathBuf::from("."));
 
+    if let Some(threads) = args.flag_threads {
+        if threads == 0 {
+            return Err(coded_error!("E0901", "The number of threads must be at least 1."));
+        }
+        parallel::set_thread_count(threads)?;
+    }
+
+    let encoding = args.flag_string_encoding.as_deref().map(config::parse_string_encoding).transpose()?.unwrap_or_default();
+
     if args.cmd_binarize {
         binarize::cmd_binarize(PathBuf::from(args.arg_source.as_ref().unwrap()), PathBuf::from(args.arg_target.as_ref().unwrap()))
     } else if args.cmd_rapify {
-        config::cmd_rapify(&mut get_input(&args)?, &mut get_output(&args)?, path, &includefolders)
+        let mut options = config::ParseOptions::default();
+        if args.flag_lint_types {
+            let mut lint = config::TypeLint::default();
+            if let Some(file) = &args.flag_string_properties {
+                lint.extend_from_file(Path::new(file)).prepend_error("Failed to read string properties file:")?;
+            }
+            options.type_lint = Some(lint);
+        }
+        options.strict_duplicates = args.flag_strict_duplicates;
+        options.strict_directives = args.flag_strict_directives;
+        options.limits = preprocess_limits(args);
+        if let Some(schema) = &args.flag_schema {
+            options.schema = Some(schema::Schema::load(&PathBuf::from(schema)).prepend_error("Failed to read schema:")?);
+        }
+
+        if args.flag_quantize_floats.is_some_and(|digits| !(1..=9).contains(&digits)) {
+            return Err(coded_error!("E0901", "--quantize-floats takes 1 to 9 significant digits."));
+        }
+        let rapify_options = config::RapifyOptions {
+            encoding,
+            quantize_floats: args.flag_quantize_floats,
+            share_classes: args.flag_share_classes,
+        };
+
+        config::cmd_rapify(&mut get_input(args)?, &mut get_output(args)?, path, &includefolders, &options, &rapify_options, args.flag_verbose)
     } else if args.cmd_derapify {
-        config::cmd_derapify(&mut get_input(&args)?, &mut get_output(&args)?)
+        match &args.flag_source_map {
+            Some(map) => {
+                let mut map = File::create(map).prepend_error("Failed to open source map file:")?;
+                config::cmd_derapify_with_source_map(&mut get_input(args)?, &mut get_output(args)?, &mut map, encoding)
+            },
+            None => config::cmd_derapify(&mut get_input(args)?, &mut get_output(args)?, encoding)
+        }
+    } else if args.cmd_configget {
+        if args.flag_mod_dir.is_empty() {
+            config::cmd_config_get(&mut get_input(args)?, &mut stdout(), &args.arg_entrypath, args.flag_offset)
+        } else {
+            let filesystem = vfs::VirtualFilesystem::new(args.flag_mod_dir.iter().map(PathBuf::from).collect());
+            let data = filesystem.read(args.arg_source.as_ref().unwrap())?;
+            config::cmd_config_get(&mut Cursor::new(data), &mut stdout(), &args.arg_entrypath, args.flag_offset)
+        }
+    } else if args.cmd_configdiff {
+        let options = configdiff::DiffOptions {
+            resolve_inheritance: args.flag_resolve_inheritance,
+            json: args.flag_json,
+            color: args.arg_target.is_none() && stdout().is_terminal(),
+            ignore: args.flag_ignore_property.clone(),
+        };
+        let diff = |mut output: &mut dyn std::io::Write| {
+            let mut old = File::open(&args.arg_old).prepend_error("Failed to open old config:")?;
+            let mut new = File::open(&args.arg_new).prepend_error("Failed to open new config:")?;
+            configdiff::cmd_config_diff(&mut old, &mut new, &mut output, &options)
+        };
+        if args.flag_quiet {
+            quiet_exit(diff(&mut sink()));
+        }
+        diff(&mut get_output(args)?).map(|_| ())
+    } else if args.cmd_modelcfg {
+        modelcfg::cmd_modelcfg_check(&PathBuf::from(args.arg_source.as_ref().unwrap()), &includefolders)
+    } else if args.cmd_p3dinfo {
+        p3d::cmd_p3d_info(&mut get_input(args)?, &mut stdout())
     } else if args.cmd_preprocess {
-        preprocess::cmd_preprocess(&mut get_input(&args)?, &mut get_output(&args)?, path, &includefolders)
+        preprocess::cmd_preprocess(&mut get_input(&args)?, &mut get_output(&args)?, path, &includefolders, &preprocess_limits(args))
+    } else if args.cmd_configbundle {
+        match mod_dirs(args) {
+            Some(filesystem) => {
+                let source = args.arg_source.as_ref().ok_or_else(|| coded_error!("E0903", "configbundle with --mod-dir requires the path of the config in game."))?;
+                let data = filesystem.read(source)?;
+                // Configs in PBOs are usually rapified and have no includes left to resolve
+                if data.starts_with(b"\0raP") {
+                    config::cmd_derapify(&mut Cursor::new(data), &mut get_output(args)?, encoding)
+                } else {
+                    config::cmd_config_bundle(&mut Cursor::new(data), &mut get_output(args)?, None, &includefolders, &config::ParseOptions::default())
+                }
+            },
+            None => config::cmd_config_bundle(&mut get_input(args)?, &mut get_output(args)?, path, &includefolders, &config::ParseOptions::default())
+        }
     } else if args.cmd_build || args.cmd_pack {
-        let flag_privatekey = args.flag_key.as_ref().map(PathBuf::from);
+        // Flags override the settings of the project file
+        let project_file = match &args.flag_config {
+            Some(file) => Some(PathBuf::from(file)),
+            None => project::ProjectConfig::find(Path::new(&args.arg_sourcefolder))
+        };
+        let project = project_file.as_deref().map(project::ProjectConfig::load).transpose()?.unwrap_or_default();
+        let or_project = |flags: &Vec<String>, settings: &Vec<String>| if flags.is_empty() { settings.clone() } else { flags.clone() };
+        let excludes = or_project(&args.flag_exclude, &project.build.exclude);
+        let headerext = or_project(&args.flag_headerext, &project.build.headerext);
+        let includefolders = if args.flag_include.is_empty() {
+            project.preprocess.include.iter().cloned().chain(std::iter::once(PathBuf::from("."))).collect()
+        } else {
+            includefolders
+        };
+        let binarize = args.cmd_build && project.build.binarize.unwrap_or(true);
+        let texture_max_size = args.flag_texture_max_size.or(project.textures.max_size);
+        let texture_policy = args.flag_texture_policy.as_ref().map(PathBuf::from).or_else(|| project.textures.policy.clone())
+            .map(|path| texpolicy::TexturePolicy::load(&path)).transpose()?;
+
+        let flag_privatekey = single_key(args)?.map(PathBuf::from).or_else(|| project.sign.key.clone());
         let flag_signature = args.flag_signature.as_ref().map(PathBuf::from);
 
+        if args.flag_all && args.arg_target.is_none() {
+            return Err(coded_error!("E0903", "build --all requires the folder to write the PBOs to."));
+        }
+        if args.flag_all && flag_signature.is_some() {
+            return Err(coded_error!("E0902", "Cannot use one signature path for several PBOs."));
+        }
         if flag_privatekey.is_some() && args.arg_target.is_none() {
-            return Err(error!("Cannot sign a pbo that is piped to stdout."));
+            return Err(coded_error!("E0902", "Cannot sign a pbo that is piped to stdout."));
+        }
+        if args.flag_report.is_some() && args.arg_target.is_none() {
+            return Err(coded_error!("E0902", "Cannot write a report of a pbo that is piped to stdout."));
+        }
+        let report_format = args.flag_report_format.as_deref().map(buildreport::parse_report_format).transpose()?
+            .unwrap_or(buildreport::ReportFormat::Json);
+
+        let alignment = args.flag_align.or(project.build.align).unwrap_or(1);
+        if texture_max_size == Some(0) {
+            return Err(coded_error!("E0901", "The maximum texture size must be at least 1."));
+        }
+
+        let source = PathBuf::from(&args.arg_sourcefolder);
+        let jobs: Vec<(PathBuf, Option<PathBuf>)> = if args.flag_all {
+            let target = PathBuf::from(args.arg_target.as_ref().unwrap());
+            let targets: Vec<(PathBuf, PathBuf)> = addon_folders(&source)?.into_iter()
+                .map(|addon| {
+                    let name = format!("{}.pbo", addon.file_name().unwrap().to_string_lossy());
+                    let target = target.join(name);
+                    (addon, target)
+                })
+                .collect();
+            // Folders differing only in case would be built into the same PBO at the same time
+            check_target_collisions(&targets)?;
+            create_folder(&target)?;
+            targets.into_iter().map(|(addon, target)| (addon, Some(target))).collect()
+        } else {
+            vec![(source, args.arg_target.as_ref().map(PathBuf::from))]
+        };
+        let mut pending = Vec::new();
+        for (source, target) in jobs {
+            match &target {
+                Some(target) if output_policy(args).skips(&source, target)? => print_skipped(target, false),
+                _ => pending.push((source, target))
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
         }
 
-        if args.cmd_build {
-            pbo::cmd_build(PathBuf::from(&args.arg_sourcefolder), &mut get_output(&args)?, &args.flag_headerext, &args.flag_exclude, &includefolders)?;
+        let build_pbo = |source: &Path, target: Option<&Path>, progress: Option<&dyn Fn(progress::Progress)>| -> PboResult {
+            let pack = pbo::PackOptions {
+                headerext: &headerext,
+                excludes: &excludes,
+                alignment,
+                texture_max_size,
+                texture_policy: texture_policy.as_ref(),
+                dedupe: args.flag_dedupe,
+                check_audio: args.flag_check_audio,
+                keep_empty_dirs: args.flag_keep_empty_dirs,
+                progress,
+            };
+            let write_pbo = |mut output: &mut dyn Write| -> Result<pbo::BuildStats, Error> {
+                if binarize {
+                    let cfgpatches = match (args.flag_auto_cfgpatches, args.flag_check_cfgpatches) {
+                        (true, _) => pbo::CfgPatchesMode::Fix,
+                        (_, true) => pbo::CfgPatchesMode::Check,
+                        _ => pbo::CfgPatchesMode::Ignore
+                    };
+                    let resources = resources::ResourceCheck {
+                        paths: args.flag_check_paths.iter().map(PathBuf::from).collect(),
+                        strict: args.flag_strict_resources,
+                    };
+                    let cache = if args.flag_no_cache {
+                        None
+                    } else {
+                        let directory = args.flag_cache.as_ref().map(PathBuf::from).unwrap_or_else(|| buildcache::BuildCache::default_directory(source));
+                        Some(buildcache::BuildCache::new(directory, &format!("{:?} {:?}", includefolders, cfgpatches)))
+                    };
+                    let filesystem = mod_dirs(args);
+                    pbo::cmd_build(source.to_path_buf(), &mut output, &pbo::BuildOptions {
+                        pack,
+                        includefolders: &includefolders,
+                        cache: cache.as_ref(),
+                        cfgpatches,
+                        resources,
+                        filesystem: filesystem.as_ref(),
+                    })
+                } else {
+                    pbo::cmd_pack(source.to_path_buf(), &mut output, &pack)
+                }
+            };
+            // Written next to the target first, so a failed build never leaves part of a PBO
+            let stats = match target {
+                Some(target) => {
+                    let mut stats = None;
+                    let written = output_policy(args).write(target, |file| {
+                        stats = Some(write_pbo(file)?);
+                        Ok(())
+                    })?;
+                    match stats {
+                        Some(stats) if written => stats,
+                        _ => return Err(coded_error!("E0805", "Output file '{}' already exists.", target.display()))
+                    }
+                },
+                None => write_pbo(&mut stdout())?
+            };
+
+            let authority = match (&flag_privatekey, target) {
+                (Some(pkey), Some(target)) => {
+                    let mut file = File::open(pkey).prepend_error(format!("Failed to open {}:", pkey.display()))?;
+                    let authority = sign::BIPrivateKey::read(&mut file).map(|key| key.name().to_string()).ok();
+                    sign::cmd_sign(pkey.clone(), target.to_path_buf(), flag_signature.clone(), project.sign_version()?)?;
+                    authority
+                },
+                _ => None
+            };
+            Ok((stats, authority))
+        };
+
+        // Each result with the number of warnings raised for it, `None` for PBOs that weren't
+        // started after another one failed
+        let outcomes: Vec<Option<(PboResult, u32)>> = if let [(source, target)] = pending.as_slice() {
+            let progress = progress::stderr_progress().map(|p| p as &dyn Fn(progress::Progress));
+            let warnings_before = warnings::get_total_warning_count();
+            let result = build_pbo(source, target.as_deref(), progress);
+            vec![Some((result, warnings::get_total_warning_count().saturating_sub(warnings_before)))]
         } else {
-            pbo::cmd_pack(PathBuf::from(&args.arg_sourcefolder), &mut get_output(&args)?, &args.flag_headerext, &args.flag_exclude)?;
+            let stopped = AtomicBool::new(false);
+            let built: Vec<Option<(PboResult, Vec<PendingWarning>)>> = parallel::map_collect(pending.clone(), |(source, target)| {
+                if stopped.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let buffer: WarningBuffer = Arc::default();
+                let result = buffer_warnings(buffer.clone(), || build_pbo(&source, target.as_deref(), None));
+                if result.is_err() && !args.flag_keep_going {
+                    stopped.store(true, Ordering::Relaxed);
+                }
+                Some((result, std::mem::take(&mut *buffer.lock().unwrap())))
+            });
+
+            // Printed one PBO after the other, so repeats are only suppressed and warnings only
+            // counted within the PBO they were raised for, like when building it alone
+            built.into_iter().map(|outcome| outcome.map(|(result, warnings)| {
+                warnings::begin_warning_scope();
+                let warnings_before = warnings::get_total_warning_count();
+                emit_warnings(current_warning_buffer().as_ref(), warnings);
+                (result, warnings::get_total_warning_count().saturating_sub(warnings_before))
+            })).collect()
+        };
+
+        // Reported either way, failed builds with their error
+        if let Some(report) = &args.flag_report {
+            let mut pbos = Vec::new();
+            for ((source, target), outcome) in pending.iter().zip(&outcomes) {
+                let (Some(target), Some((result, warnings))) = (target, outcome) else { continue };
+                pbos.push(match result {
+                    Ok((stats, authority)) => buildreport::PboReport::built(source, target, stats, authority.clone(), *warnings)?,
+                    Err(e) => buildreport::PboReport::failed(source, target, e, *warnings)
+                });
+            }
+            buildreport::BuildReport::new(pbos).write_to_file(report_format, Path::new(report))?;
+        }
+
+        if !args.flag_all {
+            return outcomes.into_iter().flatten().next().unwrap().0.map(|_| ());
+        }
+        let mut failed = 0;
+        for ((source, _), outcome) in pending.iter().zip(outcomes) {
+            if let Some((Err(e), _)) = outcome {
+                Err::<(), Error>(e).prepend_error(format!("Failed to build {}:", source.display())).print_error(false);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(coded_error!("E0829", "{} of {} PBOs failed to build.", failed, pending.len()));
+        }
+        Ok(())
+    } else if args.cmd_packmission {
+        let source = PathBuf::from(&args.arg_sourcefolder);
+        let options = mission::MissionOptions {
+            binarize_sqm: args.flag_binarize_sqm,
+            convert_images: args.flag_convert_images,
+            world: args.flag_world.clone(),
+        };
+        let pbo_name = mission::mission_pbo_name(&source, options.world.as_deref())?;
+
+        let target = args.arg_target.as_ref().map(|t| {
+            let target = PathBuf::from(t);
+            if target.is_dir() { target.join(&pbo_name) } else { target }
+        });
+        if let Some(target) = &target && target.file_name() != Some(std::ffi::OsStr::new(&pbo_name)) {
+            warning(format!("The PBO of the mission should be named {}.", pbo_name), Some("mission-pbo-name"), (None, None));
+        }
+        let key = single_key(args)?;
+        if key.is_some() && target.is_none() {
+            return Err(coded_error!("E0902", "Cannot sign a pbo that is piped to stdout."));
+        }
+
+        let progress = progress::stderr_progress().map(|p| p as &dyn Fn(progress::Progress));
+        match &target {
+            Some(target) => {
+                if target.exists() && !args.flag_force {
+                    return Err(coded_error!("E0805", "Output file '{}' already exists. Use -f/--force to overwrite.", target.display()));
+                }
+                let mut file = create_file(target)?;
+                mission::cmd_pack_mission(source, &mut file, &args.flag_headerext, &args.flag_exclude, &options, progress)?;
+            },
+            None => mission::cmd_pack_mission(source, &mut stdout(), &args.flag_headerext, &args.flag_exclude, &options, progress)?
         }
 
-        if let Some(pkey) = flag_privatekey {
-            sign::cmd_sign(pkey, PathBuf::from(args.arg_target.as_ref().unwrap()), flag_signature, sign::BISignVersion::V3)?;
+        if let (Some(pkey), Some(target)) = (key, target) {
+            sign::cmd_sign(PathBuf::from(pkey), target, args.flag_signature.as_ref().map(PathBuf::from), sign::BISignVersion::V3)?;
         }
 
         Ok(())
+    } else if args.cmd_cache && args.cmd_gc {
+        let directory = PathBuf::from(args.arg_cachedir.as_deref().unwrap_or(buildcache::DEFAULT_CACHE_DIRECTORY));
+        buildcache::cmd_cache_gc(&directory, args.flag_max_age.unwrap_or(30))
     } else if args.cmd_inspect {
-        pbo::cmd_inspect(&mut get_input(&args)?)
+        let format = args.flag_format.as_deref().map(pbo::parse_listing_format).transpose()?;
+        match (&args.arg_source, format) {
+            (Some(source), None) => detect::cmd_inspect_any(Path::new(source)),
+            (_, format) => {
+                check_pbo_source(args)?;
+                pbo::cmd_inspect_with_format(&mut get_input(args)?, &mut stdout(), format.unwrap_or_default())
+            }
+        }
     } else if args.cmd_cat {
+        check_pbo_source(args)?;
         pbo::cmd_cat(&mut get_input(&args)?, &mut get_output(&args)?, &args.arg_filename)
+    } else if args.cmd_grep {
+        let options = pbogrep::GrepOptions {
+            ignore_case: args.flag_ignore_case,
+            regex: args.flag_regex,
+            entries: args.flag_entries.clone(),
+            derap: args.flag_derap,
+            binary: args.flag_binary,
+        };
+        let pbos: Vec<PathBuf> = args.arg_pbos.iter().map(PathBuf::from).collect();
+        pbogrep::cmd_pbo_grep(&args.arg_pattern, &pbos, &options, &mut stdout())?;
+        Ok(())
+    } else if args.cmd_pbodiff {
+        detect::check_pbo_file(Path::new(&args.arg_old))?;
+        detect::check_pbo_file(Path::new(&args.arg_new))?;
+        let mut old = File::open(&args.arg_old).prepend_error("Failed to open old PBO:")?;
+        let mut new = File::open(&args.arg_new).prepend_error("Failed to open new PBO:")?;
+        patch::cmd_pbo_diff(&mut old, &mut new, &mut get_output(args)?)
+    } else if args.cmd_pbopatch {
+        detect::check_pbo_file(Path::new(&args.arg_old))?;
+        let mut old = File::open(&args.arg_old).prepend_error("Failed to open old PBO:")?;
+        let mut patchfile = File::open(&args.arg_patch).prepend_error("Failed to open patch:")?;
+        patch::cmd_pbo_patch(&mut old, &mut patchfile, &mut get_output(args)?)
     } else if args.cmd_unpack {
-        pbo::cmd_unpack(&mut get_input(&args)?, PathBuf::from(&args.arg_targetfolder), args.flag_force)
+        let flat = args.flag_flat.then(|| args.flag_separator.as_deref().unwrap_or(pbo::DEFAULT_FLAT_SEPARATOR));
+        let target = PathBuf::from(&args.arg_targetfolder);
+        match &args.arg_source {
+            Some(source) if Path::new(source).is_dir() => {
+                let count = pbo::cmd_unpack_folder(Path::new(source), &target, args.flag_force, args.flag_lenient, flat, args.flag_dedupe, args.flag_keep_empty_dirs)?;
+                eprintln!("Unpacked {} PBOs.", count);
+                Ok(())
+            },
+            _ => {
+                check_pbo_source(args)?;
+                pbo::cmd_unpack(&mut get_input(&args)?, target, args.flag_force, args.flag_lenient, flat, args.flag_dedupe, args.flag_keep_empty_dirs)
+            }
+        }
     } else if args.cmd_keygen {
         sign::cmd_keygen(PathBuf::from(&args.arg_keyname), args.flag_force)
     } else if args.cmd_sign {
         let version = if args.flag_v2 { sign::BISignVersion::V2 } else { sign::BISignVersion::V3 };
-        sign::cmd_sign(PathBuf::from(&args.arg_privatekey), PathBuf::from(&args.arg_pbo), signature, version)
+        let keys: Vec<PathBuf> = std::iter::once(&args.arg_privatekey).chain(&args.flag_key).map(PathBuf::from).collect();
+        if signature.is_some() {
+            if keys.len() > 1 || !args.flag_authority.is_empty() {
+                return Err(coded_error!("E0902", "A signature path can only be given when signing with a single key and without --authority."));
+            }
+            return sign::cmd_sign(keys[0].clone(), PathBuf::from(&args.arg_pbo), signature, version);
+        }
+        if !args.flag_authority.is_empty() && args.flag_authority.len() != keys.len() {
+            return Err(coded_error!("E0902", "Got {} authorities for {} keys, --authority has to be given once per key.", args.flag_authority.len(), keys.len()));
+        }
+
+        let authorities = args.flag_authority.iter().cloned().map(Some).chain(std::iter::repeat(None));
+        let keys: Vec<(PathBuf, Option<String>)> = keys.into_iter().zip(authorities).collect();
+        for path in sign::cmd_sign_with_keys(&keys, &PathBuf::from(&args.arg_pbo), version)? {
+            if args.flag_verbose {
+                eprintln!("Wrote {}", path.display());
+            }
+        }
+        Ok(())
+    } else if args.cmd_resign {
+        let version = if args.flag_v2 { sign::BISignVersion::V2 } else { sign::BISignVersion::V3 };
+        let targets: Vec<PathBuf> = args.arg_pbos.iter().map(PathBuf::from).collect();
+        let paths = sign::cmd_resign(Path::new(&args.arg_privatekey), &targets, version)?;
+        if args.flag_verbose {
+            for path in &paths {
+                eprintln!("Wrote {}", path.display());
+            }
+        }
+        eprintln!("Converted {} signatures.", paths.len());
+        Ok(())
+    } else if args.cmd_manifest {
+        let directory = PathBuf::from(&args.arg_moddir);
+        let manifest_path = args.arg_manifest.as_ref().map(PathBuf::from).unwrap_or_else(|| directory.join(manifest::MANIFEST_FILE));
+        if args.cmd_create {
+            if manifest_path.exists() && !args.flag_force {
+                return Err(coded_error!("E0805", "Manifest '{}' already exists. Use -f/--force to overwrite.", manifest_path.display()));
+            }
+            let mut buffer: Vec<u8> = Vec::new();
+            manifest::cmd_manifest_create(&directory, Path::new(&args.arg_key), &mut buffer, &args.flag_exclude)?;
+            std::fs::write(&manifest_path, buffer).prepend_error("Failed to write manifest:")
+        } else {
+            let mut file = File::open(&manifest_path).prepend_error("Failed to open manifest:")?;
+            manifest::cmd_manifest_verify(&directory, Path::new(&args.arg_key), &mut file)
+        }
     } else if args.cmd_verify {
-        sign::cmd_verify(PathBuf::from(&args.arg_publickey), PathBuf::from(&args.arg_pbo), signature)
+        let cache = args.flag_cache_file.as_ref().map(|path| verifycache::VerifyCache::new(PathBuf::from(path), !args.flag_no_cache_trust));
+        sign::cmd_verify(PathBuf::from(&args.arg_publickey), PathBuf::from(&args.arg_pbo), signature, cache.as_ref())
+    } else if args.cmd_checksignatures {
+        let cache = args.flag_cache_file.as_ref().map(|path| verifycache::VerifyCache::new(PathBuf::from(path), !args.flag_no_cache_trust));
+        sign::cmd_check_signatures(PathBuf::from(&args.arg_addonsfolder), PathBuf::from(&args.arg_keysfolder), progress::stderr_progress(), cache.as_ref())
+    } else if args.cmd_serve {
+        serve_verify(args)
     } else if args.cmd_paa2img {
         if args.arg_source.is_none() || args.arg_target.is_none() {
-            return Err(error!("paa2img requires both source and target paths"));
+            return Err(coded_error!("E0903", "paa2img requires both source and target paths"));
+        }
+        let options = paa::DecodeOptions {
+            strict: args.flag_strict,
+            flip_vertical: args.flag_flip_v,
+            flip_horizontal: args.flag_flip_h,
+        };
+        let level = args.flag_level.unwrap_or(0);
+        let source = PathBuf::from(args.arg_source.as_ref().unwrap());
+        let target = PathBuf::from(args.arg_target.as_ref().unwrap());
+        if source.is_dir() {
+            finish_batch(paa::cmd_paa2img_folder(&source, &target, "png", level, &options, output_policy(args))?)
+        } else {
+            print_skipped(&target, paa::cmd_paa2img(&source, &target, level, &options, output_policy(args))?);
+            Ok(())
         }
-        paa::cmd_paa2img(&PathBuf::from(args.arg_source.as_ref().unwrap()), &PathBuf::from(args.arg_target.as_ref().unwrap()), args.flag_force)
+    } else if args.cmd_img2paa && args.flag_analyze {
+        let formats = match &args.flag_formats {
+            Some(formats) => paaanalysis::parse_formats(formats)?,
+            None => paaanalysis::DEFAULT_FORMATS.to_vec()
+        };
+        paaanalysis::cmd_img2paa_analyze(&PathBuf::from(args.arg_source.as_ref().unwrap()), args.arg_target.as_ref().map(Path::new),
+            &formats, args.flag_write_best, args.flag_compress, args.flag_force)
     } else if args.cmd_img2paa {
         if args.arg_source.is_none() || args.arg_target.is_none() {
-            return Err(error!("img2paa requires both source and target paths"));
+            return Err(coded_error!("E0903", "img2paa requires both source and target paths"));
         }
-        let paa_type = match args.flag_type.as_ref().map(|s| s.as_str()) {
-            Some("DXT1") => paa::PaaType::DXT1,
-            Some("DXT5") | None => paa::PaaType::DXT5,
-            Some(t) => return Err(error!("Unknown PAA type: {}. Supported types: DXT1, DXT5", t)),
+        let paa_type = args.flag_type.as_deref().map(paa::parse_paa_type_or_auto).transpose()?.flatten();
+        let lzo = match &args.flag_lzo {
+            Some(policy) => paa::parse_lzo_policy(policy)?,
+            None => paa::LzoPolicy::default()
+        };
+        let options = paa::EncodeOptions {
+            lzo,
+            flip_vertical: args.flag_flip_v,
+            flip_horizontal: args.flag_flip_h,
+            ignore_exif: args.flag_ignore_exif,
+            quality: args.flag_quality.as_deref().map(paa::parse_encode_quality).transpose()?.unwrap_or_default(),
+            tag_tool: args.flag_tag_tool,
+            ..Default::default()
         };
-        paa::cmd_img2paa(&PathBuf::from(args.arg_source.as_ref().unwrap()), &PathBuf::from(args.arg_target.as_ref().unwrap()), paa_type, args.flag_compress, args.flag_force)
+        let source = PathBuf::from(args.arg_source.as_ref().unwrap());
+        let target = PathBuf::from(args.arg_target.as_ref().unwrap());
+        if source.is_dir() {
+            finish_batch(paa::cmd_img2paa_folder(&source, &target, paa_type, &options, output_policy(args), args.flag_manifest.as_deref().map(Path::new))?)
+        } else if args.flag_manifest.is_some() {
+            Err(coded_error!("E0903", "--manifest requires a source folder."))
+        } else {
+            print_skipped(&target, paa::cmd_img2paa(&source, &target, paa_type, &options, args.flag_verbose, output_policy(args), progress::stderr_progress())?);
+            Ok(())
+        }
+    } else if args.cmd_paaset {
+        let edits = paa::TaggEdits {
+            flag: args.flag_flag,
+            avg_color: args.flag_avg_color.as_deref().map(paa::parse_avg_color).transpose()?,
+            swizzle: args.flag_swizzle.as_deref().map(paa::parse_swizzle).transpose()?,
+        };
+        let source = PathBuf::from(args.arg_source.as_ref().unwrap());
+        let target = args.arg_target.as_ref().map(PathBuf::from).unwrap_or_else(|| source.clone());
+        paa::cmd_paa_set(&source, &target, &edits, args.flag_force)
+    } else if args.cmd_paaretype {
+        let paa_type = paa::parse_paa_type(args.flag_type.as_ref().unwrap())?;
+        let source = PathBuf::from(args.arg_source.as_ref().unwrap());
+        let target = args.arg_target.as_ref().map(PathBuf::from).unwrap_or_else(|| source.clone());
+        paa::cmd_paa_retype(&source, &target, paa_type, args.flag_allow_lossy_alpha, args.flag_force)
+    } else if args.cmd_paaalpha {
+        paa::cmd_paa_alpha_extract(&PathBuf::from(args.arg_source.as_ref().unwrap()), &PathBuf::from(args.arg_target.as_ref().unwrap()), args.flag_force)
+    } else if args.cmd_paacompare {
+        let compare = |mut output: &mut dyn std::io::Write| paaanalysis::cmd_paa_compare(Path::new(&args.arg_old), Path::new(&args.arg_new),
+            args.flag_source.as_deref().map(Path::new), args.flag_max_psnr_loss, &mut output);
+        if args.flag_quiet {
+            quiet_exit(compare(&mut sink()));
+        }
+        compare(&mut stdout()).map(|_| ())
+    } else if args.cmd_paathumbs {
+        let summary = paa::cmd_paa_thumbs(Path::new(&args.arg_sourcefolder), Path::new(&args.arg_targetfolder), args.flag_size.unwrap_or(64))?;
+        eprintln!("{}", summary);
+        Ok(())
+    } else if args.cmd_paasetalpha {
+        let retype = args.flag_retype.as_deref().map(paa::parse_paa_type).transpose()?;
+        let source = PathBuf::from(args.arg_source.as_ref().unwrap());
+        let target = args.arg_target.as_ref().map(PathBuf::from).unwrap_or_else(|| source.clone());
+        paa::cmd_paa_alpha_inject(&source, &PathBuf::from(&args.arg_mask), &target, retype, args.flag_force)
+    } else if args.cmd_texheaders {
+        let count = texheaders::cmd_texheaders(Path::new(&args.arg_sourcefolder), mod_dirs(args).as_ref())?;
+        if args.flag_verbose {
+            eprintln!("Wrote the headers of {} textures.", count);
+        }
+        Ok(())
+    } else if args.cmd_texreport {
+        let mut options = texreport::AlbedoOptions::default();
+        if let Some(range) = &args.flag_albedo_range {
+            (options.min, options.max) = texreport::parse_albedo_range(range)?;
+        }
+        if let Some(percent) = args.flag_max_outside {
+            options.max_outside = percent / 100.0;
+        }
+        let albedo = if args.flag_albedo { Some(&options) } else { None };
+        texreport::cmd_texture_report(Path::new(&args.arg_sourcefolder), albedo, args.flag_csv.as_deref().map(Path::new))
+    } else if args.cmd_explain {
+        diagnostics::cmd_explain(&args.arg_code, &mut stdout())
+    } else if args.cmd_texturepolicy {
+        let policy = texpolicy::TexturePolicy::load(Path::new(&args.arg_policy))?;
+        texpolicy::cmd_texture_policy_check(Path::new(&args.arg_sourcefolder), &policy, args.flag_enforce)?;
+        Ok(())
     } else {
         unreachable!()
     }
 }
 
+/// Exits without printing anything with 0 if the compared files are the same, 1 if they differ and
+/// 2 if comparing them failed.
+fn quiet_exit(result: Result<bool, Error>) -> ! {
+    std::process::exit(match result {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(_) => 2
+    })
+}
+
 pub fn args(args: &mut Args) {
     if cfg!(windows) {
         ansi_support();
@@ -217,15 +1132,52 @@ pub fn args(args: &mut Args) {
 
     // Set up warning system
     for warning in &args.flag_warning {
-        warnings::mute_warning(warning.clone());
+        match warning.split_once('=') {
+            Some((name, "unlimited")) => warnings::set_warning_unlimited(name),
+            Some((name, maximum)) => match maximum.parse::<u32>() {
+                Ok(maximum) => warnings::set_warning_maximum_for(name, maximum),
+                Err(_) => Err::<(), Error>(coded_error!("E0901", "Invalid warning maximum \"{}\", expected -w {}=<number> or -w {}=unlimited.", maximum, name, name)).print_error(true)
+            },
+            None => warnings::mute_warning(warning.clone())
+        }
     }
+    warnings::set_warning_deduplication(args.flag_dedupe_warnings);
     if args.flag_verbose {
         warnings::set_warnings_maximum(std::u32::MAX);
     }
+    if args.flag_quiet {
+        warnings::set_warnings_maximum(0);
+    }
+    match args.flag_message_format.as_deref() {
+        None | Some("text") => {},
+        Some("json") => set_json_diagnostics(true),
+        Some(format) => Err::<(), Error>(coded_error!("E0901", "Unknown message format \"{}\", expected text or json.", format)).print_error(true)
+    }
+    if let Some(output) = &args.flag_json_diagnostics {
+        if output == "-" && writes_to_stdout(args) {
+            Err::<(), Error>(coded_error!("E0902", "Cannot write the diagnostics to stdout, the command writes its output there. Use a file instead.")).print_error(true);
+        }
+        set_json_diagnostics_output(PathBuf::from(output));
+    }
+    if !args.legacy.is_empty() {
+        warning(format!("Used the command line of the original armake: {}. These spellings might be removed in the future.", args.legacy.join(", ")),
+            Some("legacy-cli"), (None, None));
+    }
 
     run_command(&args).print_error(true);
 
-    print_warning_summary();
+    finish_diagnostics();
+}
+
+#[cfg(feature = "serve")]
+fn serve_verify(args: &Args) -> Result<(), Error> {
+    let idle_timeout = args.flag_idle_timeout.map(std::time::Duration::from_secs).unwrap_or(serve::IDLE_TIMEOUT);
+    serve::cmd_serve_verify(&args.arg_address, Path::new(&args.arg_keysfolder), Path::new(&args.arg_rootfolder), idle_timeout)
+}
+
+#[cfg(not(feature = "serve"))]
+fn serve_verify(_args: &Args) -> Result<(), Error> {
+    Err(coded_error!("E0817", "This armake2 was built without the serve feature."))
 }
 
 #[cfg(windows)]
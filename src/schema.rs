@@ -0,0 +1,214 @@
+//! Opt-in check of config property names against a schema
+//!
+//! A schema lists the properties the engine knows for the classes of a config root like
+//! `CfgWeapons`. It is a JSON object with one key per root, whose value is either a list of
+//! properties valid for every class of the root or an object mapping class names to their
+//! properties, with `*` for properties valid for every class:
+//!
+//! ```json
+//! {
+//!     "CfgPatches": ["units", "weapons", "requiredVersion", "requiredAddons"],
+//!     "CfgWeapons": {
+//!         "*": ["scope", "displayName", "model", "picture"],
+//!         "ItemCore": ["ItemInfo"]
+//!     }
+//! }
+//! ```
+//!
+//! Properties listed for a class are valid for every class inheriting from it, as are the
+//! properties the parent classes define in the checked config themselves. Parents are looked up
+//! in the enclosing scopes like the engine does, so a root class may inherit from a class defined
+//! at the top level of the config. Classes that neither
+//! inherit from a listed class nor belong to a root with properties for every class are skipped,
+//! as are nested classes like `ItemInfo`.
+
+use std::collections::{HashMap};
+use std::fs::{File};
+use std::io::{BufReader, Error};
+use std::path::{Path};
+
+use serde::Deserialize;
+
+use crate::config::{Config, ConfigEntry, ConfigName, ancestor_names, inherited_entries};
+
+/// Core properties of CfgPatches, CfgWeapons and CfgVehicles
+const BUILTIN_SCHEMA: &str = r#"{
+    "CfgPatches": [
+        "name", "author", "authors", "url", "requiredVersion", "requiredAddons", "units", "weapons",
+        "magazines", "ammo", "version", "versionStr", "versionAr", "skipWhenMissingDependencies"
+    ],
+    "CfgWeapons": {
+        "*": [
+            "scope", "scopeCurator", "scopeArsenal", "author", "displayName", "displayNameShort",
+            "descriptionShort", "description", "model", "picture", "icon", "UiPicture",
+            "hiddenSelections", "hiddenSelectionsTextures", "hiddenSelectionsMaterials", "type",
+            "simulation", "magazines", "magazineWell", "modes", "muzzles", "reloadTime",
+            "magazineReloadTime", "handAnim", "baseWeapon", "cursor", "cursorAim", "dlc",
+            "recoil", "initSpeed", "maxZeroing", "discreteDistance", "discreteDistanceInitIndex",
+            "class", "inertia", "aimTransitionSpeed", "dexterity", "canShootInWater",
+            "selectionFireAnim", "cameraDir", "memoryPointCamera", "opticsZoomMin",
+            "opticsZoomMax", "opticsZoomInit", "distanceZoomMin", "distanceZoomMax", "value",
+            "allowedSlots", "mass", "weaponInfoType", "useModelOptics", "modelOptics"
+        ]
+    },
+    "CfgVehicles": {
+        "*": [
+            "scope", "scopeCurator", "scopeArsenal", "author", "displayName", "displayNameShort",
+            "description", "model", "picture", "icon", "editorPreview", "editorCategory",
+            "editorSubcategory", "vehicleClass", "faction", "side", "crew", "typicalCargo",
+            "hiddenSelections", "hiddenSelectionsTextures", "hiddenSelectionsMaterials",
+            "armor", "armorStructural", "maxSpeed", "fuelCapacity", "transportSoldier",
+            "transportMaxWeapons", "transportMaxMagazines", "transportMaxBackpacks",
+            "maximumLoad", "weapons", "magazines", "items", "linkedItems", "respawnWeapons",
+            "respawnMagazines", "respawnLinkedItems", "uniformClass", "backpack", "destrType",
+            "simulation", "mapSize", "accuracy", "cost", "threat", "camouflage", "audible",
+            "textSingular", "textPlural", "nameSound", "identityTypes", "genericNames",
+            "mass", "dlc", "animationList", "textureList", "class"
+        ]
+    }
+}"#;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RootSchema {
+    Properties(Vec<String>),
+    Classes(HashMap<String, Vec<String>>),
+}
+
+/// Known properties by config root and class
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    roots: HashMap<ConfigName, HashMap<ConfigName, Vec<String>>>,
+}
+
+/// Property that isn't in the schema for its class
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownProperty {
+    /// Path of the class, e.g. `CfgWeapons/my_rifle`
+    pub class: String,
+    /// Name of the property as written in the config
+    pub property: String,
+    /// Known property with the most similar name
+    pub suggestion: Option<String>,
+    /// Position of the property in the preprocessed input, if known
+    pub position: Option<usize>,
+}
+
+/// Returns the Levenshtein distance between two strings, ignoring case.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+impl Schema {
+    /// Returns the built-in schema with the core properties of CfgPatches, CfgWeapons and
+    /// CfgVehicles.
+    pub fn builtin() -> Schema {
+        Schema::from_json(BUILTIN_SCHEMA).unwrap()
+    }
+
+    /// Parses a schema from JSON, see the module documentation for the format.
+    pub fn from_json(input: &str) -> Result<Schema, Error> {
+        let roots: HashMap<String, RootSchema> = serde_json::from_str(input).map_err(|e| coded_error!("E0111", "Invalid schema: {}", e))?;
+
+        Ok(Schema {
+            roots: roots.into_iter().map(|(root, schema)| {
+                let classes = match schema {
+                    RootSchema::Properties(properties) => HashMap::from([(ConfigName::new("*"), properties)]),
+                    RootSchema::Classes(classes) => classes.into_iter().map(|(c, p)| (ConfigName::new(&c), p)).collect(),
+                };
+                (ConfigName::new(&root), classes)
+            }).collect(),
+        })
+    }
+
+    /// Reads a schema from a JSON file, or returns the built-in one for `builtin`.
+    pub fn load(path: &Path) -> Result<Schema, Error> {
+        if path == Path::new("builtin") {
+            return Ok(Schema::builtin());
+        }
+
+        let mut input = String::new();
+        std::io::Read::read_to_string(&mut BufReader::new(File::open(path)?), &mut input)?;
+        Schema::from_json(&input)
+    }
+
+    /// Returns the properties of the config that aren't in the schema, see the module
+    /// documentation for which classes are checked.
+    pub fn check(&self, config: &Config) -> Vec<UnknownProperty> {
+        let mut unknown: Vec<UnknownProperty> = Vec::new();
+
+        for (root_name, root_entry) in config.root().entries().unwrap_or_default() {
+            let (Some(classes), ConfigEntry::ClassEntry(root)) = (self.roots.get(root_name), root_entry) else {
+                continue;
+            };
+
+            let scopes = [config.root(), root];
+            for (class_name, entry) in root.entries().unwrap_or_default() {
+                let ConfigEntry::ClassEntry(class) = entry else { continue };
+                let Some(entries) = class.entries() else { continue };
+
+                // Known properties with the spelling of the schema or the class they are defined in
+                let mut known: HashMap<ConfigName, &str> = HashMap::new();
+                let mut listed = false;
+                let ancestors = ancestor_names(class, &scopes).unwrap_or_default();
+                for name in std::iter::once(class_name.as_str()).chain(ancestors) {
+                    if let Some(properties) = classes.get(&ConfigName::new(name)) {
+                        known.extend(properties.iter().map(|p| (ConfigName::new(p), p.as_str())));
+                        listed = true;
+                    }
+                }
+                known.extend(inherited_entries(class, &scopes, 0).entries.into_iter().map(|(n, _, _)| (n.clone(), n.as_str())));
+                if let Some(properties) = classes.get(&ConfigName::new("*")) {
+                    known.extend(properties.iter().map(|p| (ConfigName::new(p), p.as_str())));
+                    listed = true;
+                }
+                if !listed {
+                    continue;
+                }
+
+                for (index, (name, value)) in entries.iter().enumerate() {
+                    if matches!(value, ConfigEntry::ClassEntry(_)) || known.contains_key(name) {
+                        continue;
+                    }
+
+                    let suggestion = known.values()
+                        .map(|k| (edit_distance(name.as_str(), k), *k))
+                        .filter(|(distance, _)| *distance <= 2.max(name.len() / 4))
+                        .min()
+                        .map(|(_, k)| k.to_string());
+                    unknown.push(UnknownProperty {
+                        class: format!("{}/{}", root_name, class_name),
+                        property: name.to_string(),
+                        suggestion,
+                        position: class.entry_position(index),
+                    });
+                }
+            }
+        }
+
+        unknown
+    }
+
+    /// Checks the config like `check`, adding a warning for each unknown property.
+    pub(crate) fn lint(&self, config: &Config, warnings: &mut Vec<(Option<usize>, String, Option<&'static str>)>) {
+        for property in self.check(config) {
+            let message = match &property.suggestion {
+                Some(suggestion) => format!("Unknown property \"{}\" in {}, did you mean \"{}\"?", property.property, property.class, suggestion),
+                None => format!("Unknown property \"{}\" in {}.", property.property, property.class),
+            };
+            warnings.push((property.position, message, Some("unknown-property")));
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Verification server for tools that can't run or link armake2 themselves
+//!
+//! The server listens on a TCP address like `127.0.0.1:9000` or, on Unix, on a socket given as
+//! `unix:/path/to/socket`, and answers every request line with one response line, both JSON
+//! objects:
+//!
+//! ```text
+//! {"id": 1, "op": "verify-signature", "path": "incoming/my_addon.pbo"}
+//! {"id":1,"ok":true,"result":{"status":"ok","detail":"my_key"}}
+//! {"op": "inspect", "path": "../secret.pbo"}
+//! {"ok":false,"error":"Path \"../secret.pbo\" has to be relative to the root folder without \"..\"."}
+//! ```
+//!
+//! The operations are:
+//!
+//! - `verify-signature`: checks the signatures next to the PBO against the keys like
+//!   `check_signatures` does. `status` is `ok`, `failed` or `no-signature`, `detail` is the name
+//!   of the accepting key or why the PBO isn't accepted.
+//! - `verify-checksum`: checks the SHA-1 checksum at the end of the PBO. `valid` is whether it
+//!   matches, `checksum` and `computed` are the stored and the actual one in hex.
+//! - `inspect`: returns the `header_extensions` of the PBO, its `entries` with `name` and `size`
+//!   and the number of bytes of `trailing_data` after the checksum.
+//!
+//! The `id` is optional and returned as it was sent. Paths are relative to the root folder and
+//! can't leave it, neither with `..` nor through symbolic links. Keys are read once on start.
+//!
+//! A socket file left behind by a server that didn't shut down is replaced on start.
+//!
+//! Every worker serves one connection at a time, so a connection that stays idle for longer
+//! than the idle timeout, 60 seconds by default, or doesn't read its responses is closed to free
+//! its worker. Clients that keep a connection open between requests have to expect that.
+//!
+//! There is no TLS and no authentication besides access to the socket, so it is meant to be
+//! used by other programs on the same host.
+
+use std::collections::{HashMap};
+use std::fs::{File, read};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration};
+
+use openssl::hash::{MessageDigest, hash};
+use serde::{Deserialize};
+use serde_json::{Map, Value, json};
+
+use crate::error::*;
+use crate::parallel;
+use crate::pbo::{PBO, read_layout};
+use crate::sign::{BIPublicKey, SignatureStatus, check_pbo, read_keys};
+
+/// Longest request line accepted, the connection is closed after a longer one
+pub const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Bytes of a too long request read before closing the connection, see `handle_connection`
+const DRAIN_LIMIT: u64 = 1024 * 1024;
+
+/// Time a connection may be idle before it is closed, so idle clients don't block a worker
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    op: String,
+    path: String,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Listener {
+    /// Accepts a connection whose reads and writes fail after waiting for `timeout`, so neither a
+    /// client that sends nothing nor one that doesn't read keeps a worker forever.
+    fn accept(&self, timeout: Duration) -> Result<Connection, Error> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Connection::Tcp(stream))
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Connection::Unix(stream))
+            }
+        }
+    }
+}
+
+/// Binds a Unix socket, replacing a socket file left behind by a server that is gone. A socket
+/// another server still answers on is kept and the bind fails.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<UnixListener, Error> {
+    use std::fs::{remove_file};
+    use std::os::unix::fs::{FileTypeExt};
+
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            let is_socket = path.symlink_metadata().map(|m| m.file_type().is_socket()).unwrap_or(false);
+            if !is_socket || UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            remove_file(path)?;
+            UnixListener::bind(path)
+        },
+        result => result
+    }
+}
+
+/// What every worker needs to answer requests
+struct State {
+    keys: HashMap<String, BIPublicKey>,
+    /// Canonical path of the root folder
+    root: PathBuf,
+}
+
+/// Server answering verification requests, see the module documentation for the protocol
+pub struct VerifyServer {
+    listener: Listener,
+    state: State,
+    idle_timeout: Duration,
+}
+
+impl VerifyServer {
+    /// Reads the keys and starts listening on the address, either `host:port` or, on Unix,
+    /// `unix:<path>`. A port of 0 picks a free one, see `local_addr`.
+    pub fn bind(listen_addr: &str, keys_dir: &Path, root: &Path) -> Result<VerifyServer, Error> {
+        let root = root.canonicalize().prepend_error(format!("Failed to open root folder {}:", root.display()))?;
+        let keys = read_keys(keys_dir)?;
+
+        let listener = match listen_addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Listener::Unix(bind_unix(Path::new(path)).prepend_error(format!("Failed to listen on {}:", listen_addr))?, PathBuf::from(path)),
+            #[cfg(not(unix))]
+            Some(_) => return Err(coded_error!("E0817", "Unix sockets are only supported on Unix.")),
+            None => Listener::Tcp(TcpListener::bind(listen_addr).prepend_error(format!("Failed to listen on {}:", listen_addr))?)
+        };
+
+        Ok(VerifyServer {
+            listener,
+            state: State { keys, root },
+            idle_timeout: IDLE_TIMEOUT,
+        })
+    }
+
+    /// Returns the address the server listens on, in the format `bind` takes.
+    pub fn local_addr(&self) -> Result<String, Error> {
+        match &self.listener {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(format!("unix:{}", path.display()))
+        }
+    }
+
+    /// Returns the number of keys requests are verified against.
+    pub fn key_count(&self) -> usize {
+        self.state.keys.len()
+    }
+
+    /// Sets how long a connection may wait for a request or for the client to read a response
+    /// before it is closed, `IDLE_TIMEOUT` by default. Fails for a timeout of zero.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        if timeout.is_zero() {
+            return Err(coded_error!("E0901", "Idle timeout has to be longer than zero."));
+        }
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    /// Answers requests until the process ends, on as many worker threads as the thread pool
+    /// has. Each worker serves one connection at a time, further connections wait until one is
+    /// free. As idle connections are closed after the idle timeout, a worker is free again at
+    /// the latest once its client has been idle for that long.
+    pub fn serve(self) -> Result<(), Error> {
+        let workers = parallel::thread_count().max(1);
+        let (sender, receiver) = mpsc::sync_channel::<Connection>(workers);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let state = Arc::new(self.state);
+
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            let state = state.clone();
+            thread::spawn(move || loop {
+                let connection = match receiver.lock().unwrap().recv() {
+                    Ok(connection) => connection,
+                    Err(_) => break
+                };
+                if let Err(e) = handle_connection(connection, &state) {
+                    warning(format!("Connection failed: {}", e), Some("serve-connection-failed"), (None, None));
+                }
+            });
+        }
+
+        loop {
+            match self.listener.accept(self.idle_timeout) {
+                Ok(connection) => sender.send(connection).map_err(|_| coded_error!("E0823", "All workers stopped."))?,
+                Err(e) => warning(format!("Failed to accept connection: {}", e), Some("serve-connection-failed"), (None, None))
+            }
+        }
+    }
+}
+
+/// Answers the requests of one connection until the client closes it.
+fn handle_connection<S: Read + Write>(stream: S, state: &State) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut line: Vec<u8> = Vec::new();
+        match reader.by_ref().take(MAX_REQUEST_SIZE as u64 + 1).read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {},
+            // Closing idle connections is expected, not a failure
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(()),
+            Err(e) => return Err(e)
+        }
+
+        // The rest of the line is still unread, so the connection can't continue
+        let too_long = line.len() > MAX_REQUEST_SIZE;
+        let response = if too_long {
+            failure(None, coded_error!("E0824", "Request exceeds {} bytes.", MAX_REQUEST_SIZE))
+        } else if line.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        } else {
+            respond(&line, state)
+        };
+
+        let mut output = serde_json::to_vec(&response).map_err(Error::from)?;
+        output.push(b'\n');
+        reader.get_mut().write_all(&output)?;
+        reader.get_mut().flush()?;
+
+        if too_long {
+            // Closing with unread data resets the connection, which can drop the response before
+            // the client reads it
+            reader.take(DRAIN_LIMIT).skip_until(b'\n')?;
+            return Ok(());
+        }
+    }
+}
+
+fn failure(id: Option<Value>, error: Error) -> Value {
+    let mut response = Map::new();
+    if let Some(id) = id {
+        response.insert("id".to_string(), id);
+    }
+    response.insert("ok".to_string(), Value::Bool(false));
+    response.insert("error".to_string(), Value::String(error.to_string()));
+    Value::Object(response)
+}
+
+/// Returns the response to one request line.
+fn respond(line: &[u8], state: &State) -> Value {
+    let request: Request = match serde_json::from_slice(line) {
+        Ok(request) => request,
+        Err(e) => return failure(None, coded_error!("E0824", "Invalid request: {}", e))
+    };
+
+    let result = match request.op.as_str() {
+        "verify-signature" => resolve(&state.root, &request.path).map(|path| verify_signature(&path, &state.keys)),
+        "verify-checksum" => resolve(&state.root, &request.path).and_then(|path| verify_checksum(&path)),
+        "inspect" => resolve(&state.root, &request.path).and_then(|path| inspect(&path)),
+        op => Err(coded_error!("E0824", "Unknown operation \"{}\", expected verify-signature, verify-checksum or inspect.", op))
+    };
+
+    match result {
+        Ok(result) => {
+            let mut response = Map::new();
+            if let Some(id) = request.id {
+                response.insert("id".to_string(), id);
+            }
+            response.insert("ok".to_string(), Value::Bool(true));
+            response.insert("result".to_string(), result);
+            Value::Object(response)
+        },
+        Err(e) => failure(request.id, e)
+    }
+}
+
+/// Returns the file at a path relative to the root, failing for paths outside of it.
+/// Backslashes are accepted like in engine paths.
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, Error> {
+    let relative: PathBuf = path.split(['\\', '/']).collect();
+    if path.is_empty() || path.contains(['\0', ':']) || path.starts_with(['\\', '/']) || !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(coded_error!("E0313", "Path \"{}\" has to be relative to the root folder without \"..\".", path));
+    }
+
+    let resolved = root.join(&relative).canonicalize().prepend_error(format!("Failed to open \"{}\":", path))?;
+    if !resolved.starts_with(root) {
+        return Err(coded_error!("E0313", "Path \"{}\" leads outside of the root folder.", path));
+    }
+    if !resolved.is_file() {
+        return Err(coded_error!("E0824", "Path \"{}\" is not a file.", path));
+    }
+
+    Ok(resolved)
+}
+
+fn verify_signature(path: &Path, keys: &HashMap<String, BIPublicKey>) -> Value {
+    let (status, detail) = check_pbo(path, keys, None);
+    let status = match status {
+        SignatureStatus::Ok => "ok",
+        SignatureStatus::Failed => "failed",
+        SignatureStatus::NoSignature => "no-signature",
+    };
+    json!({ "status": status, "detail": detail })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn verify_checksum(path: &Path) -> Result<Value, Error> {
+    let bytes = read(path)?;
+    let layout = read_layout(&bytes).prepend_error("Failed to read PBO:")?;
+    let stored = bytes.get((layout.data_end + 1)..(layout.data_end + 21))
+        .ok_or_else(|| coded_error!("E0303", "Checksum after the data of the last entry is missing or truncated."))?;
+    let computed = hash(MessageDigest::sha1(), &bytes[..layout.data_end])?;
+
+    Ok(json!({ "valid": *stored == *computed, "checksum": to_hex(stored), "computed": to_hex(&computed) }))
+}
+
+fn inspect(path: &Path) -> Result<Value, Error> {
+    let pbo = PBO::read(&mut BufReader::new(File::open(path)?)).prepend_error("Failed to read PBO:")?;
+
+    let header_extensions: Map<String, Value> = pbo.header_extensions.iter()
+        .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+        .collect();
+    let entries: Vec<Value> = pbo.files.iter()
+        .map(|(name, data)| json!({ "name": name, "size": data.get_ref().len() }))
+        .collect();
+
+    Ok(json!({ "header_extensions": header_extensions, "entries": entries, "trailing_data": pbo.trailing_data }))
+}
+
+/// Verifies PBOs under `root` against the keys in `keys_dir` for clients connecting to the
+/// address, see the module documentation. Runs until the process ends.
+pub fn cmd_serve_verify(listen_addr: &str, keys_dir: &Path, root: &Path, idle_timeout: Duration) -> Result<(), Error> {
+    let mut server = VerifyServer::bind(listen_addr, keys_dir, root)?;
+    server.set_idle_timeout(idle_timeout)?;
+    eprintln!("Listening on {} with {} keys.", server.local_addr()?, server.key_count());
+    server.serve()
+}
@@ -1,16 +1,24 @@
 //! Functions for creating and working with BI keys and signatures
 
-use std::fs::{File};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, read_dir};
 use std::io::{Read, Write, Error, Cursor};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use openssl::bn::{BigNum, BigNumContext};
 use openssl::hash::{Hasher, MessageDigest, DigestBytes};
+use openssl::pkey::{Private, Public};
 use openssl::rsa::{Rsa};
 
+use crate::detect::{FileKind};
+use crate::error::*;
 use crate::io::*;
+use crate::parallel;
 use crate::pbo::*;
+use crate::progress::{Progress};
+use crate::verifycache::{VerifyCache};
 
 /// BI private key (.biprivatekey)
 pub struct BIPrivateKey {
@@ -35,7 +43,10 @@ pub struct BIPublicKey {
 }
 
 /// BI signature version
-#[derive(Copy,Clone)]
+///
+/// The version decides which hashes are signed, so a signature can only be converted to another
+/// version by signing the PBO again with the private key, see `cmd_resign`.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
 pub enum BISignVersion {
     /// Version 2
     V2,
@@ -115,14 +126,51 @@ fn filehash(pbo: &PBO, version: BISignVersion) -> DigestBytes {
     h.finish().unwrap()
 }
 
-fn generate_hashes(pbo: &PBO, version: BISignVersion, length: u32) -> (BigNum, BigNum, BigNum) {
-    let checksum = pbo.checksum.clone().unwrap();
-    let hash1 = checksum.as_slice();
+/// Digests of the parts of a PBO its signatures are made of, which is all that's needed to verify
+/// them without the PBO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PboHashes {
+    /// Checksum at the end of the PBO
+    pub checksum: Vec<u8>,
+    /// Hash of the lowercase names of all entries with data
+    pub names: Vec<u8>,
+    /// Hash of the entries v2 signatures cover
+    pub files_v2: Vec<u8>,
+    /// Hash of the entries v3 signatures cover
+    pub files_v3: Vec<u8>,
+    pub prefix: Option<String>,
+    /// Bytes after the checksum, which signatures don't cover
+    pub trailing_data: usize,
+}
+
+impl PboHashes {
+    /// Hashes a PBO that was read, which has a checksum.
+    pub fn from_pbo(pbo: &PBO) -> PboHashes {
+        PboHashes {
+            checksum: pbo.checksum.clone().unwrap(),
+            names: namehash(pbo).to_vec(),
+            files_v2: filehash(pbo, BISignVersion::V2).to_vec(),
+            files_v3: filehash(pbo, BISignVersion::V3).to_vec(),
+            prefix: pbo.header_extensions.get("prefix").cloned(),
+            trailing_data: pbo.trailing_data,
+        }
+    }
+
+    fn files(&self, version: BISignVersion) -> &[u8] {
+        match version {
+            BISignVersion::V2 => &self.files_v2,
+            BISignVersion::V3 => &self.files_v3,
+        }
+    }
+}
+
+fn generate_hashes(hashes: &PboHashes, version: BISignVersion, length: u32) -> (BigNum, BigNum, BigNum) {
+    let hash1 = hashes.checksum.as_slice();
 
     let mut h = Hasher::new(MessageDigest::sha1()).unwrap();
     h.update(hash1).unwrap();
-    h.update(&*namehash(pbo)).unwrap();
-    if let Some(prefix) = pbo.header_extensions.get("prefix") {
+    h.update(&hashes.names).unwrap();
+    if let Some(prefix) = &hashes.prefix {
         h.update(prefix.as_bytes()).unwrap();
         if !prefix.ends_with('\\') {
             h.update(b"\\").unwrap();
@@ -131,9 +179,9 @@ fn generate_hashes(pbo: &PBO, version: BISignVersion, length: u32) -> (BigNum, B
     let hash2 = &*h.finish().unwrap();
 
     h = Hasher::new(MessageDigest::sha1()).unwrap();
-    h.update(&*filehash(pbo, version)).unwrap();
-    h.update(&*namehash(pbo)).unwrap();
-    if let Some(prefix) = pbo.header_extensions.get("prefix") {
+    h.update(hashes.files(version)).unwrap();
+    h.update(&hashes.names).unwrap();
+    if let Some(prefix) = &hashes.prefix {
         h.update(prefix.as_bytes()).unwrap();
         if !prefix.ends_with('\\') {
             h.update(b"\\").unwrap();
@@ -188,7 +236,9 @@ impl BIPrivateKey {
         let length = input.read_u32::<LittleEndian>()?;
         let exponent = input.read_u32::<LittleEndian>()?;
 
-        assert_eq!(temp, length / 16 * 9 + 20);
+        if temp != length / 16 * 9 + 20 {
+            return Err(coded_error!("E0801", "Invalid private key length."));
+        }
 
         let mut buffer = vec![0; (length / 8) as usize];
         input.read_exact(&mut buffer)?;
@@ -269,9 +319,26 @@ impl BIPrivateKey {
         }
     }
 
+    /// Returns the name of the key's authority.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Converts the key for use with OpenSSL, to sign other data than PBOs with the same key.
+    pub(crate) fn to_rsa(&self) -> Result<Rsa<Private>, Error> {
+        Rsa::from_private_components(self.n.to_owned()?, BigNum::from_u32(self.exponent)?, self.d.to_owned()?, self.p.to_owned()?,
+            self.q.to_owned()?, self.dmp1.to_owned()?, self.dmq1.to_owned()?, self.iqmp.to_owned()?)
+            .map_err(|e| coded_error!("E0801", "Invalid private key: {}", e))
+    }
+
     /// Signs the given PBO with this private key.
     pub fn sign(&self, pbo: &PBO, version: BISignVersion) -> BISign {
-        let (hash1, hash2, hash3) = generate_hashes(pbo, version, self.length);
+        self.sign_hashes(&PboHashes::from_pbo(pbo), version)
+    }
+
+    /// Signs a PBO by its hashes, which lets several keys sign it without hashing it again.
+    pub fn sign_hashes(&self, hashes: &PboHashes, version: BISignVersion) -> BISign {
+        let (hash1, hash2, hash3) = generate_hashes(hashes, version, self.length);
 
         let mut ctx = BigNumContext::new().unwrap();
 
@@ -324,7 +391,9 @@ impl BIPublicKey {
         let length = input.read_u32::<LittleEndian>()?;
         let exponent = input.read_u32::<LittleEndian>()?;
 
-        assert_eq!(temp, length / 8 + 20);
+        if temp != length / 8 + 20 {
+            return Err(coded_error!("E0801", "Invalid key length."));
+        }
 
         let mut buffer = vec![0; (length / 8) as usize];
         input.read_exact(&mut buffer)?;
@@ -339,10 +408,27 @@ impl BIPublicKey {
         })
     }
 
+    /// Returns the name of the key's authority.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Converts the key for use with OpenSSL, to verify other data than PBOs with the same key.
+    pub(crate) fn to_rsa(&self) -> Result<Rsa<Public>, Error> {
+        Rsa::from_public_components(self.n.to_owned()?, BigNum::from_u32(self.exponent)?)
+            .map_err(|e| coded_error!("E0801", "Invalid public key: {}", e))
+    }
+
     // @todo: example
     /// Verifies a signature against this public key.
     pub fn verify(&self, pbo: &PBO, signature: &BISign) -> Result<(), Error> {
-        let (real_hash1, real_hash2, real_hash3) = generate_hashes(pbo, signature.version, self.length);
+        self.verify_hashes(&PboHashes::from_pbo(pbo), signature)
+    }
+
+    /// Verifies a signature against this public key like `verify`, given only the hashes of the
+    /// PBO.
+    pub fn verify_hashes(&self, hashes: &PboHashes, signature: &BISign) -> Result<(), Error> {
+        let (real_hash1, real_hash2, real_hash3) = generate_hashes(hashes, signature.version, self.length);
 
         let mut ctx = BigNumContext::new().unwrap();
 
@@ -357,17 +443,17 @@ impl BIPublicKey {
 
         if real_hash1 != signed_hash1 {
             let (s, r) = display_hashes(signed_hash1, real_hash1);
-            return Err(error!("Hash 1 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
+            return Err(coded_error!("E0802", "Hash 1 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
         }
 
         if real_hash2 != signed_hash2 {
             let (s, r) = display_hashes(signed_hash2, real_hash2);
-            return Err(error!("Hash 2 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
+            return Err(coded_error!("E0802", "Hash 2 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
         }
 
         if real_hash3 != signed_hash3 {
             let (s, r) = display_hashes(signed_hash3, real_hash3);
-            return Err(error!("Hash 3 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
+            return Err(coded_error!("E0802", "Hash 3 doesn't match\nSigned hash: {}\nReal hash:   {}", s, r));
         }
 
         Ok(())
@@ -407,7 +493,9 @@ impl BISign {
         let length = input.read_u32::<LittleEndian>()?;
         let exponent = input.read_u32::<LittleEndian>()?;
 
-        assert_eq!(temp, length / 8 + 20);
+        if temp != length / 8 + 20 {
+            return Err(coded_error!("E0803", "Invalid signature key length."));
+        }
 
         let mut buffer = vec![0; (length / 8) as usize];
         input.read_exact(&mut buffer)?;
@@ -425,7 +513,7 @@ impl BISign {
             2 => BISignVersion::V2,
             3 => BISignVersion::V3,
             _ => {
-                return Err(error!("Unknown BISign version."));
+                return Err(coded_error!("E0803", "Unknown BISign version."));
             }
         };
 
@@ -455,6 +543,26 @@ impl BISign {
         })
     }
 
+    /// Returns the name of the authority that signed.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the version of the signature.
+    pub fn version(&self) -> BISignVersion {
+        self.version
+    }
+
+    /// Returns the length of the signing key in bits.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns whether the signature was made with the private key.
+    pub fn is_signed_by(&self, key: &BIPrivateKey) -> bool {
+        self.n == key.n && self.exponent == key.exponent
+    }
+
     /// Writes the signature to the given output.
     pub fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
         output.write_cstring(&self.name)?;
@@ -475,6 +583,71 @@ impl BISign {
     }
 }
 
+/// Error for a signature given where another kind of file was expected, pointing to `resign`
+/// since that is usually an attempt to convert the signature to another version.
+fn signature_instead_of(path: &Path, expected: &str) -> Error {
+    coded_error!("E0804", "{} is a signature, not {}. Signature version conversion requires the private key, sign the PBO again with \"armake2 resign <privatekey> <pbo>\".",
+        path.display(), expected)
+}
+
+/// Reads a private key, failing clearly if the file is a public key or a signature.
+fn read_private_key(path: &Path) -> Result<BIPrivateKey, Error> {
+    match FileKind::detect_file(path).prepend_error(format!("Failed to open private key {}:", path.display()))? {
+        FileKind::Signature => Err(signature_instead_of(path, "a private key")),
+        FileKind::PublicKey => Err(coded_error!("E0804", "{} is a public key, signing requires the private key (.biprivatekey).", path.display())),
+        _ => BIPrivateKey::read(&mut File::open(path)?).prepend_error(format!("Failed to read private key {}:", path.display()))
+    }
+}
+
+/// Reads a public key, failing clearly if the file is a private key or a signature.
+fn read_public_key(path: &Path) -> Result<BIPublicKey, Error> {
+    match FileKind::detect_file(path).prepend_error(format!("Failed to open public key {}:", path.display()))? {
+        FileKind::Signature => Err(signature_instead_of(path, "a public key")),
+        FileKind::PrivateKey => Err(coded_error!("E0804", "{} is a private key, verifying requires its public key (.bikey).", path.display())),
+        _ => BIPublicKey::read(&mut File::open(path)?).prepend_error(format!("Failed to read public key {}:", path.display()))
+    }
+}
+
+/// Reads a PBO to sign, failing clearly if the file is a signature or encrypted.
+fn read_pbo_to_sign(path: &Path) -> Result<PBO, Error> {
+    if FileKind::detect_file(path).prepend_error("Failed to open PBO:")? == FileKind::Signature {
+        return Err(signature_instead_of(path, "a PBO"));
+    }
+    crate::detect::check_pbo_file(path)?;
+    PBO::read(&mut File::open(path).prepend_error("Failed to open PBO:")?).prepend_error("Failed to read PBO:")
+}
+
+/// Authority, version and key length of a signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureInfo {
+    pub authority: String,
+    pub version: BISignVersion,
+    /// Length of the signing key in bits
+    pub length: u32,
+}
+
+impl SignatureInfo {
+    /// Reads the information from a signature file, failing clearly for keys.
+    pub fn read(path: &Path) -> Result<SignatureInfo, Error> {
+        match FileKind::detect_file(path).prepend_error("Failed to open signature:")? {
+            FileKind::PublicKey | FileKind::PrivateKey => return Err(coded_error!("E0804", "{} is a key, not a signature.", path.display())),
+            _ => {}
+        }
+        let sig = BISign::read(&mut File::open(path)?).prepend_error("Failed to read signature:")?;
+        Ok(SignatureInfo { authority: sig.name, version: sig.version, length: sig.length })
+    }
+}
+
+/// Prints the authority, version and key length of a signature.
+pub fn cmd_signature_info<O: Write>(path: &Path, output: &mut O) -> Result<(), Error> {
+    let info = SignatureInfo::read(path)?;
+    writeln!(output, "Authority:  {}", info.authority)?;
+    let version: u32 = info.version.into();
+    writeln!(output, "Version:    {}", version)?;
+    writeln!(output, "Key length: {} bits", info.length)?;
+    Ok(())
+}
+
 /// Generates a key pair with the given name.
 ///
 /// The output paths are created by appending extensions to the keyname.
@@ -488,7 +661,7 @@ pub fn cmd_keygen(keyname: PathBuf, force: bool) -> Result<(), Error> {
 
     // Check if private key exists
     if private_key_path.exists() && !force {
-        return Err(error!("Private key '{}' already exists. Use -f/--force to overwrite.", private_key_path.display()));
+        return Err(coded_error!("E0805", "Private key '{}' already exists. Use -f/--force to overwrite.", private_key_path.display()));
     }
 
     private_key.write(&mut File::create(&private_key_path).unwrap()).expect("Failed to write private key");
@@ -500,7 +673,7 @@ pub fn cmd_keygen(keyname: PathBuf, force: bool) -> Result<(), Error> {
     if public_key_path.exists() && !force {
         // If we already wrote the private key, we should remove it to keep things consistent
         std::fs::remove_file(&private_key_path).ok();
-        return Err(error!("Public key '{}' already exists. Use -f/--force to overwrite.", public_key_path.display()));
+        return Err(coded_error!("E0805", "Public key '{}' already exists. Use -f/--force to overwrite.", public_key_path.display()));
     }
 
     public_key.write(&mut File::create(public_key_path).unwrap()).expect("Failed to write public key");
@@ -512,8 +685,8 @@ pub fn cmd_keygen(keyname: PathBuf, force: bool) -> Result<(), Error> {
 ///
 /// If the signature path is not given it is inferred from the PBO path.
 pub fn cmd_sign(privatekey_path: PathBuf, pbo_path: PathBuf, signature_path: Option<PathBuf>, version: BISignVersion) -> Result<(), Error> {
-    let privatekey = BIPrivateKey::read(&mut File::open(&privatekey_path).expect("Failed to open private key")).expect("Failed to read private key");
-    let pbo = PBO::read(&mut File::open(&pbo_path).expect("Failed to open PBO")).expect("Failed to read PBO");
+    let privatekey = read_private_key(&privatekey_path)?;
+    let pbo = read_pbo_to_sign(&pbo_path)?;
 
     let sig_path = match signature_path {
         Some(path) => path,
@@ -530,12 +703,128 @@ pub fn cmd_sign(privatekey_path: PathBuf, pbo_path: PathBuf, signature_path: Opt
     Ok(())
 }
 
+/// Signs a PBO with several private keys, hashing it only once. Each key writes
+/// `<pbo>.<authority>.bisign` next to the PBO, the authority being the name of the key unless it
+/// is given. Existing signatures of the same authority are overwritten like with `cmd_sign`.
+///
+/// Returns the paths of the written signatures. Nothing is written if two keys have the same
+/// authority.
+pub fn cmd_sign_with_keys(keys: &[(PathBuf, Option<String>)], pbo_path: &Path, version: BISignVersion) -> Result<Vec<PathBuf>, Error> {
+    let mut privatekeys: Vec<(BIPrivateKey, String)> = Vec::with_capacity(keys.len());
+    let mut authorities: HashSet<String> = HashSet::new();
+    for (path, authority) in keys {
+        let privatekey = read_private_key(path)?;
+        let authority = authority.clone().unwrap_or_else(|| privatekey.name.clone());
+        if authority.is_empty() || authority.contains(['/', '\\']) {
+            return Err(coded_error!("E0806", "Invalid authority \"{}\", it is used as part of the signature file name.", authority));
+        }
+        if !authorities.insert(authority.to_lowercase()) {
+            return Err(coded_error!("E0806", "More than one key signs with the authority \"{}\".", authority));
+        }
+        privatekeys.push((privatekey, authority));
+    }
+
+    let pbo = read_pbo_to_sign(pbo_path)?;
+    let hashes = PboHashes::from_pbo(&pbo);
+
+    let mut paths = Vec::with_capacity(privatekeys.len());
+    for (privatekey, authority) in privatekeys {
+        let mut path = pbo_path.to_path_buf();
+        path.set_extension(format!("pbo.{}.bisign", authority));
+
+        let sig = privatekey.sign_hashes(&hashes, version);
+        sig.write(&mut create_file(&path)?).prepend_error(format!("Failed to write signature {}:", path.display()))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Returns the PBO a signature named `<pbo name>.<authority>.bisign` belongs to.
+fn signed_pbo(signature_path: &Path) -> Result<PathBuf, Error> {
+    let name = signature_path.file_name().unwrap_or_default().to_string_lossy();
+    match name.to_lowercase().rfind(".pbo.") {
+        Some(end) => Ok(signature_path.with_file_name(&name[..end + 4])),
+        None => Err(coded_error!("E0807", "Failed to find the PBO of {}, signatures are named <pbo name>.<authority>.bisign.", signature_path.display()))
+    }
+}
+
+/// Signs the PBO again with the version for each of its signatures that the key made, writing
+/// them to the same paths. Returns those paths.
+fn resign_pbo(privatekey: &BIPrivateKey, pbo_path: &Path, version: BISignVersion) -> Result<Vec<PathBuf>, Error> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in find_signatures(pbo_path)? {
+        let sig = BISign::read(&mut File::open(&path)?).prepend_error(format!("Failed to read signature {}:", path.display()))?;
+        if sig.is_signed_by(privatekey) {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Ok(paths);
+    }
+
+    let pbo = read_pbo_to_sign(pbo_path).prepend_error(format!("Failed to sign {}:", pbo_path.display()))?;
+    let hashes = PboHashes::from_pbo(&pbo);
+    for path in &paths {
+        let sig = privatekey.sign_hashes(&hashes, version);
+        sig.write(&mut create_file(path)?).prepend_error(format!("Failed to write signature {}:", path.display()))?;
+    }
+    Ok(paths)
+}
+
+/// Converts signatures to another version by signing their PBOs again with the private key that
+/// made them, which is the only way to do it, since each version signs different hashes.
+///
+/// Each target is a PBO, a signature or a folder, whose PBOs and subfolders are converted in
+/// batch. Every signature of a PBO made with the key is overwritten, keeping its name, and
+/// signatures of other keys are left alone. Returns the paths of the written signatures. Fails
+/// for a PBO or signature given directly that the key has no signature of.
+pub fn cmd_resign(privatekey_path: &Path, targets: &[PathBuf], version: BISignVersion) -> Result<Vec<PathBuf>, Error> {
+    let privatekey = read_private_key(privatekey_path)?;
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for target in targets {
+        if target.is_dir() {
+            let mut pbos: Vec<PathBuf> = Vec::new();
+            find_files(target, "pbo", true, &mut pbos).prepend_error(format!("Failed to read {}:", target.display()))?;
+            pbos.sort();
+            for pbo in pbos {
+                paths.extend(resign_pbo(&privatekey, &pbo, version)?);
+            }
+            continue;
+        }
+
+        let pbo = match FileKind::detect_file(target).prepend_error(format!("Failed to open {}:", target.display()))? {
+            FileKind::Signature => {
+                let sig = BISign::read(&mut File::open(target)?).prepend_error(format!("Failed to read signature {}:", target.display()))?;
+                if !sig.is_signed_by(&privatekey) {
+                    return Err(coded_error!("E0808", "{} wasn't made with the key of \"{}\", only the private key that made a signature can convert it.",
+                        target.display(), privatekey.name));
+                }
+                signed_pbo(target)?
+            },
+            FileKind::PublicKey | FileKind::PrivateKey => return Err(coded_error!("E0804", "{} is a key, resign takes PBOs, signatures and folders.", target.display())),
+            _ => target.clone()
+        };
+
+        let written = resign_pbo(&privatekey, &pbo, version)?;
+        if written.is_empty() {
+            return Err(coded_error!("E0808", "{} has no signature made with the key of \"{}\". Use \"armake2 sign\" to sign it for the first time.",
+                pbo.display(), privatekey.name));
+        }
+        paths.extend(written);
+    }
+
+    Ok(paths)
+}
+
 /// Verifies a signature for a pbo against a given public key.
 ///
-/// If the signature path is not given it is inferred from the PBO path.
-pub fn cmd_verify(publickey_path: PathBuf, pbo_path: PathBuf, signature_path: Option<PathBuf>) -> Result<(), Error> {
-    let publickey = BIPublicKey::read(&mut File::open(&publickey_path).expect("Failed to open public key")).expect("Failed to read public key");
-    let pbo = PBO::read(&mut File::open(&pbo_path).expect("Failed to open PBO")).expect("Failed to read PBO");
+/// If the signature path is not given it is inferred from the PBO path. With a cache, the hashes
+/// of the PBO are taken from it, see `VerifyCache`.
+pub fn cmd_verify(publickey_path: PathBuf, pbo_path: PathBuf, signature_path: Option<PathBuf>, cache: Option<&VerifyCache>) -> Result<(), Error> {
+    crate::detect::check_pbo_file(&pbo_path)?;
+    let publickey = read_public_key(&publickey_path)?;
 
     let sig_path = match signature_path {
         Some(path) => path,
@@ -546,7 +835,223 @@ pub fn cmd_verify(publickey_path: PathBuf, pbo_path: PathBuf, signature_path: Op
         }
     };
 
-    let sig = BISign::read(&mut File::open(&sig_path).expect("Failed to open signature")).expect("Failed to read signature");
+    let sig = BISign::read(&mut File::open(&sig_path).prepend_error(format!("Failed to open signature {}:", sig_path.display()))?)
+        .prepend_error(format!("Failed to read signature {}:", sig_path.display()))?;
+
+    let hashes = match cache {
+        Some(cache) => {
+            let hashes = cache.hashes(&pbo_path).prepend_error("Failed to read PBO:")?;
+            cache.save()?;
+            hashes
+        },
+        None => PboHashes::from_pbo(&PBO::read(&mut File::open(&pbo_path).expect("Failed to open PBO")).expect("Failed to read PBO"))
+    };
+    if hashes.trailing_data > 0 {
+        warning(format!("{} has {} bytes of data after its checksum, which is ignored.", pbo_path.display(), hashes.trailing_data),
+            Some("trailing-data"), (None, None));
+    }
+
+    publickey.verify_hashes(&hashes, &sig)
+}
+
+/// Result of checking the signatures of a single PBO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// At least one signature validates against a known key
+    Ok,
+    /// There are signatures from known authorities, but none of them validate
+    Failed,
+    /// There is no signature from any known authority
+    NoSignature,
+}
+
+/// Signature check result for a single PBO
+#[derive(Debug)]
+pub struct SignatureCheck {
+    /// Path to the PBO
+    pub pbo: PathBuf,
+    /// Whether the PBO would be accepted
+    pub status: SignatureStatus,
+    /// Name of the accepting key or the reason the PBO isn't accepted
+    pub detail: String,
+}
+
+pub(crate) fn find_files(directory: &Path, extension: &str, recursive: bool, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find_files(&path, extension, recursive, files)?;
+            }
+        } else if path.extension().map(|e| e.eq_ignore_ascii_case(extension)).unwrap_or(false) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns all signatures next to the PBO, named `<pbo name>.*.bisign`.
+fn find_signatures(pbo_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let prefix = format!("{}.", pbo_path.file_name().unwrap().to_string_lossy().to_lowercase());
+    let mut signatures: Vec<PathBuf> = Vec::new();
+    find_files(pbo_path.parent().unwrap_or(Path::new(".")), "bisign", false, &mut signatures)?;
+    signatures.retain(|s| s.file_name().unwrap().to_string_lossy().to_lowercase().starts_with(&prefix));
+    signatures.sort();
+
+    Ok(signatures)
+}
+
+/// Reads the keys in the folder by their lowercase authority names, warning about unreadable ones.
+pub(crate) fn read_keys(keys: &Path) -> Result<HashMap<String, BIPublicKey>, Error> {
+    let mut key_paths: Vec<PathBuf> = Vec::new();
+    find_files(keys, "bikey", false, &mut key_paths).prepend_error("Failed to read keys folder:")?;
+
+    let mut key_map: HashMap<String, BIPublicKey> = HashMap::new();
+    for path in key_paths {
+        match File::open(&path).and_then(|mut f| BIPublicKey::read(&mut f)) {
+            Ok(key) => { key_map.insert(key.name.to_lowercase(), key); },
+            Err(e) => warning(format!("Failed to read key \"{}\": {}", path.display(), e), Some("invalid-key"), (None, None)),
+        }
+    }
+
+    Ok(key_map)
+}
+
+pub(crate) fn check_pbo(pbo_path: &Path, keys: &HashMap<String, BIPublicKey>, cache: Option<&VerifyCache>) -> (SignatureStatus, String) {
+    let signatures = match find_signatures(pbo_path) {
+        Ok(signatures) => signatures,
+        Err(e) => return (SignatureStatus::Failed, format!("failed to list signatures: {}", e))
+    };
+
+    // Like the game, ignore signatures from authorities we don't have a key for
+    let mut known: Vec<(PathBuf, BISign, &BIPublicKey)> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for path in signatures {
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        match File::open(&path).and_then(|mut f| BISign::read(&mut f)) {
+            Ok(signature) => {
+                if let Some(key) = keys.get(&signature.name.to_lowercase()) {
+                    known.push((path, signature, key));
+                }
+            },
+            Err(e) => errors.push(format!("{}: {}", file_name, e.to_string().lines().next().unwrap_or(""))),
+        }
+    }
+
+    if known.is_empty() {
+        return if errors.is_empty() {
+            (SignatureStatus::NoSignature, "no signature from a known authority".to_string())
+        } else {
+            (SignatureStatus::Failed, errors.join("; "))
+        };
+    }
+
+    let hashes = crate::detect::check_pbo_file(pbo_path).and_then(|_| match cache {
+        Some(cache) => cache.hashes(pbo_path),
+        None => File::open(pbo_path).and_then(|mut f| PBO::read(&mut f)).map(|pbo| PboHashes::from_pbo(&pbo))
+    });
+    let hashes = match hashes {
+        Ok(hashes) => hashes,
+        Err(e) => return (SignatureStatus::Failed, format!("failed to read PBO: {}", e.to_string().lines().next().unwrap_or("")))
+    };
+
+    for (path, signature, key) in &known {
+        match key.verify_hashes(&hashes, signature) {
+            Ok(()) => return (SignatureStatus::Ok, key.name.clone()),
+            Err(e) => errors.push(format!("{}: {}", path.file_name().unwrap().to_string_lossy(), e.to_string().lines().next().unwrap_or("")))
+        }
+    }
+
+    (SignatureStatus::Failed, errors.join("; "))
+}
+
+/// Checks every PBO in the addons folder against the keys in the keys folder.
+///
+/// A PBO is accepted if at least one of the signatures next to it validates against a key with
+/// the same authority name. As in the game, signatures from authorities without a key are ignored
+/// instead of failing the PBO, so a PBO with only such signatures is reported as having no
+/// signature. PBOs are checked in parallel and the results are returned sorted by path.
+///
+/// Behavior that may differ from BI's DSCheckSignatures:
+///
+/// - The addons folder is searched recursively, so a whole mod preset can be checked at once.
+/// - Authority names are compared case-insensitively.
+/// - Both v2 and v3 signatures are accepted.
+/// - An unreadable signature fails the PBO unless another signature validates, since its
+///   authority can't be determined.
+pub fn check_signatures(addons: &Path, keys: &Path) -> Result<Vec<SignatureCheck>, Error> {
+    check_signatures_with_progress(addons, keys, None)
+}
+
+/// Checks signatures like `check_signatures`, calling `progress` after each PBO.
+///
+/// Since the PBOs are checked in parallel, the callback has to be `Sync`. Calls never overlap
+/// and `current` always increases.
+pub fn check_signatures_with_progress(addons: &Path, keys: &Path, progress: Option<&(dyn Fn(Progress) + Sync)>) -> Result<Vec<SignatureCheck>, Error> {
+    check_signatures_with_cache(addons, keys, progress, None)
+}
+
+/// Checks signatures like `check_signatures_with_progress`, taking the hashes of the PBOs from the
+/// cache, see `VerifyCache`. The cache isn't saved.
+pub fn check_signatures_with_cache(addons: &Path, keys: &Path, progress: Option<&(dyn Fn(Progress) + Sync)>, cache: Option<&VerifyCache>) -> Result<Vec<SignatureCheck>, Error> {
+    let key_map = read_keys(keys)?;
+
+    let mut pbos: Vec<PathBuf> = Vec::new();
+    find_files(addons, "pbo", true, &mut pbos).prepend_error("Failed to read addons folder:")?;
+    pbos.sort();
+
+    let total = pbos.len();
+    let finished = Mutex::new(0);
+
+    Ok(parallel::map_collect(pbos, |pbo| {
+        let (status, detail) = check_pbo(&pbo, &key_map, cache);
+
+        if let Some(progress) = progress {
+            let mut finished = finished.lock().unwrap();
+            *finished += 1;
+            progress(Progress {
+                current: *finished,
+                total,
+                label: pbo.file_name().unwrap().to_string_lossy().to_string(),
+            });
+        }
+
+        SignatureCheck {
+            pbo,
+            status,
+            detail,
+        }
+    }))
+}
 
-    publickey.verify(&pbo, &sig)
+/// Checks the signatures of all PBOs in the addons folder against the keys in the keys folder
+/// and prints one line per PBO. Fails if any PBO wouldn't be accepted. With a cache, it is saved
+/// once all PBOs were checked.
+pub fn cmd_check_signatures(addons: PathBuf, keys: PathBuf, progress: Option<&(dyn Fn(Progress) + Sync)>, cache: Option<&VerifyCache>) -> Result<(), Error> {
+    let results = check_signatures_with_cache(&addons, &keys, progress, cache)?;
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
+
+    for result in &results {
+        let status = match result.status {
+            SignatureStatus::Ok => "OK",
+            SignatureStatus::Failed => "FAILED",
+            SignatureStatus::NoSignature => "NO-SIGNATURE",
+        };
+        let path = result.pbo.strip_prefix(&addons).unwrap_or(&result.pbo);
+        println!("{:12} {} ({})", status, path.display(), result.detail);
+    }
+
+    let ok = results.iter().filter(|r| r.status == SignatureStatus::Ok).count();
+    let failed = results.iter().filter(|r| r.status == SignatureStatus::Failed).count();
+    let unsigned = results.len() - ok - failed;
+    println!("\n{} PBOs checked: {} OK, {} failed, {} without signature", results.len(), ok, failed, unsigned);
+
+    if ok < results.len() {
+        return Err(coded_error!("E0809", "{} of {} PBOs would not be accepted.", results.len() - ok, results.len()));
+    }
+
+    Ok(())
 }
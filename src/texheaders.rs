@@ -0,0 +1,465 @@
+//! Texture header cache of binarized addons (texHeaders.bin)
+//!
+//! Binarize writes a `texHeaders.bin` into every addon with the type, mipmap sizes and colors of
+//! the textures its models use, so the game doesn't have to open each PAA to learn them. The
+//! game takes the mid-range color of distant objects from it, so a stale file shows up as wrongly
+//! colored models. `build` writes it again from the packed textures, `texheaders` regenerates it
+//! in a source folder.
+//!
+//! The file is `0DHT`, version 1 and the number of textures, followed by one header per texture.
+//! All values are little endian.
+
+use std::collections::{BTreeSet};
+use std::fs::{File};
+use std::io::{BufReader, Cursor, Error, Read, Seek, Write};
+use std::path::{Path};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::*;
+use crate::io::*;
+use crate::p3d::{P3DInfo};
+use crate::paa::{PaaHeader, PaaType};
+use crate::paths::{InternalPath, entry_name};
+use crate::pbo::{PBO, file_allowed, list_files, source_header_extensions};
+use crate::vfs::{VirtualFilesystem};
+
+/// Name of the file in the addon root
+pub const TEXHEADERS_FILE: &str = "texHeaders.bin";
+
+const MAGIC: &[u8; 4] = b"0DHT";
+const VERSION: u32 = 1;
+
+/// Texture types of TexConvert.cfg by the file name suffixes they are chosen by. Textures with
+/// other suffixes are diffuse textures (0).
+const SUFFIX_TYPES: [(&str, u32); 20] = [
+    ("_co", 0), ("_ca", 0), ("_cdt", 0),
+    ("_lco", 1), ("_lca", 1),
+    ("_dt", 2), ("_mco", 2),
+    ("_no", 3), ("_nohq", 3), ("_nopx", 3), ("_nof", 3), ("_ns", 3), ("_nshq", 3),
+    ("_sm", 4), ("_smdi", 4),
+    ("_mc", 7),
+    ("_as", 8), ("_ads", 8),
+    ("_dtsmdi", 10),
+    ("_mask", 11),
+];
+
+/// Size and position of one mipmap of a texture
+#[derive(Debug, Clone, PartialEq)]
+pub struct MipmapHeader {
+    pub width: u16,
+    pub height: u16,
+    /// Always 0
+    pub unknown: u16,
+    /// Pixel format like `TextureHeader::pixel_format`
+    pub format: u8,
+    /// Always 3
+    pub unknown2: u8,
+    /// Offset of the mipmap in the PAA
+    pub offset: u32,
+}
+
+/// Header of one texture
+///
+/// Fields binarize always writes the same value to are kept as read, so a file is written back
+/// unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureHeader {
+    /// Always 1
+    pub color_palettes: u32,
+    /// Always 0
+    pub palette_pointer: u32,
+    /// Average color as floats between 0 and 1, in the order of the CGVA tagg
+    pub average_color_f: [f32; 4],
+    /// Content of the CGVA tagg
+    pub average_color: [u8; 4],
+    /// Content of the CXAM tagg
+    pub max_color: [u8; 4],
+    /// Always 0
+    pub clamp_flags: u32,
+    /// Always 0xFFFFFFFF
+    pub transparent_color: u32,
+    /// Whether the PAA has a CXAM tagg
+    pub has_max_color: bool,
+    /// Whether the FLAG tagg asks for interpolated alpha
+    pub is_alpha: bool,
+    /// Whether the FLAG tagg asks for alpha testing
+    pub is_transparent: bool,
+    /// Whether the average alpha isn't fully opaque
+    pub is_alpha_non_opaque: bool,
+    /// Pixel format of the engine: 1 for AI88, 3 for ARGB1555, 4 for ARGB4444, 5 for ARGB8888,
+    /// 6 for DXT1, 8 for DXT3 and 10 for DXT5
+    pub pixel_format: u32,
+    /// Always 1
+    pub little_endian: u8,
+    /// Whether the texture is a PAA rather than a PAC
+    pub is_paa: bool,
+    /// Path of the texture in game, like `myaddon\data\gun_co.paa`
+    pub path: String,
+    /// Texture type from the file name suffix, see `SUFFIX_TYPES`
+    pub suffix_type: u32,
+    pub mipmaps: Vec<MipmapHeader>,
+    /// Size of the PAA in bytes
+    pub file_size: u32,
+}
+
+fn pixel_format(paa_type: PaaType) -> u32 {
+    match paa_type {
+        PaaType::AI88 => 1,
+        PaaType::ARGB1555 => 3,
+        PaaType::ARGB4444 => 4,
+        PaaType::ARGB8888 => 5,
+        PaaType::DXT1 => 6,
+        PaaType::DXT3 => 8,
+        PaaType::DXT5 => 10,
+    }
+}
+
+/// Returns the texture type for the suffix of the file name.
+fn suffix_type(path: &InternalPath) -> u32 {
+    let stem = path.components().last().unwrap_or_default().split('.').next().unwrap_or_default();
+    SUFFIX_TYPES.iter()
+        .filter(|(suffix, _)| stem.ends_with(suffix))
+        .max_by_key(|(suffix, _)| suffix.len())
+        .map(|(_, t)| *t)
+        .unwrap_or(0)
+}
+
+fn read_bool<I: Read>(input: &mut I) -> Result<bool, Error> {
+    Ok(input.read_u8()? != 0)
+}
+
+impl TextureHeader {
+    /// Creates the header binarize would write for the PAA at the path in game.
+    pub fn from_paa(path: &InternalPath, paa: &PaaHeader) -> TextureHeader {
+        let average_color = paa.average_color.unwrap_or([0, 0, 0, 255]);
+        let format = pixel_format(paa.paa_type);
+
+        TextureHeader {
+            color_palettes: 1,
+            palette_pointer: 0,
+            average_color_f: average_color.map(|c| c as f32 / 255.0),
+            average_color,
+            max_color: paa.max_color.unwrap_or([255, 255, 255, 255]),
+            clamp_flags: 0,
+            transparent_color: 0xFFFF_FFFF,
+            has_max_color: paa.max_color.is_some(),
+            is_alpha: paa.flag == Some(1),
+            is_transparent: paa.flag == Some(2),
+            is_alpha_non_opaque: average_color[3] < 255,
+            pixel_format: format,
+            little_endian: 1,
+            is_paa: !path.as_str().ends_with(".pac"),
+            path: path.to_string(),
+            suffix_type: suffix_type(path),
+            mipmaps: paa.mipmaps.iter().map(|(width, height, offset)| MipmapHeader {
+                width: *width,
+                height: *height,
+                unknown: 0,
+                format: format as u8,
+                unknown2: 3,
+                offset: *offset,
+            }).collect(),
+            file_size: paa.file_size as u32,
+        }
+    }
+
+    fn read<I: Read>(input: &mut I) -> Result<TextureHeader, Error> {
+        let color_palettes = input.read_u32::<LittleEndian>()?;
+        let palette_pointer = input.read_u32::<LittleEndian>()?;
+        let mut average_color_f = [0f32; 4];
+        for value in average_color_f.iter_mut() {
+            *value = input.read_f32::<LittleEndian>()?;
+        }
+        let mut average_color = [0u8; 4];
+        input.read_exact(&mut average_color)?;
+        let mut max_color = [0u8; 4];
+        input.read_exact(&mut max_color)?;
+        let clamp_flags = input.read_u32::<LittleEndian>()?;
+        let transparent_color = input.read_u32::<LittleEndian>()?;
+        let has_max_color = read_bool(input)?;
+        let is_alpha = read_bool(input)?;
+        let is_transparent = read_bool(input)?;
+        let is_alpha_non_opaque = read_bool(input)?;
+        let mipmap_count = input.read_u32::<LittleEndian>()?;
+        let pixel_format = input.read_u32::<LittleEndian>()?;
+        let little_endian = input.read_u8()?;
+        let is_paa = read_bool(input)?;
+        let path = input.read_cstring_lossy()?;
+        let suffix_type = input.read_u32::<LittleEndian>()?;
+
+        let count = input.read_u32::<LittleEndian>()?;
+        if count != mipmap_count {
+            return Err(coded_error!("E0421", "Texture \"{}\" has {} mipmaps, but {} mipmap headers.", path, mipmap_count, count));
+        }
+        let mut mipmaps = Vec::with_capacity(count.min(16) as usize);
+        for _ in 0..count {
+            mipmaps.push(MipmapHeader {
+                width: input.read_u16::<LittleEndian>()?,
+                height: input.read_u16::<LittleEndian>()?,
+                unknown: input.read_u16::<LittleEndian>()?,
+                format: input.read_u8()?,
+                unknown2: input.read_u8()?,
+                offset: input.read_u32::<LittleEndian>()?,
+            });
+        }
+        let file_size = input.read_u32::<LittleEndian>()?;
+
+        Ok(TextureHeader {
+            color_palettes,
+            palette_pointer,
+            average_color_f,
+            average_color,
+            max_color,
+            clamp_flags,
+            transparent_color,
+            has_max_color,
+            is_alpha,
+            is_transparent,
+            is_alpha_non_opaque,
+            pixel_format,
+            little_endian,
+            is_paa,
+            path,
+            suffix_type,
+            mipmaps,
+            file_size,
+        })
+    }
+
+    fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+        output.write_u32::<LittleEndian>(self.color_palettes)?;
+        output.write_u32::<LittleEndian>(self.palette_pointer)?;
+        for value in &self.average_color_f {
+            output.write_f32::<LittleEndian>(*value)?;
+        }
+        output.write_all(&self.average_color)?;
+        output.write_all(&self.max_color)?;
+        output.write_u32::<LittleEndian>(self.clamp_flags)?;
+        output.write_u32::<LittleEndian>(self.transparent_color)?;
+        output.write_all(&[self.has_max_color as u8, self.is_alpha as u8, self.is_transparent as u8, self.is_alpha_non_opaque as u8])?;
+        output.write_u32::<LittleEndian>(field_u32(self.mipmaps.len(), "Number of mipmaps")?)?;
+        output.write_u32::<LittleEndian>(self.pixel_format)?;
+        output.write_all(&[self.little_endian, self.is_paa as u8])?;
+        output.write_cstring(&self.path)?;
+        output.write_u32::<LittleEndian>(self.suffix_type)?;
+        output.write_u32::<LittleEndian>(field_u32(self.mipmaps.len(), "Number of mipmaps")?)?;
+        for mipmap in &self.mipmaps {
+            output.write_u16::<LittleEndian>(mipmap.width)?;
+            output.write_u16::<LittleEndian>(mipmap.height)?;
+            output.write_u16::<LittleEndian>(mipmap.unknown)?;
+            output.write_all(&[mipmap.format, mipmap.unknown2])?;
+            output.write_u32::<LittleEndian>(mipmap.offset)?;
+        }
+        output.write_u32::<LittleEndian>(self.file_size)?;
+        Ok(())
+    }
+}
+
+/// Contents of a texHeaders.bin
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TexHeaders {
+    pub textures: Vec<TextureHeader>,
+}
+
+impl TexHeaders {
+    pub fn read<I: Read>(input: &mut I) -> Result<TexHeaders, Error> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(coded_error!("E0421", "File is not a texHeaders.bin."));
+        }
+        let version = input.read_u32::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(coded_error!("E0421", "Unsupported texHeaders.bin version {}.", version));
+        }
+
+        let count = input.read_u32::<LittleEndian>()?;
+        let mut textures = Vec::new();
+        for i in 0..count {
+            textures.push(TextureHeader::read(input).prepend_error(format!("Failed to read texture header {}:", i))?);
+        }
+        Ok(TexHeaders { textures })
+    }
+
+    pub fn write<O: Write>(&self, output: &mut O) -> Result<(), Error> {
+        output.write_all(MAGIC)?;
+        output.write_u32::<LittleEndian>(VERSION)?;
+        output.write_u32::<LittleEndian>(field_u32(self.textures.len(), "Number of textures")?)?;
+        for texture in &self.textures {
+            texture.write(output)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the header of the texture at the path in game, ignoring case and slash direction.
+    pub fn get(&self, path: &str) -> Option<&TextureHeader> {
+        let path = InternalPath::new(path).ok()?;
+        self.textures.iter().find(|t| InternalPath::new(&t.path).is_ok_and(|p| p == path))
+    }
+
+    /// Creates the headers for the PAAs of a PBO and the textures its models use. `models` are
+    /// the textures of the models if they were read before binarizing, see `model_textures`,
+    /// otherwise the models of the PBO are read. Textures of other addons are read from the mod
+    /// folders of `filesystem`, or copied from `existing`, the texHeaders.bin the PBO had so far,
+    /// with a `texheaders-copied` warning.
+    pub fn from_pbo(pbo: &PBO, models: Option<&BTreeSet<InternalPath>>, existing: Option<&TexHeaders>, filesystem: Option<&VirtualFilesystem>) -> TexHeaders {
+        let prefix = pbo.header_extensions.get("prefix").map(String::as_str).unwrap_or_default();
+
+        let mut sources = Sources::default();
+        for (name, data) in pbo.files.iter() {
+            let lower = name.to_lowercase();
+            if lower.ends_with(".p3d") && models.is_some() {
+                continue;
+            }
+            sources.add(prefix, name, &mut Cursor::new(data.get_ref()));
+        }
+        if let Some(models) = models {
+            sources.references.extend(models.iter().cloned());
+        }
+
+        sources.finish(existing, filesystem)
+    }
+}
+
+/// Textures found in the files of an addon
+#[derive(Default)]
+struct Sources {
+    textures: Vec<TextureHeader>,
+    own: BTreeSet<InternalPath>,
+    references: BTreeSet<InternalPath>,
+}
+
+impl Sources {
+    /// Adds the header of a PAA or the textures of a model, ignoring other files.
+    fn add<I: Read + Seek>(&mut self, prefix: &str, name: &str, input: &mut I) {
+        let lower = name.to_lowercase();
+        if lower.ends_with(".paa") || lower.ends_with(".pac") {
+            let path = match InternalPath::new(prefix).and_then(|p| Ok(p.join(&InternalPath::new(name)?))) {
+                Ok(path) => path,
+                Err(e) => return warning(format!("Failed to read {} for {}: {}", name, TEXHEADERS_FILE, e), Some("texheaders-unreadable"), (None, None)),
+            };
+            match PaaHeader::read(input) {
+                Ok(header) => self.textures.push(TextureHeader::from_paa(&path, &header)),
+                Err(e) => warning(format!("Failed to read {} for {}: {}", name, TEXHEADERS_FILE, e), Some("texheaders-unreadable"), (None, None)),
+            }
+            self.own.insert(path);
+        } else if lower.ends_with(".p3d") {
+            match P3DInfo::read(input) {
+                // Paths leaving their folder can't be in any addon
+                Ok(info) => self.references.extend(info.textures().iter()
+                    .filter(|t| !t.is_empty() && !t.starts_with('#'))
+                    .filter_map(|t| InternalPath::new(t).ok())),
+                Err(e) => warning(format!("Failed to read the textures of {} for {}: {}", name, TEXHEADERS_FILE, e), Some("texheaders-unreadable"), (None, None)),
+            }
+        }
+    }
+
+    /// Adds the headers of the textures of other addons the models use. Without mod folders
+    /// there is nothing to look them up in, so textures that aren't in `existing` either are
+    /// left out without a warning.
+    fn finish(mut self, existing: Option<&TexHeaders>, filesystem: Option<&VirtualFilesystem>) -> TexHeaders {
+        for path in self.references.difference(&self.own) {
+            match external_header(path, filesystem) {
+                Ok(Some(header)) => self.textures.push(header),
+                Ok(None) => match existing.and_then(|e| e.get(path.as_str())) {
+                    Some(header) => {
+                        warning(format!("{} is in another addon, its header was copied from the previous {}.", path, TEXHEADERS_FILE),
+                            Some("texheaders-copied"), (None, None));
+                        self.textures.push(header.clone());
+                    },
+                    None if filesystem.is_some() => warning(format!("{} is neither in the mod folders nor in the previous {}, so it was left out.", path, TEXHEADERS_FILE),
+                        Some("texheaders-missing"), (None, None)),
+                    None => {},
+                },
+                Err(e) => warning(format!("Failed to read {} for {}: {}", path, TEXHEADERS_FILE, e), Some("texheaders-unreadable"), (None, None)),
+            }
+        }
+
+        TexHeaders { textures: self.textures }
+    }
+}
+
+/// Returns the textures the models of a source folder use, read before binarizing them since
+/// the textures of binarized models can't always be read.
+pub fn model_textures(directory: &Path, excludes: &[String]) -> Result<BTreeSet<InternalPath>, Error> {
+    let mut sources = Sources::default();
+    for path in list_files(&directory.to_path_buf())? {
+        let name = entry_name(directory, &path)?;
+        if name.to_lowercase().ends_with(".p3d") && file_allowed(&name, excludes) {
+            sources.add("", &name, &mut BufReader::new(File::open(&path)?));
+        }
+    }
+    Ok(sources.references)
+}
+
+/// Reads the header of a texture of another addon from the mod folders, `None` if there are
+/// none or the texture isn't in them.
+fn external_header(path: &InternalPath, filesystem: Option<&VirtualFilesystem>) -> Result<Option<TextureHeader>, Error> {
+    let Some(filesystem) = filesystem else { return Ok(None) };
+    if filesystem.resolve(path.as_str())?.is_none() {
+        return Ok(None);
+    }
+    let data = filesystem.read(path.as_str())?;
+    Ok(Some(TextureHeader::from_paa(path, &PaaHeader::read(&mut Cursor::new(data))?)))
+}
+
+/// Reads the texHeaders.bin of a PBO, if it has one.
+pub fn read_from_pbo(pbo: &PBO) -> Option<TexHeaders> {
+    let (name, data) = pbo.files.iter().find(|(name, _)| name.eq_ignore_ascii_case(TEXHEADERS_FILE))?;
+    match TexHeaders::read(&mut Cursor::new(data.get_ref())) {
+        Ok(headers) => Some(headers),
+        Err(e) => {
+            warning(format!("Failed to read the previous {}: {}", name, e), Some("texheaders-unreadable"), (None, None));
+            None
+        }
+    }
+}
+
+/// Replaces the texHeaders.bin of a PBO with one for its current textures, see
+/// `TexHeaders::from_pbo`. Nothing is added to PBOs without any textures.
+pub fn update_pbo(pbo: &mut PBO, models: Option<&BTreeSet<InternalPath>>, filesystem: Option<&VirtualFilesystem>) -> Result<(), Error> {
+    let existing = read_from_pbo(pbo);
+    let headers = TexHeaders::from_pbo(pbo, models, existing.as_ref(), filesystem);
+
+    let stale: Vec<String> = pbo.files.keys().filter(|name| name.eq_ignore_ascii_case(TEXHEADERS_FILE)).cloned().collect();
+    for name in stale {
+        pbo.files.remove(&name);
+    }
+    if headers.textures.is_empty() {
+        return Ok(());
+    }
+
+    let mut buffer: Vec<u8> = Vec::new();
+    headers.write(&mut buffer)?;
+    pbo.files.insert(TEXHEADERS_FILE.to_string(), Cursor::new(buffer.into_boxed_slice()));
+    Ok(())
+}
+
+/// Writes the texHeaders.bin of a source folder again, for the PAAs in it and the textures its
+/// models use. Only the headers of the PAAs are read. Returns the number of textures.
+pub fn cmd_texheaders(directory: &Path, filesystem: Option<&VirtualFilesystem>) -> Result<usize, Error> {
+    let header_extensions = source_header_extensions(directory).prepend_error("Failed to read source folder:")?;
+    let prefix = header_extensions.get("prefix").map(String::as_str).unwrap_or_default();
+
+    let path = directory.join(TEXHEADERS_FILE);
+    let mut existing: Option<TexHeaders> = None;
+    let mut sources = Sources::default();
+    for file in list_files(&directory.to_path_buf()).prepend_error("Failed to read source folder:")? {
+        let name = entry_name(directory, &file)?;
+        if name.eq_ignore_ascii_case(TEXHEADERS_FILE) {
+            match TexHeaders::read(&mut BufReader::new(File::open(&file)?)) {
+                Ok(headers) => existing = Some(headers),
+                Err(e) => warning(format!("Failed to read the previous {}: {}", name, e), Some("texheaders-unreadable"), (None, None)),
+            }
+        } else {
+            sources.add(prefix, &name, &mut BufReader::new(File::open(&file)?));
+        }
+    }
+    let headers = sources.finish(existing.as_ref(), filesystem);
+
+    let mut buffer: Vec<u8> = Vec::new();
+    headers.write(&mut buffer)?;
+    create_file(&path)?.write_all(&buffer).prepend_error(format!("Failed to write {}:", path.display()))?;
+    Ok(headers.textures.len())
+}
@@ -0,0 +1,236 @@
+//! Texture rules of a team from a policy file
+//!
+//! A policy file lists rules in TOML, each applying to the PAAs whose path matches its glob
+//! pattern. Paths are relative to the checked folder, or to the PBO when building, and are
+//! compared ignoring case and slash direction. `*` matches any characters, including slashes.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "UI icons"
+//! files = "ui/icons/*.paa"
+//! max_size = 256
+//! types = ["DXT5"]
+//! require_alpha_flag = true
+//!
+//! [[rule]]
+//! files = "weapons/*_co.paa"
+//! max_size = 2048
+//!
+//! [[rule]]
+//! files = "*"
+//! require_mips = true
+//! ```
+//!
+//! Every matching rule applies. Unknown keys are an error, so a typo doesn't silently disable a
+//! rule.
+
+use std::fmt;
+use std::fs::{read_to_string};
+use std::io::{Cursor, Error};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize};
+
+use crate::error::*;
+use crate::io::{files_with_extensions};
+use crate::paa::{Paa, PaaType};
+use crate::parallel;
+use crate::paths::{InternalPath, entry_name};
+use crate::pbo::{PBO, matches_glob};
+
+/// Names of all PAA types, as used in `types`
+const TYPE_NAMES: [&str; 7] = ["DXT1", "DXT3", "DXT5", "ARGB4444", "ARGB8888", "ARGB1555", "AI88"];
+
+/// Largest side of the smallest mipmap a complete chain has to go down to. Tools usually stop
+/// at the size of a DXT block.
+const SMALLEST_MIPMAP: u32 = 4;
+
+/// Constraints for the textures matching a pattern
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextureRule {
+    /// Name used in violations, the pattern if not set
+    pub name: Option<String>,
+    /// Glob pattern of the texture paths the rule applies to
+    pub files: String,
+    /// Largest width and height of the full size image
+    pub max_size: Option<u32>,
+    /// PAA types the textures may have, like `DXT5`, any if empty
+    pub types: Vec<String>,
+    /// Require mipmaps down to 4x4 pixels
+    pub require_mips: bool,
+    /// Require the FLAG tagg, which tells the engine how to use the alpha channel
+    pub require_alpha_flag: bool,
+}
+
+impl TextureRule {
+    /// Returns the name of the rule, or its pattern if it has none.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.files)
+    }
+
+    /// Returns whether the rule applies to the texture path.
+    pub fn matches(&self, path: &str) -> bool {
+        // Paths leaving their folder match no rule, `parse` rejects such patterns
+        match (InternalPath::new(path), InternalPath::new(&self.files)) {
+            (Ok(path), Ok(files)) => matches_glob(path.as_str(), files.as_str()),
+            _ => false
+        }
+    }
+}
+
+/// Contents of a policy file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TexturePolicy {
+    pub rule: Vec<TextureRule>,
+}
+
+/// Texture that breaks a rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// Path of the texture
+    pub path: String,
+    /// Name of the rule
+    pub rule: String,
+    /// What the texture has, like `4096x4096`
+    pub actual: String,
+    /// What the rule allows, like `at most 2048x2048`
+    pub allowed: String,
+}
+
+impl Violation {
+    fn new(path: &str, rule: &TextureRule, actual: String, allowed: String) -> Violation {
+        Violation { path: path.to_string(), rule: rule.name().to_string(), actual, allowed }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: rule \"{}\": {}, allowed is {}", self.path, self.rule, self.actual, self.allowed)
+    }
+}
+
+impl TexturePolicy {
+    /// Reads a policy file.
+    pub fn load(path: &Path) -> Result<TexturePolicy, Error> {
+        let content = read_to_string(path).prepend_error(format!("Failed to read {}:", path.display()))?;
+        Self::parse(&content).prepend_error(format!("Failed to read {}:", path.display()))
+    }
+
+    /// Parses the content of a policy file.
+    pub fn parse(content: &str) -> Result<TexturePolicy, Error> {
+        let policy: TexturePolicy = toml::from_str(content).map_err(|e| coded_error!("E0422", "{}", e))?;
+
+        for rule in &policy.rule {
+            if rule.files.is_empty() {
+                return Err(coded_error!("E0422", "Rule \"{}\" has no files pattern.", rule.name()));
+            }
+            InternalPath::new(&rule.files).prepend_error(format!("Invalid files pattern of rule \"{}\":", rule.name()))?;
+            if rule.max_size == Some(0) {
+                return Err(coded_error!("E0422", "max_size of rule \"{}\" has to be at least 1.", rule.name()));
+            }
+            if let Some(name) = rule.types.iter().find(|t| !TYPE_NAMES.iter().any(|n| n.eq_ignore_ascii_case(t))) {
+                return Err(coded_error!("E0422", "Unknown PAA type \"{}\" in rule \"{}\", expected one of {}.", name, rule.name(), TYPE_NAMES.join(", ")));
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Returns how the texture at the path breaks the rules that apply to it.
+    pub fn check(&self, path: &str, paa: &Paa) -> Vec<Violation> {
+        let (width, height) = paa.dimensions(0).unwrap_or((0, 0));
+
+        let mut violations: Vec<Violation> = Vec::new();
+        for rule in self.rule.iter().filter(|r| r.matches(path)) {
+            if let Some(max) = rule.max_size && width.max(height) > max {
+                violations.push(Violation::new(path, rule, format!("{}x{}", width, height), format!("at most {}x{}", max, max)));
+            }
+
+            if !type_allowed(paa.paa_type, &rule.types) {
+                violations.push(Violation::new(path, rule, format!("{:?}", paa.paa_type), rule.types.join(" or ")));
+            }
+
+            if rule.require_mips && !has_mipmap_chain(paa) {
+                let smallest = (0..paa.mipmap_count()).filter_map(|i| paa.dimensions(i)).min_by_key(|(w, h)| *w.max(h)).unwrap_or((0, 0));
+                violations.push(Violation::new(path, rule, format!("{} mipmaps down to {}x{}", paa.mipmap_count(), smallest.0, smallest.1),
+                    format!("mipmaps down to {}x{}", SMALLEST_MIPMAP, SMALLEST_MIPMAP)));
+            }
+
+            if rule.require_alpha_flag && paa.flag().is_none() {
+                violations.push(Violation::new(path, rule, "no alpha flag".to_string(), "alpha flag set".to_string()));
+            }
+        }
+
+        violations
+    }
+
+    /// Returns how the PAAs of a PBO break the rules, by entry name.
+    pub fn check_pbo(&self, pbo: &PBO) -> Vec<Violation> {
+        pbo.files.iter()
+            .filter(|(name, _)| name.to_lowercase().ends_with(".paa"))
+            .flat_map(|(name, data)| match Paa::from_reader(&mut Cursor::new(data.get_ref())) {
+                Ok(paa) => self.check(name, &paa),
+                Err(e) => {
+                    warning(format!("Failed to read {} to check the texture policy: {}", name, e), Some("texture-policy-unreadable"), (None, None));
+                    Vec::new()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns whether textures of the type pass a rule allowing the type names.
+fn type_allowed(paa_type: PaaType, types: &[String]) -> bool {
+    types.is_empty() || types.iter().any(|t| t.eq_ignore_ascii_case(&format!("{:?}", paa_type)))
+}
+
+/// Returns whether each mipmap halves the one before it, down to a mipmap no larger than
+/// `SMALLEST_MIPMAP` on either side.
+fn has_mipmap_chain(paa: &Paa) -> bool {
+    let sizes: Vec<(u32, u32)> = (0..paa.mipmap_count()).filter_map(|i| paa.dimensions(i)).collect();
+    let halved = sizes.windows(2).all(|w| w[1] == ((w[0].0 / 2).max(1), (w[0].1 / 2).max(1)));
+    halved && sizes.last().is_some_and(|(w, h)| *w.max(h) <= SMALLEST_MIPMAP)
+}
+
+/// Reports violations as `texture-policy` warnings, or fails with all of them if `enforce` is
+/// set. Returns the number of violations.
+pub fn report_violations(violations: &[Violation], enforce: bool) -> Result<usize, Error> {
+    if enforce && !violations.is_empty() {
+        let lines: Vec<String> = violations.iter().map(|v| format!("  {}", v)).collect();
+        return Err(coded_error!("E0401", "{} textures violate the texture policy:\n{}", violations.len(), lines.join("\n")));
+    }
+
+    for violation in violations {
+        warning(format!("Rule \"{}\": {}, allowed is {}.", violation.rule, violation.actual, violation.allowed),
+            Some("texture-policy"), (Some(violation.path.clone()), None));
+    }
+    Ok(violations.len())
+}
+
+/// Checks all PAAs in the folder and its subfolders against the policy, reading them in
+/// parallel. Violations are warnings, or an error if `enforce` is set. Returns the number of
+/// violations.
+pub fn cmd_texture_policy_check(dir: &Path, policy: &TexturePolicy, enforce: bool) -> Result<usize, Error> {
+    let paas = files_with_extensions(dir, &["paa"]).prepend_error("Failed to read texture folder:")?;
+
+    let results: Vec<Result<Vec<Violation>, Error>> = parallel::map_collect(paas.clone(), |path: PathBuf| {
+        // Named like the entries of a PBO packed from the folder
+        let name = entry_name(dir, &path)?;
+        match Paa::from_file(dir.join(&path)) {
+            Ok(paa) => Ok(policy.check(&name, &paa)),
+            Err(e) => {
+                warning(format!("Failed to read {} to check the texture policy: {}", name, e),
+                    Some("texture-policy-unreadable"), (Some(name), None));
+                Ok(Vec::new())
+            }
+        }
+    });
+
+    let mut violations: Vec<Violation> = Vec::new();
+    for result in results {
+        violations.extend(result?);
+    }
+    report_violations(&violations, enforce)
+}
@@ -0,0 +1,259 @@
+//! Reports about the textures in a folder, optionally checking the albedo range of diffuse maps
+
+use std::fs::{File};
+use std::io::{Write, Error};
+use std::path::{Path, PathBuf};
+
+use crate::error::*;
+use crate::io::{files_with_extensions};
+use crate::paa::{Paa, PaaHeader, PaaType};
+use crate::parallel;
+
+/// Options for checking the albedo of diffuse (`_co`) textures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlbedoOptions {
+    /// Lowest expected channel value
+    pub min: u8,
+    /// Highest expected channel value
+    pub max: u8,
+    /// Fraction of pixels that may be outside the range in any channel before a texture is flagged
+    pub max_outside: f32,
+    /// Largest mipmap dimension to analyze. Smaller mipmaps are much faster to decode and have
+    /// practically the same statistics.
+    pub sample_size: u32,
+}
+
+impl Default for AlbedoOptions {
+    fn default() -> AlbedoOptions {
+        AlbedoOptions {
+            min: 20,
+            max: 230,
+            max_outside: 0.05,
+            sample_size: 256,
+        }
+    }
+}
+
+/// Statistics of one color channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    /// 5th percentile
+    pub p5: u8,
+    /// Median
+    pub median: u8,
+    /// 95th percentile
+    pub p95: u8,
+    /// Fraction of pixels outside the expected range
+    pub outside: f32,
+}
+
+/// Albedo statistics of a texture, computed on a small mipmap
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbedoStats {
+    /// Size of the analyzed mipmap
+    pub sample_width: u32,
+    /// Size of the analyzed mipmap
+    pub sample_height: u32,
+    /// Statistics of the red, green and blue channel
+    pub channels: [ChannelStats; 3],
+    /// Whether too many pixels are outside the expected range in any channel
+    pub flagged: bool,
+}
+
+/// Report line for a single texture
+#[derive(Debug, Clone)]
+pub struct TextureReport {
+    /// Path of the texture
+    pub path: PathBuf,
+    /// PAA type
+    pub paa_type: PaaType,
+    /// Width of the full size image
+    pub width: u32,
+    /// Height of the full size image
+    pub height: u32,
+    /// Number of mipmaps
+    pub mipmaps: usize,
+    /// Whether the mipmaps are LZO compressed
+    pub compressed: bool,
+    /// Albedo statistics, only for diffuse textures and when requested
+    pub albedo: Option<AlbedoStats>,
+}
+
+/// Returns true if the file name marks the texture as diffuse map (`*_co.paa`).
+pub fn is_diffuse_texture(path: &Path) -> bool {
+    path.file_stem().map(|s| s.to_string_lossy().to_lowercase().ends_with("_co")).unwrap_or(false)
+}
+
+fn percentile(histogram: &[u32; 256], count: u32, p: f32) -> u8 {
+    let target = ((count as f32) * p).ceil().max(1.0) as u32;
+    let mut sum = 0;
+    for (value, n) in histogram.iter().enumerate() {
+        sum += n;
+        if sum >= target {
+            return value as u8;
+        }
+    }
+    255
+}
+
+/// Computes the albedo statistics of a PAA on its largest mipmap that fits the sample size.
+///
+/// Fully transparent pixels are ignored since their color is never visible.
+pub fn analyze_albedo(paa: &Paa, options: &AlbedoOptions) -> Result<AlbedoStats, Error> {
+    let index = (0..paa.mipmap_count())
+        .find(|i| paa.dimensions(*i).map(|(w, h)| w.max(h) <= options.sample_size).unwrap_or(false))
+        .unwrap_or_else(|| paa.mipmap_count().saturating_sub(1));
+    let img = paa.to_image_level(index)?.to_rgba8();
+
+    let mut histograms = [[0u32; 256]; 3];
+    let mut count = 0;
+    for pixel in img.pixels().filter(|p| p[3] > 0) {
+        for (channel, histogram) in histograms.iter_mut().enumerate() {
+            histogram[pixel[channel] as usize] += 1;
+        }
+        count += 1;
+    }
+
+    let mut channels = [ChannelStats { p5: 0, median: 0, p95: 0, outside: 0.0 }; 3];
+    for (stats, histogram) in channels.iter_mut().zip(histograms.iter()) {
+        if count == 0 { continue; }
+
+        let outside: u32 = histogram.iter().enumerate()
+            .filter(|(value, _)| *value < options.min as usize || *value > options.max as usize)
+            .map(|(_, n)| n)
+            .sum();
+        *stats = ChannelStats {
+            p5: percentile(histogram, count, 0.05),
+            median: percentile(histogram, count, 0.5),
+            p95: percentile(histogram, count, 0.95),
+            outside: outside as f32 / count as f32,
+        };
+    }
+
+    Ok(AlbedoStats {
+        sample_width: img.width(),
+        sample_height: img.height(),
+        flagged: channels.iter().any(|c| c.outside > options.max_outside),
+        channels,
+    })
+}
+
+fn report_texture(path: PathBuf, albedo: Option<&AlbedoOptions>) -> Result<TextureReport, Error> {
+    let albedo_options = albedo.filter(|_| is_diffuse_texture(&path));
+
+    // Only the albedo needs the mipmap data, everything else is in the headers
+    let (paa_type, sizes, compressed, albedo) = match albedo_options {
+        Some(options) => {
+            let paa = Paa::from_file(&path)?;
+            let sizes: Vec<(u32, u32)> = (0..paa.mipmap_count()).filter_map(|i| paa.dimensions(i)).collect();
+            let albedo = analyze_albedo(&paa, options).prepend_error(format!("Failed to analyze {}:", path.display()))?;
+            (paa.paa_type, sizes, paa.is_compressed(), Some(albedo))
+        },
+        None => {
+            let header = PaaHeader::read(&mut File::open(&path)?).prepend_error(format!("Failed to read \"{}\":", path.display()))?;
+            let sizes = header.mipmaps.iter().map(|(w, h, _)| (*w as u32, *h as u32)).collect();
+            (header.paa_type, sizes, header.compressed, None)
+        }
+    };
+    let (width, height) = sizes.iter().copied().max_by_key(|(w, h)| w * h)
+        .ok_or_else(|| coded_error!("E0410", "No mipmaps found in PAA file"))?;
+
+    Ok(TextureReport {
+        paa_type,
+        width,
+        height,
+        mipmaps: sizes.len(),
+        compressed,
+        albedo,
+        path,
+    })
+}
+
+/// Reads all PAAs in the folder and its subfolders, sorted by path. If `albedo` is given,
+/// diffuse textures are also analyzed. Textures are read in parallel.
+pub fn texture_report(folder: &Path, albedo: Option<&AlbedoOptions>) -> Result<Vec<TextureReport>, Error> {
+    let paths: Vec<PathBuf> = files_with_extensions(folder, &["paa"]).prepend_error("Failed to read texture folder:")?
+        .into_iter().map(|path| folder.join(path)).collect();
+
+    parallel::map_collect(paths, |path| report_texture(path, albedo))
+}
+
+/// Writes the report as CSV with one line per texture. Albedo columns are empty for textures
+/// that weren't analyzed.
+pub fn write_csv<O: Write>(reports: &[TextureReport], output: &mut O) -> Result<(), Error> {
+    write!(output, "path,type,width,height,mipmaps,compressed,albedo_flagged")?;
+    for channel in &["r", "g", "b"] {
+        write!(output, ",{0}_p5,{0}_median,{0}_p95,{0}_outside", channel)?;
+    }
+    writeln!(output)?;
+
+    for report in reports {
+        let path = report.path.to_string_lossy();
+        let path = if path.contains(',') || path.contains('"') {
+            format!("\"{}\"", path.replace('"', "\"\""))
+        } else {
+            path.to_string()
+        };
+        write!(output, "{},{:?},{},{},{},{}", path, report.paa_type, report.width, report.height, report.mipmaps, report.compressed)?;
+
+        match &report.albedo {
+            Some(albedo) => {
+                write!(output, ",{}", albedo.flagged)?;
+                for c in &albedo.channels {
+                    write!(output, ",{},{},{},{:.4}", c.p5, c.median, c.p95, c.outside)?;
+                }
+            },
+            None => write!(output, "{}", ",".repeat(13))?
+        }
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+/// Prints a report of all textures in the folder and optionally writes it as CSV.
+///
+/// With albedo options, diffuse textures with too many pixels outside the albedo range are
+/// warned about and make the command fail.
+pub fn cmd_texture_report(folder: &Path, albedo: Option<&AlbedoOptions>, csv: Option<&Path>) -> Result<(), Error> {
+    let reports = texture_report(folder, albedo)?;
+
+    for report in &reports {
+        let path = report.path.strip_prefix(folder).unwrap_or(&report.path);
+        println!("{:6} {:>5}x{:<5} {:2} mips{} {}", format!("{:?}", report.paa_type), report.width, report.height, report.mipmaps,
+            if report.compressed { " LZO" } else { "    " }, path.display());
+
+        if let Some(stats) = &report.albedo && stats.flagged {
+            let options = albedo.unwrap();
+            let channels: Vec<String> = ["R", "G", "B"].iter().zip(stats.channels.iter())
+                .map(|(name, c)| format!("{} {:.1}%", name, c.outside * 100.0))
+                .collect();
+            warning(format!("Too many pixels outside the albedo range {}-{}: {}.", options.min, options.max, channels.join(", ")),
+                Some("albedo-out-of-range"), (Some(path.to_string_lossy().to_string()), None));
+        }
+    }
+
+    if let Some(csv) = csv {
+        let mut file = File::create(csv).prepend_error("Failed to open CSV file:")?;
+        write_csv(&reports, &mut file).prepend_error("Failed to write CSV file:")?;
+    }
+
+    let flagged = reports.iter().filter(|r| r.albedo.as_ref().map(|a| a.flagged).unwrap_or(false)).count();
+    if flagged > 0 {
+        return Err(coded_error!("E0402", "{} of {} diffuse textures are outside the albedo range.", flagged,
+            reports.iter().filter(|r| r.albedo.is_some()).count()));
+    }
+
+    Ok(())
+}
+
+/// Parses an albedo range given as `min-max`.
+pub fn parse_albedo_range(input: &str) -> Result<(u8, u8), Error> {
+    let (min, max) = input.split_once('-').ok_or_else(|| coded_error!("E0901", "Invalid albedo range \"{}\", expected min-max like 20-230.", input))?;
+    let min: u8 = min.trim().parse().map_err(|_| coded_error!("E0901", "Invalid albedo range minimum \"{}\".", min))?;
+    let max: u8 = max.trim().parse().map_err(|_| coded_error!("E0901", "Invalid albedo range maximum \"{}\".", max))?;
+    if min > max {
+        return Err(coded_error!("E0901", "Albedo range minimum {} is larger than the maximum {}.", min, max));
+    }
+    Ok((min, max))
+}
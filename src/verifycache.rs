@@ -0,0 +1,170 @@
+//! Cache of PBO hashes for repeated signature checks
+//!
+//! Hashing every PBO of a large mod set takes minutes, while checking the signatures against the
+//! hashes is quick. The cache stores the hashes of every PBO together with its size and
+//! modification time, and while both still match, the stored hashes are used instead of reading
+//! the PBO again.
+//!
+//! This trusts that a PBO changed only if its size or modification time did. A PBO that was
+//! modified in place with its modification time restored passes with its old hashes, so checks
+//! that have to catch tampering should not trust the cache, see `VerifyCache::new`.
+
+use std::collections::{HashMap};
+use std::fs::{File, read_to_string, rename};
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+use crate::io::{create_file};
+use crate::pbo::{PBO};
+use crate::sign::{PboHashes};
+
+/// Version of the cache file format, files of other versions are ignored
+const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: Vec<CacheEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    /// Modification time in nanoseconds since the epoch
+    modified: u128,
+    checksum: String,
+    names: String,
+    files_v2: String,
+    files_v3: String,
+    prefix: Option<String>,
+    trailing_data: usize,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+impl CacheEntry {
+    fn hashes(&self) -> Option<PboHashes> {
+        Some(PboHashes {
+            checksum: from_hex(&self.checksum)?,
+            names: from_hex(&self.names)?,
+            files_v2: from_hex(&self.files_v2)?,
+            files_v3: from_hex(&self.files_v3)?,
+            prefix: self.prefix.clone(),
+            trailing_data: self.trailing_data,
+        })
+    }
+}
+
+/// Returns the size and modification time of a file.
+fn stamp(path: &Path) -> Result<(u64, u128), Error> {
+    let metadata = path.metadata()?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+/// Hashes of PBOs stored in a file between signature checks
+pub struct VerifyCache {
+    path: PathBuf,
+    trust: bool,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl VerifyCache {
+    /// Loads the cache from the file, starting empty if it doesn't exist or is of another format
+    /// version. Without `trust`, every PBO is hashed again and the cache is only updated.
+    pub fn new(path: PathBuf, trust: bool) -> VerifyCache {
+        let entries = read_to_string(&path).ok()
+            .and_then(|content| serde_json::from_str::<CacheFile>(&content).ok())
+            .filter(|file| file.version == FORMAT_VERSION)
+            .map(|file| file.entries.into_iter().map(|e| (e.path.clone(), e)).collect())
+            .unwrap_or_default();
+
+        VerifyCache {
+            path,
+            trust,
+            entries: Mutex::new(entries),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of PBOs whose stored hashes were used
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of PBOs that had to be hashed
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the hashes of the PBO, reading and hashing it unless the cache is trusted and its
+    /// size and modification time match the stored ones.
+    pub fn hashes(&self, pbo_path: &Path) -> Result<PboHashes, Error> {
+        let key = pbo_path.canonicalize().unwrap_or_else(|_| pbo_path.to_path_buf());
+        let (size, modified) = stamp(pbo_path)?;
+
+        if self.trust {
+            let entries = self.entries.lock().unwrap();
+            if let Some(hashes) = entries.get(&key).filter(|e| e.size == size && e.modified == modified).and_then(CacheEntry::hashes) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(hashes);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let hashes = PboHashes::from_pbo(&PBO::read(&mut File::open(pbo_path)?)?);
+        self.entries.lock().unwrap().insert(key.clone(), CacheEntry {
+            path: key,
+            size,
+            modified,
+            checksum: to_hex(&hashes.checksum),
+            names: to_hex(&hashes.names),
+            files_v2: to_hex(&hashes.files_v2),
+            files_v3: to_hex(&hashes.files_v3),
+            prefix: hashes.prefix.clone(),
+            trailing_data: hashes.trailing_data,
+        });
+        Ok(hashes)
+    }
+
+    /// Writes the cache back to its file. The file is written next to it under a temporary name
+    /// first and then renamed, so a concurrent check never reads a partial file and the last
+    /// writer wins.
+    pub fn save(&self) -> Result<(), Error> {
+        let mut entries: Vec<CacheEntry> = self.entries.lock().unwrap().values().cloned().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let content = serde_json::to_string(&CacheFile {
+            version: FORMAT_VERSION,
+            entries,
+        }).map_err(Error::from)?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = create_file(&temporary)?;
+        file.write_all(content.as_bytes()).prepend_error("Failed to write verify cache:")?;
+        file.sync_all()?;
+        drop(file);
+        rename(&temporary, &self.path).prepend_error("Failed to replace verify cache:")
+    }
+}
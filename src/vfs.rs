@@ -0,0 +1,128 @@
+//! Engine-style paths resolved to entries of packed PBOs
+//!
+//! The game mounts every PBO under its prefix, so `\a3\weapons_f\config.bin` is the entry
+//! `config.bin` of the PBO with the prefix `a3\weapons_f`. A `VirtualFilesystem` does the same
+//! for the PBOs in a set of mod directories, reading only the headers of each PBO and only once
+//! per directory.
+
+use std::collections::{HashMap};
+use std::fs::{File};
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{self, Config};
+use crate::error::*;
+use crate::paths::{InternalPath};
+use crate::pbo::{list_files, read_entry, read_entry_names};
+
+/// PBO found in a mod directory
+struct IndexedPbo {
+    path: PathBuf,
+    prefix: InternalPath,
+    /// Original entry names by their normalized names
+    entries: HashMap<InternalPath, String>,
+}
+
+/// Entry of a PBO that a virtual path resolves to
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualEntry {
+    /// Path of the PBO
+    pub pbo: PathBuf,
+    /// Name of the entry as stored in the PBO
+    pub entry: String,
+}
+
+/// PBOs of a set of mod directories, indexed by their prefix
+///
+/// Directories are searched in the order they were given and the first one containing the path
+/// wins. Within a directory, the PBO with the longest prefix that contains the entry wins, so
+/// `a3\weapons_f\rifles` takes precedence over `a3\weapons_f` for the files it contains.
+pub struct VirtualFilesystem {
+    directories: Vec<PathBuf>,
+    indexes: Mutex<HashMap<PathBuf, Arc<Vec<IndexedPbo>>>>,
+}
+
+fn index_directory(directory: &Path) -> Result<Vec<IndexedPbo>, Error> {
+    let mut files = list_files(&directory.to_path_buf())?;
+    files.sort();
+
+    let mut pbos: Vec<IndexedPbo> = Vec::new();
+    for path in files {
+        if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pbo")) {
+            continue;
+        }
+
+        let (prefix, names) = read_entry_names(&mut BufReader::new(File::open(&path)?))
+            .prepend_error(format!("Failed to read {}:", path.display()))?;
+        // Files with paths leaving their folder can't be resolved
+        let Ok(prefix) = InternalPath::new(&prefix.unwrap_or_default()) else { continue };
+        pbos.push(IndexedPbo {
+            prefix,
+            entries: names.into_iter().filter_map(|n| Some((InternalPath::new(&n).ok()?, n))).collect(),
+            path,
+        });
+    }
+
+    // Longest prefix first, the sort above keeps equal prefixes in path order
+    pbos.sort_by_key(|p| std::cmp::Reverse(p.prefix.as_str().len()));
+    Ok(pbos)
+}
+
+impl VirtualFilesystem {
+    /// Creates a filesystem of the PBOs in the given directories and their subdirectories. Nothing
+    /// is read until the first path is resolved.
+    pub fn new(directories: Vec<PathBuf>) -> VirtualFilesystem {
+        VirtualFilesystem {
+            directories,
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn index(&self, directory: &Path) -> Result<Arc<Vec<IndexedPbo>>, Error> {
+        let mut indexes = self.indexes.lock().unwrap();
+        if let Some(index) = indexes.get(directory) {
+            return Ok(index.clone());
+        }
+
+        let index = Arc::new(index_directory(directory).prepend_error(format!("Failed to index {}:", directory.display()))?);
+        indexes.insert(directory.to_path_buf(), index.clone());
+        Ok(index)
+    }
+
+    /// Returns the PBO and entry an engine-style path like `\a3\weapons_f\config.bin` refers to,
+    /// ignoring case, or `None` if no PBO contains it.
+    pub fn resolve(&self, path: &str) -> Result<Option<VirtualEntry>, Error> {
+        // A path leaving its folder can't be in any PBO
+        let Ok(path) = InternalPath::new(path) else { return Ok(None) };
+
+        for directory in &self.directories {
+            for pbo in self.index(directory)?.iter() {
+                if let Some(entry) = path.strip_prefix(&pbo.prefix).and_then(|n| pbo.entries.get(&n)) {
+                    return Ok(Some(VirtualEntry {
+                        pbo: pbo.path.clone(),
+                        entry: entry.clone(),
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the file at the engine-style path.
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let entry = self.resolve(path)?.ok_or_else(|| coded_error!("E0312", "\"{}\" isn't in any PBO of the mod directories.", path))?;
+        let mut input = BufReader::new(File::open(&entry.pbo)?);
+        read_entry(&mut input, &entry.entry).prepend_error(format!("Failed to read {}:", entry.pbo.display()))?
+            .ok_or_else(|| coded_error!("E0311", "{} no longer has an entry \"{}\".", entry.pbo.display(), entry.entry))
+    }
+
+    /// Reads the config at the engine-style path, like `config::load_from_pbo`.
+    pub fn load_config(&self, path: &str) -> Result<Config, Error> {
+        match self.resolve(path)? {
+            Some(entry) => config::load_from_pbo(&entry.pbo, &entry.entry),
+            None => Err(coded_error!("E0312", "\"{}\" isn't in any PBO of the mod directories.", path))
+        }
+    }
+}
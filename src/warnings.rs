@@ -7,20 +7,38 @@ static WARNING_STATE: OnceLock<Arc<Mutex<WarningState>>> = OnceLock::new();
 #[derive(Debug)]
 struct WarningState {
     maximum: u32,
+    /// Maximums of single warning types, used instead of `maximum`
+    maximums: HashMap<String, u32>,
     raised: HashMap<String, u32>,
     muted: HashSet<String>,
+    /// Whether `raise_warning_at` suppresses repeats, see `set_warning_deduplication`
+    deduplicate: bool,
+    /// Warnings raised in the current scope, as (name, file, line, message)
+    locations: HashSet<(String, String, u32, String)>,
+    /// Repeats at an already seen location per warning type
+    duplicates: HashMap<String, u32>,
 }
 
 impl Default for WarningState {
     fn default() -> Self {
         Self {
             maximum: 10,
+            maximums: HashMap::new(),
             raised: HashMap::new(),
             muted: HashSet::new(),
+            deduplicate: false,
+            locations: HashSet::new(),
+            duplicates: HashMap::new(),
         }
     }
 }
 
+impl WarningState {
+    fn maximum_for(&self, name: &str) -> u32 {
+        self.maximums.get(name).copied().unwrap_or(self.maximum)
+    }
+}
+
 /// Get or initialize the global warning state
 fn get_state() -> Arc<Mutex<WarningState>> {
     WARNING_STATE.get_or_init(|| Arc::new(Mutex::new(WarningState::default()))).clone()
@@ -40,6 +58,26 @@ pub fn get_warnings_maximum() -> u32 {
     state.lock().map(|s| s.maximum).unwrap_or(10)
 }
 
+/// Set the maximum number of warnings of one type, which takes precedence over the one set with
+/// `set_warnings_maximum`
+pub fn set_warning_maximum_for(name: impl Into<String>, maximum: u32) {
+    let state = get_state();
+    if let Ok(mut state) = state.lock() {
+        state.maximums.insert(name.into(), maximum);
+    }
+}
+
+/// Show every warning of one type, whatever the global maximum is
+pub fn set_warning_unlimited(name: impl Into<String>) {
+    set_warning_maximum_for(name, u32::MAX);
+}
+
+/// Get the maximum set for one warning type, `None` if it uses the global maximum
+pub fn get_warning_maximum_for(name: &str) -> Option<u32> {
+    let state = get_state();
+    state.lock().ok().and_then(|s| s.maximums.get(name).copied())
+}
+
 /// Add a warning type to the muted set
 pub fn mute_warning(name: impl Into<String>) {
     let state = get_state();
@@ -70,19 +108,72 @@ pub fn raise_warning(name: impl Into<String>) -> bool {
         *count += 1;
 
         // Return true if we should show this warning (not exceeded maximum)
-        *count <= state.maximum
+        *count <= state.maximum_for(&name)
     } else {
         true // Show warning if we can't get the lock
     }
 }
 
+/// Suppress repeats of the same warning at the same location with `raise_warning_at`. Off by
+/// default.
+pub fn set_warning_deduplication(enabled: bool) {
+    let state = get_state();
+    if let Ok(mut state) = state.lock() {
+        state.deduplicate = enabled;
+    }
+}
+
+/// Record a warning being raised at a line of a file. With deduplication enabled, repeats of the
+/// same message at a location already seen in the current scope, e.g. from an include shared by
+/// several targets, return false and are counted as duplicates instead of towards the maximum.
+pub fn raise_warning_at(name: impl Into<String>, file: impl Into<String>, line: u32, message: impl Into<String>) -> bool {
+    let name = name.into();
+    let state = get_state();
+
+    if let Ok(mut state) = state.lock() {
+        if state.muted.contains(&name) {
+            return false; // Warning is muted
+        }
+        if state.deduplicate && !state.locations.insert((name.clone(), file.into(), line, message.into())) {
+            *state.duplicates.entry(name).or_insert(0) += 1;
+            return false;
+        }
+    }
+
+    raise_warning(name)
+}
+
+/// Start a new scope for `raise_warning_at`, after which warnings at locations seen before are
+/// shown again. Counts and duplicates so far are kept.
+pub fn begin_warning_scope() {
+    let state = get_state();
+    if let Ok(mut state) = state.lock() {
+        state.locations.clear();
+    }
+}
+
+/// Get the number of suppressed repeats at the same location per warning type, sorted by name
+pub fn get_duplicate_summary() -> Vec<(String, u32)> {
+    let state = get_state();
+    if let Ok(state) = state.lock() {
+        let mut summary: Vec<(String, u32)> = state.duplicates.iter()
+            .filter(|(name, _)| !state.muted.contains(*name))
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+        summary.sort();
+        summary
+    } else {
+        Vec::new()
+    }
+}
+
 /// Check if a warning has exceeded the maximum
 pub fn has_exceeded_maximum(name: &str) -> bool {
     let state = get_state();
     state.lock()
         .map(|s| {
             let count = s.raised.get(name).copied().unwrap_or(0);
-            count > s.maximum
+            count > s.maximum_for(name)
         })
         .unwrap_or(false)
 }
@@ -95,29 +186,44 @@ pub fn get_warning_count(name: &str) -> u32 {
         .unwrap_or(0)
 }
 
-/// Get a summary of all warnings that exceeded the maximum
+/// Get the number of warnings raised so far, of all types that aren't muted
+pub fn get_total_warning_count() -> u32 {
+    let state = get_state();
+    state.lock()
+        .map(|s| s.raised.values().sum())
+        .unwrap_or(0)
+}
+
+/// Get a summary of all warnings that exceeded their maximum, as (name, raised, suppressed) sorted
+/// by name
 pub fn get_warning_summary() -> Vec<(String, u32, u32)> {
     let state = get_state();
     if let Ok(state) = state.lock() {
-        state.raised.iter()
+        let mut summary: Vec<(String, u32, u32)> = state.raised.iter()
             .filter(|(name, count)| {
-                !state.muted.contains(*name) && **count > state.maximum
+                !state.muted.contains(*name) && **count > state.maximum_for(name)
             })
             .map(|(name, count)| {
-                let excess = *count - state.maximum;
+                let excess = *count - state.maximum_for(name);
                 (name.clone(), *count, excess)
             })
-            .collect()
+            .collect();
+        summary.sort();
+        summary
     } else {
         Vec::new()
     }
 }
 
-/// Clear all warning state
+/// Clear all warning state, including the maximums of single types. The global maximum and
+/// whether warnings are deduplicated are kept.
 pub fn clear_warnings() {
     let state = get_state();
     if let Ok(mut state) = state.lock() {
         state.raised.clear();
         state.muted.clear();
+        state.maximums.clear();
+        state.locations.clear();
+        state.duplicates.clear();
     }
-}
\ No newline at end of file
+}
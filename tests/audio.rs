@@ -0,0 +1,158 @@
+use std::fs::{create_dir_all, write};
+use std::process::{Command};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use tempfile::{tempdir};
+
+use armake2::audio::*;
+
+/// An Ogg page with a correct checksum
+fn ogg_page(flags: u8, granule: i64, sequence: u32, packets: &[&[u8]]) -> Vec<u8> {
+    let mut segments: Vec<u8> = Vec::new();
+    for packet in packets {
+        segments.extend(std::iter::repeat_n(255, packet.len() / 255));
+        segments.push((packet.len() % 255) as u8);
+    }
+
+    let mut page: Vec<u8> = b"OggS\0".to_vec();
+    page.push(flags);
+    page.write_i64::<LittleEndian>(granule).unwrap();
+    page.write_u32::<LittleEndian>(0x1234).unwrap();
+    page.write_u32::<LittleEndian>(sequence).unwrap();
+    page.write_u32::<LittleEndian>(0).unwrap();
+    page.push(segments.len() as u8);
+    page.extend_from_slice(&segments);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// An Ogg Vorbis file whose packets are only the headers and filler
+fn ogg(channels: u8, sample_rate: u32, frames: i64) -> Vec<u8> {
+    let mut identification: Vec<u8> = b"\x01vorbis\0\0\0\0".to_vec();
+    identification.push(channels);
+    identification.write_u32::<LittleEndian>(sample_rate).unwrap();
+    identification.extend_from_slice(&[0; 12]);
+    identification.extend_from_slice(&[0xb8, 1]);
+
+    let mut data = ogg_page(2, 0, 0, &[&identification]);
+    data.extend(ogg_page(0, 0, 1, &[b"\x03vorbis", b"\x05vorbis"]));
+    data.extend(ogg_page(4, frames, 2, &[&[0; 300]]));
+    data
+}
+
+fn wave_format(channels: u16, sample_rate: u32) -> Vec<u8> {
+    let mut format: Vec<u8> = Vec::new();
+    format.write_u16::<LittleEndian>(1).unwrap();
+    format.write_u16::<LittleEndian>(channels).unwrap();
+    format.write_u32::<LittleEndian>(sample_rate).unwrap();
+    format.write_u32::<LittleEndian>(sample_rate * u32::from(channels) * 2).unwrap();
+    format.write_u16::<LittleEndian>(channels * 2).unwrap();
+    format.write_u16::<LittleEndian>(16).unwrap();
+    format
+}
+
+/// A 16-bit PCM WAV file whose data chunk claims `length` bytes
+fn wav(channels: u16, sample_rate: u32, length: u32, samples: &[u8]) -> Vec<u8> {
+    let mut data: Vec<u8> = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+    data.extend(wave_format(channels, sample_rate));
+    data.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+    data.extend_from_slice(b"data");
+    data.write_u32::<LittleEndian>(length).unwrap();
+    data.extend_from_slice(samples);
+    data
+}
+
+fn wss(compression: u32, channels: u16, sample_rate: u32, samples: &[u8]) -> Vec<u8> {
+    let mut data: Vec<u8> = b"WSS0".to_vec();
+    data.write_u32::<LittleEndian>(compression).unwrap();
+    data.extend(wave_format(channels, sample_rate));
+    data.write_u16::<LittleEndian>(0).unwrap();
+    data.extend_from_slice(samples);
+    data
+}
+
+#[test]
+fn test_audio_ogg() {
+    // The CRC-32 of Ogg has no initial value or final XOR
+    assert_eq!(0x89a1_897f, ogg_crc(b"123456789"));
+
+    let info = AudioInfo::read(AudioFormat::Ogg, &ogg(2, 44100, 88200)).unwrap();
+    assert_eq!(AudioInfo { format: AudioFormat::Ogg, channels: 2, sample_rate: 44100, frames: 88200 }, info);
+    assert!(check(AudioFormat::Ogg, &ogg(1, 48000, 10)).is_empty());
+
+    assert_eq!(vec![AudioProblem::Channels(6), AudioProblem::SampleRate(22050)], check(AudioFormat::Ogg, &ogg(6, 22050, 10)));
+    assert_eq!(vec![AudioProblem::Empty], check(AudioFormat::Ogg, &ogg(2, 44100, 0)));
+
+    // Corrupt bytes fail the checksum, missing ones the page lengths
+    let mut corrupt = ogg(2, 44100, 10);
+    corrupt[40] ^= 1;
+    assert_eq!("audio-invalid", check(AudioFormat::Ogg, &corrupt)[0].warning_name());
+    let complete = ogg(2, 44100, 10);
+    assert_eq!("audio-truncated", check(AudioFormat::Ogg, &complete[..complete.len() - 1])[0].warning_name());
+    let last_page = complete.len() - ogg_page(4, 10, 2, &[&[0; 300]]).len();
+    assert_eq!(vec![AudioProblem::Truncated("the stream has no last page".to_string())], check(AudioFormat::Ogg, &complete[..last_page]));
+
+    let opus = ogg_page(6, 0, 0, &[b"OpusHead\x01\x02\0\0\x80\xbb\0\0\0\0\0"]);
+    assert_eq!(vec![AudioProblem::Invalid("the stream isn't Vorbis".to_string())], check(AudioFormat::Ogg, &opus));
+    assert_eq!("audio-invalid", check(AudioFormat::Ogg, b"RIFF")[0].warning_name());
+}
+
+#[test]
+fn test_audio_wav() {
+    let info = AudioInfo::read(AudioFormat::Wav, &wav(2, 48000, 8, &[0; 8])).unwrap();
+    assert_eq!((2, 48000, 2), (info.channels, info.sample_rate, info.frames));
+    assert!(check(AudioFormat::Wav, &wav(1, 44100, 4, &[0; 4])).is_empty());
+
+    assert_eq!(vec![AudioProblem::SampleRate(8000), AudioProblem::Empty], check(AudioFormat::Wav, &wav(1, 8000, 0, &[])));
+    assert_eq!(vec![AudioProblem::Truncated("the data chunk has 4 of 8 bytes".to_string())], check(AudioFormat::Wav, &wav(2, 44100, 8, &[0; 4])));
+    let header = wav(2, 44100, 8, &[]);
+    assert_eq!(vec![AudioProblem::Truncated("there is no data chunk".to_string())], check(AudioFormat::Wav, &header[..header.len() - 8]));
+    assert_eq!("audio-invalid", check(AudioFormat::Wav, &wav(0, 44100, 4, &[0; 4]))[0].warning_name());
+}
+
+#[test]
+fn test_audio_wss() {
+    let info = AudioInfo::read(AudioFormat::Wss, &wss(0, 2, 44100, &[0; 16])).unwrap();
+    assert_eq!((AudioFormat::Wss, 2, 44100, 4), (info.format, info.channels, info.sample_rate, info.frames));
+    assert_eq!(8, AudioInfo::read(AudioFormat::Wss, &wss(4, 1, 44100, &[0; 8])).unwrap().frames);
+
+    assert_eq!(vec![AudioProblem::Channels(4)], check(AudioFormat::Wss, &wss(8, 4, 48000, &[0; 16])));
+    assert_eq!(vec![AudioProblem::Empty], check(AudioFormat::Wss, &wss(0, 1, 44100, &[])));
+    assert_eq!(vec![AudioProblem::Truncated("the last sample is incomplete".to_string())], check(AudioFormat::Wss, &wss(0, 2, 44100, &[0; 6])));
+    assert_eq!(vec![AudioProblem::Truncated("the header is incomplete".to_string())], check(AudioFormat::Wss, &wss(0, 2, 44100, &[])[..20]));
+    assert_eq!(vec![AudioProblem::Invalid("unknown compression 3".to_string())], check(AudioFormat::Wss, &wss(3, 2, 44100, &[0; 4])));
+}
+
+#[test]
+fn test_audio_check_pack() {
+    assert_eq!(Some(AudioFormat::Ogg), AudioFormat::from_path("sounds\\Shot.OGG"));
+    assert_eq!(None, AudioFormat::from_path("sounds\\shot.paa"));
+
+    let dir = tempdir().unwrap();
+    let addon = dir.path().join("addon");
+    create_dir_all(addon.join("sounds")).unwrap();
+    write(addon.join("sounds").join("good.ogg"), ogg(2, 44100, 100)).unwrap();
+    write(addon.join("sounds").join("surround.ogg"), ogg(6, 44100, 100)).unwrap();
+    write(addon.join("sounds").join("cut.wav"), wav(1, 44100, 100, &[0; 10])).unwrap();
+    write(addon.join("sounds").join("radio.wss"), wss(4, 1, 22050, &[0; 10])).unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+
+    let output = armake2(&["pack", "--check-audio", "addon", "addon.pbo"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(3, stderr.matches("[audio-").count(), "{}", stderr);
+    assert!(stderr.contains("sounds\\surround.ogg") && stderr.contains("[audio-channels]"), "{}", stderr);
+    assert!(stderr.contains("sounds\\cut.wav") && stderr.contains("[audio-truncated]"), "{}", stderr);
+    assert!(stderr.contains("sounds\\radio.wss") && stderr.contains("[audio-sample-rate]"), "{}", stderr);
+    assert!(!stderr.contains("good.ogg"), "{}", stderr);
+
+    // Only on request
+    let output = armake2(&["pack", "addon", "unchecked.pbo"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("[audio-"));
+}
@@ -0,0 +1,119 @@
+use std::fs::{File, create_dir_all, read_to_string, write};
+use std::process::{Command};
+
+use tempfile::{tempdir};
+
+use armake2::buildreport::*;
+use armake2::pbo::*;
+
+#[test]
+fn test_build_report() {
+    let dir = tempdir().unwrap();
+    let addon = dir.path().join("addon");
+    create_dir_all(&addon).unwrap();
+    write(addon.join("config.cpp"), "class CfgPatches { class addon { units[] = {}; }; };\nvalue = abc;\n").unwrap();
+    write(addon.join("data.txt"), "hello").unwrap();
+    let broken = dir.path().join("broken");
+    create_dir_all(&broken).unwrap();
+    write(broken.join("config.cpp"), "class CfgPatches {\n").unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+    assert!(armake2(&["keygen", "release"]).status.success());
+
+    let output = armake2(&["build", "-k", "release.biprivatekey", "--report", "report.json", "addon", "addon.pbo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_str(&read_to_string(dir.path().join("report.json")).unwrap()).unwrap();
+    assert_eq!(FORMAT_VERSION as u64, report["version"].as_u64().unwrap());
+    let pbo = &report["pbos"][0];
+    let checksum = PBO::read(&mut File::open(dir.path().join("addon.pbo")).unwrap()).unwrap().checksum.unwrap();
+    assert_eq!(("built", "release"), (pbo["status"].as_str().unwrap(), pbo["authority"].as_str().unwrap()));
+    assert_eq!(checksum.iter().map(|b| format!("{:02x}", b)).collect::<String>(), pbo["sha1"].as_str().unwrap());
+    assert_eq!(dir.path().join("addon.pbo").metadata().unwrap().len(), pbo["size"].as_u64().unwrap());
+    assert_eq!((2, 1, 0, 1), (pbo["files"].as_u64().unwrap(), pbo["configs"].as_u64().unwrap(),
+        pbo["textures"].as_u64().unwrap(), pbo["warnings"].as_u64().unwrap()));
+    assert!(pbo.get("error").is_none());
+
+    let output = armake2(&["build", "--no-cache", "--report", "report.md", "--report-format", "md", "addon", "unsigned.pbo"]);
+    assert!(output.status.success());
+    let markdown = read_to_string(dir.path().join("report.md")).unwrap();
+    assert!(markdown.starts_with("| PBO | Size | SHA-1 | Signed by | Files | Configs | Textures | Warnings |\n|---|"), "{}", markdown);
+    assert!(markdown.contains("| unsigned.pbo | "), "{}", markdown);
+    assert!(markdown.lines().nth(2).unwrap().ends_with(" |  | 2 | 1 | 0 | 1 |"), "{}", markdown);
+
+    // Failed builds are reported and still fail
+    let output = armake2(&["build", "--report", "failed.toml", "--report-format", "toml", "broken", "broken.pbo"]);
+    assert!(!output.status.success());
+    let report: toml::Value = toml::from_str(&read_to_string(dir.path().join("failed.toml")).unwrap()).unwrap();
+    let pbo = &report["pbos"].as_array().unwrap()[0];
+    assert_eq!("failed", pbo["status"].as_str().unwrap());
+    assert!(pbo["error"].as_str().unwrap().contains("Failed to parse config"), "{}", pbo["error"]);
+    assert!(pbo.get("sha1").is_none());
+
+    assert!(!armake2(&["build", "--report", "stdout.json", "addon"]).status.success());
+    assert!(!armake2(&["build", "--report", "x.json", "--report-format", "xml", "addon", "xml.pbo"]).status.success());
+}
+
+#[test]
+fn test_build_report_all() {
+    let dir = tempdir().unwrap();
+    for name in ["a", "c"] {
+        create_dir_all(dir.path().join("addons").join(name)).unwrap();
+        write(dir.path().join("addons").join(name).join("config.cpp"), format!("class CfgPatches {{ class {} {{ units[] = {{}}; }}; }};\nvalue = abc;\n", name)).unwrap();
+    }
+    create_dir_all(dir.path().join("addons/b")).unwrap();
+    write(dir.path().join("addons/b/config.cpp"), "class CfgPatches {\n").unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+    let statuses = |report: &serde_json::Value| report["pbos"].as_array().unwrap().iter()
+        .map(|pbo| (pbo["target"].as_str().unwrap().replace('\\', "/"), pbo["status"].as_str().unwrap().to_string(), pbo["warnings"].as_u64().unwrap()))
+        .collect::<Vec<_>>();
+
+    // Every PBO is reported with the warnings raised for it, also when built in parallel
+    let output = armake2(&["build", "--no-cache", "--all", "--keep-going", "--report", "all.json", "addons", "out"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("1 of 3 PBOs failed to build. [E0829]") && stderr.contains("Failed to build addons"), "{}", stderr);
+    let report: serde_json::Value = serde_json::from_str(&read_to_string(dir.path().join("all.json")).unwrap()).unwrap();
+    assert_eq!(vec![
+        ("out/a.pbo".to_string(), "built".to_string(), 1),
+        ("out/b.pbo".to_string(), "failed".to_string(), 0),
+        ("out/c.pbo".to_string(), "built".to_string(), 1),
+    ], statuses(&report));
+    assert!(dir.path().join("out/c.pbo").exists());
+    // The failed PBO isn't left half written
+    assert_eq!(2, std::fs::read_dir(dir.path().join("out")).unwrap().count());
+
+    // Without --keep-going, PBOs after the failed one aren't built
+    let output = armake2(&["build", "--no-cache", "--threads", "1", "--all", "--report", "stopped.json", "addons", "stopped"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_str(&read_to_string(dir.path().join("stopped.json")).unwrap()).unwrap();
+    assert_eq!(vec![
+        ("stopped/a.pbo".to_string(), "built".to_string(), 1),
+        ("stopped/b.pbo".to_string(), "failed".to_string(), 0),
+    ], statuses(&report));
+    assert!(!dir.path().join("stopped/c.pbo").exists());
+
+    assert!(!armake2(&["build", "--all", "addons"]).status.success());
+    assert!(!armake2(&["build", "--all", "-s", "one.bisign", "addons", "signed"]).status.success());
+
+    // Folders that only differ in case would write the same PBO on Windows and macOS
+    create_dir_all(dir.path().join("cased/main")).unwrap();
+    create_dir_all(dir.path().join("cased/Main")).unwrap();
+    if std::fs::read_dir(dir.path().join("cased")).unwrap().count() == 2 {
+        let output = armake2(&["build", "--no-cache", "--all", "cased", "cased_out"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("[E0825]") && stderr.contains("cased_out"), "{}", stderr);
+        assert!(!dir.path().join("cased_out").exists());
+    }
+}
+
+#[test]
+fn test_build_report_markdown_failures() {
+    let failed = PboReport::failed("a".as_ref(), "out/a|b.pbo".as_ref(), &std::io::Error::other("Failed to write PBO:\nNo space left"), 3);
+    let mut output: Vec<u8> = Vec::new();
+    BuildReport::new(vec![failed]).write(ReportFormat::Markdown, &mut output).unwrap();
+    let markdown = String::from_utf8(output).unwrap();
+    assert!(markdown.contains("\n| a\\|b.pbo |  | **failed** |  | 0 | 0 | 0 | 3 |\n"), "{}", markdown);
+    assert!(markdown.ends_with("\na|b.pbo failed:\n\n```\nFailed to write PBO:\nNo space left\n```\n"), "{}", markdown);
+}
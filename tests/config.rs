@@ -1,6 +1,7 @@
 use std::io::{Cursor, Seek, SeekFrom};
 
 use armake2::config::*;
+use armake2::error::*;
 
 #[test]
 fn config_read() {
@@ -46,3 +47,698 @@ class CfgPatches {
     };
 };", output.trim());
 }
+
+#[test]
+fn config_lint_types() {
+    let lint = TypeLint::default();
+    let mut warnings: Vec<(usize, String, Option<&'static str>)> = Vec::new();
+
+    lint.check(&ConfigName::new("reloadTime"), &ConfigEntry::StringEntry("0.1".to_string()), 1, &mut warnings);
+    lint.check(&ConfigName::new("reloadTime"), &ConfigEntry::FloatEntry(0.1), 2, &mut warnings);
+    lint.check(&ConfigName::new("displayName"), &ConfigEntry::IntEntry(1), 3, &mut warnings);
+    lint.check(&ConfigName::new("displayName"), &ConfigEntry::StringEntry("1".to_string()), 4, &mut warnings);
+    lint.check(&ConfigName::new("versionStr"), &ConfigEntry::StringEntry("3.5.0.0".to_string()), 5, &mut warnings);
+    lint.check(&ConfigName::new("name"), &ConfigEntry::StringEntry("inf".to_string()), 6, &mut warnings);
+
+    let found: Vec<(usize, Option<&'static str>)> = warnings.iter().map(|w| (w.0, w.2)).collect();
+    assert_eq!(vec![(1, Some("string-looks-numeric")), (3, Some("number-in-string-property"))], found);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("properties.txt");
+    std::fs::write(&path, "// custom\n\nmyLabel\n").unwrap();
+
+    let mut lint = TypeLint::default();
+    lint.extend_from_file(&path).unwrap();
+
+    warnings.clear();
+    lint.check(&ConfigName::new("MYLABEL"), &ConfigEntry::FloatEntry(3.0), 7, &mut warnings);
+    assert_eq!(1, warnings.len());
+
+    let options = ParseOptions { type_lint: Some(lint), ..Default::default() };
+    let input = "class CfgWeapons { class Rifle { displayName = 5; reloadTime = \"0.1\"; }; };";
+    let config = Config::read_with_options(&mut Cursor::new(input), None, &Vec::new(), &options).unwrap();
+    assert!(config.to_string().unwrap().contains("reloadTime = \"0.1\";"));
+}
+
+#[test]
+fn config_warning_locations() {
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("locations.cpp");
+    let input = "first = abc;\n#include \"locations.h\"\nlast = xyz;\n";
+    std::fs::write(&main, input).unwrap();
+    std::fs::write(dir.path().join("locations.h"), "value = def;\nother = ghi;\n").unwrap();
+
+    collect_diagnostics(true);
+    Config::read(&mut Cursor::new(input), Some(main), &Vec::new()).unwrap();
+    let diagnostics: Vec<CollectedDiagnostic> = take_diagnostics().into_iter()
+        .filter(|d| d.file.as_deref().is_some_and(|f| f.contains("locations.")))
+        .collect();
+    collect_diagnostics(false);
+
+    // The first line of the input and of the include are reported where they were written
+    let locations: Vec<(String, u32)> = diagnostics.iter()
+        .map(|d| (d.file.as_deref().unwrap().rsplit(['/', '\\']).next().unwrap().to_string(), d.line.unwrap()))
+        .collect();
+    assert_eq!(vec![
+        ("locations.cpp".to_string(), 1),
+        ("locations.h".to_string(), 1),
+        ("locations.h".to_string(), 2),
+        ("locations.cpp".to_string(), 3),
+    ], locations, "{:?}", diagnostics);
+}
+
+#[test]
+fn config_session() {
+    use std::fs::{File, write};
+    use std::time::{Duration, SystemTime};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("config.cpp");
+    let outer = dir.path().join("outer.hpp");
+    let inner = dir.path().join("inner.hpp");
+    write(&outer, "#include \"inner.hpp\"\n").unwrap();
+    write(&inner, "#define VALUE 1\n").unwrap();
+
+    let text = "#include \"outer.hpp\"\nvalue = VALUE;\n";
+    write(&root, text).unwrap();
+    let session = ConfigSession::new(Vec::new(), ParseOptions::default());
+
+    let config = session.parse(text, Some(root.clone())).unwrap();
+    assert_eq!("value = 1;\n", config.to_string().unwrap());
+    assert_eq!(SessionStats { include_hits: 0, include_misses: 2, root_hits: 0, root_misses: 1 }, session.stats());
+
+    session.parse(text, Some(root.clone())).unwrap();
+    assert_eq!(1, session.stats().root_hits);
+
+    // Changing the nested include invalidates the root, but the outer include stays cached
+    write(&inner, "#define VALUE 2\n").unwrap();
+    File::options().write(true).open(&inner).unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+
+    let config = session.parse(text, Some(root.clone())).unwrap();
+    assert_eq!("value = 2;\n", config.to_string().unwrap());
+    assert_eq!(SessionStats { include_hits: 1, include_misses: 3, root_hits: 1, root_misses: 2 }, session.stats());
+
+    std::thread::scope(|s| {
+        for i in 0..4 {
+            let session = &session;
+            let origin = dir.path().join(format!("root{}.cpp", i));
+            write(&origin, text).unwrap();
+            s.spawn(move || {
+                let config = session.parse(text, Some(origin)).unwrap();
+                assert_eq!("value = 2;\n", config.to_string().unwrap());
+            });
+        }
+    });
+    assert_eq!(6, session.stats().root_misses);
+
+    // Creating a file that was probed for without being found invalidates the root too
+    let probing = dir.path().join("probing.cpp");
+    let text = "#if __has_include(\"optional.hpp\")\nvalue = 1;\n#else\nvalue = 0;\n#endif\n";
+    write(&probing, text).unwrap();
+    assert_eq!("value = 0;\n", session.parse(text, Some(probing.clone())).unwrap().to_string().unwrap());
+    write(dir.path().join("optional.hpp"), "").unwrap();
+    assert_eq!("value = 1;\n", session.parse(text, Some(probing.clone())).unwrap().to_string().unwrap());
+}
+
+#[test]
+fn config_duplicate_classes() {
+    let input = "\
+class CfgPatches { class first_addon { units[] = {}; }; };
+class CfgPatches { class second_addon { units[] = {}; }; };
+class CfgVehicles {
+    class Car;
+    class Car { maxSpeed = 100; displayName = \"Car\"; class Turrets { class MainTurret { gun = 1; }; }; };
+    class Car { maxSpeed = 120; class Turrets { class MainTurret { optics = 1; }; }; };
+};";
+
+    let config = Config::read(&mut Cursor::new(input), None, &Vec::new()).unwrap();
+    assert_eq!("class CfgPatches {
+    class first_addon {
+        units[] = {};
+    };
+    class second_addon {
+        units[] = {};
+    };
+};
+class CfgVehicles {
+    class Car {
+        maxSpeed = 120;
+        displayName = \"Car\";
+        class Turrets {
+            class MainTurret {
+                gun = 1;
+                optics = 1;
+            };
+        };
+    };
+};", config.to_string().unwrap().trim());
+
+    let options = ParseOptions { strict_duplicates: true, ..Default::default() };
+    let forward = "class Base; class Base { x = 1; };";
+    assert!(Config::read_with_options(&mut Cursor::new(forward), None, &Vec::new(), &options).is_ok());
+
+    let err = Config::read_with_options(&mut Cursor::new(input), None, &Vec::new(), &options).unwrap_err();
+    assert!(err.to_string().contains("Class \"CfgPatches\" is already defined in the same scope at line 1"));
+
+    // Every class of a large scope is merged into its own first definition
+    let many: String = (0..1000).map(|i| format!("class C{0} {{ a = 1; }};\nclass c{0} {{ b = 2; }};\n", i)).collect();
+    let config = Config::read(&mut Cursor::new(many), None, &Vec::new()).unwrap();
+    let entries = config.root().entries().unwrap();
+    assert_eq!(1000, entries.len());
+    let ConfigEntry::ClassEntry(last) = &entries[999].1 else { panic!() };
+    assert_eq!(("C999", vec!["a", "b"]), (entries[999].0.as_str(), last.entries().unwrap().iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>()));
+}
+
+#[test]
+fn config_case_insensitive_names() {
+    let mut names = std::collections::HashSet::new();
+    assert!(names.insert(ConfigName::new("CfgPatches")));
+    assert!(!names.insert(ConfigName::new("cfgPATCHES")));
+    assert!(names.contains(&ConfigName::new("cfgpatches")));
+    assert_eq!("CfgPatches", names.iter().next().unwrap().to_string());
+    assert!(ConfigName::new("bus") < ConfigName::new("Car"));
+
+    let input = "\
+class CfgPatches { class my_addon { units[] = {\"my_car\"}; }; };
+class cfgPatches { class other_addon {}; };
+class CfgVehicles {
+    class Car;
+    class car { maxSpeed = 100; };
+    class My_Car: CAR {};
+};";
+    let config = Config::read(&mut Cursor::new(input), None, &Vec::new()).unwrap();
+    assert_eq!("class CfgPatches {
+    class my_addon {
+        units[] = {\"my_car\"};
+    };
+    class other_addon {};
+};
+class CfgVehicles {
+    class Car {
+        maxSpeed = 100;
+    };
+    class My_Car: CAR {};
+};", config.to_string().unwrap().trim());
+    assert!(config.missing_cfgpatches_entries().iter().all(|m| m.class != "My_Car"));
+    let (name, _) = &config.root().entries().unwrap()[0];
+    assert!(*name == ConfigName::new("cfgpatches"));
+    assert_eq!("CfgPatches", name.as_str());
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.cpp"), input).unwrap();
+    let rapify = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+            .args(args).args(["-f", "config.cpp", "config.bin"]).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+    let stderr = rapify(&["rapify"]);
+    assert!(stderr.contains("config.cpp:2: warning: Class \"cfgPatches\" is spelled \"CfgPatches\" at config.cpp:1."), "{}", stderr);
+    assert!(stderr.contains("config.cpp:5: warning: Class \"car\" is spelled \"Car\" at config.cpp:4."), "{}", stderr);
+    assert_eq!(2, stderr.matches("[class-case-mismatch]").count(), "{}", stderr);
+    assert!(!rapify(&["rapify", "-w", "class-case-mismatch"]).contains("class-case-mismatch"));
+}
+
+#[test]
+fn config_source_map() {
+    let input = String::from("\
+class CfgPatches {
+    class my_addon {
+        units[] = {\"a\", \"b\"};
+        requiredVersion = 1.56;
+    };
+};
+class CfgVehicles {
+    class Car;
+    class MyCar: Car {
+        displayName = \"My Car\";
+        class Empty {};
+    };
+};
+version = 3;
+");
+    let config = Config::from_string(input, None, &Vec::new()).unwrap();
+    let rapified = config.to_cursor().unwrap().into_inner();
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut map: Vec<u8> = Vec::new();
+    cmd_derapify_with_source_map(&mut Cursor::new(&rapified), &mut output, &mut map, StringEncoding::Utf8).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    let map = String::from_utf8(map).unwrap();
+    assert_eq!(10, map.lines().count());
+    assert!(map.starts_with("{\"line\":1,\"offset\":18,\"path\":\"CfgPatches\"}\n"));
+
+    let derapified = Config::read_rapified(&mut Cursor::new(&rapified)).unwrap();
+    for entry in derapified.source_map() {
+        let line = lines[entry.line - 1].trim();
+        let name = entry.path.rsplit('/').next().unwrap();
+        let expected_type = match line.split_whitespace().next().unwrap() {
+            "class" if !line.ends_with("{") && !line.ends_with("{};") => 3,
+            "class" => 0,
+            _ if line.contains("[]") => 2,
+            _ => 1,
+        };
+
+        assert!(line.contains(name), "line {} \"{}\" doesn't match {}", entry.line, line, entry.path);
+        assert_eq!(expected_type, rapified[entry.offset as usize], "wrong entry type at {} for {}", entry.offset, entry.path);
+        assert_eq!(&name.as_bytes()[..], &rapified[(entry.offset as usize + if expected_type == 1 { 2 } else { 1 })..][..name.len()]);
+    }
+
+    let mut value: Vec<u8> = Vec::new();
+    cmd_config_get(&mut Cursor::new(&rapified), &mut value, "cfgvehicles>>MyCar>>displayName", true).unwrap();
+    let value = String::from_utf8(value).unwrap();
+    let offset: usize = value.lines().next().unwrap().trim_start_matches("// offset ").parse().unwrap();
+    assert_eq!("displayName = \"My Car\";", value.lines().nth(1).unwrap());
+    assert_eq!(1, rapified[offset]);
+
+    let mut value: Vec<u8> = Vec::new();
+    cmd_config_get(&mut Cursor::new(b"a = 1;".to_vec()), &mut value, "a", false).unwrap();
+    assert_eq!("a = 1;\n", String::from_utf8(value).unwrap());
+    assert!(cmd_config_get(&mut Cursor::new(b"a = 1;".to_vec()), &mut Vec::new(), "a", true).is_err());
+    assert!(cmd_config_get(&mut Cursor::new(&rapified), &mut Vec::new(), "CfgPatches/missing", false).is_err());
+}
+
+fn string_value(config: &Config, name: &str) -> String {
+    match config.root().get(name) {
+        Some(ConfigEntry::StringEntry(s)) => s.clone(),
+        e => panic!("{} is not a string: {:?}", name, e),
+    }
+}
+
+#[test]
+fn config_string_roundtrip() {
+    let input = String::from("\
+cyrillic = \"Автомат Калашникова\";
+cjk = \"日本語のテキスト\";
+degrees = \"25°C\";
+quotes = \"He said \"\"hi\"\"\";
+newline = \"first\" \\n \"second\";
+literal = \"not\\na newline\";
+array[] = {\"Дом\", \"a \"\"b\"\"\", \"x\" \\n \"y\"};
+");
+
+    let config = Config::from_string(input, None, &Vec::new()).unwrap();
+    assert_eq!("Автомат Калашникова", string_value(&config, "cyrillic"));
+    assert_eq!("He said \"hi\"", string_value(&config, "quotes"));
+    assert_eq!("first\nsecond", string_value(&config, "newline"));
+    assert_eq!("not\\na newline", string_value(&config, "literal"));
+
+    let text = config.to_string().unwrap();
+    assert!(text.contains("newline = \"first\" \\n \"second\";\n"));
+    assert!(text.contains("literal = \"not\\na newline\";\n"));
+
+    let rapified = config.to_cursor().unwrap().into_inner();
+    let derapified = Config::read_rapified(&mut Cursor::new(&rapified)).unwrap();
+    assert_eq!(text, derapified.to_string().unwrap());
+
+    let reparsed = Config::from_string(derapified.to_string().unwrap(), None, &Vec::new()).unwrap();
+    for name in &["cyrillic", "cjk", "degrees", "quotes", "newline", "literal"] {
+        assert_eq!(string_value(&config, name), string_value(&reparsed, name));
+    }
+    assert_eq!(text, reparsed.to_string().unwrap());
+}
+
+#[test]
+fn config_string_encoding() {
+    let config = Config::from_string(String::from("name = \"Дом 25°\";"), None, &Vec::new()).unwrap();
+
+    let mut rapified: Vec<u8> = Vec::new();
+    config.write_rapified_with_encoding(&mut rapified, StringEncoding::Windows1251).unwrap();
+    assert!(rapified.windows(7).any(|w| w == [0xC4, 0xEE, 0xEC, 0x20, 0x32, 0x35, 0xB0]));
+
+    let derapified = Config::read_rapified_with_encoding(&mut Cursor::new(&rapified), StringEncoding::Windows1251).unwrap();
+    assert_eq!("Дом 25°", string_value(&derapified, "name"));
+    assert!(Config::read_rapified(&mut Cursor::new(&rapified)).is_err());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_derapify(&mut Cursor::new(&rapified), &mut output, parse_string_encoding("CP-1251").unwrap()).unwrap();
+    assert_eq!("name = \"Дом 25°\";\n", String::from_utf8(output).unwrap());
+
+    let cjk = Config::from_string(String::from("name = \"日本\";"), None, &Vec::new()).unwrap();
+    assert!(cjk.write_rapified_with_encoding(&mut Vec::new(), StringEncoding::Windows1252).is_err());
+    assert!(parse_string_encoding("latin9").is_err());
+}
+
+#[test]
+fn config_ignored_directives() {
+    let rapify = |input: &str, options: &ParseOptions| -> Result<Vec<u8>, std::io::Error> {
+        let config = Config::read_with_options(&mut Cursor::new(input.to_string()), None, &Vec::new(), options)?;
+        Ok(config.to_cursor()?.into_inner().to_vec())
+    };
+    let expected = rapify("class CfgPatches { class a { x = 1; y[] = {2}; }; };", &ParseOptions::default()).unwrap();
+
+    let patterns = [
+        "#pragma hemtt suppress pw3",
+        "    #pragma warning(disable: 1)",
+        "#line 12 \"addons\\main\\config.cpp\"",
+        "__EXEC(_count = 1)",
+        "__EXEC(_name = \"a(b\"; _x = (1 + 2));",
+        "__EVAL(_count + 1);",
+    ];
+    for pattern in &patterns {
+        let input = format!("class CfgPatches {{\n{0}\n    class a {{\n{0}\n        x = 1;\n{0}\n        y[] = {{2}};\n    }};\n}};\n{0}\n", pattern);
+        assert_eq!(expected, rapify(&input, &ParseOptions::default()).unwrap(), "{}", pattern);
+
+        let strict = ParseOptions { strict_directives: true, ..Default::default() };
+        assert!(rapify(&input, &strict).is_err(), "{}", pattern);
+    }
+
+    // Known directives are still handled by the preprocessor
+    let input = "#ifdef MISSING\n#pragma never\n#endif\nclass CfgPatches { class a { x = 1; y[] = {2}; }; };";
+    assert_eq!(expected, rapify(input, &ParseOptions { strict_directives: true, ..Default::default() }).unwrap());
+}
+
+#[test]
+fn config_missing_cfgpatches_entries() {
+    let input = String::from("\
+class CfgPatches {
+    class main {
+        units[] = {\"listed_car\"};
+        requiredVersion = 2.1;
+        requiredAddons[] = {\"A3_Data_F\"};
+    };
+};
+class CfgVehicles {
+    class Car;
+    class Listed_Car: Car {};
+    class new_car: Car {};
+    delete old_car;
+};
+class CfgWeapons {
+    class Rifle_Base_F;
+    class new_rifle: Rifle_Base_F { scope = 2; };
+};
+class cfgMagazines {
+    class new_mag { count = 30; };
+};
+");
+    let mut config = Config::from_string(input, None, &Vec::new()).unwrap();
+
+    let missing = config.missing_cfgpatches_entries();
+    let names: Vec<(&str, &str)> = missing.iter().map(|m| (m.array.as_str(), m.class.as_str())).collect();
+    assert_eq!(vec![("units", "new_car"), ("weapons", "new_rifle"), ("magazines", "new_mag")], names);
+
+    assert_eq!(missing, config.add_missing_cfgpatches_entries());
+    assert!(config.missing_cfgpatches_entries().is_empty());
+    assert!(config.add_missing_cfgpatches_entries().is_empty());
+
+    let array = |path: &str| -> Vec<String> {
+        match config.find(path) {
+            Some((ConfigEntry::ArrayEntry(a), _)) => a.elements().iter().map(|e| match e {
+                ConfigArrayElement::StringElement(s) => s.clone(),
+                _ => panic!("{}", path)
+            }).collect(),
+            _ => panic!("{}", path)
+        }
+    };
+    assert_eq!(vec!["listed_car", "new_car"], array("CfgPatches/main/units"));
+    assert_eq!(vec!["new_rifle"], array("CfgPatches/main/weapons"));
+    assert_eq!(vec!["new_mag"], array("CfgPatches/main/magazines"));
+    assert_eq!(vec!["A3_Data_F"], array("CfgPatches/main/requiredAddons"));
+    assert!(matches!(config.find("CfgPatches/main/requiredVersion"), Some((ConfigEntry::FloatEntry(_), _))));
+
+    // Without a CfgPatches class there is nowhere to add them
+    let mut config = Config::from_string(String::from("class CfgVehicles { class new_car {}; };"), None, &Vec::new()).unwrap();
+    assert_eq!(1, config.missing_cfgpatches_entries().len());
+    assert!(config.add_missing_cfgpatches_entries().is_empty());
+}
+
+#[test]
+fn test_config_invalid_constructs() {
+    let parse = |input: &str| Config::from_string(String::from(input), None, &[]).unwrap_err().to_string();
+
+    let error = parse("class CfgWeapons {\n    class rifle {\n        magazines[0] = \"x\";\n    };\n};\n");
+    assert!(error.starts_with("In line 3:\n\n  magazines[0] = \"x\";\n            "), "{}", error);
+    assert!(error.ends_with("Indexed array assignment \"magazines[0]\" is not valid config syntax, use \"magazines[] = {...}\" instead."), "{}", error);
+    assert!(parse("a[ 12 ] += {1};").contains("\"a[12]\""));
+
+    let error = parse("class CfgWeapons {\n    items[] = {1, class Foo { x = 1; }};\n};\n");
+    assert!(error.starts_with("In line 2:\n\n  items[] = {1, class Foo { x = 1; }};\n                "), "{}", error);
+    assert!(error.ends_with("Classes cannot appear inside arrays, define the class outside of the array instead."), "{}", error);
+    assert!(parse("a[] = {{class Foo: Bar {}}};").contains("Classes cannot appear inside arrays"));
+
+    // Other errors still list the expected tokens, and words that just look like classes are strings
+    assert!(parse("class a { x = 1 }; }").contains("expected"));
+    let config = Config::from_string(String::from("a[] = {class, classy};"), None, &[]).unwrap();
+    assert_eq!("a[] = {\"class\", \"classy\"};\n", config.to_string().unwrap());
+}
+
+#[test]
+fn test_config_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("inc")).unwrap();
+    std::fs::write(dir.path().join("config.cpp"), "\
+#define VERSION 2
+class CfgPatches {
+    #include \"patches.hpp\"
+};
+value = VERSION;
+").unwrap();
+    std::fs::write(dir.path().join("patches.hpp"), "\
+class my_addon {
+  units[] = {};
+  #include \"inc\\deps.hpp\"
+  };
+").unwrap();
+    std::fs::write(dir.path().join("inc").join("deps.hpp"), "\
+requiredAddons[]={\"A3_Data_F\"};
+#include \"version.hpp\"
+").unwrap();
+    std::fs::write(dir.path().join("inc").join("version.hpp"), "\
+#ifdef VERSION
+class Version { major = VERSION; };
+#else
+class Version { major = 0; };
+#endif
+").unwrap();
+
+    let path = dir.path().join("config.cpp");
+    let mut output: Vec<u8> = Vec::new();
+    cmd_config_bundle(&mut std::fs::File::open(&path).unwrap(), &mut output, Some(path.clone()), &[], &ParseOptions::default()).unwrap();
+    assert_eq!("\
+class CfgPatches {
+    // Begin of patches.hpp
+    class my_addon {
+        units[] = {};
+        // Begin of inc/deps.hpp
+        requiredAddons[] = {\"A3_Data_F\"};
+        // Begin of inc/version.hpp
+        class Version {
+            major = 2;
+        };
+        // End of inc/version.hpp
+        // End of inc/deps.hpp
+    };
+    // End of patches.hpp
+};
+value = 2;
+", String::from_utf8(output).unwrap());
+
+    // Parse errors point to the included file
+    std::fs::write(dir.path().join("inc").join("deps.hpp"), "requiredAddons[]={\"A3_Data_F\"};\nclass {};\n").unwrap();
+    let error = cmd_config_bundle(&mut std::fs::File::open(&path).unwrap(), &mut Vec::new(), Some(path.clone()), &[], &ParseOptions::default()).unwrap_err().to_string();
+    assert!(error.contains("deps.hpp"), "{}", error);
+}
+
+#[test]
+fn test_rapify_share_classes() {
+    let mut input = String::from("class CfgVehicles {\n");
+    for i in 0..200 {
+        input += &format!("    class Vehicle{} {{ scope = 2; displayName = \"Vehicle {}\"; class Turrets {{ class MainTurret {{ \
+            gunnerName = \"Commander of the main turret\"; weapons[] = {{\"LongWeaponClassName_01\", \"LongWeaponClassName_02\"}}; \
+            maxElev = {}; }}; }}; }};\n", i, i, if i % 2 == 0 { "0.3" } else { "0.300001" });
+    }
+    input += "};\n";
+    let config = Config::from_string(input, None, &Vec::new()).unwrap();
+
+    let mut plain: Vec<u8> = Vec::new();
+    config.write_rapified(&mut plain).unwrap();
+    assert_eq!(plain.len(), config.rapified_size(StringEncoding::Utf8));
+
+    let mut shared: Vec<u8> = Vec::new();
+    let options = RapifyOptions { share_classes: true, ..Default::default() };
+    let size = config.write_rapified_with_options(&mut shared, &options).unwrap();
+    assert_eq!(size, shared.len());
+    assert!(shared.len() < plain.len() * 2 / 3, "{} of {} bytes", shared.len(), plain.len());
+
+    let expected = Config::read_rapified(&mut Cursor::new(&plain)).unwrap().to_string().unwrap();
+    assert_eq!(expected, Config::read_rapified(&mut Cursor::new(&shared)).unwrap().to_string().unwrap());
+
+    // The two float values only differ in the sixth digit, rounding them lets all turrets be shared
+    let mut quantized: Vec<u8> = Vec::new();
+    let options = RapifyOptions { share_classes: true, quantize_floats: Some(5), ..Default::default() };
+    config.write_rapified_with_options(&mut quantized, &options).unwrap();
+    assert!(quantized.len() < shared.len());
+
+    let derapified = Config::read_rapified(&mut Cursor::new(&quantized)).unwrap().to_string().unwrap();
+    assert!(!derapified.contains("0.300001"));
+    assert_eq!(expected.replace("0.300001", "0.3"), derapified);
+
+    // Comparing classes doesn't warn about their floats
+    let config = Config::from_string("class A { class B { x = -0.0; }; }; class C { class B { x = -0.0; }; };".to_string(), None, &Vec::new()).unwrap();
+    let raised = armake2::warnings::get_warning_count("special-float");
+    let options = RapifyOptions { share_classes: true, ..Default::default() };
+    config.write_rapified_with_options(&mut Vec::new(), &options).unwrap();
+    assert_eq!(raised, armake2::warnings::get_warning_count("special-float"));
+}
+
+#[test]
+fn test_quantize_float() {
+    assert_eq!(1.23, quantize_float(1.234_567_9, 3));
+    assert_eq!(12300.0, quantize_float(12345.0, 3));
+    assert_eq!(-0.000_123, quantize_float(-0.000_123_45, 3));
+    assert_eq!(0.3, quantize_float(0.300_001, 5));
+    assert_eq!(0.0, quantize_float(0.0, 2));
+    assert!(quantize_float(f32::NAN, 2).is_nan());
+}
+
+#[test]
+fn test_config_external_classes() {
+    let input = "\
+class CfgVehicles {
+    class Vehicle;
+    class Car: Vehicle { maxSpeed = 100; };
+    class Tank: Car {
+        class Turrets: Turrets {};
+    };
+    class Plane: Air {};
+    delete Old;
+};
+class CfgWeapons {
+    class Rifle: Rifle_Base_F {};
+};
+";
+    let config = Config::read(&mut Cursor::new(input), None, &Vec::new()).unwrap();
+    let class = |config: &Config, path: &str| match config.find(path) {
+        Some((ConfigEntry::ClassEntry(c), _)) => c.clone(),
+        _ => panic!("{} is not a class", path)
+    };
+    assert!(matches!(config.find("CfgVehicles/Vehicle"), Some((ConfigEntry::ExternalClass, _))));
+    assert!(class(&config, "CfgVehicles/Car").entries().is_some());
+
+    // External classes and deletions keep their entry types when rapified
+    let mut rapified = config.to_cursor().unwrap();
+    rapified.seek(SeekFrom::Start(0)).unwrap();
+    let derapified = Config::read_rapified(&mut rapified).unwrap();
+    assert!(matches!(derapified.find("CfgVehicles/Vehicle"), Some((ConfigEntry::ExternalClass, _))));
+    assert!(class(&derapified, "CfgVehicles/Old").is_deletion());
+    let output = derapified.to_string().unwrap();
+    assert!(output.contains("    class Vehicle;\n    class Car: Vehicle {\n"), "{}", output);
+    assert!(output.contains("    delete Old;\n"), "{}", output);
+
+    // Only parents that are neither defined nor declared are reported
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.cpp"), input).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["rapify", "config.cpp", "config.bin"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(2, stderr.matches("[undefined-base-class]").count(), "{}", stderr);
+    assert!(stderr.contains("config.cpp:7: warning: Class \"Plane\" inherits from \"Air\", which isn't defined. Declare it with \"class Air;\""), "{}", stderr);
+    assert!(stderr.contains("config.cpp:11: warning: Class \"Rifle\" inherits from \"Rifle_Base_F\""), "{}", stderr);
+}
+
+#[test]
+fn test_format_float() {
+    assert_eq!("0.5", format_float(0.5));
+    assert_eq!("2.0", format_float(2.0));
+    assert_eq!("0.1", format_float(0.1));
+    assert_eq!("-1.56", format_float(-1.56));
+    assert_eq!("100000000000000000000.0", format_float(1e20));
+    assert_eq!("0.0000001", format_float(1e-7));
+    assert_eq!("-0.00000000015", format_float(-1.5e-10));
+    assert_eq!("-0.0", format_float(-0.0));
+    assert_eq!("0.0", format_float(f32::NAN));
+    assert_eq!(f32::MAX, format_float(f32::INFINITY).parse::<f32>().unwrap());
+    assert_eq!(-f32::MAX, format_float(f32::NEG_INFINITY).parse::<f32>().unwrap());
+
+    // Every finite float reads back with the same bits, and the config parser reads it as one
+    fn property(bits: u32) -> quickcheck::TestResult {
+        let value = f32::from_bits(bits);
+        if !value.is_finite() {
+            return quickcheck::TestResult::discard();
+        }
+        let text = format_float(value);
+        let config = Config::from_string(format!("x = {};", text), None, &[]).unwrap();
+        let parsed = match config.root().get("x") {
+            Some(ConfigEntry::FloatEntry(f)) => *f,
+            e => panic!("{} is read as {:?}", text, e)
+        };
+        quickcheck::TestResult::from_bool(text.contains('.') && !text.contains('e') && parsed.to_bits() == bits)
+    }
+    quickcheck::QuickCheck::new().quickcheck(property as fn(u32) -> quickcheck::TestResult);
+}
+
+#[test]
+fn test_config_number_types() {
+    let config = Config::from_string("scope = 2; mass = 2.0; a[] = {1, 1.0, -0.0, 0xFFFFFFFF}; big = 3000000000;".to_string(), None, &[]).unwrap();
+    assert!(matches!(config.root().get("scope"), Some(ConfigEntry::IntEntry(2))));
+    assert!(matches!(config.root().get("mass"), Some(ConfigEntry::FloatEntry(f)) if *f == 2.0));
+    assert!(matches!(config.root().get("big"), Some(ConfigEntry::FloatEntry(f)) if *f == 3e9));
+
+    let mut rapified: Vec<u8> = Vec::new();
+    config.write_rapified(&mut rapified).unwrap();
+    let output = Config::read_rapified(&mut Cursor::new(&rapified)).unwrap().to_string().unwrap();
+    assert_eq!("scope = 2;\nmass = 2.0;\na[] = {1, 1.0, -0.0, -1};\nbig = 3000000000.0;\n", output);
+}
+
+#[test]
+fn test_config_number_roundtrip() {
+    // Rapifying the derapified text gives back the same binary
+    fn property(ints: Vec<i32>, floats: Vec<u32>) -> bool {
+        let floats: Vec<f32> = floats.into_iter().map(f32::from_bits).filter(|f| f.is_finite()).collect();
+        let mut source = String::from("class A {\n");
+        for (i, value) in ints.iter().enumerate() {
+            source.push_str(&format!("    i{} = {};\n", i, value));
+        }
+        for (i, value) in floats.iter().enumerate() {
+            source.push_str(&format!("    f{} = {};\n", i, format_float(*value)));
+        }
+        source.push_str(&format!("    a[] = {{{}}};\n}};\n", ints.iter().map(|i| i.to_string()).chain(floats.iter().map(|f| format_float(*f))).collect::<Vec<_>>().join(", ")));
+
+        let mut first: Vec<u8> = Vec::new();
+        Config::from_string(source, None, &[]).unwrap().write_rapified(&mut first).unwrap();
+        let derapified = Config::read_rapified(&mut Cursor::new(&first)).unwrap().to_string().unwrap();
+        let mut second: Vec<u8> = Vec::new();
+        Config::from_string(derapified, None, &[]).unwrap().write_rapified(&mut second).unwrap();
+        first == second
+    }
+    quickcheck::QuickCheck::new().quickcheck(property as fn(Vec<i32>, Vec<u32>) -> bool);
+}
+
+#[test]
+fn test_config_special_floats() {
+    let mut rapified: Vec<u8> = Vec::new();
+    Config::from_string("a = 1.0; b = 2.0; c[] = {3.0};".to_string(), None, &[]).unwrap().write_rapified(&mut rapified).unwrap();
+    for (value, special) in [(1.0f32, f32::NAN), (2.0, f32::INFINITY), (3.0, -0.0)] {
+        let position = rapified.windows(4).position(|w| w == value.to_le_bytes()).unwrap();
+        rapified[position..position + 4].copy_from_slice(&special.to_le_bytes());
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.bin"), &rapified).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["derapify", "config.bin", "config.cpp"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(3, stderr.matches("[special-float]").count(), "{}", stderr);
+    assert!(stderr.contains("Value of \"a\" is NaN, written as 0.0."), "{}", stderr);
+    let derapified = std::fs::read_to_string(dir.path().join("config.cpp")).unwrap();
+    assert!(derapified.starts_with("a = 0.0;\nb = 340282350000000000000000000000000000000.0;\nc[] = {-0.0};\n"), "{}", derapified);
+}
+
+#[test]
+fn test_config_read_rapified_entry_types() {
+    // Root class with `class Vehicle;` (type 3), `delete Old;` (type 4) and `list[] += {1};` (type 5)
+    let mut rapified: Vec<u8> = b"\0raP\0\0\0\0\x08\0\0\0".to_vec();
+    let body: &[u8] = b"\0\x03\x03Vehicle\0\x04Old\0\x05\x01\0\0\0list\0\x01\x02\x01\0\0\0";
+    rapified.extend(&(16 + body.len() as u32).to_le_bytes());
+    rapified.extend(body);
+    rapified.extend(&[0; 4]);
+
+    let config = Config::read_rapified(&mut Cursor::new(rapified)).unwrap();
+    assert_eq!("class Vehicle;\ndelete Old;\nlist[] += {1};\n", config.to_string().unwrap());
+}
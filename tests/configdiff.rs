@@ -0,0 +1,270 @@
+use std::fs::{write};
+use std::io::{Cursor};
+use std::process::{Command};
+
+use tempfile::{tempdir};
+
+use armake2::config::*;
+use armake2::configdiff::*;
+
+fn config(input: &str) -> Config {
+    Config::from_string(String::from(input), None, &[]).unwrap()
+}
+
+#[test]
+fn test_config_diff() {
+    let old = config("
+        class CfgVehicles {
+            class Car {
+                maxSpeed = 100;
+                displayName = \"Car\";
+                hiddenSelections[] = {\"camo1\", \"camo2\"};
+                sounds[] = {1, 2, 3, 4, 5, 6, 7, 8};
+                class Wheels {};
+            };
+            class Old {};
+        };
+    ");
+    let new = config("
+        class cfgvehicles {
+            class Truck: Car {};
+            class Car {
+                sounds[] = {8, 7, 6, 5, 4, 3, 2, 1};
+                hiddenSelections[] = {\"camo1\", \"camo3\", \"camo4\"};
+                DISPLAYNAME = \"Car\";
+                maxSpeed = 120;
+                armor = 50;
+                wheels = 4;
+            };
+        };
+    ");
+
+    // Order and case of names don't matter
+    assert!(diff_configs(&old, &old, false).is_empty());
+
+    let changes = diff_configs(&old, &new, false);
+    assert_eq!(vec![
+        ConfigChange::ClassAdded { path: "cfgvehicles/Truck".to_string() },
+        ConfigChange::ArrayChanged { path: "cfgvehicles/Car/sounds".to_string(), old_length: 8, new_length: 8 },
+        ConfigChange::ElementChanged { path: "cfgvehicles/Car/hiddenSelections".to_string(), index: 1, old: Some("\"camo2\"".to_string()), new: Some("\"camo3\"".to_string()) },
+        ConfigChange::ElementChanged { path: "cfgvehicles/Car/hiddenSelections".to_string(), index: 2, old: None, new: Some("\"camo4\"".to_string()) },
+        ConfigChange::PropertyChanged { path: "cfgvehicles/Car/maxSpeed".to_string(), old: "100".to_string(), new: "120".to_string() },
+        ConfigChange::PropertyAdded { path: "cfgvehicles/Car/armor".to_string(), value: "50".to_string() },
+        ConfigChange::ClassRemoved { path: "cfgvehicles/Car/wheels".to_string() },
+        ConfigChange::PropertyAdded { path: "cfgvehicles/Car/wheels".to_string(), value: "4".to_string() },
+        ConfigChange::ClassRemoved { path: "cfgvehicles/Old".to_string() },
+    ], changes);
+
+    assert_eq!("~ cfgvehicles/Car/sounds: array changed (8 -> 8 elements)", changes[1].to_text());
+    assert_eq!("+ cfgvehicles/Car/hiddenSelections[2] = \"camo4\"", changes[3].to_text());
+    assert_eq!("{\"change\":\"element-changed\",\"path\":\"cfgvehicles/Car/hiddenSelections\",\"index\":2,\"old\":null,\"new\":\"\\\"camo4\\\"\"}", changes[3].to_json());
+}
+
+#[test]
+fn test_config_diff_resolve_inheritance() {
+    let old = config("
+        class CfgVehicles {
+            class Car {
+                maxSpeed = 100;
+                class Turrets {
+                    class MainTurret { gunner = 1; };
+                };
+            };
+            class Truck: Car {
+                class Turrets: Turrets {
+                    class MainTurret: MainTurret {};
+                };
+            };
+        };
+    ");
+    let new = config("
+        class CfgVehicles {
+            class Car {
+                maxSpeed = 120;
+                class Turrets {
+                    class MainTurret { gunner = 2; };
+                };
+            };
+            class Truck: Car {
+                class Turrets: Turrets {
+                    class MainTurret: MainTurret {};
+                };
+            };
+        };
+    ");
+
+    assert_eq!(2, diff_configs(&old, &new, false).len());
+
+    let changes: Vec<String> = diff_configs(&old, &new, true).iter().map(|c| c.to_text()).collect();
+    assert_eq!(vec![
+        "~ CfgVehicles/Car/maxSpeed: 100 -> 120",
+        "~ CfgVehicles/Car/Turrets/MainTurret/gunner: 1 -> 2",
+        "~ CfgVehicles/Truck/maxSpeed: 100 -> 120",
+        "~ CfgVehicles/Truck/Turrets/MainTurret/gunner: 1 -> 2",
+    ], changes);
+
+    // Rapified and text configs can be compared with each other
+    let mut rapified: Vec<u8> = Vec::new();
+    old.write_rapified(&mut rapified).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    let options = DiffOptions { resolve_inheritance: true, json: true, ..Default::default() };
+    cmd_config_diff(&mut Cursor::new(rapified), &mut Cursor::new(new.to_string().unwrap()), &mut output, &options).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(4, output.lines().count());
+    assert!(output.starts_with("{\"change\":\"changed\",\"path\":\"CfgVehicles/Car/maxSpeed\",\"old\":\"100\",\"new\":\"120\"}\n"), "{}", output);
+}
+
+#[test]
+fn test_config_diff_inheritance_case() {
+    let old = config("
+        class CfgVehicles {
+            class Car { maxSpeed = 100; class turrets { class MainTurret { gunner = 1; }; }; };
+            class Truck: car {
+                class Turrets: TURRETS { class mainturret: MAINTURRET {}; };
+            };
+        };
+    ");
+    let new = config("
+        class cfgvehicles {
+            class CAR { MaxSpeed = 120; class Turrets { class mainTurret { gunner = 2; }; }; };
+            class Truck: Car {
+                class turrets: Turrets { class MainTurret: mainturret {}; };
+            };
+        };
+    ");
+
+    // Parents are found whatever case they are declared and referenced in, and names keep the
+    // spelling of the new config
+    let changes: Vec<String> = diff_configs(&old, &new, true).iter().map(|c| c.to_text()).collect();
+    assert_eq!(vec![
+        "~ cfgvehicles/CAR/MaxSpeed: 100 -> 120",
+        "~ cfgvehicles/CAR/Turrets/mainTurret/gunner: 1 -> 2",
+        "~ cfgvehicles/Truck/MaxSpeed: 100 -> 120",
+        "~ cfgvehicles/Truck/turrets/MainTurret/gunner: 1 -> 2",
+    ], changes);
+}
+
+#[test]
+fn test_config_diff_external_classes() {
+    let old = config("
+        class CfgVehicles {
+            class Vehicle;
+            class Car: Vehicle { maxSpeed = 100; };
+        };
+    ");
+    let new = config("
+        class CfgVehicles {
+            class Vehicle { armor = 20; };
+            class Car: Vehicle { maxSpeed = 100; };
+        };
+    ");
+
+    // Inheritance stops at the declaration, whose entries are unknown
+    assert!(diff_configs(&old, &old, true).is_empty());
+
+    let changes: Vec<String> = diff_configs(&old, &new, true).iter().map(|c| c.to_text()).collect();
+    assert_eq!(vec![
+        "? CfgVehicles/Vehicle/armor: unknown — external parent \"Vehicle\" -> 20",
+        "? CfgVehicles/Car/armor: unknown — external parent \"Vehicle\" -> 20",
+    ], changes);
+
+    let changes: Vec<String> = diff_configs(&new, &old, true).iter().map(|c| c.to_text()).collect();
+    assert_eq!(vec![
+        "? CfgVehicles/Vehicle/armor: 20 -> unknown — external parent \"Vehicle\"",
+        "? CfgVehicles/Car/armor: 20 -> unknown — external parent \"Vehicle\"",
+    ], changes);
+    assert_eq!(
+        "{\"change\":\"unknown\",\"path\":\"CfgVehicles/Car/armor\",\"external_parent\":\"Vehicle\",\"old\":null,\"new\":\"20\"}",
+        diff_configs(&old, &new, true)[1].to_json());
+
+    // Without resolving inheritance only the declaration itself changed
+    let changes: Vec<String> = diff_configs(&old, &new, false).iter().map(|c| c.to_text()).collect();
+    assert_eq!(vec!["+ CfgVehicles/Vehicle/armor = 20"], changes);
+}
+
+#[test]
+fn test_config_diff_ignore() {
+    let old = config("
+        class CfgVehicles {
+            class Car { dlc = \"Old\"; maxSpeed = 100; class Wheels { count = 4; }; };
+            class Tank { DLC = \"Old\"; armor = 500; };
+        };
+    ");
+    let new = config("
+        class CfgVehicles {
+            class Car { dlc = \"New\"; maxSpeed = 120; class Wheels { count = 6; }; };
+            class Tank { DLC = \"New\"; armor = 600; author = \"me\"; };
+        };
+    ");
+
+    let changes = |patterns: &[&str]| -> Vec<String> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        diff_configs_ignoring(&old, &new, false, &patterns).iter().map(|c| c.to_text()).collect()
+    };
+
+    // Names match in every class, ignoring case
+    assert_eq!(vec![
+        "~ CfgVehicles/Car/maxSpeed: 100 -> 120",
+        "~ CfgVehicles/Car/Wheels/count: 4 -> 6",
+        "~ CfgVehicles/Tank/armor: 500 -> 600",
+        "+ CfgVehicles/Tank/author = \"me\"",
+    ], changes(&["dlc"]));
+    assert_eq!(6, changes(&[]).len());
+    assert_eq!(vec!["~ CfgVehicles/Tank/armor: 500 -> 600"], changes(&["dlc", "cfgvehicles/car", "Author"]));
+
+    // Matching a class skips everything in it, whatever it contains
+    assert_eq!(vec!["~ CfgVehicles/Car/dlc: \"Old\" -> \"New\"", "~ CfgVehicles/Car/maxSpeed: 100 -> 120"], changes(&["Wheels", "CfgVehicles/Tank"]));
+    assert!(changes(&["CfgVehicles"]).is_empty());
+    assert!(changes(&["*"]).is_empty());
+    assert_eq!(vec!["~ CfgVehicles/Car/Wheels/count: 4 -> 6"], changes(&["cfgvehicles/*/dlc", "CfgVehicles/*/maxSpeed", "CfgVehicles/Tank"]));
+
+    assert!(is_ignored("CfgVehicles/Car/dlc", &["DLC".to_string()]));
+    assert!(!is_ignored("CfgVehicles/Car/dlcs", &["dlc".to_string()]));
+    assert!(!is_ignored("CfgVehicles/Car", &["CfgVehicles/*/dlc".to_string()]));
+}
+
+#[test]
+fn test_config_diff_color() {
+    let old = config("class A { x = 1; y = 2; };");
+    let new = config("class A { x = 3; z = 4; };");
+    let changes = diff_configs(&old, &new, false);
+
+    colored::control::set_override(true);
+    let lines: Vec<String> = changes.iter().map(|c| c.to_colored_text()).collect();
+    colored::control::unset_override();
+
+    assert_eq!("\x1b[33m~ A/x:\x1b[0m \x1b[31m1\x1b[0m \x1b[33m->\x1b[0m \x1b[32m3\x1b[0m", lines[0]);
+    assert_eq!("\x1b[32m+ A/z = 4\x1b[0m", lines[1]);
+    assert_eq!("\x1b[31m- A/y = 2\x1b[0m", lines[2]);
+
+    // Without color the output is the same as the plain text
+    let mut output: Vec<u8> = Vec::new();
+    cmd_config_diff(&mut Cursor::new(old.to_string().unwrap()), &mut Cursor::new(new.to_string().unwrap()), &mut output, &DiffOptions::default()).unwrap();
+    assert_eq!("~ A/x: 1 -> 3\n+ A/z = 4\n- A/y = 2\n", String::from_utf8(output).unwrap());
+}
+
+#[test]
+fn test_config_diff_quiet() {
+    let dir = tempdir().unwrap();
+    write(dir.path().join("old.cpp"), "class A { x = 1; dlc = \"a\"; };\n").unwrap();
+    write(dir.path().join("same.cpp"), "class a { dlc = \"a\"; X = 1; };\n").unwrap();
+    write(dir.path().join("new.cpp"), "class A { x = 1; dlc = \"b\"; };\n").unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+    let quiet = |args: &[&str]| {
+        let output = armake2(&[&["configdiff", "--quiet"], args].concat());
+        assert!(output.stdout.is_empty() && output.stderr.is_empty(), "{:?}", output);
+        output.status.code().unwrap()
+    };
+
+    assert_eq!(0, quiet(&["old.cpp", "same.cpp"]));
+    assert_eq!(1, quiet(&["old.cpp", "new.cpp"]));
+    assert_eq!(0, quiet(&["--ignore-property", "DLC", "old.cpp", "new.cpp"]));
+    assert_eq!(2, quiet(&["old.cpp", "missing.cpp"]));
+
+    // Without --quiet, differences are printed and not an error
+    let output = armake2(&["configdiff", "old.cpp", "new.cpp"]);
+    assert!(output.status.success());
+    assert_eq!("~ A/dlc: \"a\" -> \"b\"\n", String::from_utf8_lossy(&output.stdout));
+}
@@ -0,0 +1,134 @@
+use std::fs::{write};
+use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+
+use tempfile::{tempdir};
+
+use armake2::config::*;
+use armake2::detect::*;
+use armake2::paa::*;
+use armake2::pbo::*;
+use armake2::sign::*;
+
+fn bytes<F: FnOnce(&mut Cursor<Vec<u8>>)>(f: F) -> Vec<u8> {
+    let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    f(&mut cursor);
+    cursor.into_inner()
+}
+
+#[test]
+fn test_detect_formats() {
+    let config = Config::from_string("foo = 1;".to_string(), None, &Vec::new()).unwrap();
+    assert_eq!(FileKind::RapifiedConfig, FileKind::detect(&config.to_cursor().unwrap().into_inner()));
+
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(8, 8, image::Rgba([0, 0, 0, 255])));
+    for paa_type in &[PaaType::DXT1, PaaType::DXT5] {
+        let paa = bytes(|c| Paa::from_image(&img, *paa_type, false).unwrap().write(c).unwrap());
+        assert_eq!(FileKind::Paa(*paa_type), FileKind::detect(&paa));
+    }
+
+    let dir = tempdir().unwrap();
+    write(dir.path().join("script.sqf"), "hint \"hi\";").unwrap();
+    let pbo = PBO::from_directory(PathBuf::from(dir.path()), false, &Vec::new(), &Vec::new()).unwrap();
+    let pbo_bytes = pbo.to_cursor().unwrap().into_inner();
+    assert_eq!(FileKind::Pbo, FileKind::detect(&pbo_bytes));
+
+    // PBO without header extensions, starting directly with a file entry
+    let mut old_pbo = b"script.sqf\0".to_vec();
+    old_pbo.extend(&[0; 16]);
+    old_pbo.extend(&[0; 21]);
+    assert_eq!(FileKind::Pbo, FileKind::detect(&old_pbo));
+
+    assert_eq!(FileKind::Mlod, FileKind::detect(b"MLOD\x01\x01\x00\x00"));
+    assert_eq!(FileKind::Odol, FileKind::detect(b"ODOL\x46\x00\x00\x00"));
+
+    let key = BIPrivateKey::generate(1024, "test".to_string());
+    let signature = key.sign(&PBO::read(&mut Cursor::new(pbo_bytes)).unwrap(), BISignVersion::V3);
+    assert_eq!(FileKind::PrivateKey, FileKind::detect(&bytes(|c| key.write(c).unwrap())));
+    assert_eq!(FileKind::PublicKey, FileKind::detect(&bytes(|c| key.to_public_key().write(c).unwrap())));
+    assert_eq!(FileKind::Signature, FileKind::detect(&bytes(|c| signature.write(c).unwrap())));
+}
+
+#[test]
+fn test_detect_unknown() {
+    assert_eq!(FileKind::Unknown, FileKind::detect(b""));
+    assert_eq!(FileKind::Unknown, FileKind::detect(b"\0"));
+    assert_eq!(FileKind::Unknown, FileKind::detect(b"class CfgPatches {};\n"));
+    assert_eq!(FileKind::Unknown, FileKind::detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+    // PAA type code without taggs
+    assert_eq!(FileKind::Unknown, FileKind::detect(b"\x05\xff\0\0\0\0\0\0"));
+    // Looks like a key name, but no key blob follows
+    assert_eq!(FileKind::Unknown, FileKind::detect(b"name\0\x94\0\0\0\x06\x02\0\0\0\x24\0\0RSA3"));
+}
+
+#[test]
+fn test_inspect_any() {
+    let dir = tempdir().unwrap();
+    let unknown = dir.path().join("notes.txt");
+    write(&unknown, "just some text").unwrap();
+    assert!(cmd_inspect_any(&unknown).is_err());
+
+    let model = dir.path().join("model.p3d");
+    write(&model, b"MLOD\x01\x01\x00\x00").unwrap();
+    assert_eq!(FileKind::Mlod, FileKind::detect_file(&model).unwrap());
+    cmd_inspect_any(&model).unwrap();
+}
+
+#[test]
+fn test_pbo_variants() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    write(source.join("script.sqf"), "hint \"hi\";").unwrap();
+    let pbo_bytes = PBO::from_directory(source, false, &Vec::new(), &Vec::new()).unwrap().to_cursor().unwrap().into_inner();
+
+    // Encrypted by the packing method of an entry, whatever the extension
+    let mut encrypted = pbo_bytes.clone();
+    let method = encrypted.windows(11).position(|w| w == b"script.sqf\0").unwrap() + 11;
+    encrypted[method..(method + 4)].copy_from_slice(b"rcnE");
+    for name in ["addons/dlc.EBO", "addons/dlc.pbo"] {
+        assert_eq!(Some("an encrypted PBO (EBO)"), encrypted_container(&encrypted));
+        let error = check_pbo_container(Path::new(name), &encrypted).unwrap_err().to_string();
+        assert_eq!(format!("{} is an encrypted PBO (EBO), which can't be read. Its content is encrypted and only the game can decrypt it.", name), error);
+    }
+    assert_eq!(None, encrypted_container(&pbo_bytes));
+    assert_eq!(None, encrypted_container(b"\0sreV\0\0\0\0"));
+    check_pbo_container(Path::new("addons/dlc.ebo"), &pbo_bytes).unwrap();
+
+    for (name, description) in [("mission.xbo", "an Xbox PBO"), ("island.ifa", "a renamed PBO (IFA)")] {
+        let path = dir.path().join(name);
+        write(&path, &pbo_bytes).unwrap();
+        check_pbo_file(&path).unwrap();
+        check_pbo_container(&path, &pbo_bytes).unwrap();
+
+        let error = check_pbo_container(&path, b"\x8f\x13\xc2\xa7\x55\x01").unwrap_err().to_string();
+        assert_eq!(format!("{} has the extension of {}, but doesn't start with a PBO header.", path.display(), description), error);
+    }
+
+    // Other extensions are left to the PBO reader
+    check_pbo_container(Path::new("addon.pbo"), b"\x8f\x13").unwrap();
+    check_pbo_container(Path::new("addon"), b"").unwrap();
+    assert_eq!(None, pbo_variant(Path::new("addon.pbo")));
+
+    let armake2 = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+    std::fs::create_dir(dir.path().join("addons")).unwrap();
+    write(dir.path().join("dlc.ebo"), &encrypted).unwrap();
+    write(dir.path().join("addons").join("dlc.pbo"), &encrypted).unwrap();
+    let output = armake2(&["unpack", "dlc.ebo", "dlc"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("dlc.ebo is an encrypted PBO (EBO)"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.path().join("dlc").exists());
+
+    // Every command reading PBOs checks them
+    for args in [&["inspect", "dlc.ebo"][..], &["unpack", "addons", "unpacked"], &["grep", "hint", "addons/dlc.pbo"],
+        &["pbodiff", "dlc.ebo", "mission.xbo", "patch.bin"], &["verify", "missing.bikey", "dlc.ebo"]] {
+        let output = armake2(args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("is an encrypted PBO (EBO)"), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    }
+
+    let output = armake2(&["unpack", "mission.xbo", "mission"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[renamed-pbo]"));
+    assert!(dir.path().join("mission").join("script.sqf").exists());
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashSet};
+use std::fs::{read_dir, read_to_string, write};
+use std::io::{Cursor};
+use std::path::{PathBuf};
+use std::process::{Command};
+
+use regex::{Regex};
+use tempfile::{tempdir};
+
+use armake2::config::*;
+use armake2::diagnostics::*;
+use armake2::error::*;
+use armake2::preprocess::*;
+use armake2::coded_error;
+
+#[test]
+fn test_diagnostics_table() {
+    let format = Regex::new(r"^[EW]\d{4}$").unwrap();
+    let mut codes: HashSet<&str> = HashSet::new();
+    let mut names: HashSet<&str> = HashSet::new();
+    for diagnostic in DIAGNOSTICS {
+        assert!(format.is_match(diagnostic.code), "{}", diagnostic.code);
+        assert!(codes.insert(diagnostic.code), "{} is used twice", diagnostic.code);
+        // Warnings have names, errors don't
+        assert_eq!(diagnostic.code.starts_with('W'), diagnostic.name.is_some(), "{}", diagnostic.code);
+        if let Some(name) = diagnostic.name {
+            assert!(names.insert(name), "{} is used twice", name);
+        }
+        assert!(!diagnostic.summary.is_empty() && !diagnostic.explanation.is_empty(), "{}", diagnostic.code);
+    }
+
+    assert_eq!(Some("E0202"), by_code("e0202").map(|d| d.code));
+    assert_eq!(Some("W0101"), warning_code("unquoted-string"));
+    assert_eq!(None, warning_code("no-such-warning"));
+}
+
+#[test]
+fn test_diagnostics_every_warning_has_code() {
+    let patterns = [
+        Regex::new(r#"Some\("([a-z0-9]+(?:-[a-z0-9]+)+)"\)"#).unwrap(),
+        // Sound and model warnings are named where their problems are defined
+        Regex::new(r#""((?:audio|modelcfg)(?:-[a-z0-9]+)+)""#).unwrap(),
+    ];
+    let mut names: HashSet<String> = HashSet::new();
+    for entry in read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap() {
+        let source = read_to_string(entry.unwrap().path()).unwrap();
+        for pattern in &patterns {
+            names.extend(pattern.captures_iter(&source).map(|c| c[1].to_string()));
+        }
+    }
+
+    assert!(names.len() > 40, "{:?}", names);
+    for name in names {
+        assert!(warning_code(&name).is_some(), "warning \"{}\" has no code", name);
+    }
+}
+
+#[test]
+fn test_diagnostics_every_error_has_code() {
+    // Errors of the operating system are wrapped with prepend_error, every other one has a code
+    let uncoded = Regex::new(r"(?:^|[^_\w])error!\(").unwrap();
+    let code = Regex::new(r#"coded_error!\("([EW]\d{4})""#).unwrap();
+    let mut used: HashSet<String> = HashSet::new();
+    for entry in read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.file_name().unwrap() == "error.rs" {
+            continue;
+        }
+        let source = read_to_string(&path).unwrap();
+        for (i, line) in source.lines().enumerate() {
+            assert!(!uncoded.is_match(line), "{}:{} has an error without code: {}", path.display(), i + 1, line.trim());
+        }
+        used.extend(code.captures_iter(&source).map(|c| c[1].to_string()));
+    }
+
+    assert!(used.len() > 80, "{:?}", used);
+    for code in used {
+        assert!(by_code(&code).is_some_and(|d| d.name.is_none()), "error code {} isn't in the table", code);
+    }
+}
+
+#[test]
+fn test_diagnostics_error_codes() {
+    let error: Result<(), _> = Err(coded_error!("E0301", "Failed to read PBO."));
+    let error = error.prepend_error("Failed to unpack:").unwrap_err();
+    assert_eq!("Failed to unpack:\nFailed to read PBO.", error.to_string());
+    assert_eq!(Some("E0301"), coded(&error).map(|c| c.code));
+
+    let input = "class CfgPatches {\n    value = 1;\n}};\n";
+    let error = Config::read(&mut Cursor::new(input), None, &Vec::new()).err().unwrap();
+    let error = coded(&error).unwrap();
+    assert_eq!(("E0101", Some(3)), (error.code, error.line));
+    assert!(error.column.is_some());
+
+    let error = preprocess("#include \"missing.hpp\"\n".to_string(), None, &Vec::new()).err().unwrap();
+    assert_eq!(Some("E0202"), coded(&error).map(|c| c.code));
+}
+
+#[test]
+fn test_diagnostics_cli() {
+    let dir = tempdir().unwrap();
+    write(dir.path().join("warning.cpp"), "value = abc;\n").unwrap();
+    write(dir.path().join("include.cpp"), "#include \"missing.hpp\"\n").unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+
+    let output = armake2(&["rapify", "warning.cpp", "warning.bin"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[unquoted-string] [W0101]"));
+
+    let output = armake2(&["rapify", "include.cpp", "include.bin"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[E0202]"));
+
+    let output = armake2(&["rapify", "--message-format", "json", "warning.cpp", "warning_json.bin"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<serde_json::Value> = stderr.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let warning = lines.iter().find(|line| line["code"] == "W0101").unwrap();
+    assert_eq!(("warning", "unquoted-string", 1), (warning["level"].as_str().unwrap(), warning["name"].as_str().unwrap(), warning["line"].as_u64().unwrap()));
+    assert!(warning["file"].as_str().unwrap().ends_with("warning.cpp"));
+
+    let output = armake2(&["rapify", "--message-format", "json", "include.cpp", "include_json.bin"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(("error", "E0202"), (error["level"].as_str().unwrap(), error["code"].as_str().unwrap()));
+
+    let output = armake2(&["explain", "w0101"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("W0101: "));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("(warning \"unquoted-string\")"));
+    assert!(!armake2(&["explain", "E9999"]).status.success());
+    assert!(!armake2(&["rapify", "--message-format", "xml", "warning.cpp", "warning_xml.bin"]).status.success());
+}
+
+#[test]
+fn test_diagnostics_collected() {
+    let input = "value = abc;\nother = def;\nclass Base {};\nclass Base { x = 1; };\n";
+    collect_diagnostics(true);
+    Config::read(&mut Cursor::new(input), Some(PathBuf::from("collected.cpp")), &Vec::new()).unwrap();
+    let diagnostics: Vec<CollectedDiagnostic> = take_diagnostics().into_iter().filter(|d| d.file.as_deref() == Some("collected.cpp")).collect();
+    collect_diagnostics(false);
+
+    let unquoted: Vec<(Option<u32>, String)> = diagnostics.iter()
+        .filter(|d| d.name.as_deref() == Some("unquoted-string"))
+        .map(|d| (d.line, d.message.clone()))
+        .collect();
+    assert_eq!(2, unquoted.len(), "{:?}", diagnostics);
+    assert_eq!((Some(1), Some(2)), (unquoted[0].0, unquoted[1].0));
+    let duplicate = diagnostics.iter().find(|d| d.name.as_deref() == Some("duplicate-class-merged")).unwrap();
+    assert_eq!((Severity::Warning, Some("W0103")), (duplicate.level, duplicate.code.as_deref()));
+    assert!(duplicate.message.contains("Base") && !duplicate.message.contains("\x1b"), "{}", duplicate.message);
+
+    let json = serde_json::to_value(&diagnostics[0]).unwrap();
+    let keys: Vec<&str> = json.as_object().unwrap().keys().map(String::as_str).collect();
+    assert_eq!(vec!["level", "code", "name", "file", "line", "column", "message"], keys);
+    assert_eq!(("warning", "collected.cpp"), (json["level"].as_str().unwrap(), json["file"].as_str().unwrap()));
+}
+
+#[test]
+fn test_diagnostics_json_output() {
+    let dir = tempdir().unwrap();
+    let values: String = (0..12).map(|i| format!("value{} = abc;\n", i)).collect();
+    write(dir.path().join("warnings.cpp"), values).unwrap();
+    write(dir.path().join("include.cpp"), "#include \"missing.hpp\"\n").unwrap();
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+
+    let output = armake2(&["rapify", "--json-diagnostics", "diagnostics.json", "warnings.cpp", "warnings.bin"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("warning"), "{}", String::from_utf8_lossy(&output.stderr));
+    let diagnostics: Vec<serde_json::Value> = serde_json::from_str(&read_to_string(dir.path().join("diagnostics.json")).unwrap()).unwrap();
+    // 10 warnings are shown, the other 2 are in the summary at the end
+    assert_eq!(11, diagnostics.len());
+    assert!(diagnostics[..10].iter().all(|d| d["name"] == "unquoted-string" && d["code"] == "W0101" && d["level"] == "warning"));
+    assert_eq!((1, 10), (diagnostics[0]["line"].as_u64().unwrap(), diagnostics[9]["line"].as_u64().unwrap()));
+    assert!(diagnostics[10]["name"].is_null() && diagnostics[10]["message"].as_str().unwrap().starts_with("2 warnings of type \"unquoted-string\" were suppressed"));
+
+    // Errors end up in the array too, which is written before exiting
+    let output = armake2(&["rapify", "--json-diagnostics", "-", "include.cpp", "include.bin"]);
+    assert!(!output.status.success());
+    let diagnostics: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let error = diagnostics.last().unwrap();
+    assert_eq!(("error", "E0202"), (error["level"].as_str().unwrap(), error["code"].as_str().unwrap()));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // The output of rapify without a target goes to stdout, where the array doesn't fit in
+    let output = armake2(&["rapify", "--json-diagnostics", "-", "warnings.cpp"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("E0902"), "{}", String::from_utf8_lossy(&output.stderr));
+}
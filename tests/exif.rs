@@ -0,0 +1,123 @@
+use std::fs::{write};
+use std::io::{Cursor};
+use std::process::{Command};
+
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use tempfile::{tempdir};
+
+use armake2::exif::*;
+use armake2::io::{OutputPolicy};
+use armake2::paa::*;
+
+/// EXIF data with only the orientation tag, as a TIFF structure
+fn exif_tiff(orientation: u16, little_endian: bool) -> Vec<u8> {
+    let u16_bytes = |v: u16| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+    let u32_bytes = |v: u32| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+
+    let mut tiff: Vec<u8> = if little_endian { b"II".to_vec() } else { b"MM".to_vec() };
+    tiff.extend(u16_bytes(42));
+    tiff.extend(u32_bytes(8));
+    tiff.extend(u16_bytes(1));
+    tiff.extend(u16_bytes(0x0112));
+    tiff.extend(u16_bytes(3));
+    tiff.extend(u32_bytes(1));
+    tiff.extend(u16_bytes(orientation));
+    tiff.extend([0, 0]);
+    tiff.extend(u32_bytes(0));
+    tiff
+}
+
+/// Inserts an APPn segment after the start of image marker of a JPEG file.
+fn with_segment(jpeg: &[u8], marker: u8, segment: &[u8]) -> Vec<u8> {
+    let mut tagged = jpeg[..2].to_vec();
+    tagged.extend([0xFF, marker]);
+    tagged.extend(((segment.len() + 2) as u16).to_be_bytes());
+    tagged.extend(segment);
+    tagged.extend(&jpeg[2..]);
+    tagged
+}
+
+fn jpeg(img: &DynamicImage) -> Vec<u8> {
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    img.write_to(&mut bytes, ImageOutputFormat::Jpeg(100)).unwrap();
+    bytes.into_inner()
+}
+
+fn tagged_jpeg(img: &DynamicImage, orientation: u16) -> Vec<u8> {
+    with_segment(&jpeg(img), 0xE1, &[b"Exif\0\0".to_vec(), exif_tiff(orientation, true)].concat())
+}
+
+#[test]
+fn test_exif_orientations() {
+    let (width, height) = (2u32, 3u32);
+    let stored = DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 0, 255])));
+    let pixel = |x: u32, y: u32| Rgba([x as u8, y as u8, 0, 255]);
+
+    // Where each pixel of the upright image comes from in the stored one
+    let sources: [(bool, &dyn Fn(u32, u32) -> (u32, u32)); 8] = [
+        (false, &|x, y| (x, y)),
+        (false, &|x, y| (width - 1 - x, y)),
+        (false, &|x, y| (width - 1 - x, height - 1 - y)),
+        (false, &|x, y| (x, height - 1 - y)),
+        (true, &|x, y| (y, x)),
+        (true, &|x, y| (y, height - 1 - x)),
+        (true, &|x, y| (width - 1 - y, height - 1 - x)),
+        (true, &|x, y| (width - 1 - y, x)),
+    ];
+
+    for (i, (swapped, source)) in sources.iter().enumerate() {
+        let value = i as u16 + 1;
+        assert_eq!(Some(value), orientation(&tagged_jpeg(&stored, value)));
+        assert_eq!(Some(value), orientation(&exif_tiff(value, true)));
+        assert_eq!(Some(value), orientation(&exif_tiff(value, false)));
+
+        let upright = apply_orientation(stored.clone(), value).to_rgba8();
+        let (w, h) = if *swapped { (height, width) } else { (width, height) };
+        let expected = RgbaImage::from_fn(w, h, |x, y| {
+            let (sx, sy) = source(x, y);
+            pixel(sx, sy)
+        });
+        assert_eq!(expected, upright, "orientation {}", value);
+    }
+
+    assert_eq!(None, orientation(&jpeg(&stored)));
+    assert_eq!(None, orientation(&tagged_jpeg(&stored, 9)));
+    assert_eq!(None, orientation(b"not an image"));
+}
+
+#[test]
+fn test_img2paa_exif_orientation() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("photo.jpg");
+    let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 8, Rgba([200, 100, 50, 255])));
+    write(&source, tagged_jpeg(&img, 6)).unwrap();
+
+    for ignore_exif in [false, true] {
+        let target = dir.path().join("photo.paa");
+        let options = EncodeOptions { ignore_exif, ..Default::default() };
+        cmd_img2paa(&source, &target, Some(PaaType::DXT1), &options, false, OutputPolicy::Overwrite, None).unwrap();
+        let size = Paa::from_file(&target).unwrap().dimensions(0).unwrap();
+        assert_eq!(if ignore_exif { (16, 8) } else { (8, 16) }, size);
+    }
+
+    // PNGs have no EXIF orientation to apply
+    let png = dir.path().join("texture.png");
+    img.save(&png).unwrap();
+    let opened = open_image(&png, true).unwrap();
+    assert_eq!((16, 8), (opened.width(), opened.height()));
+}
+
+#[test]
+fn test_img2paa_icc_warning() {
+    let dir = tempdir().unwrap();
+    let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([10, 20, 30, 255])));
+    write(dir.path().join("plain.jpg"), jpeg(&img)).unwrap();
+    write(dir.path().join("profiled.jpg"), with_segment(&jpeg(&img), 0xE2, b"ICC_PROFILE\0\x01\x01fake profile")).unwrap();
+
+    for (name, warns) in [("plain", false), ("profiled", true)] {
+        let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+            .args(["img2paa", "-f", &format!("{}.jpg", name), &format!("{}.paa", name)]).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(warns, String::from_utf8_lossy(&output.stderr).contains("[icc-ignored]"), "{}", name);
+    }
+}
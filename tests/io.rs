@@ -0,0 +1,181 @@
+use std::fs::{File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use armake2::error::*;
+use armake2::io::*;
+
+fn touch(path: &Path, time: SystemTime) {
+    File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+}
+
+#[test]
+fn test_output_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.png");
+    let target = dir.path().join("target.paa");
+    std::fs::write(&source, b"source").unwrap();
+
+    assert_eq!(OutputPolicy::Fail, OutputPolicy::from_flags(false, false, false));
+    assert_eq!(OutputPolicy::Overwrite, OutputPolicy::from_flags(true, false, false));
+    assert_eq!(OutputPolicy::SkipExisting, OutputPolicy::from_flags(true, true, false));
+    assert_eq!(OutputPolicy::IfNewer, OutputPolicy::from_flags(false, false, true));
+
+    // Missing targets are written with every policy
+    for policy in [OutputPolicy::Fail, OutputPolicy::SkipExisting, OutputPolicy::IfNewer] {
+        assert!(!policy.skips(&source, &target).unwrap());
+        policy.create(&target).unwrap().unwrap().write_all(b"target").unwrap();
+        std::fs::remove_file(&target).unwrap();
+    }
+
+    std::fs::write(&target, b"target").unwrap();
+    let error = OutputPolicy::Fail.create(&target).unwrap_err();
+    assert_eq!(ErrorKind::AlreadyExists, error.kind());
+    assert!(error.to_string().contains("Use -f/--force to overwrite."), "{}", error);
+    assert_eq!(Some("E0805"), coded(&error).map(|c| c.code));
+
+    assert!(OutputPolicy::SkipExisting.skips(&source, &target).unwrap());
+    assert!(OutputPolicy::SkipExisting.create(&target).unwrap().is_none());
+    assert!(!OutputPolicy::Overwrite.skips(&source, &target).unwrap());
+    OutputPolicy::Overwrite.create(&target).unwrap().unwrap();
+    assert_eq!(0, std::fs::metadata(&target).unwrap().len());
+}
+
+#[test]
+fn test_output_policy_if_newer() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.png");
+    let target = dir.path().join("target.paa");
+    std::fs::write(&source, b"source").unwrap();
+    std::fs::write(&target, b"target").unwrap();
+
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let policy = OutputPolicy::IfNewer;
+
+    // A target written in the same instant as its source is up to date
+    touch(&source, time);
+    touch(&target, time);
+    assert!(policy.skips(&source, &target).unwrap());
+
+    touch(&target, time - Duration::from_secs(1));
+    assert!(!policy.skips(&source, &target).unwrap());
+    policy.create(&target).unwrap().unwrap();
+
+    // Folders count as modified when their newest file was
+    let folder = dir.path().join("addon");
+    std::fs::create_dir_all(folder.join("data")).unwrap();
+    std::fs::write(folder.join("data").join("texture.paa"), b"").unwrap();
+    touch(&target, SystemTime::now() + Duration::from_secs(60));
+    assert!(policy.skips(&folder, &target).unwrap());
+    touch(&folder.join("data").join("texture.paa"), SystemTime::now() + Duration::from_secs(120));
+    assert!(!policy.skips(&folder, &target).unwrap());
+}
+
+#[test]
+fn test_path_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let long = dir.path().join("a".repeat(300)).join("file.txt");
+
+    let error = check_path_length(&long).unwrap_err();
+    let unit = if cfg!(windows) { "UTF-16 units" } else { "bytes" };
+    assert!(error.to_string().contains(&format!("longer than 255 {}", unit)), "{}", error);
+    assert!(check_path_length(&dir.path().join("a".repeat(200)).join("file.txt")).is_ok());
+    // Measured in the unit of the limits, which counts 400 bytes of UTF-8 as 200 UTF-16 units
+    assert_eq!(cfg!(windows), check_path_length(&dir.path().join("ä".repeat(200)).join("file.txt")).is_ok());
+
+    // Errors name the path and why it couldn't be created
+    let error = create_file(&dir.path().join("missing").join("file.txt")).unwrap_err();
+    assert_eq!(ErrorKind::NotFound, error.kind());
+    assert!(error.to_string().contains("missing"), "{}", error);
+    assert!(error.to_string().contains("parent folder doesn't exist"), "{}", error);
+
+    assert!(create_folder(&long).unwrap_err().to_string().contains("too long"));
+    assert_eq!(dir.path(), extended_path(dir.path()));
+}
+
+#[test]
+fn test_target_collisions() {
+    let pair = |source: &str, target: &str| (PathBuf::from(source), PathBuf::from(target));
+    assert!(check_target_collisions(&[pair("a.png", "out/a.paa"), pair("b.png", "out/b.paa")]).is_ok());
+
+    let error = check_target_collisions(&[
+        pair("src/Tex.png", "out/Tex.paa"),
+        pair("src/b.png", "out/b.paa"),
+        pair("src/tex.png", "out/tex.paa"),
+        pair("src/b.tga", "out/b.paa"),
+        pair("src/TEX.jpg", "out/TEX.paa"),
+    ]).unwrap_err().to_string();
+    assert_eq!("Several files would be written to the same target, file names are compared ignoring case:\n    \
+        src/Tex.png, src/tex.png, src/TEX.jpg -> out/Tex.paa\n    src/b.png, src/b.tga -> out/b.paa", error);
+}
+
+#[test]
+fn test_output_policy_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target.paa");
+
+    // Failed writes leave nothing behind
+    let failed = OutputPolicy::Fail.write(&target, |writer| {
+        writer.write_all(b"partial")?;
+        Err(std::io::Error::other("failed"))
+    });
+    assert!(failed.is_err());
+    assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+
+    assert!(OutputPolicy::Fail.write(&target, |writer| writer.write_all(b"first")).unwrap());
+    assert_eq!(ErrorKind::AlreadyExists, OutputPolicy::Fail.write(&target, |writer| writer.write_all(b"second")).unwrap_err().kind());
+    assert!(!OutputPolicy::SkipExisting.write(&target, |writer| writer.write_all(b"second")).unwrap());
+    assert!(OutputPolicy::Overwrite.write(&target, |_| Err(std::io::Error::other("failed"))).is_err());
+    assert_eq!(b"first".to_vec(), std::fs::read(&target).unwrap());
+    assert!(OutputPolicy::Overwrite.write(&target, |writer| writer.write_all(b"second")).unwrap());
+    assert_eq!(b"second".to_vec(), std::fs::read(&target).unwrap());
+    assert_eq!(1, std::fs::read_dir(dir.path()).unwrap().count());
+
+    // A new target doesn't exist until it is complete, and one created meanwhile is kept
+    let other = dir.path().join("other.paa");
+    assert!(!OutputPolicy::SkipExisting.write(&other, |writer| {
+        assert!(!other.exists());
+        std::fs::write(&other, b"meanwhile")?;
+        writer.write_all(b"first")
+    }).unwrap());
+    assert_eq!(b"meanwhile".to_vec(), std::fs::read(&other).unwrap());
+    std::fs::remove_file(&other).unwrap();
+    assert_eq!(ErrorKind::AlreadyExists, OutputPolicy::Fail.write(&other, |writer| {
+        std::fs::write(&other, b"meanwhile")?;
+        writer.write_all(b"first")
+    }).unwrap_err().kind());
+    assert_eq!(b"meanwhile".to_vec(), std::fs::read(&other).unwrap());
+    assert_eq!(2, std::fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn test_output_policy_write_concurrently() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target.paa");
+    let target = &target;
+
+    std::thread::scope(|scope| {
+        for thread in 0..8u8 {
+            scope.spawn(move || {
+                for round in 0..20u8 {
+                    let byte = thread * 20 + round;
+                    OutputPolicy::Overwrite.write(target, |writer| {
+                        // Written in pieces so interleaved writes would mix
+                        for _ in 0..64 {
+                            writer.write_all(&[byte; 1024])?;
+                            writer.flush()?;
+                        }
+                        Ok(())
+                    }).unwrap();
+
+                    let content = std::fs::read(target).unwrap();
+                    assert_eq!(64 * 1024, content.len());
+                    assert!(content.iter().all(|b| *b == content[0]));
+                }
+            });
+        }
+    });
+
+    assert_eq!(1, std::fs::read_dir(dir.path()).unwrap().count());
+}
@@ -0,0 +1,79 @@
+use std::fs::{create_dir_all, read, write};
+use std::path::{Path};
+use std::process::{Command, Output};
+
+use tempfile::{tempdir};
+
+use armake2::legacy::*;
+
+fn args(line: &str) -> Vec<String> {
+    line.split(' ').map(String::from).collect()
+}
+
+fn armake2(dir: &Path, line: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir).args(line.split(' ')).output().unwrap()
+}
+
+#[test]
+fn test_translate_args() {
+    let unchanged = ["armake2 build -f -x *.txt addon addon.pbo", "armake2 sign key.biprivatekey addon.pbo", "armake2 binarize model.p3d out.p3d", "armake2 --version"];
+    for line in unchanged {
+        assert_eq!((args(line), Vec::new()), translate_args(args(line)).unwrap());
+    }
+
+    let translated = [
+        ("armake -v", "armake --version"),
+        ("armake build -p -i include -w unquoted-string addon addon.pbo", "armake pack -w unquoted-string addon addon.pbo"),
+        ("armake build -fp -x *.txt addon addon.pbo", "armake pack -f -x *.txt addon addon.pbo"),
+        ("armake build --packonly addon addon.pbo", "armake pack addon addon.pbo"),
+        ("armake sign -f -s addon.sig key.biprivatekey addon.pbo", "armake sign -f key.biprivatekey addon.pbo addon.sig"),
+        ("armake sign --signature=addon.sig key.biprivatekey addon.pbo", "armake sign key.biprivatekey addon.pbo addon.sig"),
+        ("armake binarize -f -i include config.cpp config.bin", "armake rapify -f -i include config.cpp config.bin"),
+    ];
+    for (line, expected) in translated {
+        let (result, notes) = translate_args(args(line)).unwrap();
+        assert_eq!(args(expected), result, "{}", line);
+        assert_eq!(1, notes.len(), "{}", line);
+    }
+
+    assert!(translate_args(args("armake unpack -x *.paa addon.pbo out")).is_err());
+    assert!(translate_args(args("armake sign -s")).is_err());
+}
+
+#[test]
+fn test_legacy_cli_matrix() {
+    let dir = tempdir().unwrap();
+    create_dir_all(dir.path().join("addon")).unwrap();
+    write(dir.path().join("addon/script.sqf"), b"hint \"legacy\";").unwrap();
+    write(dir.path().join("config.cpp"), b"class CfgPatches { class addon { units[] = {}; requiredVersion = 1.0; }; };").unwrap();
+    assert!(armake2(dir.path(), "keygen key").status.success());
+
+    // (original spelling, armake2 spelling, file both have to write identically)
+    let matrix = [
+        ("build -p addon legacy.pbo", "pack addon new.pbo", "{}.pbo"),
+        ("binarize config.cpp legacy.bin", "rapify config.cpp new.bin", "{}.bin"),
+        ("sign -s legacy.bisign key.biprivatekey new.pbo", "sign key.biprivatekey new.pbo new.bisign", "{}.bisign"),
+    ];
+    for (legacy, new, file) in matrix {
+        let old_output = armake2(dir.path(), legacy);
+        assert_eq!(Some(0), old_output.status.code(), "{}: {}", legacy, String::from_utf8_lossy(&old_output.stderr));
+        assert!(String::from_utf8_lossy(&old_output.stderr).contains("[legacy-cli]"), "{}", legacy);
+
+        let new_output = armake2(dir.path(), new);
+        assert_eq!(Some(0), new_output.status.code(), "{}: {}", new, String::from_utf8_lossy(&new_output.stderr));
+        assert!(new_output.stderr.is_empty(), "{}", new);
+
+        let legacy_file = read(dir.path().join(file.replace("{}", "legacy"))).unwrap();
+        assert_eq!(read(dir.path().join(file.replace("{}", "new"))).unwrap(), legacy_file, "{}", legacy);
+    }
+
+    let version = armake2(dir.path(), "-v");
+    assert_eq!(Some(0), version.status.code());
+    assert_eq!(armake2(dir.path(), "--version").stdout, version.stdout);
+
+    // Muting the warning works like for any other
+    assert!(armake2(dir.path(), "build -w legacy-cli -p -f addon legacy.pbo").stderr.is_empty());
+
+    assert_eq!(Some(1), armake2(dir.path(), "unpack -x *.sqf legacy.pbo out").status.code());
+    assert_eq!(Some(1), armake2(dir.path(), "build -p missing legacy.pbo").status.code());
+}
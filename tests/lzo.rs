@@ -0,0 +1,48 @@
+use std::io::{Read};
+
+use armake2::lzo::*;
+use armake2::paa::{LzoCodec, MiniLzo};
+
+#[test]
+fn test_lzo_reader() {
+    let mut state = 0x1234_5678u32;
+    let noise: Vec<u8> = (0..100_000).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect();
+
+    // Long runs, matches far back and incompressible data cover every kind of instruction
+    let mut mixed: Vec<u8> = vec![7; 300_000];
+    mixed.extend(&noise[..40_000]);
+    mixed.extend(b"abcabcabcabd".repeat(1000));
+    mixed.extend(&noise[..40_000]);
+    mixed.extend(&noise[50_000..]);
+
+    for data in [Vec::new(), vec![1, 2, 3], noise, mixed] {
+        let compressed = MiniLzo.compress(&data).unwrap();
+        assert_eq!(data, decompress(&compressed, data.len()).unwrap());
+        verify(&compressed, data.len()).unwrap();
+
+        // Reads of odd sizes don't line up with the instructions
+        let mut reader = LzoReader::new(&compressed, data.len());
+        let mut output: Vec<u8> = Vec::new();
+        let mut buffer = [0u8; 777];
+        loop {
+            let read = reader.read(&mut buffer).unwrap();
+            if read == 0 {
+                break;
+            }
+            output.extend(&buffer[..read]);
+        }
+        assert_eq!(data, output);
+        reader.finish().unwrap();
+
+        if !data.is_empty() {
+            assert!(verify(&compressed, data.len() - 1).is_err());
+            assert!(verify(&compressed, data.len() + 1).is_err());
+            assert!(verify(&compressed[..compressed.len() / 2], data.len()).is_err());
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::{create_dir_all, read_to_string, remove_file, write};
+use std::io::{Cursor};
+use std::path::{Path};
+
+use tempfile::{tempdir};
+
+use armake2::manifest::*;
+use armake2::sign::*;
+
+fn create_mod(dir: &Path) {
+    create_dir_all(dir.join("addons")).unwrap();
+    create_dir_all(dir.join("keys")).unwrap();
+    write(dir.join("addons").join("mod.pbo"), "pbo").unwrap();
+    write(dir.join("keys").join("mod.bikey"), "key").unwrap();
+    write(dir.join("mod.dll"), "dll").unwrap();
+    write(dir.join("debug.log"), "log").unwrap();
+}
+
+fn roundtrip(dir: &Path, signing: &ManifestKey, verifying: &ManifestKey) -> ManifestDiff {
+    let manifest = Manifest::from_directory(dir, &["*.log".to_string()]).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    manifest.write_signed(&mut output, signing).unwrap();
+
+    let reread = Manifest::read_verified(&mut Cursor::new(&output), verifying).unwrap();
+    assert_eq!(manifest, reread);
+    diff_manifest(dir, &reread).unwrap()
+}
+
+#[test]
+fn test_manifest_rsa() {
+    let dir = tempdir().unwrap();
+    let mod_dir = dir.path().join("@mod");
+    create_mod(&mod_dir);
+
+    let key_path = dir.path().join("authority");
+    cmd_keygen(key_path.clone(), false).unwrap();
+    let private = ManifestKey::from_file(&dir.path().join("authority.biprivatekey")).unwrap();
+    let public = ManifestKey::from_file(&dir.path().join("authority.bikey")).unwrap();
+
+    let manifest_path = mod_dir.join(MANIFEST_FILE);
+    let mut output: Vec<u8> = Vec::new();
+    cmd_manifest_create(&mod_dir, &dir.path().join("authority.biprivatekey"), &mut output, &["*.log".to_string()]).unwrap();
+    write(&manifest_path, &output).unwrap();
+
+    let text = read_to_string(&manifest_path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!("armake2-manifest 1", lines[0]);
+    assert_eq!("exclude *.log", lines[1]);
+    assert!(lines[2].starts_with("file ") && lines[2].ends_with(" 3 addons/mod.pbo"));
+    assert!(lines[3].ends_with(" 3 keys/mod.bikey"));
+    assert!(lines[4].ends_with(" 3 mod.dll"));
+    assert!(lines[5].starts_with("signature rsa authority "));
+    assert_eq!(6, lines.len());
+
+    assert!(cmd_manifest_verify(&mod_dir, &dir.path().join("authority.bikey"), &mut Cursor::new(&output)).is_ok());
+    assert!(roundtrip(&mod_dir, &private, &public).is_empty());
+
+    // Logs stay excluded, changes to other files are reported
+    write(mod_dir.join("other.log"), "log").unwrap();
+    write(mod_dir.join("mod.dll"), "evil").unwrap();
+    write(mod_dir.join("userconfig.hpp"), "new").unwrap();
+    remove_file(mod_dir.join("keys").join("mod.bikey")).unwrap();
+
+    let manifest = Manifest::read_verified(&mut Cursor::new(&output), &public).unwrap();
+    let diff = diff_manifest(&mod_dir, &manifest).unwrap();
+    assert_eq!(vec!["userconfig.hpp".to_string()], diff.added);
+    assert_eq!(vec!["keys/mod.bikey".to_string()], diff.removed);
+    assert_eq!(vec!["mod.dll".to_string()], diff.modified);
+    assert!(cmd_manifest_verify(&mod_dir, &dir.path().join("authority.bikey"), &mut Cursor::new(&output)).is_err());
+
+    // Tampering with the listed files or the exclusions breaks the signature
+    let tampered = text.replace("exclude *.log", "exclude *.dll");
+    assert!(Manifest::read_verified(&mut Cursor::new(tampered.as_bytes()), &public).is_err());
+
+    cmd_keygen(dir.path().join("other"), false).unwrap();
+    let other = ManifestKey::from_file(&dir.path().join("other.bikey")).unwrap();
+    assert!(Manifest::read_verified(&mut Cursor::new(&output), &other).is_err());
+}
+
+#[test]
+fn test_manifest_hmac() {
+    let dir = tempdir().unwrap();
+    let mod_dir = dir.path().join("@mod");
+    create_mod(&mod_dir);
+
+    write(dir.path().join("secret.txt"), "correct horse battery staple\n").unwrap();
+    write(dir.path().join("wrong.txt"), "incorrect horse").unwrap();
+    let secret = ManifestKey::from_file(&dir.path().join("secret.txt")).unwrap();
+    let wrong = ManifestKey::from_file(&dir.path().join("wrong.txt")).unwrap();
+
+    assert!(roundtrip(&mod_dir, &secret, &secret).is_empty());
+
+    let mut output: Vec<u8> = Vec::new();
+    Manifest::from_directory(&mod_dir, &[]).unwrap().write_signed(&mut output, &secret).unwrap();
+    assert!(String::from_utf8(output.clone()).unwrap().contains("\nfile "));
+    assert!(String::from_utf8(output.clone()).unwrap().contains(" debug.log\n"));
+    assert!(Manifest::read_verified(&mut Cursor::new(&output), &wrong).is_err());
+
+    cmd_keygen(dir.path().join("authority"), false).unwrap();
+    let public = ManifestKey::from_file(&dir.path().join("authority.bikey")).unwrap();
+    assert!(Manifest::read_verified(&mut Cursor::new(&output), &public).is_err());
+    assert!(Manifest::from_directory(&mod_dir, &[]).unwrap().write_signed(&mut Vec::new(), &public).is_err());
+}
@@ -0,0 +1,66 @@
+use std::fs::{create_dir_all, write};
+use std::io::{Cursor};
+use std::path::{Path};
+
+use tempfile::{tempdir};
+
+use armake2::config::*;
+use armake2::mission::*;
+use armake2::paa::{Paa};
+use armake2::pbo::*;
+
+const DESCRIPTION: &str = "briefingName = \"Escape\";\noverviewPicture = \"images\\overview.paa\";\n";
+
+fn mission_folder(root: &Path) -> std::path::PathBuf {
+    let mission = root.join("co10_escape.Altis");
+    create_dir_all(mission.join("images")).unwrap();
+    write(mission.join("mission.sqm"), "version = 53;\nclass Mission { class Intel { briefingName = \"Escape\"; }; };\n").unwrap();
+    write(mission.join("description.ext"), DESCRIPTION).unwrap();
+    image::DynamicImage::new_rgba8(8, 8).save(mission.join("images").join("overview.png")).unwrap();
+    mission
+}
+
+#[test]
+fn test_mission_name() {
+    assert_eq!(("co10_escape".to_string(), "Altis".to_string()), mission_name(Path::new("missions/co10_escape.Altis"), None).unwrap());
+    assert_eq!(("co10_escape".to_string(), "Tanoa".to_string()), mission_name(Path::new("co10_escape.Altis"), Some("Tanoa")).unwrap());
+    assert_eq!("escape.Stratis.pbo", mission_pbo_name(Path::new("escape"), Some("Stratis")).unwrap());
+    assert!(mission_name(Path::new("escape"), None).is_err());
+}
+
+#[test]
+fn test_pack_mission() {
+    let dir = tempdir().unwrap();
+    let mission = mission_folder(dir.path());
+
+    let options = MissionOptions { binarize_sqm: true, convert_images: true, world: None };
+    let mut output: Vec<u8> = Vec::new();
+    cmd_pack_mission(mission.clone(), &mut output, &[], &[], &options, None).unwrap();
+    let pbo = PBO::read(&mut Cursor::new(output)).unwrap();
+
+    let sqm = pbo.files.get("mission.sqm").unwrap().get_ref().to_vec();
+    assert!(sqm.starts_with(b"\0raP"));
+    let config = Config::read_rapified(&mut Cursor::new(sqm)).unwrap();
+    assert!(config.find("Mission/Intel/briefingName").is_some());
+
+    assert_eq!(DESCRIPTION.as_bytes(), &pbo.files.get("description.ext").unwrap().get_ref()[..]);
+    assert!(pbo.files.get("images\\overview.png").is_none());
+    let paa = Paa::from_reader(&mut Cursor::new(pbo.files.get("images\\overview.paa").unwrap().get_ref().to_vec())).unwrap();
+    assert_eq!(Some((8, 8)), paa.dimensions(0));
+
+    // Without options everything is packed as it is
+    let plain = PBO::from_mission_directory(mission.clone(), &[], &MissionOptions::default(), None).unwrap();
+    assert!(plain.files.get("mission.sqm").unwrap().get_ref().starts_with(b"version"));
+    assert!(plain.files.get("images\\overview.png").is_some());
+
+    // An unreadable briefingName is only a warning
+    write(mission.join("description.ext"), "briefingName = \"Escape\"\nclass {").unwrap();
+    assert!(read_briefing_name(&mission.join("description.ext")).is_err());
+    write(mission.join("description.ext"), "briefingName[] = {1};").unwrap();
+    assert!(read_briefing_name(&mission.join("description.ext")).is_err());
+    assert!(PBO::from_mission_directory(mission.clone(), &[], &MissionOptions::default(), None).is_ok());
+
+    std::fs::remove_file(mission.join("mission.sqm")).unwrap();
+    let error = PBO::from_mission_directory(mission.clone(), &[], &options, None).err().unwrap();
+    assert!(error.to_string().contains("has no mission.sqm"), "{}", error);
+}
@@ -0,0 +1,103 @@
+use std::fs::{File, write};
+
+use linked_hash_map::{LinkedHashMap};
+use tempfile::{tempdir};
+
+use armake2::modelcfg::*;
+use armake2::p3d::*;
+
+fn write_p3d(path: &std::path::Path, selections: &[&str]) {
+    let mut taggs: LinkedHashMap<String, Box<[u8]>> = LinkedHashMap::new();
+    for selection in selections {
+        taggs.insert(selection.to_string(), vec![0u8; 0].into_boxed_slice());
+    }
+
+    let p3d = P3D {
+        version: 257,
+        lods: vec![LOD {
+            version_major: 28,
+            version_minor: 256,
+            resolution: 1.0,
+            points: Vec::new(),
+            face_normals: Vec::new(),
+            faces: Vec::new(),
+            taggs,
+        }],
+    };
+    p3d.write(&mut File::create(path).unwrap()).unwrap();
+}
+
+#[test]
+fn test_modelcfg_check() {
+    let dir = tempdir().unwrap();
+    write(dir.path().join("model.cfg"), "\
+class CfgSkeletons {
+    class Default {
+        isDiscrete = 1;
+        skeletonInherit = \"\";
+        skeletonBones[] = {};
+    };
+    class Car_Skeleton: Default {
+        skeletonBones[] = {
+            \"door\", \"\",
+            \"handle\", \"door\",
+            \"wheel\", \"axle\",
+            \"axle\", \"\"
+        };
+    };
+    class Truck_Skeleton: Car_Skeleton {
+        skeletonInherit = \"Car_Skeleton\";
+        skeletonBones[] = {\"hood\", \"engine\"};
+    };
+};
+
+class CfgModels {
+    class Default {
+        sections[] = {};
+        skeletonName = \"\";
+    };
+    class car: Default {
+        skeletonName = \"Car_Skeleton\";
+        sections[] = {\"light\"};
+        class Animations {
+            class Door {
+                type = \"rotation\";
+                selection = \"door\";
+            };
+            class Handle: Door {
+                selection = \"handle\";
+            };
+            class Trunk: Door {
+                selection = \"trunk\";
+            };
+        };
+    };
+    class Truk: car {
+        skeletonName = \"Truck_Skeleton\";
+    };
+};
+").unwrap();
+
+    write_p3d(&dir.path().join("car.p3d"), &["door", "#Mass#"]);
+    write_p3d(&dir.path().join("truck.p3d"), &[]);
+
+    let issues = check_modelcfg(dir.path(), &Vec::new()).unwrap();
+    let mut names: Vec<(&str, Option<u32>)> = issues.iter().map(|i| (i.name, i.line)).collect();
+    names.sort();
+
+    assert_eq!(vec![
+        ("modelcfg-missing-model", Some(21)),
+        ("modelcfg-missing-selection", Some(28)),
+        ("modelcfg-missing-selection", Some(34)),
+        ("modelcfg-missing-selection", Some(37)),
+        ("modelcfg-undefined-parent", Some(11)),
+        ("modelcfg-undefined-parent", Some(17)),
+        ("modelcfg-unknown-model", Some(42)),
+        ("modelcfg-unknown-selection", Some(37)),
+    ], names);
+
+    let messages: Vec<&str> = issues.iter().map(|i| i.message.as_str()).collect();
+    assert!(messages.contains(&"Parent \"axle\" of bone \"wheel\" in skeleton \"Car_Skeleton\" is defined after its child."));
+    assert!(messages.contains(&"Parent \"engine\" of bone \"hood\" in skeleton \"Truck_Skeleton\" is not defined."));
+    assert!(messages.contains(&"There is no class in CfgModels for model \"truck.p3d\"."));
+}
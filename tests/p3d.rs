@@ -0,0 +1,332 @@
+use std::io::{Cursor, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use linked_hash_map::{LinkedHashMap};
+
+use armake2::io::*;
+use armake2::p3d::*;
+use armake2::paa::{LzoCodec, MiniLzo};
+
+/// Writes ODOL models with just enough content for listing LODs
+struct OdolWriter {
+    output: Vec<u8>,
+    version: u32,
+}
+
+impl OdolWriter {
+    fn u32(&mut self, value: u32) {
+        self.output.write_u32::<LittleEndian>(value).unwrap();
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.output.write_f32::<LittleEndian>(value).unwrap();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.output.push(value);
+    }
+
+    fn zeros(&mut self, length: usize) {
+        self.output.extend(std::iter::repeat_n(0, length));
+    }
+
+    fn string(&mut self, value: &str) {
+        self.output.write_cstring(value).unwrap();
+    }
+
+    /// Writes an array, compressed with LZO or LZSS where the format requires it.
+    fn array(&mut self, count: u32, data: &[u8]) {
+        self.u32(count);
+        if data.is_empty() {
+            return;
+        }
+
+        let compressed = data.len() >= 1024;
+        if self.version >= 64 {
+            self.u8(compressed as u8);
+        }
+        if !compressed {
+            self.output.write_all(data).unwrap();
+        } else if self.version >= 44 {
+            let lzo = MiniLzo.compress(data).unwrap();
+            self.output.write_all(&lzo).unwrap();
+        } else {
+            // Only literals, 8 per flag byte
+            for chunk in data.chunks(8) {
+                self.u8(0xff);
+                self.output.write_all(chunk).unwrap();
+            }
+            self.u32(data.iter().fold(0u32, |sum, b| sum.wrapping_add(*b as u32)));
+        }
+    }
+
+    fn model_info(&mut self) {
+        let v = self.version;
+        self.zeros(4 * 6 + 12 + 4 * 3 + 12 * 2);
+        if v >= 70 { self.f32(1.0); }
+        if v >= 71 { self.f32(1.0); }
+        if v >= 52 { self.zeros(24); }
+        self.zeros(12 * 3 + 4 * 9 + 4);
+        if v >= 73 { self.u8(0); }
+        if v >= 42 { self.zeros(16); }
+        if v >= 43 { self.zeros(8); }
+        self.zeros(1 + 4 + 1);
+        if v >= 48 { self.f32(0.0); }
+        self.u8(1);
+
+        self.string("OFP2_ManSkeleton");
+        self.u8(1);
+        self.u32(2);
+        for name in &["pelvis", "", "spine", "pelvis"] {
+            self.string(name);
+        }
+        if v > 40 { self.string(""); }
+
+        self.u8(0);
+        // Large enough to be compressed
+        let masses: Vec<u8> = (0..300u32).flat_map(|i| (i as f32).to_le_bytes()).collect();
+        self.array(300, &masses);
+        self.zeros(16);
+        if v >= 72 { self.f32(0.0); }
+        if v >= 53 { self.u8(0xff); }
+        if v >= 54 { self.u8(0xff); }
+        self.zeros(12 + 4 + 1);
+        self.string("vehicle");
+        self.string("");
+        self.u8(0);
+        self.u32(0);
+        if v >= 57 { self.zeros(12); }
+    }
+
+    fn animations(&mut self, lods: u32) {
+        self.u32(2);
+
+        self.u32(0);
+        self.string("wheel");
+        self.string("wheel");
+        self.zeros(8);
+        if self.version >= 56 { self.zeros(8); }
+        self.u32(0);
+        self.zeros(8);
+
+        self.u32(9);
+        self.string("hide_door");
+        self.string("damage");
+        self.zeros(8);
+        if self.version >= 56 { self.zeros(8); }
+        self.u32(0);
+        self.zeros(if self.version >= 55 { 8 } else { 4 });
+
+        self.u32(lods);
+        for _i in 0..lods {
+            self.u32(1);
+            self.u32(2);
+            self.u32(0);
+            self.u32(1);
+        }
+        for _i in 0..lods {
+            self.u32(0);
+            self.zeros(24);
+            self.u32(0);
+        }
+    }
+
+    fn material(&mut self, name: &str) {
+        self.string(name);
+        self.u32(11);
+        self.zeros(16 * 6 + 4 + 4 * 4);
+        self.string("a3\\data_f\\default.bisurf");
+        self.zeros(8);
+        self.u32(2);
+        self.u32(1);
+        for texture in &["#(argb,8,8,3)color(0.5,0.5,0.5,1,DT)", "a3\\data_f\\env_co.paa"] {
+            self.u32(0);
+            self.string(texture);
+            self.u32(0);
+            self.u8(0);
+        }
+        self.zeros(4 + 48);
+        self.u32(0);
+        self.string("");
+        self.u32(0);
+        self.u8(0);
+    }
+
+    fn lod(&mut self, textures: &[&str], materials: &[&str], selections: &[&str]) {
+        let index_size = if self.version >= 69 { 4 } else { 2 };
+
+        self.u32(1);
+        self.string("\\a3\\data_f\\proxies\\driver.p3d");
+        self.zeros(48 + 12 + 4);
+
+        self.u32(1);
+        self.u32(0);
+        self.u32(1);
+        self.u32(1);
+        self.u32(0);
+
+        if self.version >= 50 {
+            self.u32(4);
+        } else {
+            self.u32(4);
+            self.u8(1);
+            self.u32(0);
+        }
+        if self.version >= 51 { self.f32(1.0); }
+        self.zeros(8 + 36 + 4);
+
+        self.u32(textures.len() as u32);
+        for texture in textures {
+            self.string(texture);
+        }
+        self.u32(materials.len() as u32);
+        for material in materials {
+            self.material(material);
+        }
+
+        self.array(0, &[]);
+        let edges = vec![1u8; 600 * index_size];
+        self.array(600, &edges);
+
+        self.u32(1);
+        self.zeros(6);
+        self.u8(3);
+        self.zeros(3 * index_size);
+
+        self.u32(2);
+        for material in [0i32, -1] {
+            self.zeros(4 * 5 + 2 + 4);
+            self.output.write_i32::<LittleEndian>(material).unwrap();
+            if material == -1 { self.string("a3\\data_f\\concrete.bisurf"); }
+            self.u32(1);
+            self.f32(1.0);
+            if self.version >= 67 {
+                self.u32(1);
+                self.zeros(44);
+            }
+        }
+
+        self.u32(selections.len() as u32);
+        for selection in selections {
+            self.string(selection);
+            self.array(1, &vec![0; index_size]);
+            self.u32(0);
+            self.u8(1);
+            self.array(2, &[0; 8]);
+            let vertices = vec![2u8; 1000 * index_size];
+            self.array(1000, &vertices);
+            self.array(3, &[255; 3]);
+        }
+
+        // Data after the named selections is never read
+        self.string("#EndOfLOD#");
+    }
+
+    fn model(version: u32) -> Vec<u8> {
+        let mut writer = OdolWriter { output: b"ODOL".to_vec(), version };
+        writer.u32(version);
+        if version >= 59 { writer.u32(0); }
+        if version >= 58 { writer.string(""); }
+
+        writer.u32(2);
+        writer.f32(1.0);
+        writer.f32(1e13);
+        writer.model_info();
+        writer.u8(1);
+        writer.animations(2);
+
+        // LOD offsets are filled in once the LODs are written
+        let starts = writer.output.len();
+        writer.zeros(4 * 4);
+        writer.zeros(2);
+
+        let first = writer.output.len() as u32;
+        writer.lod(&["a3\\data_f\\body_co.paa", "a3\\data_f\\glass_ca.paa"], &["a3\\data_f\\body.rvmat"], &["door", "wheel", "-proxy:\\a3\\data_f\\proxies\\driver.p3d.001"]);
+        let second = writer.output.len() as u32;
+        writer.lod(&["A3\\data_f\\BODY_co.paa", ""], &[], &["door"]);
+        let end = writer.output.len() as u32;
+
+        for (i, offset) in [first, second, second, end].iter().enumerate() {
+            writer.output[starts + i * 4..starts + i * 4 + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        writer.output
+    }
+}
+
+#[test]
+fn test_p3d_info_odol() {
+    for version in [43, 50, 68, 73] {
+        let data = OdolWriter::model(version);
+        let info = P3DInfo::read(&mut Cursor::new(data)).unwrap_or_else(|e| panic!("version {}: {}", version, e));
+
+        assert_eq!(P3DFormat::Odol, info.format);
+        assert_eq!(version, info.version);
+        assert_eq!(2, info.lods.len());
+        assert_eq!(1.0, info.lods[0].resolution);
+        assert_eq!(1e13, info.lods[1].resolution);
+        assert_eq!(vec!["door", "wheel", "-proxy:\\a3\\data_f\\proxies\\driver.p3d.001"], info.lods[0].selections);
+        assert_eq!(vec!["a3\\data_f\\body.rvmat"], info.lods[0].materials);
+        assert_eq!(vec!["door"], info.lods[1].selections);
+
+        // Textures are listed once, even if they differ in case
+        assert_eq!(vec!["a3\\data_f\\body_co.paa", "a3\\data_f\\glass_ca.paa"], info.textures());
+        assert_eq!(vec!["a3\\data_f\\body.rvmat"], info.materials());
+    }
+}
+
+#[test]
+fn test_p3d_info_odol_errors() {
+    let mut data = OdolWriter::model(73);
+    data[4] = 75;
+    let error = P3DInfo::read(&mut Cursor::new(data)).unwrap_err();
+    assert_eq!("ODOL version 75 not supported, supported versions are 40 to 73.", error.to_string());
+
+    let data = OdolWriter::model(73);
+    let truncated = data[..data.len() - 20].to_vec();
+    let error = P3DInfo::read(&mut Cursor::new(truncated)).unwrap_err();
+    assert!(error.to_string().contains("Failed to read LOD 2:"), "{}", error);
+
+    assert!(P3DInfo::read(&mut Cursor::new(b"\0raP".to_vec())).is_err());
+}
+
+#[test]
+fn test_p3d_info_mlod() {
+    let face = |texture: &str, material: &str| Face {
+        vertices: (0..3).map(|_| Vertex::new()).collect(),
+        flags: 0,
+        texture: texture.to_string(),
+        material: material.to_string(),
+    };
+    let mut taggs: LinkedHashMap<String, Box<[u8]>> = LinkedHashMap::new();
+    taggs.insert("door".to_string(), Box::new([]));
+    taggs.insert("#Mass#".to_string(), Box::new([]));
+
+    let p3d = P3D {
+        version: 257,
+        lods: vec![LOD {
+            version_major: 28,
+            version_minor: 256,
+            resolution: 1.0,
+            points: (0..3).map(|_| Point::new()).collect(),
+            face_normals: Vec::new(),
+            faces: vec![face("body_co.paa", "body.rvmat"), face("body_co.paa", ""), face("", "glass.rvmat")],
+            taggs,
+        }],
+    };
+    assert_eq!(vec!["body_co.paa"], p3d.textures());
+    assert_eq!(vec!["body.rvmat", "glass.rvmat"], p3d.materials());
+
+    let mut buffer: Vec<u8> = Vec::new();
+    p3d.write(&mut buffer).unwrap();
+    let info = P3DInfo::read(&mut Cursor::new(buffer.clone())).unwrap();
+    assert_eq!(P3DFormat::Mlod, info.format);
+    assert_eq!(vec!["door"], info.lods[0].selections);
+    assert_eq!(p3d.textures(), info.textures());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_p3d_info(&mut Cursor::new(buffer), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("format: Mlod\nversion: 257\nlods: 1\n"), "{}", output);
+    assert!(output.contains("textures:\n    body_co.paa\nmaterials:\n    body.rvmat\n    glass.rvmat\n"), "{}", output);
+}
@@ -0,0 +1,1586 @@
+use std::collections::{HashSet};
+use std::io::{Cursor};
+use std::path::{Path};
+use std::sync::{Mutex};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use minilzo_rs::LZO;
+
+use armake2::error::*;
+use armake2::io::{BatchSummary, OutputPolicy};
+use armake2::jobmanifest::*;
+use armake2::paa::*;
+use armake2::progress::*;
+
+fn paa_bytes(paa_type: u16, mipmaps: &[(u16, u16, Vec<u8>)]) -> Vec<u8> {
+    paa_bytes_with_filler(paa_type, &[], mipmaps)
+}
+
+/// Builds a PAA with the given bytes between the type and the offset tagg.
+fn paa_bytes_with_filler(paa_type: u16, filler: &[u8], mipmaps: &[(u16, u16, Vec<u8>)]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::new();
+    output.write_u16::<LittleEndian>(paa_type).unwrap();
+    output.extend(filler);
+
+    output.extend(b"GGATSFFO");
+    output.write_u32::<LittleEndian>(mipmaps.len() as u32 * 4).unwrap();
+
+    let mut offset = output.len() + mipmaps.len() * 4;
+    for (_, _, data) in mipmaps {
+        output.write_u32::<LittleEndian>(offset as u32).unwrap();
+        offset += 7 + data.len();
+    }
+
+    for (width, height, data) in mipmaps {
+        output.write_u16::<LittleEndian>(*width).unwrap();
+        output.write_u16::<LittleEndian>(*height).unwrap();
+        output.extend(&(data.len() as u32).to_le_bytes()[0..3]);
+        output.extend(data);
+    }
+
+    output
+}
+
+#[test]
+fn test_paa_compression_flag_on_height() {
+    let blocks = vec![0u8; 64 * 64 / 2];
+    let compressed = LZO::init().unwrap().compress(&blocks).unwrap();
+    assert!(compressed.len() < blocks.len());
+
+    let input = paa_bytes(0xFF01, &[(64, 64 | 0x8000, compressed)]);
+    collect_diagnostics(true);
+    let paa = Paa::from_reader(&mut Cursor::new(input)).unwrap();
+    let diagnostics: Vec<CollectedDiagnostic> = take_diagnostics().into_iter()
+        .filter(|d| d.name.as_deref() == Some("nonstandard-compression-flag"))
+        .collect();
+    collect_diagnostics(false);
+
+    assert_eq!(1, diagnostics.len(), "{:?}", diagnostics);
+    assert_eq!(Severity::Warning, diagnostics[0].level);
+    assert!(diagnostics[0].message.contains("Mipmap 0 (64x64)"), "{}", diagnostics[0].message);
+
+    let img = paa.to_image().unwrap();
+    assert_eq!(64, img.width());
+    assert_eq!(64, img.height());
+}
+
+/// Reads the raw bytes of every mipmap (header and payload) by following the non-zero offsets of
+/// the offset tagg.
+fn raw_mipmaps(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut pos = 2;
+    let mut offsets: Vec<usize> = Vec::new();
+    while &bytes[pos..(pos + 4)] == b"GGAT" || &bytes[pos..(pos + 4)] == b"TAGG" {
+        let size = u32::from_le_bytes(bytes[(pos + 8)..(pos + 12)].try_into().unwrap()) as usize;
+        if &bytes[(pos + 4)..(pos + 8)] == b"SFFO" {
+            offsets = bytes[(pos + 12)..(pos + 12 + size)].chunks(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()) as usize)
+                .filter(|offset| *offset != 0)
+                .collect();
+        }
+        pos += 12 + size;
+    }
+
+    offsets.iter().map(|&offset| {
+        let size = u32::from_le_bytes([bytes[offset + 4], bytes[offset + 5], bytes[offset + 6], 0]) as usize;
+        bytes[offset..(offset + 7 + size)].to_vec()
+    }).collect()
+}
+
+#[test]
+fn test_paa_set_keeps_mipmaps() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(32, 32, |x, y| {
+        image::Rgba([(x * 8) as u8, (y * 8) as u8, 0, 255])
+    }));
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.paa");
+    let target = dir.path().join("target.paa");
+    Paa::from_image(&img, PaaType::DXT5, true).unwrap().write_to_file(&source).unwrap();
+
+    let edits = TaggEdits {
+        flag: Some(1),
+        avg_color: Some(parse_avg_color("10203040").unwrap()),
+        swizzle: Some(parse_swizzle("1-A,R,G,B").unwrap()),
+    };
+    cmd_paa_set(&source, &target, &edits, false).unwrap();
+
+    let before = std::fs::read(&source).unwrap();
+    let after = std::fs::read(&target).unwrap();
+    assert_eq!(before.len() + 2 * 16, after.len());
+    assert_eq!(raw_mipmaps(&before), raw_mipmaps(&after));
+
+    assert!(after.windows(12).any(|w| w == b"GGATCGVA\x04\x00\x00\x00" || w == b"TAGGCGVA\x04\x00\x00\x00"));
+    assert!(after.windows(4).any(|w| w == [0x10, 0x20, 0x30, 0x40]));
+    assert!(after.windows(8).any(|w| w == b"ZIWS\x04\x00\x00\x00"));
+
+    // Editing in place again only changes the tagg data
+    cmd_paa_set(&target, &target, &TaggEdits { avg_color: Some(AverageColor::Auto), ..Default::default() }, false).unwrap();
+    let again = std::fs::read(&target).unwrap();
+    assert_eq!(after.len(), again.len());
+    assert_eq!(raw_mipmaps(&before), raw_mipmaps(&again));
+    Paa::from_reader(&mut Cursor::new(&again)).unwrap().to_image().unwrap();
+
+    let error = cmd_paa_set(&source, &target, &edits, false).unwrap_err();
+    assert_eq!(Some("E0805"), coded(&error).map(|c| c.code));
+    assert_eq!(again, std::fs::read(&target).unwrap());
+    assert!(parse_swizzle("R,G,B").is_err());
+    assert!(parse_avg_color("123").is_err());
+}
+
+struct FailingLzo;
+
+impl LzoCodec for FailingLzo {
+    fn compress(&self, _data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "LZO initialization failed: test"))
+    }
+
+    fn decompress(&self, _data: &[u8], _size: usize) -> Result<Vec<u8>, std::io::Error> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "LZO initialization failed: test"))
+    }
+}
+
+#[test]
+fn test_paa_lzo_unavailable() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(64, 64, |x, y| {
+        image::Rgba([(x * 4) as u8, (y * 4) as u8, 0, 255])
+    }));
+
+    // Without LZO, compression falls back to the exact output of an uncompressed encode
+    let mut fallback = Cursor::new(Vec::new());
+    Paa::from_image_with_lzo(&img, PaaType::DXT1, true, &FailingLzo).unwrap().write(&mut fallback).unwrap();
+    let mut uncompressed = Cursor::new(Vec::new());
+    Paa::from_image(&img, PaaType::DXT1, false).unwrap().write(&mut uncompressed).unwrap();
+    assert_eq!(uncompressed.into_inner(), fallback.into_inner());
+
+    let blocks = vec![0u8; 64 * 64 / 2];
+    let compressed = LZO::init().unwrap().compress(&blocks).unwrap();
+    let input = paa_bytes(0xFF01, &[(64 | 0x8000, 64, compressed)]);
+
+    let err = Paa::from_reader_with_lzo(&mut Cursor::new(&input), &FailingLzo).unwrap_err();
+    assert!(err.to_string().contains("mipmap 0 (64x64)"));
+    assert!(err.to_string().contains("LZO initialization failed"));
+
+    Paa::from_reader_with_lzo(&mut Cursor::new(&input), &MiniLzo).unwrap().to_image().unwrap();
+}
+
+#[test]
+fn test_paa_minilzo_threads() {
+    // Each thread compresses with its own instance, so concurrent use gives the same bytes
+    let blocks: Vec<u8> = (0..0x10000u32).map(|i| (i / 7 % 13) as u8).collect();
+    let expected = MiniLzo.compress(&blocks).unwrap();
+    let results: Vec<Vec<u8>> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4).map(|_| scope.spawn(|| (0..8).map(|_| MiniLzo.compress(&blocks).unwrap()).last().unwrap())).collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert!(results.iter().all(|r| *r == expected));
+    assert_eq!(blocks, MiniLzo.decompress(&expected, blocks.len()).unwrap());
+}
+
+#[test]
+fn test_paa_decompress_levels() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(256, 256, |x, y| {
+        image::Rgba([(x % 7 * 30) as u8, (y % 5 * 50) as u8, ((x + y) % 3 * 80) as u8, 255])
+    }));
+    let mut bytes: Vec<u8> = Vec::new();
+    Paa::from_image(&img, PaaType::ARGB8888, true).unwrap().write_to(&mut bytes).unwrap();
+    let expected = Paa::from_reader_with_lzo(&mut Cursor::new(&bytes), &MiniLzo).unwrap();
+
+    // Several levels are decompressed in parallel and decode like the ones decompressed on read
+    let mut paa = Paa::from_reader(&mut Cursor::new(&bytes)).unwrap();
+    paa.decompress_levels(&(0..paa.mipmap_count()).collect::<Vec<usize>>()).unwrap();
+    for index in 0..paa.mipmap_count() {
+        assert_eq!(expected.to_image_level(index).unwrap(), paa.to_image_level(index).unwrap());
+    }
+
+    let err = paa.decompress_levels(&[0, paa.mipmap_count()]).unwrap_err();
+    assert!(err.to_string().contains("has no mipmap"), "{}", err);
+}
+
+#[test]
+fn test_paa_taggs_after_filler() {
+    let blocks = vec![0u8; 4 * 4 / 2];
+    let input = paa_bytes_with_filler(0xFF01, &[0; 32], &[(4, 4, blocks.clone())]);
+
+    let paa = Paa::from_reader(&mut Cursor::new(input)).unwrap();
+    assert!(paa.has_nonstandard_layout());
+
+    let img = paa.to_image().unwrap();
+    assert_eq!(4, img.width());
+
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    paa.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+    assert_eq!(b"SFFO", &bytes[6..10]);
+
+    let normalized = Paa::from_reader(&mut Cursor::new(bytes)).unwrap();
+    assert!(!normalized.has_nonstandard_layout());
+    assert_eq!(4, normalized.to_image().unwrap().height());
+}
+
+#[test]
+fn test_paa_encode_progress() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(16, 8, image::Rgba([255, 0, 0, 255])));
+    let updates: Mutex<Vec<Progress>> = Mutex::new(Vec::new());
+
+    let paa = Paa::from_image_with_progress(&img, PaaType::DXT1, false, Some(&|p| updates.lock().unwrap().push(p))).unwrap();
+
+    // Levels are encoded at the same time, so they can finish in any order
+    let updates = updates.into_inner().unwrap();
+    assert_eq!(5, updates.len());
+    assert_eq!((1..=5).collect::<Vec<usize>>(), updates.iter().map(|p| p.current).collect::<Vec<usize>>());
+    assert!(updates.iter().all(|p| p.total == 5));
+    let labels: HashSet<&str> = updates.iter().map(|p| p.label.as_str()).collect();
+    assert_eq!(HashSet::from(["Mipmap 16x8", "Mipmap 8x4", "Mipmap 4x2", "Mipmap 2x1", "Mipmap 1x1"]), labels);
+    assert_eq!(16, paa.to_image().unwrap().width());
+}
+
+/// Largest difference of the first row of each mipmap to the ramp of the 16-bit source, in 8-bit
+/// steps, and the fewest distinct values of a row
+fn ramp_banding(paa: &Paa, width: u32, low: u32, high: u32) -> (f64, usize) {
+    let mut max_error: f64 = 0.0;
+    let mut unique = usize::MAX;
+    for level in 1..5 {
+        let mipmap = paa.to_image_level(level).unwrap().to_rgba8();
+        let scale = f64::from(width) / f64::from(mipmap.width());
+        let row: Vec<u8> = (0..mipmap.width()).map(|x| mipmap.get_pixel(x, 0)[0]).collect();
+        for (x, value) in row.iter().enumerate() {
+            let position = (x as f64 + 0.5) * scale - 0.5;
+            let ideal = (f64::from(low) + f64::from(high - low) * position / f64::from(width)) / 257.0;
+            max_error = max_error.max((f64::from(*value) - ideal).abs());
+        }
+        unique = unique.min(row.iter().collect::<HashSet<_>>().len());
+    }
+    (max_error, unique)
+}
+
+#[test]
+fn test_paa_16bit_gradient() {
+    // A shallow slope of a heightmap, which only has 14 distinct values in 8 bits
+    let (width, low, high) = (1024, 25600, 29000);
+    let img = image::DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(width, 4, |x, _| image::Luma([(low + (high - low) * x / width) as u16])));
+
+    for paa_type in [PaaType::ARGB8888, PaaType::AI88] {
+        // Flipping used to convert to 8-bit RGBA before the mipmaps were resized
+        let options = EncodeOptions { flip_vertical: true, ..Default::default() };
+        let native = Paa::from_image_with_options(&img, paa_type, &options, &MiniLzo, None).unwrap();
+        let legacy = Paa::from_image_with_options(&img, paa_type, &EncodeOptions { legacy_pipeline: true, ..options }, &MiniLzo, None).unwrap();
+
+        let (native_error, native_unique) = ramp_banding(&native, width, low, high);
+        let (legacy_error, legacy_unique) = ramp_banding(&legacy, width, low, high);
+        // Quantized once, every level is the rounded ramp
+        assert!(native_error <= 0.505, "{:?}: {}", paa_type, native_error);
+        assert!(legacy_error > native_error, "{:?}: {} {}", paa_type, legacy_error, native_error);
+        assert!(native_unique >= legacy_unique && native_unique >= 12, "{:?}: {} {}", paa_type, native_unique, legacy_unique);
+    }
+}
+
+#[test]
+fn test_paa_ai88() {
+    assert_eq!(PaaType::AI88, parse_paa_type("ai88").unwrap());
+
+    let img = image::DynamicImage::ImageLumaA8(image::ImageBuffer::from_fn(8, 8, |x, y| image::LumaA([(x * 30) as u8, (255 - y * 20) as u8])));
+    let mut output = Cursor::new(Vec::new());
+    Paa::from_image(&img, PaaType::AI88, false).unwrap().write(&mut output).unwrap();
+
+    let paa = Paa::from_reader(&mut Cursor::new(output.into_inner())).unwrap();
+    assert_eq!((PaaType::AI88, 4), (paa.paa_type, paa.mipmap_count()));
+    for (x, y, pixel) in paa.to_image().unwrap().to_rgba8().enumerate_pixels() {
+        let gray = (x * 30) as u8;
+        assert_eq!([gray, gray, gray, (255 - y * 20) as u8], pixel.0);
+    }
+
+    // Colors are stored as their luminance
+    let red = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(4, 4, image::Rgb([255, 0, 0])));
+    let luminance = red.to_luma8().get_pixel(0, 0)[0];
+    let paa = Paa::from_image(&red, PaaType::AI88, false).unwrap();
+    assert_eq!([luminance, luminance, luminance, 255], paa.to_image().unwrap().to_rgba8().get_pixel(0, 0).0);
+
+    // The color taggs are those of the stored intensity, analyzed without going through RGBA
+    let mut output = Cursor::new(Vec::new());
+    paa.write(&mut output).unwrap();
+    let header = PaaHeader::read(&mut Cursor::new(output.into_inner())).unwrap();
+    assert_eq!(Some([luminance, luminance, luminance, 255]), header.average_color);
+    assert_eq!(armake2::paaanalysis::analyze_rgba(&img.to_rgba8()), armake2::paaanalysis::analyze_luma_alpha(&img.to_luma_alpha8()));
+}
+
+/// Texture with a constant color and alpha per 4x4 block, so DXT5 stores it losslessly.
+fn block_texture(size: u32, alpha: impl Fn(u32, u32) -> u8) -> image::DynamicImage {
+    image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(size, size, |x, y| {
+        let red = if (x / 4 + y / 4) % 2 == 0 { 255 } else { 0 };
+        image::Rgba([red, 0, 255, alpha(x / 4, y / 4)])
+    }))
+}
+
+#[test]
+fn test_paa_alpha_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("texture.paa");
+    let mask = dir.path().join("mask.png");
+
+    let img = block_texture(16, |bx, by| (bx * 64 + by * 16) as u8);
+    Paa::from_image(&img, PaaType::DXT5, true).unwrap().write_to_file(&source).unwrap();
+
+    cmd_paa_alpha_extract(&source, &mask, false).unwrap();
+    let extracted = image::open(&mask).unwrap();
+    assert_eq!(image::ColorType::L8, extracted.color());
+    let extracted = extracted.to_luma8();
+    for (x, y, pixel) in extracted.enumerate_pixels() {
+        assert_eq!((x / 4 * 64 + y / 4 * 16) as u8, pixel[0]);
+    }
+    let error = cmd_paa_alpha_extract(&source, &mask, false).unwrap_err();
+    assert_eq!(Some("E0805"), coded(&error).map(|c| c.code));
+
+    let inverted = image::ImageBuffer::from_fn(16, 16, |x, y| image::Luma([255 - (x / 4 * 64 + y / 4 * 16) as u8]));
+    inverted.save(&mask).unwrap();
+    let target = dir.path().join("injected.paa");
+    cmd_paa_alpha_inject(&source, &mask, &target, None, false).unwrap();
+
+    let original = Paa::from_file(&source).unwrap().to_image().unwrap().to_rgba8();
+    let injected = Paa::from_file(&target).unwrap();
+    assert_eq!(PaaType::DXT5, injected.paa_type);
+    assert!(injected.is_compressed());
+
+    let injected = injected.to_image().unwrap().to_rgba8();
+    for ((x, y, before), after) in original.enumerate_pixels().zip(injected.pixels()) {
+        assert_eq!(before.0[0..3], after.0[0..3]);
+        assert_eq!(255 - (x / 4 * 64 + y / 4 * 16) as u8, after[3]);
+    }
+
+    image::ImageBuffer::from_pixel(8, 8, image::Luma([0u8])).save(&mask).unwrap();
+    assert!(cmd_paa_alpha_inject(&source, &mask, &target, None, true).is_err());
+}
+
+#[test]
+fn test_paa_alpha_inject_retype() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("texture.paa");
+    let mask = dir.path().join("mask.png");
+
+    Paa::from_image(&block_texture(8, |_, _| 255), PaaType::DXT1, false).unwrap().write_to_file(&source).unwrap();
+    image::ImageBuffer::from_fn(8, 8, |x, _| image::Luma([if x < 4 { 0u8 } else { 128 }])).save(&mask).unwrap();
+
+    cmd_paa_alpha_inject(&source, &mask, &source, Some(PaaType::DXT5), false).unwrap();
+
+    let paa = Paa::from_file(&source).unwrap();
+    assert_eq!(PaaType::DXT5, paa.paa_type);
+    let img = paa.to_image().unwrap().to_rgba8();
+    assert_eq!(0, img.get_pixel(0, 0)[3]);
+    assert_eq!(128, img.get_pixel(7, 7)[3]);
+}
+
+#[test]
+fn test_paa_unordered_mipmaps() {
+    let input = paa_bytes(0xFF01, &[
+        (4, 4, vec![0u8; 8]),
+        (8, 8, vec![0xFFu8; 32]),
+        (2, 2, vec![0u8; 8]),
+        (1, 1, vec![0u8; 8]),
+    ]);
+
+    let paa = Paa::from_reader(&mut Cursor::new(input)).unwrap();
+    let img = paa.to_image().unwrap();
+    assert_eq!(8, img.width());
+    assert_eq!(8, img.height());
+
+    let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    paa.write(&mut output).unwrap();
+    let sizes: Vec<u16> = raw_mipmaps(&output.into_inner()).iter().map(|m| u16::from_le_bytes([m[0], m[1]])).collect();
+    assert_eq!(vec![8, 4, 2, 1], sizes);
+}
+
+#[test]
+fn test_paa_from_raw_mipmaps() {
+    // Any 8 bytes are a valid BC1 block
+    let blocks = |count: usize, seed: u32| -> Vec<u8> {
+        (0..(count * 8) as u32).map(|i| (i.wrapping_mul(2_654_435_761).wrapping_add(seed) >> 13) as u8).collect()
+    };
+    let levels: Vec<RawMipmap> = [(16u16, 16u16, 16), (8, 8, 4), (4, 4, 1), (2, 2, 1), (1, 1, 1)].iter()
+        .map(|(w, h, count)| RawMipmap { width: *w, height: *h, data: blocks(*count, *w as u32) })
+        .collect();
+
+    for compress in [false, true] {
+        let paa = Paa::from_raw_mipmaps(PaaType::DXT1, levels.clone(), &RawMipmapOptions { compress }).unwrap();
+        let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        paa.write(&mut output).unwrap();
+
+        let bytes = output.into_inner();
+        let reread = Paa::from_reader(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(PaaType::DXT1, reread.paa_type);
+        assert_eq!(levels.len(), reread.mipmap_count());
+
+        for (i, level) in levels.iter().enumerate() {
+            let (width, height) = (level.width as usize, level.height as usize);
+            let mut expected = vec![0u8; width * height * 4];
+            texpresso::Format::Bc1.decompress(&level.data, width, height, &mut expected);
+            assert_eq!(expected, reread.to_image_level(i).unwrap().to_rgba8().into_raw());
+        }
+
+        // The average color comes from the 1x1 level
+        let smallest = reread.to_image_level(levels.len() - 1).unwrap().to_rgba8().into_raw();
+        let position = bytes.windows(8).position(|w| w == b"GGATCGVA").unwrap() + 12;
+        assert_eq!(&smallest[..], &bytes[position..(position + 4)]);
+    }
+}
+
+#[test]
+fn test_paa_from_raw_mipmaps_invalid() {
+    let error = Paa::from_raw_mipmaps(PaaType::DXT5, vec![
+        RawMipmap { width: 8, height: 8, data: vec![0; 64] },
+        RawMipmap { width: 4, height: 4, data: vec![0; 8] },
+    ], &RawMipmapOptions::default()).unwrap_err().to_string();
+    assert!(error.contains("Mipmap 1 (4x4)"), "{}", error);
+    assert!(error.contains("8 bytes") && error.contains("expected 16 bytes"), "{}", error);
+
+    assert!(Paa::from_raw_mipmaps(PaaType::DXT1, Vec::new(), &RawMipmapOptions::default()).is_err());
+    assert!(Paa::from_raw_mipmaps(PaaType::DXT3, vec![RawMipmap { width: 4, height: 4, data: vec![0; 16] }], &RawMipmapOptions::default()).is_ok());
+    assert!(Paa::from_raw_mipmaps(PaaType::DXT1, vec![RawMipmap { width: 0, height: 4, data: Vec::new() }], &RawMipmapOptions::default()).is_err());
+}
+
+#[test]
+fn test_paa_dds() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(32, 16, |x, y| image::Rgba([(x * 8) as u8, (y * 16) as u8, 40, (x * y) as u8])));
+    let paa = Paa::from_image(&img, PaaType::DXT5, false).unwrap();
+    let mut dds_bytes: Vec<u8> = Vec::new();
+    paa.to_dds(&mut dds_bytes).unwrap();
+
+    let dds = ddsfile::Dds::read(&mut Cursor::new(&dds_bytes)).unwrap();
+    assert_eq!(Some(ddsfile::D3DFormat::DXT5), dds.get_d3d_format());
+    assert_eq!((32, 16, paa.mipmap_count() as u32), (dds.get_width(), dds.get_height(), dds.get_num_mipmap_levels()));
+    let blocks: Vec<u8> = (0..paa.mipmap_count()).flat_map(|i| paa.raw_mipmap(i).unwrap().data.into_owned()).collect();
+    assert_eq!(blocks, dds.data);
+
+    // The blocks come back unchanged
+    let reread = Paa::from_dds(Cursor::new(&dds_bytes)).unwrap();
+    assert_eq!((PaaType::DXT5, paa.mipmap_count()), (reread.paa_type, reread.mipmap_count()));
+    for i in 0..paa.mipmap_count() {
+        assert_eq!(paa.raw_mipmap(i).unwrap(), reread.raw_mipmap(i).unwrap());
+    }
+
+    let mut level: Vec<u8> = Vec::new();
+    paa.to_dds_from_level(2, &mut level).unwrap();
+    let dds = ddsfile::Dds::read(&mut Cursor::new(&level)).unwrap();
+    assert_eq!((8, 4, paa.mipmap_count() as u32 - 2), (dds.get_width(), dds.get_height(), dds.get_num_mipmap_levels()));
+
+    let argb = Paa::from_image(&img, PaaType::ARGB8888, false).unwrap();
+    assert!(argb.to_dds(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_paa_dds_short_chain() {
+    // Two of the six levels, with any bytes as BC1 blocks
+    let mut dds = ddsfile::Dds::new_d3d(ddsfile::NewD3dParams {
+        height: 32,
+        width: 32,
+        depth: None,
+        format: ddsfile::D3DFormat::DXT1,
+        mipmap_levels: Some(2),
+        caps2: None,
+    }).unwrap();
+    assert_eq!(640, dds.data.len());
+    dds.data = (0..640u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8).collect();
+    let mut bytes: Vec<u8> = Vec::new();
+    dds.write(&mut bytes).unwrap();
+
+    let paa = Paa::from_dds(Cursor::new(&bytes)).unwrap();
+    assert_eq!((PaaType::DXT1, 2), (paa.paa_type, paa.mipmap_count()));
+    assert_eq!((Some((32, 32)), Some((16, 16))), (paa.dimensions(0), paa.dimensions(1)));
+    assert_eq!(&dds.data[..512], &paa.raw_mipmap(0).unwrap().data[..]);
+    assert_eq!(&dds.data[512..], &paa.raw_mipmap(1).unwrap().data[..]);
+
+    // The average color is the one of the smallest level
+    let smallest = paa.to_image_level(1).unwrap().to_rgba8().into_raw();
+    let average: Vec<u32> = (0..4).map(|c| smallest.iter().skip(c).step_by(4).map(|v| *v as u32).sum::<u32>() / 256).collect();
+    let mut output: Vec<u8> = Vec::new();
+    paa.write(&mut Cursor::new(&mut output)).unwrap();
+    let position = output.windows(8).position(|w| w == b"GGATCGVA").unwrap() + 12;
+    let stored: Vec<u32> = output[position..(position + 4)].iter().map(|v| *v as u32).collect();
+    assert_eq!(average, stored);
+
+    let mut written: Vec<u8> = Vec::new();
+    paa.to_dds(&mut written).unwrap();
+    assert_eq!(dds.data, ddsfile::Dds::read(&mut Cursor::new(&written)).unwrap().data);
+
+    bytes.truncate(bytes.len() - 10);
+    let error = Paa::from_dds(Cursor::new(&bytes)).unwrap_err().to_string();
+    assert!(error.contains("DDS ends within mipmap 1 (16x16)"), "{}", error);
+}
+
+#[test]
+fn test_paa_dds_unsupported() {
+    let dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+        height: 4,
+        width: 4,
+        depth: None,
+        format: ddsfile::DxgiFormat::BC1_UNorm,
+        mipmap_levels: None,
+        array_layers: None,
+        caps2: None,
+        is_cubemap: false,
+        resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+        alpha_mode: ddsfile::AlphaMode::Unknown,
+    }).unwrap();
+    let mut bytes: Vec<u8> = Vec::new();
+    dds.write(&mut bytes).unwrap();
+    let error = Paa::from_dds(Cursor::new(&bytes)).unwrap_err().to_string();
+    assert!(error.contains("DX10 header") && error.contains("BC1_UNorm"), "{}", error);
+
+    let dds = ddsfile::Dds::new_d3d(ddsfile::NewD3dParams {
+        height: 4,
+        width: 4,
+        depth: None,
+        format: ddsfile::D3DFormat::A8R8G8B8,
+        mipmap_levels: None,
+        caps2: None,
+    }).unwrap();
+    let mut bytes: Vec<u8> = Vec::new();
+    dds.write(&mut bytes).unwrap();
+    assert!(Paa::from_dds(Cursor::new(&bytes)).unwrap_err().to_string().contains("A8R8G8B8"));
+    assert!(Paa::from_dds(Cursor::new(b"PNG not a DDS")).is_err());
+
+    // Conversions through a DDS can't change the type or flip the blocks
+    let dir = tempfile::tempdir().unwrap();
+    let dds = ddsfile::Dds::new_d3d(ddsfile::NewD3dParams { height: 8, width: 8, depth: None, format: ddsfile::D3DFormat::DXT1, mipmap_levels: None, caps2: None }).unwrap();
+    let source = dir.path().join("texture.DDS");
+    dds.write(&mut std::fs::File::create(&source).unwrap()).unwrap();
+    let target = dir.path().join("texture.paa");
+    assert!(cmd_img2paa(&source, &target, Some(PaaType::DXT1), &EncodeOptions::default(), false, OutputPolicy::Overwrite, None).unwrap());
+    assert_eq!(PaaType::DXT1, Paa::from_file(&target).unwrap().paa_type);
+    let error = cmd_img2paa(&source, &target, Some(PaaType::DXT5), &EncodeOptions::default(), false, OutputPolicy::Overwrite, None).unwrap_err().to_string();
+    assert!(error.contains("is DXT1"), "{}", error);
+    let flipped = EncodeOptions { flip_vertical: true, ..Default::default() };
+    assert!(cmd_img2paa(&source, &target, None, &flipped, false, OutputPolicy::Overwrite, None).is_err());
+
+    let copy = dir.path().join("copy.dds");
+    assert!(cmd_paa2img(&target, &copy, 0, &DecodeOptions::default(), OutputPolicy::Fail).unwrap());
+    let copied = ddsfile::Dds::read(std::fs::File::open(&copy).unwrap()).unwrap();
+    assert_eq!((Some(ddsfile::D3DFormat::DXT1), &dds.data), (copied.get_d3d_format(), &copied.data));
+}
+
+#[test]
+fn test_paa_misdetected_compression() {
+    // Half of the blocks are repeated, so the data compresses to more than half of its size and
+    // isn't detected as compressed without the flag
+    let mut state = 0x1234_5678u32;
+    let mut blocks: Vec<u8> = (0..1024).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect();
+    blocks.extend(blocks.clone()[..64].repeat(16));
+    let compressed = LZO::init().unwrap().compress(&blocks).unwrap();
+    assert!(compressed.len() >= blocks.len() / 2 && compressed.len() < blocks.len(), "{} of {}", compressed.len(), blocks.len());
+
+    let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF01, &[(64, 64, compressed)]))).unwrap();
+    let mut expected = vec![0u8; 64 * 64 * 4];
+    texpresso::Format::Bc1.decompress(&blocks, 64, 64, &mut expected);
+    assert_eq!(expected, paa.to_image().unwrap().to_rgba8().into_raw());
+
+}
+
+#[test]
+fn test_paa_dxt_size_mismatch() {
+    let blocks: Vec<u8> = (0..2048u32).map(|i| (i * 7 % 251) as u8).collect();
+    let decode = |data: &[u8]| {
+        let mut expected = vec![0u8; 64 * 64 * 4];
+        texpresso::Format::Bc1.decompress(data, 64, 64, &mut expected);
+        expected
+    };
+
+    // Extra data is cut off
+    let mut larger = blocks.clone();
+    larger.extend(vec![0xAB; 100]);
+    let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF01, &[(64, 64, larger)]))).unwrap();
+    assert_eq!(decode(&blocks), paa.to_image().unwrap().to_rgba8().into_raw());
+    let strict = DecodeOptions { strict: true, ..Default::default() };
+    assert_eq!(decode(&blocks), paa.to_image_with_options(&strict).unwrap().to_rgba8().into_raw());
+
+    // Missing data is filled with zeros, or an error in strict mode
+    for length in [1500, 0] {
+        let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF01, &[(64, 64, blocks[..length].to_vec())]))).unwrap();
+        let mut padded = blocks[..length].to_vec();
+        padded.resize(2048, 0);
+        assert_eq!(decode(&padded), paa.to_image().unwrap().to_rgba8().into_raw());
+
+        let error = paa.to_image_with_options(&strict).unwrap_err().to_string();
+        assert!(error.contains(&format!("{} bytes", length)) && error.contains("expected 2048 bytes"), "{}", error);
+    }
+
+    // DXT5 mipmaps smaller than a block
+    let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF05, &[(2, 2, vec![0xFF; 20])]))).unwrap();
+    assert_eq!(2, paa.to_image().unwrap().width());
+    let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF05, &[(2, 2, vec![0xFF; 3])]))).unwrap();
+    assert_eq!(2, paa.to_image().unwrap().width());
+}
+
+#[test]
+fn test_paa_default_conversions() {
+    // The defaults match img2paa without --type and --lzo
+    assert!(DEFAULT_COMPRESSION);
+    assert_eq!(LzoPolicy::Always, EncodeOptions::default().lzo);
+
+    let opaque = block_texture(64, |_, _| 255);
+    let paa = Paa::try_from(&opaque).unwrap();
+    assert_eq!(PaaType::DXT1, paa.paa_type);
+    assert!(paa.is_compressed());
+    assert_eq!(7, paa.mipmap_count());
+
+    let img = block_texture(64, |x, _| x as u8 * 4);
+    let paa = Paa::try_from(&img).unwrap();
+    assert_eq!(PaaType::DXT5, paa.paa_type);
+    assert!(paa.is_compressed());
+    assert_eq!(7, paa.mipmap_count());
+
+    let dir = tempfile::tempdir().unwrap();
+    let (source, target) = (dir.path().join("texture.png"), dir.path().join("texture.paa"));
+    img.save(&source).unwrap();
+    assert!(cmd_img2paa(&source, &target, None, &EncodeOptions::default(), false, OutputPolicy::Overwrite, None).unwrap());
+    let mut output = Cursor::new(Vec::new());
+    paa.write(&mut output).unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), output.into_inner());
+
+    let decoded = image::DynamicImage::try_from(&paa).unwrap();
+    assert_eq!(paa.to_image().unwrap().to_rgba8().into_raw(), decoded.to_rgba8().into_raw());
+    assert_eq!((64, 64), (decoded.width(), decoded.height()));
+}
+
+#[test]
+fn test_paa_write_forward_only() {
+    let img = block_texture(64, |x, y| (x * 16 + y) as u8);
+    let lzo = Paa::from_image(&img, PaaType::DXT5, true).unwrap();
+    let argb = Paa::from_image(&img, PaaType::ARGB4444, false).unwrap();
+    let read = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF01, &[(8, 8, vec![0x11; 32]), (4, 4, vec![0x22; 8])]))).unwrap();
+
+    for paa in [lzo, argb, read] {
+        let mut seeking = Cursor::new(Vec::new());
+        paa.write(&mut seeking).unwrap();
+
+        // Vec<u8> can't seek
+        let mut forward: Vec<u8> = Vec::new();
+        paa.write_to(&mut forward).unwrap();
+
+        assert_eq!(seeking.into_inner(), forward);
+        assert_eq!(paa.mipmap_count(), Paa::from_reader(&mut Cursor::new(forward)).unwrap().mipmap_count());
+    }
+}
+
+/// MiniLzo that counts how often it was asked to compress
+struct CountingLzo(std::cell::Cell<usize>);
+
+impl LzoCodec for CountingLzo {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        self.0.set(self.0.get() + 1);
+        MiniLzo.compress(data)
+    }
+
+    fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>, std::io::Error> {
+        MiniLzo.decompress(data, size)
+    }
+}
+
+#[test]
+fn test_paa_lzo_policy() {
+    let mut state: u32 = 12345;
+    let noise = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(256, 256, |_, _| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        image::Rgba((state >> 3).to_le_bytes())
+    }));
+    let flat = image::DynamicImage::new_rgba8(256, 256);
+
+    // Mipmaps down to 16x16 are large enough to be compressed
+    let attempts = |img: &image::DynamicImage, lzo: LzoPolicy| {
+        let codec = CountingLzo(std::cell::Cell::new(0));
+        let paa = Paa::from_image_with_options(img, PaaType::ARGB4444, &EncodeOptions { lzo, ..Default::default() }, &codec, None).unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        paa.write_to(&mut output).unwrap();
+        let reread = Paa::from_reader(&mut Cursor::new(output)).unwrap();
+        assert_eq!(paa.mipmap_count(), reread.mipmap_count());
+        let uncompressed = Paa::from_image(img, PaaType::ARGB4444, false).unwrap();
+        assert_eq!(uncompressed.to_image().unwrap().to_rgba8().into_raw(), reread.to_image().unwrap().to_rgba8().into_raw());
+
+        (codec.0.get(), paa.mipmap_stats())
+    };
+
+    let adaptive = LzoPolicy::Adaptive { min_savings: 10.0 };
+    assert_eq!(5, attempts(&noise, LzoPolicy::Always).0);
+    assert_eq!(0, attempts(&noise, LzoPolicy::Never).0);
+    assert_eq!(1, attempts(&noise, adaptive).0);
+    assert_eq!(5, attempts(&flat, adaptive).0);
+
+    let (_, stats) = attempts(&flat, LzoPolicy::Always);
+    assert_eq!((256, 256, 256 * 256 * 2), (stats[0].width, stats[0].height, stats[0].original_size));
+    assert!(stats[0].stored_size < stats[0].original_size / 10);
+    let (_, stats) = attempts(&flat, LzoPolicy::Never);
+    assert!(stats.iter().all(|m| m.stored_size == m.original_size));
+
+    assert_eq!(LzoPolicy::Adaptive { min_savings: 5.0 }, parse_lzo_policy("adaptive:5").unwrap());
+    assert_eq!(LzoPolicy::Adaptive { min_savings: 10.0 }, parse_lzo_policy("Adaptive").unwrap());
+    assert_eq!(LzoPolicy::Never, parse_lzo_policy("never").unwrap());
+    assert!(parse_lzo_policy("adaptive:200").is_err());
+    assert!(parse_lzo_policy("sometimes").is_err());
+}
+
+#[test]
+fn test_paa_downscaled() {
+    let img = block_texture(64, |_, _| 255);
+    let paa = Paa::from_image(&img, PaaType::DXT1, true).unwrap();
+    assert!(paa.downscaled(64).unwrap().is_none());
+
+    // Smaller mipmaps are reused
+    let small = paa.downscaled(40).unwrap().unwrap();
+    assert_eq!(Some((32, 32)), small.dimensions(0));
+    assert_eq!(PaaType::DXT1, small.paa_type);
+    assert!(small.is_compressed());
+    assert_eq!(paa.to_image_level(1).unwrap().to_rgba8().into_raw(), small.to_image().unwrap().to_rgba8().into_raw());
+
+    // Without mipmaps, the base level is resized and encoded again
+    let single = Paa::from_reader(&mut Cursor::new(paa_bytes(0xFF05, &[(16, 8, vec![0; 128])]))).unwrap();
+    let resized = single.downscaled(4).unwrap().unwrap();
+    assert_eq!(Some((4, 2)), resized.dimensions(0));
+    assert_eq!(PaaType::DXT5, resized.paa_type);
+
+    // Sizes are rounded down to powers of two
+    let resized = single.downscaled(6).unwrap().unwrap();
+    assert_eq!(Some((4, 2)), resized.dimensions(0));
+}
+
+#[test]
+fn test_img2paa_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let target = dir.path().join("target");
+    std::fs::create_dir_all(source.join("data")).unwrap();
+
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(8, 8, |x, y| image::Rgba([(x * 32) as u8, (y * 32) as u8, 0, 255])));
+    img.save(source.join("a_co.png")).unwrap();
+    img.save(source.join("data").join("b_co.PNG")).unwrap();
+    std::fs::write(source.join("data").join("broken.png"), b"not an image").unwrap();
+    std::fs::write(source.join("readme.txt"), b"").unwrap();
+
+    let options = EncodeOptions::default();
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT5), &options, OutputPolicy::SkipExisting, None).unwrap();
+    assert_eq!(BatchSummary { converted: 2, skipped: 0, failed: 1 }, summary);
+    assert_eq!("2 converted, 0 skipped, 1 failed", summary.to_string());
+    assert!(target.join("data").join("b_co.paa").exists());
+    assert!(!target.join("data").join("broken.paa").exists());
+
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT5), &options, OutputPolicy::SkipExisting, None).unwrap();
+    assert_eq!(BatchSummary { converted: 0, skipped: 2, failed: 1 }, summary);
+
+    let images = dir.path().join("images");
+    let summary = cmd_paa2img_folder(&target, &images, "png", 0, &DecodeOptions::default(), OutputPolicy::Fail).unwrap();
+    assert_eq!(2, summary.converted);
+    assert!(image::open(images.join("a_co.png")).is_ok());
+
+    // Unlike the batch, a single conversion fails on an existing target
+    assert!(cmd_paa2img(&target.join("a_co.paa"), &images.join("a_co.png"), 0, &DecodeOptions::default(), OutputPolicy::Fail).is_err());
+}
+
+#[test]
+fn test_img2paa_folder_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let target = dir.path().join("target");
+    let manifest = dir.path().join("jobs.json");
+    std::fs::create_dir_all(&source).unwrap();
+
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(8, 8, |x, y| image::Rgba([(x * 32) as u8, (y * 32) as u8, 0, 255])));
+    for name in ["a_co.png", "b_co.png", "c_co.png"] {
+        img.save(source.join(name)).unwrap();
+    }
+    std::fs::write(source.join("broken_co.png"), b"not an image").unwrap();
+
+    // The broken file stops the first run short of the whole batch
+    let options = EncodeOptions::default();
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT5), &options, OutputPolicy::Fail, Some(&manifest)).unwrap();
+    assert_eq!(BatchSummary { converted: 3, skipped: 0, failed: 1 }, summary);
+
+    let jobs = JobManifest::read(&manifest).unwrap();
+    assert_eq!(4, jobs.len());
+    let failed: Vec<&Job> = jobs.iter().filter(|job| job.status == JobStatus::Failed).collect();
+    assert_eq!(1, failed.len());
+    assert_eq!(source.join("broken_co.png"), failed[0].source);
+    assert_eq!(target.join("broken_co.paa"), failed[0].target);
+    assert!(failed[0].error.as_ref().unwrap().contains("Failed to open image"), "{:?}", failed[0].error);
+    assert!(jobs.iter().filter(|job| job.status == JobStatus::Done).all(|job| job.error.is_none() && job.target.exists()));
+
+    // Running it again only converts the fixed file, although the targets of the others exist
+    img.save(source.join("broken_co.png")).unwrap();
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT5), &options, OutputPolicy::Fail, Some(&manifest)).unwrap();
+    assert_eq!(BatchSummary { converted: 1, skipped: 3, failed: 0 }, summary);
+    assert!(JobManifest::read(&manifest).unwrap().iter().all(|job| job.status == JobStatus::Done));
+
+    // Changed sources, missing targets and unfinished jobs run again
+    let blue = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(8, 8, |_, _| image::Rgba([0, 0, 255, 255])));
+    blue.save(source.join("a_co.png")).unwrap();
+    std::fs::remove_file(target.join("b_co.paa")).unwrap();
+    let mut content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!("c_co.png", Path::new(content["jobs"][3]["source"].as_str().unwrap()).file_name().unwrap());
+    content["jobs"][3]["status"] = "pending".into();
+    std::fs::write(&manifest, content.to_string()).unwrap();
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT5), &options, OutputPolicy::Overwrite, Some(&manifest)).unwrap();
+    assert_eq!(BatchSummary { converted: 3, skipped: 1, failed: 0 }, summary);
+
+    // So do all jobs with other options
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT1), &options, OutputPolicy::Overwrite, Some(&manifest)).unwrap();
+    assert_eq!(BatchSummary { converted: 4, skipped: 0, failed: 0 }, summary);
+    let summary = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT1), &options, OutputPolicy::Overwrite, Some(&manifest)).unwrap();
+    assert_eq!(BatchSummary { converted: 0, skipped: 4, failed: 0 }, summary);
+}
+
+#[test]
+fn test_paa_round_trip_matrix() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(32, 16, |x, y| {
+        image::Rgba([(x / 8 * 64) as u8, (y / 4 * 64) as u8, 0x80, if x < 16 { 255 } else { 0 }])
+    }));
+
+    for paa_type in [PaaType::DXT1, PaaType::DXT3, PaaType::DXT5, PaaType::ARGB4444, PaaType::ARGB1555, PaaType::ARGB8888, PaaType::AI88] {
+        for compress in [false, true] {
+            let mut output: Vec<u8> = Vec::new();
+            Paa::from_image(&img, paa_type, compress).unwrap().write_to(&mut output).unwrap();
+            assert_eq!(paa_type as u16, u16::from_le_bytes([output[0], output[1]]));
+
+            let paa = Paa::from_reader(&mut Cursor::new(output)).unwrap();
+            assert_eq!(paa_type, paa.paa_type);
+            assert_eq!(compress, paa.is_compressed(), "{:?}", paa_type);
+            assert_eq!(6, paa.mipmap_count());
+            let decoded = paa.to_image().unwrap();
+            assert_eq!((32, 16), (decoded.width(), decoded.height()));
+
+            // ARGB8888 is the only lossless type
+            if paa_type == PaaType::ARGB8888 {
+                assert_eq!(img.to_rgba8().into_raw(), decoded.to_rgba8().into_raw());
+            }
+        }
+    }
+
+    // Pixels are stored as little endian ARGB values, like ARGB4444
+    let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(0x8888, &[(1, 1, vec![0x10, 0x20, 0x30, 0x40])]))).unwrap();
+    assert_eq!(PaaType::ARGB8888, paa.paa_type);
+    assert_eq!(vec![0x30, 0x20, 0x10, 0x40], paa.to_image().unwrap().to_rgba8().into_raw());
+    assert_eq!(PaaType::ARGB8888, parse_paa_type("argb8888").unwrap());
+}
+
+#[test]
+fn test_paa_dxt3() {
+    // DXT3 stores 4 bits of alpha per pixel, so multiples of 17 survive exactly in any pattern
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(16, 16, |x, y| image::Rgba([0x40, 0x80, 0xc0, ((x + y * 3) % 16 * 17) as u8])));
+    for quality in [EncodeQuality::Default, EncodeQuality::Exhaustive] {
+        let paa = Paa::from_image_with_options(&img, PaaType::DXT3, &EncodeOptions { quality, ..Default::default() }, &MiniLzo, None).unwrap();
+        let decoded = paa.to_image().unwrap().to_rgba8();
+        for (source, decoded) in img.to_rgba8().pixels().zip(decoded.pixels()) {
+            assert_eq!(source[3], decoded[3]);
+        }
+    }
+    assert_eq!(PaaType::DXT3, parse_paa_type("dxt3").unwrap());
+}
+
+#[test]
+fn test_paa_auto_type() {
+    let image = |alpha: &dyn Fn(u32, u32) -> u8| image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(64, 64, |x, y| image::Rgba([200, 100, 50, alpha(x, y)])));
+    assert_eq!(None, parse_paa_type_or_auto("AUTO").unwrap());
+    assert_eq!(Some(PaaType::DXT5), parse_paa_type_or_auto("dxt5").unwrap());
+
+    assert_eq!(PaaType::DXT1, auto_paa_type(&image(&|_, _| 255)));
+    assert_eq!(PaaType::DXT1, auto_paa_type(&image::DynamicImage::ImageRgb8(image::ImageBuffer::new(8, 8))));
+    assert_eq!(PaaType::DXT5, auto_paa_type(&image(&|x, _| if x < 32 { 255 } else { 128 })));
+    // A transparent border and a stray pixel don't count
+    let bordered = image(&|x, y| if x == 0 || y == 0 || x == 63 || y == 63 || (x, y) == (10, 10) { 0 } else { 255 });
+    assert_eq!(PaaType::DXT1, auto_paa_type(&bordered));
+    assert_eq!(1.0 / (62.0 * 62.0), transparent_share(&bordered));
+    assert_eq!(PaaType::DXT5, auto_paa_type(&image(&|x, y| if x < 8 && y < 8 { 0 } else { 255 })));
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("opaque.png");
+    image(&|_, _| 255).save(&source).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).args(["img2paa".as_ref(), "-v".as_ref(), source.as_os_str(), dir.path().join("opaque.paa").as_os_str()]).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Picked DXT1 for"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(PaaType::DXT1, Paa::from_file(&dir.path().join("opaque.paa")).unwrap().paa_type);
+}
+
+#[test]
+fn test_paa_16bit_formats() {
+    // Bit layouts of single pixels: ARGB4444 and ARGB1555 have alpha in the highest bits, AI88
+    // stores intensity then alpha
+    let pixel = |paa_type: u16, data: [u8; 2]| {
+        let paa = Paa::from_reader(&mut Cursor::new(paa_bytes(paa_type, &[(1, 1, data.to_vec())]))).unwrap();
+        paa.to_image().unwrap().to_rgba8().into_raw()
+    };
+    assert_eq!(vec![0x11, 0x22, 0x33, 0xff], pixel(0x4444, 0xf123u16.to_le_bytes()));
+    assert_eq!(vec![0xff, 0x00, 0x84, 0xff], pixel(0x1555, 0xfc10u16.to_le_bytes()));
+    assert_eq!(vec![0x00, 0xff, 0x00, 0x00], pixel(0x1555, 0x03e0u16.to_le_bytes()));
+    assert_eq!(vec![0x40, 0x40, 0x40, 0x80], pixel(0x8080, [0x40, 0x80]));
+    assert_eq!(PaaType::ARGB1555, parse_paa_type("argb1555").unwrap());
+
+    // Values each format stores exactly survive a round trip, the others are rounded to the nearest
+    let roundtrip = |paa_type: PaaType, img: &image::DynamicImage| {
+        let mut output: Vec<u8> = Vec::new();
+        Paa::from_image(img, paa_type, true).unwrap().write_to(&mut output).unwrap();
+        let paa = Paa::from_reader(&mut Cursor::new(output)).unwrap();
+        paa.to_image().unwrap().to_rgba8()
+    };
+    let exact_4444 = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(8, 4, |x, y| image::Rgba([(x * 34) as u8, (y * 51) as u8, 255 - (x * 17) as u8, ((x * y) % 16 * 17) as u8])));
+    assert_eq!(exact_4444.to_rgba8(), roundtrip(PaaType::ARGB4444, &exact_4444));
+
+    let expand = |v: u32| ((v << 3) | (v >> 2)) as u8;
+    let exact_1555 = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(8, 4, |x, y| image::Rgba([expand(x * 4), expand(y * 10), expand(31 - x), if (x + y) % 2 == 0 { 255 } else { 0 }])));
+    assert_eq!(exact_1555.to_rgba8(), roundtrip(PaaType::ARGB1555, &exact_1555));
+    let rounded = roundtrip(PaaType::ARGB1555, &image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(8, 4, image::Rgba([5, 250, 130, 127]))));
+    assert_eq!([8, 247, 132, 0], rounded.get_pixel(0, 0).0);
+
+    let exact_ai88 = image::DynamicImage::ImageLumaA8(image::ImageBuffer::from_fn(8, 4, |x, y| image::LumaA([(x * 31) as u8, (y * 80) as u8])));
+    let decoded = roundtrip(PaaType::AI88, &exact_ai88);
+    for (x, y, pixel) in decoded.enumerate_pixels() {
+        assert_eq!([(x * 31) as u8, (x * 31) as u8, (x * 31) as u8, (y * 80) as u8], pixel.0);
+    }
+}
+
+fn flipped(img: &image::DynamicImage, vertical: bool, horizontal: bool) -> Vec<u8> {
+    let img = if vertical { img.flipv() } else { img.clone() };
+    let img = if horizontal { img.fliph() } else { img };
+    img.to_rgba8().into_raw()
+}
+
+#[test]
+fn test_paa_flip() {
+    // Uniform 4x4 blocks keep DXT lossless, so every type can be compared exactly
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(16, 8, |x, y| {
+        image::Rgba([(x / 4 * 80) as u8, (y / 4 * 200) as u8, 0x40, if x < 8 { 255 } else { 0 }])
+    }));
+
+    for paa_type in [PaaType::DXT1, PaaType::DXT5, PaaType::ARGB8888] {
+        for lzo in [LzoPolicy::Never, LzoPolicy::Always] {
+            let plain = Paa::from_image_with_options(&img, paa_type, &EncodeOptions { lzo, ..Default::default() }, &MiniLzo, None).unwrap();
+            let mut plain_bytes: Vec<u8> = Vec::new();
+            plain.write_to(&mut plain_bytes).unwrap();
+            let reference = plain.to_image().unwrap();
+
+            for (vertical, horizontal) in [(true, false), (false, true), (true, true)] {
+                let options = EncodeOptions { lzo, flip_vertical: vertical, flip_horizontal: horizontal, ..Default::default() };
+                let paa = Paa::from_image_with_options(&img, paa_type, &options, &MiniLzo, None).unwrap();
+                let expected = flipped(&reference, vertical, horizontal);
+                assert_eq!(expected, paa.to_image().unwrap().to_rgba8().into_raw(), "{:?} {} {}", paa_type, vertical, horizontal);
+
+                // Flipping while decoding undoes it, also with strict decoding
+                for strict in [false, true] {
+                    let decode = DecodeOptions { strict, flip_vertical: vertical, flip_horizontal: horizontal };
+                    assert_eq!(reference.to_rgba8().into_raw(), paa.to_image_with_options(&decode).unwrap().to_rgba8().into_raw());
+                    assert_eq!(expected, plain.to_image_with_options(&decode).unwrap().to_rgba8().into_raw());
+                }
+
+                // The taggs describe the same pixels
+                let mut bytes: Vec<u8> = Vec::new();
+                paa.write_to(&mut bytes).unwrap();
+                for name in [b"GGATCGVA", b"GGATCXAM"] {
+                    let tagg = |bytes: &[u8]| {
+                        let start = bytes.windows(8).position(|w| w == name).unwrap();
+                        bytes[start..start + 16].to_vec()
+                    };
+                    assert_eq!(tagg(&plain_bytes), tagg(&bytes));
+                }
+
+                // Mipmaps are generated from the flipped image
+                if paa_type == PaaType::ARGB8888 {
+                    for index in 1..paa.mipmap_count() {
+                        let expected = flipped(&plain.to_image_level(index).unwrap(), vertical, horizontal);
+                        let actual = paa.to_image_level(index).unwrap().to_rgba8().into_raw();
+                        assert!(expected.iter().zip(&actual).all(|(a, b)| a.abs_diff(*b) <= 1), "mipmap {}", index);
+                    }
+                }
+            }
+        }
+    }
+
+    // Strips come from top to bottom, so only horizontal flips are possible
+    let paa = Paa::from_image(&img, PaaType::ARGB8888, false).unwrap();
+    let mut strips: Vec<u8> = Vec::new();
+    paa.decode_strips_with_options(0, &DecodeOptions { flip_horizontal: true, ..Default::default() }, |_, strip| {
+        strips.extend_from_slice(strip);
+        Ok(())
+    }).unwrap();
+    assert_eq!(flipped(&img, false, true), strips);
+    assert!(paa.decode_strips_with_options(0, &DecodeOptions { flip_vertical: true, ..Default::default() }, |_, _| Ok(())).is_err());
+}
+
+#[test]
+fn test_paa_mipmap_size_limit() {
+    // 2048x1024 ARGB8888 is exactly 0x800000 bytes, which sets the bit that also flags compression
+    let level = RawMipmap { width: 2048, height: 1024, data: vec![0x7F; 0x800000] };
+    let paa = Paa::from_raw_mipmaps(PaaType::ARGB8888, vec![level.clone()], &RawMipmapOptions::default()).unwrap();
+
+    let mut seekable: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    paa.write(&mut seekable).unwrap();
+    let mut forward: Vec<u8> = Vec::new();
+    paa.write_to(&mut forward).unwrap();
+    assert_eq!(seekable.get_ref(), &forward);
+
+    let mipmap = &raw_mipmaps(&forward)[0];
+    assert_eq!([0x00, 0x08, 0x00, 0x04, 0x00, 0x00, 0x80], mipmap[..7]);
+    let reread = Paa::from_reader(&mut Cursor::new(&forward)).unwrap();
+    assert!(!reread.is_compressed());
+    assert_eq!(level.data, reread.to_image_level(0).unwrap().to_rgba8().into_raw());
+
+    // One byte less is 0x7FFFFF, which doesn't flag compression either and is read as truncated data
+    let mut truncated = level.data.clone();
+    truncated.pop();
+    let input = paa_bytes(0x8888, &[(2048, 1024, truncated)]);
+    assert_eq!([0xFF, 0xFF, 0x7F], input[(input.len() - 0x7FFFFF - 3)..(input.len() - 0x7FFFFF)]);
+    assert!(!Paa::from_reader(&mut Cursor::new(&input)).unwrap().is_compressed());
+
+    // 2048x2048 ARGB8888 doesn't fit, unless LZO makes it small enough
+    let square = RawMipmap { width: 2048, height: 2048, data: vec![0x7F; 0x1000000] };
+    let error = Paa::from_raw_mipmaps(PaaType::ARGB8888, vec![square.clone()], &RawMipmapOptions::default()).unwrap_err();
+    assert!(error.to_string().contains("16777216 bytes of ARGB8888 data"), "{}", error);
+    assert!(error.to_string().contains("Enable LZO compression"), "{}", error);
+    assert!(Paa::from_raw_mipmaps(PaaType::ARGB8888, vec![square], &RawMipmapOptions { compress: true }).is_ok());
+
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(2048, 2048, image::Rgba([1, 2, 3, 4])));
+    assert!(Paa::from_image(&img, PaaType::ARGB8888, false).is_err());
+
+    // Older writers stored such a mipmap with the lower 24 bits of its size, 0 here
+    let mut input = paa_bytes(0x8888, &[(2048, 2048, Vec::new())]);
+    input.extend(vec![0x7F; 0x1000000]);
+    let error = Paa::from_reader(&mut Cursor::new(&input)).unwrap_err();
+    assert!(error.to_string().contains("truncated size"), "{}", error);
+}
+
+#[test]
+fn test_paa_mipmap_counts() {
+    // Red and blue match, so the order ARGB8888 stores them in doesn't matter
+    let pixel = |level: usize, x: usize| [level as u8, x as u8, level as u8, 0xFF];
+
+    // 17 levels don't fit into the halving chain of PAAs the encoder writes, a generated file
+    // can still list that many
+    for count in [1usize, 15, 17] {
+        let levels: Vec<(u16, u16, Vec<u8>)> = (0..count).map(|level| {
+            let width = count - level;
+            (width as u16, 1, (0..width).flat_map(|x| pixel(level, x)).collect())
+        }).collect();
+        let input = paa_bytes(0x8888, &levels);
+        assert_eq!(count, raw_mipmaps(&input).len());
+
+        let paa = Paa::from_reader(&mut Cursor::new(&input)).unwrap();
+        assert_eq!(count, paa.mipmap_count());
+
+        let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        paa.write(&mut output).unwrap();
+        let mut forward: Vec<u8> = Vec::new();
+        paa.write_to(&mut forward).unwrap();
+        assert_eq!(output.get_ref(), &forward);
+
+        let written = raw_mipmaps(&forward);
+        assert_eq!(count, written.len());
+        for (level, (mipmap, (width, _, data))) in written.iter().zip(&levels).enumerate() {
+            assert_eq!(*width, u16::from_le_bytes([mipmap[0], mipmap[1]]), "level {} of {}", level, count);
+            assert_eq!(&data[..], &mipmap[7..]);
+        }
+
+        let reread = Paa::from_reader(&mut Cursor::new(&forward)).unwrap();
+        assert_eq!(count, reread.mipmap_count());
+        let last = reread.to_image_level(count - 1).unwrap().to_rgba8().into_raw();
+        assert_eq!(pixel(count - 1, 0).to_vec(), last);
+    }
+}
+
+#[test]
+fn test_paa_encoder_levels() {
+    assert_eq!(1, mipmap_levels(1, 1));
+    assert_eq!(7, mipmap_levels(64, 16));
+    assert_eq!(7, mipmap_levels(100, 3));
+    assert_eq!(15, mipmap_levels(MAX_MIPMAP_DIMENSION - 1, 1));
+
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(64, 16, image::Rgba([10, 20, 30, 255])));
+    assert_eq!(7, Paa::from_image(&img, PaaType::ARGB4444, false).unwrap().mipmap_count());
+
+    let wide = image::DynamicImage::ImageRgba8(image::RgbaImage::new(MAX_MIPMAP_DIMENSION, 1));
+    assert!(Paa::from_image(&wide, PaaType::ARGB4444, false).is_err());
+}
+
+#[test]
+fn test_paa_retype() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.paa");
+    let target = dir.path().join("target.paa");
+    let types = [PaaType::DXT1, PaaType::DXT5, PaaType::ARGB4444, PaaType::ARGB8888];
+
+    for from in types {
+        for to in types.iter().copied().filter(|t| *t != from) {
+            // Uniform 4x4 blocks with multiples of 17 survive every type, DXT1 only keeps opaque alpha
+            let opaque = from == PaaType::DXT1 || to == PaaType::DXT1;
+            let img = block_texture(64, |bx, by| if opaque { 255 } else { ((bx + by) % 16 * 17) as u8 });
+            Paa::from_image(&img, from, true).unwrap().write_to_file(&source).unwrap();
+            cmd_paa_set(&source, &source, &TaggEdits { flag: Some(1), swizzle: Some(parse_swizzle("1-A,R,G,B").unwrap()), ..Default::default() }, false).unwrap();
+
+            cmd_paa_retype(&source, &target, to, false, true).unwrap();
+            let original = Paa::from_file(&source).unwrap();
+            let retyped = Paa::from_file(&target).unwrap();
+            assert_eq!(to, retyped.paa_type);
+            assert_eq!(original.mipmap_count(), retyped.mipmap_count());
+            assert!(original.is_compressed() && retyped.is_compressed(), "{:?} to {:?}", from, to);
+            // Decoding moves the inverted alpha of the swizzle back
+            let mut unswizzled = img.to_rgba8();
+            unswizzled.pixels_mut().for_each(|p| p[3] = 255 - p[3]);
+            assert_eq!(unswizzled, retyped.to_image().unwrap().to_rgba8(), "{:?} to {:?}", from, to);
+
+            let bytes = std::fs::read(&target).unwrap();
+            assert!(bytes.windows(8).any(|w| w == b"GGATZIWS"), "{:?} to {:?}", from, to);
+            assert!(bytes.windows(8).any(|w| w == b"GGATGALF"), "{:?} to {:?}", from, to);
+            assert_eq!(1, bytes.windows(8).filter(|w| w == b"GGATCGVA").count());
+        }
+    }
+
+    let img = block_texture(16, |bx, _| (bx * 64) as u8);
+    Paa::from_image(&img, PaaType::DXT5, false).unwrap().write_to_file(&source).unwrap();
+    let error = cmd_paa_retype(&source, &target, PaaType::DXT1, false, true).unwrap_err();
+    assert!(error.to_string().contains("--allow-lossy-alpha"), "{}", error);
+    cmd_paa_retype(&source, &target, PaaType::DXT1, true, true).unwrap();
+    assert_eq!(PaaType::DXT1, Paa::from_file(&target).unwrap().paa_type);
+
+    let error = cmd_paa_retype(&source, &target, PaaType::DXT1, true, false).unwrap_err();
+    assert_eq!((std::io::ErrorKind::AlreadyExists, Some("E0805")), (error.kind(), coded(&error).map(|c| c.code)));
+    assert!(cmd_paa_retype(&source, &source, PaaType::DXT5, false, false).is_err());
+}
+
+#[test]
+fn test_paa_raw_mipmap() {
+    let levels: Vec<RawMipmap> = [(32u16, 16u16), (16, 8), (8, 4), (4, 2), (2, 1), (1, 1)].iter()
+        .map(|(w, h)| {
+            let size = (*w as usize).div_ceil(4) * (*h as usize).div_ceil(4) * 16;
+            RawMipmap { width: *w, height: *h, data: (0..size).map(|i| (i * 7 % 5) as u8).collect() }
+        })
+        .collect();
+
+    for compress in [false, true] {
+        let paa = Paa::from_raw_mipmaps(PaaType::DXT5, levels.clone(), &RawMipmapOptions { compress }).unwrap();
+        let mut output: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        paa.write(&mut output).unwrap();
+        let reread = Paa::from_reader(&mut Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(compress, reread.is_compressed());
+
+        for (i, level) in levels.iter().enumerate() {
+            let raw = reread.raw_mipmap(i).unwrap();
+            assert_eq!(BcFormat::Bc3, raw.format);
+            assert_eq!((level.width as u32, level.height as u32), (raw.width, raw.height));
+            assert_eq!(&level.data[..], &raw.data[..]);
+
+            // The same blocks the decoder decompresses
+            let (width, height) = (level.width as usize, level.height as usize);
+            let mut expected = vec![0u8; width * height * 4];
+            texpresso::Format::Bc3.decompress(&raw.data, width, height, &mut expected);
+            assert_eq!(expected, reread.to_image_level(i).unwrap().to_rgba8().into_raw());
+        }
+        assert!(reread.raw_mipmap(levels.len()).is_err());
+    }
+
+    let argb = Paa::from_image(&image::DynamicImage::new_rgba8(4, 4), PaaType::ARGB8888, false).unwrap();
+    let error = argb.raw_mipmap(0).unwrap_err().to_string();
+    assert!(error.contains("aren't block compressed"), "{}", error);
+}
+
+#[test]
+fn test_paa_exhaustive_quality() {
+    // Banding-prone gradient with a little alpha variation
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 32, |x, y| {
+        image::Rgba([(x * 4) as u8, (y * 3 + x) as u8, (255 - x * 2) as u8, (200 + y) as u8])
+    }));
+    let source = img.to_rgba8().into_raw();
+    let squared_error = |paa: &Paa| -> u64 {
+        paa.to_image().unwrap().to_rgba8().into_raw().iter().zip(&source).map(|(a, b)| (*a as i64 - *b as i64).pow(2) as u64).sum()
+    };
+
+    for paa_type in [PaaType::DXT1, PaaType::DXT5] {
+        let (default, report) = Paa::from_image_with_report(&img, paa_type, &EncodeOptions::default(), &MiniLzo, None).unwrap();
+        assert!(report.is_none());
+
+        let options = EncodeOptions { quality: EncodeQuality::Exhaustive, ..Default::default() };
+        let (exhaustive, report) = Paa::from_image_with_report(&img, paa_type, &options, &MiniLzo, None).unwrap();
+        let report = report.unwrap();
+
+        assert_eq!(default.mipmap_count(), exhaustive.mipmap_count());
+        assert!(squared_error(&exhaustive) <= squared_error(&default), "{:?}", paa_type);
+        assert!(report.error <= report.default_error);
+        assert!(report.average_improvement() >= 0.0);
+        // 16x8 blocks in the base level, then 8x4, 4x2 and 2x1, and one for each of the 3 smallest levels
+        assert_eq!(128 + 32 + 8 + 2 + 1 + 1 + 1, report.blocks);
+    }
+
+    // The faster fits pick other endpoints for the gradient
+    let encode = |quality: EncodeQuality| Paa::from_image_with_report(&img, PaaType::DXT1, &EncodeOptions { quality, ..Default::default() }, &MiniLzo, None).unwrap();
+    let (default, (fast, report)) = (encode(EncodeQuality::Default).0, encode(EncodeQuality::Fast));
+    assert!(report.is_none());
+    assert_eq!(default.mipmap_count(), fast.mipmap_count());
+    assert_ne!(default.raw_mipmap(0).unwrap(), fast.raw_mipmap(0).unwrap());
+
+    assert_eq!(EncodeQuality::Exhaustive, parse_encode_quality("Exhaustive").unwrap());
+    assert_eq!(EncodeQuality::Fast, parse_encode_quality("fast").unwrap());
+    assert_eq!(EncodeQuality::Cluster, parse_encode_quality("cluster").unwrap());
+    assert!(parse_encode_quality("best").is_err());
+}
+
+#[test]
+fn test_paa_thumbnail() {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(256, 128, |x, y| image::Rgba([x as u8, y as u8, 0, 255])));
+    let paa = Paa::from_image(&img, PaaType::DXT5, false).unwrap();
+
+    // 64x32 is the smallest mipmap that is large enough, so it's used as it is
+    assert_eq!((64, 32), image::GenericImageView::dimensions(&paa.thumbnail(64).unwrap()));
+    assert_eq!((100, 50), image::GenericImageView::dimensions(&paa.thumbnail(100).unwrap()));
+    assert_eq!((256, 128), image::GenericImageView::dimensions(&paa.thumbnail(512).unwrap()));
+    assert!(paa.thumbnail(0).is_err());
+}
+
+#[test]
+fn test_paa_read_thumbnail() {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(256, 128, |x, y| image::Rgba([x as u8, y as u8, 0, 255])));
+    let paa = Paa::from_image(&img, PaaType::DXT5, true).unwrap();
+    let mut bytes = Cursor::new(Vec::new());
+    paa.write_to(&mut bytes).unwrap();
+    let mut bytes = bytes.into_inner();
+
+    let thumbnail = Paa::read_thumbnail(&mut Cursor::new(&bytes), 64).unwrap();
+    assert_eq!(paa.thumbnail(64).unwrap().to_rgba8(), thumbnail.to_rgba8());
+    assert_eq!((100, 50), image::GenericImageView::dimensions(&Paa::read_thumbnail(&mut Cursor::new(&bytes), 100).unwrap()));
+    assert!(Paa::read_thumbnail(&mut Cursor::new(&bytes), 0).is_err());
+
+    // Levels that aren't needed aren't read, so a corrupt base level doesn't matter
+    let base = PaaHeader::read(&mut Cursor::new(&bytes)).unwrap().mipmaps[0].2 as usize;
+    bytes[(base + 7)..(base + 64)].fill(0xFF);
+    assert!(Paa::from_reader(&mut Cursor::new(&bytes)).unwrap().to_image().is_err());
+    assert_eq!(thumbnail.to_rgba8(), Paa::read_thumbnail(&mut Cursor::new(&bytes), 64).unwrap().to_rgba8());
+}
+
+#[test]
+fn test_paa_thumbs() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let target = dir.path().join("thumbs");
+    std::fs::create_dir_all(source.join("data")).unwrap();
+
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(128, 128, |x, y| image::Rgba([x as u8, y as u8, 0, 255])));
+    let paa = Paa::from_image(&img, PaaType::DXT1, true).unwrap();
+    paa.write_to_file(source.join("data").join("gun_co.paa")).unwrap();
+    paa.write_to_file(source.join("renamed.bin")).unwrap();
+    std::fs::write(source.join("readme.txt"), "not a texture").unwrap();
+
+    // Known type and tagg, but the offsets point past the end
+    let mut broken = paa_bytes(0xFF01, &[(4, 4, vec![0; 8])]);
+    broken.truncate(broken.len() - 10);
+    std::fs::write(source.join("broken.paa"), broken).unwrap();
+
+    let summary = cmd_paa_thumbs(&source, &target, 32).unwrap();
+    assert_eq!(BatchSummary { converted: 2, skipped: 0, failed: 1 }, summary);
+    assert_eq!((32, 32), image::image_dimensions(target.join("data").join("gun_co.png")).unwrap());
+    assert_eq!((32, 32), image::image_dimensions(target.join("renamed.png")).unwrap());
+    assert_eq!((32, 32), image::image_dimensions(target.join("broken.png")).unwrap());
+    assert!(!target.join("readme.png").exists());
+
+    // Thumbnails newer than their texture are kept
+    let summary = cmd_paa_thumbs(&source, &target, 32).unwrap();
+    assert_eq!(BatchSummary { converted: 0, skipped: 3, failed: 0 }, summary);
+}
+
+/// Reads a PAA like the engine does, independently of `Paa`: the type, the taggs it looks up by
+/// name and the mipmaps at their offsets.
+fn engine_read(bytes: &[u8]) -> (u16, Vec<([u8; 4], Vec<u8>)>, Vec<(u16, u16, Vec<u8>)>) {
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize;
+    let mut taggs = Vec::new();
+    let mut offsets = Vec::new();
+    let mut pos = 2;
+    while &bytes[pos..pos + 4] == b"GGAT" || &bytes[pos..pos + 4] == b"TAGG" {
+        let name = [bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]];
+        let length = u32_at(pos + 8);
+        let data = bytes[pos + 12..pos + 12 + length].to_vec();
+        match &name {
+            b"SFFO" => offsets = data.chunks(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize).filter(|o| *o != 0).collect(),
+            b"CGVA" | b"CXAM" | b"GALF" | b"ZIWS" => taggs.push((name, data)),
+            _ => {}
+        }
+        pos += 12 + length;
+    }
+
+    let mipmaps = offsets.iter().map(|o| {
+        let width = u16::from_le_bytes([bytes[*o], bytes[o + 1]]);
+        let height = u16::from_le_bytes([bytes[o + 2], bytes[o + 3]]);
+        let size = u32_at(o + 4) & 0xFF_FFFF;
+        (width, height, bytes[o + 7..o + 7 + size].to_vec())
+    }).collect();
+    (u16::from_le_bytes([bytes[0], bytes[1]]), taggs, mipmaps)
+}
+
+#[test]
+fn test_paa_tool_tagg() {
+    let dir = tempfile::tempdir().unwrap();
+    let img = block_texture(32, |bx, by| ((bx + by) * 30) as u8);
+    let options = EncodeOptions { lzo: LzoPolicy::Always, tag_tool: true, ..Default::default() };
+    let tagged = Paa::from_image_with_options(&img, PaaType::DXT5, &options, &MiniLzo, None).unwrap();
+    let untagged = Paa::from_image(&img, PaaType::DXT5, true).unwrap();
+    assert_eq!(Some(tool_name()), tagged.tool());
+    assert!(tool_name().starts_with("armake2 "));
+    assert_eq!(None, untagged.tool());
+
+    let mut bytes: Vec<u8> = Vec::new();
+    tagged.write_to(&mut bytes).unwrap();
+    let mut plain: Vec<u8> = Vec::new();
+    untagged.write_to(&mut plain).unwrap();
+    assert!(bytes.windows(8).any(|w| w == b"GGATLOOT"));
+    assert_eq!(engine_read(&plain), engine_read(&bytes));
+
+    // Kept by reading and writing, and by the commands that change taggs or data
+    let path = dir.path().join("tagged.paa");
+    std::fs::write(&path, &bytes).unwrap();
+    let mut written = Cursor::new(Vec::new());
+    Paa::from_file(&path).unwrap().write(&mut written).unwrap();
+    assert_eq!(bytes, written.into_inner());
+    assert_eq!(Some(tool_name()), PaaHeader::read(&mut Cursor::new(&bytes)).unwrap().tool);
+
+    cmd_paa_set(&path, &path, &TaggEdits { flag: Some(2), ..Default::default() }, true).unwrap();
+    assert_eq!(Some(tool_name()), Paa::from_file(&path).unwrap().tool());
+    let retyped = dir.path().join("retyped.paa");
+    cmd_paa_retype(&path, &retyped, PaaType::ARGB8888, false, false).unwrap();
+    assert_eq!(Some(tool_name()), Paa::from_file(&retyped).unwrap().tool());
+    assert_eq!(Some(tool_name()), Paa::from_file(&path).unwrap().downscaled(8).unwrap().unwrap().tool());
+
+    let mut removed = Paa::from_file(&path).unwrap();
+    removed.set_tool(None).unwrap();
+    assert_eq!(None, removed.tool());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_paa_info(&path, &mut output).unwrap();
+    let info = String::from_utf8(output).unwrap();
+    assert!(info.contains("Type:          DXT5\nSize:          32x32\n"), "{}", info);
+    assert!(info.contains(&format!("Flag:          2\nWritten by:    {}\n", tool_name())), "{}", info);
+    std::fs::write(&retyped, &plain).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    cmd_paa_info(&retyped, &mut output).unwrap();
+    assert!(!String::from_utf8(output).unwrap().contains("Written by"));
+}
+
+#[test]
+fn test_paa_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("levels.paa");
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(64, 32, |x, y| image::Rgba([(x * 4) as u8, (y * 8) as u8, 0, 255])));
+    let options = EncodeOptions { lzo: LzoPolicy::Always, ..Default::default() };
+    Paa::from_image_with_options(&img, PaaType::DXT1, &options, &MiniLzo, None).unwrap().write_to_file(&path).unwrap();
+
+    let paa = Paa::from_file(&path).unwrap();
+    assert_eq!(7, paa.mipmap_count());
+    assert_eq!(Some((16, 8)), paa.dimensions(2));
+    assert_eq!(None, paa.dimensions(7));
+    let error = paa.to_image_level(7).unwrap_err().to_string();
+    assert_eq!("PAA has no mipmap 7, only levels 0 to 6.", error);
+
+    let level = dir.path().join("level2.png");
+    assert!(cmd_paa2img(&path, &level, 2, &DecodeOptions::default(), OutputPolicy::Fail).unwrap());
+    let decoded = image::open(&level).unwrap();
+    assert_eq!((16, 8), (decoded.width(), decoded.height()));
+    assert_eq!(paa.to_image_level(2).unwrap().to_rgba8(), decoded.to_rgba8());
+    assert!(cmd_paa2img(&path, &dir.path().join("level9.png"), 9, &DecodeOptions::default(), OutputPolicy::Fail).is_err());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_paa_info(&path, &mut output).unwrap();
+    let info = String::from_utf8(output).unwrap();
+    assert!(info.contains("\nTagg  Bytes\nCGVA      4\nCXAM      4\nSFFO     64\n"), "{}", info);
+    assert!(info.contains("\nLevel      Size    Stored  LZO\n    0     64x32  "), "{}", info);
+    assert_eq!(7, info.lines().filter(|l| l.ends_with("  yes") || l.ends_with("  no")).count(), "{}", info);
+    assert!(info.lines().any(|l| l.starts_with("    0 ") && l.ends_with("  yes")), "{}", info);
+
+    let header = PaaHeader::read(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(Some(&("SFFO".to_string(), 64)), header.taggs.last());
+}
+
+#[test]
+fn test_img2paa_folder_collisions() {
+    let dir = tempfile::tempdir().unwrap();
+    let (source, target) = (dir.path().join("images"), dir.path().join("paas"));
+    std::fs::create_dir_all(source.join("sub")).unwrap();
+    let img = image::RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+    for name in ["sub/Tex.png", "sub/tex.tga", "other.png"] {
+        img.save(source.join(name)).unwrap();
+    }
+
+    let error = cmd_img2paa_folder(&source, &target, Some(PaaType::DXT1), &EncodeOptions::default(), OutputPolicy::Fail, None).unwrap_err().to_string();
+    assert!(error.starts_with("Several files would be written to the same target"), "{}", error);
+    assert!(error.contains("Tex.png") && error.contains("tex.tga") && !error.contains("other"), "{}", error);
+    // Nothing is converted, with a job manifest neither
+    assert!(!target.exists());
+    assert!(cmd_img2paa_folder(&source, &target, Some(PaaType::DXT1), &EncodeOptions::default(), OutputPolicy::Fail, Some(&dir.path().join("jobs.json"))).is_err());
+    assert!(!target.exists() && !dir.path().join("jobs.json").exists());
+
+    std::fs::remove_file(source.join("sub/tex.tga")).unwrap();
+    assert_eq!(2, cmd_img2paa_folder(&source, &target, Some(PaaType::DXT1), &EncodeOptions::default(), OutputPolicy::Fail, None).unwrap().converted);
+}
+
+#[test]
+fn test_paa_image_analysis() {
+    use armake2::paaanalysis::*;
+
+    let rgba = |pixels: &[[u8; 4]]| image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(pixels.len() as u32, 1, |x, _| image::Rgba(pixels[x as usize])));
+
+    let mixed = rgba(&[[0, 0, 0, 255], [255, 255, 255, 255], [10, 20, 30, 0], [1, 2, 3, 128]]);
+    assert_eq!(ImageStats {
+        average_color: [66, 69, 72, 159],
+        maximum_color: [255, 255, 255, 255],
+        minimum_color: [0, 0, 0, 0],
+        has_alpha: true,
+        binary_alpha: false,
+        grayscale: false,
+    }, analyze(&mixed));
+    assert_eq!(Some(1), alpha_flag(&mixed));
+    assert_eq!([66, 69, 72, 159], average_color(&mixed));
+    assert_eq!([255, 255, 255, 255], maximum_color(&mixed));
+
+    let cutout = rgba(&[[200, 200, 200, 0], [100, 100, 100, 255]]);
+    let stats = analyze(&cutout);
+    assert_eq!(([150, 150, 150, 127], [100, 100, 100, 0]), (stats.average_color, stats.minimum_color));
+    assert!(stats.grayscale && stats.binary_alpha);
+    assert_eq!(Some(2), stats.alpha_flag());
+
+    // Other color types are converted like the encoder does
+    let gray = image::DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(2, 2, |x, y| image::Luma([((x + y * 2) * 0x4000) as u16])));
+    let stats = analyze(&gray);
+    assert_eq!(([95, 95, 95, 255], [191, 191, 191, 255], [0, 0, 0, 255]), (stats.average_color, stats.maximum_color, stats.minimum_color));
+    assert!(stats.grayscale && !stats.has_alpha);
+    assert_eq!(None, stats.alpha_flag());
+    assert_eq!(analyze(&gray), analyze_rgba(&gray.to_rgba8()));
+
+    let empty = analyze(&image::DynamicImage::new_rgba8(0, 0));
+    assert_eq!(([0; 4], [0; 4], false), (empty.average_color, empty.minimum_color, empty.has_alpha));
+
+    // The encoder stores the same colors
+    let mut written = Cursor::new(Vec::new());
+    Paa::from_image(&mixed.resize_exact(4, 4, image::imageops::FilterType::Nearest), PaaType::DXT5, false).unwrap().write(&mut written).unwrap();
+    let header = PaaHeader::read(&mut Cursor::new(written.into_inner())).unwrap();
+    let stats = analyze(&mixed.resize_exact(4, 4, image::imageops::FilterType::Nearest));
+    assert_eq!((Some(stats.average_color), Some(stats.maximum_color)), (header.average_color, header.max_color));
+}
+
+#[test]
+fn test_paa_write_layout() {
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(2, 2, image::Rgba([0x10, 0x20, 0x30, 0x80])));
+    let paa = Paa::from_image(&img, PaaType::ARGB8888, false).unwrap();
+
+    let mut expected: Vec<u8> = vec![0x88, 0x88];
+    expected.extend(b"GGATCGVA\x04\x00\x00\x00\x10\x20\x30\x80");
+    expected.extend(b"GGATCXAM\x04\x00\x00\x00\x10\x20\x30\x80");
+    expected.extend(b"GGATGALF\x04\x00\x00\x00\x01\x00\x00\x00");
+    expected.extend(b"GGATSFFO\x40\x00\x00\x00\x80\x00\x00\x00\x97\x00\x00\x00");
+    expected.extend([0; 56]);
+    expected.extend([0; 2]);
+    expected.extend(b"\x02\x00\x02\x00\x10\x00\x00");
+    expected.extend(b"\x30\x20\x10\x80".repeat(4));
+    expected.extend(b"\x01\x00\x01\x00\x04\x00\x00\x30\x20\x10\x80");
+    expected.extend([0; 6]);
+
+    let mut written: Vec<u8> = Vec::new();
+    paa.write_to(&mut written).unwrap();
+    assert_eq!(expected, written);
+    let mut seeked: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    paa.write(&mut seeked).unwrap();
+    assert_eq!(expected, seeked.into_inner());
+
+    let read = Paa::from_reader(&mut Cursor::new(&written)).unwrap();
+    assert_eq!((2, Some(1)), (read.mipmap_count(), read.flag()));
+    assert_eq!(img.to_rgba8(), read.to_image().unwrap().to_rgba8());
+    let header = PaaHeader::read(&mut Cursor::new(&written)).unwrap();
+    assert_eq!(vec![(2, 2, 0x80), (1, 1, 0x97)], header.mipmaps);
+
+    let opaque = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(8, 8, image::Rgba([1, 2, 3, 255])));
+    assert_eq!(None, Paa::from_image(&opaque, PaaType::DXT5, false).unwrap().flag());
+    let cutout = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(8, 8, |x, _| image::Rgba([1, 2, 3, if x < 4 { 0 } else { 255 }])));
+    assert_eq!(Some(2), Paa::from_image(&cutout, PaaType::DXT5, false).unwrap().flag());
+}
+
+#[test]
+fn test_paa_write_fixture() {
+    // A DXT5 with alpha and three mipmaps: the color and flag taggs, 16 offsets, an empty
+    // palette, the mipmaps from 16x16 down to 4x4 and the empty one after them
+    let fixture = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/dxt5.paa")).unwrap();
+    let paa = Paa::from_reader(&mut Cursor::new(&fixture)).unwrap();
+    assert!(!paa.has_nonstandard_layout());
+    assert_eq!(3, paa.mipmap_count());
+
+    let mut written: Vec<u8> = Vec::new();
+    paa.write_to(&mut written).unwrap();
+    assert_eq!(fixture, written);
+
+    // An encoded one has the same taggs in the same order and is framed the same way
+    let encoded = Paa::from_image(&paa.to_image().unwrap(), PaaType::DXT5, false).unwrap();
+    let mut written: Vec<u8> = Vec::new();
+    encoded.write_to(&mut written).unwrap();
+    let names = |bytes: &[u8]| engine_read(bytes).1.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(vec![*b"CGVA", *b"CXAM", *b"GALF"], names(&fixture));
+    assert_eq!(names(&fixture), names(&written));
+    // Up to the offsets, only the colors differ
+    let mut masked = written[..0x3e].to_vec();
+    masked[0x0e..0x12].copy_from_slice(&fixture[0x0e..0x12]);
+    masked[0x1e..0x22].copy_from_slice(&fixture[0x1e..0x22]);
+    assert_eq!(fixture[..0x3e], masked[..]);
+    let header = PaaHeader::read(&mut Cursor::new(&written)).unwrap();
+    assert_eq!(0x80, header.mipmaps[0].2);
+    assert_eq!([0; 2], written[0x7e..0x80]);
+    assert_eq!([0; 6], written[written.len() - 6..]);
+}
+
+#[test]
+fn test_paa_write_compression_flag() {
+    // LZO compression is flagged by the highest bit of the width, the size field is the stored size
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(256, 256, image::Rgba([10, 20, 30, 255])));
+    let paa = Paa::from_image(&img, PaaType::DXT1, true).unwrap();
+    let mut written: Vec<u8> = Vec::new();
+    paa.write_to(&mut written).unwrap();
+
+    let header = PaaHeader::read(&mut Cursor::new(&written)).unwrap();
+    let (width, height, offset) = header.mipmaps[0];
+    assert_eq!((256, 256), (width, height));
+    let offset = offset as usize;
+    assert_eq!(256 | 0x8000, u16::from_le_bytes([written[offset], written[offset + 1]]));
+    assert_eq!(256, u16::from_le_bytes([written[offset + 2], written[offset + 3]]));
+    let size = u32::from_le_bytes([written[offset + 4], written[offset + 5], written[offset + 6], 0]) as usize;
+    assert!(size < 256 * 256 / 2);
+    let next = header.mipmaps[1].2 as usize;
+    assert_eq!(offset + 7 + size, next);
+
+    // Small mipmaps that don't get smaller are stored as they are, without the flag
+    let (width, _, offset) = *header.mipmaps.last().unwrap();
+    assert_eq!(width, u16::from_le_bytes([written[offset as usize], written[offset as usize + 1]]));
+
+    let read = Paa::from_reader(&mut Cursor::new(&written)).unwrap();
+    assert!(read.is_compressed());
+    assert_eq!(img.to_rgba8(), read.to_image().unwrap().to_rgba8());
+}
+
+#[test]
+fn test_paa_swizzle() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("normal.paa");
+    let original = image::RgbaImage::from_fn(8, 8, |x, y| image::Rgba([x as u8 * 30, y as u8 * 30, 128, 255 - x as u8 * 10]));
+
+    // Red and alpha swapped like by a swizzle of "R,A,G,B"
+    let mut stored = original.clone();
+    stored.pixels_mut().for_each(|p| p.0 = [p[3], p[1], p[2], p[0]]);
+    Paa::from_image(&image::DynamicImage::ImageRgba8(stored.clone()), PaaType::ARGB8888, true).unwrap().write_to_file(&path).unwrap();
+    cmd_paa_set(&path, &path, &TaggEdits { swizzle: Some(parse_swizzle("R,A,G,B").unwrap()), ..Default::default() }, false).unwrap();
+
+    let paa = Paa::from_file(&path).unwrap();
+    assert_eq!(Some([1, 0, 2, 3]), paa.swizzle());
+    assert_eq!(original, paa.to_image().unwrap().to_rgba8());
+    let mut strips = image::RgbaImage::new(8, 8);
+    paa.decode_strips(0, |y, strip| {
+        strips.as_mut()[y as usize * 8 * 4..][..strip.len()].copy_from_slice(strip);
+        Ok(())
+    }).unwrap();
+    assert_eq!(original, strips);
+
+    // Channels from constants keep their stored value, inverted ones are inverted back
+    cmd_paa_set(&path, &path, &TaggEdits { swizzle: Some(parse_swizzle("1,1-R,G,B").unwrap()), ..Default::default() }, false).unwrap();
+    let decoded = Paa::from_file(&path).unwrap().to_image().unwrap().to_rgba8();
+    let pixel = stored.get_pixel(3, 5);
+    assert_eq!(image::Rgba([255 - pixel[0], pixel[1], pixel[2], pixel[3]]), *decoded.get_pixel(3, 5));
+
+    // Encoded again with the swizzle kept, so the stored channels are what is resized
+    let downscaled = Paa::from_file(&path).unwrap().downscaled(4).unwrap().unwrap();
+    assert_eq!(Some([8, 5, 2, 3]), downscaled.swizzle());
+}
@@ -0,0 +1,77 @@
+//! Peak memory of decoding, measured with an allocator that tracks it. Kept apart from the other
+//! PAA tests since the allocator counts the allocations of every test in the binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use armake2::paa::*;
+
+struct PeakAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(current, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Returns the result of `f` and how far it raised the allocated memory at most.
+fn peak_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let start = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(start, Ordering::SeqCst);
+    let result = f();
+    (result, PEAK.load(Ordering::SeqCst) - start)
+}
+
+#[test]
+fn test_paa_decode_peak_memory() {
+    const SIZE: u16 = 1024;
+    let blocks: Vec<u8> = (0..SIZE as usize * SIZE as usize).map(|i| ((i / 16) % 7 * 37) as u8).collect();
+    let paa = Paa::from_raw_mipmaps(PaaType::DXT5, vec![RawMipmap { width: SIZE, height: SIZE, data: blocks.clone() }],
+        &RawMipmapOptions { compress: true }).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    paa.write_to(&mut output).unwrap();
+    assert!(output.len() < blocks.len() / 10);
+
+    let image_size = SIZE as usize * SIZE as usize * 4;
+    let strip_size = SIZE as usize * 4 * 4;
+
+    // The decompressed blocks never exist as a whole, only the compressed data, the output and
+    // a strip of each
+    let mut input = Cursor::new(output);
+    let ((paa, image), peak) = peak_allocation(|| {
+        let paa = Paa::from_reader(&mut input).unwrap();
+        let image = paa.to_image().unwrap();
+        (paa, image)
+    });
+    assert!(peak < image_size + 256 * 1024, "{} bytes for a {} byte image", peak, image_size);
+
+    let mut expected = vec![0u8; image_size];
+    texpresso::Format::Bc3.decompress(&blocks, SIZE as usize, SIZE as usize, &mut expected);
+    assert_eq!(expected, image.to_rgba8().into_raw());
+
+    let mut hash: u64 = 0;
+    let mut rows = 0;
+    let (_, peak) = peak_allocation(|| paa.decode_strips(0, |y, rgba| {
+        assert_eq!(rows, y);
+        rows += (rgba.len() / strip_size * 4) as u32;
+        hash = rgba.iter().fold(hash, |h, b| h.wrapping_mul(31).wrapping_add(*b as u64));
+        Ok(())
+    }).unwrap());
+    assert_eq!(SIZE as u32, rows);
+    assert_eq!(expected.iter().fold(0u64, |h, b| h.wrapping_mul(31).wrapping_add(*b as u64)), hash);
+    assert!(peak < 256 * 1024, "{} bytes", peak);
+}
@@ -0,0 +1,102 @@
+use std::process::{Command};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use tempfile::{tempdir};
+
+use armake2::paa::*;
+use armake2::paaanalysis::*;
+
+/// Image that ARGB4444 stores exactly, with a smooth alpha gradient that DXT1 can't represent
+fn quantized_image() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+        Rgba([((x / 4) * 17) as u8, ((y / 4) * 17) as u8, 136, (((x + y) / 8) * 17) as u8])
+    }))
+}
+
+#[test]
+fn test_analyze_formats() {
+    let analyses = analyze_formats(&quantized_image(), &DEFAULT_FORMATS).unwrap();
+    assert_eq!(vec![PaaType::DXT1, PaaType::DXT5, PaaType::ARGB4444], analyses.iter().map(|a| a.paa_type).collect::<Vec<PaaType>>());
+
+    let (dxt1, dxt5, argb) = (&analyses[0], &analyses[1], &analyses[2]);
+    assert!(argb.psnr_rgb.is_infinite() && argb.psnr_alpha.is_infinite());
+    assert_eq!(0.0, argb.delta_e_p99);
+    assert!(argb.is_visually_lossless());
+    assert!(!dxt1.is_visually_lossless());
+    assert!(dxt1.psnr_alpha < dxt5.psnr_alpha);
+
+    assert!(dxt1.size < dxt5.size && dxt5.size < argb.size);
+    assert!(analyses.iter().all(|a| a.size_lzo <= a.size));
+    assert!(argb.size_lzo < argb.size);
+
+    let best = recommend(&analyses).unwrap();
+    assert!(best.is_visually_lossless());
+    assert!(analyses.iter().filter(|a| a.is_visually_lossless()).all(|a| a.size_lzo >= best.size_lzo));
+}
+
+#[test]
+fn test_parse_formats() {
+    assert_eq!(vec![PaaType::DXT1, PaaType::DXT5], parse_formats("dxt1, DXT5,dxt1").unwrap());
+    assert!(parse_formats("dxt1,png").is_err());
+    assert!(parse_formats(",").is_err());
+}
+
+#[test]
+fn test_analyze_write_best() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("icon.png");
+    let target = dir.path().join("icon_ca.paa");
+    quantized_image().save(&source).unwrap();
+
+    cmd_img2paa_analyze(&source, Some(&target), &[PaaType::DXT1], false, false, false).unwrap();
+    assert!(!target.exists());
+
+    cmd_img2paa_analyze(&source, Some(&target), &[PaaType::DXT1, PaaType::ARGB4444], true, true, false).unwrap();
+    let paa = Paa::from_file(&target).unwrap();
+    assert_eq!(PaaType::ARGB4444, paa.paa_type);
+    assert!(paa.is_compressed());
+    assert_eq!(quantized_image().to_rgba8(), paa.to_image().unwrap().to_rgba8());
+
+    assert!(cmd_img2paa_analyze(&source, Some(&target), &[PaaType::DXT1], true, false, false).is_err());
+}
+
+#[test]
+fn test_paa_compare() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("icon.png");
+    quantized_image().save(&source).unwrap();
+    for (name, paa_type, compress) in [("dxt1", PaaType::DXT1, false), ("dxt5", PaaType::DXT5, false), ("argb", PaaType::ARGB4444, false), ("argb_lzo", PaaType::ARGB4444, true)] {
+        Paa::from_image(&quantized_image(), paa_type, compress).unwrap().write_to_file(dir.path().join(format!("{}.paa", name))).unwrap();
+    }
+    let path = |name: &str| dir.path().join(format!("{}.paa", name));
+
+    // Compression doesn't change the pixels
+    let mut output: Vec<u8> = Vec::new();
+    assert!(!cmd_paa_compare(&path("argb"), &path("argb_lzo"), None, None, &mut output).unwrap());
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0].len(), lines[1].len(), "{}", output);
+    assert_eq!(vec!["Old", "ARGB4444", "64x64", "7"], lines[1].split_whitespace().take(4).collect::<Vec<&str>>());
+    assert_eq!(vec!["inf", "inf", "0.00", "0.00"], lines[2].split_whitespace().skip(6).collect::<Vec<&str>>());
+    assert!(output.ends_with("The PAAs decode to the same pixels.\n"));
+
+    assert!(cmd_paa_compare(&path("argb"), &path("dxt5"), None, None, &mut Vec::new()).unwrap());
+    // DXT1 loses the alpha gradient, DXT5 keeps most of it
+    assert!(cmd_paa_compare(&path("dxt5"), &path("dxt1"), Some(&source), Some(3.0), &mut Vec::new()).unwrap());
+    assert!(!cmd_paa_compare(&path("dxt1"), &path("dxt5"), Some(&source), Some(0.0), &mut Vec::new()).unwrap());
+    assert!(cmd_paa_compare(&path("argb"), &path("dxt5"), Some(&source), Some(1000.0), &mut Vec::new()).unwrap());
+
+    assert_eq!(0.0, psnr_loss(f64::INFINITY, f64::INFINITY));
+    assert!(psnr_loss(f64::INFINITY, 40.0).is_infinite());
+    assert!(psnr_loss(30.0, 40.0) < 0.0);
+
+    let quiet = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args([&["paacompare", "--quiet"], args].concat()).output().unwrap();
+        assert!(output.stdout.is_empty() && output.stderr.is_empty(), "{:?}", output);
+        output.status.code().unwrap()
+    };
+    assert_eq!(0, quiet(&["argb.paa", "argb_lzo.paa"]));
+    assert_eq!(1, quiet(&["dxt5.paa", "dxt1.paa"]));
+    assert_eq!(0, quiet(&["--source", "icon.png", "--max-psnr-loss", "0", "dxt1.paa", "dxt5.paa"]));
+    assert_eq!(2, quiet(&["dxt5.paa", "missing.paa"]));
+}
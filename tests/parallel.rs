@@ -0,0 +1,157 @@
+use std::fs::{create_dir_all, write};
+use std::io::{Cursor};
+use std::path::{Path};
+use std::process::{Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration};
+
+use armake2::paa::*;
+use armake2::parallel::*;
+
+/// Runs a batch of sleeping tasks and returns the highest number of tasks that ran at once.
+fn max_concurrency() -> usize {
+    let running = AtomicUsize::new(0);
+    let max = AtomicUsize::new(0);
+
+    let results: Vec<usize> = map_collect((0..16).collect(), |i| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        max.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        running.fetch_sub(1, Ordering::SeqCst);
+        i * 2
+    });
+    assert_eq!((0..16).map(|i| i * 2).collect::<Vec<usize>>(), results);
+
+    max.load(Ordering::SeqCst)
+}
+
+// The pool is global, so all settings are checked in one test.
+#[test]
+fn test_thread_count() {
+    set_thread_count(2).unwrap();
+    assert_eq!(2, thread_count());
+    let max = max_concurrency();
+    assert!(max <= 2, "{} tasks ran at once", max);
+
+    set_thread_count(1).unwrap();
+    assert_eq!(1, max_concurrency());
+    let noise = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(203, 77, |x, y| {
+        let hash = (x * 7919 + y * 104_729).wrapping_mul(2_654_435_761);
+        image::Rgba([(hash >> 8) as u8, (x * 5) as u8, (y * 3) as u8, (hash >> 20) as u8])
+    }));
+    let encodings = [PaaType::DXT1, PaaType::DXT5].iter()
+        .flat_map(|t| [EncodeQuality::Fast, EncodeQuality::Cluster, EncodeQuality::Default, EncodeQuality::Exhaustive].map(|q| (*t, q)));
+    let encode = |paa_type: PaaType, quality: EncodeQuality| -> Vec<u8> {
+        let options = EncodeOptions { quality, lzo: LzoPolicy::Always, ..Default::default() };
+        let mut bytes: Vec<u8> = Vec::new();
+        Paa::from_image_with_options(&noise, paa_type, &options, &MiniLzo, None).unwrap().write_to(&mut bytes).unwrap();
+        bytes
+    };
+    let sequential_encodings: Vec<Vec<u8>> = encodings.clone().map(|(t, q)| encode(t, q)).collect();
+    let caller = thread::current().id();
+    let threads: Vec<thread::ThreadId> = map_collect(vec![0; 8], |_: i32| thread::current().id());
+    assert!(threads.iter().all(|id| *id == caller));
+
+    // Results short-circuit like with a sequential collect
+    let result: Result<Vec<i32>, String> = map_collect(vec![1, 2, 3], |i| if i == 2 { Err(format!("{} failed", i)) } else { Ok(i) });
+    assert_eq!(Err("2 failed".to_string()), result);
+
+    // Levels of a PAA decompressed in parallel end up like sequentially decompressed ones
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(512, 512, |x, y| image::Rgba([x as u8 & 0xF8, y as u8 & 0xF8, 0, 255])));
+    let mut bytes: Vec<u8> = Vec::new();
+    Paa::from_image(&img, PaaType::ARGB8888, true).unwrap().write_to(&mut bytes).unwrap();
+    let sequential = Paa::from_reader(&mut Cursor::new(&bytes)).unwrap().to_image().unwrap().to_rgba8();
+    set_thread_count(4).unwrap();
+    let paa = Paa::from_reader(&mut Cursor::new(&bytes)).unwrap();
+    assert_eq!(sequential, paa.to_image().unwrap().to_rgba8());
+    assert_eq!(img.to_rgba8(), sequential);
+
+    // Blocks and levels encoded in parallel are the same as sequentially encoded ones
+    for ((paa_type, quality), sequential) in encodings.zip(sequential_encodings) {
+        assert!(encode(paa_type, quality) == sequential, "{:?} {:?}", paa_type, quality);
+    }
+
+    // The first broken level is reported, no matter which thread finished first
+    let mut broken = bytes.clone();
+    let second = broken.windows(8).position(|w| w == b"GGATSFFO").unwrap() + 12 + 4;
+    let second = u32::from_le_bytes(broken[second..second + 4].try_into().unwrap()) as usize;
+    assert_ne!(0, broken[second + 1] & 0x80);
+    broken[second + 7..second + 10].copy_from_slice(&[0x11, 0, 0]);
+    let mut paa = Paa::from_reader(&mut Cursor::new(&broken)).unwrap();
+    let levels: Vec<usize> = (0..paa.mipmap_count()).collect();
+    let error = paa.decompress_levels(&levels).unwrap_err().to_string();
+    assert!(error.contains("Failed to decompress mipmap 1 (256x256)"), "{}", error);
+
+    set_thread_count(0).unwrap();
+    assert!(thread_count() >= 1);
+}
+
+/// Builds a DXT1 PAA with one mipmap followed by `extra` bytes, which `paa2img` warns about.
+fn paa_with_extra_data(size: u16, extra: usize) -> Vec<u8> {
+    let data = vec![0x55u8; size as usize * size as usize / 2 + extra];
+
+    let mut output: Vec<u8> = vec![0x01, 0xFF];
+    output.extend(b"GGATSFFO");
+    output.extend(4u32.to_le_bytes());
+    output.extend((output.len() as u32 + 4).to_le_bytes());
+    output.extend(size.to_le_bytes());
+    output.extend(size.to_le_bytes());
+    output.extend(&(data.len() as u32).to_le_bytes()[0..3]);
+    output.extend(data);
+    output
+}
+
+fn paa2img_stderr(source: &Path, target: &Path) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2"))
+        .args(["paa2img", "--threads", "4"]).arg(source).arg(target).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output.stderr
+}
+
+#[test]
+fn test_warning_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    create_dir_all(&source).unwrap();
+
+    // Earlier files are larger, so later ones tend to finish first
+    for i in 0..8 {
+        write(source.join(format!("texture_{}.paa", i)), paa_with_extra_data(512 >> i, i + 1)).unwrap();
+    }
+
+    let first = paa2img_stderr(&source, &dir.path().join("first"));
+    let second = paa2img_stderr(&source, &dir.path().join("second"));
+    assert_eq!(String::from_utf8_lossy(&first), String::from_utf8_lossy(&second));
+
+    let stderr = String::from_utf8_lossy(&first);
+    let extra: Vec<usize> = stderr.lines()
+        .filter(|l| l.contains("[dxt-size-mismatch]"))
+        .map(|l| {
+            let size: usize = l.split("mipmap ").nth(1).unwrap().split('x').next().unwrap().parse().unwrap();
+            let bytes: usize = l.split(" has ").nth(1).unwrap().split(' ').next().unwrap().parse().unwrap();
+            bytes - size * size / 2
+        })
+        .collect();
+    assert_eq!((1..=8).collect::<Vec<usize>>(), extra);
+}
+
+#[test]
+fn test_threads_pack() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(&source).unwrap();
+    write(source.join("script.sqf"), "hint \"hello\";\n").unwrap();
+
+    for args in [&["pack", "--threads", "1"][..], &["build", "--threads", "1", "--no-cache"][..]] {
+        let target = dir.path().join(format!("{}.pbo", args[0]));
+        let output = Command::new(env!("CARGO_BIN_EXE_armake2"))
+            .args(args).arg(&source).arg(&target).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(target.exists());
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2"))
+        .args(["pack", "--threads", "0"]).arg(&source).arg(dir.path().join("zero.pbo")).output().unwrap();
+    assert!(!output.status.success());
+}
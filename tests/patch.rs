@@ -0,0 +1,100 @@
+use std::fs::{create_dir_all, write};
+use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+
+use tempfile::{tempdir};
+
+use armake2::error::*;
+use armake2::patch::*;
+use armake2::pbo::*;
+
+fn build_pbo(dir: &Path, files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    for (name, data) in files {
+        let path = dir.join(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, data).unwrap();
+    }
+
+    let pbo = PBO::from_directory(PathBuf::from(dir), false, &Vec::new(), &Vec::new()).unwrap();
+    pbo.to_cursor().unwrap().into_inner()
+}
+
+fn roundtrip(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut patch: Vec<u8> = Vec::new();
+    cmd_pbo_diff(&mut Cursor::new(old), &mut Cursor::new(new), &mut patch).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_pbo_patch(&mut Cursor::new(old), &mut Cursor::new(&patch), &mut output).unwrap();
+    assert_eq!(new, &output[..]);
+
+    patch
+}
+
+#[test]
+fn test_patch_roundtrip() {
+    let large: Vec<u8> = (0..20000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut edited = large.clone();
+    edited[10000] ^= 0xFF;
+    edited.extend(b"appended");
+
+    let old_dir = tempdir().unwrap();
+    let old = build_pbo(&old_dir.path().join("addon"), &[
+        ("config.cpp", b"class CfgPatches {};".to_vec()),
+        ("data\\large.bin", large.clone()),
+        ("removed.sqf", b"hint \"bye\";".to_vec()),
+    ]);
+
+    let new_dir = tempdir().unwrap();
+    let new = build_pbo(&new_dir.path().join("addon"), &[
+        ("config.cpp", b"class CfgPatches {};".to_vec()),
+        ("data\\large.bin", edited),
+        ("added.sqf", b"hint \"hi\";".to_vec()),
+    ]);
+
+    let patch = roundtrip(&old, &new);
+    assert!(patch.len() < 2000);
+
+    roundtrip(&new, &old);
+    roundtrip(&old, &old);
+}
+
+#[test]
+fn test_patch_wrong_base() {
+    let old_dir = tempdir().unwrap();
+    let old = build_pbo(&old_dir.path().join("addon"), &[("a.sqf", b"1".to_vec())]);
+    let new_dir = tempdir().unwrap();
+    let new = build_pbo(&new_dir.path().join("addon"), &[("a.sqf", b"2".to_vec())]);
+    let other_dir = tempdir().unwrap();
+    let other = build_pbo(&other_dir.path().join("addon"), &[("a.sqf", b"3".to_vec())]);
+
+    let mut patch: Vec<u8> = Vec::new();
+    cmd_pbo_diff(&mut Cursor::new(&old), &mut Cursor::new(&new), &mut patch).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    assert!(cmd_pbo_patch(&mut Cursor::new(&other), &mut Cursor::new(&patch), &mut output).is_err());
+}
+
+#[test]
+fn test_patch_corrupt_sizes() {
+    let old_dir = tempdir().unwrap();
+    let old = build_pbo(&old_dir.path().join("addon"), &[("a.sqf", b"hint \"old\";".to_vec())]);
+    let new_dir = tempdir().unwrap();
+    let new = build_pbo(&new_dir.path().join("addon"), &[("a.sqf", b"hint \"new\";".to_vec())]);
+
+    let mut patch: Vec<u8> = Vec::new();
+    cmd_pbo_diff(&mut Cursor::new(&old), &mut Cursor::new(&new), &mut patch).unwrap();
+    let header_end = 8 + 4 + 20 + 20;
+
+    // A header claiming 4 GiB fails on the missing data instead of allocating it
+    let mut huge = patch[..header_end].to_vec();
+    huge.extend([0xff; 4]);
+    huge.extend(b"short");
+    let mut output: Vec<u8> = Vec::new();
+    let error = cmd_pbo_patch(&mut Cursor::new(&old), &mut Cursor::new(&huge), &mut output).unwrap_err();
+    assert_eq!(Some("E0317"), coded(&error).map(|c| c.code));
+    assert_eq!("Patch ends 5 bytes into data of 4294967295 bytes.", error.to_string());
+
+    // Cut off inside the entries
+    let mut output: Vec<u8> = Vec::new();
+    assert!(cmd_pbo_patch(&mut Cursor::new(&old), &mut Cursor::new(&patch[..patch.len() - 10]), &mut output).is_err());
+}
@@ -0,0 +1,114 @@
+use std::path::{Component, Path, PathBuf};
+
+use quickcheck::{QuickCheck, TestResult};
+
+use armake2::paths::*;
+
+/// Turns arbitrary strings into file names that are valid on every platform.
+fn file_names(names: Vec<String>) -> Vec<String> {
+    names.into_iter()
+        .map(|n| n.chars().filter(|c| c.is_ascii_alphanumeric() || "_-. ".contains(*c)).collect::<String>())
+        .map(|n| n.trim_matches(['.', ' ']).to_string())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// Builds a path from arbitrary pieces, mixing in separators and traversal.
+fn path_of(pieces: &[(u8, String)]) -> String {
+    pieces.iter().map(|(kind, piece)| match kind % 6 {
+        0 => format!("{}\\", piece),
+        1 => format!("{}/", piece),
+        2 => "..\\".to_string(),
+        3 => "./".to_string(),
+        4 => format!("C:{}", piece),
+        _ => piece.clone(),
+    }).collect()
+}
+
+#[test]
+fn test_internal_path_round_trip() {
+    fn property(names: Vec<String>) -> TestResult {
+        let names = file_names(names);
+        if names.is_empty() {
+            return TestResult::discard();
+        }
+
+        let root = Path::new("mod").join("addons");
+        let host: PathBuf = names.iter().fold(root.clone(), |p, n| p.join(n));
+        let internal = InternalPath::from_host(&root, &host).unwrap();
+
+        let back = internal.to_host(&root);
+        let relative = internal.to_host(Path::new(""));
+        TestResult::from_bool(
+            back.to_string_lossy().to_lowercase() == host.to_string_lossy().to_lowercase()
+                && InternalPath::from_host(&root, &back).unwrap() == internal
+                && InternalPath::from_host(&root, &relative).unwrap() == internal
+                && internal.as_str() == names.join("\\").to_lowercase())
+    }
+    QuickCheck::new().quickcheck(property as fn(Vec<String>) -> TestResult);
+}
+
+#[test]
+fn test_internal_path_traversal() {
+    fn property(pieces: Vec<(u8, String)>) -> bool {
+        let path = path_of(&pieces);
+        let root = Path::new("root");
+        let contained = |p: &InternalPath| {
+            let host = p.to_host(root);
+            host.starts_with(root) && host.strip_prefix(root).unwrap().components().all(|c| matches!(c, Component::Normal(_)))
+        };
+
+        let traverses = path.split(['\\', '/']).any(|c| c == ".." || c.contains(':') || c.contains('\0'));
+        match InternalPath::new(&path) {
+            Ok(p) => !traverses && contained(&p),
+            Err(_) => traverses
+        }
+    }
+    QuickCheck::new().quickcheck(property as fn(Vec<(u8, String)>) -> bool);
+
+    for path in ["..", "a\\..\\..\\b", "a/../b", "C:\\Windows", "file.txt:stream"] {
+        assert!(InternalPath::new(path).is_err(), "{}", path);
+    }
+    assert!(InternalPath::from_host(Path::new("root"), Path::new("root/../secret")).is_err());
+    assert!(InternalPath::from_host(Path::new("root"), Path::new("/etc/passwd")).is_err());
+    assert_eq!("a\\b", InternalPath::new("\\A\\.\\b\\").unwrap().as_str());
+}
+
+#[test]
+fn test_internal_path_comparison() {
+    fn property(names: Vec<String>, upper: Vec<bool>, slashes: Vec<bool>) -> TestResult {
+        let names = file_names(names);
+        if names.is_empty() {
+            return TestResult::discard();
+        }
+
+        let mixed: String = names.iter().enumerate().map(|(i, n)| {
+            let n = if upper.get(i).copied().unwrap_or(false) { n.to_uppercase() } else { n.clone() };
+            let separator = if slashes.get(i).copied().unwrap_or(false) { "/" } else { "\\" };
+            format!("{}{}", separator, n)
+        }).collect();
+
+        let plain = InternalPath::new(&names.join("\\")).unwrap();
+        let other = InternalPath::new(&mixed).unwrap();
+        let prefix = InternalPath::new(&names[0]).unwrap();
+        TestResult::from_bool(plain == other && other.starts_with(&prefix)
+            && prefix.join(&other.strip_prefix(&prefix).unwrap()) == plain)
+    }
+    QuickCheck::new().quickcheck(property as fn(Vec<String>, Vec<bool>, Vec<bool>) -> TestResult);
+
+    let path = InternalPath::new("\\A3\\Data_F\\foo.paa").unwrap();
+    assert_eq!("a3\\data_f\\foo.paa", path.to_string());
+    assert!(path.starts_with(&InternalPath::new("a3/DATA_F").unwrap()));
+    assert!(!path.starts_with(&InternalPath::new("a3\\data").unwrap()));
+    assert_eq!(Some(InternalPath::default()), path.strip_prefix(&path));
+    assert_eq!(vec!["a3", "data_f", "foo.paa"], path.components().collect::<Vec<&str>>());
+}
+
+#[test]
+fn test_entry_name() {
+    let root = Path::new("addon");
+    assert_eq!("Data\\Gun_CO.paa", entry_name(root, &root.join("Data").join("Gun_CO.paa")).unwrap());
+    assert_eq!("Data\\Gun_CO.paa", entry_name(root, &Path::new("Data").join("Gun_CO.paa")).unwrap());
+    assert!(entry_name(root, Path::new("addon/../secret")).is_err());
+    assert!(entry_name(root, Path::new("addon/file.txt:stream")).is_err());
+}
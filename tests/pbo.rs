@@ -0,0 +1,866 @@
+use std::cell::{RefCell};
+use std::collections::{HashMap};
+use std::fs::{create_dir_all, read, read_dir, read_to_string, write};
+use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+use std::process::{Command};
+use std::thread::{sleep};
+use std::time::{Duration, SystemTime};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use openssl::hash::{MessageDigest, hash};
+use tempfile::{tempdir};
+
+use armake2::buildcache::*;
+use armake2::config::{Config};
+use armake2::paa::{Paa, PaaType};
+use armake2::pbo::*;
+use armake2::resources::*;
+use armake2::warnings::{get_warning_count};
+use armake2::progress::*;
+
+fn write_header(output: &mut Vec<u8>, name: &[u8], packing_method: u32, size: u32) {
+    output.extend(name);
+    output.push(0);
+    output.write_u32::<LittleEndian>(packing_method).unwrap();
+    output.write_u32::<LittleEndian>(size).unwrap();
+    output.write_u32::<LittleEndian>(0).unwrap();
+    output.write_u32::<LittleEndian>(0).unwrap();
+    output.write_u32::<LittleEndian>(size).unwrap();
+}
+
+/// Builds a PBO from raw entries. The header size of each entry may differ from its actual data
+/// to simulate decoy entries.
+fn pbo_bytes(entries: &[(&[u8], u32, &[u8])]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::new();
+
+    write_header(&mut output, b"", 0x5665_7273, 0);
+    output.extend(b"prefix\0test\0\0");
+
+    for (name, size, _) in entries {
+        write_header(&mut output, name, 0, *size);
+    }
+    write_header(&mut output, b"", 0, 0);
+
+    for (_, _, data) in entries {
+        output.extend(*data);
+    }
+
+    output.push(0);
+    output.extend(&[0xAB; 20]);
+
+    output
+}
+
+#[test]
+fn test_unpack_lenient() {
+    let input = pbo_bytes(&[
+        (b"config.cpp", 3, b"foo"),
+        (b"CONFIG.cpp", 3, b"bar"),
+        (b"..\\..\\evil.sqf", 4, b"evil"),
+        (b"scripts\\in\x01it?.sqf", 4, b"init"),
+        (b"decoy.sqf", 0x7FFF_FFFF, b""),
+    ]);
+
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("out");
+
+    assert!(cmd_unpack(&mut Cursor::new(input.clone()), output.clone(), false, false, None, false, false).is_err());
+    assert!(!output.exists());
+
+    cmd_unpack(&mut Cursor::new(input), output.clone(), false, true, None, false, false).unwrap();
+
+    assert_eq!(b"foo".to_vec(), read(output.join("config.cpp")).unwrap());
+    assert_eq!(b"bar".to_vec(), read(output.join("CONFIG_1.cpp")).unwrap());
+    assert_eq!(b"evil".to_vec(), read(output.join("evil.sqf")).unwrap());
+    assert_eq!(b"init".to_vec(), read(output.join("scripts").join("in_it_.sqf")).unwrap());
+    assert!(!output.join("decoy.sqf").exists());
+    assert!(!dir.path().join("evil.sqf").exists());
+
+    let manifest = read_to_string(output.join("$MANIFEST$")).unwrap();
+    assert_eq!("\
+CONFIG_1.cpp\tCONFIG.cpp
+evil.sqf\t..\\\\..\\\\evil.sqf
+scripts\\in_it_.sqf\tscripts\\\\in\\u{1}it?.sqf
+", manifest);
+}
+
+#[test]
+fn test_unpack_flat() {
+    let input = pbo_bytes(&[
+        (b"config.cpp", 3, b"foo"),
+        (b"data\\textures\\thing.paa", 5, b"thing"),
+        (b"data\\scripts\\init.sqf", 4, b"init"),
+    ]);
+
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("out");
+    cmd_unpack(&mut Cursor::new(input.clone()), output.clone(), false, false, Some("__"), false, false).unwrap();
+
+    assert_eq!(b"thing".to_vec(), read(output.join("data__textures__thing.paa")).unwrap());
+    assert_eq!(b"init".to_vec(), read(output.join("data__scripts__init.sqf")).unwrap());
+    assert!(!output.join("data").exists());
+    assert_eq!("\
+config.cpp\tconfig.cpp
+data__textures__thing.paa\tdata\\\\textures\\\\thing.paa
+data__scripts__init.sqf\tdata\\\\scripts\\\\init.sqf
+", read_to_string(output.join("$MANIFEST$")).unwrap());
+
+    // Collisions are found before anything is written
+    let colliding = pbo_bytes(&[
+        (b"a\\b.sqf", 1, b"1"),
+        (b"a_b.sqf", 1, b"2"),
+    ]);
+    let error = cmd_unpack(&mut Cursor::new(colliding), dir.path().join("colliding"), false, false, Some("_"), false, false).unwrap_err().to_string();
+    assert!(error.contains("\"a\\b.sqf\" and \"a_b.sqf\" would both be written to \"a_b.sqf\""), "{}", error);
+    assert!(!dir.path().join("colliding").exists());
+
+    let colliding = pbo_bytes(&[
+        (b"data\\A.paa", 1, b"1"),
+        (b"data_a.paa", 1, b"2"),
+    ]);
+    let error = cmd_unpack(&mut Cursor::new(colliding), dir.path().join("colliding"), false, false, Some("_"), false, false).unwrap_err().to_string();
+    assert!(error.contains("\"data\\A.paa\" and \"data_a.paa\" would both be written to \"data_a.paa\""), "{}", error);
+
+    // Library users can rename and skip entries as they like
+    let pbo = PBO::read(&mut Cursor::new(input)).unwrap();
+    let hooked = dir.path().join("hooked");
+    let written = unpack_with(&pbo, &hooked, |name| name.ends_with(".paa").then(|| PathBuf::from("textures").join(flatten_entry_name(name, "-")))).unwrap();
+    assert_eq!(vec![("data\\textures\\thing.paa".to_string(), PathBuf::from("textures").join("data-textures-thing.paa"))], written);
+    assert_eq!(b"thing".to_vec(), read(hooked.join("textures").join("data-textures-thing.paa")).unwrap());
+    assert!(unpack_with(&pbo, &hooked, |_| Some(PathBuf::from("../evil"))).is_err());
+}
+
+/// Returns the content of every file in a folder by its path relative to it.
+fn folder_contents(folder: &Path) -> HashMap<PathBuf, Vec<u8>> {
+    let mut contents: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+    for entry in read_dir(folder).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            for (sub, data) in folder_contents(&path) {
+                contents.insert(PathBuf::from(path.file_name().unwrap()).join(sub), data);
+            }
+        } else {
+            contents.insert(PathBuf::from(path.file_name().unwrap()), read(&path).unwrap());
+        }
+    }
+    contents
+}
+
+#[test]
+fn test_unpack_dedupe() {
+    let bank = vec![7; 4096];
+    let dir = tempdir().unwrap();
+    let addons = dir.path().join("addons");
+    create_dir_all(addons.join("sub")).unwrap();
+    write(addons.join("a.pbo"), pbo_bytes(&[
+        (b"sounds\\bank.wss", 4096, &bank),
+        (b"sounds\\copy.wss", 4096, &bank),
+        (b"config.cpp", 1, b"a"),
+        (b"empty.txt", 0, b""),
+    ])).unwrap();
+    write(addons.join("sub").join("b.pbo"), pbo_bytes(&[
+        (b"sounds\\bank.wss", 4096, &bank),
+        (b"config.cpp", 1, b"b"),
+        (b"empty.txt", 0, b""),
+    ])).unwrap();
+
+    let plain = dir.path().join("plain");
+    let linked = dir.path().join("linked");
+    assert_eq!(2, cmd_unpack_folder(&addons, &plain, false, false, None, false, false).unwrap());
+    assert_eq!(2, cmd_unpack_folder(&addons, &linked, false, false, None, true, false).unwrap());
+    assert_eq!(folder_contents(&plain), folder_contents(&linked));
+    assert_eq!(b"b".to_vec(), read(linked.join("sub").join("b").join("config.cpp")).unwrap());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let links = |path: &Path| path.metadata().unwrap().nlink();
+        assert_eq!(3, links(&linked.join("a").join("sounds").join("bank.wss")));
+        assert_eq!(3, links(&linked.join("sub").join("b").join("sounds").join("bank.wss")));
+        assert_eq!(1, links(&linked.join("a").join("empty.txt")));
+        assert_eq!(1, links(&plain.join("a").join("sounds").join("bank.wss")));
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["unpack", "--dedupe", "addons", "reported"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Linked 2 duplicate files, saving 8192 bytes."), "{}", stderr);
+    assert!(!stderr.contains("couldn't be hard linked"), "{}", stderr);
+
+    // Packing reads the links once, into the same PBO
+    let pack = |source: PathBuf, dedupe: bool| {
+        let mut output: Vec<u8> = Vec::new();
+        cmd_pack(source, &mut output, &PackOptions { dedupe, ..Default::default() }).unwrap();
+        output
+    };
+    assert_eq!(pack(plain.join("a"), false), pack(linked.join("a"), true));
+    assert_eq!(pack(linked.join("a"), false), pack(linked.join("a"), true));
+
+    // Copies are recognized by the hashes of the first pack
+    let expected = pack(plain.join("a"), false);
+    assert_eq!(expected, pack(plain.join("a"), true));
+    let hashes = read_to_string(BuildCache::default_directory(&plain.join("a")).join("hashes")).unwrap();
+    let bank_hashes: Vec<&str> = hashes.lines().filter(|l| l.ends_with(".wss")).map(|l| l.split(' ').next().unwrap()).collect();
+    assert_eq!(2, bank_hashes.len(), "{}", hashes);
+    assert_eq!(bank_hashes[0], bank_hashes[1]);
+    assert_eq!(expected, pack(plain.join("a"), true));
+
+    // Unpacking over linked files replaces them instead of changing the files they are linked to
+    let changed = pbo_bytes(&[(b"sounds\\bank.wss", 3, b"new")]);
+    cmd_unpack(&mut Cursor::new(changed), linked.join("a"), true, false, None, false, false).unwrap();
+    assert_eq!(b"new".to_vec(), read(linked.join("a").join("sounds").join("bank.wss")).unwrap());
+    assert_eq!(bank, read(linked.join("a").join("sounds").join("copy.wss")).unwrap());
+    assert_eq!(bank, read(linked.join("sub").join("b").join("sounds").join("bank.wss")).unwrap());
+
+    let error = cmd_unpack_folder(&addons, &linked, false, false, None, true, false).unwrap_err().to_string();
+    assert!(error.contains("already exists and is not empty"), "{}", error);
+}
+
+/// Returns the header extension section, which follows the 21 byte extension header.
+fn header_extension_bytes(pbo: &[u8]) -> &[u8] {
+    let mut end = 21;
+    loop {
+        let length = pbo[end..].iter().position(|b| *b == 0).unwrap();
+        end += length + 1;
+        if length == 0 {
+            return &pbo[21..end];
+        }
+    }
+}
+
+#[test]
+fn test_unpack_pack_header_extensions() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    create_dir_all(&source).unwrap();
+    write(source.join("script.sqf"), "hint \"hi\";").unwrap();
+
+    let mut pbo = PBO::from_directory(source, false, &Vec::new(), &Vec::new()).unwrap();
+    pbo.header_extensions.insert("prefix".to_string(), "x\\test".to_string());
+    for (key, value) in [("zeta", "1"), ("alpha", "2"), ("version", "1.0=final")] {
+        pbo.header_extensions.insert(key.to_string(), value.to_string());
+    }
+    let original = pbo.to_cursor().unwrap().into_inner();
+
+    let unpacked = dir.path().join("unpacked");
+    cmd_unpack(&mut Cursor::new(original.clone()), unpacked.clone(), false, false, None, false, false).unwrap();
+    assert!(read_to_string(unpacked.join("$PBO_META$.json")).unwrap().contains("\"version\": \"1.0=final\""));
+
+    let mut repacked: Vec<u8> = Vec::new();
+    cmd_pack(unpacked.clone(), &mut repacked, &PackOptions::default()).unwrap();
+    assert_eq!(header_extension_bytes(&original), header_extension_bytes(&repacked));
+    assert!(PBO::read(&mut Cursor::new(&repacked)).unwrap().files.get("$PBO_META$.json").is_none());
+
+    // Overrides keep the position of the extension
+    let mut overridden: Vec<u8> = Vec::new();
+    cmd_pack(unpacked.clone(), &mut overridden, &PackOptions { headerext: &["zeta=3".to_string()], ..Default::default() }).unwrap();
+    let keys: Vec<String> = PBO::read(&mut Cursor::new(&overridden)).unwrap().header_extensions.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    assert_eq!(vec!["prefix=x\\test", "zeta=3", "alpha=2", "version=1.0=final"], keys);
+
+    // A prefix that doesn't match the meta file is an error
+    write(unpacked.join("$PBOPREFIX$"), "x\\other\n").unwrap();
+    let error = cmd_pack(unpacked, &mut Vec::new(), &PackOptions::default()).unwrap_err().to_string();
+    assert!(error.contains("\"prefix\" is \"x\\other\" in $PBOPREFIX$ but \"x\\test\""), "{}", error);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_unpack_long_paths() {
+    let folder = "a".repeat(100);
+    let name = format!("{}\\{}\\{}\\script.sqf", folder, folder, folder);
+    let input = pbo_bytes(&[(name.as_bytes(), 3, b"foo")]);
+
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("out");
+    cmd_unpack(&mut Cursor::new(input), output.clone(), false, false, None, false, false).unwrap();
+
+    let path = output.join(&folder).join(&folder).join(&folder).join("script.sqf");
+    assert!(path.as_os_str().len() > 260);
+    assert_eq!(b"foo".to_vec(), read(armake2::io::extended_path(&path)).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_read_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("out");
+    create_dir_all(&output).unwrap();
+    std::fs::set_permissions(&output, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Permissions don't apply to root
+    if write(output.join("probe"), b"").is_ok() {
+        return;
+    }
+
+    let input = pbo_bytes(&[(b"script.sqf", 3, b"foo")]);
+    let error = cmd_unpack(&mut Cursor::new(input), output.clone(), true, false, None, false, false).unwrap_err();
+    std::fs::set_permissions(&output, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert_eq!(std::io::ErrorKind::PermissionDenied, error.kind());
+    let message = error.to_string();
+    assert!(message.contains(&format!("Failed to create \"{}", output.display())), "{}", message);
+    assert!(message.contains("read-only"), "{}", message);
+}
+
+#[test]
+fn test_read_exceeding_limits() {
+    let input = pbo_bytes(&[
+        (b"small.sqf", 3, b"foo"),
+        (b"huge.paa", 0xF000_0000, b"bar"),
+    ]);
+
+    match PBO::read(&mut Cursor::new(input)) {
+        Ok(_) => panic!("PBO with entry data past the end of the file was read"),
+        Err(e) => assert!(e.to_string().contains("exceeds PBO format limits")),
+    }
+}
+
+#[test]
+fn test_from_directory_progress() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(&source).unwrap();
+    write(source.join("config.cpp"), "class CfgPatches {};").unwrap();
+    write(source.join("script.sqf"), "hint \"hi\";").unwrap();
+    write(source.join("notes.txt"), "excluded").unwrap();
+
+    let updates: RefCell<Vec<Progress>> = RefCell::new(Vec::new());
+    let pbo = PBO::from_directory_with_progress(source, false, &["*.txt".to_string()], &Vec::new(), Some(&|p| updates.borrow_mut().push(p))).unwrap();
+
+    let updates = updates.into_inner();
+    assert_eq!(vec![1, 2, 3], updates.iter().map(|p| p.current).collect::<Vec<usize>>());
+    assert!(updates.iter().all(|p| p.total == 3));
+    assert_eq!(2, pbo.files.len());
+}
+
+/// Returns name, data offset and size of every entry, including the header extension entry
+fn entry_layout(bytes: &[u8]) -> Vec<(String, usize, usize)> {
+    let mut cursor = Cursor::new(bytes);
+    let mut headers: Vec<(String, usize)> = Vec::new();
+    loop {
+        let mut name: Vec<u8> = Vec::new();
+        loop {
+            let b = cursor.read_u8().unwrap();
+            if b == 0 { break; }
+            name.push(b);
+        }
+        let method = cursor.read_u32::<LittleEndian>().unwrap();
+        cursor.set_position(cursor.position() + 12);
+        let size = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+
+        if method == 0x5665_7273 {
+            while cursor.get_ref()[cursor.position() as usize] != 0 || cursor.get_ref()[cursor.position() as usize + 1] != 0 {
+                cursor.set_position(cursor.position() + 1);
+            }
+            cursor.set_position(cursor.position() + 2);
+        } else if name.is_empty() {
+            break;
+        } else {
+            headers.push((String::from_utf8(name).unwrap(), size));
+        }
+    }
+
+    let mut offset = cursor.position() as usize;
+    headers.into_iter().map(|(name, size)| {
+        offset += size;
+        (name, offset - size, size)
+    }).collect()
+}
+
+#[test]
+fn test_write_aligned() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(source.join("data")).unwrap();
+    write(source.join("config.cpp"), "class CfgPatches {};").unwrap();
+    write(source.join("data").join("large.bin"), vec![7; 5000]).unwrap();
+    write(source.join("empty.sqf"), "").unwrap();
+    write(source.join("script.sqf"), "hint \"hi\";").unwrap();
+
+    let mut pbo = PBO::from_directory(source.clone(), false, &Vec::new(), &Vec::new()).unwrap();
+    pbo.header_extensions.insert("prefix".to_string(), "x\\test".to_string());
+
+    let compact = pbo.to_cursor().unwrap().into_inner();
+    assert!(entry_layout(&compact).iter().all(|(name, _, _)| !name.starts_with("$PAD$")));
+
+    let mut aligned: Vec<u8> = Vec::new();
+    pbo.write_aligned(&mut aligned, 4096).unwrap();
+
+    let layout = entry_layout(&aligned);
+    for (name, offset, size) in &layout {
+        if *size > 0 && !name.starts_with("$PAD$") {
+            assert_eq!(0, offset % 4096, "{} starts at {}", name, offset);
+        }
+        if name.starts_with("$PAD$") {
+            assert!(aligned[*offset..(offset + size)].iter().all(|b| *b == 0));
+        }
+    }
+    assert!(layout.iter().any(|(name, _, _)| name.starts_with("$PAD$")));
+
+    let checksum_start = aligned.len() - 20;
+    assert_eq!(&hash(MessageDigest::sha1(), &aligned[..(checksum_start - 1)]).unwrap()[..], &aligned[checksum_start..]);
+
+    let reread = PBO::read(&mut Cursor::new(&aligned)).unwrap();
+    assert_eq!(b"hint \"hi\";".to_vec(), reread.files.get("script.sqf").unwrap().get_ref().to_vec());
+    assert_eq!("x\\test", reread.header_extensions.get("prefix").unwrap());
+
+    let mut rewritten: Vec<u8> = Vec::new();
+    reread.write_aligned(&mut rewritten, 4096).unwrap();
+    assert_eq!(aligned, rewritten);
+    assert_eq!(compact, reread.to_cursor().unwrap().into_inner());
+
+    let output = dir.path().join("out");
+    cmd_unpack(&mut Cursor::new(&aligned), output.clone(), false, false, None, false, false).unwrap();
+    assert!(output.join("script.sqf").exists());
+    assert!(!output.join("$PAD$0001").exists());
+    assert_eq!("prefix=x\\test\n", read_to_string(output.join("$PBOPREFIX$")).unwrap());
+
+    assert!(pbo.write_aligned(&mut Vec::new(), 0).is_err());
+}
+
+fn artifact_times(cache: &Path) -> HashMap<PathBuf, SystemTime> {
+    read_dir(cache).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().unwrap() == "bin")
+        .map(|p| (p.clone(), p.metadata().unwrap().modified().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_build_cache() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(source.join("data")).unwrap();
+    write(source.join("macros.hpp"), "#define VALUE 1").unwrap();
+    write(source.join("config.cpp"), "#include \"macros.hpp\"\nclass CfgPatches { value = VALUE; };").unwrap();
+    write(source.join("data").join("a.rvmat"), "ambient[] = {1, 1, 1, 1};").unwrap();
+    write(source.join("data").join("b.rvmat"), "ambient[] = {0, 0, 0, 1};").unwrap();
+
+    let cache_dir = BuildCache::default_directory(&source);
+    assert_eq!(dir.path().canonicalize().unwrap().join(".armake2-cache"), cache_dir);
+
+    let build = |cache: Option<&BuildCache>| {
+        let mut output: Vec<u8> = Vec::new();
+        cmd_build(source.clone(), &mut output, &BuildOptions { cache, ..Default::default() }).unwrap();
+        output
+    };
+
+    let uncached = build(None);
+    assert!(!cache_dir.exists());
+
+    let cache = BuildCache::new(cache_dir.clone(), "");
+    assert_eq!(uncached, build(Some(&cache)));
+    assert_eq!((0, 3), (cache.hits(), cache.misses()));
+    let before = artifact_times(&cache_dir);
+    assert_eq!(3, before.len());
+
+    sleep(Duration::from_millis(1100));
+    write(source.join("data").join("b.rvmat"), "ambient[] = {0, 0, 0, 0.5};").unwrap();
+
+    let cache = BuildCache::new(cache_dir.clone(), "");
+    let rebuilt = build(Some(&cache));
+    assert_eq!((2, 1), (cache.hits(), cache.misses()));
+    assert_eq!(build(None), rebuilt);
+
+    let after = artifact_times(&cache_dir);
+    let changed: Vec<&PathBuf> = before.keys().filter(|p| before[*p] != after[*p]).collect();
+    assert_eq!(1, changed.len());
+
+    // Included files invalidate the config, touching them without changes doesn't
+    write(source.join("macros.hpp"), "#define VALUE 1").unwrap();
+    let cache = BuildCache::new(cache_dir.clone(), "");
+    build(Some(&cache));
+    assert_eq!((3, 0), (cache.hits(), cache.misses()));
+
+    write(source.join("macros.hpp"), "#define VALUE 2").unwrap();
+    let cache = BuildCache::new(cache_dir.clone(), "");
+    assert_eq!(build(None), build(Some(&cache)));
+    assert_eq!((2, 1), (cache.hits(), cache.misses()));
+
+    // Different options never reuse artifacts
+    let cache = BuildCache::new(cache_dir.clone(), "other");
+    build(Some(&cache));
+    assert_eq!((0, 3), (cache.hits(), cache.misses()));
+
+    assert_eq!(0, gc(&cache_dir, Duration::from_secs(60)).unwrap());
+    assert_eq!(6, gc(&cache_dir, Duration::ZERO).unwrap());
+    assert_eq!(0, read_dir(&cache_dir).unwrap().count());
+}
+
+#[test]
+fn test_build_cache_warnings() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(&source).unwrap();
+    write(source.join("config.cpp"), "class CfgPatches { class main { author = unquoted; }; };").unwrap();
+    let cache_dir = dir.path().join("cache");
+
+    let build = || {
+        let cache = BuildCache::new(cache_dir.clone(), "");
+        let before = get_warning_count("unquoted-string");
+        cmd_build(source.clone(), &mut Vec::new(), &BuildOptions { cache: Some(&cache), ..Default::default() }).unwrap();
+        (cache.hits(), get_warning_count("unquoted-string") - before)
+    };
+
+    // Reusing the rapified config raises its warnings again
+    assert_eq!((0, 1), build());
+    assert_eq!((1, 1), build());
+}
+
+#[test]
+fn test_build_cfgpatches() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(&source).unwrap();
+    write(source.join("config.cpp"), "class CfgPatches { class main { units[] = {}; }; };\nclass CfgVehicles { class Car; class new_car: Car {}; };").unwrap();
+
+    let build = |mode: CfgPatchesMode| {
+        let mut output: Vec<u8> = Vec::new();
+        cmd_build(source.clone(), &mut output, &BuildOptions { cfgpatches: mode, ..Default::default() }).unwrap();
+        let pbo = PBO::read(&mut Cursor::new(output)).unwrap();
+        let mut rapified = Cursor::new(pbo.files.get("config.bin").unwrap().get_ref().to_vec());
+        Config::read_rapified(&mut rapified).unwrap().to_string().unwrap()
+    };
+
+    let unchanged = build(CfgPatchesMode::Ignore);
+    assert!(unchanged.contains("units[] = {};"), "{}", unchanged);
+    assert_eq!(unchanged, build(CfgPatchesMode::Check));
+
+    let fixed = build(CfgPatchesMode::Fix);
+    assert!(fixed.contains("units[] = {\"new_car\"};"), "{}", fixed);
+
+    // The check warns on a warm cache like on a cold one
+    let cache_dir = dir.path().join("cache");
+    for mode in [CfgPatchesMode::Check, CfgPatchesMode::Fix] {
+        let cache = BuildCache::new(cache_dir.clone(), &format!("{:?}", mode));
+        for hits in [0, 1] {
+            let before = get_warning_count("cfgpatches-missing-class");
+            cmd_build(source.clone(), &mut Vec::new(), &BuildOptions { cache: Some(&cache), cfgpatches: mode, ..Default::default() }).unwrap();
+            assert_eq!((hits, 1), (cache.hits(), get_warning_count("cfgpatches-missing-class") - before));
+        }
+    }
+}
+
+#[test]
+fn test_read_trailing_data() {
+    let clean = pbo_bytes(&[(b"script.sqf", 10, b"hint \"hi\";")]);
+    let mut input = clean.clone();
+    input.extend((0..1024u32).map(|i| (i * 7 % 256) as u8));
+
+    let pbo = PBO::read(&mut Cursor::new(&input)).unwrap();
+    assert_eq!(1024, pbo.trailing_data);
+    assert_eq!(Some(vec![0xAB; 20]), pbo.checksum);
+    assert_eq!(0, PBO::read(&mut Cursor::new(&clean)).unwrap().trailing_data);
+
+    let (lenient, _) = PBO::read_lenient(&mut Cursor::new(&input)).unwrap();
+    assert_eq!((1024, Some(vec![0xAB; 20])), (lenient.trailing_data, lenient.checksum));
+
+    // Repacking drops the junk
+    let mut repacked: Vec<u8> = Vec::new();
+    pbo.write(&mut repacked).unwrap();
+    let reread = PBO::read(&mut Cursor::new(&repacked)).unwrap();
+    assert_eq!(0, reread.trailing_data);
+    assert_eq!(b"hint \"hi\";".to_vec(), reread.files.get("script.sqf").unwrap().get_ref().to_vec());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_inspect_with_format(&mut Cursor::new(&input), &mut output, ListingFormat::Native).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("# Trailing data: 1024 bytes after the checksum"));
+
+    assert!(PBO::read(&mut Cursor::new(&clean[..clean.len() - 5])).is_err());
+}
+
+#[test]
+fn test_build_missing_resources() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(source.join("data")).unwrap();
+    write(source.join("$PBOPREFIX$"), "x\\mymod\\addons\\main").unwrap();
+    write(source.join("data").join("thing.p3d"), "MLOD").unwrap();
+    write(source.join("config.cpp"), r##"
+class CfgVehicles {
+    class thing {
+        model = "\x\mymod\addons\main\data\thing.p3d";
+        typo = "\x\mymod\addons\main\data\thnig.p3d";
+        hiddenSelectionsTextures[] = {"#(argb,8,8,3)color(1,0,0,1)", "", {"\A3\data_f\default_co.paa"}};
+        sound[] = {"\x\other\addons\sounds\engine.wss", 1, 1};
+        displayName = "\x\mymod is not a file";
+    };
+};
+"##).unwrap();
+
+    // Another mod with the sound in one of its PBOs
+    let other = dir.path().join("@other");
+    let other_source = dir.path().join("sounds");
+    create_dir_all(other.join("addons")).unwrap();
+    create_dir_all(&other_source).unwrap();
+    write(other_source.join("$PBOPREFIX$"), "x\\other\\addons\\sounds").unwrap();
+    write(other_source.join("engine.wss"), "WSS").unwrap();
+    PBO::from_directory(other_source, false, &[], &[]).unwrap()
+        .write(&mut std::fs::File::create(other.join("addons").join("sounds.pbo")).unwrap()).unwrap();
+
+    let pbo = PBO::from_directory(source.clone(), true, &[], &[]).unwrap();
+    let missing = |paths: &[&Path]| -> Vec<(String, String)> {
+        let check = ResourceCheck { paths: paths.iter().map(|p| p.to_path_buf()).collect(), strict: false };
+        find_missing_resources(&pbo, &check).unwrap().into_iter().map(|m| (m.property, m.path)).collect()
+    };
+
+    assert_eq!(vec![
+        ("CfgVehicles/thing/typo".to_string(), "\\x\\mymod\\addons\\main\\data\\thnig.p3d".to_string()),
+        ("CfgVehicles/thing/hiddenSelectionsTextures".to_string(), "\\A3\\data_f\\default_co.paa".to_string()),
+        ("CfgVehicles/thing/sound".to_string(), "\\x\\other\\addons\\sounds\\engine.wss".to_string()),
+    ], missing(&[]));
+    assert_eq!(vec![
+        ("CfgVehicles/thing/typo".to_string(), "\\x\\mymod\\addons\\main\\data\\thnig.p3d".to_string()),
+    ], missing(&[Path::new("a3"), &other]));
+
+    let build = |strict: bool| {
+        let check = ResourceCheck { paths: vec![PathBuf::from("a3"), other.clone()], strict };
+        cmd_build(source.clone(), &mut Vec::new(), &BuildOptions { resources: check, ..Default::default() })
+    };
+    assert!(build(false).is_ok());
+    assert_eq!("1 referenced files are missing.", build(true).unwrap_err().to_string());
+
+    // Warnings point to the line of the source config
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["build", "--check-paths", "a3", "--check-paths", "@other", "addon", "addon.pbo"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("config.cpp:5: warning: CfgVehicles/thing/typo references"), "{}", stderr);
+}
+
+#[test]
+fn test_pack_texture_max_size() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("addon");
+    create_dir_all(&source).unwrap();
+    let img = image::DynamicImage::new_rgba8(256, 256);
+    Paa::from_image(&img, PaaType::DXT5, false).unwrap().write_to_file(source.join("big_co.paa")).unwrap();
+    Paa::from_image(&img.thumbnail(64, 64), PaaType::DXT5, false).unwrap().write_to_file(source.join("small_co.paa")).unwrap();
+    write(source.join("broken_co.paa"), b"not a paa").unwrap();
+    let original = read(source.join("big_co.paa")).unwrap();
+    write(source.join("copy_co.paa"), &original).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let warnings = get_warning_count("textures-downscaled");
+    cmd_pack(source.clone(), &mut output, &PackOptions { texture_max_size: Some(128), ..Default::default() }).unwrap();
+    assert_eq!(warnings + 1, get_warning_count("textures-downscaled"));
+    let pbo = PBO::read(&mut Cursor::new(output)).unwrap();
+
+    let paa = |name: &str| Paa::from_reader(&mut Cursor::new(pbo.files.get(name).unwrap().get_ref().to_vec())).unwrap();
+    assert_eq!(Some((128, 128)), paa("big_co.paa").dimensions(0));
+    assert_eq!(8, paa("big_co.paa").mipmap_count());
+    assert_eq!(Some((64, 64)), paa("small_co.paa").dimensions(0));
+    assert_eq!(pbo.files.get("big_co.paa").unwrap().get_ref(), pbo.files.get("copy_co.paa").unwrap().get_ref());
+    assert_eq!(b"not a paa".to_vec(), pbo.files.get("broken_co.paa").unwrap().get_ref().to_vec());
+
+    // Sources are left untouched
+    assert_eq!(original, read(source.join("big_co.paa")).unwrap());
+
+    let mut pbo = PBO::from_directory(source.clone(), false, &[], &[]).unwrap();
+    let size = pbo.files.get("big_co.paa").unwrap().get_ref().len();
+    let saved = downscale_textures(&mut pbo, 128);
+    assert!(saved > 0);
+    assert_eq!(size - saved / 2, pbo.files.get("big_co.paa").unwrap().get_ref().len());
+    assert_eq!(size - saved / 2, pbo.files.get("copy_co.paa").unwrap().get_ref().len());
+    assert_eq!(0, downscale_textures(&mut pbo, 128));
+}
+
+#[test]
+fn test_inspect_mikero_format() {
+    let mut input = pbo_bytes(&[
+        (b"config.bin", 3, b"foo"),
+        (b"scripts\\init.sqf", 4, b"init"),
+        (b"$PAD$0001", 2, &[0, 0]),
+        (b"data/texture.paa", 5, b"paa!!"),
+    ]);
+    let position = input.windows(16).position(|w| w == b"scripts\\init.sqf").unwrap() + 17 + 12;
+    input[position..(position + 4)].copy_from_slice(&1_700_000_000u32.to_le_bytes());
+
+    let mut output: Vec<u8> = Vec::new();
+    cmd_inspect_with_format(&mut Cursor::new(&input), &mut output, ListingFormat::Mikero).unwrap();
+    assert_eq!("\
+prefix=test
+
+config.bin\t3\t
+scripts\\init.sqf\t4\t14/11/2023 22:13:20
+data\\texture.paa\t5\t
+", String::from_utf8(output).unwrap());
+
+    assert_eq!(ListingFormat::Mikero, parse_listing_format("Mikero").unwrap());
+    assert!(parse_listing_format("extractpbo").is_err());
+}
+
+/// Builds a PBO with the header quirks of old tools, in the layout `PBO::write` uses otherwise.
+fn legacy_pbo_bytes(timestamp: u32, terminated: bool, padding: usize) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::new();
+    output.push(0);
+    output.write_u32::<LittleEndian>(0x5665_7273).unwrap();
+    for field in [0, 0, timestamp, 0] {
+        output.write_u32::<LittleEndian>(field).unwrap();
+    }
+    output.extend(b"prefix\0x\\test\0");
+    if terminated {
+        output.push(0);
+    }
+    output.extend(vec![0; padding]);
+
+    let entries: [(&[u8], &[u8]); 2] = [(b"a.sqf", b"aaa"), (b"data\\b.sqf", b"bb")];
+    for (name, data) in entries {
+        write_header(&mut output, name, 0, data.len() as u32);
+    }
+    write_header(&mut output, b"", 0, 0);
+    for (_, data) in entries {
+        output.extend(data);
+    }
+
+    let checksum = hash(MessageDigest::sha1(), &output).unwrap();
+    output.push(0);
+    output.extend(&*checksum);
+    output
+}
+
+#[test]
+fn test_read_legacy_headers() {
+    let dir = tempdir().unwrap();
+    let inspect_warns = |name: &str, bytes: &[u8]| {
+        let path = dir.path().join(name);
+        write(&path, bytes).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_armake2")).arg("inspect").arg(&path).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stderr).matches("[legacy-pbo-header]").count()
+    };
+
+    let regular = legacy_pbo_bytes(0, true, 0);
+    assert_eq!(regular, PBO::read(&mut Cursor::new(&regular)).unwrap().to_cursor().unwrap().into_inner());
+    assert_eq!(0, inspect_warns("regular.pbo", &regular));
+
+    // The unterminated list used to swallow the first entry header as an extension
+    for (name, quirk, quirks) in [
+        ("timestamp", legacy_pbo_bytes(1_000_000_000, true, 0), 1),
+        ("padding", legacy_pbo_bytes(0, true, 3), 1),
+        ("unterminated", legacy_pbo_bytes(0, false, 0), 1),
+        ("all", legacy_pbo_bytes(1_000_000_000, true, 16), 2),
+    ] {
+        let pbo = PBO::read(&mut Cursor::new(&quirk)).unwrap();
+        let files: Vec<(String, Vec<u8>)> = pbo.files.iter().map(|(n, d)| (n.clone(), d.get_ref().to_vec())).collect();
+        assert_eq!(vec![("a.sqf".to_string(), b"aaa".to_vec()), ("data\\b.sqf".to_string(), b"bb".to_vec())], files, "{}", name);
+        assert_eq!(vec![("prefix", "x\\test")], pbo.header_extensions.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>(), "{}", name);
+
+        let (lenient, renamed) = PBO::read_lenient(&mut Cursor::new(&quirk)).unwrap();
+        assert_eq!((2, 0), (lenient.files.len(), renamed.len()), "{}", name);
+        assert_eq!(Some(b"bb".to_vec()), read_entry(&mut Cursor::new(&quirk), "data/b.sqf").unwrap(), "{}", name);
+
+        // Written again with the same quirks, or regularly when aligned
+        assert_eq!(quirk, pbo.to_cursor().unwrap().into_inner(), "{}", name);
+        let mut aligned: Vec<u8> = Vec::new();
+        pbo.write_aligned(&mut aligned, 4).unwrap();
+        assert_eq!(2, PBO::read(&mut Cursor::new(&aligned)).unwrap().files.iter().filter(|(n, _)| !n.starts_with("$PAD$")).count());
+
+        // Once per quirk, although the listing reads the headers again
+        assert_eq!(quirks, inspect_warns(&format!("{}.pbo", name), &quirk), "{}", name);
+    }
+
+    // The nulls at the end of a PBO without entries aren't padding
+    let empty = pbo_bytes(&[]);
+    let pbo = PBO::read(&mut Cursor::new(&empty)).unwrap();
+    assert_eq!((0, Some(vec![0xAB; 20])), (pbo.files.len(), pbo.checksum));
+    assert_eq!(0, inspect_warns("empty.pbo", &empty));
+}
+
+#[test]
+fn test_pbo_empty_folders_and_zero_byte_files() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    create_dir_all(source.join("data").join("empty")).unwrap();
+    create_dir_all(source.join("deep").join("er").join("empty")).unwrap();
+    write(source.join("$NOBIN$"), "").unwrap();
+    write(source.join("data").join("placeholder.txt"), "").unwrap();
+    write(source.join("data").join("script.sqf"), "hint \"hi\";").unwrap();
+    create_dir_all(source.join("data").join("kept")).unwrap();
+    write(source.join("data").join("kept").join(".keep"), "").unwrap();
+
+    let pack = |folder: &Path, keep_empty_dirs: bool| {
+        let mut output: Vec<u8> = Vec::new();
+        cmd_pack(folder.to_path_buf(), &mut output, &PackOptions { keep_empty_dirs, ..Default::default() }).unwrap();
+        let pbo = PBO::read(&mut Cursor::new(output)).unwrap();
+        let mut entries: Vec<(String, Vec<u8>)> = pbo.files.iter().map(|(name, data)| (name.clone(), data.get_ref().to_vec())).collect();
+        entries.sort();
+        (entries, pbo)
+    };
+
+    // Zero-byte files are packed either way, empty folders only as $EMPTYDIR$ entries
+    let (entries, _) = pack(&source, false);
+    assert_eq!(vec!["$NOBIN$", "data\\kept\\.keep", "data\\placeholder.txt", "data\\script.sqf"], entries.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>());
+    let (entries, pbo) = pack(&source, true);
+    assert_eq!(vec!["$NOBIN$", "data\\empty\\$EMPTYDIR$", "data\\kept\\.keep", "data\\placeholder.txt", "data\\script.sqf", "deep\\er\\empty\\$EMPTYDIR$"],
+        entries.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>());
+    assert!(entries.iter().filter(|(name, _)| name.ends_with("$EMPTYDIR$")).all(|(_, data)| data.is_empty()));
+
+    // Building keeps them the same way
+    let mut built: Vec<u8> = Vec::new();
+    cmd_build(source.clone(), &mut built, &BuildOptions { pack: PackOptions { keep_empty_dirs: true, ..Default::default() }, ..Default::default() }).unwrap();
+    let built = PBO::read(&mut Cursor::new(built)).unwrap();
+    assert!(built.files.contains_key("deep\\er\\empty\\$EMPTYDIR$"));
+
+    let bytes = pbo.to_cursor().unwrap().into_inner();
+    let unpacked = dir.path().join("unpacked");
+    cmd_unpack(&mut Cursor::new(bytes.clone()), unpacked.clone(), false, false, None, false, true).unwrap();
+    assert_eq!(0, read_dir(unpacked.join("data").join("empty")).unwrap().count());
+    assert_eq!(0, read_dir(unpacked.join("deep").join("er").join("empty")).unwrap().count());
+    assert_eq!(Vec::<u8>::new(), read(unpacked.join("data").join("placeholder.txt")).unwrap());
+    assert_eq!(Vec::<u8>::new(), read(unpacked.join("$NOBIN$")).unwrap());
+    assert_eq!(Vec::<u8>::new(), read(unpacked.join("data").join("kept").join(".keep")).unwrap());
+    assert_eq!(entries, pack(&unpacked, true).0);
+
+    // Without the flag the placeholders are plain files
+    let plain = dir.path().join("plain");
+    cmd_unpack(&mut Cursor::new(bytes), plain.clone(), false, false, None, false, false).unwrap();
+    assert!(plain.join("data").join("empty").join("$EMPTYDIR$").is_file());
+}
+
+#[test]
+fn test_pbo_nobin_folders() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("source");
+    for folder in ["raw", "notest", "raw\\nested"].map(|f| f.replace('\\', "/")) {
+        create_dir_all(source.join(&folder)).unwrap();
+        write(source.join(folder).join("config.cpp"), "class CfgPatches {};\n").unwrap();
+    }
+    write(source.join("config.cpp"), "class CfgPatches {};\n").unwrap();
+    write(source.join("raw").join("$NOBIN$"), "").unwrap();
+    write(source.join("notest").join("$NOBIN-NOTEST$"), "").unwrap();
+
+    let pbo = PBO::from_directory(source, true, &Vec::new(), &Vec::new()).unwrap();
+    let mut names: Vec<&str> = pbo.files.keys().map(|name| name.as_str()).collect();
+    names.sort();
+    assert_eq!(vec!["config.bin", "notest\\$NOBIN-NOTEST$", "notest\\config.cpp", "raw\\$NOBIN$", "raw\\config.cpp", "raw\\nested\\config.cpp"], names);
+    assert_eq!(b"class CfgPatches {};\n".to_vec(), pbo.files["raw\\nested\\config.cpp"].get_ref().to_vec());
+}
+
+#[test]
+fn test_pack_exclude_patterns() {
+    let source = tempdir().unwrap();
+    for file in ["script.sqf", "notes.txt", "readme", "data/thing.paa", "data/thing.psd", "x"] {
+        let path = source.path().join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, b"data").unwrap();
+    }
+
+    let packed = |excludes: &[&str]| {
+        let excludes: Vec<String> = excludes.iter().map(|e| e.to_string()).collect();
+        let pbo = PBO::from_directory(source.path().to_path_buf(), false, &excludes, &[]).unwrap();
+        let mut names: Vec<String> = pbo.files.keys().cloned().collect();
+        names.sort();
+        names
+    };
+
+    // Stars match any number of characters, including none and at the end of the pattern
+    assert_eq!(vec!["data\\thing.paa", "script.sqf", "x"], packed(&["*.txt", "read*", "*.psd"]));
+    assert_eq!(vec!["notes.txt", "readme", "script.sqf", "x"], packed(&["data\\*"]));
+    assert_eq!(vec!["data\\thing.paa", "data\\thing.psd", "notes.txt", "readme", "script.sqf"], packed(&["x*"]));
+    assert!(packed(&["*"]).is_empty());
+}
@@ -0,0 +1,73 @@
+use std::fs::{File, create_dir_all, write};
+use std::path::{Path, PathBuf};
+
+use tempfile::{tempdir};
+
+use armake2::pbo::*;
+use armake2::pbogrep::*;
+
+fn build(source: &Path, target: &Path) {
+    let pbo = PBO::from_directory(source.to_path_buf(), true, &[], &[]).unwrap();
+    pbo.write(&mut File::create(target).unwrap()).unwrap();
+}
+
+fn grep(pattern: &str, paths: &[PathBuf], options: &GrepOptions) -> Vec<String> {
+    let mut output: Vec<u8> = Vec::new();
+    let count = cmd_pbo_grep(pattern, paths, options, &mut output).unwrap();
+    let lines: Vec<String> = String::from_utf8(output).unwrap().lines().map(str::to_string).collect();
+    assert_eq!(count, lines.len());
+    lines
+}
+
+#[test]
+fn test_pbo_grep() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("main");
+    create_dir_all(source.join("functions")).unwrap();
+    write(source.join("config.cpp"), "class CfgPatches { class main { units[] = {}; }; };\nclass CfgSounds { class Alarm { sound[] = {\"sounds\\alarm.ogg\", 1, 1}; }; };\n").unwrap();
+    write(source.join("functions/fn_alarm.sqf"), "// Plays the alarm\r\nplaySound \"Alarm\";\nhint \"a.b\";\n").unwrap();
+    write(source.join("data.bin"), b"\0\x01alarm\0ALARM").unwrap();
+
+    let addons = dir.path().join("addons");
+    create_dir_all(addons.join("sub")).unwrap();
+    build(&source, &addons.join("main.pbo"));
+    write(source.join("functions/fn_alarm.sqf"), "playSound \"Other\";\n").unwrap();
+    build(&source, &addons.join("sub/other.pbo"));
+
+    let main = addons.join("main.pbo");
+    let main_name = main.display().to_string();
+    let default = GrepOptions::default();
+
+    // Literal and case sensitive by default, binary entries and rapified configs skipped
+    assert_eq!(vec![format!("{}:functions\\fn_alarm.sqf:2: playSound \"Alarm\";", main_name)], grep("Alarm", &[main.clone()], &default));
+    assert!(grep("a.b", &[main.clone()], &default).iter().all(|l| l.ends_with("hint \"a.b\";")));
+    assert!(grep("a.c", &[main.clone()], &default).is_empty());
+
+    let ignore_case = GrepOptions { ignore_case: true, ..Default::default() };
+    let lines = grep("alarm", &[main.clone()], &ignore_case);
+    assert_eq!(2, lines.len());
+    assert!(lines[0].ends_with(":1: // Plays the alarm"), "{}", lines[0]);
+
+    let regex = GrepOptions { regex: true, ..Default::default() };
+    assert_eq!(1, grep("^hint \"a.b\";$", &[main.clone()], &regex).len());
+    assert!(cmd_pbo_grep("(", &[main.clone()], &regex, &mut Vec::new()).is_err());
+
+    // Derapified configs can be searched for classes
+    let derap = GrepOptions { derap: true, ..Default::default() };
+    let lines = grep("class Alarm", &[main.clone()], &derap);
+    assert_eq!(1, lines.len());
+    assert!(lines[0].starts_with(&format!("{}:config.bin:", main_name)), "{}", lines[0]);
+
+    // Byte offsets in binary entries
+    let binary = GrepOptions { binary: true, ignore_case: true, entries: vec!["DATA.*".to_string()], ..Default::default() };
+    assert_eq!(vec![format!("{}:data.bin:@2: alarm", main_name), format!("{}:data.bin:@8: ALARM", main_name)],
+        grep("alarm", &[main.clone()], &binary));
+
+    // Entry patterns ignore slash direction, folders are searched recursively in order
+    let sqf = GrepOptions { entries: vec!["functions/*.sqf".to_string()], ..Default::default() };
+    let lines = grep("playSound", &[addons.clone()], &sqf);
+    assert_eq!(2, lines.len());
+    assert!(lines[0].starts_with(&main_name) && lines[1].contains("other.pbo") && lines[1].ends_with("\"Other\";"), "{:?}", lines);
+
+    assert!(cmd_pbo_grep("x", &[dir.path().join("missing.pbo")], &default, &mut Vec::new()).is_err());
+}
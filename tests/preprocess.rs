@@ -1,6 +1,8 @@
 use std::io::{Write};
 use std::fs::{File, create_dir};
 use std::path::{PathBuf};
+use std::process::{Command};
+use std::time::{Instant};
 
 use tempfile::{tempdir};
 
@@ -96,12 +98,36 @@ bar_foo\n");
     assert_eq!((2, Some(PathBuf::from("myfile"))), info.line_origins[2]);
 }
 
+#[test]
+fn test_preprocess_include_case() {
+    let includedir = tempdir().unwrap();
+    let addondir = includedir.path().join("Whatever");
+    create_dir(&addondir).unwrap();
+    File::create(addondir.join("Script_Macros.hpp")).unwrap().write_all(b"#define FOO bar\n").unwrap();
+    File::create(addondir.join("$PBOPREFIX$")).unwrap().write_all(b"x\\CBA\\addons\\whatever\n").unwrap();
+    let includefolders = vec![PathBuf::from(includedir.path())];
+
+    // The game ignores case and slash direction, and so does the lookup by prefix
+    let input = String::from("#include \"\\x\\cba\\Addons/Whatever\\script_macros.HPP\"\nFOO\n");
+    let (output, _) = preprocess(input, Some(PathBuf::from("myfile")), &includefolders).unwrap();
+    assert_eq!("bar", output.trim());
+
+    let input = String::from("#include \"\\x\\cba\\..\\whatever\\script_macros.hpp\"\n");
+    assert!(preprocess(input, Some(PathBuf::from("myfile")), &includefolders).is_err());
+}
+
 #[test]
 fn test_proprocess_bom() {
     let input = String::from_utf8(vec![0xef,0xbb,0xbf]).unwrap() + "blub";
     let (output, _) = preprocess(input, None, &Vec::new()).unwrap();
 
     assert_eq!("blub", output.trim());
+
+    // Shorter than a BOM, or starting with a character of more than one byte
+    for input in ["", "a", "ä;"] {
+        let (output, _) = preprocess(input.to_string(), None, &Vec::new()).unwrap();
+        assert_eq!(input, output.trim());
+    }
 }
 
 #[test]
@@ -122,3 +148,104 @@ class test\\
     assert_eq!(5, info.line_origins.len());
     assert_eq!(8, info.line_origins[2].0);
 }
+
+#[test]
+fn test_preprocess_has_include() {
+    let dir = tempdir().unwrap();
+    let addondir = dir.path().join("addon");
+    create_dir(&addondir).unwrap();
+    File::create(addondir.join("$PBOPREFIX$")).unwrap().write_all(b"x\\myaddon").unwrap();
+    File::create(addondir.join("script_macros.hpp")).unwrap().write_all(b"").unwrap();
+
+    let origin = dir.path().join("config.cpp");
+    File::create(&origin).unwrap();
+
+    let input = String::from("\
+#if __has_include(\"\\x\\myaddon\\script_macros.hpp\")
+prefixed
+#endif
+#if __has_include(\"missing.hpp\")
+missing
+#else
+not_missing
+#endif
+#if !__has_include(\"missing.hpp\")
+negated
+#endif
+#ifexists \"addon\\script_macros.hpp\"
+relative
+#endif
+#ifdef UNDEFINED
+#if __has_include(\"skipped.hpp\")
+#endif
+#endif
+");
+
+    let includefolders = vec![PathBuf::from(dir.path())];
+    let (output, info) = preprocess(input, Some(origin), &includefolders).unwrap();
+
+    let lines: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(vec!["prefixed", "not_missing", "negated", "relative"], lines);
+    assert!(info.includes.is_empty());
+
+    let missing = dir.path().canonicalize().unwrap().join("missing.hpp");
+    assert_eq!(vec![missing.clone(), missing], info.probed_includes);
+}
+
+fn macro_bomb(levels: usize) -> String {
+    let mut input = String::from("#define A0 x x x x x x x x x x\n");
+    for level in 1..levels {
+        input += &format!("#define A{} {}\n", level, vec![format!("A{}", level - 1); 10].join(" "));
+    }
+    input + &format!("A{}\n", levels - 1)
+}
+
+#[test]
+fn test_preprocess_limits() {
+    let limits = PreprocessLimits { max_output_size: 1024 * 1024, ..Default::default() };
+    let start = Instant::now();
+
+    // Ten billion tokens stop at the size limit
+    let error = preprocess_with_limits(macro_bomb(10), None, &[], None, &limits).unwrap_err().to_string();
+    assert!(error.contains("Macros expand to more than 1048576 bytes: A9 > A8 > A7"), "{}", error);
+    assert!(preprocess_with_limits(macro_bomb(4), None, &[], None, &limits).is_ok());
+
+    // Expansions count even if nothing ends up in the output, and across lines
+    let input = macro_bomb(6).replace("A5\n", "#define DROP(x)\n") + &"DROP(A5)\n".repeat(100);
+    let error = preprocess_with_limits(input, None, &[], None, &limits).unwrap_err().to_string();
+    assert!(error.contains("Macros expand to more than 1048576 bytes: DROP > A5 > "), "{}", error);
+
+    let error = preprocess_with_limits("x = 1;\n".repeat(200000), None, &[], None, &limits).unwrap_err().to_string();
+    assert!(error.contains("Output is larger than 1048576 bytes."), "{}", error);
+
+    // Arguments are expanded outside of the recursion check
+    let input = String::from("#define F(x) G(F(x))\n#define G(x) x\nF(1)\n");
+    let error = preprocess_with_limits(input, None, &[], None, &limits).unwrap_err().to_string();
+    assert!(error.contains("Macro expansion is deeper than 128 levels: F > G > F > G"), "{}", error);
+    let error = preprocess_with_limits(macro_bomb(4), None, &[], None, &PreprocessLimits { max_macro_depth: 3, ..limits }).unwrap_err().to_string();
+    assert!(error.contains("deeper than 3 levels: A3 > A2 > A1 > A0"), "{}", error);
+    assert!(preprocess_with_limits(macro_bomb(4), None, &[], None, &PreprocessLimits { max_macro_depth: 4, ..limits }).is_ok());
+
+    assert!(start.elapsed().as_secs() < 10, "{:?}", start.elapsed());
+}
+
+#[test]
+fn test_preprocess_include_depth() {
+    let dir = tempdir().unwrap();
+    File::create(dir.path().join("a.hpp")).unwrap().write_all(b"#include \"b.hpp\"\n").unwrap();
+    File::create(dir.path().join("b.hpp")).unwrap().write_all(b"#include \"a.hpp\"\n").unwrap();
+    let origin = dir.path().join("config.cpp");
+    File::create(&origin).unwrap();
+
+    let limits = PreprocessLimits { max_include_depth: 5, ..Default::default() };
+    let error = preprocess_with_limits("#include \"a.hpp\"\n".to_string(), Some(origin.clone()), &[], None, &limits).unwrap_err().to_string();
+    assert!(error.contains("Includes are nested deeper than 5 levels: "), "{}", error);
+    assert!(error.contains("config.cpp > "), "{}", error);
+    assert_eq!(2, error.matches("a.hpp > ").count(), "{}", error);
+
+    // The command line takes the limits too
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["preprocess", "--max-include-depth", "5", "a.hpp"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nested deeper than 5 levels"), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,78 @@
+use std::fs::{create_dir_all, read, write};
+use std::path::{Path};
+use std::process::{Command};
+
+use tempfile::{tempdir};
+
+use armake2::project::*;
+
+fn armake2(dir: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_project_build() {
+    let dir = tempdir().unwrap();
+    let project = dir.path();
+    create_dir_all(project.join("addons/main")).unwrap();
+    create_dir_all(project.join("include/common")).unwrap();
+    create_dir_all(project.join("keys")).unwrap();
+    write(project.join("include/common/version.hpp"), "#define VERSION 7\n").unwrap();
+    write(project.join("addons/main/config.cpp"), "#include \"\\common\\version.hpp\"\nclass CfgPatches { class main { units[] = {}; version = VERSION; }; };\n").unwrap();
+    write(project.join("addons/main/script.sqf"), "hint \"main\";").unwrap();
+    write(project.join("addons/main/notes.txt"), "not packed").unwrap();
+    armake2(&project.join("keys"), &["keygen", "mygroup"]);
+
+    write(project.join(PROJECT_FILE), "\
+[build]
+exclude = [\"*.txt\"]
+headerext = [\"version=1.2\"]
+
+[preprocess]
+include = [\"include\"]
+
+[sign]
+key = \"keys/mygroup.biprivatekey\"
+").unwrap();
+
+    // Found from a subfolder, paths in it are relative to its folder
+    armake2(&project.join("addons"), &["build", "main", "configured.pbo"]);
+
+    // An empty project file elsewhere keeps the settings of the one in the project out
+    let flagged = tempdir().unwrap();
+    let key = project.join("keys/mygroup.biprivatekey");
+    let include = project.join("include");
+    write(flagged.path().join(PROJECT_FILE), "").unwrap();
+    armake2(&project.join("addons"), &["build", "--config", flagged.path().join(PROJECT_FILE).to_str().unwrap(),
+        "-x", "*.txt", "-e", "version=1.2", "-i", include.to_str().unwrap(), "-k", key.to_str().unwrap(), "main", "flagged.pbo"]);
+
+    let configured = read(project.join("addons/configured.pbo")).unwrap();
+    assert_eq!(read(project.join("addons/flagged.pbo")).unwrap(), configured);
+    assert_eq!(read(project.join("addons/flagged.pbo.mygroup.bisign")).unwrap(), read(project.join("addons/configured.pbo.mygroup.bisign")).unwrap());
+    assert!(!configured.windows(10).any(|w| w == b"not packed"));
+    assert!(configured.windows(11).any(|w| w == b"version\x001.2"));
+
+    // Flags override the file
+    armake2(&project.join("addons"), &["build", "-x", "*.sqf", "main", "overridden.pbo"]);
+    let overridden = read(project.join("addons/overridden.pbo")).unwrap();
+    assert!(overridden.windows(10).any(|w| w == b"not packed"));
+    assert!(!overridden.windows(11).any(|w| w == b"hint \"main\""));
+}
+
+#[test]
+fn test_project_errors() {
+    let error = ProjectConfig::parse("[build]\nbinarize = \"yes\"\n").unwrap_err().to_string();
+    assert!(error.contains("build.binarize") && error.contains("expected a boolean"), "{}", error);
+
+    let error = ProjectConfig::parse("[preprocess]\ndefines = [\"DEBUG\"]\n").unwrap_err().to_string();
+    assert!(error.contains("unknown field `defines`"), "{}", error);
+
+    let error = ProjectConfig::parse("[sign]\nversion = 4\n").unwrap_err().to_string();
+    assert!(error.contains("sign.version"), "{}", error);
+
+    let config = ProjectConfig::parse("[textures]\nmax_size = 1024\n[build]\nbinarize = false\n").unwrap();
+    assert_eq!(Some(1024), config.textures.max_size);
+    assert_eq!(Some(false), config.build.binarize);
+    assert!(config.build.exclude.is_empty());
+}
@@ -0,0 +1,100 @@
+// Byte-exact fixtures of the file formats, for checking that output doesn't depend on the
+// endianness or pointer width of the host. Everything happens in memory, so the tests also run
+// under Miri, which can interpret a big-endian target:
+//
+//     cargo miri test --test roundtrip --target s390x-unknown-linux-gnu
+//
+// or natively on one through qemu, e.g. with cross:
+//
+//     cross test --test roundtrip --target powerpc64-unknown-linux-gnu
+
+use std::io::{Cursor};
+
+use armake2::config::*;
+use armake2::io::*;
+use armake2::paa::*;
+use armake2::pbo::*;
+
+/// A 2x2 ARGB8888 PAA with both mipmaps uncompressed, the offsets padded to 16, the empty
+/// palette and the terminating empty mipmap
+const PAA: &[u8] = b"\x88\x88\
+    GGATCGVA\x04\0\0\0\x64\x32\x32\xff\
+    GGATCXAM\x04\0\0\0\xc8\x64\x32\xff\
+    GGATSFFO\x40\0\0\0\x70\0\0\0\x87\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\
+    \x02\0\x02\0\x10\0\0\x32\0\0\xff\x32\0\xc8\xff\x32\x64\0\xff\x32\x64\xc8\xff\
+    \x01\0\x01\0\x04\0\0\x32\x32\x64\xff\
+    \0\0\0\0\0\0";
+
+/// `class A { b = 1; c[] = {2.5, "x"}; };` rapified
+const CONFIG: &[u8] = b"\0raP\0\0\0\0\x08\0\0\0\x2f\0\0\0\
+    \0\x01\0A\0\x19\0\0\0\
+    \0\x02\x01\x02b\0\x01\0\0\0\x02c\0\x02\x01\0\0\x20\x40\0x\0\
+    \0\0\0\0";
+
+/// A PBO with a `Vers` header, a prefix and one file
+const PBO: &[u8] = b"\0sreV\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0prefix\0test\0\0\
+    a.txt\0\0\0\0\0\x05\0\0\0\0\0\0\0\0\0\0\0\x05\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    hello\
+    \0\x51\x2b\x3c\xd7\xf6\x90\x96\x92\x19\xac\xb9\xdd\x3f\x2b\x62\xaa\x46\x0d\xe7\x77";
+
+#[test]
+fn test_roundtrip_paa() {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(2, 2, |x, y| image::Rgba([x as u8 * 200, y as u8 * 100, 50, 255])));
+    let paa = Paa::from_image(&img, PaaType::ARGB8888, false).unwrap();
+
+    let mut output = Cursor::new(Vec::new());
+    paa.write(&mut output).unwrap();
+    assert_eq!(PAA, &output.into_inner()[..]);
+    let mut output: Vec<u8> = Vec::new();
+    paa.write_to(&mut output).unwrap();
+    assert_eq!(PAA, &output[..]);
+
+    let read = Paa::from_reader(&mut Cursor::new(PAA)).unwrap();
+    assert_eq!(Some((2, 2)), read.dimensions(0));
+    assert_eq!(Some((1, 1)), read.dimensions(1));
+    assert_eq!(img.to_rgba8().into_raw(), read.to_image().unwrap().to_rgba8().into_raw());
+
+    let header = PaaHeader::read(&mut Cursor::new(PAA)).unwrap();
+    assert_eq!(vec![(2, 2, 0x70), (1, 1, 0x87)], header.mipmaps);
+    assert_eq!(Some([0x64, 0x32, 0x32, 0xff]), header.average_color);
+}
+
+#[test]
+fn test_roundtrip_config() {
+    let config = Config::from_string("class A { b = 1; c[] = {2.5, \"x\"}; };".to_string(), None, &[]).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    config.write_rapified(&mut output).unwrap();
+    assert_eq!(CONFIG, &output[..]);
+
+    let read = Config::read_rapified(&mut Cursor::new(CONFIG)).unwrap();
+    assert_eq!("class A {\n    b = 1;\n    c[] = {2.5, \"x\"};\n};\n", read.to_string().unwrap());
+}
+
+#[test]
+fn test_roundtrip_pbo_read() {
+    let pbo = PBO::read(&mut Cursor::new(PBO)).unwrap();
+    assert_eq!(Some(&"test".to_string()), pbo.header_extensions.get("prefix"));
+    assert_eq!(b"hello", &pbo.files.get("a.txt").unwrap().get_ref()[..]);
+    assert_eq!(&PBO[PBO.len() - 20..], &pbo.checksum.unwrap()[..]);
+}
+
+// The checksum is calculated by OpenSSL, which Miri can't run
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_roundtrip_pbo_write() {
+    let pbo = PBO::read(&mut Cursor::new(PBO)).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    pbo.write(&mut output).unwrap();
+    assert_eq!(PBO, &output[..]);
+}
+
+#[test]
+fn test_field_u32() {
+    assert_eq!(0xFFFF_FFFF, field_u32(0xFFFF_FFFFu64, "Size").unwrap());
+    let error = field_u32(0x1_0000_0000u64, "Size of data.bin").unwrap_err().to_string();
+    assert_eq!("Size of data.bin 4294967296 doesn't fit into a 32-bit field.", error);
+}
@@ -0,0 +1,115 @@
+use armake2::config::*;
+use armake2::preprocess::*;
+use armake2::schema::*;
+
+fn config(input: &str) -> Config {
+    Config::from_string(String::from(input), None, &[]).unwrap()
+}
+
+#[test]
+fn test_schema_builtin() {
+    let input = "class CfgWeapons {\n    class arifle_Base {\n        displayName = \"Base\";\n        dispalyName = \"Typo\";\n        class ItemInfo { anything = 1; };\n    };\n};\n";
+    let (preprocessed, info) = preprocess(String::from(input), None, &[]).unwrap();
+    let unknown = Schema::builtin().check(&Config::from_preprocessed(&preprocessed, &info, &ParseOptions::default()).unwrap());
+
+    assert_eq!(1, unknown.len());
+    assert_eq!("CfgWeapons/arifle_Base", unknown[0].class);
+    assert_eq!("dispalyName", unknown[0].property);
+    assert_eq!(Some("displayName".to_string()), unknown[0].suggestion);
+
+    let position = unknown[0].position.unwrap();
+    assert!(preprocessed[position..].starts_with("dispalyName"), "{}", &preprocessed[position..]);
+
+    // Rapified configs don't know where their entries were
+    let mut rapified: Vec<u8> = Vec::new();
+    config(input).write_rapified(&mut rapified).unwrap();
+    let unknown = Schema::builtin().check(&Config::read_rapified(&mut std::io::Cursor::new(rapified)).unwrap());
+    assert_eq!(1, unknown.len());
+    assert_eq!(None, unknown[0].position);
+}
+
+#[test]
+fn test_schema_inheritance() {
+    let schema = Schema::from_json(r#"{
+        "CfgVehicles": {
+            "Car": ["maxSpeed", "armor"]
+        },
+        "CfgPatches": ["units"]
+    }"#).unwrap();
+
+    let unknown = schema.check(&config("
+        class CfgPatches {
+            class my_addon { units[] = {}; weapons[] = {}; };
+        };
+        class cfgvehicles {
+            class Car;
+            class My_Car_Base: Car {
+                armor = 10;
+                customProperty = 1;
+            };
+            class My_Car: My_Car_Base {
+                MAXSPEED = 100;
+                customProperty = 2;
+                armour = 20;
+            };
+            class House {
+                whatever = 1;
+            };
+        };
+    "));
+
+    // House doesn't inherit from a class of the schema and is skipped
+    let unknown: Vec<(String, String, Option<String>)> = unknown.into_iter().map(|u| (u.class, u.property, u.suggestion)).collect();
+    assert_eq!(vec![
+        ("CfgPatches/my_addon".to_string(), "weapons".to_string(), None),
+        ("cfgvehicles/My_Car_Base".to_string(), "customProperty".to_string(), None),
+        ("cfgvehicles/My_Car".to_string(), "armour".to_string(), Some("armor".to_string())),
+    ], unknown);
+
+    assert!(Schema::from_json("{\"CfgVehicles\": 1}").is_err());
+}
+
+#[test]
+fn test_schema_inheritance_case() {
+    let schema = Schema::from_json(r#"{"cfgVehicles": {"CAR": ["maxSpeed"]}}"#).unwrap();
+
+    let unknown = schema.check(&config("
+        class CfgVehicles {
+            class car;
+            class my_car_base: Car { customProperty = 1; };
+            class My_Car: MY_CAR_BASE { MaxSpeed = 1; CUSTOMPROPERTY = 2; maxSped = 3; };
+        };
+    "));
+
+    let unknown: Vec<(String, String, Option<String>)> = unknown.into_iter().map(|u| (u.class, u.property, u.suggestion)).collect();
+    assert_eq!(vec![
+        ("CfgVehicles/my_car_base".to_string(), "customProperty".to_string(), None),
+        ("CfgVehicles/My_Car".to_string(), "maxSped".to_string(), Some("maxSpeed".to_string())),
+    ], unknown);
+}
+
+#[test]
+fn test_schema_inheritance_outer_scope() {
+    let schema = Schema::from_json(r#"{"CfgVehicles": {"Car": ["maxSpeed"]}}"#).unwrap();
+
+    // Parents are looked up in the enclosing scopes like the engine does
+    let unknown = schema.check(&config("
+        class Car { customProperty = 1; };
+        class CfgVehicles {
+            class My_Car: Car { maxSpeed = 1; customProperty = 2; };
+        };
+    "));
+    assert!(unknown.is_empty());
+
+    let schema = Schema::from_json(r#"{"CfgVehicles": {"Car_Base": ["maxSpeed"]}}"#).unwrap();
+    let unknown = schema.check(&config("
+        class Car_Base;
+        class CfgVehicles {
+            class My_Car: Car_Base { maxSpeed = 1; maxSped = 2; };
+        };
+    "));
+    let unknown: Vec<(String, String, Option<String>)> = unknown.into_iter().map(|u| (u.class, u.property, u.suggestion)).collect();
+    assert_eq!(vec![
+        ("CfgVehicles/My_Car".to_string(), "maxSped".to_string(), Some("maxSpeed".to_string())),
+    ], unknown);
+}
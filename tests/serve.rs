@@ -0,0 +1,211 @@
+#![cfg(feature = "serve")]
+
+use std::fs::{File, create_dir_all, read, write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use tempfile::{tempdir};
+
+use armake2::parallel;
+use armake2::pbo::*;
+use armake2::serve::*;
+use armake2::sign::*;
+
+fn write_pbo(path: &Path, content: &[u8]) {
+    let source = tempdir().unwrap();
+    write(source.path().join("script.sqf"), content).unwrap();
+
+    let pbo = PBO::from_directory(source.path().to_path_buf(), false, &Vec::new(), &Vec::new()).unwrap();
+    create_dir_all(path.parent().unwrap()).unwrap();
+    pbo.write(&mut File::create(path).unwrap()).unwrap();
+}
+
+fn write_signature(pbo_path: &Path, key: &BIPrivateKey, name: &str) {
+    let pbo = PBO::read(&mut File::open(pbo_path).unwrap()).unwrap();
+    let path = PathBuf::from(format!("{}.{}.bisign", pbo_path.display(), name));
+    key.sign(&pbo, BISignVersion::V3).write(&mut File::create(path).unwrap()).unwrap();
+}
+
+/// Sends each request as one line and returns the response lines.
+fn exchange<S: Read + Write>(stream: S, requests: &[&str]) -> Vec<Value> {
+    let mut reader = BufReader::new(stream);
+    requests.iter().map(|request| {
+        writeln!(reader.get_mut(), "{}", request).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }).collect()
+}
+
+#[test]
+fn test_serve_verify() {
+    let good = BIPrivateKey::generate(1024, "good".to_string());
+    let keys = tempdir().unwrap();
+    good.to_public_key().write(&mut File::create(keys.path().join("good.bikey")).unwrap()).unwrap();
+
+    let folder = tempdir().unwrap();
+    let root = folder.path().join("root");
+    let path = |name: &str| root.join(name);
+
+    write_pbo(&path("addons/signed.pbo"), b"1");
+    write_signature(&path("addons/signed.pbo"), &good, "good");
+    write_pbo(&path("addons/tampered.pbo"), b"2");
+    write_signature(&path("addons/tampered.pbo"), &good, "good");
+    write_pbo(&path("addons/tampered.pbo"), b"changed");
+    write_pbo(&path("unsigned.pbo"), b"3");
+    write_pbo(&folder.path().join("outside.pbo"), b"4");
+
+    let mut corrupted = read(path("unsigned.pbo")).unwrap();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xff;
+    write(path("corrupted.pbo"), corrupted).unwrap();
+
+    let server = VerifyServer::bind("127.0.0.1:0", keys.path(), &root).unwrap();
+    let address = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    let responses = exchange(TcpStream::connect(&address).unwrap(), &[
+        r#"{"id": 1, "op": "verify-signature", "path": "addons/signed.pbo"}"#,
+        r#"{"id": "two", "op": "verify-signature", "path": "addons\\tampered.pbo"}"#,
+        r#"{"op": "verify-signature", "path": "./unsigned.pbo"}"#,
+        r#"{"id": 4, "op": "verify-checksum", "path": "unsigned.pbo"}"#,
+        r#"{"id": 5, "op": "verify-checksum", "path": "corrupted.pbo"}"#,
+        r#"{"id": 6, "op": "inspect", "path": "unsigned.pbo"}"#,
+    ]);
+
+    assert_eq!(json!({"id": 1, "ok": true, "result": {"status": "ok", "detail": "good"}}), responses[0]);
+    assert_eq!("two", responses[1]["id"]);
+    assert_eq!("failed", responses[1]["result"]["status"]);
+    assert_eq!(None, responses[2].get("id"));
+    assert_eq!("no-signature", responses[2]["result"]["status"]);
+
+    assert_eq!(true, responses[3]["result"]["valid"]);
+    assert_eq!(responses[3]["result"]["checksum"], responses[3]["result"]["computed"]);
+    assert_eq!(40, responses[3]["result"]["checksum"].as_str().unwrap().len());
+    assert_eq!(false, responses[4]["result"]["valid"]);
+
+    assert!(responses[5]["result"]["header_extensions"]["prefix"].is_string());
+    assert_eq!(json!([{"name": "script.sqf", "size": 1}]), responses[5]["result"]["entries"]);
+    assert_eq!(0, responses[5]["result"]["trailing_data"]);
+
+    // Paths can't leave the root, every failure keeps the connection open
+    let responses = exchange(TcpStream::connect(&address).unwrap(), &[
+        r#"{"id": 1, "op": "inspect", "path": "../outside.pbo"}"#,
+        r#"{"id": 2, "op": "inspect", "path": "addons/../../outside.pbo"}"#,
+        &format!(r#"{{"id": 3, "op": "inspect", "path": {}}}"#, json!(folder.path().join("outside.pbo"))),
+        r#"{"id": 4, "op": "inspect", "path": "missing.pbo"}"#,
+        r#"{"id": 5, "op": "inspect", "path": "addons"}"#,
+        r#"{"id": 6, "op": "delete", "path": "unsigned.pbo"}"#,
+        r#"{"id": 7, "op": "inspect"}"#,
+        "not json",
+        r#"{"id": 9, "op": "verify-signature", "path": "addons/signed.pbo"}"#,
+    ]);
+
+    for (i, response) in responses[..8].iter().enumerate() {
+        assert_eq!(false, response["ok"], "{}", i);
+        assert!(response["error"].is_string(), "{}", i);
+    }
+    assert_eq!(json!(1), responses[0]["id"]);
+    assert!(responses[5]["error"].as_str().unwrap().contains("Unknown operation \"delete\""));
+    assert_eq!(true, responses[8]["ok"]);
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(folder.path().join("outside.pbo"), path("link.pbo")).unwrap();
+        let responses = exchange(TcpStream::connect(&address).unwrap(), &[
+            r#"{"op": "inspect", "path": "link.pbo"}"#,
+        ]);
+        assert!(responses[0]["error"].as_str().unwrap().contains("outside of the root folder"));
+    }
+
+    // Too long requests are answered and the connection is closed
+    let mut stream = TcpStream::connect(&address).unwrap();
+    let request = format!(r#"{{"op": "inspect", "path": "{}"}}"#, "a".repeat(MAX_REQUEST_SIZE));
+    let _ = writeln!(stream, "{}", request);
+    let mut output = String::new();
+    let _ = BufReader::new(stream).read_to_string(&mut output);
+    let response: Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+    assert!(response["error"].as_str().unwrap().contains("exceeds"));
+    assert_eq!(1, output.lines().count());
+}
+
+#[test]
+fn test_serve_idle_connections() {
+    let keys = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    write_pbo(&root.path().join("unsigned.pbo"), b"1");
+
+    let timeout = Duration::from_millis(300);
+    let mut server = VerifyServer::bind("127.0.0.1:0", keys.path(), root.path()).unwrap();
+    assert!(server.set_idle_timeout(Duration::ZERO).is_err());
+    server.set_idle_timeout(timeout).unwrap();
+    let address = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    // Clients that connect and send nothing take every worker until they time out
+    let idle: Vec<TcpStream> = (0..parallel::thread_count().max(1)).map(|_| TcpStream::connect(&address).unwrap()).collect();
+    let start = Instant::now();
+    let responses = exchange(TcpStream::connect(&address).unwrap(), &[
+        r#"{"id": 1, "op": "verify-signature", "path": "unsigned.pbo"}"#,
+    ]);
+    assert_eq!("no-signature", responses[0]["result"]["status"]);
+    assert!(start.elapsed() >= timeout, "answered after {:?}", start.elapsed());
+
+    // The idle connections were closed by the server
+    for mut stream in idle {
+        let mut output = Vec::new();
+        assert_eq!(0, stream.read_to_end(&mut output).unwrap());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serve_verify_unix_socket() {
+    use std::os::unix::net::{UnixStream};
+
+    let keys = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    write_pbo(&root.path().join("unsigned.pbo"), b"1");
+
+    let socket = keys.path().join("verify.sock");
+    let address = format!("unix:{}", socket.display());
+    let server = VerifyServer::bind(&address, keys.path(), root.path()).unwrap();
+    assert_eq!(address, server.local_addr().unwrap());
+    assert_eq!(0, server.key_count());
+    thread::spawn(move || server.serve());
+
+    let responses = exchange(UnixStream::connect(&socket).unwrap(), &[
+        r#"{"id": 1, "op": "verify-signature", "path": "unsigned.pbo"}"#,
+    ]);
+    assert_eq!("no-signature", responses[0]["result"]["status"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serve_replaces_stale_unix_socket() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let keys = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    let socket = keys.path().join("verify.sock");
+    let address = format!("unix:{}", socket.display());
+
+    // A listener dropped without removing its file, like a crashed server leaves it
+    drop(UnixListener::bind(&socket).unwrap());
+    let server = VerifyServer::bind(&address, keys.path(), root.path()).unwrap();
+    thread::spawn(move || server.serve());
+    assert!(UnixStream::connect(&socket).is_ok());
+
+    // A server still answering on the socket keeps it
+    assert!(VerifyServer::bind(&address, keys.path(), root.path()).is_err());
+    assert!(UnixStream::connect(&socket).is_ok());
+
+    let file = keys.path().join("not-a-socket");
+    write(&file, b"data").unwrap();
+    assert!(VerifyServer::bind(&format!("unix:{}", file.display()), keys.path(), root.path()).is_err());
+    assert_eq!(b"data", &read(&file).unwrap()[..]);
+}
@@ -0,0 +1,248 @@
+use std::fs::{create_dir_all, write, File};
+use std::path::{Path, PathBuf};
+
+use tempfile::{tempdir};
+
+use armake2::pbo::*;
+use armake2::sign::*;
+use armake2::verifycache::*;
+use armake2::warnings::{get_warning_count};
+
+fn write_pbo(path: &Path, content: &[u8]) -> PBO {
+    let source = tempdir().unwrap();
+    write(source.path().join("script.sqf"), content).unwrap();
+
+    let pbo = PBO::from_directory(source.path().to_path_buf(), false, &Vec::new(), &Vec::new()).unwrap();
+    create_dir_all(path.parent().unwrap()).unwrap();
+    pbo.write(&mut File::create(path).unwrap()).unwrap();
+
+    PBO::read(&mut File::open(path).unwrap()).unwrap()
+}
+
+fn write_signature(pbo_path: &Path, key: &BIPrivateKey, name: &str, version: BISignVersion) {
+    let pbo = PBO::read(&mut File::open(pbo_path).unwrap()).unwrap();
+    let path = PathBuf::from(format!("{}.{}.bisign", pbo_path.display(), name));
+    key.sign(&pbo, version).write(&mut File::create(path).unwrap()).unwrap();
+}
+
+#[test]
+fn test_check_signatures() {
+    let good = BIPrivateKey::generate(1024, "good".to_string());
+    let unknown = BIPrivateKey::generate(1024, "unknown".to_string());
+
+    let keys = tempdir().unwrap();
+    good.to_public_key().write(&mut File::create(keys.path().join("good.bikey")).unwrap()).unwrap();
+
+    let addons = tempdir().unwrap();
+    let path = |name: &str| addons.path().join(name);
+
+    write_pbo(&path("signed.pbo"), b"1");
+    write_signature(&path("signed.pbo"), &good, "good", BISignVersion::V3);
+
+    write_pbo(&path("foreign.pbo"), b"2");
+    write_signature(&path("foreign.pbo"), &unknown, "unknown", BISignVersion::V3);
+
+    write_pbo(&path("unsigned.pbo"), b"3");
+
+    write_pbo(&path("tampered.pbo"), b"4");
+    write_signature(&path("tampered.pbo"), &good, "good", BISignVersion::V3);
+    write_pbo(&path("tampered.pbo"), b"changed");
+
+    // Found recursively, unknown authorities are ignored and v2 is accepted
+    write_pbo(&path("sub/nested.pbo"), b"5");
+    write_signature(&path("sub/nested.pbo"), &unknown, "unknown", BISignVersion::V3);
+    write_signature(&path("sub/nested.pbo"), &good, "good", BISignVersion::V2);
+
+    write_pbo(&path("garbage.pbo"), b"6");
+    write(path("garbage.pbo.good.bisign"), b"garbage").unwrap();
+
+    // Authority names are compared case-insensitively
+    write_pbo(&path("uppercase.pbo"), b"7");
+    write_signature(&path("uppercase.pbo"), &good, "GOOD", BISignVersion::V3);
+    let signature_path = path("uppercase.pbo.GOOD.bisign");
+    let mut signature = std::fs::read(&signature_path).unwrap();
+    signature[..4].copy_from_slice(b"GOOD");
+    write(&signature_path, signature).unwrap();
+
+    let results = check_signatures(addons.path(), keys.path()).unwrap();
+    let statuses: Vec<(String, SignatureStatus)> = results.iter()
+        .map(|r| (r.pbo.strip_prefix(addons.path()).unwrap().to_string_lossy().replace('\\', "/"), r.status))
+        .collect();
+
+    assert_eq!(vec![
+        ("foreign.pbo".to_string(), SignatureStatus::NoSignature),
+        ("garbage.pbo".to_string(), SignatureStatus::Failed),
+        ("signed.pbo".to_string(), SignatureStatus::Ok),
+        ("sub/nested.pbo".to_string(), SignatureStatus::Ok),
+        ("tampered.pbo".to_string(), SignatureStatus::Failed),
+        ("unsigned.pbo".to_string(), SignatureStatus::NoSignature),
+        ("uppercase.pbo".to_string(), SignatureStatus::Ok),
+    ], statuses);
+
+    assert!(cmd_check_signatures(addons.path().to_path_buf(), keys.path().to_path_buf(), None, None).is_err());
+}
+
+#[test]
+fn test_verify_trailing_data() {
+    let key = BIPrivateKey::generate(1024, "good".to_string());
+    let dir = tempdir().unwrap();
+    let pbo_path = dir.path().join("addon.pbo");
+    let key_path = dir.path().join("good.bikey");
+    key.to_public_key().write(&mut File::create(&key_path).unwrap()).unwrap();
+
+    write_pbo(&pbo_path, b"hint \"hi\";");
+    write_signature(&pbo_path, &key, "good", BISignVersion::V3);
+
+    let mut data = std::fs::read(&pbo_path).unwrap();
+    data.extend(std::iter::repeat_n(0xAB, 1024));
+    write(&pbo_path, data).unwrap();
+
+    let raised = get_warning_count("trailing-data");
+    cmd_verify(key_path.clone(), pbo_path.clone(), None, None).unwrap();
+    assert_eq!(raised + 1, get_warning_count("trailing-data"));
+
+    // Also when the hashes come from the cache
+    let cache = VerifyCache::new(dir.path().join("cache.json"), true);
+    for _ in 0..2 {
+        cmd_verify(key_path.clone(), pbo_path.clone(), None, Some(&cache)).unwrap();
+    }
+    assert_eq!(1, cache.hits());
+    assert_eq!(raised + 3, get_warning_count("trailing-data"));
+}
+
+#[test]
+fn test_check_signatures_cache() {
+    let key = BIPrivateKey::generate(1024, "good".to_string());
+    let keys = tempdir().unwrap();
+    key.to_public_key().write(&mut File::create(keys.path().join("good.bikey")).unwrap()).unwrap();
+
+    let addons = tempdir().unwrap();
+    let pbo_path = addons.path().join("addon.pbo");
+    write_pbo(&pbo_path, b"hint \"original\";");
+    write_signature(&pbo_path, &key, "good", BISignVersion::V3);
+
+    let cache_dir = tempdir().unwrap();
+    let cache_path = cache_dir.path().join("verify.cache");
+    let check = |trust: bool| {
+        let cache = VerifyCache::new(cache_path.clone(), trust);
+        let status = check_signatures_with_cache(addons.path(), keys.path(), None, Some(&cache)).unwrap()[0].status;
+        cache.save().unwrap();
+        (status, cache.hits(), cache.misses())
+    };
+
+    assert_eq!((SignatureStatus::Ok, 0, 1), check(true));
+    assert_eq!((SignatureStatus::Ok, 1, 0), check(true));
+    assert_eq!(vec!["verify.cache"], std::fs::read_dir(cache_dir.path()).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect::<Vec<_>>());
+
+    // Tamper with the PBO without changing its size or modification time
+    let modified = std::fs::metadata(&pbo_path).unwrap().modified().unwrap();
+    let data = std::fs::read(&pbo_path).unwrap();
+    let position = data.windows(8).position(|w| w == b"original").unwrap();
+    let mut tampered = data.clone();
+    tampered[position..position + 8].copy_from_slice(b"tampered");
+    write(&pbo_path, tampered).unwrap();
+    File::options().write(true).open(&pbo_path).unwrap().set_modified(modified).unwrap();
+
+    // The trusted cache doesn't notice, which is the tradeoff of using it
+    assert_eq!((SignatureStatus::Ok, 1, 0), check(true));
+    assert_eq!((SignatureStatus::Failed, 0, 1), check(false));
+    // Rehashing updated the cache
+    assert_eq!((SignatureStatus::Failed, 1, 0), check(true));
+
+    // Files of other format versions are ignored
+    write(&cache_path, r#"{"version":0,"entries":[]}"#).unwrap();
+    assert_eq!((SignatureStatus::Failed, 0, 1), check(true));
+}
+
+#[test]
+fn test_sign_with_keys() {
+    let own = BIPrivateKey::generate(1024, "own".to_string());
+    let community = BIPrivateKey::generate(1024, "community".to_string());
+    let keys = tempdir().unwrap();
+    for key in [&own, &community] {
+        key.write(&mut File::create(keys.path().join(format!("{}.biprivatekey", key.name()))).unwrap()).unwrap();
+        key.to_public_key().write(&mut File::create(keys.path().join(format!("{}.bikey", key.name()))).unwrap()).unwrap();
+    }
+
+    let addons = tempdir().unwrap();
+    let pbo_path = addons.path().join("addon.pbo");
+    write_pbo(&pbo_path, b"hint \"signed twice\";");
+
+    let key_paths = vec![
+        (keys.path().join("own.biprivatekey"), Some("mygroup".to_string())),
+        (keys.path().join("community.biprivatekey"), None),
+    ];
+    let paths = cmd_sign_with_keys(&key_paths, &pbo_path, BISignVersion::V3).unwrap();
+    assert_eq!(vec![addons.path().join("addon.pbo.mygroup.bisign"), addons.path().join("addon.pbo.community.bisign")], paths);
+
+    for (key, path) in [("own", &paths[0]), ("community", &paths[1])] {
+        cmd_verify(keys.path().join(format!("{}.bikey", key)), pbo_path.clone(), Some(path.clone()), None).unwrap();
+    }
+    assert!(check_signatures(addons.path(), keys.path()).unwrap().iter().all(|c| c.status == SignatureStatus::Ok));
+
+    // Both keys would write the same signature file
+    let conflicting = vec![
+        (keys.path().join("own.biprivatekey"), Some("shared".to_string())),
+        (keys.path().join("community.biprivatekey"), Some("Shared".to_string())),
+    ];
+    let error = cmd_sign_with_keys(&conflicting, &pbo_path, BISignVersion::V3).unwrap_err();
+    assert!(error.to_string().contains("More than one key"), "{}", error);
+    assert!(!addons.path().join("addon.pbo.shared.bisign").exists());
+}
+
+#[test]
+fn test_resign() {
+    let key = BIPrivateKey::generate(1024, "mygroup".to_string());
+    let other = BIPrivateKey::generate(1024, "other".to_string());
+    let keys = tempdir().unwrap();
+    let key_path = keys.path().join("mygroup.biprivatekey");
+    key.write(&mut File::create(&key_path).unwrap()).unwrap();
+    let public_path = keys.path().join("mygroup.bikey");
+    key.to_public_key().write(&mut File::create(&public_path).unwrap()).unwrap();
+
+    let addons = tempdir().unwrap();
+    let first = addons.path().join("first.pbo");
+    let second = addons.path().join("sub").join("second.pbo");
+    write_pbo(&first, b"1");
+    write_pbo(&second, b"2");
+    write_signature(&first, &key, "mygroup", BISignVersion::V2);
+    write_signature(&first, &other, "other", BISignVersion::V2);
+    write_signature(&second, &key, "renamed", BISignVersion::V2);
+    let signature = addons.path().join("first.pbo.mygroup.bisign");
+    assert_eq!(BISignVersion::V2, SignatureInfo::read(&signature).unwrap().version);
+
+    // A signature alone can't be converted
+    for error in [
+        cmd_sign(signature.clone(), first.clone(), None, BISignVersion::V3).unwrap_err(),
+        cmd_sign(key_path.clone(), signature.clone(), None, BISignVersion::V3).unwrap_err(),
+        cmd_verify(signature.clone(), first.clone(), None, None).unwrap_err(),
+        cmd_resign(&signature, std::slice::from_ref(&first), BISignVersion::V3).unwrap_err(),
+    ] {
+        assert!(error.to_string().contains("conversion requires the private key"), "{}", error);
+        assert!(error.to_string().contains("armake2 resign"), "{}", error);
+    }
+    let error = cmd_sign(public_path.clone(), first.clone(), None, BISignVersion::V3).unwrap_err();
+    assert!(error.to_string().contains("is a public key"), "{}", error);
+
+    let paths = cmd_resign(&key_path, std::slice::from_ref(&signature), BISignVersion::V3).unwrap();
+    assert_eq!(vec![signature.clone()], paths);
+    let info = SignatureInfo::read(&signature).unwrap();
+    assert_eq!(SignatureInfo { authority: "mygroup".to_string(), version: BISignVersion::V3, length: 1024 }, info);
+    cmd_verify(public_path.clone(), first.clone(), Some(signature.clone()), None).unwrap();
+
+    // Signatures of other keys are left alone, folders are converted in batch keeping the names
+    let other_signature = addons.path().join("first.pbo.other.bisign");
+    assert_eq!(BISignVersion::V2, SignatureInfo::read(&other_signature).unwrap().version);
+    let paths = cmd_resign(&key_path, &[addons.path().to_path_buf()], BISignVersion::V3).unwrap();
+    let renamed = addons.path().join("sub").join("second.pbo.renamed.bisign");
+    assert_eq!(vec![signature.clone(), renamed.clone()], paths);
+    cmd_verify(public_path.clone(), second.clone(), Some(renamed), None).unwrap();
+    assert_eq!(BISignVersion::V2, SignatureInfo::read(&other_signature).unwrap().version);
+
+    let error = cmd_resign(&key_path, &[other_signature], BISignVersion::V3).unwrap_err();
+    assert!(error.to_string().contains("wasn't made with the key"), "{}", error);
+    let unsigned = addons.path().join("unsigned.pbo");
+    write_pbo(&unsigned, b"3");
+    let error = cmd_resign(&key_path, &[unsigned], BISignVersion::V3).unwrap_err();
+    assert!(error.to_string().contains("has no signature made with the key"), "{}", error);
+}
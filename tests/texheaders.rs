@@ -0,0 +1,188 @@
+use std::fs::{File, create_dir_all, read, write};
+use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+use std::process::{Command};
+
+use linked_hash_map::{LinkedHashMap};
+use tempfile::{tempdir};
+
+use armake2::p3d::*;
+use armake2::paa::*;
+use armake2::paths::*;
+use armake2::pbo::*;
+use armake2::texheaders::*;
+use armake2::vfs::*;
+
+fn paa_bytes(size: u32, paa_type: PaaType) -> Vec<u8> {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(size, size, |x, y| image::Rgba([x as u8, y as u8, 0, 128])));
+    let mut buffer = Cursor::new(Vec::new());
+    Paa::from_image(&img, paa_type, false).unwrap().write(&mut buffer).unwrap();
+    buffer.into_inner()
+}
+
+/// Returns an MLOD model whose only face uses the texture.
+fn model(texture: &str) -> Vec<u8> {
+    let face = Face {
+        vertices: (0..3).map(|_| Vertex::new()).collect(),
+        flags: 0,
+        texture: texture.to_string(),
+        material: String::new(),
+    };
+    let p3d = P3D {
+        version: 257,
+        lods: vec![LOD {
+            version_major: 28,
+            version_minor: 256,
+            resolution: 1.0,
+            points: (0..3).map(|_| Point::new()).collect(),
+            face_normals: Vec::new(),
+            faces: vec![face],
+            taggs: LinkedHashMap::new(),
+        }],
+    };
+    let mut buffer: Vec<u8> = Vec::new();
+    p3d.write(&mut buffer).unwrap();
+    buffer
+}
+
+fn write_files(directory: &Path, files: &[(&str, &[u8])]) {
+    for (file, content) in files {
+        let path = directory.join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, content).unwrap();
+    }
+}
+
+fn write_pbo(directory: &Path, name: &str, prefix: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let source = tempdir().unwrap();
+    write(source.path().join("$PBOPREFIX$"), prefix).unwrap();
+    write_files(source.path(), files);
+
+    create_dir_all(directory).unwrap();
+    let path = directory.join(format!("{}.pbo", name));
+    PBO::from_directory(source.path().to_path_buf(), false, &[], &[]).unwrap()
+        .write(&mut File::create(&path).unwrap()).unwrap();
+    path
+}
+
+/// Returns a file from tests/data.
+fn fixture(name: &str) -> Vec<u8> {
+    read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(name)).unwrap()
+}
+
+/// The headers of tests/data/dxt5.paa as `myaddon\data\glass_ca.paa` and of an opaque DXT1
+/// `myaddon\data\gun_co.paa`
+fn texheaders_bytes() -> Vec<u8> {
+    fixture("texHeaders.bin")
+}
+
+#[test]
+fn test_texheaders_read_write() {
+    let input = texheaders_bytes();
+    let headers = TexHeaders::read(&mut Cursor::new(&input)).unwrap();
+    assert_eq!(2, headers.textures.len());
+
+    let texture = headers.get("MyAddon/Data/Gun_co.paa").unwrap();
+    assert_eq!([128, 64, 0, 255], texture.average_color);
+    assert_eq!(6, texture.pixel_format);
+    assert!(!texture.is_alpha && !texture.is_alpha_non_opaque);
+    assert_eq!(vec![(8, 8, 112), (4, 4, 151)], texture.mipmaps.iter().map(|m| (m.width, m.height, m.offset)).collect::<Vec<_>>());
+    assert_eq!(172, texture.file_size);
+
+    // The header made from the PAA is the one in the file
+    let paa = PaaHeader::read(&mut Cursor::new(fixture("dxt5.paa"))).unwrap();
+    let glass = TextureHeader::from_paa(&InternalPath::new("myaddon\\data\\glass_ca.paa").unwrap(), &paa);
+    assert_eq!(headers.get("myaddon\\data\\glass_ca.paa").unwrap(), &glass);
+
+    let mut output: Vec<u8> = Vec::new();
+    headers.write(&mut output).unwrap();
+    assert_eq!(input, output);
+
+    assert!(TexHeaders::read(&mut Cursor::new(b"0DHT\x02\0\0\0\0\0\0\0")).is_err());
+    assert!(TexHeaders::read(&mut Cursor::new(&input[..input.len() - 2])).is_err());
+}
+
+#[test]
+fn test_texheaders_from_paa() {
+    let data = paa_bytes(16, PaaType::DXT5);
+    let paa = Paa::from_reader(&mut Cursor::new(&data)).unwrap();
+    let header = PaaHeader::read(&mut Cursor::new(&data)).unwrap();
+    assert_eq!(data.len() as u64, header.file_size);
+    assert_eq!(paa.mipmap_count(), header.mipmaps.len());
+
+    let texture = TextureHeader::from_paa(&InternalPath::new("myaddon\\data\\glass_ca.paa").unwrap(), &header);
+    assert_eq!(10, texture.pixel_format);
+    assert_eq!((16, 16), (texture.mipmaps[0].width, texture.mipmaps[0].height));
+    assert!(texture.mipmaps.iter().all(|m| m.format == 10 && m.unknown2 == 3));
+    assert_eq!(header.average_color.unwrap(), texture.average_color);
+    assert!(texture.is_alpha_non_opaque);
+    assert_eq!(0, texture.suffix_type);
+    assert_eq!(3, TextureHeader::from_paa(&InternalPath::new("myaddon\\data\\gun_nohq.paa").unwrap(), &header).suffix_type);
+    assert_eq!(10, TextureHeader::from_paa(&InternalPath::new("MyAddon/Data/Gun_DTSMDI.paa").unwrap(), &header).suffix_type);
+}
+
+#[test]
+fn test_texheaders_from_folder() {
+    let dir = tempdir().unwrap();
+    let addon = dir.path().join("addon");
+    write_files(&addon, &[
+        ("$PBOPREFIX$", b"myaddon"),
+        ("data/gun_co.paa", &paa_bytes(16, PaaType::DXT1)),
+        ("data/gun.p3d", &model("a3\\data_f\\body_co.paa")),
+        ("config.cpp", b"class CfgPatches { class myaddon { units[] = {}; }; };\n"),
+    ]);
+
+    // The external texture is only in the previous file
+    let mut previous = TexHeaders::read(&mut Cursor::new(texheaders_bytes())).unwrap();
+    previous.textures[0].path = "a3\\data_f\\body_co.paa".to_string();
+    let mut buffer: Vec<u8> = Vec::new();
+    previous.write(&mut buffer).unwrap();
+    write(addon.join("texHeaders.bin"), &buffer).unwrap();
+
+    assert_eq!(2, cmd_texheaders(&addon, None).unwrap());
+    let headers = TexHeaders::read(&mut Cursor::new(read(addon.join("texHeaders.bin")).unwrap())).unwrap();
+    assert_eq!(6, headers.get("myaddon\\data\\gun_co.paa").unwrap().pixel_format);
+    assert_eq!(previous.textures[0], *headers.get("a3\\data_f\\body_co.paa").unwrap());
+
+    // Mod folders take precedence over the copy
+    let game = tempdir().unwrap();
+    write_pbo(&game.path().join("Addons"), "data_f", "a3\\data_f", &[("body_co.paa", &paa_bytes(8, PaaType::ARGB8888))]);
+    let filesystem = VirtualFilesystem::new(vec![game.path().to_path_buf()]);
+    assert_eq!(2, cmd_texheaders(&addon, Some(&filesystem)).unwrap());
+    let headers = TexHeaders::read(&mut Cursor::new(read(addon.join("texHeaders.bin")).unwrap())).unwrap();
+    let body = headers.get("a3\\data_f\\body_co.paa").unwrap();
+    assert_eq!((5, 8), (body.pixel_format, body.mipmaps[0].width));
+
+    // Packing writes it from the packed textures
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+        .args(["build", "addon", "addon.pbo"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[texheaders-copied]"), "{}", String::from_utf8_lossy(&output.stderr));
+    let pbo = PBO::read(&mut File::open(dir.path().join("addon.pbo")).unwrap()).unwrap();
+    let packed = read_from_pbo(&pbo).unwrap();
+    assert_eq!(2, packed.textures.len());
+    assert_eq!(1, pbo.files.keys().filter(|name| name.eq_ignore_ascii_case("texHeaders.bin")).count());
+}
+
+#[test]
+fn test_texheaders_missing() {
+    let dir = tempdir().unwrap();
+    let addon = dir.path().join("addon");
+    write_files(&addon, &[
+        ("$PBOPREFIX$", b"myaddon"),
+        ("data/gun.p3d", &model("a3\\data_f\\body_co.paa")),
+    ]);
+    let game = tempdir().unwrap();
+    create_dir_all(game.path().join("Addons")).unwrap();
+
+    // Without mod folders there is nothing to look the texture up in
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2"))
+        .args(["texheaders", addon.to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("[texheaders-missing]"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_armake2"))
+        .args(["texheaders", "--mod-dir", game.path().to_str().unwrap(), addon.to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[texheaders-missing]"), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,169 @@
+use std::fs::{create_dir_all, write};
+use std::path::{Path};
+use std::process::{Command};
+
+use tempfile::{tempdir};
+
+use armake2::paa::*;
+use armake2::texpolicy::*;
+
+fn paa(size: u32, paa_type: PaaType) -> Paa {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(size, size, |x, y| image::Rgba([x as u8, y as u8, 0, 255])));
+    Paa::from_image(&img, paa_type, false).unwrap()
+}
+
+fn write_paa(path: &Path, paa: &Paa) {
+    create_dir_all(path.parent().unwrap()).unwrap();
+    paa.write_to_file(path).unwrap();
+}
+
+/// Returns the actual and allowed values of the violations.
+fn violations(policy: &str, path: &str, paa: &Paa) -> Vec<(String, String, String)> {
+    TexturePolicy::parse(policy).unwrap().check(path, paa).into_iter().map(|v| (v.rule, v.actual, v.allowed)).collect()
+}
+
+#[test]
+fn test_texture_policy_parse() {
+    let policy = TexturePolicy::parse("[[rule]]\nfiles = \"*\"\nmax_size = 256\n\n[[rule]]\nname = \"icons\"\nfiles = \"ui/*\"\ntypes = [\"dxt5\"]\n").unwrap();
+    assert_eq!(vec!["*", "icons"], policy.rule.iter().map(|r| r.name()).collect::<Vec<&str>>());
+    assert!(TexturePolicy::parse("").unwrap().rule.is_empty());
+
+    // Typos don't silently disable a rule
+    let error = TexturePolicy::parse("[[rule]]\nfiles = \"*\"\nmax_sise = 256\n").unwrap_err().to_string();
+    assert!(error.contains("unknown field `max_sise`"), "{}", error);
+    let error = TexturePolicy::parse("[[rules]]\nfiles = \"*\"\n").unwrap_err().to_string();
+    assert!(error.contains("unknown field `rules`"), "{}", error);
+
+    let error = TexturePolicy::parse("[[rule]]\nfiles = \"*\"\ntypes = [\"DXT2\"]\n").unwrap_err().to_string();
+    assert!(error.contains("Unknown PAA type \"DXT2\" in rule \"*\""), "{}", error);
+    assert!(TexturePolicy::parse("[[rule]]\nmax_size = 256\n").is_err());
+    assert!(TexturePolicy::parse("[[rule]]\nfiles = \"*\"\nmax_size = 0\n").is_err());
+    assert!(TexturePolicy::parse("[[rule]]\nfiles = \"*\"\nrequire_mips = \"yes\"\n").is_err());
+}
+
+#[test]
+fn test_texture_policy_matching() {
+    let rule = TexturePolicy::parse("[[rule]]\nfiles = \"UI/Icons/*.paa\"\n").unwrap().rule.remove(0);
+    assert!(rule.matches("ui\\icons\\gun_ca.paa"));
+    assert!(rule.matches("ui/icons/sub/gun_ca.PAA"));
+    assert!(!rule.matches("data/ui/icons/gun_ca.paa"));
+    assert!(!rule.matches("ui/gun_ca.paa"));
+
+    // Every matching rule applies, others don't
+    let policy = "[[rule]]\nfiles = \"*_co.paa\"\nmax_size = 16\n\n[[rule]]\nfiles = \"*\"\nmax_size = 8\n\n[[rule]]\nfiles = \"ui/*\"\nmax_size = 1\n";
+    assert_eq!(2, violations(policy, "data/gun_co.paa", &paa(32, PaaType::DXT1)).len());
+    assert_eq!(1, violations(policy, "data/gun_nohq.paa", &paa(32, PaaType::DXT1)).len());
+}
+
+#[test]
+fn test_texture_policy_max_size() {
+    let policy = "[[rule]]\nname = \"small\"\nfiles = \"*\"\nmax_size = 16\n";
+    assert!(violations(policy, "a.paa", &paa(16, PaaType::DXT1)).is_empty());
+    assert_eq!(vec![("small".to_string(), "32x32".to_string(), "at most 16x16".to_string())], violations(policy, "a.paa", &paa(32, PaaType::DXT1)));
+
+    // Either side counts
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::new(8, 64));
+    let tall = Paa::from_image(&img, PaaType::DXT1, false).unwrap();
+    assert_eq!("8x64", violations(policy, "a.paa", &tall)[0].1);
+}
+
+#[test]
+fn test_texture_policy_types() {
+    let policy = "[[rule]]\nfiles = \"*\"\ntypes = [\"DXT5\", \"argb8888\"]\n";
+    assert!(violations(policy, "a.paa", &paa(8, PaaType::DXT5)).is_empty());
+    assert!(violations(policy, "a.paa", &paa(8, PaaType::ARGB8888)).is_empty());
+    assert_eq!(vec![("*".to_string(), "DXT1".to_string(), "DXT5 or argb8888".to_string())], violations(policy, "a.paa", &paa(8, PaaType::DXT1)));
+}
+
+#[test]
+fn test_texture_policy_mips() {
+    let policy = "[[rule]]\nfiles = \"*\"\nrequire_mips = true\n";
+    assert!(violations(policy, "a.paa", &paa(64, PaaType::DXT1)).is_empty());
+
+    let level = |size: u16| RawMipmap { width: size, height: size, data: vec![0; size as usize * size as usize * 4] };
+    let raw = |sizes: &[u16]| Paa::from_raw_mipmaps(PaaType::ARGB8888, sizes.iter().map(|s| level(*s)).collect(), &RawMipmapOptions::default()).unwrap();
+
+    // Chains may end at the size of a DXT block, but have to halve every level
+    assert!(violations(policy, "a.paa", &raw(&[64, 32, 16, 8, 4])).is_empty());
+    assert_eq!(vec![("*".to_string(), "1 mipmaps down to 64x64".to_string(), "mipmaps down to 4x4".to_string())],
+        violations(policy, "a.paa", &raw(&[64])));
+    assert_eq!(1, violations(policy, "a.paa", &raw(&[64, 16, 8, 4])).len());
+    assert_eq!(1, violations(policy, "a.paa", &raw(&[64, 32, 16])).len());
+    assert!(violations(policy, "a.paa", &raw(&[2])).is_empty());
+}
+
+#[test]
+fn test_texture_policy_alpha_flag() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("icon_ca.paa");
+    write_paa(&path, &paa(8, PaaType::DXT5));
+
+    let policy = "[[rule]]\nfiles = \"*_ca.paa\"\nrequire_alpha_flag = true\n";
+    let unflagged = Paa::from_file(&path).unwrap();
+    assert_eq!(None, unflagged.flag());
+    assert_eq!(vec![("*_ca.paa".to_string(), "no alpha flag".to_string(), "alpha flag set".to_string())],
+        violations(policy, "icon_ca.paa", &unflagged));
+
+    cmd_paa_set(&path, &path, &TaggEdits { flag: Some(1), ..Default::default() }, true).unwrap();
+    let flagged = Paa::from_file(&path).unwrap();
+    assert_eq!(Some(1), flagged.flag());
+    assert!(violations(policy, "icon_ca.paa", &flagged).is_empty());
+}
+
+#[test]
+fn test_texture_policy_check() {
+    let dir = tempdir().unwrap();
+    let addon = dir.path().join("addon");
+    write_paa(&addon.join("ui").join("icon_ca.paa"), &paa(64, PaaType::DXT5));
+    write_paa(&addon.join("data").join("gun_co.paa"), &paa(64, PaaType::DXT1));
+    write(addon.join("config.cpp"), "class CfgPatches { class addon { units[] = {}; }; };\n").unwrap();
+
+    let policy_file = dir.path().join("texture-policy.toml");
+    write(&policy_file, "[[rule]]\nname = \"icons\"\nfiles = \"ui/*\"\nmax_size = 32\n\n[[rule]]\nfiles = \"*\"\ntypes = [\"DXT5\"]\n").unwrap();
+    let policy = TexturePolicy::load(&policy_file).unwrap();
+
+    assert_eq!(2, cmd_texture_policy_check(&addon, &policy, false).unwrap());
+    let error = cmd_texture_policy_check(&addon, &policy, true).unwrap_err().to_string();
+    assert!(error.contains("2 textures violate the texture policy"), "{}", error);
+    assert!(error.contains("ui\\icon_ca.paa: rule \"icons\": 64x64, allowed is at most 32x32"), "{}", error);
+    assert!(error.contains("data\\gun_co.paa: rule \"*\": DXT1, allowed is DXT5"), "{}", error);
+
+    let armake2 = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path()).args(args).output().unwrap();
+
+    let output = armake2(&["texturepolicy", "texture-policy.toml", "addon"]);
+    assert!(output.status.success());
+    assert_eq!(2, String::from_utf8_lossy(&output.stderr).matches("[texture-policy]").count());
+    assert!(!armake2(&["texturepolicy", "--enforce", "texture-policy.toml", "addon"]).status.success());
+
+    // Builds check the PAAs as they are packed, so downscaled ones pass the size rule
+    let output = armake2(&["build", "--texture-policy", "texture-policy.toml", "addon", "addon.pbo"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("data\\gun_co.paa: rule \"*\""), "{}", String::from_utf8_lossy(&output.stderr));
+
+    write(&policy_file, "[[rule]]\nfiles = \"ui/*\"\nmax_size = 32\n").unwrap();
+    assert!(!armake2(&["build", "--texture-policy", "texture-policy.toml", "addon", "unscaled.pbo"]).status.success());
+    let output = armake2(&["build", "--texture-policy", "texture-policy.toml", "--texture-max-size", "32", "addon", "scaled.pbo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!armake2(&["pack", "--texture-policy", "texture-policy.toml", "addon", "unscaled_packed.pbo"]).status.success());
+    let output = armake2(&["pack", "--texture-policy", "texture-policy.toml", "--texture-max-size", "32", "addon", "packed.pbo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // The project file can name the policy too
+    write(dir.path().join("armake.toml"), "[textures]\npolicy = \"texture-policy.toml\"\n").unwrap();
+    assert!(!armake2(&["build", "addon", "project.pbo"]).status.success());
+    assert!(!armake2(&["pack", "addon", "project.pbo"]).status.success());
+}
+
+#[test]
+fn test_texture_policy_check_unreadable() {
+    let dir = tempdir().unwrap();
+    write_paa(&dir.path().join("icon_ca.paa"), &paa(64, PaaType::DXT5));
+    write(dir.path().join("broken_co.paa"), b"not a paa").unwrap();
+
+    // The unreadable texture is a warning and the others are still checked
+    let policy = TexturePolicy::parse("[[rule]]\nfiles = \"*\"\nmax_size = 32\n").unwrap();
+    assert_eq!(1, cmd_texture_policy_check(dir.path(), &policy, false).unwrap());
+    let error = cmd_texture_policy_check(dir.path(), &policy, true).unwrap_err().to_string();
+    assert!(error.contains("1 textures violate the texture policy"), "{}", error);
+    assert!(armake2::warnings::get_warning_count("texture-policy-unreadable") >= 2);
+}
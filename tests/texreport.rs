@@ -0,0 +1,91 @@
+use std::fs::{File, OpenOptions, create_dir_all, read_to_string};
+use std::path::{Path};
+
+use tempfile::{tempdir};
+
+use armake2::paa::*;
+use armake2::texreport::*;
+
+fn write_texture(path: &Path, color: [u8; 4], border: [u8; 4]) {
+    let img = image::DynamicImage::ImageRgba8(image::ImageBuffer::from_fn(64, 64, |x, _| {
+        // A thin border on one side, which stays under the threshold
+        if x == 0 { image::Rgba(border) } else { image::Rgba(color) }
+    }));
+    create_dir_all(path.parent().unwrap()).unwrap();
+    Paa::from_image(&img, PaaType::DXT5, true).unwrap().write_to_file(path).unwrap();
+}
+
+#[test]
+fn test_texture_report_albedo() {
+    let dir = tempdir().unwrap();
+    write_texture(&dir.path().join("good_co.paa"), [128, 128, 128, 255], [255, 255, 255, 255]);
+    write_texture(&dir.path().join("data").join("dark_CO.paa"), [0, 0, 0, 255], [0, 0, 0, 255]);
+    write_texture(&dir.path().join("data").join("dark_nohq.paa"), [0, 0, 0, 255], [0, 0, 0, 255]);
+    // Transparent pixels don't count
+    write_texture(&dir.path().join("hidden_co.paa"), [0, 0, 0, 0], [128, 128, 128, 255]);
+
+    let options = AlbedoOptions { sample_size: 16, ..Default::default() };
+    let reports = texture_report(dir.path(), Some(&options)).unwrap();
+    let names: Vec<String> = reports.iter().map(|r| r.path.file_name().unwrap().to_string_lossy().to_string()).collect();
+    assert_eq!(vec!["dark_CO.paa", "dark_nohq.paa", "good_co.paa", "hidden_co.paa"], names);
+
+    let dark = reports[0].albedo.as_ref().unwrap();
+    assert!(dark.flagged);
+    assert_eq!((16, 16), (dark.sample_width, dark.sample_height));
+    assert!(dark.channels[0].outside > 0.9);
+    assert!(reports[1].albedo.is_none());
+    assert_eq!((64, 64, 7), (reports[1].width, reports[1].height, reports[1].mipmaps));
+
+    let good = reports[2].albedo.as_ref().unwrap();
+    assert!(!good.flagged);
+    assert!((120..=136).contains(&good.channels[1].median));
+    assert!(good.channels[1].p5 <= good.channels[1].median && good.channels[1].median <= good.channels[1].p95);
+
+    // Analyze the full size image, downscaling blends the border into the transparent pixels
+    let hidden = analyze_albedo(&Paa::from_file(&reports[3].path).unwrap(), &AlbedoOptions::default()).unwrap();
+    assert_eq!(64, hidden.sample_width);
+    assert!(!hidden.flagged);
+    assert!(hidden.channels.iter().all(|c| c.outside == 0.0));
+
+    let csv = dir.path().join("report.csv");
+    assert!(cmd_texture_report(dir.path(), Some(&options), Some(&csv)).is_err());
+    let content = read_to_string(&csv).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(5, lines.len());
+    assert!(lines[0].starts_with("path,type,width,height,mipmaps,compressed,albedo_flagged,r_p5,r_median,r_p95,r_outside"));
+    assert_eq!(lines[0].split(',').count(), lines[2].split(',').count());
+    assert!(lines[1].contains(",DXT5,64,64,7,true,true,"));
+
+    cmd_texture_report(dir.path(), None, None).unwrap();
+}
+
+#[test]
+fn test_texture_report_headers_only() {
+    let dir = tempdir().unwrap();
+    let nohq = dir.path().join("gun_nohq.paa");
+    let diffuse = dir.path().join("gun_co.paa");
+    write_texture(&nohq, [128, 128, 128, 255], [128, 128, 128, 255]);
+    write_texture(&diffuse, [128, 128, 128, 255], [128, 128, 128, 255]);
+
+    // Without the data of the last mipmap, only the headers can still be read
+    for path in [&nohq, &diffuse] {
+        let (_, _, offset) = *PaaHeader::read(&mut File::open(path).unwrap()).unwrap().mipmaps.last().unwrap();
+        OpenOptions::new().write(true).open(path).unwrap().set_len(offset as u64 + 7).unwrap();
+    }
+    assert!(Paa::from_file(&nohq).is_err());
+
+    let reports = texture_report(dir.path(), None).unwrap();
+    assert_eq!(2, reports.len());
+    assert_eq!((PaaType::DXT5, 64, 64, 7, true), (reports[1].paa_type, reports[1].width, reports[1].height, reports[1].mipmaps, reports[1].compressed));
+
+    // The albedo of diffuse textures needs the data
+    assert!(texture_report(dir.path(), Some(&AlbedoOptions::default())).is_err());
+}
+
+#[test]
+fn test_parse_albedo_range() {
+    assert_eq!((10, 240), parse_albedo_range("10-240").unwrap());
+    assert!(parse_albedo_range("240-10").is_err());
+    assert!(parse_albedo_range("10").is_err());
+    assert!(parse_albedo_range("10-300").is_err());
+}
@@ -0,0 +1,101 @@
+use std::fs::{File, create_dir_all, write};
+use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+use std::process::{Command};
+
+use tempfile::{tempdir};
+
+use armake2::config::*;
+use armake2::pbo::*;
+use armake2::vfs::*;
+
+/// Packs the files into `<directory>/<name>.pbo` with the given prefix.
+fn write_pbo(directory: &Path, name: &str, prefix: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let source = tempdir().unwrap();
+    write(source.path().join("$PBOPREFIX$"), prefix).unwrap();
+    for (file, content) in files {
+        let path = source.path().join(file);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, content).unwrap();
+    }
+
+    create_dir_all(directory).unwrap();
+    let path = directory.join(format!("{}.pbo", name));
+    PBO::from_directory(source.path().to_path_buf(), false, &[], &[]).unwrap()
+        .write(&mut File::create(&path).unwrap()).unwrap();
+    path
+}
+
+fn rapified(input: &str) -> Vec<u8> {
+    let config = Config::from_string(input.to_string(), None, &[]).unwrap();
+    config.to_cursor().unwrap().into_inner().to_vec()
+}
+
+#[test]
+fn test_virtual_filesystem() {
+    let game = tempdir().unwrap();
+    let addons = game.path().join("Addons");
+    let weapons = write_pbo(&addons, "weapons_f", "a3\\weapons_f", &[
+        ("config.bin", &rapified("class CfgWeapons { class Rifle { scope = 1; }; };")),
+        ("data/rifle.p3d", b"MLOD"),
+        ("rifles/stale.sqf", b"shadowed"),
+    ]);
+    let rifles = write_pbo(&addons.join("sub"), "weapons_f_rifles", "A3\\Weapons_F\\Rifles", &[
+        ("config.cpp", b"class CfgWeapons { class MX { scope = 2; }; };"),
+        ("Stale.sqf", b"current"),
+    ]);
+
+    let other = tempdir().unwrap();
+    let overriding = write_pbo(other.path(), "override", "a3\\weapons_f", &[("data/rifle.p3d", b"ODOL")]);
+
+    let filesystem = VirtualFilesystem::new(vec![other.path().to_path_buf(), game.path().to_path_buf()]);
+    let resolve = |path: &str| filesystem.resolve(path).unwrap().map(|e| (e.pbo, e.entry));
+
+    assert_eq!(Some((weapons.clone(), "config.bin".to_string())), resolve("\\a3\\weapons_f\\config.bin"));
+    assert_eq!(Some((rifles.clone(), "config.cpp".to_string())), resolve("\\A3\\WEAPONS_F\\RIFLES\\CONFIG.CPP"));
+    assert_eq!(Some((rifles.clone(), "Stale.sqf".to_string())), resolve("a3/weapons_f/rifles/stale.sqf"));
+    assert_eq!(Some((overriding, "data\\rifle.p3d".to_string())), resolve("\\a3\\weapons_f\\data\\rifle.p3d"));
+    assert_eq!(None, resolve("\\a3\\weapons_f\\rifles\\missing.sqf"));
+    assert_eq!(None, resolve("\\a3\\weapons_fx\\config.bin"));
+
+    assert_eq!(b"ODOL".to_vec(), filesystem.read("\\a3\\weapons_f\\data\\rifle.p3d").unwrap());
+    assert!(filesystem.read("\\a3\\missing.p3d").is_err());
+
+    let config = filesystem.load_config("\\a3\\weapons_f\\config.bin").unwrap();
+    assert!(config.find("CfgWeapons/Rifle/scope").is_some());
+    let config = filesystem.load_config("\\a3\\weapons_f\\rifles\\config.cpp").unwrap();
+    assert!(config.find("cfgweapons/mx").is_some());
+
+    let config = load_from_pbo(&weapons, "CONFIG.BIN").unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    config.write(&mut output).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains("scope = 1;"));
+    assert!(load_from_pbo(&weapons, "missing.bin").is_err());
+
+    let mut input = Cursor::new(std::fs::read(&rifles).unwrap());
+    assert_eq!(Some(b"current".to_vec()), read_entry(&mut input, "stale.SQF").unwrap());
+}
+
+#[test]
+fn test_configbundle_mod_dir() {
+    let game = tempdir().unwrap();
+    write_pbo(game.path(), "weapons_f", "a3\\weapons_f", &[
+        ("config.bin", &rapified("class CfgWeapons { class Rifle { scope = 1; }; };")),
+        ("rifles/config.cpp", b"#define SCOPE 2\nclass CfgWeapons { class MX { scope = SCOPE; }; };"),
+    ]);
+
+    let bundle = |path: &str| Command::new(env!("CARGO_BIN_EXE_armake2"))
+        .args(["configbundle", "--mod-dir", game.path().to_str().unwrap(), path]).output().unwrap();
+
+    let output = bundle("\\A3\\weapons_f\\config.bin");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("scope = 1;"));
+
+    let output = bundle("\\a3\\weapons_f\\rifles\\config.cpp");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("scope = 2;"));
+
+    let output = bundle("\\a3\\weapons_f\\missing.cpp");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("E0312"), "{}", String::from_utf8_lossy(&output.stderr));
+}
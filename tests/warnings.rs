@@ -0,0 +1,122 @@
+use armake2::warnings::*;
+
+// The warning state is shared by the whole process, so everything is tested in one function.
+#[test]
+fn test_warnings_at_location() {
+    clear_warnings();
+    set_warnings_maximum(2);
+
+    // Without deduplication every repeat counts
+    assert!(raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert!(raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert!(!raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert!(get_duplicate_summary().is_empty());
+
+    clear_warnings();
+    set_warning_deduplication(true);
+
+    // Only the first warning at a location counts
+    assert!(raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert!(!raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert!(!raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    assert_eq!(1, get_warning_count("unquoted-string"));
+    assert_eq!(vec![("unquoted-string".to_string(), 2)], get_duplicate_summary());
+
+    // Other lines, files, names and messages are separate, duplicates don't use up the maximum
+    assert!(raise_warning_at("unquoted-string", "common.hpp", 4, "a"));
+    assert!(raise_warning_at("redefinition-wo-undef", "common.hpp", 3, "a"));
+    assert!(!raise_warning_at("unquoted-string", "common.hpp", 3, "b"));
+    assert!(!raise_warning_at("unquoted-string", "other.hpp", 3, "a"));
+    assert!(has_exceeded_maximum("unquoted-string"));
+    assert_eq!(vec![("unquoted-string".to_string(), 4, 2)], get_warning_summary());
+
+    // Location-less warnings are never deduplicated
+    assert!(raise_warning("no-location"));
+    assert!(raise_warning("no-location"));
+
+    // Muted warnings are neither shown nor counted as duplicates
+    mute_warning("muted");
+    assert!(!raise_warning_at("muted", "common.hpp", 1, "a"));
+    assert!(!raise_warning_at("muted", "common.hpp", 1, "a"));
+    assert_eq!(0, get_warning_count("muted"));
+    assert!(get_duplicate_summary().iter().all(|(name, _)| name != "muted"));
+
+    // A new scope shows warnings at known locations again
+    assert!(!raise_warning_at("redefinition-wo-undef", "common.hpp", 3, "a"));
+    begin_warning_scope();
+    assert!(raise_warning_at("redefinition-wo-undef", "common.hpp", 3, "a"));
+    assert_eq!(2, get_warning_count("redefinition-wo-undef"));
+    assert_eq!(Some(1), get_duplicate_summary().iter().find(|(name, _)| name == "redefinition-wo-undef").map(|(_, count)| *count));
+
+    clear_warnings();
+    assert!(get_duplicate_summary().is_empty());
+    assert!(raise_warning_at("unquoted-string", "common.hpp", 3, "a"));
+    set_warning_deduplication(false);
+
+    // Maximums of single types take precedence over the global one, muting over both
+    clear_warnings();
+    set_warning_maximum_for("unquoted-string", 3);
+    set_warning_unlimited("redefinition-wo-undef");
+    set_warning_maximum_for("muted", 5);
+    mute_warning("muted");
+    assert_eq!((Some(3), Some(u32::MAX), None), (get_warning_maximum_for("unquoted-string"),
+        get_warning_maximum_for("redefinition-wo-undef"), get_warning_maximum_for("other")));
+    let shown = |name: &str| (0..20).filter(|_| raise_warning(name)).count();
+    assert_eq!((3, 20, 2, 0), (shown("unquoted-string"), shown("redefinition-wo-undef"), shown("other"), shown("muted")));
+    assert!(has_exceeded_maximum("unquoted-string") && has_exceeded_maximum("other"));
+    assert!(!has_exceeded_maximum("redefinition-wo-undef") && !has_exceeded_maximum("muted"));
+    assert_eq!(vec![("other".to_string(), 20, 18), ("unquoted-string".to_string(), 20, 17)], get_warning_summary());
+
+    // Clearing resets the maximums of single types, but not the global one
+    clear_warnings();
+    assert_eq!((None, 2), (get_warning_maximum_for("unquoted-string"), get_warnings_maximum()));
+    assert_eq!((2, 2), (shown("unquoted-string"), shown("redefinition-wo-undef")));
+    assert!(get_warning_summary().iter().all(|(_, _, excess)| *excess == 18));
+
+    clear_warnings();
+    set_warnings_maximum(10);
+}
+
+#[test]
+fn test_warning_maximum_cli() {
+    let dir = tempfile::tempdir().unwrap();
+    let config: String = (0..6).map(|i| format!("value{} = abc;\n", i)).collect();
+    std::fs::write(dir.path().join("config.cpp"), config).unwrap();
+
+    let rapify = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+            .arg("rapify").args(args).args(["config.cpp", "config.bin"]).arg("-f").output().unwrap();
+        (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+    let shown = |stderr: &str| stderr.lines().filter(|l| l.contains("[unquoted-string] [W0101]")).count();
+
+    let (success, stderr) = rapify(&["-w", "unquoted-string=2"]);
+    assert!(success, "{}", stderr);
+    assert_eq!(2, shown(&stderr));
+    assert!(stderr.contains("4 warnings of type \"unquoted-string\" were suppressed after reaching the maximum of 2 set for this type with \"-w unquoted-string=2\""), "{}", stderr);
+
+    let (_, stderr) = rapify(&["-w", "unquoted-string=unlimited"]);
+    assert_eq!((6, false), (shown(&stderr), stderr.contains("suppressed")));
+    let (_, stderr) = rapify(&["-w", "unquoted-string"]);
+    assert_eq!(0, shown(&stderr));
+    let (success, stderr) = rapify(&["-w", "unquoted-string=many"]);
+    assert!(!success && stderr.contains("Invalid warning maximum \"many\""), "{}", stderr);
+}
+
+#[test]
+fn test_warning_dedupe_cli() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.cpp"), "a = abc; b = abc; c = def;\n").unwrap();
+
+    let rapify = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_armake2")).current_dir(dir.path())
+            .arg("rapify").args(args).args(["config.cpp", "config.bin"]).arg("-f").output().unwrap();
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let shown = |stderr: &str| stderr.lines().filter(|l| l.contains("[unquoted-string] [W0101]")).count();
+
+    assert_eq!(3, shown(&rapify(&[])));
+    let stderr = rapify(&["--dedupe-warnings"]);
+    assert_eq!(2, shown(&stderr), "{}", stderr);
+    assert!(stderr.contains("1 duplicate occurrence of warnings of type \"unquoted-string\" at the same location were suppressed."), "{}", stderr);
+}